@@ -0,0 +1,721 @@
+//! 本模块实现了 ACPI 系统描述表的查找
+//!
+//! 固件把根系统描述指针（RSDP）放在 EBDA 的第一个 KiB 或 BIOS 只读区域中，
+//! 从它出发经 RSDT（或 64 位的 XSDT）即可按签名找到其他表。
+//! [`init`] 校验 RSDP 及每张表的校验和，之后按签名查找时只在记录下来的表中查找。
+//!
+//! 目前解析 MADT 中的本地 APIC 地址、处理器、IO APIC 和中断源覆盖，FADT 中的电源管理寄存器，
+//! HPET 表中的寄存器地址，以及 MCFG 中 PCIe 配置空间的映射区域。
+//! 不解释 AML，只在 DSDT 中按字节查找关机所需的 `\_S5_` 对象
+
+use alloc::vec::Vec;
+use core::mem::size_of;
+
+use conquer_once::spin::OnceCell;
+use x86_64::PhysAddr;
+
+use crate::memory;
+
+/// RSDP 的签名
+const RSDP_SIGNATURE: &[u8; 8] = b"RSD PTR ";
+
+/// BIOS 数据区中保存 EBDA 段地址的位置
+const EBDA_POINTER: u64 = 0x40e;
+/// 在 EBDA 中搜索的字节数
+const EBDA_SEARCH_LEN: u64 = 1024;
+/// BIOS 只读区域
+const BIOS_AREA: core::ops::Range<u64> = 0xe_0000..0x10_0000;
+
+/// MADT 中处理器本地 APIC 条目的类型
+const MADT_LOCAL_APIC: u8 = 0;
+/// MADT 中 IO APIC 条目的类型
+const MADT_IO_APIC: u8 = 1;
+/// MADT 中中断源覆盖条目的类型
+const MADT_INTERRUPT_OVERRIDE: u8 = 2;
+/// MADT 中本地 APIC 地址覆盖条目的类型
+const MADT_LOCAL_APIC_OVERRIDE: u8 = 5;
+/// 处理器本地 APIC 条目的标志：处理器已启用
+const LOCAL_APIC_ENABLED: u32 = 1 << 0;
+/// 处理器本地 APIC 条目的标志：处理器可以在运行时启用
+const LOCAL_APIC_ONLINE_CAPABLE: u32 = 1 << 1;
+
+/// 通用地址结构中表示系统内存空间的地址空间编号
+pub const ADDRESS_SPACE_MEMORY: u8 = 0;
+/// 通用地址结构中表示 IO 端口空间的地址空间编号
+pub const ADDRESS_SPACE_IO: u8 = 1;
+/// 通用地址结构中表示 PCI 配置空间的地址空间编号
+pub const ADDRESS_SPACE_PCI: u8 = 2;
+
+/// FADT 的标志：支持复位寄存器
+const FADT_RESET_REGISTER: u32 = 1 << 10;
+/// FADT 中复位寄存器及之前的字段的长度，ACPI 2.0 起才有
+const FADT_RESET_LEN: usize = 129;
+/// FADT 中 64 位扩展地址及之前的字段的长度
+const FADT_EXTENDED_LEN: usize = 244;
+
+/// 根系统描述指针，ACPI 2.0 起增加了 XSDT 地址等字段
+#[repr(C, packed)]
+struct Rsdp {
+    signature: [u8; 8],    // "RSD PTR "
+    checksum: u8,          // 前 20 字节的校验和
+    oem_id: [u8; 6],       // OEM 标识
+    revision: u8,          // 0 表示 ACPI 1.0，2 表示 ACPI 2.0 及以上
+    rsdt_address: u32,     // RSDT 的物理地址
+    length: u32,           // 整个结构的长度（ACPI 2.0）
+    xsdt_address: u64,     // XSDT 的物理地址（ACPI 2.0）
+    extended_checksum: u8, // 整个结构的校验和（ACPI 2.0）
+    reserved: [u8; 3],     // 保留
+}
+
+/// 所有系统描述表共有的表头
+#[repr(C, packed)]
+struct SdtHeader {
+    signature: [u8; 4],    // 表的签名
+    length: u32,           // 包括表头在内的长度
+    revision: u8,          // 表的版本
+    checksum: u8,          // 整张表的校验和
+    oem_id: [u8; 6],       // OEM 标识
+    oem_table_id: [u8; 8], // OEM 表标识
+    oem_revision: u32,     // OEM 版本
+    creator_id: u32,       // 创建者标识
+    creator_revision: u32, // 创建者版本
+}
+
+/// MADT 中的一个处理器
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Processor {
+    pub processor_id: u8, // ACPI 处理器编号
+    pub apic_id: u8,      // 本地 APIC ID
+    pub enabled: bool,    // 是否已启用或可以启用
+}
+
+/// MADT 中的一个 IO APIC
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IoApic {
+    pub id: u8,            // IO APIC 的编号
+    pub address: PhysAddr, // 寄存器的物理地址
+    pub gsi_base: u32,     // 第一个输入引脚对应的全局系统中断号
+}
+
+/// MADT 中的一个中断源覆盖，描述 ISA 中断连接到的全局系统中断及其触发方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InterruptOverride {
+    pub source: u8,            // ISA 中断号
+    pub gsi: u32,              // 全局系统中断号
+    pub active_low: bool,      // 是否低电平有效
+    pub level_triggered: bool, // 是否电平触发
+}
+
+/// 多处理器 APIC 描述表（MADT）中的信息
+#[derive(Debug, Clone)]
+pub struct Madt {
+    pub local_apic_address: PhysAddr,      // 本地 APIC 寄存器的物理地址
+    pub processors: Vec<Processor>,        // 系统中的处理器
+    pub io_apics: Vec<IoApic>,             // 系统中的 IO APIC
+    pub overrides: Vec<InterruptOverride>, // ISA 中断的覆盖
+}
+
+/// HPET 描述表中的信息
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Hpet {
+    pub address: PhysAddr, // 寄存器的物理地址
+    pub number: u8,        // HPET 的序号
+    pub min_tick: u16,     // 周期模式下不丢失中断的最小周期，以主计数器的计数为单位
+}
+
+/// 通用地址结构，描述位于内存、IO 端口或 PCI 配置空间中的寄存器
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GenericAddress {
+    pub space: u8,       // 地址空间编号，例如 [`ADDRESS_SPACE_IO`]
+    pub bit_width: u8,   // 寄存器的位宽
+    pub bit_offset: u8,  // 寄存器在地址处的位偏移
+    pub access_size: u8, // 访问宽度，1~4 分别表示 1、2、4、8 字节，0 表示未指定
+    pub address: u64,    // 寄存器的地址
+}
+
+impl GenericAddress {
+    /// 解析 12 字节的通用地址结构
+    fn parse(bytes: &[u8]) -> Option<GenericAddress> {
+        let bytes = bytes.get(..12)?;
+        Some(GenericAddress {
+            space: bytes[0],
+            bit_width: bytes[1],
+            bit_offset: bytes[2],
+            access_size: bytes[3],
+            address: u64::from_le_bytes(bytes[4..12].try_into().ok()?),
+        })
+    }
+}
+
+/// 固定 ACPI 描述表（FADT）中的信息
+///
+/// 电源管理寄存器块都以 IO 端口给出，扩展的 64 位地址位于 IO 空间时优先使用扩展地址
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fadt {
+    pub dsdt: PhysAddr,                         // DSDT 的物理地址
+    pub sci_interrupt: u16,                     // SCI 中断连接到的 ISA 中断
+    pub smi_command: u16,                       // SMI 命令端口，0 表示已处于 ACPI 模式
+    pub acpi_enable: u8,                        // 写入 SMI 命令端口以进入 ACPI 模式的值
+    pub acpi_disable: u8,                       // 写入 SMI 命令端口以退出 ACPI 模式的值
+    pub pm1a_control: u16,                      // PM1a 控制寄存器块的端口
+    pub pm1b_control: Option<u16>,              // PM1b 控制寄存器块的端口
+    pub pm_timer: Option<u16>,                  // 电源管理定时器的端口
+    pub century: u8,                            // CMOS 中世纪寄存器的索引，0 表示没有
+    pub flags: u32,                             // 固定功能标志
+    pub reset_register: Option<GenericAddress>, // 复位寄存器，固件不支持时为 `None`
+    pub reset_value: u8,                        // 写入复位寄存器以复位系统的值
+}
+
+/// MCFG 中的一段 PCIe 增强配置空间（ECAM），每条总线占 1 MiB
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EcamRegion {
+    pub base: PhysAddr, // 总线 0 的配置空间所在的物理地址，`start_bus` 之前的部分不存在
+    pub segment: u16,   // PCI 段组号
+    pub start_bus: u8,  // 第一条总线
+    pub end_bus: u8,    // 最后一条总线
+}
+
+impl Madt {
+    /// ISA 中断对应的全局系统中断和触发方式
+    ///
+    /// 没有覆盖的 ISA 中断按规范恒等映射，并且是高电平有效的边沿触发
+    ///
+    /// # 参数
+    ///
+    /// - `irq`: ISA 中断号
+    pub fn isa_irq(&self, irq: u8) -> InterruptOverride {
+        self.overrides
+            .iter()
+            .find(|o| o.source == irq)
+            .copied()
+            .unwrap_or(InterruptOverride {
+                source: irq,
+                gsi: u32::from(irq),
+                active_low: false,
+                level_triggered: false,
+            })
+    }
+}
+
+/// 根系统描述表中记录的所有表
+struct RootTable {
+    revision: u8,          // RSDP 的版本
+    oem_id: [u8; 6],       // OEM 标识
+    tables: Vec<PhysAddr>, // 校验和正确的表的物理地址
+}
+
+/// 由 [`init`] 记录之后不再改变
+static ROOT: OnceCell<RootTable> = OnceCell::uninit();
+
+/// 校验和：所有字节相加的低 8 位为 0
+fn checksum_ok(bytes: &[u8]) -> bool {
+    bytes.iter().fold(0u8, |sum, &b| sum.wrapping_add(b)) == 0
+}
+
+/// 读取物理内存中的一段字节
+///
+/// # 返回
+///
+/// 尚未调用 [`memory::install`] 时返回 `None`
+fn phys_bytes(addr: u64, len: u64) -> Option<&'static [u8]> {
+    let virt = memory::phys_to_virt(PhysAddr::new(addr))?;
+    Some(unsafe { core::slice::from_raw_parts(virt.as_ptr(), len as usize) })
+}
+
+/// 在一段物理内存中按 16 字节对齐搜索 RSDP
+fn search_rsdp(range: core::ops::Range<u64>) -> Option<&'static Rsdp> {
+    let bytes = phys_bytes(range.start, range.end - range.start)?;
+    bytes
+        .chunks_exact(16)
+        .enumerate()
+        .filter(|(_, chunk)| chunk.starts_with(RSDP_SIGNATURE))
+        .map(|(i, _)| &bytes[i * 16..])
+        .find(|candidate| rsdp_ok(candidate))
+        .map(|candidate| unsafe { &*candidate.as_ptr().cast::<Rsdp>() })
+}
+
+/// 检查以 RSDP 开头的字节的签名和校验和，ACPI 2.0 起还要检查整个结构的校验和
+fn rsdp_ok(bytes: &[u8]) -> bool {
+    let v1_len = core::mem::offset_of!(Rsdp, length);
+    if bytes.len() < size_of::<Rsdp>()
+        || !bytes.starts_with(RSDP_SIGNATURE)
+        || !checksum_ok(&bytes[..v1_len])
+    {
+        return false;
+    }
+    let revision = bytes[core::mem::offset_of!(Rsdp, revision)];
+    revision < 2 || checksum_ok(&bytes[..size_of::<Rsdp>()])
+}
+
+/// 读取物理地址处的 RSDP，签名或校验和错误时返回 `None`
+fn rsdp_at(addr: PhysAddr) -> Option<&'static Rsdp> {
+    let bytes = phys_bytes(addr.as_u64(), size_of::<Rsdp>() as u64)?;
+    rsdp_ok(bytes).then(|| unsafe { &*bytes.as_ptr().cast::<Rsdp>() })
+}
+
+/// 在 EBDA 和 BIOS 只读区域中搜索 RSDP
+fn scan_rsdp() -> Option<&'static Rsdp> {
+    let ebda_segment = phys_bytes(EBDA_POINTER, 2)?;
+    let ebda = u64::from(u16::from_le_bytes([ebda_segment[0], ebda_segment[1]])) << 4;
+    let in_ebda = (ebda != 0)
+        .then(|| search_rsdp(ebda..ebda + EBDA_SEARCH_LEN))
+        .flatten();
+    in_ebda.or_else(|| search_rsdp(BIOS_AREA))
+}
+
+/// 读取物理地址处的系统描述表，校验和错误时返回 `None`
+fn table_at(addr: u64) -> Option<&'static [u8]> {
+    let header = phys_bytes(addr, size_of::<SdtHeader>() as u64)?;
+    let header = unsafe { header.as_ptr().cast::<SdtHeader>().read_unaligned() };
+    let table = phys_bytes(addr, u64::from(header.length))?;
+    checksum_ok(table).then_some(table)
+}
+
+/// 找到 RSDP 并记录根系统描述表中校验和正确的表，需要在 [`memory::install`] 之后调用
+///
+/// # 参数
+///
+/// - `rsdp`: 引导程序给出的 RSDP 物理地址，为 `None` 或无效时在 BIOS 区域中搜索
+///
+/// # 返回
+///
+/// 没有找到 RSDP 或根系统描述表无效时返回 `false`
+pub fn init(rsdp: Option<PhysAddr>) -> bool {
+    if ROOT.is_initialized() {
+        return true;
+    }
+    let Some(rsdp) = rsdp.and_then(rsdp_at).or_else(scan_rsdp) else {
+        return false;
+    };
+    // ACPI 2.0 起优先使用 XSDT，其中的表地址为 64 位
+    let (root, entry_size) = if rsdp.revision >= 2 && rsdp.xsdt_address != 0 {
+        (rsdp.xsdt_address, 8)
+    } else {
+        (u64::from(rsdp.rsdt_address), 4)
+    };
+    let Some(root) = table_at(root) else {
+        log::warn!("ACPI root table at {:#x} is invalid", root);
+        return false;
+    };
+    let tables: Vec<_> = root[size_of::<SdtHeader>()..]
+        .chunks_exact(entry_size)
+        .map(|entry| {
+            let mut addr = [0; 8];
+            addr[..entry_size].copy_from_slice(entry);
+            u64::from_le_bytes(addr)
+        })
+        .filter(|&addr| table_at(addr).is_some())
+        .map(PhysAddr::new)
+        .collect();
+    let root = ROOT.get_or_init(|| RootTable {
+        revision: rsdp.revision,
+        oem_id: rsdp.oem_id,
+        tables,
+    });
+    log::info!(
+        "ACPI revision {} from {}, {} tables",
+        root.revision,
+        core::str::from_utf8(&root.oem_id).unwrap_or("?").trim_end(),
+        root.tables.len()
+    );
+    true
+}
+
+/// 按签名查找系统描述表
+///
+/// 尚未调用 [`init`] 时先在 BIOS 区域中搜索 RSDP
+///
+/// # 参数
+///
+/// - `signature`: 表的签名，例如 `b"APIC"`
+///
+/// # 返回
+///
+/// 表的物理地址，没有找到时返回 `None`
+pub fn find_table(signature: &[u8; 4]) -> Option<PhysAddr> {
+    if !init(None) {
+        return None;
+    }
+    ROOT.get()?
+        .tables
+        .iter()
+        .copied()
+        .find(|addr| table_at(addr.as_u64()).is_some_and(|table| table.starts_with(signature)))
+}
+
+/// 已记录的所有表的签名，尚未找到 RSDP 时为空
+pub fn signatures() -> Vec<[u8; 4]> {
+    ROOT.get().map_or_else(Vec::new, |root| {
+        root.tables
+            .iter()
+            .filter_map(|addr| table_at(addr.as_u64()))
+            .map(|table| [table[0], table[1], table[2], table[3]])
+            .collect()
+    })
+}
+
+/// 解析 MADT
+///
+/// # 返回
+///
+/// 没有找到 MADT 时返回 `None`
+pub fn madt() -> Option<Madt> {
+    parse_madt(table_at(find_table(b"APIC")?.as_u64())?)
+}
+
+/// 解析 MADT 的内容
+///
+/// # 参数
+///
+/// - `table`: 包括表头在内的整张表
+fn parse_madt(table: &[u8]) -> Option<Madt> {
+    let header_len = size_of::<SdtHeader>();
+    let mut local_apic_address = u64::from(u32::from_le_bytes(
+        table[header_len..header_len + 4].try_into().ok()?,
+    ));
+    let mut processors = Vec::new();
+    let mut io_apics = Vec::new();
+    let mut overrides = Vec::new();
+    // 表头之后是 32 位的本地 APIC 地址和标志，然后是变长的条目
+    let mut entries = table.get(header_len + 8..)?;
+    while let &[kind, len, ..] = entries {
+        let len = usize::from(len);
+        if len < 2 || len > entries.len() {
+            break;
+        }
+        let entry = &entries[..len];
+        match kind {
+            MADT_LOCAL_APIC if len >= 8 => {
+                let flags = u32::from_le_bytes(entry[4..8].try_into().ok()?);
+                processors.push(Processor {
+                    processor_id: entry[2],
+                    apic_id: entry[3],
+                    enabled: flags & (LOCAL_APIC_ENABLED | LOCAL_APIC_ONLINE_CAPABLE) != 0,
+                });
+            }
+            MADT_IO_APIC if len >= 12 => io_apics.push(IoApic {
+                id: entry[2],
+                address: PhysAddr::new(u64::from(u32::from_le_bytes(entry[4..8].try_into().ok()?))),
+                gsi_base: u32::from_le_bytes(entry[8..12].try_into().ok()?),
+            }),
+            MADT_INTERRUPT_OVERRIDE if len >= 10 => {
+                let flags = u16::from_le_bytes(entry[8..10].try_into().ok()?);
+                // 极性和触发方式各占两位，0 表示遵循总线的默认值，ISA 为高电平有效的边沿触发
+                overrides.push(InterruptOverride {
+                    source: entry[3],
+                    gsi: u32::from_le_bytes(entry[4..8].try_into().ok()?),
+                    active_low: flags & 0b11 == 0b11,
+                    level_triggered: (flags >> 2) & 0b11 == 0b11,
+                });
+            }
+            MADT_LOCAL_APIC_OVERRIDE if len >= 12 => {
+                local_apic_address = u64::from_le_bytes(entry[4..12].try_into().ok()?);
+            }
+            _ => {}
+        }
+        entries = &entries[len..];
+    }
+    Some(Madt {
+        local_apic_address: PhysAddr::new(local_apic_address),
+        processors,
+        io_apics,
+        overrides,
+    })
+}
+
+/// 解析 FADT
+///
+/// # 返回
+///
+/// 没有找到 FADT 时返回 `None`
+pub fn fadt() -> Option<Fadt> {
+    parse_fadt(table_at(find_table(b"FACP")?.as_u64())?)
+}
+
+/// 解析 FADT 的内容
+///
+/// # 参数
+///
+/// - `table`: 包括表头在内的整张表
+fn parse_fadt(table: &[u8]) -> Option<Fadt> {
+    let u16_at = |offset: usize| {
+        Some(u16::from_le_bytes(
+            table.get(offset..offset + 2)?.try_into().ok()?,
+        ))
+    };
+    let u32_at = |offset: usize| {
+        Some(u32::from_le_bytes(
+            table.get(offset..offset + 4)?.try_into().ok()?,
+        ))
+    };
+    // 扩展地址位于 IO 空间且不为 0 时取代 32 位的寄存器块地址
+    let port = |legacy: usize, extended: usize| {
+        let extended = (table.len() >= FADT_EXTENDED_LEN)
+            .then(|| GenericAddress::parse(&table[extended..]))
+            .flatten()
+            .filter(|gas| gas.space == ADDRESS_SPACE_IO && gas.address != 0);
+        match extended {
+            Some(gas) => Some(gas.address as u16),
+            None => u32_at(legacy).map(|port| port as u16),
+        }
+    };
+    let mut dsdt = u64::from(u32_at(40)?);
+    if table.len() >= FADT_EXTENDED_LEN {
+        let x_dsdt = u64::from_le_bytes(table[140..148].try_into().ok()?);
+        if x_dsdt != 0 {
+            dsdt = x_dsdt;
+        }
+    }
+    let flags = u32_at(112)?;
+    let reset_register = (table.len() >= FADT_RESET_LEN && flags & FADT_RESET_REGISTER != 0)
+        .then(|| GenericAddress::parse(&table[116..]))
+        .flatten()
+        .filter(|gas| gas.address != 0);
+    Some(Fadt {
+        dsdt: PhysAddr::new(dsdt),
+        sci_interrupt: u16_at(46)?,
+        smi_command: u32_at(48)? as u16,
+        acpi_enable: table[52],
+        acpi_disable: table[53],
+        pm1a_control: port(64, 172)?,
+        pm1b_control: port(68, 184).filter(|&port| port != 0),
+        pm_timer: port(76, 208).filter(|&port| port != 0),
+        century: table[108],
+        flags,
+        reset_register,
+        reset_value: table.get(128).copied().unwrap_or(0),
+    })
+}
+
+/// DSDT 中 `\_S5_` 对象给出的 PM1a 和 PM1b 的睡眠类型，用于进入软关机状态
+///
+/// # 返回
+///
+/// 没有找到 FADT、DSDT 或其中没有 `\_S5_` 时返回 `None`
+pub fn s5_sleep_type() -> Option<(u8, u8)> {
+    let dsdt = table_at(fadt()?.dsdt.as_u64())?;
+    parse_s5(dsdt.get(size_of::<SdtHeader>()..)?)
+}
+
+/// 在 AML 字节码中查找 `Name (_S5_, Package () { a, b, ... })` 并取出前两个元素
+///
+/// # 参数
+///
+/// - `aml`: DSDT 表头之后的字节码
+fn parse_s5(aml: &[u8]) -> Option<(u8, u8)> {
+    /// 定义命名对象的 NameOp
+    const NAME_OP: u8 = 0x08;
+    /// 包的 PackageOp
+    const PACKAGE_OP: u8 = 0x12;
+    /// 一字节常量的前缀
+    const BYTE_PREFIX: u8 = 0x0a;
+
+    let start = aml.windows(4).enumerate().find_map(|(i, name)| {
+        let named = match i {
+            0 => false,
+            1 => aml[0] == NAME_OP,
+            _ => aml[i - 1] == NAME_OP || (aml[i - 2] == NAME_OP && aml[i - 1] == b'\\'),
+        };
+        (name == b"_S5_" && named).then_some(i + 4)
+    })?;
+    let mut bytes = aml.get(start..)?.iter().copied();
+    if bytes.next()? != PACKAGE_OP {
+        return None;
+    }
+    // 包长度的第一个字节的最高两位为之后还有几个字节
+    let length_bytes = usize::from(bytes.next()? >> 6);
+    let mut bytes = bytes.skip(length_bytes + 1);
+    // 0 和 1 分别编码为 ZeroOp 和 OneOp，其余的值带有一字节常量的前缀
+    let mut element = || match bytes.next()? {
+        BYTE_PREFIX => bytes.next(),
+        value => Some(value),
+    };
+    Some((element()? & 0b111, element()? & 0b111))
+}
+
+/// 解析 HPET 描述表
+///
+/// # 返回
+///
+/// 没有找到 HPET 表或寄存器不在内存空间中时返回 `None`
+pub fn hpet() -> Option<Hpet> {
+    parse_hpet(table_at(find_table(b"HPET")?.as_u64())?)
+}
+
+/// 解析 HPET 描述表的内容
+///
+/// # 参数
+///
+/// - `table`: 包括表头在内的整张表
+fn parse_hpet(table: &[u8]) -> Option<Hpet> {
+    // 表头之后依次为 4 字节的硬件标识、12 字节的通用地址结构、序号和最小周期
+    let body = table.get(size_of::<SdtHeader>()..)?.get(..19)?;
+    let address = &body[4..16];
+    if address[0] != ADDRESS_SPACE_MEMORY {
+        return None;
+    }
+    Some(Hpet {
+        address: PhysAddr::new(u64::from_le_bytes(address[4..12].try_into().ok()?)),
+        number: body[16],
+        min_tick: u16::from_le_bytes(body[17..19].try_into().ok()?),
+    })
+}
+
+/// 解析 MCFG 中的所有 ECAM 区域
+///
+/// # 返回
+///
+/// 没有找到 MCFG 时返回 `None`
+pub fn mcfg() -> Option<Vec<EcamRegion>> {
+    Some(parse_mcfg(table_at(find_table(b"MCFG")?.as_u64())?))
+}
+
+/// 解析 MCFG 的内容
+///
+/// # 参数
+///
+/// - `table`: 包括表头在内的整张表
+fn parse_mcfg(table: &[u8]) -> Vec<EcamRegion> {
+    // 表头之后是 8 字节的保留字段，然后是 16 字节的条目
+    table
+        .get(size_of::<SdtHeader>() + 8..)
+        .unwrap_or_default()
+        .chunks_exact(16)
+        .map(|entry| EcamRegion {
+            base: PhysAddr::new(u64::from_le_bytes(entry[0..8].try_into().unwrap())),
+            segment: u16::from_le_bytes([entry[8], entry[9]]),
+            start_bus: entry[10],
+            end_bus: entry[11],
+        })
+        .collect()
+}
+
+#[test_case]
+fn test_parse_madt_entries() {
+    let mut table = alloc::vec![0u8; size_of::<SdtHeader>()];
+    table.extend_from_slice(&0xfee0_0000u32.to_le_bytes());
+    table.extend_from_slice(&1u32.to_le_bytes());
+    // 处理器 0，APIC ID 0，已启用
+    table.extend_from_slice(&[MADT_LOCAL_APIC, 8, 0, 0, 1, 0, 0, 0]);
+    // IO APIC 1，位于 0xfec00000，从全局系统中断 0 开始
+    table.extend_from_slice(&[MADT_IO_APIC, 12, 1, 0, 0x00, 0x00, 0xc0, 0xfe, 0, 0, 0, 0]);
+    // IRQ0 连接到全局系统中断 2，使用总线默认的触发方式
+    table.extend_from_slice(&[MADT_INTERRUPT_OVERRIDE, 10, 0, 0, 2, 0, 0, 0, 0, 0]);
+    // IRQ9 为低电平有效的电平触发
+    table.extend_from_slice(&[MADT_INTERRUPT_OVERRIDE, 10, 0, 9, 9, 0, 0, 0, 0x0f, 0]);
+
+    let madt = parse_madt(&table).unwrap();
+    assert_eq!(madt.local_apic_address, PhysAddr::new(0xfee0_0000));
+    assert_eq!(madt.processors.len(), 1);
+    assert_eq!(
+        madt.io_apics,
+        [IoApic {
+            id: 1,
+            address: PhysAddr::new(0xfec0_0000),
+            gsi_base: 0,
+        }]
+    );
+    let timer = madt.isa_irq(0);
+    assert_eq!(timer.gsi, 2);
+    assert!(!timer.active_low && !timer.level_triggered);
+    let sci = madt.isa_irq(9);
+    assert!(sci.active_low && sci.level_triggered);
+    assert_eq!(madt.isa_irq(1).gsi, 1);
+}
+
+#[test_case]
+fn test_parse_hpet() {
+    let mut table = alloc::vec![0u8; size_of::<SdtHeader>()];
+    table.extend_from_slice(&0x8086_a201u32.to_le_bytes());
+    // 内存空间，64 位宽，位于 0xfed00000
+    table.extend_from_slice(&[ADDRESS_SPACE_MEMORY, 64, 0, 0]);
+    table.extend_from_slice(&0xfed0_0000u64.to_le_bytes());
+    table.extend_from_slice(&[0, 0x80, 0x00, 0]);
+    assert_eq!(
+        parse_hpet(&table),
+        Some(Hpet {
+            address: PhysAddr::new(0xfed0_0000),
+            number: 0,
+            min_tick: 0x80,
+        })
+    );
+    // I/O 空间中的 HPET 无法映射
+    table[size_of::<SdtHeader>() + 4] = 1;
+    assert_eq!(parse_hpet(&table), None);
+    assert_eq!(parse_hpet(&table[..size_of::<SdtHeader>() + 8]), None);
+}
+
+#[test_case]
+fn test_parse_mcfg() {
+    let mut table = alloc::vec![0u8; size_of::<SdtHeader>() + 8];
+    table.extend_from_slice(&0xb000_0000u64.to_le_bytes());
+    table.extend_from_slice(&[0, 0, 0, 0xff, 0, 0, 0, 0]);
+    assert_eq!(
+        parse_mcfg(&table),
+        [EcamRegion {
+            base: PhysAddr::new(0xb000_0000),
+            segment: 0,
+            start_bus: 0,
+            end_bus: 0xff,
+        }]
+    );
+    assert!(parse_mcfg(&table[..size_of::<SdtHeader>()]).is_empty());
+}
+
+#[test_case]
+fn test_parse_fadt() {
+    let mut table = alloc::vec![0u8; FADT_EXTENDED_LEN];
+    table[40..44].copy_from_slice(&0x7fe0_0040u32.to_le_bytes());
+    table[46] = 9;
+    table[48] = 0xb2;
+    table[52] = 0xf1;
+    table[64..68].copy_from_slice(&0x604u32.to_le_bytes());
+    table[76..80].copy_from_slice(&0x608u32.to_le_bytes());
+    table[112..116].copy_from_slice(&FADT_RESET_REGISTER.to_le_bytes());
+    table[116] = ADDRESS_SPACE_IO;
+    table[117] = 8;
+    table[120..128].copy_from_slice(&0xcf9u64.to_le_bytes());
+    table[128] = 0x06;
+    // 扩展的 PM1a 控制寄存器块优先于 32 位地址
+    table[172] = ADDRESS_SPACE_IO;
+    table[176..184].copy_from_slice(&0xb004u64.to_le_bytes());
+
+    let fadt = parse_fadt(&table).unwrap();
+    assert_eq!(fadt.dsdt, PhysAddr::new(0x7fe0_0040));
+    assert_eq!(fadt.sci_interrupt, 9);
+    assert_eq!(fadt.smi_command, 0xb2);
+    assert_eq!(fadt.acpi_enable, 0xf1);
+    assert_eq!(fadt.pm1a_control, 0xb004);
+    assert_eq!(fadt.pm1b_control, None);
+    assert_eq!(fadt.pm_timer, Some(0x608));
+    assert_eq!(fadt.reset_register.map(|gas| gas.address), Some(0xcf9));
+    assert_eq!(fadt.reset_value, 0x06);
+
+    // ACPI 1.0 的 FADT 没有复位寄存器和扩展地址
+    let fadt = parse_fadt(&table[..116]).unwrap();
+    assert_eq!(fadt.pm1a_control, 0x604);
+    assert_eq!(fadt.reset_register, None);
+}
+
+#[test_case]
+fn test_parse_s5() {
+    // QEMU 的 DSDT 中为 Name (_S5_, Package (0x04) { Zero, Zero, Zero, Zero })
+    let qemu = [
+        0x08, b'_', b'S', b'5', b'_', 0x12, 0x06, 0x04, 0x00, 0x00, 0x00, 0x00,
+    ];
+    assert_eq!(parse_s5(&qemu), Some((0, 0)));
+    let bochs = [
+        0x08, b'\\', b'_', b'S', b'5', b'_', 0x12, 0x0a, 0x04, 0x0a, 0x05, 0x0a, 0x07, 0x00,
+    ];
+    assert_eq!(parse_s5(&bochs), Some((5, 7)));
+    // 作为方法名或字符串的一部分出现的 _S5_ 不是对象定义
+    assert_eq!(parse_s5(&[0x14, b'_', b'S', b'5', b'_', 0x12, 0x06]), None);
+}
+
+#[test_case]
+fn test_checksum() {
+    assert!(checksum_ok(&[]));
+    assert!(checksum_ok(&[0x01, 0xff]));
+    assert!(checksum_ok(&[0x80, 0x80, 0x00]));
+    assert!(!checksum_ok(&[0x01, 0x02]));
+}
@@ -0,0 +1,78 @@
+//! 本模块实现了内核堆的映射和全局分配器
+
+use core::alloc::{GlobalAlloc, Layout};
+
+use linked_list_allocator::LockedHeap;
+use x86_64::VirtAddr;
+use x86_64::structures::paging::mapper::MapToError;
+use x86_64::structures::paging::{FrameAllocator, Mapper, Page, PageTableFlags, Size4KiB};
+
+/// 内核堆的起始虚拟地址
+pub const HEAP_START: usize = 0x_4444_4444_0000;
+/// 内核堆的大小
+pub const HEAP_SIZE: usize = 1024 * 1024;
+
+/// 在分配和释放期间禁用中断的堆，使中断处理函数和任务生成器也能安全地分配内存
+struct InterruptSafeHeap(LockedHeap);
+
+unsafe impl GlobalAlloc for InterruptSafeHeap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        use x86_64::instructions::interrupts;
+
+        interrupts::without_interrupts(|| unsafe { self.0.alloc(layout) })
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        use x86_64::instructions::interrupts;
+
+        interrupts::without_interrupts(|| unsafe { self.0.dealloc(ptr, layout) })
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: InterruptSafeHeap = InterruptSafeHeap(LockedHeap::empty());
+
+/// 将内核堆区域映射到物理帧，并初始化全局分配器
+///
+/// # 参数
+///
+/// - `mapper`: 页表映射器
+/// - `frame_allocator`: 物理帧分配器
+pub fn init_heap(
+    mapper: &mut impl Mapper<Size4KiB>,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+) -> Result<(), MapToError<Size4KiB>> {
+    let page_range = {
+        let heap_start = VirtAddr::new(HEAP_START as u64);
+        let heap_end = heap_start + HEAP_SIZE as u64 - 1u64;
+        let heap_start_page = Page::containing_address(heap_start);
+        let heap_end_page = Page::containing_address(heap_end);
+        Page::range_inclusive(heap_start_page, heap_end_page)
+    };
+
+    for page in page_range {
+        let frame = frame_allocator
+            .allocate_frame()
+            .ok_or(MapToError::FrameAllocationFailed)?;
+        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+        unsafe { mapper.map_to(page, frame, flags, frame_allocator)?.flush() };
+    }
+
+    unsafe {
+        ALLOCATOR.0.lock().init(HEAP_START as *mut u8, HEAP_SIZE);
+    }
+
+    Ok(())
+}
+
+/// 获取内核堆的使用情况
+///
+/// # 返回
+///
+/// `(已使用字节数, 总字节数)`，分配器正被占用时返回 `None`，因此可以在中断上下文中调用
+pub fn heap_usage() -> Option<(usize, usize)> {
+    ALLOCATOR
+        .0
+        .try_lock()
+        .map(|heap| (heap.used(), heap.size()))
+}
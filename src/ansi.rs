@@ -0,0 +1,183 @@
+//! 本模块实现了 ANSI 转义序列的解析状态机
+//!
+//! 解析器只负责将字节流切分为普通字符和 CSI 序列，具体的解释由各输出后端完成
+
+/// CSI 序列最多支持的参数个数
+pub const MAX_PARAMS: usize = 8;
+
+/// 转义字符
+pub const ESC: u8 = 0x1b;
+
+/// 解析器状态
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Ground, // 普通字符
+    Escape, // 已读入 ESC
+    Csi,    // 已读入 ESC [
+}
+
+/// 解析得到的动作
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    /// 普通字节，原样输出
+    Print(u8),
+    /// 完整的 CSI 序列
+    Csi(CsiSequence),
+}
+
+/// CSI 序列：`ESC [ 参数 ; 参数 ... 终止字节`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CsiSequence {
+    params: [u16; MAX_PARAMS],
+    len: usize,
+    /// 终止字节，决定序列的含义（如 `m` 为 SGR，`H` 为光标定位）
+    pub final_byte: u8,
+}
+
+impl CsiSequence {
+    /// 已解析的参数
+    pub fn params(&self) -> &[u16] {
+        &self.params[..self.len]
+    }
+
+    /// 获取第 `index` 个参数，缺省或为 0 时返回 `default`
+    ///
+    /// # 参数
+    ///
+    /// - `index`: 参数下标
+    /// - `default`: 默认值
+    pub fn param_or(&self, index: usize, default: u16) -> u16 {
+        match self.params().get(index) {
+            Some(&value) if value != 0 => value,
+            _ => default,
+        }
+    }
+}
+
+/// ANSI 转义序列解析器
+pub struct Parser {
+    state: State,
+    params: [u16; MAX_PARAMS],
+    len: usize,
+}
+
+impl Parser {
+    /// 创建处于初始状态的解析器
+    pub const fn new() -> Self {
+        Self {
+            state: State::Ground,
+            params: [0; MAX_PARAMS],
+            len: 0,
+        }
+    }
+
+    /// 输入一个字节
+    ///
+    /// # 参数
+    ///
+    /// - `byte`: 输入的字节
+    ///
+    /// # 返回
+    ///
+    /// 字节属于尚未结束的转义序列时返回 `None`
+    pub fn advance(&mut self, byte: u8) -> Option<Action> {
+        match self.state {
+            State::Ground if byte == ESC => {
+                self.state = State::Escape;
+                None
+            }
+            State::Ground => Some(Action::Print(byte)),
+            State::Escape if byte == b'[' => {
+                self.state = State::Csi;
+                self.params = [0; MAX_PARAMS];
+                self.len = 0;
+                None
+            }
+            // 不支持的转义序列，直接丢弃
+            State::Escape => {
+                self.state = State::Ground;
+                None
+            }
+            State::Csi => self.advance_csi(byte),
+        }
+    }
+
+    fn advance_csi(&mut self, byte: u8) -> Option<Action> {
+        match byte {
+            b'0'..=b'9' => {
+                if self.len == 0 {
+                    self.len = 1;
+                }
+                if let Some(param) = self.params.get_mut(self.len - 1) {
+                    *param = param
+                        .saturating_mul(10)
+                        .saturating_add(u16::from(byte - b'0'));
+                }
+                None
+            }
+            b';' => {
+                // 第一个参数为空时补一个缺省参数
+                if self.len == 0 {
+                    self.len = 1;
+                }
+                self.len = (self.len + 1).min(MAX_PARAMS);
+                None
+            }
+            0x40..=0x7e => {
+                self.state = State::Ground;
+                Some(Action::Csi(CsiSequence {
+                    params: self.params,
+                    len: self.len,
+                    final_byte: byte,
+                }))
+            }
+            // 非法字节，放弃当前序列
+            _ => {
+                self.state = State::Ground;
+                None
+            }
+        }
+    }
+}
+
+impl Default for Parser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[test_case]
+fn test_parse_plain_bytes() {
+    let mut parser = Parser::new();
+    assert_eq!(parser.advance(b'a'), Some(Action::Print(b'a')));
+    assert_eq!(parser.advance(b'\n'), Some(Action::Print(b'\n')));
+}
+
+#[test_case]
+fn test_parse_csi_params() {
+    let mut parser = Parser::new();
+    let mut last = None;
+    for &byte in b"\x1b[1;31m" {
+        last = parser.advance(byte);
+    }
+    let Some(Action::Csi(seq)) = last else {
+        panic!("expected CSI sequence, got {:?}", last);
+    };
+    assert_eq!(seq.final_byte, b'm');
+    assert_eq!(seq.params(), &[1, 31]);
+    assert_eq!(parser.advance(b'x'), Some(Action::Print(b'x')));
+}
+
+#[test_case]
+fn test_parse_csi_defaults() {
+    let mut parser = Parser::new();
+    let mut last = None;
+    for &byte in b"\x1b[;5H" {
+        last = parser.advance(byte);
+    }
+    let Some(Action::Csi(seq)) = last else {
+        panic!("expected CSI sequence, got {:?}", last);
+    };
+    assert_eq!(seq.param_or(0, 1), 1);
+    assert_eq!(seq.param_or(1, 1), 5);
+}
@@ -0,0 +1,247 @@
+//! 本模块实现了本地 APIC（LAPIC）的启用和访问
+//!
+//! 每个处理器都有自己的本地 APIC。xAPIC 模式下寄存器位于同一段物理地址上，访问到的是当前处理器的那一个，
+//! 寄存器都是 32 位的，按 16 字节对齐；处理器支持时改用 x2APIC 模式，通过 MSR 访问同样的寄存器。
+//!
+//! 启用后本地 APIC 负责发送处理器间中断（IPI）、接收经它投递的中断并接受中断结束（EOI）信号。
+//! 引导处理器的 LINT0 保持为 ExtINT，8259 的中断在被改由 APIC 投递之前照常到达。
+//! 每个处理器的定时器见 [`timer`] 子模块
+
+use core::sync::atomic::{AtomicU8, AtomicU64, Ordering};
+
+use x86_64::{PhysAddr, VirtAddr};
+
+use crate::arch::msr::{APIC_BASE_ENABLE, APIC_BASE_X2APIC, IA32_APIC_BASE, Msr};
+use crate::cpu::{self, Feature};
+use crate::memory;
+
+pub mod timer;
+
+/// 本地 APIC 寄存器区域的大小
+const LAPIC_SIZE: u64 = 4096;
+
+/// 伪中断的向量号，低 4 位必须全为 1
+pub const SPURIOUS_VECTOR: u8 = 0xff;
+
+/// APIC ID 寄存器
+const REG_ID: usize = 0x20;
+/// 任务优先级寄存器
+const REG_TPR: usize = 0x80;
+/// 中断结束寄存器，写入 0 表示当前中断处理完毕
+const REG_EOI: usize = 0xb0;
+/// 伪中断向量寄存器，包含软件启用位
+const REG_SVR: usize = 0xf0;
+/// 中断命令寄存器的低 32 位，写入时发送 IPI
+const REG_ICR_LOW: usize = 0x300;
+/// 中断命令寄存器的高 32 位，其中高 8 位为目标 APIC ID
+const REG_ICR_HIGH: usize = 0x310;
+/// LINT0 引脚的本地向量表项
+const REG_LVT_LINT0: usize = 0x350;
+/// LINT1 引脚的本地向量表项
+const REG_LVT_LINT1: usize = 0x360;
+
+/// SVR：软件启用本地 APIC
+const SVR_ENABLE: u32 = 1 << 8;
+
+/// LVT：投递模式 NMI
+const LVT_NMI: u32 = 0b100 << 8;
+/// LVT：投递模式 ExtINT，由 8259 提供向量号
+const LVT_EXTINT: u32 = 0b111 << 8;
+/// LVT：屏蔽该中断源
+const LVT_MASKED: u32 = 1 << 16;
+
+/// ICR：投递模式 NMI，忽略向量号
+const ICR_NMI: u32 = 0b100 << 8;
+/// ICR：投递模式 INIT
+const ICR_INIT: u32 = 0b101 << 8;
+/// ICR：投递模式 STARTUP，低 8 位为启动代码所在的页号
+const ICR_STARTUP: u32 = 0b110 << 8;
+/// ICR：上一个 IPI 仍在发送中
+const ICR_SEND_PENDING: u32 = 1 << 12;
+/// ICR：电平有效（assert）
+const ICR_LEVEL_ASSERT: u32 = 1 << 14;
+/// ICR：电平触发
+const ICR_TRIGGER_LEVEL: u32 = 1 << 15;
+
+/// x2APIC 模式下第一个寄存器对应的 MSR，寄存器偏移除以 16 后与之相加
+const X2APIC_MSR_BASE: u32 = 0x800;
+
+/// 访问模式：尚未启用
+const MODE_DISABLED: u8 = 0;
+/// 访问模式：通过 MMIO 访问的 xAPIC
+const MODE_XAPIC: u8 = 1;
+/// 访问模式：通过 MSR 访问的 x2APIC
+const MODE_X2APIC: u8 = 2;
+
+/// 本地 APIC 的访问模式，所有处理器相同
+static MODE: AtomicU8 = AtomicU8::new(MODE_DISABLED);
+
+/// xAPIC 寄存器映射到的虚拟地址
+static BASE: AtomicU64 = AtomicU64::new(0);
+
+/// 启用引导处理器的本地 APIC，支持时切换到 x2APIC 模式
+///
+/// # 参数
+///
+/// - `phys`: xAPIC 寄存器的物理地址，来自 MADT
+///
+/// # 返回
+///
+/// 映射寄存器失败时返回 `false`
+pub fn init(phys: PhysAddr) -> bool {
+    if is_initialized() {
+        return true;
+    }
+    let mode = if cpu::has(Feature::X2Apic) {
+        MODE_X2APIC
+    } else {
+        let Some(base) = memory::map_mmio(phys, LAPIC_SIZE) else {
+            return false;
+        };
+        BASE.store(base.as_u64(), Ordering::Release);
+        MODE_XAPIC
+    };
+    MODE.store(mode, Ordering::Release);
+    enable(true);
+    log::info!(
+        "local APIC {} enabled in {} mode",
+        id(),
+        if is_x2apic() { "x2APIC" } else { "xAPIC" }
+    );
+    true
+}
+
+/// 以与引导处理器相同的模式启用应用处理器的本地 APIC，需要在 [`init`] 之后调用
+pub fn init_ap() {
+    assert!(is_initialized(), "local APIC not initialized");
+    enable(false);
+}
+
+/// 在当前处理器上启用本地 APIC
+///
+/// # 参数
+///
+/// - `bsp`: 是否为引导处理器，只有引导处理器接收 8259 的中断
+fn enable(bsp: bool) {
+    let mut flags = APIC_BASE_ENABLE;
+    if is_x2apic() {
+        flags |= APIC_BASE_X2APIC;
+    }
+    unsafe { IA32_APIC_BASE.update(flags, 0) };
+    // 接受所有优先级的中断
+    write(REG_TPR, 0);
+    write(REG_LVT_LINT0, if bsp { LVT_EXTINT } else { LVT_MASKED });
+    write(REG_LVT_LINT1, LVT_NMI);
+    write(REG_SVR, SVR_ENABLE | u32::from(SPURIOUS_VECTOR));
+}
+
+/// 本地 APIC 是否已启用
+pub fn is_initialized() -> bool {
+    MODE.load(Ordering::Acquire) != MODE_DISABLED
+}
+
+/// 是否处于 x2APIC 模式
+pub fn is_x2apic() -> bool {
+    MODE.load(Ordering::Acquire) == MODE_X2APIC
+}
+
+/// x2APIC 模式下寄存器对应的 MSR
+fn msr(offset: usize) -> Msr {
+    Msr::new(X2APIC_MSR_BASE + (offset >> 4) as u32)
+}
+
+/// xAPIC 模式下寄存器的地址
+fn register(offset: usize) -> *mut u32 {
+    let base = BASE.load(Ordering::Acquire);
+    assert_ne!(base, 0, "local APIC not initialized");
+    (VirtAddr::new(base) + offset as u64).as_mut_ptr()
+}
+
+/// 读取寄存器
+fn read(offset: usize) -> u32 {
+    if is_x2apic() {
+        unsafe { msr(offset).read() as u32 }
+    } else {
+        unsafe { register(offset).read_volatile() }
+    }
+}
+
+/// 写入寄存器
+fn write(offset: usize, value: u32) {
+    if is_x2apic() {
+        unsafe { msr(offset).write(u64::from(value)) }
+    } else {
+        unsafe { register(offset).write_volatile(value) }
+    }
+}
+
+/// 当前处理器的 APIC ID
+pub fn id() -> u32 {
+    if is_x2apic() {
+        read(REG_ID)
+    } else {
+        read(REG_ID) >> 24
+    }
+}
+
+/// 通知本地 APIC 当前中断已处理完毕
+pub fn eoi() {
+    write(REG_EOI, 0);
+}
+
+/// 向另一个处理器发送 IPI，等待本地 APIC 把它发出
+///
+/// # 参数
+///
+/// - `apic_id`: 目标处理器的 APIC ID
+/// - `command`: 写入 ICR 低 32 位的值
+fn send_ipi(apic_id: u32, command: u32) {
+    if is_x2apic() {
+        // x2APIC 的 ICR 是一个 64 位 MSR，写入即发送，没有发送中的状态位
+        unsafe { msr(REG_ICR_LOW).write((u64::from(apic_id) << 32) | u64::from(command)) };
+        return;
+    }
+    write(REG_ICR_HIGH, apic_id << 24);
+    write(REG_ICR_LOW, command);
+    while read(REG_ICR_LOW) & ICR_SEND_PENDING != 0 {
+        core::hint::spin_loop();
+    }
+}
+
+/// 向处理器发送 NMI，即使目标禁用了中断也会被处理
+///
+/// # 参数
+///
+/// - `apic_id`: 目标处理器的 APIC ID
+pub fn send_nmi(apic_id: u32) {
+    send_ipi(apic_id, ICR_NMI);
+}
+
+/// 向处理器发送 INIT IPI，让它复位并等待 STARTUP IPI
+///
+/// # 参数
+///
+/// - `apic_id`: 目标处理器的 APIC ID
+pub fn send_init(apic_id: u32) {
+    send_ipi(apic_id, ICR_INIT | ICR_LEVEL_ASSERT | ICR_TRIGGER_LEVEL);
+    // 较早的处理器还需要一次撤销电平的 INIT
+    send_ipi(apic_id, ICR_INIT | ICR_TRIGGER_LEVEL);
+}
+
+/// 向处理器发送 STARTUP IPI，让它在实模式下从 `page * 4096` 处开始执行
+///
+/// # 参数
+///
+/// - `apic_id`: 目标处理器的 APIC ID
+/// - `page`: 启动代码所在的物理页号，必须位于 1 MiB 以下
+pub fn send_startup(apic_id: u32, page: u8) {
+    send_ipi(apic_id, ICR_STARTUP | u32::from(page));
+}
+
+#[test_case]
+fn test_x2apic_msr_mapping() {
+    assert_eq!(msr(REG_ID).index(), 0x802);
+    assert_eq!(msr(REG_EOI).index(), 0x80b);
+    assert_eq!(msr(REG_ICR_LOW).index(), 0x830);
+    assert_eq!(SPURIOUS_VECTOR & 0xf, 0xf);
+}
@@ -0,0 +1,136 @@
+//! 本模块实现了本地 APIC 定时器
+//!
+//! 每个处理器的本地 APIC 都有一个按总线时钟分频递减的定时器，减到 0 时产生中断。
+//! 它的频率没有标准值，启动时用 HPET 或 PIT 的 2 号通道计时 10 毫秒来校准，所有处理器共用校准结果。
+//!
+//! 定时器可以工作在周期模式，也可以只在给定的时间之后触发一次。各处理器的定时器中断驱动本处理器的抢占，
+//! 全局的时钟仍由 PIT 推进
+
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::time::Duration;
+
+use super::{read, write};
+use crate::time;
+
+/// 定时器中断的向量号
+pub const TIMER_VECTOR: u8 = 0xf0;
+
+/// 定时器的本地向量表项
+const REG_LVT_TIMER: usize = 0x320;
+/// 初始计数寄存器，写入后开始递减
+const REG_INITIAL_COUNT: usize = 0x380;
+/// 当前计数寄存器
+const REG_CURRENT_COUNT: usize = 0x390;
+/// 分频配置寄存器
+const REG_DIVIDE: usize = 0x3e0;
+
+/// 分频配置：16 分频
+const DIVIDE_BY_16: u32 = 0b0011;
+
+/// LVT：屏蔽定时器中断
+const LVT_MASKED: u32 = 1 << 16;
+/// LVT：周期模式
+const LVT_PERIODIC: u32 = 0b01 << 17;
+
+/// 校准持续的毫秒数
+const CALIBRATION_MS: u64 = 10;
+
+/// 每毫秒递减的计数，为 0 时尚未校准
+static COUNTS_PER_MS: AtomicU64 = AtomicU64::new(0);
+
+/// 校准定时器的频率，并让当前处理器的定时器以 tick 为周期运行
+///
+/// 需要在本地 APIC 启用之后调用，校准只进行一次
+pub fn init() {
+    if COUNTS_PER_MS.load(Ordering::Acquire) == 0 {
+        let counts = calibrate();
+        COUNTS_PER_MS.store(counts, Ordering::Release);
+        log::info!("APIC timer counts at {} kHz", counts);
+    }
+    start_periodic(time::tick_duration());
+}
+
+/// 让应用处理器的定时器以 tick 为周期运行，引导处理器尚未校准时什么都不做
+pub fn init_ap() {
+    if is_calibrated() {
+        start_periodic(time::tick_duration());
+    }
+}
+
+/// 定时器是否已经校准
+pub fn is_calibrated() -> bool {
+    COUNTS_PER_MS.load(Ordering::Acquire) != 0
+}
+
+/// 定时器分频之后递减的频率（Hz），尚未校准时返回 0
+pub fn frequency_hz() -> u64 {
+    COUNTS_PER_MS.load(Ordering::Acquire) * 1000
+}
+
+/// 计时 [`CALIBRATION_MS`] 毫秒，统计定时器在这段时间内递减的计数
+///
+/// # 返回
+///
+/// 每毫秒递减的计数
+fn calibrate() -> u64 {
+    write(REG_DIVIDE, DIVIDE_BY_16);
+    write(REG_LVT_TIMER, LVT_MASKED);
+    let elapsed = time::measure(
+        CALIBRATION_MS,
+        || write(REG_INITIAL_COUNT, u32::MAX),
+        || u32::MAX - read(REG_CURRENT_COUNT),
+    );
+    write(REG_INITIAL_COUNT, 0);
+    (u64::from(elapsed) / CALIBRATION_MS).max(1)
+}
+
+/// 把时长换算成定时器的计数，至少为 1
+///
+/// # 参数
+///
+/// - `duration`: 时长
+fn counts_for(duration: Duration) -> u32 {
+    let per_ms = COUNTS_PER_MS.load(Ordering::Acquire);
+    assert_ne!(per_ms, 0, "APIC timer not calibrated");
+    let counts = duration.as_nanos() * u128::from(per_ms) / 1_000_000;
+    counts.clamp(1, u128::from(u32::MAX)) as u32
+}
+
+/// 让当前处理器的定时器周期性地产生中断
+///
+/// # 参数
+///
+/// - `period`: 中断的间隔
+pub fn start_periodic(period: Duration) {
+    let counts = counts_for(period);
+    write(REG_DIVIDE, DIVIDE_BY_16);
+    write(REG_LVT_TIMER, LVT_PERIODIC | u32::from(TIMER_VECTOR));
+    write(REG_INITIAL_COUNT, counts);
+}
+
+/// 让当前处理器的定时器在 `after` 之后产生一次中断，取代之前的设置
+///
+/// 不再需要周期性中断的处理器可以用它只在下一个到期时间醒来
+///
+/// # 参数
+///
+/// - `after`: 距离中断的时长
+pub fn set_oneshot(after: Duration) {
+    let counts = counts_for(after);
+    write(REG_DIVIDE, DIVIDE_BY_16);
+    write(REG_LVT_TIMER, u32::from(TIMER_VECTOR));
+    write(REG_INITIAL_COUNT, counts);
+}
+
+/// 停止当前处理器的定时器
+pub fn stop() {
+    write(REG_LVT_TIMER, LVT_MASKED);
+    write(REG_INITIAL_COUNT, 0);
+}
+
+/// 定时器是否在驱动当前处理器的抢占，此时 PIT 的中断只推进全局时钟
+///
+/// 一次性中断触发之后仍然返回 `true`，设置者负责再次设置
+pub fn drives_preemption() -> bool {
+    super::is_initialized() && read(REG_LVT_TIMER) & LVT_MASKED == 0
+}
@@ -0,0 +1,7 @@
+//! 本模块封装了 x86_64 架构相关的底层指令
+//!
+//! 驱动程序通过这里的类型访问硬件，而不必各自编写 `unsafe` 的内联汇编
+
+pub mod msr;
+pub mod port;
+pub mod tsc;
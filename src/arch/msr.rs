@@ -0,0 +1,112 @@
+//! 本模块实现了型号特定寄存器（MSR）的读写
+//!
+//! 常用的 MSR 以 [`Msr`] 常量的形式给出，供 APIC、系统调用和线程局部存储等子系统使用
+
+use core::arch::asm;
+
+/// 型号特定寄存器
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Msr(u32);
+
+/// 本地 APIC 的基地址和启用位
+pub const IA32_APIC_BASE: Msr = Msr::new(0x1b);
+/// TSC-deadline 模式下的本地 APIC 定时器截止时间
+pub const IA32_TSC_DEADLINE: Msr = Msr::new(0x6e0);
+/// 扩展功能启用寄存器
+pub const IA32_EFER: Msr = Msr::new(0xc000_0080);
+/// `syscall`/`sysret` 使用的段选择子
+pub const IA32_STAR: Msr = Msr::new(0xc000_0081);
+/// 64 位模式下 `syscall` 的入口地址
+pub const IA32_LSTAR: Msr = Msr::new(0xc000_0082);
+/// `syscall` 时需要清除的 RFLAGS 位
+pub const IA32_FMASK: Msr = Msr::new(0xc000_0084);
+/// FS 段基址
+pub const IA32_FS_BASE: Msr = Msr::new(0xc000_0100);
+/// GS 段基址
+pub const IA32_GS_BASE: Msr = Msr::new(0xc000_0101);
+/// `swapgs` 时与 GS 段基址交换的值
+pub const IA32_KERNEL_GS_BASE: Msr = Msr::new(0xc000_0102);
+
+/// EFER：启用 `syscall`/`sysret`
+pub const EFER_SCE: u64 = 1 << 0;
+/// EFER：启用长模式
+pub const EFER_LME: u64 = 1 << 8;
+/// EFER：长模式已激活（只读）
+pub const EFER_LMA: u64 = 1 << 10;
+/// EFER：启用不可执行页
+pub const EFER_NXE: u64 = 1 << 11;
+
+/// APIC_BASE：当前处理器是引导处理器
+pub const APIC_BASE_BSP: u64 = 1 << 8;
+/// APIC_BASE：启用 x2APIC 模式
+pub const APIC_BASE_X2APIC: u64 = 1 << 10;
+/// APIC_BASE：启用本地 APIC
+pub const APIC_BASE_ENABLE: u64 = 1 << 11;
+/// APIC_BASE：基地址所在的位
+pub const APIC_BASE_ADDRESS_MASK: u64 = 0x000f_ffff_ffff_f000;
+
+impl Msr {
+    /// 创建一个 MSR
+    ///
+    /// # 参数
+    ///
+    /// - `index`: 寄存器编号
+    pub const fn new(index: u32) -> Self {
+        Self(index)
+    }
+
+    /// 寄存器编号
+    pub const fn index(self) -> u32 {
+        self.0
+    }
+
+    /// 读取寄存器
+    ///
+    /// # Safety
+    ///
+    /// 调用者必须保证当前处理器支持该寄存器，否则会触发 #GP
+    pub unsafe fn read(self) -> u64 {
+        let (low, high): (u32, u32);
+        unsafe {
+            asm!("rdmsr", in("ecx") self.0, out("eax") low, out("edx") high, options(nomem, nostack, preserves_flags));
+        }
+        (u64::from(high) << 32) | u64::from(low)
+    }
+
+    /// 写入寄存器
+    ///
+    /// # Safety
+    ///
+    /// 调用者必须保证当前处理器支持该寄存器，并且写入的值不会破坏内存安全
+    pub unsafe fn write(self, value: u64) {
+        let low = value as u32;
+        let high = (value >> 32) as u32;
+        unsafe {
+            asm!("wrmsr", in("ecx") self.0, in("eax") low, in("edx") high, options(nostack, preserves_flags));
+        }
+    }
+
+    /// 读取寄存器，设置 `set` 中的位并清除 `clear` 中的位后写回
+    ///
+    /// # 参数
+    ///
+    /// - `set`: 要设置的位
+    /// - `clear`: 要清除的位
+    ///
+    /// # Safety
+    ///
+    /// 与 [`Msr::read`] 和 [`Msr::write`] 相同
+    pub unsafe fn update(self, set: u64, clear: u64) {
+        unsafe {
+            let value = self.read();
+            self.write((value & !clear) | set);
+        }
+    }
+}
+
+#[test_case]
+fn test_efer_reports_long_mode() {
+    let efer = unsafe { IA32_EFER.read() };
+    assert_ne!(efer & EFER_LME, 0);
+    assert_ne!(efer & EFER_LMA, 0);
+}
@@ -0,0 +1,132 @@
+//! 本模块实现了 I/O 端口的读写
+//!
+//! [`Port`] 按数据宽度区分 `in`/`out` 指令，支持 `u8`、`u16` 和 `u32` 三种宽度
+
+use core::arch::asm;
+use core::marker::PhantomData;
+
+/// 可以通过 I/O 端口读写的数据宽度
+pub trait PortValue: Copy {
+    /// 从端口读取一个值
+    ///
+    /// # Safety
+    ///
+    /// 读取端口可能产生副作用，调用者必须保证这对该端口是安全的
+    unsafe fn read_from_port(port: u16) -> Self;
+
+    /// 向端口写入一个值
+    ///
+    /// # Safety
+    ///
+    /// 写入端口可能改变硬件状态，调用者必须保证这对该端口是安全的
+    unsafe fn write_to_port(port: u16, value: Self);
+}
+
+impl PortValue for u8 {
+    unsafe fn read_from_port(port: u16) -> Self {
+        let value: u8;
+        unsafe {
+            asm!("in al, dx", out("al") value, in("dx") port, options(nomem, nostack, preserves_flags));
+        }
+        value
+    }
+
+    unsafe fn write_to_port(port: u16, value: Self) {
+        unsafe {
+            asm!("out dx, al", in("dx") port, in("al") value, options(nomem, nostack, preserves_flags));
+        }
+    }
+}
+
+impl PortValue for u16 {
+    unsafe fn read_from_port(port: u16) -> Self {
+        let value: u16;
+        unsafe {
+            asm!("in ax, dx", out("ax") value, in("dx") port, options(nomem, nostack, preserves_flags));
+        }
+        value
+    }
+
+    unsafe fn write_to_port(port: u16, value: Self) {
+        unsafe {
+            asm!("out dx, ax", in("dx") port, in("ax") value, options(nomem, nostack, preserves_flags));
+        }
+    }
+}
+
+impl PortValue for u32 {
+    unsafe fn read_from_port(port: u16) -> Self {
+        let value: u32;
+        unsafe {
+            asm!("in eax, dx", out("eax") value, in("dx") port, options(nomem, nostack, preserves_flags));
+        }
+        value
+    }
+
+    unsafe fn write_to_port(port: u16, value: Self) {
+        unsafe {
+            asm!("out dx, eax", in("dx") port, in("eax") value, options(nomem, nostack, preserves_flags));
+        }
+    }
+}
+
+/// I/O 端口
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Port<T> {
+    port: u16,
+    _phantom: PhantomData<T>,
+}
+
+impl<T: PortValue> Port<T> {
+    /// 创建一个 I/O 端口
+    ///
+    /// # 参数
+    ///
+    /// - `port`: 端口号
+    pub const fn new(port: u16) -> Self {
+        Self {
+            port,
+            _phantom: PhantomData,
+        }
+    }
+
+    /// 从端口读取一个值
+    ///
+    /// # Safety
+    ///
+    /// 读取端口可能产生副作用，调用者必须保证这对该端口是安全的
+    pub unsafe fn read(&mut self) -> T {
+        unsafe { T::read_from_port(self.port) }
+    }
+
+    /// 向端口写入一个值
+    ///
+    /// # Safety
+    ///
+    /// 写入端口可能改变硬件状态，调用者必须保证这对该端口是安全的
+    pub unsafe fn write(&mut self, value: T) {
+        unsafe { T::write_to_port(self.port, value) }
+    }
+}
+
+#[test_case]
+fn test_port_round_trip() {
+    use x86_64::instructions::interrupts;
+
+    // CRT 控制器的光标位置低字节寄存器可读可写
+    const CRTC_ADDRESS_PORT: u16 = 0x3d4;
+    const CRTC_DATA_PORT: u16 = 0x3d5;
+    const CRTC_CURSOR_LOCATION_LOW: u8 = 0x0f;
+
+    interrupts::without_interrupts(|| {
+        let mut address = Port::<u8>::new(CRTC_ADDRESS_PORT);
+        let mut data = Port::<u8>::new(CRTC_DATA_PORT);
+        unsafe {
+            address.write(CRTC_CURSOR_LOCATION_LOW);
+            let saved = data.read();
+            data.write(saved ^ 0x55);
+            assert_eq!(data.read(), saved ^ 0x55);
+            data.write(saved);
+        }
+    });
+}
@@ -0,0 +1,10 @@
+//! 本模块实现了时间戳计数器（TSC）的读取
+//!
+//! TSC 随 CPU 时钟递增，读取开销很小，适合统计短时间间隔；
+//! 它的频率与 tick 无关，换算成时间需要另外校准
+
+/// 读取时间戳计数器
+pub fn read() -> u64 {
+    // `rdtsc` 在所有 x86_64 处理器上都可用
+    unsafe { core::arch::x86_64::_rdtsc() }
+}
@@ -0,0 +1,230 @@
+//! 本模块实现了块设备的公共接口和设备表
+//!
+//! 磁盘驱动实现 [`BlockDevice`]，以固定大小的块为单位读写；[`init`] 探测所有驱动支持的控制器，
+//! 找到的设备登记在设备表中，按名称查找
+
+pub mod ahci;
+pub mod ata;
+pub mod nvme;
+pub mod virtio;
+
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::fmt;
+use core::time::Duration;
+
+use spin::Mutex;
+
+use crate::thread::WaitQueue;
+use crate::time::{self, Instant};
+
+/// 扇区的大小，也是目前所有驱动的块大小
+pub const SECTOR_SIZE: usize = 512;
+
+/// 块设备读写时的错误
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockError {
+    OutOfRange, // 访问的块超出了设备的容量或驱动能寻址的范围
+    BadBuffer,  // 缓冲区的长度不是块大小的整数倍
+    Timeout,    // 设备在限定的时间内没有完成请求
+    Device,     // 设备报告了错误
+}
+
+impl fmt::Display for BlockError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BlockError::OutOfRange => write!(f, "block out of range"),
+            BlockError::BadBuffer => write!(f, "buffer is not a whole number of blocks"),
+            BlockError::Timeout => write!(f, "device timed out"),
+            BlockError::Device => write!(f, "device error"),
+        }
+    }
+}
+
+/// 块设备
+pub trait BlockDevice: Send + Sync {
+    /// 设备名，例如 `ata0`
+    fn name(&self) -> &str;
+
+    /// 块的字节数
+    fn block_size(&self) -> usize {
+        SECTOR_SIZE
+    }
+
+    /// 设备的块数
+    fn block_count(&self) -> u64;
+
+    /// 从第 `lba` 块开始读取，填满 `buf`
+    ///
+    /// # 参数
+    ///
+    /// - `lba`: 起始块号
+    /// - `buf`: 长度为块大小整数倍的缓冲区
+    fn read_blocks(&self, lba: u64, buf: &mut [u8]) -> Result<(), BlockError>;
+
+    /// 从第 `lba` 块开始写入 `buf` 中的数据
+    ///
+    /// # 参数
+    ///
+    /// - `lba`: 起始块号
+    /// - `buf`: 长度为块大小整数倍的缓冲区
+    fn write_blocks(&self, lba: u64, buf: &[u8]) -> Result<(), BlockError>;
+
+    /// 把设备缓存中的数据写入介质
+    fn flush(&self) -> Result<(), BlockError> {
+        Ok(())
+    }
+}
+
+/// 检查一次读写请求的范围
+///
+/// # 参数
+///
+/// - `device`: 块设备
+/// - `lba`: 起始块号
+/// - `len`: 缓冲区的字节数
+///
+/// # 返回
+///
+/// 请求的块数
+pub fn check_request(device: &dyn BlockDevice, lba: u64, len: usize) -> Result<u64, BlockError> {
+    if !len.is_multiple_of(device.block_size()) {
+        return Err(BlockError::BadBuffer);
+    }
+    let count = (len / device.block_size()) as u64;
+    match lba.checked_add(count) {
+        Some(end) if end <= device.block_count() => Ok(count),
+        _ => Err(BlockError::OutOfRange),
+    }
+}
+
+/// 等待设备完成请求
+///
+/// 设备的中断可用时挂起当前线程，每次中断处理函数唤醒 `queue` 之后检查一次；否则轮询
+///
+/// # 参数
+///
+/// - `queue`: 中断处理函数唤醒的等待队列
+/// - `interrupts`: 设备的中断是否可用
+/// - `timeout`: 最长的等待时间
+/// - `poll`: 检查请求是否完成，完成时返回结果
+pub(crate) fn wait_for<T>(
+    queue: &Arc<WaitQueue>,
+    interrupts: bool,
+    timeout: Duration,
+    mut poll: impl FnMut() -> Option<T>,
+) -> Result<T, BlockError> {
+    use x86_64::instructions::interrupts as cpu;
+
+    if !interrupts {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Some(result) = poll() {
+                return Ok(result);
+            }
+            if Instant::now() >= deadline {
+                return Err(BlockError::Timeout);
+            }
+            core::hint::spin_loop();
+        }
+    }
+    // 到期时由定时器唤醒等待队列，按 tick 判断是否超时，与定时器一致
+    let ticks = time::duration_to_ticks(timeout).max(1);
+    let deadline = time::ticks() + ticks;
+    let timer = time::wake_after(ticks, queue.clone());
+    let result = cpu::without_interrupts(|| {
+        loop {
+            if let Some(result) = poll() {
+                break Ok(result);
+            }
+            if time::ticks() >= deadline {
+                break Err(BlockError::Timeout);
+            }
+            queue.block_current();
+        }
+    });
+    timer.cancel();
+    result
+}
+
+/// 已找到的块设备
+static DEVICES: Mutex<Vec<Arc<dyn BlockDevice>>> = Mutex::new(Vec::new());
+
+/// 登记块设备
+///
+/// # 参数
+///
+/// - `device`: 块设备，名称应在设备表中唯一
+pub fn register(device: Arc<dyn BlockDevice>) {
+    log::info!(
+        "{}: {} blocks of {} bytes ({} MiB)",
+        device.name(),
+        device.block_count(),
+        device.block_size(),
+        (device.block_count() * device.block_size() as u64) >> 20
+    );
+    DEVICES.lock().push(device);
+}
+
+/// 所有已登记的块设备
+pub fn devices() -> Vec<Arc<dyn BlockDevice>> {
+    DEVICES.lock().clone()
+}
+
+/// 按名称查找块设备
+///
+/// # 参数
+///
+/// - `name`: 设备名
+pub fn find(name: &str) -> Option<Arc<dyn BlockDevice>> {
+    DEVICES
+        .lock()
+        .iter()
+        .find(|device| device.name() == name)
+        .cloned()
+}
+
+/// 探测所有磁盘控制器并登记找到的设备
+///
+/// 需要在 [`pci::init`](crate::pci::init) 之后调用；驱动需要挂起线程等待中断，
+/// 因此也应在线程初始化和中断改由 APIC 投递之后调用，否则退回轮询
+pub fn init() {
+    ata::init();
+    ahci::init();
+    nvme::init();
+    virtio::init();
+}
+
+#[test_case]
+fn test_check_request() {
+    struct Empty;
+
+    impl BlockDevice for Empty {
+        fn name(&self) -> &str {
+            "empty"
+        }
+
+        fn block_count(&self) -> u64 {
+            8
+        }
+
+        fn read_blocks(&self, _lba: u64, _buf: &mut [u8]) -> Result<(), BlockError> {
+            Ok(())
+        }
+
+        fn write_blocks(&self, _lba: u64, _buf: &[u8]) -> Result<(), BlockError> {
+            Ok(())
+        }
+    }
+
+    assert_eq!(check_request(&Empty, 6, 2 * SECTOR_SIZE), Ok(2));
+    assert_eq!(
+        check_request(&Empty, 7, 2 * SECTOR_SIZE),
+        Err(BlockError::OutOfRange)
+    );
+    assert_eq!(
+        check_request(&Empty, u64::MAX, SECTOR_SIZE),
+        Err(BlockError::OutOfRange)
+    );
+    assert_eq!(check_request(&Empty, 0, 100), Err(BlockError::BadBuffer));
+}
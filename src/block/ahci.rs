@@ -0,0 +1,720 @@
+//! 本模块实现了 AHCI 控制器上 SATA 磁盘的 DMA 驱动
+//!
+//! AHCI 控制器的寄存器位于 BAR5 指向的内存中，每个端口接一个设备，有自己的命令列表和接收 FIS 的区域。
+//! 命令列表最多 32 个槽，每个槽指向一张命令表，表中是发给设备的 FIS 和描述数据缓冲区的 PRD 表；
+//! 驱动在槽中填好命令之后置位 PxCI 中对应的位，设备完成之后清除这一位并产生中断。
+//!
+//! 每个端口最多使用 [`MAX_SLOTS`] 个槽，每个槽有一块固定的 DMA 缓冲区，数据在缓冲区和调用者的内存之间复制，
+//! 多个线程可以同时在不同的槽上发出命令。
+//! 中断可用时等待的线程挂起，直到中断处理函数唤醒端口的等待队列；否则轮询 PxCI。
+//! 设备报告错误之后端口停止处理命令，由下一个发现错误的线程重启端口，此时尚未完成的命令都按失败返回
+
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering, fence};
+use core::time::Duration;
+
+use spin::Mutex;
+use x86_64::{PhysAddr, VirtAddr};
+
+use super::{BlockDevice, BlockError, SECTOR_SIZE};
+use crate::memory::{
+    self,
+    dma::{self, DmaRegion},
+};
+use crate::pci::{self, Bar, Device, msi};
+use crate::sync::SpinLock;
+use crate::thread::WaitQueue;
+use crate::time::Instant;
+
+/// PCI 存储控制器的类别
+const CLASS_STORAGE: u8 = 0x01;
+/// PCI SATA 控制器的子类别
+const SUBCLASS_SATA: u8 = 0x06;
+/// AHCI 控制器的编程接口
+const PROG_IF_AHCI: u8 = 0x01;
+/// 寄存器所在的 BAR
+const ABAR: usize = 5;
+
+/// 控制器寄存器：能力
+const HBA_CAP: u64 = 0x00;
+/// 控制器寄存器：全局控制
+const HBA_GHC: u64 = 0x04;
+/// 控制器寄存器：各端口的中断状态
+const HBA_IS: u64 = 0x08;
+/// 控制器寄存器：实现了的端口
+const HBA_PI: u64 = 0x0c;
+/// 控制器寄存器：扩展能力
+const HBA_CAP2: u64 = 0x24;
+/// 控制器寄存器：BIOS 交接控制和状态
+const HBA_BOHC: u64 = 0x28;
+
+/// 能力：支持 64 位地址
+const CAP_S64A: u32 = 1 << 31;
+/// 扩展能力：支持 BIOS 交接
+const CAP2_BOH: u32 = 1 << 0;
+/// BIOS 交接：BIOS 拥有控制器
+const BOHC_BOS: u32 = 1 << 0;
+/// BIOS 交接：请求操作系统拥有控制器
+const BOHC_OOS: u32 = 1 << 1;
+/// 全局控制：产生中断
+const GHC_IE: u32 = 1 << 1;
+/// 全局控制：使用 AHCI 模式
+const GHC_AE: u32 = 1 << 31;
+
+/// 第 0 个端口的寄存器的偏移
+const PORT_BASE: u64 = 0x100;
+/// 每个端口的寄存器的大小
+const PORT_SIZE: u64 = 0x80;
+
+/// 端口寄存器：命令列表的物理地址
+const PORT_CLB: u64 = 0x00;
+/// 端口寄存器：命令列表的物理地址的高 32 位
+const PORT_CLBU: u64 = 0x04;
+/// 端口寄存器：接收 FIS 区域的物理地址
+const PORT_FB: u64 = 0x08;
+/// 端口寄存器：接收 FIS 区域的物理地址的高 32 位
+const PORT_FBU: u64 = 0x0c;
+/// 端口寄存器：中断状态，写 1 清除
+const PORT_IS: u64 = 0x10;
+/// 端口寄存器：中断使能
+const PORT_IE: u64 = 0x14;
+/// 端口寄存器：命令和状态
+const PORT_CMD: u64 = 0x18;
+/// 端口寄存器：设备的状态和错误
+const PORT_TFD: u64 = 0x20;
+/// 端口寄存器：设备的签名
+const PORT_SIG: u64 = 0x24;
+/// 端口寄存器：SATA 链路状态
+const PORT_SSTS: u64 = 0x28;
+/// 端口寄存器：SATA 错误，写 1 清除
+const PORT_SERR: u64 = 0x30;
+/// 端口寄存器：已发出的命令
+const PORT_CI: u64 = 0x38;
+
+/// 命令和状态：处理命令列表
+const CMD_ST: u32 = 1 << 0;
+/// 命令和状态：启动设备
+const CMD_SUD: u32 = 1 << 1;
+/// 命令和状态：给设备上电
+const CMD_POD: u32 = 1 << 2;
+/// 命令和状态：接收 FIS
+const CMD_FRE: u32 = 1 << 4;
+/// 命令和状态：FIS 接收正在运行
+const CMD_FR: u32 = 1 << 14;
+/// 命令和状态：命令列表正在运行
+const CMD_CR: u32 = 1 << 15;
+
+/// 中断：收到设备发来的寄存器 FIS
+const IS_DHRS: u32 = 1 << 0;
+/// 中断：收到 PIO 设置 FIS
+const IS_PSS: u32 = 1 << 1;
+/// 中断：接口错误
+const IS_IFS: u32 = 1 << 27;
+/// 中断：主机总线数据错误
+const IS_HBDS: u32 = 1 << 28;
+/// 中断：主机总线致命错误
+const IS_HBFS: u32 = 1 << 29;
+/// 中断：设备报告了错误
+const IS_TFES: u32 = 1 << 30;
+/// 使端口停止处理命令的错误
+const IS_ERRORS: u32 = IS_IFS | IS_HBDS | IS_HBFS | IS_TFES;
+
+/// 设备状态：设备请求传输数据
+const TFD_DRQ: u32 = 1 << 3;
+/// 设备状态：忙
+const TFD_BUSY: u32 = 1 << 7;
+
+/// 链路状态：检测到设备并建立了通信
+const SSTS_DET_PRESENT: u32 = 3;
+/// 链路状态：接口处于活动状态
+const SSTS_IPM_ACTIVE: u32 = 1;
+/// SATA 磁盘的签名，ATAPI 等其他设备的签名不同
+const SIG_ATA: u32 = 0x0000_0101;
+
+/// 每个端口最多使用的命令槽数
+const MAX_SLOTS: usize = 8;
+/// 每个槽的 DMA 缓冲区大小，也是一条命令最多传输的字节数
+const SLOT_BUFFER_SIZE: usize = 16 * 1024;
+/// 命令列表在端口 DMA 内存中的偏移，32 个命令头各占 32 字节
+const COMMAND_LIST_OFFSET: usize = 0;
+/// 接收 FIS 区域在端口 DMA 内存中的偏移，大小为 256 字节
+const RECEIVED_FIS_OFFSET: usize = 0x400;
+/// 第 0 个槽的命令表在端口 DMA 内存中的偏移
+const COMMAND_TABLE_OFFSET: usize = 0x800;
+/// 每张命令表的大小：128 字节的命令 FIS 区域加上一个 PRD
+const COMMAND_TABLE_SIZE: usize = 0x100;
+/// PRD 表在命令表中的偏移
+const PRDT_OFFSET: usize = 0x80;
+
+/// 命令头：命令 FIS 的长度，以双字为单位
+const HEADER_FIS_LENGTH: u32 = (FIS_LENGTH / 4) as u32;
+/// 命令头：数据由主机写往设备
+const HEADER_WRITE: u32 = 1 << 6;
+/// PRD：传输完成时产生中断
+const PRD_INTERRUPT: u32 = 1 << 31;
+
+/// 主机发往设备的寄存器 FIS 的类型
+const FIS_TYPE_H2D: u8 = 0x27;
+/// 寄存器 FIS 的长度
+const FIS_LENGTH: usize = 20;
+/// 寄存器 FIS：这是一条命令而不是控制寄存器的更新
+const FIS_COMMAND: u8 = 1 << 7;
+/// 设备寄存器：使用 LBA 寻址
+const DEVICE_LBA: u8 = 1 << 6;
+
+/// 命令：读扇区，48 位 LBA，DMA
+const COMMAND_READ_DMA_EXT: u8 = 0x25;
+/// 命令：写扇区，48 位 LBA，DMA
+const COMMAND_WRITE_DMA_EXT: u8 = 0x35;
+/// 命令：把缓存写入介质，48 位 LBA
+const COMMAND_FLUSH_CACHE_EXT: u8 = 0xea;
+/// 命令：读取设备的标识数据
+const COMMAND_IDENTIFY: u8 = 0xec;
+
+/// IDENTIFY 数据：支持的命令集，第 10 位表示支持 48 位 LBA
+const IDENTIFY_COMMAND_SETS: usize = 83;
+/// IDENTIFY 数据：48 位 LBA 支持的命令集位
+const COMMAND_SET_LBA48: u16 = 1 << 10;
+/// IDENTIFY 数据：28 位 LBA 可寻址的扇区数，占两个字
+const IDENTIFY_SECTORS: usize = 60;
+/// IDENTIFY 数据：48 位 LBA 可寻址的扇区数，占四个字
+const IDENTIFY_SECTORS_LBA48: usize = 100;
+/// IDENTIFY 数据：型号字符串
+const IDENTIFY_MODEL: core::ops::Range<usize> = 27..47;
+
+/// 等待一条命令完成的最长时间
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(5);
+/// 等待端口停止或设备空闲的最长时间
+const PORT_TIMEOUT: Duration = Duration::from_millis(500);
+/// 等待 BIOS 交出控制器的最长时间
+const HANDOFF_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// 内存映射的寄存器
+#[derive(Debug, Clone, Copy)]
+struct Registers(VirtAddr);
+
+impl Registers {
+    fn read(&self, offset: u64) -> u32 {
+        unsafe { (self.0 + offset).as_ptr::<u32>().read_volatile() }
+    }
+
+    fn write(&self, offset: u64, value: u32) {
+        unsafe { (self.0 + offset).as_mut_ptr::<u32>().write_volatile(value) }
+    }
+
+    /// 置位或清除寄存器中的位
+    fn update(&self, offset: u64, set: u32, clear: u32) {
+        self.write(offset, (self.read(offset) & !clear) | set);
+    }
+
+    /// 轮询直到 `done` 对寄存器的值成立
+    ///
+    /// # 返回
+    ///
+    /// 在 `timeout` 之内是否成立
+    fn wait(&self, offset: u64, timeout: Duration, done: impl Fn(u32) -> bool) -> bool {
+        let deadline = Instant::now() + timeout;
+        while !done(self.read(offset)) {
+            if Instant::now() >= deadline {
+                return false;
+            }
+            core::hint::spin_loop();
+        }
+        true
+    }
+}
+
+/// 构造主机发往设备的寄存器 FIS
+///
+/// # 参数
+///
+/// - `command`: ATA 命令
+/// - `lba`: 起始扇区，48 位
+/// - `count`: 扇区数
+fn command_fis(command: u8, lba: u64, count: u16) -> [u8; FIS_LENGTH] {
+    let lba = lba.to_le_bytes();
+    let count = count.to_le_bytes();
+    let mut fis = [0; FIS_LENGTH];
+    fis[0] = FIS_TYPE_H2D;
+    fis[1] = FIS_COMMAND;
+    fis[2] = command;
+    fis[4..7].copy_from_slice(&lba[0..3]);
+    fis[7] = DEVICE_LBA;
+    fis[8..11].copy_from_slice(&lba[3..6]);
+    fis[12..14].copy_from_slice(&count);
+    fis
+}
+
+/// AHCI 端口上的 SATA 磁盘
+pub struct AhciDisk {
+    name: String,                   // 设备名
+    model: String,                  // 设备报告的型号
+    port: Registers,                // 端口的寄存器
+    memory: DmaRegion,              // 命令列表、接收 FIS 区域和命令表
+    buffers: Vec<Mutex<DmaRegion>>, // 每个槽的数据缓冲区
+    free_slots: SpinLock<u32>,      // 空闲的槽，第 n 位对应第 n 个槽
+    sectors: u64,                   // 扇区数
+    failed: AtomicBool,             // 端口是否因错误停止了处理命令
+    generation: AtomicU64,          // 端口重启的次数
+    recovering: Mutex<()>,          // 保证同时只有一个线程重启端口
+    interrupts: AtomicBool,         // 端口的中断是否可用
+    queue: Arc<WaitQueue>,          // 等待命令完成或空闲槽的线程
+}
+
+impl AhciDisk {
+    /// 设备报告的型号
+    pub fn model(&self) -> &str {
+        &self.model
+    }
+
+    /// 读取并清除端口的中断状态，发现错误时记录下来，由中断处理函数和等待的线程调用
+    fn poll_status(&self) {
+        let status = self.port.read(PORT_IS);
+        if status == 0 {
+            return;
+        }
+        self.port.write(PORT_IS, status);
+        if status & IS_ERRORS != 0 {
+            self.failed.store(true, Ordering::SeqCst);
+        }
+    }
+
+    /// 停止端口处理命令列表和接收 FIS
+    fn stop(&self) -> bool {
+        self.port.update(PORT_CMD, 0, CMD_ST);
+        if !self
+            .port
+            .wait(PORT_CMD, PORT_TIMEOUT, |cmd| cmd & CMD_CR == 0)
+        {
+            return false;
+        }
+        self.port.update(PORT_CMD, 0, CMD_FRE);
+        self.port
+            .wait(PORT_CMD, PORT_TIMEOUT, |cmd| cmd & CMD_FR == 0)
+    }
+
+    /// 清除错误状态，等待设备空闲后开始处理命令列表
+    fn start(&self) {
+        self.port.write(PORT_SERR, u32::MAX);
+        self.port.write(PORT_IS, u32::MAX);
+        self.port.update(PORT_CMD, CMD_FRE, 0);
+        self.port.wait(PORT_TFD, PORT_TIMEOUT, |tfd| {
+            tfd & (TFD_BUSY | TFD_DRQ) == 0
+        });
+        self.port.update(PORT_CMD, CMD_ST, 0);
+    }
+
+    /// 在命令失败之后重启端口，其他线程已经重启过时直接返回
+    ///
+    /// # 参数
+    ///
+    /// - `generation`: 调用者发出命令时端口重启的次数
+    fn recover(&self, generation: u64) {
+        let _guard = self.recovering.lock();
+        if self.generation.load(Ordering::SeqCst) != generation {
+            return;
+        }
+        log::warn!(
+            "{}: resetting port, status {:#x}",
+            self.name,
+            self.port.read(PORT_TFD)
+        );
+        self.stop();
+        self.start();
+        self.failed.store(false, Ordering::SeqCst);
+        self.generation.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// 取得一个空闲的槽，没有时等待其他命令完成
+    fn claim_slot(&self) -> Result<usize, BlockError> {
+        super::wait_for(
+            &self.queue,
+            self.interrupts.load(Ordering::Acquire),
+            COMMAND_TIMEOUT,
+            || {
+                let mut free = self.free_slots.lock();
+                let slot = free.trailing_zeros();
+                (slot < u32::BITS).then(|| {
+                    *free &= !(1 << slot);
+                    slot as usize
+                })
+            },
+        )
+    }
+
+    /// 归还槽并唤醒等待空闲槽的线程
+    fn release_slot(&self, slot: usize) {
+        *self.free_slots.lock() |= 1 << slot;
+        self.queue.wake_all();
+    }
+
+    /// 在槽中填好命令并发出，等待它完成
+    ///
+    /// # 参数
+    ///
+    /// - `slot`: 已取得的槽
+    /// - `command`: ATA 命令
+    /// - `lba`: 起始扇区
+    /// - `bytes`: 经槽的缓冲区传输的字节数，为 0 时不传输数据
+    /// - `write`: 数据是否由主机写往设备
+    fn execute(
+        &self,
+        slot: usize,
+        command: u8,
+        lba: u64,
+        bytes: usize,
+        write: bool,
+    ) -> Result<(), BlockError> {
+        let table = COMMAND_TABLE_OFFSET + slot * COMMAND_TABLE_SIZE;
+        let table_phys = self.memory.phys() + table as u64;
+        let count = (bytes / SECTOR_SIZE) as u16;
+        unsafe {
+            let fis = self.memory.ptr::<[u8; FIS_LENGTH]>(table);
+            fis.write_volatile(command_fis(command, lba, count));
+            let prd = self.memory.ptr::<[u32; 4]>(table + PRDT_OFFSET);
+            let buffer = self.buffers[slot].lock().phys().as_u64();
+            prd.write_volatile([
+                buffer as u32,
+                (buffer >> 32) as u32,
+                0,
+                (bytes.max(1) as u32 - 1) | PRD_INTERRUPT,
+            ]);
+            let flags = HEADER_FIS_LENGTH
+                | if write { HEADER_WRITE } else { 0 }
+                | (u32::from(bytes > 0) << 16);
+            let header = self.memory.ptr::<[u32; 8]>(COMMAND_LIST_OFFSET + slot * 32);
+            header.write_volatile([
+                flags,
+                0,
+                table_phys.as_u64() as u32,
+                (table_phys.as_u64() >> 32) as u32,
+                0,
+                0,
+                0,
+                0,
+            ]);
+        }
+        let generation = self.generation.load(Ordering::SeqCst);
+        // 命令表写完之后才能发出命令
+        fence(Ordering::SeqCst);
+        self.port.write(PORT_CI, 1 << slot);
+        let result = super::wait_for(
+            &self.queue,
+            self.interrupts.load(Ordering::Acquire),
+            COMMAND_TIMEOUT,
+            || {
+                self.poll_status();
+                // 重启端口会清除 PxCI，重启之前发出的命令都按失败返回
+                if self.failed.load(Ordering::SeqCst)
+                    || self.generation.load(Ordering::SeqCst) != generation
+                {
+                    Some(Err(BlockError::Device))
+                } else if self.port.read(PORT_CI) & (1 << slot) == 0 {
+                    Some(Ok(()))
+                } else {
+                    None
+                }
+            },
+        )
+        .and_then(|result| result);
+        if result.is_err() {
+            self.recover(generation);
+        }
+        result
+    }
+
+    /// 用一个槽执行一条命令
+    ///
+    /// # 参数
+    ///
+    /// - `command`: ATA 命令
+    /// - `lba`: 起始扇区
+    /// - `bytes`: 传输的字节数，不超过 [`SLOT_BUFFER_SIZE`]
+    /// - `write`: 数据是否由主机写往设备
+    /// - `buffer`: 发出命令之前或之后访问槽的缓冲区，写入时在之前调用，读取时在之后调用
+    fn command(
+        &self,
+        command: u8,
+        lba: u64,
+        bytes: usize,
+        write: bool,
+        buffer: impl FnOnce(&mut DmaRegion),
+    ) -> Result<(), BlockError> {
+        let slot = self.claim_slot()?;
+        let mut buffer = Some(buffer);
+        if write && let Some(buffer) = buffer.take() {
+            buffer(&mut self.buffers[slot].lock());
+        }
+        let result = self.execute(slot, command, lba, bytes, write);
+        if result.is_ok()
+            && let Some(buffer) = buffer.take()
+        {
+            buffer(&mut self.buffers[slot].lock());
+        }
+        self.release_slot(slot);
+        result
+    }
+
+    /// 读取设备的标识数据
+    fn identify(&self) -> Result<[u16; 256], BlockError> {
+        let mut words = [0; 256];
+        self.command(COMMAND_IDENTIFY, 0, SECTOR_SIZE, false, |buffer| {
+            for (word, bytes) in words.iter_mut().zip(buffer.as_slice().chunks_exact(2)) {
+                *word = u16::from_le_bytes([bytes[0], bytes[1]]);
+            }
+        })?;
+        Ok(words)
+    }
+}
+
+impl BlockDevice for AhciDisk {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn block_count(&self) -> u64 {
+        self.sectors
+    }
+
+    fn read_blocks(&self, lba: u64, buf: &mut [u8]) -> Result<(), BlockError> {
+        super::check_request(self, lba, buf.len())?;
+        for (index, chunk) in buf.chunks_mut(SLOT_BUFFER_SIZE).enumerate() {
+            let lba = lba + (index * SLOT_BUFFER_SIZE / SECTOR_SIZE) as u64;
+            self.command(COMMAND_READ_DMA_EXT, lba, chunk.len(), false, |buffer| {
+                chunk.copy_from_slice(&buffer.as_slice()[..chunk.len()]);
+            })?;
+        }
+        Ok(())
+    }
+
+    fn write_blocks(&self, lba: u64, buf: &[u8]) -> Result<(), BlockError> {
+        super::check_request(self, lba, buf.len())?;
+        for (index, chunk) in buf.chunks(SLOT_BUFFER_SIZE).enumerate() {
+            let lba = lba + (index * SLOT_BUFFER_SIZE / SECTOR_SIZE) as u64;
+            self.command(COMMAND_WRITE_DMA_EXT, lba, chunk.len(), true, |buffer| {
+                buffer.as_mut_slice()[..chunk.len()].copy_from_slice(chunk);
+            })?;
+        }
+        Ok(())
+    }
+
+    fn flush(&self) -> Result<(), BlockError> {
+        self.command(COMMAND_FLUSH_CACHE_EXT, 0, 0, false, |_| {})
+    }
+}
+
+/// 已初始化的控制器
+struct Controller {
+    hba: Registers,                     // 控制器的寄存器
+    disks: Vec<(usize, Arc<AhciDisk>)>, // 端口号和端口上的磁盘
+}
+
+/// 所有已初始化的控制器，中断处理函数从这里找到产生中断的端口
+static CONTROLLERS: SpinLock<Vec<Controller>> = SpinLock::new(Vec::new());
+
+/// 所有 AHCI 控制器共用的中断处理函数
+fn handle_interrupt() {
+    for controller in CONTROLLERS.lock().iter() {
+        let pending = controller.hba.read(HBA_IS);
+        if pending == 0 {
+            continue;
+        }
+        for (index, disk) in &controller.disks {
+            if pending & (1 << index) != 0 {
+                disk.poll_status();
+                disk.queue.wake_all();
+            }
+        }
+        // 先清除端口的状态再清除控制器的状态，否则电平触发的 INTx 会立即再次到达
+        controller.hba.write(HBA_IS, pending);
+    }
+}
+
+/// 从 BIOS 手中取得控制器，没有实现 BIOS 交接时直接返回
+fn take_ownership(hba: Registers) {
+    if hba.read(HBA_CAP2) & CAP2_BOH == 0 {
+        return;
+    }
+    hba.update(HBA_BOHC, BOHC_OOS, 0);
+    if !hba.wait(HBA_BOHC, HANDOFF_TIMEOUT, |bohc| bohc & BOHC_BOS == 0) {
+        log::warn!("AHCI: BIOS did not release the controller");
+    }
+}
+
+/// 初始化端口上的 SATA 磁盘
+///
+/// # 参数
+///
+/// - `hba`: 控制器的寄存器
+/// - `index`: 端口号
+/// - `slots`: 端口使用的槽数
+/// - `wide`: 控制器是否支持 64 位地址
+///
+/// # 返回
+///
+/// 端口上没有 SATA 磁盘、内存不足或磁盘没有响应 IDENTIFY 时返回 `None`
+fn init_port(hba: Registers, index: usize, slots: usize, wide: bool) -> Option<AhciDisk> {
+    let port = Registers(hba.0 + PORT_BASE + index as u64 * PORT_SIZE);
+    let status = port.read(PORT_SSTS);
+    if status & 0xf != SSTS_DET_PRESENT
+        || (status >> 8) & 0xf != SSTS_IPM_ACTIVE
+        || port.read(PORT_SIG) != SIG_ATA
+    {
+        return None;
+    }
+    // 不支持 64 位地址的控制器只能访问 4 GiB 以下的内存
+    let allocate = |size| {
+        let region = if wide {
+            DmaRegion::new(size)
+        } else {
+            DmaRegion::new_below(size, dma::DMA32_LIMIT)
+        };
+        if region.is_none() {
+            log::warn!("AHCI port {}: out of DMA memory", index);
+        }
+        region
+    };
+    let memory = allocate(COMMAND_TABLE_OFFSET + slots * COMMAND_TABLE_SIZE)?;
+    let buffers = (0..slots)
+        .map(|_| allocate(SLOT_BUFFER_SIZE).map(Mutex::new))
+        .collect::<Option<Vec<_>>>()?;
+    let disk = AhciDisk {
+        name: String::new(),
+        model: String::new(),
+        port,
+        memory,
+        buffers,
+        free_slots: SpinLock::new((1 << slots) - 1),
+        sectors: 0,
+        failed: AtomicBool::new(false),
+        generation: AtomicU64::new(0),
+        recovering: Mutex::new(()),
+        interrupts: AtomicBool::new(false),
+        queue: Arc::new(WaitQueue::new()),
+    };
+    if !disk.stop() {
+        log::warn!("AHCI port {}: port did not stop", index);
+        return None;
+    }
+    let set_address = |low: u64, high: u64, address: PhysAddr| {
+        port.write(low, address.as_u64() as u32);
+        port.write(high, (address.as_u64() >> 32) as u32);
+    };
+    set_address(
+        PORT_CLB,
+        PORT_CLBU,
+        disk.memory.phys() + COMMAND_LIST_OFFSET as u64,
+    );
+    set_address(
+        PORT_FB,
+        PORT_FBU,
+        disk.memory.phys() + RECEIVED_FIS_OFFSET as u64,
+    );
+    port.update(PORT_CMD, CMD_SUD | CMD_POD, 0);
+    disk.start();
+
+    let identify = disk.identify().ok()?;
+    let sectors = if identify[IDENTIFY_COMMAND_SETS] & COMMAND_SET_LBA48 != 0 {
+        identify[IDENTIFY_SECTORS_LBA48..IDENTIFY_SECTORS_LBA48 + 4]
+            .iter()
+            .rev()
+            .fold(0, |sectors, &word| (sectors << 16) | u64::from(word))
+    } else {
+        u64::from(identify[IDENTIFY_SECTORS]) | (u64::from(identify[IDENTIFY_SECTORS + 1]) << 16)
+    };
+    Some(AhciDisk {
+        model: super::ata::identify_string(&identify[IDENTIFY_MODEL]),
+        sectors,
+        ..disk
+    })
+}
+
+/// 初始化一个 AHCI 控制器和它的所有端口
+///
+/// # 参数
+///
+/// - `device`: 控制器的 PCI 设备
+/// - `next`: 下一个磁盘的编号
+fn init_controller(device: &Device, next: &AtomicUsize) {
+    let Some(Bar::Memory { address, size, .. }) = device.bar(ABAR) else {
+        return;
+    };
+    let Some(base) = memory::map_mmio(PhysAddr::new(address), size) else {
+        log::warn!("AHCI: failed to map registers at {:#x}", address);
+        return;
+    };
+    device.enable(pci::COMMAND_MEMORY | pci::COMMAND_BUS_MASTER);
+    let hba = Registers(base);
+    take_ownership(hba);
+    hba.update(HBA_GHC, GHC_AE, 0);
+
+    let cap = hba.read(HBA_CAP);
+    let slots = (((cap >> 8) & 0x1f) as usize + 1).min(MAX_SLOTS);
+    let wide = cap & CAP_S64A != 0;
+    let implemented = hba.read(HBA_PI);
+    let mut disks = Vec::new();
+    for index in (0..32).filter(|index| implemented & (1 << index) != 0) {
+        if let Some(mut disk) = init_port(hba, index, slots, wide) {
+            disk.name = format!("ahci{}", next.fetch_add(1, Ordering::Relaxed));
+            log::info!("{}: {} on port {}", disk.name, disk.model, index);
+            disks.push((index, Arc::new(disk)));
+        }
+    }
+    if disks.is_empty() {
+        return;
+    }
+    for (_, disk) in &disks {
+        super::register(disk.clone());
+    }
+    CONTROLLERS.lock().push(Controller {
+        hba,
+        disks: disks.clone(),
+    });
+
+    // 设备在 IDENTIFY 时按轮询方式工作，登记好中断处理函数之后再打开中断
+    if msi::route(device, handle_interrupt).is_some() {
+        for (_, disk) in &disks {
+            disk.port.write(PORT_IE, IS_DHRS | IS_PSS | IS_ERRORS);
+            disk.interrupts.store(true, Ordering::Release);
+        }
+        hba.write(HBA_IS, u32::MAX);
+        hba.update(HBA_GHC, GHC_IE, 0);
+    }
+}
+
+/// 探测所有 AHCI 控制器并登记找到的 SATA 磁盘
+pub fn init() {
+    let next = AtomicUsize::new(0);
+    for device in pci::find_class(CLASS_STORAGE, SUBCLASS_SATA)
+        .filter(|device| device.prog_if == PROG_IF_AHCI)
+    {
+        init_controller(device, &next);
+    }
+}
+
+#[test_case]
+fn test_command_fis() {
+    let fis = command_fis(COMMAND_READ_DMA_EXT, 0x0605_0403_0201, 0x0102);
+    assert_eq!(
+        fis[..14],
+        [
+            FIS_TYPE_H2D,
+            FIS_COMMAND,
+            COMMAND_READ_DMA_EXT,
+            0,
+            0x01,
+            0x02,
+            0x03,
+            DEVICE_LBA,
+            0x04,
+            0x05,
+            0x06,
+            0,
+            0x02,
+            0x01
+        ]
+    );
+}
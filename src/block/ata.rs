@@ -0,0 +1,360 @@
+//! 本模块实现了 ATA 磁盘的 PIO 驱动
+//!
+//! 两个 IDE 通道各有一组命令寄存器和一个控制寄存器，每个通道最多接主从两个设备。
+//! PCI IDE 控制器处于兼容模式时使用固定的端口，处于原生模式时端口由 BAR 给出；没有 PCI IDE 控制器时按兼容模式探测。
+//!
+//! 探测时向每个设备发送 IDENTIFY，只接受支持 LBA 的 ATA 设备，ATAPI 和 SATA 设备由它们的签名排除。
+//! 读写使用 28 位 LBA，每个扇区在设备准备好数据之后经数据寄存器逐字传输；通道的中断保持关闭，通过轮询状态等待设备
+
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use spin::Mutex;
+
+use super::{BlockDevice, BlockError, SECTOR_SIZE};
+use crate::arch::port::Port;
+use crate::pci::{self, Bar};
+
+/// 兼容模式下两个通道的命令寄存器和控制寄存器端口
+const LEGACY_CHANNELS: [(u16, u16); 2] = [(0x1f0, 0x3f6), (0x170, 0x376)];
+
+/// PCI 存储控制器的类别
+const CLASS_STORAGE: u8 = 0x01;
+/// PCI IDE 控制器的子类别
+const SUBCLASS_IDE: u8 = 0x01;
+
+/// 命令寄存器：数据
+const REG_DATA: u16 = 0;
+/// 命令寄存器：扇区数
+const REG_SECTOR_COUNT: u16 = 2;
+/// 命令寄存器：LBA 的 0~7 位
+const REG_LBA_LOW: u16 = 3;
+/// 命令寄存器：LBA 的 8~15 位
+const REG_LBA_MID: u16 = 4;
+/// 命令寄存器：LBA 的 16~23 位
+const REG_LBA_HIGH: u16 = 5;
+/// 命令寄存器：设备选择和 LBA 的 24~27 位
+const REG_DRIVE: u16 = 6;
+/// 命令寄存器：读取时为状态，写入时为命令
+const REG_STATUS: u16 = 7;
+
+/// 状态：出错
+const STATUS_ERROR: u8 = 1 << 0;
+/// 状态：设备已准备好传输数据
+const STATUS_DATA_REQUEST: u8 = 1 << 3;
+/// 状态：设备故障
+const STATUS_DEVICE_FAULT: u8 = 1 << 5;
+/// 状态：忙
+const STATUS_BUSY: u8 = 1 << 7;
+
+/// 控制寄存器：关闭通道的中断
+const CONTROL_NO_INTERRUPT: u8 = 1 << 1;
+
+/// 设备选择：使用 LBA 寻址，其余两位为兼容保留的 1
+const DRIVE_LBA: u8 = 0xe0;
+/// 设备选择：从设备
+const DRIVE_SLAVE: u8 = 1 << 4;
+
+/// 命令：读扇区
+const COMMAND_READ_SECTORS: u8 = 0x20;
+/// 命令：写扇区
+const COMMAND_WRITE_SECTORS: u8 = 0x30;
+/// 命令：把设备缓存写入介质
+const COMMAND_CACHE_FLUSH: u8 = 0xe7;
+/// 命令：读取设备的标识数据
+const COMMAND_IDENTIFY: u8 = 0xec;
+
+/// IDENTIFY 数据：能力字，第 9 位表示支持 LBA
+const IDENTIFY_CAPABILITIES: usize = 49;
+/// IDENTIFY 数据：28 位 LBA 可寻址的扇区总数，占两个字
+const IDENTIFY_SECTORS: usize = 60;
+/// IDENTIFY 数据：型号字符串，占 20 个字
+const IDENTIFY_MODEL: core::ops::Range<usize> = 27..47;
+/// 能力字：支持 LBA
+const CAPABILITY_LBA: u16 = 1 << 9;
+
+/// 28 位 LBA 能寻址的扇区数
+const LBA28_LIMIT: u64 = 1 << 28;
+/// 一条读写命令最多传输的扇区数
+const MAX_SECTORS_PER_COMMAND: usize = 256;
+
+/// 等待设备时最多读取状态的次数
+const STATUS_POLLS: usize = 1_000_000;
+
+/// 一个 IDE 通道
+struct Channel {
+    command: u16, // 命令寄存器的起始端口
+    control: u16, // 控制寄存器的端口，读取时为备用状态
+}
+
+impl Channel {
+    /// 读取命令寄存器
+    fn read(&self, reg: u16) -> u8 {
+        unsafe { Port::new(self.command + reg).read() }
+    }
+
+    /// 写入命令寄存器
+    fn write(&self, reg: u16, value: u8) {
+        unsafe { Port::new(self.command + reg).write(value) }
+    }
+
+    /// 读取备用状态，不会清除设备的中断请求
+    fn alternate_status(&self) -> u8 {
+        unsafe { Port::new(self.control).read() }
+    }
+
+    /// 选择设备，之后等待约 400 ns 让状态反映新选择的设备
+    fn select(&self, drive: u8) {
+        self.write(REG_DRIVE, drive);
+        for _ in 0..4 {
+            self.alternate_status();
+        }
+    }
+
+    /// 等待设备不再忙
+    ///
+    /// # 返回
+    ///
+    /// 设备不再忙时的状态
+    fn wait_not_busy(&self) -> Result<u8, BlockError> {
+        (0..STATUS_POLLS)
+            .map(|_| self.alternate_status())
+            .find(|status| status & STATUS_BUSY == 0)
+            .ok_or(BlockError::Timeout)
+    }
+
+    /// 等待设备不再忙，并检查它是否报告了错误
+    fn check_status(&self) -> Result<(), BlockError> {
+        let status = self.wait_not_busy()?;
+        if status & (STATUS_ERROR | STATUS_DEVICE_FAULT) != 0 {
+            return Err(BlockError::Device);
+        }
+        Ok(())
+    }
+
+    /// 等待设备准备好传输一个扇区的数据
+    fn wait_data(&self) -> Result<(), BlockError> {
+        for _ in 0..STATUS_POLLS {
+            let status = self.read(REG_STATUS);
+            if status & STATUS_BUSY != 0 {
+                continue;
+            }
+            if status & (STATUS_ERROR | STATUS_DEVICE_FAULT) != 0 {
+                return Err(BlockError::Device);
+            }
+            if status & STATUS_DATA_REQUEST != 0 {
+                return Ok(());
+            }
+        }
+        Err(BlockError::Timeout)
+    }
+
+    /// 设置 28 位 LBA 和扇区数并发送读写命令
+    fn start(&self, drive: u8, lba: u64, count: usize, command: u8) -> Result<(), BlockError> {
+        self.select(drive | ((lba >> 24) as u8 & 0x0f));
+        self.wait_not_busy()?;
+        // 扇区数为 0 表示 256 个扇区
+        self.write(REG_SECTOR_COUNT, count as u8);
+        self.write(REG_LBA_LOW, lba as u8);
+        self.write(REG_LBA_MID, (lba >> 8) as u8);
+        self.write(REG_LBA_HIGH, (lba >> 16) as u8);
+        self.write(REG_STATUS, command);
+        Ok(())
+    }
+
+    /// 读取一个扇区的数据
+    fn read_sector(&self, sector: &mut [u8]) {
+        let mut data = Port::<u16>::new(self.command + REG_DATA);
+        for word in sector.chunks_exact_mut(2) {
+            word.copy_from_slice(&unsafe { data.read() }.to_le_bytes());
+        }
+    }
+
+    /// 写入一个扇区的数据
+    fn write_sector(&self, sector: &[u8]) {
+        let mut data = Port::<u16>::new(self.command + REG_DATA);
+        for word in sector.chunks_exact(2) {
+            unsafe { data.write(u16::from_le_bytes([word[0], word[1]])) };
+        }
+    }
+
+    /// 向设备发送 IDENTIFY 并读取 256 个字的标识数据
+    ///
+    /// # 返回
+    ///
+    /// 没有设备或设备不是 ATA 设备时返回 `None`
+    fn identify(&self, drive: u8) -> Option<[u16; 256]> {
+        self.select(drive);
+        self.write(REG_SECTOR_COUNT, 0);
+        self.write(REG_LBA_LOW, 0);
+        self.write(REG_LBA_MID, 0);
+        self.write(REG_LBA_HIGH, 0);
+        self.write(REG_STATUS, COMMAND_IDENTIFY);
+        // 状态为 0 表示没有设备，浮空的总线读到 0xff
+        if matches!(self.read(REG_STATUS), 0 | 0xff) {
+            return None;
+        }
+        self.wait_not_busy().ok()?;
+        // ATAPI 和 SATA 设备在这两个寄存器中留下非零的签名
+        if self.read(REG_LBA_MID) != 0 || self.read(REG_LBA_HIGH) != 0 {
+            return None;
+        }
+        self.wait_data().ok()?;
+        let mut bytes = [0; SECTOR_SIZE];
+        self.read_sector(&mut bytes);
+        let mut words = [0; 256];
+        for (word, bytes) in words.iter_mut().zip(bytes.chunks_exact(2)) {
+            *word = u16::from_le_bytes([bytes[0], bytes[1]]);
+        }
+        Some(words)
+    }
+}
+
+/// ATA 磁盘
+pub struct AtaDrive {
+    name: String,                 // 设备名
+    model: String,                // 设备报告的型号
+    channel: Arc<Mutex<Channel>>, // 所在的通道，与同一通道上的另一个设备共享
+    drive: u8,                    // 设备选择寄存器的值
+    sectors: u64,                 // 扇区数
+}
+
+impl AtaDrive {
+    /// 设备报告的型号
+    pub fn model(&self) -> &str {
+        &self.model
+    }
+
+    /// 逐条命令读写扇区，每条命令最多 256 个扇区
+    fn transfer(
+        &self,
+        lba: u64,
+        len: usize,
+        command: u8,
+        mut sector: impl FnMut(&Channel, usize),
+    ) -> Result<(), BlockError> {
+        super::check_request(self, lba, len)?;
+        let channel = self.channel.lock();
+        let mut done = 0;
+        while done < len / SECTOR_SIZE {
+            let count = (len / SECTOR_SIZE - done).min(MAX_SECTORS_PER_COMMAND);
+            channel.start(self.drive, lba + done as u64, count, command)?;
+            for index in done..done + count {
+                channel.wait_data()?;
+                sector(&channel, index);
+            }
+            // 写入的最后一个扇区在设备不再忙之后才完成
+            channel.check_status()?;
+            done += count;
+        }
+        Ok(())
+    }
+}
+
+impl BlockDevice for AtaDrive {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn block_count(&self) -> u64 {
+        self.sectors
+    }
+
+    fn read_blocks(&self, lba: u64, buf: &mut [u8]) -> Result<(), BlockError> {
+        self.transfer(lba, buf.len(), COMMAND_READ_SECTORS, |channel, index| {
+            channel.read_sector(&mut buf[index * SECTOR_SIZE..(index + 1) * SECTOR_SIZE]);
+        })
+    }
+
+    fn write_blocks(&self, lba: u64, buf: &[u8]) -> Result<(), BlockError> {
+        self.transfer(lba, buf.len(), COMMAND_WRITE_SECTORS, |channel, index| {
+            channel.write_sector(&buf[index * SECTOR_SIZE..(index + 1) * SECTOR_SIZE]);
+        })
+    }
+
+    fn flush(&self) -> Result<(), BlockError> {
+        let channel = self.channel.lock();
+        // 设备忙时不能改变选择，上一条命令的错误也要先报告
+        channel.check_status()?;
+        channel.select(self.drive);
+        channel.wait_not_busy()?;
+        channel.write(REG_STATUS, COMMAND_CACHE_FLUSH);
+        channel.check_status()
+    }
+}
+
+/// IDENTIFY 数据中的字符串，每个字的高字节在前，末尾用空格填充
+///
+/// # 参数
+///
+/// - `words`: 字符串所在的字
+pub(super) fn identify_string(words: &[u16]) -> String {
+    let bytes: Vec<u8> = words.iter().flat_map(|word| word.to_be_bytes()).collect();
+    String::from_utf8_lossy(&bytes).trim().into()
+}
+
+/// 两个通道的命令寄存器和控制寄存器端口
+///
+/// PCI IDE 控制器编程接口的第 0 位和第 2 位分别表示主、次通道处于原生模式，
+/// 此时命令寄存器和控制块由 BAR0~BAR3 给出，控制寄存器位于控制块的第 2 个端口
+fn channel_ports() -> [(u16, u16); 2] {
+    let mut ports = LEGACY_CHANNELS;
+    let Some(controller) = pci::find_class(CLASS_STORAGE, SUBCLASS_IDE).next() else {
+        return ports;
+    };
+    for (index, ports) in ports.iter_mut().enumerate() {
+        if controller.prog_if & (1 << (index * 2)) == 0 {
+            continue;
+        }
+        if let (Some(Bar::Io { port: command, .. }), Some(Bar::Io { port: control, .. })) =
+            (controller.bar(index * 2), controller.bar(index * 2 + 1))
+        {
+            *ports = (command, control + 2);
+        }
+    }
+    controller.enable(pci::COMMAND_IO);
+    ports
+}
+
+/// 探测两个通道上的设备并登记找到的 ATA 磁盘
+pub fn init() {
+    for (index, (command, control)) in channel_ports().into_iter().enumerate() {
+        let channel = Channel { command, control };
+        // 浮空的总线读到 0xff，说明没有这个通道
+        if channel.alternate_status() == 0xff {
+            continue;
+        }
+        unsafe { Port::new(control).write(CONTROL_NO_INTERRUPT) };
+        let channel = Arc::new(Mutex::new(channel));
+        for (slave, drive) in [DRIVE_LBA, DRIVE_LBA | DRIVE_SLAVE].into_iter().enumerate() {
+            let Some(identify) = channel.lock().identify(drive) else {
+                continue;
+            };
+            if identify[IDENTIFY_CAPABILITIES] & CAPABILITY_LBA == 0 {
+                continue;
+            }
+            let sectors = u64::from(identify[IDENTIFY_SECTORS])
+                | (u64::from(identify[IDENTIFY_SECTORS + 1]) << 16);
+            let drive = AtaDrive {
+                name: format!("ata{}", index * 2 + slave),
+                model: identify_string(&identify[IDENTIFY_MODEL]),
+                channel: channel.clone(),
+                drive,
+                sectors: sectors.min(LBA28_LIMIT),
+            };
+            log::info!("{}: {}", drive.name, drive.model);
+            super::register(Arc::new(drive));
+        }
+    }
+}
+
+#[test_case]
+fn test_identify_string() {
+    // "QEMU HARDDISK"，每个字的高字节在前
+    let words = [
+        0x5145, 0x4d55, 0x2048, 0x4152, 0x4444, 0x4953, 0x4b20, 0x2020,
+    ];
+    assert_eq!(identify_string(&words), "QEMU HARDDISK");
+}
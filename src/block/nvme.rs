@@ -0,0 +1,772 @@
+//! 本模块实现了 NVMe 控制器的驱动
+//!
+//! 控制器的寄存器位于 BAR0，驱动与控制器通过成对的提交队列和完成队列通信：驱动把 64 字节的命令写入提交队列，
+//! 再写门铃寄存器通知控制器；控制器把 16 字节的完成项写入完成队列，每转一圈翻转完成项中的相位位。
+//!
+//! 管理队列用于识别控制器和命名空间、创建 I/O 队列；所有命名空间共用一对 I/O 队列，最多同时执行
+//! [`MAX_COMMANDS`] 条命令，命令编号就是槽号，每个槽有一块固定的 DMA 缓冲区和预先填好的 PRP 列表。
+//!
+//! 两个完成队列都使用 MSI-X 的第 0 个表项，控制器没有 MSI-X 时使用 MSI 的唯一向量，中断处理函数收取完成项并唤醒等待的线程；中断不可用时轮询完成队列
+
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::time::Duration;
+
+use spin::Mutex;
+use x86_64::{PhysAddr, VirtAddr};
+
+use super::{BlockDevice, BlockError};
+use crate::memory::{self, dma::DmaRegion};
+use crate::pci::{self, Bar, Device, msi};
+use crate::sync::SpinLock;
+use crate::thread::WaitQueue;
+use crate::time::Instant;
+
+/// PCI 存储控制器的类别
+const CLASS_STORAGE: u8 = 0x01;
+/// PCI 非易失性存储控制器的子类别
+const SUBCLASS_NVM: u8 = 0x08;
+/// NVMe 控制器的编程接口
+const PROG_IF_NVME: u8 = 0x02;
+
+/// 寄存器：能力，64 位
+const REG_CAP: u64 = 0x00;
+/// 寄存器：中断屏蔽置位，只对 INTx 和 MSI 有效
+const REG_INTMS: u64 = 0x0c;
+/// 寄存器：中断屏蔽清除
+const REG_INTMC: u64 = 0x10;
+/// 寄存器：控制器配置
+const REG_CC: u64 = 0x14;
+/// 寄存器：控制器状态
+const REG_CSTS: u64 = 0x1c;
+/// 寄存器：管理队列的大小
+const REG_AQA: u64 = 0x24;
+/// 寄存器：管理提交队列的物理地址，64 位
+const REG_ASQ: u64 = 0x28;
+/// 寄存器：管理完成队列的物理地址，64 位
+const REG_ACQ: u64 = 0x30;
+/// 第一个门铃寄存器的偏移
+const DOORBELL_BASE: u64 = 0x1000;
+
+/// 能力：支持 NVM 命令集
+const CAP_CSS_NVM: u64 = 1 << 37;
+/// 控制器配置：启用
+const CC_EN: u32 = 1 << 0;
+/// 控制器配置：I/O 提交队列项为 2^6 字节
+const CC_IOSQES: u32 = 6 << 16;
+/// 控制器配置：I/O 完成队列项为 2^4 字节
+const CC_IOCQES: u32 = 4 << 20;
+/// 控制器状态：就绪
+const CSTS_RDY: u32 = 1 << 0;
+/// 控制器状态：发生了致命错误
+const CSTS_CFS: u32 = 1 << 1;
+
+/// 提交队列项的大小
+const SUBMISSION_SIZE: usize = 64;
+/// 完成队列项的大小
+const COMPLETION_SIZE: usize = 16;
+/// 管理队列的大小
+const ADMIN_QUEUE_SIZE: u16 = 32;
+/// I/O 队列的大小
+const IO_QUEUE_SIZE: u16 = 64;
+/// I/O 队列的编号
+const IO_QUEUE_ID: u16 = 1;
+
+/// 管理命令：创建 I/O 提交队列
+const ADMIN_CREATE_SQ: u8 = 0x01;
+/// 管理命令：创建 I/O 完成队列
+const ADMIN_CREATE_CQ: u8 = 0x05;
+/// 管理命令：识别
+const ADMIN_IDENTIFY: u8 = 0x06;
+/// 管理命令：设置特性
+const ADMIN_SET_FEATURES: u8 = 0x09;
+/// 识别：命名空间
+const IDENTIFY_NAMESPACE: u32 = 0;
+/// 识别：控制器
+const IDENTIFY_CONTROLLER: u32 = 1;
+/// 识别：活动命名空间的列表
+const IDENTIFY_ACTIVE_NAMESPACES: u32 = 2;
+/// 特性：队列数
+const FEATURE_NUMBER_OF_QUEUES: u32 = 0x07;
+/// 创建队列：队列物理连续
+const QUEUE_CONTIGUOUS: u32 = 1 << 0;
+/// 创建完成队列：产生中断
+const QUEUE_INTERRUPTS: u32 = 1 << 1;
+
+/// I/O 命令：刷新缓存
+const IO_FLUSH: u8 = 0x00;
+/// I/O 命令：写
+const IO_WRITE: u8 = 0x01;
+/// I/O 命令：读
+const IO_READ: u8 = 0x02;
+
+/// 控制器使用的内存页大小，即控制器配置中 MPS 为 0 时的 4 KiB
+const PAGE_SIZE: usize = 4096;
+/// 同时执行的 I/O 命令数
+const MAX_COMMANDS: usize = 8;
+/// 每个槽的数据缓冲区大小，也是一条命令最多传输的字节数
+const SLOT_BUFFER_SIZE: usize = 16 * 1024;
+/// 等待一条命令完成的最长时间
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// 内存映射的寄存器
+#[derive(Debug, Clone, Copy)]
+struct Registers(VirtAddr);
+
+impl Registers {
+    fn read(&self, offset: u64) -> u32 {
+        unsafe { (self.0 + offset).as_ptr::<u32>().read_volatile() }
+    }
+
+    fn write(&self, offset: u64, value: u32) {
+        unsafe { (self.0 + offset).as_mut_ptr::<u32>().write_volatile(value) }
+    }
+
+    fn read_u64(&self, offset: u64) -> u64 {
+        u64::from(self.read(offset)) | (u64::from(self.read(offset + 4)) << 32)
+    }
+
+    fn write_u64(&self, offset: u64, value: u64) {
+        self.write(offset, value as u32);
+        self.write(offset + 4, (value >> 32) as u32);
+    }
+}
+
+/// 一个完成项
+#[derive(Debug, Clone, Copy)]
+struct Completion {
+    result: u32, // 命令相关的结果
+    id: u16,     // 命令编号
+    status: u16, // 状态，0 表示成功
+}
+
+/// 解析完成项
+///
+/// # 返回
+///
+/// 完成项的相位位和内容
+fn parse_completion(entry: [u32; 4]) -> (bool, Completion) {
+    (
+        entry[3] & (1 << 16) != 0,
+        Completion {
+            result: entry[0],
+            id: entry[3] as u16,
+            status: (entry[3] >> 17) as u16,
+        },
+    )
+}
+
+/// 一对提交队列和完成队列
+struct QueuePair {
+    size: u16,                   // 两个队列的项数
+    submissions: DmaRegion,      // 提交队列
+    completions: DmaRegion,      // 完成队列
+    tail: u16,                   // 下一个提交项的位置
+    head: u16,                   // 下一个完成项的位置
+    phase: bool,                 // 新完成项的相位位
+    submit_doorbell: VirtAddr,   // 提交队列的尾门铃
+    complete_doorbell: VirtAddr, // 完成队列的头门铃
+}
+
+impl QueuePair {
+    /// 分配队列的内存
+    ///
+    /// # 参数
+    ///
+    /// - `regs`: 控制器的寄存器
+    /// - `id`: 队列编号，0 为管理队列
+    /// - `size`: 队列的项数
+    /// - `stride`: 门铃寄存器的间隔
+    fn new(regs: Registers, id: u16, size: u16, stride: u64) -> Option<Self> {
+        let doorbell = regs.0 + DOORBELL_BASE + u64::from(id) * 2 * stride;
+        Some(Self {
+            size,
+            submissions: DmaRegion::new(usize::from(size) * SUBMISSION_SIZE)?,
+            completions: DmaRegion::new(usize::from(size) * COMPLETION_SIZE)?,
+            tail: 0,
+            head: 0,
+            phase: true,
+            submit_doorbell: doorbell,
+            complete_doorbell: doorbell + stride,
+        })
+    }
+
+    /// 写入一条命令并通知控制器
+    fn submit(&mut self, command: [u32; 16]) {
+        let offset = usize::from(self.tail) * SUBMISSION_SIZE;
+        unsafe {
+            self.submissions
+                .ptr::<[u32; 16]>(offset)
+                .write_volatile(command)
+        };
+        self.tail = (self.tail + 1) % self.size;
+        // 命令写完之后才能移动尾指针
+        core::sync::atomic::fence(Ordering::SeqCst);
+        unsafe {
+            self.submit_doorbell
+                .as_mut_ptr::<u32>()
+                .write_volatile(u32::from(self.tail))
+        };
+    }
+
+    /// 取出一个新的完成项，并告诉控制器这一项已经处理
+    fn poll(&mut self) -> Option<Completion> {
+        let offset = usize::from(self.head) * COMPLETION_SIZE;
+        let entry = unsafe { self.completions.ptr::<[u32; 4]>(offset).read_volatile() };
+        let (phase, completion) = parse_completion(entry);
+        if phase != self.phase {
+            return None;
+        }
+        self.head += 1;
+        if self.head == self.size {
+            self.head = 0;
+            self.phase = !self.phase;
+        }
+        unsafe {
+            self.complete_doorbell
+                .as_mut_ptr::<u32>()
+                .write_volatile(u32::from(self.head))
+        };
+        Some(completion)
+    }
+}
+
+/// 一对队列上命令槽的状态
+struct QueueState {
+    pair: QueuePair,                     // 提交队列和完成队列
+    free: u32,                           // 空闲的槽
+    done: u32,                           // 已经完成、发出者尚未取走的槽
+    abandoned: u32,                      // 已经超时的槽，完成时直接回收
+    results: [Completion; MAX_COMMANDS], // 每个槽的完成项
+}
+
+impl QueueState {
+    /// 收取所有新的完成项
+    fn collect(&mut self) {
+        while let Some(completion) = self.pair.poll() {
+            let slot = usize::from(completion.id);
+            if slot >= MAX_COMMANDS {
+                continue;
+            }
+            if self.abandoned & (1 << slot) != 0 {
+                self.abandoned &= !(1 << slot);
+                self.free |= 1 << slot;
+            } else {
+                self.results[slot] = completion;
+                self.done |= 1 << slot;
+            }
+        }
+    }
+}
+
+/// 一对队列和它的命令槽
+struct Queue {
+    state: SpinLock<QueueState>,    // 队列和槽的状态
+    buffers: Vec<Mutex<DmaRegion>>, // 每个槽的数据缓冲区，末尾一页为 PRP 列表
+}
+
+impl Queue {
+    /// 建立命令槽，并在每个槽的 PRP 列表中填入缓冲区第 1 页之后各页的物理地址
+    ///
+    /// # 参数
+    ///
+    /// - `pair`: 提交队列和完成队列
+    /// - `slots`: 槽数，不超过 [`MAX_COMMANDS`]
+    fn new(pair: QueuePair, slots: usize) -> Option<Self> {
+        let mut buffers = Vec::with_capacity(slots);
+        for _ in 0..slots {
+            let mut region = DmaRegion::new(SLOT_BUFFER_SIZE + PAGE_SIZE)?;
+            let phys = region.phys().as_u64();
+            let list = &mut region.as_mut_slice()[SLOT_BUFFER_SIZE..];
+            for (page, entry) in
+                (1..(SLOT_BUFFER_SIZE / PAGE_SIZE) as u64).zip(list.chunks_exact_mut(8))
+            {
+                entry.copy_from_slice(&(phys + page * PAGE_SIZE as u64).to_le_bytes());
+            }
+            buffers.push(Mutex::new(region));
+        }
+        let empty = Completion {
+            result: 0,
+            id: 0,
+            status: 0,
+        };
+        Some(Self {
+            state: SpinLock::new(QueueState {
+                pair,
+                free: (1 << slots) - 1,
+                done: 0,
+                abandoned: 0,
+                results: [empty; MAX_COMMANDS],
+            }),
+            buffers,
+        })
+    }
+}
+
+/// NVMe 控制器
+struct Controller {
+    admin: Queue,            // 管理队列
+    io: Option<Queue>,       // I/O 队列，创建之后才有
+    max_transfer: usize,     // 一条命令最多传输的字节数
+    interrupts: AtomicBool,  // 控制器的中断是否可用
+    waiters: Arc<WaitQueue>, // 等待命令完成或空闲槽的线程
+}
+
+impl Controller {
+    /// 在一对队列上执行一条命令
+    ///
+    /// # 参数
+    ///
+    /// - `queue`: 管理队列或 I/O 队列
+    /// - `command`: 命令的 16 个双字，命令编号和 PRP 由这里填写
+    /// - `bytes`: 经槽的缓冲区传输的字节数
+    /// - `write`: 数据是否由主机写往控制器
+    /// - `buffer`: 访问槽的缓冲区，写入时在提交之前调用，读取时在完成之后调用
+    ///
+    /// # 返回
+    ///
+    /// 完成项中命令相关的结果
+    fn execute(
+        &self,
+        queue: &Queue,
+        mut command: [u32; 16],
+        bytes: usize,
+        write: bool,
+        buffer: impl FnOnce(&mut [u8]),
+    ) -> Result<u32, BlockError> {
+        let interrupts = self.interrupts.load(Ordering::Acquire);
+        let slot = super::wait_for(&self.waiters, interrupts, COMMAND_TIMEOUT, || {
+            let mut state = queue.state.lock();
+            state.collect();
+            let slot = state.free.trailing_zeros() as usize;
+            (slot < queue.buffers.len()).then(|| {
+                state.free &= !(1 << slot);
+                slot
+            })
+        })?;
+
+        let mut buffer = Some(buffer);
+        {
+            let mut region = queue.buffers[slot].lock();
+            if write && let Some(buffer) = buffer.take() {
+                buffer(&mut region.as_mut_slice()[..bytes]);
+            }
+            // 不传输数据的命令可能在 PRP 的位置放了其他地址
+            if bytes > 0 {
+                let phys = region.phys();
+                let second = if bytes <= 2 * PAGE_SIZE {
+                    phys + PAGE_SIZE as u64
+                } else {
+                    phys + SLOT_BUFFER_SIZE as u64
+                };
+                set_u64(&mut command, 6, phys);
+                set_u64(&mut command, 8, second);
+            }
+            command[0] |= (slot as u32) << 16;
+        }
+        queue.state.lock().pair.submit(command);
+
+        let result = super::wait_for(&self.waiters, interrupts, COMMAND_TIMEOUT, || {
+            let mut state = queue.state.lock();
+            state.collect();
+            (state.done & (1 << slot) != 0).then(|| {
+                state.done &= !(1 << slot);
+                state.results[slot]
+            })
+        });
+        let completion = match result {
+            Ok(completion) => completion,
+            Err(error) => {
+                // 控制器仍可能写入槽的缓冲区，等它完成之后再回收
+                let mut state = queue.state.lock();
+                state.collect();
+                if state.done & (1 << slot) == 0 {
+                    state.abandoned |= 1 << slot;
+                    return Err(error);
+                }
+                state.done &= !(1 << slot);
+                state.results[slot]
+            }
+        };
+        let result = if completion.status == 0 {
+            if let Some(buffer) = buffer.take() {
+                buffer(&mut queue.buffers[slot].lock().as_mut_slice()[..bytes]);
+            }
+            Ok(completion.result)
+        } else {
+            log::warn!(
+                "NVMe: command {:#x} failed with status {:#x}",
+                command[0] & 0xff,
+                completion.status
+            );
+            Err(BlockError::Device)
+        };
+        queue.state.lock().free |= 1 << slot;
+        self.waiters.wake_all();
+        result
+    }
+
+    /// 执行一条不传输数据的管理命令
+    fn admin(&self, command: [u32; 16]) -> Result<u32, BlockError> {
+        self.execute(&self.admin, command, 0, false, |_| {})
+    }
+
+    /// 执行识别命令，返回 4 KiB 的识别数据
+    ///
+    /// # 参数
+    ///
+    /// - `cns`: 识别的对象
+    /// - `nsid`: 命名空间编号
+    fn identify(&self, cns: u32, nsid: u32) -> Result<Vec<u8>, BlockError> {
+        let mut data = Vec::new();
+        self.execute(
+            &self.admin,
+            command(ADMIN_IDENTIFY, nsid, &[(10, cns)]),
+            PAGE_SIZE,
+            false,
+            |buffer| data.extend_from_slice(buffer),
+        )?;
+        Ok(data)
+    }
+
+    /// 收取完成项并唤醒等待的线程，由中断处理函数调用
+    fn handle_interrupt(&self) {
+        self.admin.state.lock().collect();
+        if let Some(io) = &self.io {
+            io.state.lock().collect();
+        }
+        self.waiters.wake_all();
+    }
+}
+
+/// 在命令中从第 `index` 个双字开始写入一个 64 位值
+fn set_u64(command: &mut [u32; 16], index: usize, value: PhysAddr) {
+    command[index] = value.as_u64() as u32;
+    command[index + 1] = (value.as_u64() >> 32) as u32;
+}
+
+/// 构造一条命令
+///
+/// # 参数
+///
+/// - `opcode`: 操作码
+/// - `nsid`: 命名空间编号
+/// - `dwords`: 其余需要填写的双字的编号和值
+fn command(opcode: u8, nsid: u32, dwords: &[(usize, u32)]) -> [u32; 16] {
+    let mut command = [0; 16];
+    command[0] = u32::from(opcode);
+    command[1] = nsid;
+    for &(index, value) in dwords {
+        command[index] = value;
+    }
+    command
+}
+
+/// NVMe 命名空间
+pub struct NvmeNamespace {
+    name: String,                // 设备名
+    model: String,               // 控制器报告的型号
+    controller: Arc<Controller>, // 所在的控制器
+    nsid: u32,                   // 命名空间编号
+    blocks: u64,                 // 块数
+    block_size: usize,           // 块的字节数
+}
+
+impl NvmeNamespace {
+    /// 控制器报告的型号
+    pub fn model(&self) -> &str {
+        &self.model
+    }
+
+    /// 逐条命令读写块
+    fn transfer(
+        &self,
+        opcode: u8,
+        lba: u64,
+        len: usize,
+        mut chunk: impl FnMut(usize, &mut [u8]),
+    ) -> Result<(), BlockError> {
+        super::check_request(self, lba, len)?;
+        let io = self.controller.io.as_ref().ok_or(BlockError::Device)?;
+        let step = self.controller.max_transfer;
+        for offset in (0..len).step_by(step) {
+            let bytes = (len - offset).min(step);
+            let lba = lba + (offset / self.block_size) as u64;
+            let blocks = (bytes / self.block_size) as u32;
+            let command = command(
+                opcode,
+                self.nsid,
+                &[(10, lba as u32), (11, (lba >> 32) as u32), (12, blocks - 1)],
+            );
+            self.controller
+                .execute(io, command, bytes, opcode == IO_WRITE, |buffer| {
+                    chunk(offset, buffer)
+                })?;
+        }
+        Ok(())
+    }
+}
+
+impl BlockDevice for NvmeNamespace {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    fn block_count(&self) -> u64 {
+        self.blocks
+    }
+
+    fn read_blocks(&self, lba: u64, buf: &mut [u8]) -> Result<(), BlockError> {
+        let len = buf.len();
+        self.transfer(IO_READ, lba, len, |offset, data| {
+            buf[offset..offset + data.len()].copy_from_slice(data);
+        })
+    }
+
+    fn write_blocks(&self, lba: u64, buf: &[u8]) -> Result<(), BlockError> {
+        self.transfer(IO_WRITE, lba, buf.len(), |offset, data| {
+            data.copy_from_slice(&buf[offset..offset + data.len()]);
+        })
+    }
+
+    fn flush(&self) -> Result<(), BlockError> {
+        let io = self.controller.io.as_ref().ok_or(BlockError::Device)?;
+        self.controller
+            .execute(io, command(IO_FLUSH, self.nsid, &[]), 0, false, |_| {})
+            .map(|_| ())
+    }
+}
+
+/// 所有已初始化的控制器，中断处理函数从这里找到它们
+static CONTROLLERS: SpinLock<Vec<Arc<Controller>>> = SpinLock::new(Vec::new());
+
+/// 所有 NVMe 控制器共用的中断处理函数
+fn handle_interrupt() {
+    for controller in CONTROLLERS.lock().iter() {
+        controller.handle_interrupt();
+    }
+}
+
+/// 识别数据中的字符串，末尾用空格填充
+fn identify_string(bytes: &[u8]) -> String {
+    String::from_utf8_lossy(bytes).trim().into()
+}
+
+/// 等待控制器的就绪状态变为 `ready`
+///
+/// # 返回
+///
+/// 控制器发生致命错误或在 `timeout` 之内没有变化时返回 `false`
+fn wait_ready(regs: Registers, ready: bool, timeout: Duration) -> bool {
+    let deadline = Instant::now() + timeout;
+    loop {
+        let status = regs.read(REG_CSTS);
+        if status & CSTS_CFS != 0 {
+            return false;
+        }
+        if (status & CSTS_RDY != 0) == ready {
+            return true;
+        }
+        if Instant::now() >= deadline {
+            return false;
+        }
+        core::hint::spin_loop();
+    }
+}
+
+/// 复位并启用控制器，建立管理队列
+///
+/// # 参数
+///
+/// - `regs`: 控制器的寄存器
+/// - `max_entries`: 控制器支持的最大队列项数
+/// - `stride`: 门铃寄存器的间隔
+///
+/// # 返回
+///
+/// 控制器没有就绪时返回 `None`
+fn enable_controller(regs: Registers, max_entries: u16, stride: u64) -> Option<Controller> {
+    let cap = regs.read_u64(REG_CAP);
+    let timeout = Duration::from_millis(((cap >> 24) & 0xff).max(1) * 500);
+
+    regs.write(REG_CC, regs.read(REG_CC) & !CC_EN);
+    if !wait_ready(regs, false, timeout) {
+        log::warn!("NVMe: controller did not reset");
+        return None;
+    }
+    // 初始化期间轮询完成队列
+    regs.write(REG_INTMS, u32::MAX);
+    let size = ADMIN_QUEUE_SIZE.min(max_entries);
+    let pair = QueuePair::new(regs, 0, size, stride)?;
+    regs.write(REG_AQA, u32::from(size - 1) | (u32::from(size - 1) << 16));
+    regs.write_u64(REG_ASQ, pair.submissions.phys().as_u64());
+    regs.write_u64(REG_ACQ, pair.completions.phys().as_u64());
+    regs.write(REG_CC, CC_EN | CC_IOSQES | CC_IOCQES);
+    if !wait_ready(regs, true, timeout) {
+        log::warn!("NVMe: controller did not become ready");
+        return None;
+    }
+    Some(Controller {
+        admin: Queue::new(pair, 1)?,
+        io: None,
+        max_transfer: SLOT_BUFFER_SIZE,
+        interrupts: AtomicBool::new(false),
+        waiters: Arc::new(WaitQueue::new()),
+    })
+}
+
+/// 初始化一个 NVMe 控制器并登记它的所有命名空间
+///
+/// # 参数
+///
+/// - `device`: 控制器的 PCI 设备
+/// - `index`: 控制器的编号
+fn init_controller(device: &Device, index: usize) -> Option<()> {
+    let Some(Bar::Memory { address, size, .. }) = device.bar(0) else {
+        return None;
+    };
+    let base = memory::map_mmio(PhysAddr::new(address), size)?;
+    device.enable(pci::COMMAND_MEMORY | pci::COMMAND_BUS_MASTER);
+    let regs = Registers(base);
+    let cap = regs.read_u64(REG_CAP);
+    if cap & CAP_CSS_NVM == 0 {
+        log::warn!("NVMe: controller does not support the NVM command set");
+        return None;
+    }
+    let max_entries = (cap & 0xffff) as u16 + 1;
+    let stride = 4 << ((cap >> 32) & 0xf);
+    let mut controller = enable_controller(regs, max_entries, stride)?;
+
+    let identify = controller.identify(IDENTIFY_CONTROLLER, 0).ok()?;
+    let model = identify_string(&identify[24..64]);
+    // 最大传输大小以最小页大小为单位、以 2 为底取对数，0 表示没有限制
+    let mdts = identify[77];
+    if mdts != 0 {
+        controller.max_transfer =
+            SLOT_BUFFER_SIZE.min(PAGE_SIZE.checked_shl(mdts.into()).unwrap_or(usize::MAX));
+    }
+
+    // 完成队列的中断向量字段是 MSI-X 的表项编号
+    let vector = msi::route_msix_first(device, handle_interrupt);
+    controller
+        .admin(command(
+            ADMIN_SET_FEATURES,
+            0,
+            &[(10, FEATURE_NUMBER_OF_QUEUES), (11, 0)],
+        ))
+        .ok()?;
+    let size = IO_QUEUE_SIZE.min(max_entries);
+    let pair = QueuePair::new(regs, IO_QUEUE_ID, size, stride)?;
+    let queue_size = u32::from(size - 1) << 16;
+    let interrupts = if vector.is_some() {
+        QUEUE_INTERRUPTS
+    } else {
+        0
+    };
+    controller
+        .admin({
+            let mut command = command(
+                ADMIN_CREATE_CQ,
+                0,
+                &[
+                    (10, queue_size | u32::from(IO_QUEUE_ID)),
+                    (11, QUEUE_CONTIGUOUS | interrupts),
+                ],
+            );
+            set_u64(&mut command, 6, pair.completions.phys());
+            command
+        })
+        .ok()?;
+    controller
+        .admin({
+            let mut command = command(
+                ADMIN_CREATE_SQ,
+                0,
+                &[
+                    (10, queue_size | u32::from(IO_QUEUE_ID)),
+                    (11, (u32::from(IO_QUEUE_ID) << 16) | QUEUE_CONTIGUOUS),
+                ],
+            );
+            set_u64(&mut command, 6, pair.submissions.phys());
+            command
+        })
+        .ok()?;
+    controller.io = Some(Queue::new(pair, MAX_COMMANDS)?);
+
+    let active = controller.identify(IDENTIFY_ACTIVE_NAMESPACES, 0).ok()?;
+    let mut namespaces = Vec::new();
+    for nsid in active
+        .chunks_exact(4)
+        .map(|bytes| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+        .take_while(|&nsid| nsid != 0)
+    {
+        let Ok(data) = controller.identify(IDENTIFY_NAMESPACE, nsid) else {
+            continue;
+        };
+        let blocks = u64::from_le_bytes(data[0..8].try_into().unwrap());
+        // 当前使用的块格式，块大小以 2 为底取对数
+        let format = usize::from(data[26] & 0xf);
+        let shift = data[128 + format * 4 + 2];
+        if blocks == 0 || !(9..=12).contains(&shift) {
+            continue;
+        }
+        namespaces.push((nsid, blocks, 1usize << shift));
+    }
+
+    let controller = Arc::new(controller);
+    CONTROLLERS.lock().push(controller.clone());
+    if vector.is_some() {
+        controller.interrupts.store(true, Ordering::Release);
+        regs.write(REG_INTMC, u32::MAX);
+    }
+    for (nsid, blocks, block_size) in namespaces {
+        let namespace = NvmeNamespace {
+            name: format!("nvme{}n{}", index, nsid),
+            model: model.clone(),
+            controller: controller.clone(),
+            nsid,
+            blocks,
+            block_size,
+        };
+        log::info!("{}: {}", namespace.name, namespace.model);
+        super::register(Arc::new(namespace));
+    }
+    Some(())
+}
+
+/// 探测所有 NVMe 控制器并登记它们的命名空间
+pub fn init() {
+    for (index, device) in pci::find_class(CLASS_STORAGE, SUBCLASS_NVM)
+        .filter(|device| device.prog_if == PROG_IF_NVME)
+        .enumerate()
+    {
+        if init_controller(device, index).is_none() {
+            log::warn!(
+                "NVMe controller at {}: initialization failed",
+                device.address
+            );
+        }
+    }
+}
+
+#[test_case]
+fn test_parse_completion() {
+    // 命令 3 成功完成，相位位为 1
+    let (phase, completion) = parse_completion([7, 0, 0x0001_0005, (1 << 16) | 3]);
+    assert!(phase);
+    assert_eq!(
+        (completion.id, completion.status, completion.result),
+        (3, 0, 7)
+    );
+    // 状态码 0x02（无效字段）
+    let (phase, completion) = parse_completion([0, 0, 0, (0x02 << 17) | 1]);
+    assert!(!phase);
+    assert_eq!(completion.status, 0x02);
+}
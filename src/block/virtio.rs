@@ -0,0 +1,347 @@
+//! 本模块实现了 virtio 块设备的驱动
+//!
+//! 每个请求是一条三段的描述符链：设备读取的请求头、数据缓冲区和设备写入的一个状态字节。
+//! 设备只有一个请求队列，驱动最多同时发出 [`MAX_REQUESTS`] 个请求，每个请求槽有一块固定的 DMA 缓冲区。
+//!
+//! 中断处理函数和等待的线程都会从已用环中收取完成的请求，并在槽的完成位图中标记，
+//! 发出请求的线程看到自己的槽完成之后读取状态字节。超时的请求仍在设备手中，它的槽在设备完成之后才被回收
+
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::time::Duration;
+
+use spin::Mutex;
+
+use super::{BlockDevice, BlockError, SECTOR_SIZE};
+use crate::memory::dma::DmaRegion;
+use crate::pci::Device;
+use crate::sync::SpinLock;
+use crate::thread::WaitQueue;
+use crate::virtio::{self, Buffer, Transport, VirtQueue};
+
+/// 特性：设备只读
+const FEATURE_RO: u64 = 1 << 5;
+/// 特性：设备支持刷新缓存
+const FEATURE_FLUSH: u64 = 1 << 9;
+
+/// 设备配置：以 512 字节为单位的容量
+const CONFIG_CAPACITY: u16 = 0;
+
+/// 请求类型：读
+const REQUEST_IN: u32 = 0;
+/// 请求类型：写
+const REQUEST_OUT: u32 = 1;
+/// 请求类型：刷新缓存
+const REQUEST_FLUSH: u32 = 4;
+/// 请求状态：成功
+const STATUS_OK: u8 = 0;
+
+/// 请求队列的编号
+const REQUEST_QUEUE: u16 = 0;
+/// 希望的请求队列大小
+const QUEUE_SIZE: u16 = 128;
+/// 最多同时发出的请求数
+const MAX_REQUESTS: usize = 8;
+/// 每个请求的描述符数
+const DESCRIPTORS_PER_REQUEST: usize = 3;
+/// 请求头在槽缓冲区中的偏移
+const HEADER_OFFSET: usize = 0;
+/// 请求头的大小
+const HEADER_SIZE: u32 = 16;
+/// 状态字节在槽缓冲区中的偏移
+const STATUS_OFFSET: usize = 16;
+/// 数据在槽缓冲区中的偏移
+const DATA_OFFSET: usize = 4096;
+/// 一个请求最多传输的字节数
+const MAX_TRANSFER: usize = 16 * 1024;
+/// 等待一个请求完成的最长时间
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// 请求槽的状态，与队列一起由一把锁保护
+struct Requests {
+    queue: VirtQueue,                   // 请求队列
+    heads: [Option<u16>; MAX_REQUESTS], // 每个槽正在设备手中的描述符链头
+    free: u32,                          // 空闲的槽
+    done: u32,                          // 设备已经完成、发出者尚未取走的槽
+    abandoned: u32,                     // 已经超时的槽，完成时直接回收
+}
+
+impl Requests {
+    /// 收取已用环中完成的请求
+    fn collect(&mut self) {
+        while let Some((head, _)) = self.queue.pop_used() {
+            let Some(slot) = self.heads.iter().position(|&slot| slot == Some(head)) else {
+                continue;
+            };
+            self.heads[slot] = None;
+            if self.abandoned & (1 << slot) != 0 {
+                self.abandoned &= !(1 << slot);
+                self.free |= 1 << slot;
+            } else {
+                self.done |= 1 << slot;
+            }
+        }
+    }
+}
+
+/// virtio 块设备
+pub struct VirtioBlock {
+    name: String,                   // 设备名
+    transport: Transport,           // 传输层
+    requests: SpinLock<Requests>,   // 请求队列和槽的状态
+    buffers: Vec<Mutex<DmaRegion>>, // 每个槽的请求头、状态和数据
+    sectors: u64,                   // 扇区数
+    read_only: bool,                // 设备是否只读
+    can_flush: bool,                // 设备是否支持刷新缓存
+    interrupts: AtomicBool,         // 设备的中断是否可用
+    waiters: Arc<WaitQueue>,        // 等待请求完成或空闲槽的线程
+}
+
+impl VirtioBlock {
+    /// 取得一个空闲的槽，没有时等待其他请求完成
+    fn claim_slot(&self) -> Result<usize, BlockError> {
+        super::wait_for(
+            &self.waiters,
+            self.interrupts.load(Ordering::Acquire),
+            REQUEST_TIMEOUT,
+            || {
+                let mut requests = self.requests.lock();
+                requests.collect();
+                let slot = requests.free.trailing_zeros();
+                (slot < u32::BITS).then(|| {
+                    requests.free &= !(1 << slot);
+                    slot as usize
+                })
+            },
+        )
+    }
+
+    /// 发出一个请求并等待它完成，完成或失败之后归还槽
+    ///
+    /// # 参数
+    ///
+    /// - `kind`: 请求类型
+    /// - `sector`: 起始扇区
+    /// - `bytes`: 传输的字节数，不超过 [`MAX_TRANSFER`]
+    /// - `buffer`: 访问槽的数据，写入时在发出之前调用，读取时在完成之后调用
+    fn request(
+        &self,
+        kind: u32,
+        sector: u64,
+        bytes: usize,
+        buffer: impl FnOnce(&mut [u8]),
+    ) -> Result<(), BlockError> {
+        let slot = self.claim_slot()?;
+        let mut buffer = Some(buffer);
+        let phys = {
+            let mut region = self.buffers[slot].lock();
+            if kind == REQUEST_OUT
+                && let Some(buffer) = buffer.take()
+            {
+                buffer(&mut region.as_mut_slice()[DATA_OFFSET..DATA_OFFSET + bytes]);
+            }
+            let header = region.as_mut_slice();
+            header[HEADER_OFFSET..HEADER_OFFSET + 4].copy_from_slice(&kind.to_le_bytes());
+            header[HEADER_OFFSET + 4..HEADER_OFFSET + 8].fill(0);
+            header[HEADER_OFFSET + 8..HEADER_OFFSET + 16].copy_from_slice(&sector.to_le_bytes());
+            header[STATUS_OFFSET] = 0xff;
+            region.phys()
+        };
+
+        let mut chain = Vec::with_capacity(DESCRIPTORS_PER_REQUEST);
+        chain.push(Buffer {
+            addr: phys + HEADER_OFFSET as u64,
+            len: HEADER_SIZE,
+            writable: false,
+        });
+        if bytes > 0 {
+            chain.push(Buffer {
+                addr: phys + DATA_OFFSET as u64,
+                len: bytes as u32,
+                writable: kind == REQUEST_IN,
+            });
+        }
+        chain.push(Buffer {
+            addr: phys + STATUS_OFFSET as u64,
+            len: 1,
+            writable: true,
+        });
+        {
+            let mut requests = self.requests.lock();
+            // 槽数不超过队列大小的三分之一，描述符总是够用
+            let head = requests.queue.add(&chain).expect("virtio-blk queue full");
+            requests.heads[slot] = Some(head);
+            if requests.queue.should_notify() {
+                self.transport.notify(&requests.queue);
+            }
+        }
+
+        let result = super::wait_for(
+            &self.waiters,
+            self.interrupts.load(Ordering::Acquire),
+            REQUEST_TIMEOUT,
+            || {
+                let mut requests = self.requests.lock();
+                requests.collect();
+                (requests.done & (1 << slot) != 0).then(|| requests.done &= !(1 << slot))
+            },
+        );
+        let mut requests = self.requests.lock();
+        if result.is_err() {
+            // 设备仍可能写入槽的缓冲区，等它完成之后再回收
+            if requests.done & (1 << slot) == 0 {
+                requests.abandoned |= 1 << slot;
+                return Err(BlockError::Timeout);
+            }
+            requests.done &= !(1 << slot);
+        }
+        drop(requests);
+
+        let mut region = self.buffers[slot].lock();
+        let result = if region.as_slice()[STATUS_OFFSET] == STATUS_OK {
+            if let Some(buffer) = buffer.take() {
+                buffer(&mut region.as_mut_slice()[DATA_OFFSET..DATA_OFFSET + bytes]);
+            }
+            Ok(())
+        } else {
+            Err(BlockError::Device)
+        };
+        drop(region);
+        self.requests.lock().free |= 1 << slot;
+        self.waiters.wake_all();
+        result
+    }
+
+    /// 收取完成的请求并唤醒等待的线程，由中断处理函数调用
+    fn handle_interrupt(&self) {
+        if !self.transport.uses_msix() {
+            self.transport.isr();
+        }
+        self.requests.lock().collect();
+        self.waiters.wake_all();
+    }
+}
+
+impl BlockDevice for VirtioBlock {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn block_count(&self) -> u64 {
+        self.sectors
+    }
+
+    fn read_blocks(&self, lba: u64, buf: &mut [u8]) -> Result<(), BlockError> {
+        super::check_request(self, lba, buf.len())?;
+        for (index, chunk) in buf.chunks_mut(MAX_TRANSFER).enumerate() {
+            let sector = lba + (index * MAX_TRANSFER / SECTOR_SIZE) as u64;
+            self.request(REQUEST_IN, sector, chunk.len(), |data| {
+                chunk.copy_from_slice(data);
+            })?;
+        }
+        Ok(())
+    }
+
+    fn write_blocks(&self, lba: u64, buf: &[u8]) -> Result<(), BlockError> {
+        super::check_request(self, lba, buf.len())?;
+        if self.read_only {
+            return Err(BlockError::Device);
+        }
+        for (index, chunk) in buf.chunks(MAX_TRANSFER).enumerate() {
+            let sector = lba + (index * MAX_TRANSFER / SECTOR_SIZE) as u64;
+            self.request(REQUEST_OUT, sector, chunk.len(), |data| {
+                data.copy_from_slice(chunk);
+            })?;
+        }
+        Ok(())
+    }
+
+    fn flush(&self) -> Result<(), BlockError> {
+        if !self.can_flush {
+            return Ok(());
+        }
+        self.request(REQUEST_FLUSH, 0, 0, |_| {})
+    }
+}
+
+/// 所有已初始化的 virtio 块设备，中断处理函数从这里找到它们
+static DISKS: SpinLock<Vec<Arc<VirtioBlock>>> = SpinLock::new(Vec::new());
+
+/// 所有 virtio 块设备共用的中断处理函数
+fn handle_interrupt() {
+    for disk in DISKS.lock().iter() {
+        disk.handle_interrupt();
+    }
+}
+
+/// 初始化一个 virtio 块设备
+///
+/// # 参数
+///
+/// - `device`: 设备的 PCI 设备
+/// - `index`: 设备的编号
+///
+/// # 返回
+///
+/// 初始化失败时返回 `None`
+fn init_device(device: &'static Device, index: usize) -> Option<Arc<VirtioBlock>> {
+    let transport = Transport::new(device)?;
+    let features = transport.negotiate(FEATURE_RO | FEATURE_FLUSH)?;
+    let vector = transport.route_interrupt(handle_interrupt);
+    let Some(queue) = transport.setup_queue(REQUEST_QUEUE, QUEUE_SIZE) else {
+        transport.fail();
+        return None;
+    };
+    let slots = (usize::from(queue.size()) / DESCRIPTORS_PER_REQUEST).min(MAX_REQUESTS);
+    let Some(buffers) = (0..slots)
+        .map(|_| DmaRegion::new(DATA_OFFSET + MAX_TRANSFER).map(Mutex::new))
+        .collect::<Option<Vec<_>>>()
+    else {
+        transport.fail();
+        return None;
+    };
+    let sectors = transport.config_u64(CONFIG_CAPACITY);
+    transport.driver_ok();
+    Some(Arc::new(VirtioBlock {
+        name: format!("virtio{}", index),
+        transport,
+        requests: SpinLock::new(Requests {
+            queue,
+            heads: [None; MAX_REQUESTS],
+            free: (1 << slots) - 1,
+            done: 0,
+            abandoned: 0,
+        }),
+        buffers,
+        sectors,
+        read_only: features & FEATURE_RO != 0,
+        can_flush: features & FEATURE_FLUSH != 0,
+        interrupts: AtomicBool::new(vector.is_some()),
+        waiters: Arc::new(WaitQueue::new()),
+    }))
+}
+
+/// 探测所有 virtio 块设备并登记它们
+pub fn init() {
+    for (index, device) in virtio::find(virtio::DEVICE_BLOCK).enumerate() {
+        let Some(disk) = init_device(device, index) else {
+            log::warn!("virtio-blk at {}: initialization failed", device.address);
+            continue;
+        };
+        log::info!(
+            "{}: virtio-blk{}{}",
+            disk.name,
+            if disk.transport.is_modern() {
+                ""
+            } else {
+                " (legacy)"
+            },
+            if disk.read_only { ", read-only" } else { "" }
+        );
+        DISKS.lock().push(disk.clone());
+        super::register(disk);
+    }
+}
@@ -0,0 +1,342 @@
+//! 本模块实现了控制台输出
+//!
+//! `print!` 系列宏的输出先写入启动时选定的主控制台（[`Console`] 的实现：VGA 文本模式、帧缓冲或只用串口），
+//! 再复制到所有已启用的附加输出端（串口、debugcon）。打印代码不关心输出最终去了哪里，
+//! 同一套 `println!` 在 BIOS 文本模式、图形模式和没有显示器的串口运行中都能工作
+
+use core::fmt;
+use core::sync::atomic::{AtomicU8, Ordering};
+
+use crate::sync::SpinLock;
+use crate::vga_buffer::Color;
+
+/// 主控制台的显示后端
+pub trait Console: Sync {
+    /// 后端的名称，用于日志
+    fn name(&self) -> &'static str;
+
+    /// 输出格式化文本
+    fn print(&self, args: fmt::Arguments);
+
+    /// 使用指定的前景色和背景色输出，不影响之后的输出颜色；不支持颜色的后端输出普通文本
+    fn print_colored(&self, _foreground: Color, _background: Color, args: fmt::Arguments) {
+        self.print(args);
+    }
+
+    /// 后端本身是否已经输出到附加输出端 `sink`，是则不再重复输出
+    fn covers(&self, _sink: Sink) -> bool {
+        false
+    }
+
+    /// 后端是否提供多个虚拟终端和回滚缓冲区（见 [`tty`](crate::tty)），不提供时只有 0 号终端可见
+    fn has_terminals(&self) -> bool {
+        false
+    }
+
+    /// 清空屏幕，光标回到左上角；没有屏幕的后端忽略
+    fn clear(&self) {}
+
+    /// 显示文本光标；没有光标的后端忽略
+    fn show_cursor(&self) {}
+
+    /// 保留屏幕第一行作为状态栏，之后的滚动和清屏只作用于其余各行；不支持状态栏的后端忽略
+    fn reserve_status_line(&self) {}
+
+    /// 以指定颜色重写状态栏，超出行宽的部分被截断
+    ///
+    /// 会在定时器中断中调用，相关的锁被占用时应直接跳过
+    fn status_line(&self, _text: &str, _foreground: Color, _background: Color) {}
+
+    /// panic 时接管屏幕，清屏后以红底白字显示 `render` 写入的内容
+    ///
+    /// 不能等待任何锁，panic 可能恰好发生在持有锁期间。panic 信息总会输出到串口，
+    /// 没有屏幕的后端忽略
+    fn panic(&self, _render: &mut dyn FnMut(&mut dyn fmt::Write) -> fmt::Result) {}
+}
+
+/// VGA 文本模式，写入 0xb8000 的字符缓冲区
+pub struct VgaText;
+
+impl Console for VgaText {
+    fn name(&self) -> &'static str {
+        "vga"
+    }
+
+    fn print(&self, args: fmt::Arguments) {
+        crate::vga_buffer::_print(args);
+    }
+
+    fn print_colored(&self, foreground: Color, background: Color, args: fmt::Arguments) {
+        crate::vga_buffer::_print_colored(foreground, background, args);
+    }
+
+    fn has_terminals(&self) -> bool {
+        true
+    }
+
+    fn clear(&self) {
+        crate::vga_buffer::_clear();
+    }
+
+    fn show_cursor(&self) {
+        crate::vga_buffer::WRITER.lock().show_cursor();
+    }
+
+    fn reserve_status_line(&self) {
+        for index in 0..crate::tty::TTY_COUNT {
+            crate::tty::terminal(index).lock().reserve_status_line();
+        }
+    }
+
+    fn status_line(&self, text: &str, foreground: Color, background: Color) {
+        use crate::vga_buffer::{ColorCode, MAX_BUFFER_WIDTH};
+
+        let Some(mut writer) = crate::tty::active_terminal().try_lock() else {
+            return;
+        };
+        // 用空格填满整行，覆盖上一次的内容
+        let color = ColorCode::new(foreground, background);
+        writer.write_at(0, 0, text, color);
+        for col in text.len()..MAX_BUFFER_WIDTH {
+            writer.write_at(0, col, " ", color);
+        }
+        writer.flush();
+    }
+
+    fn panic(&self, render: &mut dyn FnMut(&mut dyn fmt::Write) -> fmt::Result) {
+        use crate::vga_buffer::{self, MAX_BUFFER_WIDTH, Writer};
+
+        // 不经过 WRITER 的锁，直接写入 0xb8000；新建的写入器按 80x25 排版，需要换成当前的行数
+        let mut writer = Writer::new(true);
+        writer.resize(MAX_BUFFER_WIDTH, vga_buffer::screen_height());
+        writer.set_color(Color::White, Color::Red);
+        writer.clear_screen();
+        let _ = render(&mut writer);
+        writer.hide_cursor();
+        writer.flush();
+    }
+}
+
+/// 线性帧缓冲上的文本控制台
+pub struct FramebufferText;
+
+impl Console for FramebufferText {
+    fn name(&self) -> &'static str {
+        "framebuffer"
+    }
+
+    fn print(&self, args: fmt::Arguments) {
+        crate::framebuffer::_print(args);
+    }
+
+    fn print_colored(&self, foreground: Color, background: Color, args: fmt::Arguments) {
+        crate::framebuffer::_print_colored(foreground, background, args);
+    }
+
+    fn clear(&self) {
+        crate::framebuffer::clear();
+    }
+
+    fn reserve_status_line(&self) {
+        crate::framebuffer::reserve_status_line();
+    }
+
+    fn status_line(&self, text: &str, foreground: Color, background: Color) {
+        crate::framebuffer::write_status(text, foreground, background);
+    }
+
+    fn panic(&self, render: &mut dyn FnMut(&mut dyn fmt::Write) -> fmt::Result) {
+        crate::framebuffer::show_panic(render);
+    }
+}
+
+/// 没有显示器时只输出到串口
+pub struct SerialOnly;
+
+impl Console for SerialOnly {
+    fn name(&self) -> &'static str {
+        "serial"
+    }
+
+    fn print(&self, args: fmt::Arguments) {
+        crate::serial::_print(args);
+    }
+
+    fn covers(&self, sink: Sink) -> bool {
+        sink == Sink::Serial
+    }
+}
+
+/// 当前的主控制台，选定之前使用 VGA 文本模式
+static PRIMARY: SpinLock<&'static dyn Console> = SpinLock::new(&VgaText);
+
+/// 替换主控制台
+///
+/// # 参数
+///
+/// - `console`: 新的主控制台，之后的输出都写入它
+pub fn set_primary(console: &'static dyn Console) {
+    *PRIMARY.lock() = console;
+}
+
+/// 当前的主控制台
+pub fn primary() -> &'static dyn Console {
+    *PRIMARY.lock()
+}
+
+/// 不等待锁地取得主控制台，供 panic 处理使用；锁被占用时退回 VGA 文本模式
+pub fn primary_for_panic() -> &'static dyn Console {
+    PRIMARY.try_lock().map_or(&VgaText, |console| *console)
+}
+
+/// 按编译时的配置和可用的显示设备选择主控制台
+///
+/// 编译时设置环境变量 `RICKY_OS_CONSOLE=serial` 时只输出到串口；否则帧缓冲控制台可用时使用它，
+/// 都不满足时保持 VGA 文本模式。应在 [`framebuffer::init`](crate::framebuffer::init) 之后调用
+pub fn init() {
+    let console: &'static dyn Console = match option_env!("RICKY_OS_CONSOLE") {
+        Some("serial") => &SerialOnly,
+        _ if crate::framebuffer::is_available() => &FramebufferText,
+        _ => &VgaText,
+    };
+    set_primary(console);
+    log::info!("console: {}", console.name());
+}
+
+/// 附加输出端，主控制台的输出会复制到其中已启用的输出端
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Sink {
+    Serial = 0,
+    Debugcon = 1,
+}
+
+impl Sink {
+    /// 所有附加输出端
+    pub const ALL: [Sink; 2] = [Sink::Serial, Sink::Debugcon];
+
+    /// 该输出端在启用位图中对应的位
+    fn bit(self) -> u8 {
+        1 << self as u8
+    }
+}
+
+/// 已启用附加输出端的位图，默认都不启用
+static ENABLED: AtomicU8 = AtomicU8::new(0);
+
+/// 启用一个附加输出端
+///
+/// # 参数
+///
+/// - `sink`: 输出端
+pub fn enable(sink: Sink) {
+    ENABLED.fetch_or(sink.bit(), Ordering::Relaxed);
+}
+
+/// 禁用一个附加输出端
+///
+/// # 参数
+///
+/// - `sink`: 输出端
+pub fn disable(sink: Sink) {
+    ENABLED.fetch_and(!sink.bit(), Ordering::Relaxed);
+}
+
+/// 附加输出端是否已启用
+///
+/// # 参数
+///
+/// - `sink`: 输出端
+pub fn is_enabled(sink: Sink) -> bool {
+    ENABLED.load(Ordering::Relaxed) & sink.bit() != 0
+}
+
+/// 打印到主控制台和所有已启用的附加输出端
+#[macro_export]
+macro_rules! print {
+    ($($arg:tt)*) => ($crate::console::_print(format_args!($($arg)*)));
+}
+
+/// 打印到主控制台和所有已启用的附加输出端，并追加换行符
+#[macro_export]
+macro_rules! println {
+    () => ($crate::print!("\n"));
+    ($($arg:tt)*) => ($crate::print!("{}\n", format_args!($($arg)*)));
+}
+
+/// 使用指定的前景色和背景色打印，不影响之后的输出颜色
+///
+/// 颜色只作用于支持颜色的主控制台，附加输出端收到的是不带颜色的文本
+#[macro_export]
+macro_rules! print_colored {
+    ($fg:expr, $bg:expr, $($arg:tt)*) => (
+        $crate::console::_print_colored($fg, $bg, format_args!($($arg)*))
+    );
+}
+
+/// 使用指定的前景色和背景色打印，并追加换行符
+#[macro_export]
+macro_rules! println_colored {
+    ($fg:expr, $bg:expr) => ($crate::print_colored!($fg, $bg, "\n"));
+    ($fg:expr, $bg:expr, $($arg:tt)*) => (
+        $crate::print_colored!($fg, $bg, "{}\n", format_args!($($arg)*))
+    );
+}
+
+/// 清空主控制台的屏幕，并将光标移到左上角
+#[macro_export]
+macro_rules! clear {
+    () => {
+        $crate::console::primary().clear()
+    };
+}
+
+/// 把输出复制到主控制台没有覆盖的已启用附加输出端
+pub(crate) fn mirror(console: &dyn Console, args: fmt::Arguments) {
+    for sink in Sink::ALL {
+        if !is_enabled(sink) || console.covers(sink) {
+            continue;
+        }
+        match sink {
+            Sink::Serial => crate::serial::_print(args),
+            Sink::Debugcon => crate::debugcon::_print(args),
+        }
+    }
+}
+
+#[doc(hidden)]
+pub fn _print(args: fmt::Arguments) {
+    // 先取出主控制台再释放锁，输出期间替换主控制台不会死锁
+    let console = primary();
+    console.print(args);
+    mirror(console, args);
+}
+
+#[doc(hidden)]
+pub fn _print_colored(foreground: Color, background: Color, args: fmt::Arguments) {
+    let console = primary();
+    console.print_colored(foreground, background, args);
+    mirror(console, args);
+}
+
+#[test_case]
+fn test_enable_and_disable_sink() {
+    assert!(!is_enabled(Sink::Serial));
+    enable(Sink::Serial);
+    assert!(is_enabled(Sink::Serial));
+    assert!(!is_enabled(Sink::Debugcon));
+    disable(Sink::Serial);
+    assert!(!is_enabled(Sink::Serial));
+}
+
+#[test_case]
+fn test_select_primary_console() {
+    assert_eq!(primary().name(), "vga");
+    assert!(primary().has_terminals());
+    set_primary(&SerialOnly);
+    assert_eq!(primary().name(), "serial");
+    assert!(primary().covers(Sink::Serial));
+    assert!(!primary().covers(Sink::Debugcon));
+    assert!(!primary().has_terminals());
+    set_primary(&VgaText);
+}
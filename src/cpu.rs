@@ -0,0 +1,209 @@
+//! 本模块实现了基于 CPUID 的处理器特性检测
+//!
+//! 启动时查询一次 CPUID 并记录厂商、型号和特性标志，其他子系统在使用
+//! APIC、RDRAND 等功能前通过 [`has`] 检查处理器是否支持
+
+use core::arch::x86_64::{__cpuid, CpuidResult};
+
+use lazy_static::lazy_static;
+
+/// 处理器特性
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Feature {
+    Sse,
+    Sse2,
+    Avx,
+    Nx,
+    Rdrand,
+    Rdseed,
+    Apic,
+    X2Apic,
+    Tsc,
+    TscDeadline,
+    InvariantTsc,
+}
+
+impl Feature {
+    /// 所有特性
+    pub const ALL: [Feature; 11] = [
+        Feature::Sse,
+        Feature::Sse2,
+        Feature::Avx,
+        Feature::Nx,
+        Feature::Rdrand,
+        Feature::Rdseed,
+        Feature::Apic,
+        Feature::X2Apic,
+        Feature::Tsc,
+        Feature::TscDeadline,
+        Feature::InvariantTsc,
+    ];
+
+    /// 特性的名称
+    pub fn name(self) -> &'static str {
+        match self {
+            Feature::Sse => "sse",
+            Feature::Sse2 => "sse2",
+            Feature::Avx => "avx",
+            Feature::Nx => "nx",
+            Feature::Rdrand => "rdrand",
+            Feature::Rdseed => "rdseed",
+            Feature::Apic => "apic",
+            Feature::X2Apic => "x2apic",
+            Feature::Tsc => "tsc",
+            Feature::TscDeadline => "tsc-deadline",
+            Feature::InvariantTsc => "invariant-tsc",
+        }
+    }
+
+    /// 该特性在特性位图中对应的位
+    fn bit(self) -> u32 {
+        1 << self as u8
+    }
+}
+
+/// 基本功能叶
+const LEAF_FEATURES: u32 = 0x1;
+/// 结构化扩展特性叶
+const LEAF_STRUCTURED_FEATURES: u32 = 0x7;
+/// 扩展功能叶的起始编号，同时返回最大的扩展叶编号
+const LEAF_EXTENDED_MAX: u32 = 0x8000_0000;
+/// 扩展特性叶
+const LEAF_EXTENDED_FEATURES: u32 = 0x8000_0001;
+/// 型号字符串所在的三个扩展叶中的第一个
+const LEAF_BRAND_STRING: u32 = 0x8000_0002;
+/// 高级电源管理信息叶
+const LEAF_POWER_MANAGEMENT: u32 = 0x8000_0007;
+
+/// CPUID 查询结果
+pub struct CpuInfo {
+    vendor: [u8; 12],
+    brand: [u8; 48],
+    features: u32,
+}
+
+impl CpuInfo {
+    /// 查询 CPUID
+    fn detect() -> Self {
+        let CpuidResult {
+            eax: max_basic,
+            ebx,
+            ecx,
+            edx,
+        } = __cpuid(0);
+        let mut vendor = [0u8; 12];
+        vendor[0..4].copy_from_slice(&ebx.to_le_bytes());
+        vendor[4..8].copy_from_slice(&edx.to_le_bytes());
+        vendor[8..12].copy_from_slice(&ecx.to_le_bytes());
+
+        let mut features = 0;
+        let mut set = |feature: Feature, register: u32, bit: u32| {
+            if register & (1 << bit) != 0 {
+                features |= feature.bit();
+            }
+        };
+
+        let basic = __cpuid(LEAF_FEATURES);
+        set(Feature::Tsc, basic.edx, 4);
+        set(Feature::Apic, basic.edx, 9);
+        set(Feature::Sse, basic.edx, 25);
+        set(Feature::Sse2, basic.edx, 26);
+        set(Feature::X2Apic, basic.ecx, 21);
+        set(Feature::TscDeadline, basic.ecx, 24);
+        set(Feature::Avx, basic.ecx, 28);
+        set(Feature::Rdrand, basic.ecx, 30);
+        if max_basic >= LEAF_STRUCTURED_FEATURES {
+            set(Feature::Rdseed, __cpuid(LEAF_STRUCTURED_FEATURES).ebx, 18);
+        }
+
+        let max_extended = __cpuid(LEAF_EXTENDED_MAX).eax;
+        if max_extended >= LEAF_EXTENDED_FEATURES {
+            set(Feature::Nx, __cpuid(LEAF_EXTENDED_FEATURES).edx, 20);
+        }
+        if max_extended >= LEAF_POWER_MANAGEMENT {
+            set(Feature::InvariantTsc, __cpuid(LEAF_POWER_MANAGEMENT).edx, 8);
+        }
+
+        let mut brand = [0u8; 48];
+        if max_extended >= LEAF_BRAND_STRING + 2 {
+            for (i, chunk) in brand.chunks_exact_mut(16).enumerate() {
+                let result = __cpuid(LEAF_BRAND_STRING + i as u32);
+                for (j, register) in [result.eax, result.ebx, result.ecx, result.edx]
+                    .iter()
+                    .enumerate()
+                {
+                    chunk[j * 4..j * 4 + 4].copy_from_slice(&register.to_le_bytes());
+                }
+            }
+        }
+
+        Self {
+            vendor,
+            brand,
+            features,
+        }
+    }
+
+    /// 厂商标识，例如 `GenuineIntel`
+    pub fn vendor(&self) -> &str {
+        core::str::from_utf8(&self.vendor).unwrap_or("unknown")
+    }
+
+    /// 型号字符串，处理器不支持时为空
+    pub fn brand(&self) -> &str {
+        let len = self.brand.iter().position(|&b| b == 0).unwrap_or(48);
+        core::str::from_utf8(&self.brand[..len])
+            .unwrap_or("")
+            .trim()
+    }
+
+    /// 是否支持某个特性
+    ///
+    /// # 参数
+    ///
+    /// - `feature`: 处理器特性
+    pub fn has(&self, feature: Feature) -> bool {
+        self.features & feature.bit() != 0
+    }
+}
+
+lazy_static! {
+    static ref CPU_INFO: CpuInfo = CpuInfo::detect();
+}
+
+/// 获取 CPUID 查询结果
+pub fn info() -> &'static CpuInfo {
+    &CPU_INFO
+}
+
+/// 处理器是否支持某个特性
+///
+/// # 参数
+///
+/// - `feature`: 处理器特性
+pub fn has(feature: Feature) -> bool {
+    CPU_INFO.has(feature)
+}
+
+/// 查询 CPUID 并打印处理器信息
+pub fn init() {
+    let info = info();
+    log::info!("{} {}", info.vendor(), info.brand());
+    for feature in Feature::ALL {
+        log::debug!(
+            "{:<13} {}",
+            feature.name(),
+            if info.has(feature) { "yes" } else { "no" }
+        );
+    }
+}
+
+#[test_case]
+fn test_detects_baseline_features() {
+    // x86_64 要求处理器至少支持 SSE2 和 TSC
+    assert!(has(Feature::Sse));
+    assert!(has(Feature::Sse2));
+    assert!(has(Feature::Tsc));
+    assert_eq!(info().vendor().len(), 12);
+}
@@ -0,0 +1,45 @@
+//! 本模块实现了 QEMU debugcon 输出
+//!
+//! 写入 0xe9 端口的字节会被 QEMU 原样转发到 `-debugcon` 指定的设备（例如 `-debugcon stdio`），
+//! 不需要任何初始化，因此可以作为启动过程中最早可用的日志通道
+
+use core::fmt::{self, Write};
+
+use crate::arch::port::Port;
+
+/// debugcon 的 I/O 端口
+const DEBUGCON_PORT: u16 = 0xe9;
+
+/// debugcon 写入器，不持有任何状态
+pub struct DebugCon;
+
+impl fmt::Write for DebugCon {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let mut port = Port::<u8>::new(DEBUGCON_PORT);
+        for byte in s.bytes() {
+            unsafe { port.write(byte) };
+        }
+        Ok(())
+    }
+}
+
+/// 通过 debugcon 打印
+#[macro_export]
+macro_rules! debugcon_print {
+    ($($arg:tt)*) => ($crate::debugcon::_print(format_args!($($arg)*)));
+}
+
+/// 通过 debugcon 打印，并追加换行符
+#[macro_export]
+macro_rules! debugcon_println {
+    () => ($crate::debugcon_print!("\n"));
+    ($($arg:tt)*) => ($crate::debugcon_print!("{}\n", format_args!($($arg)*)));
+}
+
+#[doc(hidden)]
+pub fn _print(args: fmt::Arguments) {
+    // 端口写入无需加锁，多个核心同时打印时只可能出现字符交错
+    DebugCon
+        .write_fmt(args)
+        .expect("Printing to debugcon failed");
+}
@@ -0,0 +1,148 @@
+//! 本模块实现了内核日志的环形缓冲区
+//!
+//! 每条日志都会带上 tick 时间戳保存在固定大小的静态缓冲区中，不依赖堆，
+//! 因此早期启动日志在滚出屏幕之后仍可通过 [`dump`] 找回。缓冲区写满时覆盖最旧的日志
+
+use core::fmt::{self, Write};
+
+use log::Record;
+use spin::Mutex;
+
+use crate::{println, time};
+
+/// 环形缓冲区的字节数
+const DMESG_BUFFER_SIZE: usize = 16 * 1024;
+/// 读取时单行的最大字节数，超出部分被截断
+const MAX_LINE_LEN: usize = 256;
+
+/// 字节环形缓冲区
+struct RingBuffer<const N: usize> {
+    bytes: [u8; N],
+    start: usize,  // 最旧字节的位置
+    len: usize,    // 已保存的字节数
+    wrapped: bool, // 是否覆盖过旧数据，此时第一行可能不完整
+}
+
+impl<const N: usize> RingBuffer<N> {
+    const fn new() -> Self {
+        Self {
+            bytes: [0; N],
+            start: 0,
+            len: 0,
+            wrapped: false,
+        }
+    }
+
+    /// 追加一个字节，缓冲区已满时覆盖最旧的字节
+    ///
+    /// # 参数
+    ///
+    /// - `byte`: 要追加的字节
+    fn push(&mut self, byte: u8) {
+        if self.len == N {
+            self.start = (self.start + 1) % N;
+            self.len -= 1;
+            self.wrapped = true;
+        }
+        self.bytes[(self.start + self.len) % N] = byte;
+        self.len += 1;
+    }
+
+    /// 从旧到新遍历保存的每一行，不含换行符
+    ///
+    /// # 参数
+    ///
+    /// - `f`: 对每一行调用的函数
+    fn for_each_line(&self, mut f: impl FnMut(&str)) {
+        let mut line = [0u8; MAX_LINE_LEN];
+        let mut line_len = 0;
+        // 被覆盖过时跳过残缺的第一行
+        let mut skipping = self.wrapped;
+        for i in 0..self.len {
+            let byte = self.bytes[(self.start + i) % N];
+            if byte == b'\n' {
+                if !skipping {
+                    f(utf8_prefix(&line[..line_len]));
+                }
+                skipping = false;
+                line_len = 0;
+            } else if !skipping && line_len < MAX_LINE_LEN {
+                line[line_len] = byte;
+                line_len += 1;
+            }
+        }
+    }
+}
+
+impl<const N: usize> fmt::Write for RingBuffer<N> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        s.bytes().for_each(|byte| self.push(byte));
+        Ok(())
+    }
+}
+
+/// 取字节序列中最长的合法 UTF-8 前缀，用于处理被截断的行
+///
+/// # 参数
+///
+/// - `bytes`: 字节序列
+fn utf8_prefix(bytes: &[u8]) -> &str {
+    match core::str::from_utf8(bytes) {
+        Ok(s) => s,
+        Err(e) => core::str::from_utf8(&bytes[..e.valid_up_to()]).unwrap_or(""),
+    }
+}
+
+/// 内核日志缓冲区
+static DMESG: Mutex<RingBuffer<DMESG_BUFFER_SIZE>> = Mutex::new(RingBuffer::new());
+
+/// 保存一条日志，由日志器调用
+///
+/// # 参数
+///
+/// - `record`: 日志记录
+pub(crate) fn record(record: &Record) {
+    use x86_64::instructions::interrupts;
+
+    // 中断处理函数也可能记录日志，持有锁期间禁用中断
+    interrupts::without_interrupts(|| {
+        let _ = writeln!(
+            DMESG.lock(),
+            "[{:>8}] {:<5} {}: {}",
+            time::ticks(),
+            record.level(),
+            record.target(),
+            record.args()
+        );
+    });
+}
+
+/// 从旧到新遍历保存的每一条日志
+///
+/// # 参数
+///
+/// - `f`: 对每一行日志调用的函数
+pub fn for_each_line(f: impl FnMut(&str)) {
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| DMESG.lock().for_each_line(f));
+}
+
+/// 将保存的全部日志打印到控制台
+pub fn dump() {
+    for_each_line(|line| println!("{}", line));
+}
+
+#[test_case]
+fn test_ring_buffer_keeps_latest_lines() {
+    let mut ring = RingBuffer::<16>::new();
+    // 共 19 字节，最旧的 "fir" 被覆盖，残缺的 "st" 一行被跳过
+    write!(ring, "first\nsecond\nthird\n").unwrap();
+    let expected = ["second", "third"];
+    let mut count = 0;
+    ring.for_each_line(|line| {
+        assert_eq!(line, expected[count]);
+        count += 1;
+    });
+    assert_eq!(count, expected.len());
+}
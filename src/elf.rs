@@ -0,0 +1,430 @@
+//! 本模块实现了 ELF64 可执行文件的解析和加载
+//!
+//! 只支持静态链接的 x86_64 小端可执行文件（`ET_EXEC`）。所有可加载段都必须位于用户地址空间中、
+//! 不能与用户栈重叠，入口地址必须落在可执行的段中；加载中途失败时撤销已经建立的映射
+
+use alloc::vec::Vec;
+use core::fmt;
+
+use x86_64::VirtAddr;
+use x86_64::structures::paging::mapper::MapToError;
+use x86_64::structures::paging::{PageTableFlags, Size4KiB};
+
+use crate::syscall::{E2BIG, ENOEXEC, ENOMEM};
+use crate::user::{
+    self, USER_SPACE_END, USER_SPACE_START, USER_STACK_BOTTOM, USER_STACK_PAGES, USER_STACK_TOP,
+};
+
+/// ELF 文件头的大小
+const HEADER_SIZE: usize = 64;
+/// 程序头的大小
+const PROGRAM_HEADER_SIZE: usize = 56;
+/// 页大小
+const PAGE_SIZE: u64 = 4096;
+
+/// ELF 魔数
+const ELF_MAGIC: [u8; 4] = [0x7f, b'E', b'L', b'F'];
+/// 64 位 ELF
+const ELFCLASS64: u8 = 2;
+/// 小端
+const ELFDATA2LSB: u8 = 1;
+/// 可执行文件
+pub const ET_EXEC: u16 = 2;
+/// x86_64 架构
+pub const EM_X86_64: u16 = 62;
+
+/// 可加载段
+pub const PT_LOAD: u32 = 1;
+/// 段可执行
+pub const PF_X: u32 = 1;
+/// 段可写
+pub const PF_W: u32 = 2;
+/// 段可读
+pub const PF_R: u32 = 4;
+
+/// 解析或加载 ELF 文件时的错误
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ElfError {
+    Truncated,        // 文件比头部声明的短
+    BadMagic,         // 不是 ELF 文件
+    Unsupported,      // 不是 x86_64 小端 64 位可执行文件
+    BadSegment,       // 段的大小或偏移不合法
+    BadAddress,       // 段不在用户地址空间中，或与用户栈、已有映射冲突
+    BadEntry,         // 入口地址不在可执行的段中
+    ArgumentsTooLong, // 参数放不进用户栈
+    OutOfMemory,      // 没有足够的物理帧
+}
+
+impl fmt::Display for ElfError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ElfError::Truncated => write!(f, "file is truncated"),
+            ElfError::BadMagic => write!(f, "not an ELF file"),
+            ElfError::Unsupported => write!(f, "not an x86_64 executable"),
+            ElfError::BadSegment => write!(f, "malformed segment"),
+            ElfError::BadAddress => {
+                write!(f, "segment outside user space or overlapping the stack")
+            }
+            ElfError::BadEntry => write!(f, "entry point outside executable segments"),
+            ElfError::ArgumentsTooLong => write!(f, "arguments do not fit on the stack"),
+            ElfError::OutOfMemory => write!(f, "out of memory"),
+        }
+    }
+}
+
+impl ElfError {
+    /// 对应的系统调用错误码
+    pub fn errno(self) -> i64 {
+        match self {
+            ElfError::ArgumentsTooLong => E2BIG,
+            ElfError::OutOfMemory => ENOMEM,
+            _ => ENOEXEC,
+        }
+    }
+}
+
+impl From<MapToError<Size4KiB>> for ElfError {
+    fn from(error: MapToError<Size4KiB>) -> Self {
+        match error {
+            MapToError::FrameAllocationFailed => ElfError::OutOfMemory,
+            MapToError::ParentEntryHugePage | MapToError::PageAlreadyMapped(_) => {
+                ElfError::BadAddress
+            }
+        }
+    }
+}
+
+/// 程序头
+#[derive(Debug, Clone, Copy)]
+pub struct ProgramHeader {
+    pub kind: u32,   // 段类型，如 `PT_LOAD`
+    pub flags: u32,  // 段权限，`PF_R`/`PF_W`/`PF_X` 的组合
+    pub offset: u64, // 段在文件中的偏移
+    pub vaddr: u64,  // 段的虚拟地址
+    pub filesz: u64, // 段在文件中的大小
+    pub memsz: u64,  // 段在内存中的大小，超出文件大小的部分填零
+}
+
+impl ProgramHeader {
+    /// 段在内存中的结束地址（不含）
+    fn end(&self) -> Result<u64, ElfError> {
+        self.vaddr
+            .checked_add(self.memsz)
+            .ok_or(ElfError::BadAddress)
+    }
+
+    /// 段占用的页：起始地址和页数
+    fn pages(&self) -> Result<(VirtAddr, u64), ElfError> {
+        let first_page = self.vaddr & !(PAGE_SIZE - 1);
+        let pages = (self.end()? - first_page).div_ceil(PAGE_SIZE);
+        Ok((VirtAddr::new(first_page), pages))
+    }
+}
+
+/// 已通过校验的 ELF 文件
+pub struct ElfFile<'a> {
+    data: &'a [u8],
+    entry: u64,
+    phoff: usize,
+    phnum: usize,
+}
+
+impl<'a> ElfFile<'a> {
+    /// 解析并校验 ELF 文件头和程序头表
+    ///
+    /// # 参数
+    ///
+    /// - `data`: 内存中的 ELF 文件
+    pub fn parse(data: &'a [u8]) -> Result<Self, ElfError> {
+        if data.len() < HEADER_SIZE {
+            return Err(ElfError::Truncated);
+        }
+        if data[..4] != ELF_MAGIC {
+            return Err(ElfError::BadMagic);
+        }
+        if data[4] != ELFCLASS64
+            || data[5] != ELFDATA2LSB
+            || read_u16(data, 16) != ET_EXEC
+            || read_u16(data, 18) != EM_X86_64
+            || read_u16(data, 54) as usize != PROGRAM_HEADER_SIZE
+        {
+            return Err(ElfError::Unsupported);
+        }
+
+        let phoff = read_u64(data, 32) as usize;
+        let phnum = read_u16(data, 56) as usize;
+        let table_end = phnum
+            .checked_mul(PROGRAM_HEADER_SIZE)
+            .and_then(|size| size.checked_add(phoff))
+            .ok_or(ElfError::Truncated)?;
+        if table_end > data.len() {
+            return Err(ElfError::Truncated);
+        }
+
+        let entry = read_u64(data, 24);
+        if VirtAddr::try_new(entry).is_err() {
+            return Err(ElfError::BadEntry);
+        }
+
+        Ok(Self {
+            data,
+            entry,
+            phoff,
+            phnum,
+        })
+    }
+
+    /// 入口地址
+    pub fn entry(&self) -> VirtAddr {
+        VirtAddr::new(self.entry)
+    }
+
+    /// 遍历程序头
+    pub fn program_headers(&self) -> impl Iterator<Item = ProgramHeader> + '_ {
+        (0..self.phnum).map(|i| {
+            let at = self.phoff + i * PROGRAM_HEADER_SIZE;
+            ProgramHeader {
+                kind: read_u32(self.data, at),
+                flags: read_u32(self.data, at + 4),
+                offset: read_u64(self.data, at + 8),
+                vaddr: read_u64(self.data, at + 16),
+                filesz: read_u64(self.data, at + 32),
+                memsz: read_u64(self.data, at + 40),
+            }
+        })
+    }
+
+    /// 段在文件中的内容
+    ///
+    /// # 参数
+    ///
+    /// - `header`: 段的程序头
+    fn segment_data(&self, header: &ProgramHeader) -> Result<&'a [u8], ElfError> {
+        let start = usize::try_from(header.offset).map_err(|_| ElfError::BadSegment)?;
+        let len = usize::try_from(header.filesz).map_err(|_| ElfError::BadSegment)?;
+        let end = start.checked_add(len).ok_or(ElfError::BadSegment)?;
+        self.data.get(start..end).ok_or(ElfError::Truncated)
+    }
+}
+
+/// 校验所有可加载段和入口地址，不修改地址空间
+///
+/// # 参数
+///
+/// - `elf`: 已解析的 ELF 文件
+pub(crate) fn check_segments(elf: &ElfFile) -> Result<(), ElfError> {
+    let mut entry_found = false;
+    for header in elf.program_headers().filter(|h| h.kind == PT_LOAD) {
+        if header.filesz > header.memsz {
+            return Err(ElfError::BadSegment);
+        }
+        let end = header.end()?;
+        if header.vaddr < USER_SPACE_START || end > USER_SPACE_END {
+            return Err(ElfError::BadAddress);
+        }
+        elf.segment_data(&header)?;
+        if header.memsz == 0 {
+            continue;
+        }
+        // 段所在的页不能与用户栈重叠，否则栈会覆盖段的内容
+        let (first_page, _) = header.pages()?;
+        if first_page.as_u64() < USER_STACK_TOP && end > USER_STACK_BOTTOM {
+            return Err(ElfError::BadAddress);
+        }
+        if header.flags & PF_X != 0 && (header.vaddr..end).contains(&elf.entry) {
+            entry_found = true;
+        }
+    }
+    if entry_found {
+        Ok(())
+    } else {
+        Err(ElfError::BadEntry)
+    }
+}
+
+/// 将所有可加载段映射到当前地址空间
+///
+/// 所有段都先通过校验再开始映射；映射或复制中途失败时解除已经建立的映射
+///
+/// # 参数
+///
+/// - `elf`: 已解析的 ELF 文件
+pub fn load(elf: &ElfFile) -> Result<(), ElfError> {
+    check_segments(elf)?;
+    let result = (|| {
+        for header in elf.program_headers().filter(|h| h.kind == PT_LOAD) {
+            if header.memsz == 0 {
+                continue;
+            }
+            let data = elf.segment_data(&header)?;
+            let mut flags = PageTableFlags::empty();
+            if header.flags & PF_W != 0 {
+                flags |= PageTableFlags::WRITABLE;
+            }
+            if header.flags & PF_X == 0 {
+                flags |= PageTableFlags::NO_EXECUTE;
+            }
+            let (first_page, pages) = header.pages()?;
+            user::map_region(first_page, pages, flags)?;
+            // 新映射的帧已清零，超出文件大小的部分不需要再处理
+            if !user::copy_to(VirtAddr::new(header.vaddr), data) {
+                return Err(ElfError::BadAddress);
+            }
+        }
+        Ok(())
+    })();
+    if result.is_err() {
+        unload(elf);
+    }
+    result
+}
+
+/// 解除 [`load`] 为所有可加载段建立的映射
+///
+/// 当前地址空间的用户部分在加载之前应当是空的，段所在的页都属于这个文件
+///
+/// # 参数
+///
+/// - `elf`: 已通过校验的 ELF 文件
+fn unload(elf: &ElfFile) {
+    for header in elf
+        .program_headers()
+        .filter(|h| h.kind == PT_LOAD && h.memsz != 0)
+    {
+        if let Ok((first_page, pages)) = header.pages() {
+            user::unmap_region(first_page, pages);
+        }
+    }
+}
+
+/// 在当前地址空间中映射用户栈，并按 System V ABI 放入 `argc`、`argv`、空的环境变量和辅助向量
+///
+/// # 参数
+///
+/// - `argv`: 程序参数
+///
+/// # 返回
+///
+/// 用户程序入口处的栈指针，指向 `argc`
+pub fn setup_stack(argv: &[&str]) -> Result<VirtAddr, ElfError> {
+    let result = map_arguments(argv);
+    if result.is_err() {
+        user::unmap_region(VirtAddr::new(USER_STACK_BOTTOM), USER_STACK_PAGES);
+    }
+    result
+}
+
+/// 映射用户栈并写入参数，失败时由 [`setup_stack`] 解除栈的映射
+fn map_arguments(argv: &[&str]) -> Result<VirtAddr, ElfError> {
+    let top = user::map_stack()?.as_u64();
+
+    // 参数字符串放在栈顶，以 NUL 结尾
+    let strings_len: u64 = argv.iter().map(|arg| arg.len() as u64 + 1).sum();
+    let strings_start = top - strings_len;
+    // argc、argv 指针、argv 结尾的 NULL、envp 结尾的 NULL、辅助向量的 AT_NULL 项
+    let words = 1 + argv.len() as u64 + 1 + 1 + 2;
+    let sp = (strings_start - words * 8) & !0xf;
+    if top - sp > USER_STACK_PAGES * PAGE_SIZE / 2 {
+        return Err(ElfError::ArgumentsTooLong);
+    }
+
+    let mut image = Vec::with_capacity((top - sp) as usize);
+    image.extend_from_slice(&(argv.len() as u64).to_le_bytes());
+    let mut string_addr = strings_start;
+    for arg in argv {
+        image.extend_from_slice(&string_addr.to_le_bytes());
+        string_addr += arg.len() as u64 + 1;
+    }
+    image.resize(image.len() + 4 * 8, 0);
+    image.resize((strings_start - sp) as usize, 0);
+    for arg in argv {
+        image.extend_from_slice(arg.as_bytes());
+        image.push(0);
+    }
+
+    if !user::copy_to(VirtAddr::new(sp), &image) {
+        return Err(ElfError::BadAddress);
+    }
+    Ok(VirtAddr::new(sp))
+}
+
+/// 在当前地址空间中加载并运行用户程序，替换当前线程
+///
+/// 当前地址空间的用户部分应当是空的，通常由 [`process::spawn`](crate::process::spawn)
+/// 创建的进程在自己的第一个线程中调用
+///
+/// # 参数
+///
+/// - `image`: 内存中的 ELF 文件
+/// - `argv`: 程序参数
+///
+/// # 返回
+///
+/// 只在加载失败时返回
+pub fn exec(image: &[u8], argv: &[&str]) -> ElfError {
+    let result = ElfFile::parse(image).and_then(|elf| {
+        load(&elf)?;
+        match setup_stack(argv) {
+            Ok(sp) => Ok((elf.entry(), sp)),
+            Err(error) => {
+                unload(&elf);
+                Err(error)
+            }
+        }
+    });
+    match result {
+        Ok((entry, sp)) => unsafe { user::enter(entry, sp) },
+        Err(error) => error,
+    }
+}
+
+/// 读取小端的 u16
+fn read_u16(data: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes([data[at], data[at + 1]])
+}
+
+/// 读取小端的 u32
+fn read_u32(data: &[u8], at: usize) -> u32 {
+    let mut bytes = [0; 4];
+    bytes.copy_from_slice(&data[at..at + 4]);
+    u32::from_le_bytes(bytes)
+}
+
+/// 读取小端的 u64
+fn read_u64(data: &[u8], at: usize) -> u64 {
+    let mut bytes = [0; 8];
+    bytes.copy_from_slice(&data[at..at + 8]);
+    u64::from_le_bytes(bytes)
+}
+
+#[test_case]
+fn test_parse_rejects_bad_headers() {
+    assert_eq!(ElfFile::parse(&[0; 16]).err(), Some(ElfError::Truncated));
+    assert_eq!(ElfFile::parse(&[0; 64]).err(), Some(ElfError::BadMagic));
+
+    let mut header = [0u8; HEADER_SIZE];
+    header[..4].copy_from_slice(&ELF_MAGIC);
+    header[4] = ELFCLASS64;
+    header[5] = ELFDATA2LSB;
+    header[16..18].copy_from_slice(&ET_EXEC.to_le_bytes());
+    header[18..20].copy_from_slice(&EM_X86_64.to_le_bytes());
+    header[54..56].copy_from_slice(&(PROGRAM_HEADER_SIZE as u16).to_le_bytes());
+    header[24..32].copy_from_slice(&0x1234u64.to_le_bytes());
+    let elf = ElfFile::parse(&header).expect("header without segments is valid");
+    assert_eq!(elf.entry().as_u64(), 0x1234);
+    assert_eq!(elf.program_headers().count(), 0);
+    // 没有可执行的段时入口地址无处可落
+    assert_eq!(check_segments(&elf).err(), Some(ElfError::BadEntry));
+
+    // 程序头表超出文件末尾
+    header[56..58].copy_from_slice(&1u16.to_le_bytes());
+    assert_eq!(ElfFile::parse(&header).err(), Some(ElfError::Truncated));
+
+    header[56..58].copy_from_slice(&0u16.to_le_bytes());
+    header[24..32].copy_from_slice(&0x8000_0000_0000u64.to_le_bytes());
+    assert_eq!(ElfFile::parse(&header).err(), Some(ElfError::BadEntry));
+    header[24..32].copy_from_slice(&0x1234u64.to_le_bytes());
+
+    header[4] = 1;
+    assert_eq!(ElfFile::parse(&header).err(), Some(ElfError::Unsupported));
+}
@@ -0,0 +1,397 @@
+//! 本模块实现了线性帧缓冲图形输出
+//!
+//! bootloader 0.9 只在 BIOS 文本模式下启动内核，不传递帧缓冲，这里通过 QEMU/Bochs 标准 VGA 的
+//! DISPI 接口自行设置图形模式（见 [`bochs`]）。[`Framebuffer`] 提供像素、矩形和位图的绘制，
+//! [`console::Console`] 在其上用文本模式的字体绘制文字，可以代替 0xb8000 的文本缓冲区作为控制台输出。
+//!
+//! 图形模式默认关闭；编译时设置环境变量 `RICKY_OS_FRAMEBUFFER`（如 `1024x768`）后在启动时切换。
+//! 控制台默认使用内置的 8x16 字体，同时设置 `RICKY_OS_FRAMEBUFFER_FONT=vga` 时改用文本模式显存中的字体
+
+pub mod bochs;
+pub mod console;
+pub mod font;
+
+use core::fmt;
+
+use x86_64::PhysAddr;
+
+use self::console::Console;
+use self::font::Font;
+use crate::memory;
+use crate::sync::SpinLock;
+use crate::vga_buffer::Color;
+
+/// 24 位颜色
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rgb {
+    pub r: u8, // 红色分量
+    pub g: u8, // 绿色分量
+    pub b: u8, // 蓝色分量
+}
+
+impl Rgb {
+    pub const BLACK: Rgb = Rgb::new(0, 0, 0);
+
+    pub const fn new(r: u8, g: u8, b: u8) -> Self {
+        Self { r, g, b }
+    }
+
+    /// VGA 颜色编号（0~15）对应的颜色
+    pub fn from_vga_index(index: u8) -> Self {
+        VGA_PALETTE[usize::from(index & 0x0f)]
+    }
+}
+
+/// 16 种 VGA 颜色在标准调色板中的取值，按 VGA 颜色编号排列
+const VGA_PALETTE: [Rgb; 16] = [
+    Rgb::new(0x00, 0x00, 0x00),
+    Rgb::new(0x00, 0x00, 0xaa),
+    Rgb::new(0x00, 0xaa, 0x00),
+    Rgb::new(0x00, 0xaa, 0xaa),
+    Rgb::new(0xaa, 0x00, 0x00),
+    Rgb::new(0xaa, 0x00, 0xaa),
+    Rgb::new(0xaa, 0x55, 0x00),
+    Rgb::new(0xaa, 0xaa, 0xaa),
+    Rgb::new(0x55, 0x55, 0x55),
+    Rgb::new(0x55, 0x55, 0xff),
+    Rgb::new(0x55, 0xff, 0x55),
+    Rgb::new(0x55, 0xff, 0xff),
+    Rgb::new(0xff, 0x55, 0x55),
+    Rgb::new(0xff, 0x55, 0xff),
+    Rgb::new(0xff, 0xff, 0x55),
+    Rgb::new(0xff, 0xff, 0xff),
+];
+
+impl From<Color> for Rgb {
+    fn from(color: Color) -> Self {
+        Self::from_vga_index(color as u8)
+    }
+}
+
+/// 每个 32 位像素中颜色分量的排列
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PixelFormat {
+    Bgr, // 内存中依次为蓝、绿、红、保留字节
+    Rgb, // 内存中依次为红、绿、蓝、保留字节
+}
+
+impl PixelFormat {
+    /// 把颜色编码为像素值
+    fn encode(self, color: Rgb) -> u32 {
+        let (r, g, b) = (u32::from(color.r), u32::from(color.g), u32::from(color.b));
+        match self {
+            PixelFormat::Bgr => (r << 16) | (g << 8) | b,
+            PixelFormat::Rgb => (b << 16) | (g << 8) | r,
+        }
+    }
+}
+
+/// 每像素 32 位的线性帧缓冲
+pub struct Framebuffer {
+    pixels: &'static mut [u32], // 按行排列的像素，共 stride * height 个
+    width: usize,               // 可见的列数
+    height: usize,              // 行数
+    stride: usize,              // 每行占用的像素数，不小于 width
+    format: PixelFormat,        // 像素格式
+}
+
+impl Framebuffer {
+    /// 用已映射的像素内存创建帧缓冲
+    ///
+    /// # 参数
+    ///
+    /// - `pixels`: 像素内存，长度至少为 `stride * height`
+    /// - `width`: 可见的列数
+    /// - `height`: 行数
+    /// - `stride`: 每行占用的像素数
+    /// - `format`: 像素格式
+    ///
+    /// # 返回
+    ///
+    /// 像素内存不够或 `stride` 小于 `width` 时返回 `None`
+    pub fn new(
+        pixels: &'static mut [u32],
+        width: usize,
+        height: usize,
+        stride: usize,
+        format: PixelFormat,
+    ) -> Option<Self> {
+        if stride < width || pixels.len() < stride.checked_mul(height)? {
+            return None;
+        }
+        Some(Self {
+            pixels,
+            width,
+            height,
+            stride,
+            format,
+        })
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// 读取一个像素，坐标超出屏幕时返回 `None`
+    pub fn pixel(&self, x: usize, y: usize) -> Option<u32> {
+        (x < self.width && y < self.height).then(|| self.pixels[y * self.stride + x])
+    }
+
+    /// 绘制一个像素，坐标超出屏幕时忽略
+    ///
+    /// # 参数
+    ///
+    /// - `x`, `y`: 像素坐标，原点在左上角
+    /// - `color`: 颜色
+    pub fn put_pixel(&mut self, x: usize, y: usize, color: Rgb) {
+        if x < self.width && y < self.height {
+            self.pixels[y * self.stride + x] = self.format.encode(color);
+        }
+    }
+
+    /// 填充一个矩形，超出屏幕的部分被裁掉
+    ///
+    /// # 参数
+    ///
+    /// - `x`, `y`: 左上角坐标
+    /// - `width`, `height`: 矩形的宽和高
+    /// - `color`: 颜色
+    pub fn fill_rect(&mut self, x: usize, y: usize, width: usize, height: usize, color: Rgb) {
+        let value = self.format.encode(color);
+        let right = x.saturating_add(width).min(self.width);
+        let bottom = y.saturating_add(height).min(self.height);
+        if x >= right {
+            return;
+        }
+        for row in y..bottom {
+            let start = row * self.stride;
+            self.pixels[start + x..start + right].fill(value);
+        }
+    }
+
+    /// 将按行排列的位图复制到屏幕上，超出屏幕的部分被裁掉
+    ///
+    /// # 参数
+    ///
+    /// - `x`, `y`: 左上角坐标
+    /// - `width`: 位图的宽度，`image` 的长度应为它的整数倍
+    /// - `image`: 位图的像素
+    pub fn blit(&mut self, x: usize, y: usize, width: usize, image: &[Rgb]) {
+        if width == 0 || x >= self.width {
+            return;
+        }
+        for (dy, line) in image.chunks_exact(width).enumerate() {
+            let row = y + dy;
+            if row >= self.height {
+                break;
+            }
+            let start = row * self.stride;
+            let visible = self.width.saturating_sub(x).min(width);
+            for (pixel, &color) in self.pixels[start + x..][..visible].iter_mut().zip(line) {
+                *pixel = self.format.encode(color);
+            }
+        }
+    }
+
+    /// 绘制每行 8 像素的单色位图，位图的最高位在左
+    ///
+    /// # 参数
+    ///
+    /// - `x`, `y`: 左上角坐标
+    /// - `rows`: 位图的每一行
+    /// - `foreground`: 置位像素的颜色
+    /// - `background`: 其余像素的颜色
+    pub fn draw_bitmap(
+        &mut self,
+        x: usize,
+        y: usize,
+        rows: &[u8],
+        foreground: Rgb,
+        background: Rgb,
+    ) {
+        if x >= self.width {
+            return;
+        }
+        let foreground = self.format.encode(foreground);
+        let background = self.format.encode(background);
+        let visible = self.width.saturating_sub(x).min(8);
+        for (dy, &bits) in rows.iter().enumerate() {
+            let row = y + dy;
+            if row >= self.height {
+                break;
+            }
+            let start = row * self.stride + x;
+            for (dx, pixel) in self.pixels[start..start + visible].iter_mut().enumerate() {
+                *pixel = if bits & (0x80 >> dx) != 0 {
+                    foreground
+                } else {
+                    background
+                };
+            }
+        }
+    }
+
+    /// 将画面上移 `lines` 行像素，底部露出的部分填充为 `color`
+    pub fn scroll_up(&mut self, lines: usize, color: Rgb) {
+        let lines = lines.min(self.height);
+        let moved = (self.height - lines) * self.stride;
+        self.pixels
+            .copy_within(lines * self.stride..self.height * self.stride, 0);
+        let value = self.format.encode(color);
+        self.pixels[moved..self.height * self.stride].fill(value);
+    }
+
+    /// 用 `color` 填充整个屏幕
+    pub fn clear(&mut self, color: Rgb) {
+        self.fill_rect(0, 0, self.width, self.height, color);
+    }
+}
+
+/// 帧缓冲控制台，图形模式切换成功后才存在
+static CONSOLE: SpinLock<Option<Console>> = SpinLock::new(None);
+
+/// 解析 `宽x高` 形式的分辨率
+fn parse_resolution(value: &str) -> Option<(u16, u16)> {
+    let (width, height) = value.split_once(['x', 'X'])?;
+    Some((width.trim().parse().ok()?, height.trim().parse().ok()?))
+}
+
+/// 按编译时的配置切换到图形模式，并在帧缓冲上建立控制台
+///
+/// 选择文本模式的字体时，字体必须在切换之前从显存中读取。应在 [`memory::install`] 之后、
+/// [`pci::init`](crate::pci::init) 之后调用
+///
+/// # 返回
+///
+/// 是否已经切换到图形模式，未配置或显卡不支持时保持文本模式
+pub fn init() -> bool {
+    let Some(value) = option_env!("RICKY_OS_FRAMEBUFFER") else {
+        return false;
+    };
+    let Some((width, height)) = parse_resolution(value) else {
+        log::warn!("framebuffer: invalid resolution {:?}", value);
+        return false;
+    };
+    let font = match option_env!("RICKY_OS_FRAMEBUFFER_FONT") {
+        Some("vga") => {
+            let Some(physical_memory_offset) = memory::phys_to_virt(PhysAddr::new(0)) else {
+                return false;
+            };
+            Font::from_text_mode(physical_memory_offset)
+        }
+        _ => Font::builtin(),
+    };
+    let Some(framebuffer) = bochs::set_mode(width, height) else {
+        log::warn!("framebuffer: no display supporting {}x{}", width, height);
+        return false;
+    };
+    let console = Console::new(framebuffer, font);
+    log::info!(
+        "framebuffer: {}x{}, console {}x{}",
+        width,
+        height,
+        console.columns(),
+        console.rows()
+    );
+    *CONSOLE.lock() = Some(console);
+    true
+}
+
+/// 帧缓冲控制台是否可用
+pub fn is_available() -> bool {
+    CONSOLE.lock().is_some()
+}
+
+#[doc(hidden)]
+pub fn _print(args: fmt::Arguments) {
+    use core::fmt::Write;
+    if let Some(console) = CONSOLE.lock().as_mut() {
+        console.write_fmt(args).unwrap();
+    }
+}
+
+#[doc(hidden)]
+pub fn _print_colored(foreground: Color, background: Color, args: fmt::Arguments) {
+    use core::fmt::Write;
+    if let Some(console) = CONSOLE.lock().as_mut() {
+        let saved = console.color();
+        console.set_color(foreground, background);
+        console.write_fmt(args).unwrap();
+        console.set_color_indices(saved);
+    }
+}
+
+/// 清空帧缓冲控制台的滚动区域，光标回到左上角
+pub fn clear() {
+    if let Some(console) = CONSOLE.lock().as_mut() {
+        console.clear();
+    }
+}
+
+/// 保留帧缓冲控制台的第一行作为状态栏
+pub fn reserve_status_line() {
+    if let Some(console) = CONSOLE.lock().as_mut() {
+        console.reserve_status_line();
+    }
+}
+
+/// 以指定颜色重写状态栏，控制台的锁被占用时跳过
+///
+/// # 参数
+///
+/// - `text`: 状态栏的内容
+/// - `foreground`: 前景色
+/// - `background`: 背景色
+pub fn write_status(text: &str, foreground: Color, background: Color) {
+    let Some(mut console) = CONSOLE.try_lock() else {
+        return;
+    };
+    if let Some(console) = console.as_mut() {
+        console.write_status(text, foreground, background);
+    }
+}
+
+/// panic 时接管帧缓冲控制台，清屏后以红底白字显示 `render` 写入的内容
+///
+/// # 参数
+///
+/// - `render`: 写入 panic 信息的函数
+pub fn show_panic(render: &mut dyn FnMut(&mut dyn fmt::Write) -> fmt::Result) {
+    if CONSOLE.is_locked() {
+        // 调用方已禁用中断，panic 的执行流不会再回到持有锁的代码
+        unsafe { CONSOLE.force_unlock() };
+    }
+    if let Some(console) = CONSOLE.lock().as_mut() {
+        console.release_status_line();
+        console.set_color(Color::White, Color::Red);
+        console.clear();
+        let _ = render(console);
+    }
+}
+
+#[test_case]
+fn test_fill_rect_and_blit_clip() {
+    // 每行 6 个像素，只有前 4 个可见
+    static mut PIXELS: [u32; 6 * 3] = [0; 6 * 3];
+    let pixels = unsafe { (&raw mut PIXELS).as_mut() }.unwrap();
+    let mut framebuffer = Framebuffer::new(pixels, 4, 3, 6, PixelFormat::Bgr).unwrap();
+    let white = Rgb::from(Color::White);
+    framebuffer.fill_rect(2, 1, 10, 10, white);
+    assert_eq!(framebuffer.pixel(1, 1), Some(0));
+    assert_eq!(framebuffer.pixel(3, 2), Some(0x00ff_ffff));
+    // 超出可见宽度的像素不被写入
+    assert_eq!(framebuffer.pixels[6 + 4], 0);
+    framebuffer.blit(3, 0, 2, &[Rgb::new(1, 2, 3); 4]);
+    assert_eq!(framebuffer.pixel(3, 0), Some(0x0001_0203));
+    assert_eq!(framebuffer.pixels[4], 0);
+    framebuffer.scroll_up(1, Rgb::BLACK);
+    assert_eq!(framebuffer.pixel(2, 0), Some(0x00ff_ffff));
+    assert_eq!(framebuffer.pixel(3, 1), Some(0x00ff_ffff));
+    assert_eq!(framebuffer.pixel(3, 2), Some(0));
+    assert_eq!(parse_resolution("1024x768"), Some((1024, 768)));
+    assert_eq!(parse_resolution("1024"), None);
+}
@@ -0,0 +1,126 @@
+//! 本模块实现了 QEMU/Bochs 标准 VGA 的 DISPI 模式设置
+//!
+//! 显卡的 PCI ID 为 1234:1111，BAR0 是线性帧缓冲；分辨率和色深通过 0x1ce/0x1cf 端口上的
+//! DISPI 寄存器（索引/数据对）设置，启用时同时打开线性帧缓冲，之后 0xb8000 的文本缓冲区不再显示
+
+use x86_64::PhysAddr;
+
+use super::{Framebuffer, PixelFormat, Rgb};
+use crate::arch::port::Port;
+use crate::memory;
+use crate::pci::{self, Bar};
+
+/// 显卡的厂商 ID
+const VENDOR_ID: u16 = 0x1234;
+/// 显卡的设备 ID
+const DEVICE_ID: u16 = 0x1111;
+
+/// DISPI 索引寄存器端口
+const INDEX_PORT: u16 = 0x01ce;
+/// DISPI 数据寄存器端口
+const DATA_PORT: u16 = 0x01cf;
+
+/// DISPI 寄存器：接口版本
+const INDEX_ID: u16 = 0x0;
+/// DISPI 寄存器：水平分辨率
+const INDEX_XRES: u16 = 0x1;
+/// DISPI 寄存器：垂直分辨率
+const INDEX_YRES: u16 = 0x2;
+/// DISPI 寄存器：每像素位数
+const INDEX_BPP: u16 = 0x3;
+/// DISPI 寄存器：启用控制
+const INDEX_ENABLE: u16 = 0x4;
+/// DISPI 寄存器：虚拟屏幕宽度，即每行的像素数
+const INDEX_VIRT_WIDTH: u16 = 0x6;
+
+/// 最低支持的接口版本，此后才支持 32 位色深
+const ID_MIN: u16 = 0xb0c2;
+/// 已知的最高接口版本
+const ID_MAX: u16 = 0xb0c5;
+
+/// 启用控制：启用 DISPI 模式
+const ENABLE_ENABLED: u16 = 0x01;
+/// 启用控制：启用线性帧缓冲
+const ENABLE_LFB: u16 = 0x40;
+
+/// 每像素位数
+const BITS_PER_PIXEL: u16 = 32;
+/// 每像素字节数
+const BYTES_PER_PIXEL: usize = 4;
+/// DISPI 支持的最大分辨率
+const MAX_RESOLUTION: (u16, u16) = (2560, 1600);
+
+/// 读取 DISPI 寄存器
+///
+/// # 参数
+///
+/// - `index`: 寄存器编号
+fn read(index: u16) -> u16 {
+    unsafe {
+        Port::new(INDEX_PORT).write(index);
+        Port::new(DATA_PORT).read()
+    }
+}
+
+/// 写入 DISPI 寄存器
+///
+/// # 参数
+///
+/// - `index`: 寄存器编号
+/// - `value`: 要写入的值
+fn write(index: u16, value: u16) {
+    unsafe {
+        Port::new(INDEX_PORT).write(index);
+        Port::new(DATA_PORT).write(value);
+    }
+}
+
+/// 切换到 `width`x`height`、每像素 32 位的图形模式
+///
+/// # 参数
+///
+/// - `width`: 水平分辨率
+/// - `height`: 垂直分辨率
+///
+/// # 返回
+///
+/// 映射好并清屏的帧缓冲；没有这块显卡、接口版本过旧或显存不够时返回 `None`，仍保持文本模式
+pub fn set_mode(width: u16, height: u16) -> Option<Framebuffer> {
+    if width == 0 || height == 0 || width > MAX_RESOLUTION.0 || height > MAX_RESOLUTION.1 {
+        return None;
+    }
+    let device = pci::find(VENDOR_ID, DEVICE_ID).next()?;
+    let Some(Bar::Memory { address, size, .. }) = device.bar(0) else {
+        return None;
+    };
+    if !(ID_MIN..=ID_MAX).contains(&read(INDEX_ID)) {
+        return None;
+    }
+
+    // 模式寄存器只能在关闭 DISPI 时修改
+    write(INDEX_ENABLE, 0);
+    write(INDEX_XRES, width);
+    write(INDEX_YRES, height);
+    write(INDEX_BPP, BITS_PER_PIXEL);
+    write(INDEX_ENABLE, ENABLE_ENABLED | ENABLE_LFB);
+    // 显卡可能调整虚拟宽度，以它为准
+    let stride = usize::from(read(INDEX_VIRT_WIDTH).max(width));
+    let (width, height) = (usize::from(width), usize::from(height));
+
+    let bytes = (stride * height * BYTES_PER_PIXEL) as u64;
+    if bytes > size {
+        write(INDEX_ENABLE, 0);
+        return None;
+    }
+    device.enable(pci::COMMAND_MEMORY);
+    let Some(base) = memory::map_mmio(PhysAddr::new(address), bytes) else {
+        write(INDEX_ENABLE, 0);
+        return None;
+    };
+    // 映射只增不减，像素内存在之后一直有效
+    let pixels =
+        unsafe { core::slice::from_raw_parts_mut(base.as_mut_ptr::<u32>(), stride * height) };
+    let mut framebuffer = Framebuffer::new(pixels, width, height, stride, PixelFormat::Bgr)?;
+    framebuffer.clear(Rgb::BLACK);
+    Some(framebuffer)
+}
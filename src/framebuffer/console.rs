@@ -0,0 +1,401 @@
+//! 本模块实现了帧缓冲上的文本控制台
+//!
+//! 行为与 VGA 文本模式的写入器一致：解释换行、回车、退格、制表符和 ANSI SGR 颜色序列，
+//! 非 ASCII 字符转换为代码页 437 中的字形，写满屏幕后整体上移一行。
+//!
+//! 屏幕上每个字符格的内容另存一份在内存中。上移时比较新旧内容，只重绘变化的字符格，
+//! 不需要读取未缓存的显存
+
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+
+use super::font::{Font, GLYPH_WIDTH};
+use super::{Framebuffer, Rgb};
+use crate::ansi::{self, CsiSequence};
+use crate::vga_buffer::{ANSI_TO_VGA_COLOR, BRIGHT_BIT, Color, cp437};
+
+/// 退格符
+const BACKSPACE: u8 = 0x08;
+/// 驱动 ANSI 解析器时代替非 ASCII 字符的字节
+const NON_ASCII: u8 = 0xff;
+/// 制表位间隔的列数
+const TAB_WIDTH: usize = 8;
+/// 默认的前景色，与 VGA 文本模式相同
+const DEFAULT_FOREGROUND: u8 = Color::Yellow as u8;
+/// 默认的背景色
+const DEFAULT_BACKGROUND: u8 = Color::Black as u8;
+
+/// 一个字符格的内容
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Cell {
+    glyph: u8,      // 代码页 437 中的字节
+    foreground: u8, // 前景色的 VGA 颜色编号
+    background: u8, // 背景色的 VGA 颜色编号
+}
+
+impl Cell {
+    /// 背景色为 `background` 的空白字符格
+    fn blank(background: u8) -> Self {
+        Self {
+            glyph: b' ',
+            foreground: background,
+            background,
+        }
+    }
+
+    /// 两个字符格画出来是否完全相同，空白字符格不区分前景色
+    fn looks_like(&self, other: &Cell) -> bool {
+        let blank = |cell: &Cell| cell.glyph == b' ' || cell.glyph == 0;
+        if blank(self) && blank(other) {
+            self.background == other.background
+        } else {
+            self == other
+        }
+    }
+}
+
+/// 帧缓冲文本控制台
+pub struct Console {
+    framebuffer: Framebuffer, // 绘制的目标
+    font: Font,               // 字体
+    cells: Vec<Cell>,         // 屏幕上每个字符格的内容，按行排列
+    columns: usize,           // 每行的字符数
+    rows: usize,              // 字符行数
+    top: usize,               // 滚动区域的第一行，之上的行被保留不参与滚动
+    row: usize,               // 光标所在的行
+    column: usize,            // 光标所在的列
+    foreground: u8,           // 前景色的 VGA 颜色编号
+    background: u8,           // 背景色的 VGA 颜色编号
+    parser: ansi::Parser,     // ANSI 转义序列解析器
+}
+
+impl Console {
+    /// 在帧缓冲上创建控制台，光标位于左上角
+    ///
+    /// # 参数
+    ///
+    /// - `framebuffer`: 帧缓冲，控制台会清空它
+    /// - `font`: 绘制文字使用的字体
+    pub fn new(framebuffer: Framebuffer, font: Font) -> Self {
+        let columns = (framebuffer.width() / GLYPH_WIDTH).max(1);
+        let rows = (framebuffer.height() / font.height()).max(1);
+        let mut console = Self {
+            framebuffer,
+            font,
+            cells: vec![Cell::blank(DEFAULT_BACKGROUND); columns * rows],
+            columns,
+            rows,
+            top: 0,
+            row: 0,
+            column: 0,
+            foreground: DEFAULT_FOREGROUND,
+            background: DEFAULT_BACKGROUND,
+            parser: ansi::Parser::new(),
+        };
+        console.clear();
+        console
+    }
+
+    pub fn columns(&self) -> usize {
+        self.columns
+    }
+
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    /// 光标的位置（行, 列）
+    pub fn cursor(&self) -> (usize, usize) {
+        (self.row, self.column)
+    }
+
+    /// 当前的前景色和背景色（VGA 颜色编号）
+    pub fn color(&self) -> (u8, u8) {
+        (self.foreground, self.background)
+    }
+
+    /// 设置之后写入字符的前景色和背景色
+    pub fn set_color(&mut self, foreground: Color, background: Color) {
+        self.set_color_indices((foreground as u8, background as u8));
+    }
+
+    /// 按 VGA 颜色编号设置前景色和背景色，用于恢复 [`color`](Self::color) 的返回值
+    pub fn set_color_indices(&mut self, (foreground, background): (u8, u8)) {
+        self.foreground = foreground & 0x0f;
+        self.background = background & 0x0f;
+    }
+
+    /// 用背景色清空整个滚动区域，光标回到滚动区域的左上角
+    pub fn clear(&mut self) {
+        let background = Rgb::from_vga_index(self.background);
+        self.cells[self.top * self.columns..].fill(Cell::blank(self.background));
+        if self.top == 0 {
+            self.framebuffer.clear(background);
+        } else {
+            let y = self.top * self.font.height();
+            let (width, height) = (self.framebuffer.width(), self.framebuffer.height());
+            self.framebuffer
+                .fill_rect(0, y, width, height - y, background);
+        }
+        self.row = self.top;
+        self.column = 0;
+    }
+
+    /// 保留屏幕第一行作为状态栏，之后的滚动和清屏只作用于其余各行
+    pub fn reserve_status_line(&mut self) {
+        if self.rows > 1 {
+            self.top = 1;
+            self.row = self.row.max(self.top);
+        }
+    }
+
+    /// 取消保留的状态栏，之后的滚动和清屏作用于整个屏幕
+    pub fn release_status_line(&mut self) {
+        self.top = 0;
+    }
+
+    /// 以指定颜色重写状态栏，超出行宽的部分被截断，其余位置用空格填满
+    ///
+    /// # 参数
+    ///
+    /// - `text`: 状态栏的内容
+    /// - `foreground`: 前景色
+    /// - `background`: 背景色
+    pub fn write_status(&mut self, text: &str, foreground: Color, background: Color) {
+        if self.top == 0 {
+            return;
+        }
+        let mut glyphs = text.chars().map(|c| cp437::from_char(c).unwrap_or(b'?'));
+        for column in 0..self.columns {
+            let cell = Cell {
+                glyph: glyphs.next().unwrap_or(b' '),
+                foreground: foreground as u8,
+                background: background as u8,
+            };
+            self.update_cell(column, cell);
+        }
+    }
+
+    /// 将字符串写入控制台，并解释其中的 ANSI 转义序列
+    ///
+    /// # 参数
+    ///
+    /// - `s`: 要写入的字符串
+    pub fn write_string(&mut self, s: &str) {
+        for c in s.chars() {
+            let byte = if c.is_ascii() { c as u8 } else { NON_ASCII };
+            let byte = match self.parser.advance(byte) {
+                Some(ansi::Action::Print(byte)) => byte,
+                Some(ansi::Action::Csi(seq)) => {
+                    self.apply_csi(&seq);
+                    continue;
+                }
+                None => continue,
+            };
+            match byte {
+                b'\n' => self.new_line(),
+                b'\r' => self.column = 0,
+                BACKSPACE => self.backspace(),
+                b'\t' => self.tab(),
+                0x20..=0x7e => self.write_glyph(byte),
+                NON_ASCII => self.write_glyph(cp437::from_char(c).unwrap_or(b'?')),
+                // 其余的控制字符
+                _ => self.write_glyph(0xfe),
+            }
+        }
+    }
+
+    /// 在光标处绘制一个字形并前移光标
+    ///
+    /// # 参数
+    ///
+    /// - `glyph`: 代码页 437 中的字节
+    fn write_glyph(&mut self, glyph: u8) {
+        if self.column >= self.columns {
+            self.new_line();
+        }
+        self.draw_cell(self.row, self.column, glyph);
+        self.column += 1;
+    }
+
+    /// 用当前颜色在指定的字符格中绘制字形
+    fn draw_cell(&mut self, row: usize, column: usize, glyph: u8) {
+        let cell = Cell {
+            glyph,
+            foreground: self.foreground,
+            background: self.background,
+        };
+        self.cells[row * self.columns + column] = cell;
+        self.render(row, column, cell);
+    }
+
+    /// 把字符格的内容画到帧缓冲上
+    fn render(&mut self, row: usize, column: usize, cell: Cell) {
+        let height = self.font.height();
+        self.framebuffer.draw_bitmap(
+            column * GLYPH_WIDTH,
+            row * height,
+            self.font.glyph(cell.glyph),
+            Rgb::from_vga_index(cell.foreground),
+            Rgb::from_vga_index(cell.background),
+        );
+    }
+
+    /// 把字符格 `index` 改为 `cell`，画出来有变化时才重绘
+    fn update_cell(&mut self, index: usize, cell: Cell) {
+        let old = core::mem::replace(&mut self.cells[index], cell);
+        if !old.looks_like(&cell) {
+            self.render(index / self.columns, index % self.columns, cell);
+        }
+    }
+
+    /// 移至下一行，已在最后一行时滚动区域整体上移一行
+    fn new_line(&mut self) {
+        self.column = 0;
+        if self.row + 1 < self.rows {
+            self.row += 1;
+            return;
+        }
+        // 从上往下逐格替换，读取的下一行总是还没被改写
+        let last = (self.rows - 1) * self.columns;
+        for index in self.top * self.columns..last {
+            self.update_cell(index, self.cells[index + self.columns]);
+        }
+        for index in last..last + self.columns {
+            self.update_cell(index, Cell::blank(self.background));
+        }
+    }
+
+    /// 清除光标前的一个字符
+    fn backspace(&mut self) {
+        if self.column == 0 {
+            return;
+        }
+        self.column -= 1;
+        self.draw_cell(self.row, self.column, b' ');
+    }
+
+    /// 用空格填充到下一个制表位，超出行宽时换行
+    fn tab(&mut self) {
+        let next_stop = (self.column / TAB_WIDTH + 1) * TAB_WIDTH;
+        if next_stop >= self.columns {
+            self.new_line();
+            return;
+        }
+        while self.column < next_stop {
+            self.write_glyph(b' ');
+        }
+    }
+
+    /// 执行 CSI 序列，只支持 SGR 颜色和清屏，其余序列忽略
+    fn apply_csi(&mut self, seq: &CsiSequence) {
+        match seq.final_byte {
+            b'm' => self.apply_sgr(seq),
+            b'J' if seq.param_or(0, 0) == 2 => self.clear(),
+            _ => {}
+        }
+    }
+
+    /// 执行 SGR（Select Graphic Rendition）序列
+    fn apply_sgr(&mut self, seq: &CsiSequence) {
+        if seq.params().is_empty() {
+            self.set_color_indices((DEFAULT_FOREGROUND, DEFAULT_BACKGROUND));
+            return;
+        }
+        for &param in seq.params() {
+            // 粗体以高亮前景色表示
+            let bright = self.foreground & BRIGHT_BIT;
+            match param {
+                0 => self.set_color_indices((DEFAULT_FOREGROUND, DEFAULT_BACKGROUND)),
+                1 => self.foreground |= BRIGHT_BIT,
+                22 => self.foreground &= !BRIGHT_BIT,
+                30..=37 => self.foreground = ANSI_TO_VGA_COLOR[usize::from(param - 30)] | bright,
+                39 => self.foreground = DEFAULT_FOREGROUND,
+                40..=47 => self.background = ANSI_TO_VGA_COLOR[usize::from(param - 40)],
+                49 => self.background = DEFAULT_BACKGROUND,
+                90..=97 => {
+                    self.foreground = ANSI_TO_VGA_COLOR[usize::from(param - 90)] | BRIGHT_BIT
+                }
+                100..=107 => {
+                    self.background = ANSI_TO_VGA_COLOR[usize::from(param - 100)] | BRIGHT_BIT
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+impl fmt::Write for Console {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.write_string(s);
+        Ok(())
+    }
+}
+
+#[test_case]
+fn test_console_draws_and_scrolls() {
+    use crate::vga_buffer::FONT_MAX_HEIGHT;
+
+    static GLYPHS: [[u8; FONT_MAX_HEIGHT]; 256] = {
+        let mut glyphs = [[0; FONT_MAX_HEIGHT]; 256];
+        glyphs[b'A' as usize][0] = 0x80;
+        glyphs
+    };
+    static mut PIXELS: [u32; 16 * 4] = [0; 16 * 4];
+
+    // 两行两列的屏幕，字形 'A' 的第一行只有最左边的像素
+    let pixels = unsafe { (&raw mut PIXELS).as_mut() }.unwrap();
+    let framebuffer = Framebuffer::new(pixels, 16, 4, 16, super::PixelFormat::Bgr).unwrap();
+    let mut console = Console::new(framebuffer, Font::new(&GLYPHS, 2));
+    assert_eq!((console.columns(), console.rows()), (2, 2));
+
+    let yellow = 0x00ff_ff55;
+    // 默认的黄色是高亮色，SGR 31 保留高亮位得到亮红色
+    console.write_string("\x1b[31mA\x1b[0mA");
+    assert_eq!(console.framebuffer.pixel(0, 0), Some(0x00ff_5555));
+    assert_eq!(console.framebuffer.pixel(8, 0), Some(yellow));
+    assert_eq!(console.framebuffer.pixel(1, 0), Some(0));
+    assert_eq!(console.cursor(), (0, 2));
+
+    // 第三个字符换到下一行，第三行时整体上移
+    console.write_string("A\nA");
+    assert_eq!(console.cursor(), (1, 1));
+    assert_eq!(console.framebuffer.pixel(0, 0), Some(yellow));
+    assert_eq!(console.framebuffer.pixel(8, 0), Some(0));
+    assert_eq!(console.framebuffer.pixel(0, 2), Some(yellow));
+}
+
+#[test_case]
+fn test_console_status_line_is_not_scrolled() {
+    use crate::vga_buffer::FONT_MAX_HEIGHT;
+
+    static GLYPHS: [[u8; FONT_MAX_HEIGHT]; 256] = {
+        let mut glyphs = [[0; FONT_MAX_HEIGHT]; 256];
+        glyphs[b'A' as usize][0] = 0x80;
+        glyphs
+    };
+    static mut PIXELS: [u32; 16 * 6] = [0; 16 * 6];
+
+    // 三行两列的屏幕，字形 'A' 的第一行只有最左边的像素
+    let pixels = unsafe { (&raw mut PIXELS).as_mut() }.unwrap();
+    let framebuffer = Framebuffer::new(pixels, 16, 6, 16, super::PixelFormat::Bgr).unwrap();
+    let mut console = Console::new(framebuffer, Font::new(&GLYPHS, 2));
+    console.reserve_status_line();
+    assert_eq!(console.cursor(), (1, 0));
+
+    let light_gray = 0x00aa_aaaa;
+    console.write_status("A", Color::Black, Color::LightGray);
+    assert_eq!(console.framebuffer.pixel(0, 0), Some(0));
+    assert_eq!(console.framebuffer.pixel(1, 0), Some(light_gray));
+    assert_eq!(console.framebuffer.pixel(8, 0), Some(light_gray));
+
+    // 滚动和清屏都不改动状态栏
+    console.write_string("A\nA\nA");
+    assert_eq!(console.cursor(), (2, 1));
+    assert_eq!(console.framebuffer.pixel(1, 0), Some(light_gray));
+    console.clear();
+    assert_eq!(console.cursor(), (1, 0));
+    assert_eq!(console.framebuffer.pixel(1, 0), Some(light_gray));
+    assert_eq!(console.framebuffer.pixel(0, 2), Some(0));
+}
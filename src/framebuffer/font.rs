@@ -0,0 +1,360 @@
+//! 本模块实现了帧缓冲控制台使用的位图字体
+//!
+//! 字形宽 8 像素，每行一个字节，按代码页 437 编号。默认使用内置的 8x16 字体，
+//! 也可以从 VGA 文本模式的显存中复制当前的字体（见 [`Font::from_text_mode`]）
+
+use alloc::boxed::Box;
+
+use x86_64::VirtAddr;
+
+use crate::vga_buffer::{self, FONT_MAX_HEIGHT};
+
+/// 字形的宽度（像素）
+pub const GLYPH_WIDTH: usize = 8;
+
+/// 内置字体的高度（像素）
+const BUILTIN_HEIGHT: usize = 16;
+
+/// 256 个字形组成的位图字体
+pub struct Font {
+    glyphs: &'static [[u8; FONT_MAX_HEIGHT]; 256], // 每个字形的位图，最高位在左
+    height: usize,                                 // 字形的高度（像素）
+}
+
+impl Font {
+    /// 用字形位图创建字体
+    ///
+    /// # 参数
+    ///
+    /// - `glyphs`: 256 个字形，只使用每个字形的前 `height` 行
+    /// - `height`: 字形的高度，范围为 1 到 [`FONT_MAX_HEIGHT`]
+    pub fn new(glyphs: &'static [[u8; FONT_MAX_HEIGHT]; 256], height: usize) -> Self {
+        Self {
+            glyphs,
+            height: height.clamp(1, FONT_MAX_HEIGHT),
+        }
+    }
+
+    /// 内置的 8x16 代码页 437 字体
+    pub fn builtin() -> Self {
+        Self::new(&BUILTIN_GLYPHS, BUILTIN_HEIGHT)
+    }
+
+    /// 复制文本模式当前使用的字体，必须在离开文本模式之前调用
+    ///
+    /// # 参数
+    ///
+    /// - `physical_memory_offset`: 完整物理内存映射的起始虚拟地址
+    pub fn from_text_mode(physical_memory_offset: VirtAddr) -> Self {
+        // 字体在切换显示模式时复制一次，之后一直使用
+        let glyphs = Box::leak(Box::new([[0; FONT_MAX_HEIGHT]; 256]));
+        let height = unsafe { vga_buffer::read_font(physical_memory_offset, glyphs) };
+        Self::new(glyphs, height)
+    }
+
+    /// 字形的高度（像素）
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// 代码页 437 中 `byte` 对应字形的每一行
+    pub fn glyph(&self, byte: u8) -> &[u8] {
+        &self.glyphs[usize::from(byte)][..self.height]
+    }
+}
+
+/// 展开成逐行位图的内置字体
+static BUILTIN_GLYPHS: [[u8; FONT_MAX_HEIGHT]; 256] = {
+    let mut glyphs = [[0; FONT_MAX_HEIGHT]; 256];
+    let mut index = 0;
+    while index < 256 {
+        let bytes = BUILTIN_8X16[index].to_be_bytes();
+        let mut row = 0;
+        while row < BUILTIN_HEIGHT {
+            glyphs[index][row] = bytes[row];
+            row += 1;
+        }
+        index += 1;
+    }
+    glyphs
+};
+
+/// 内置字体的字形，每个字形从高字节到低字节依次为第 0 到 15 行，与 VGA BIOS 的 8x16 字体相同
+const BUILTIN_8X16: [u128; 256] = [
+    // 0x00 起：控制字符位置上的图形符号
+    0x0000_0000_0000_0000_0000_0000_0000_0000,
+    0x0000_7e81_a581_81bd_9981_817e_0000_0000,
+    0x0000_7eff_dbff_ffc3_e7ff_ff7e_0000_0000,
+    0x0000_0000_6cfe_fefe_fe7c_3810_0000_0000,
+    0x0000_0000_1038_7cfe_7c38_1000_0000_0000,
+    0x0000_0018_3c3c_e7e7_e718_183c_0000_0000,
+    0x0000_0018_3c7e_ffff_7e18_183c_0000_0000,
+    0x0000_0000_0000_183c_3c18_0000_0000_0000,
+    0xffff_ffff_ffff_e7c3_c3e7_ffff_ffff_ffff,
+    0x0000_0000_003c_6642_4266_3c00_0000_0000,
+    0xffff_ffff_ffc3_99bd_bd99_c3ff_ffff_ffff,
+    0x0000_1e0e_1a32_78cc_cccc_cc78_0000_0000,
+    0x0000_3c66_6666_663c_187e_1818_0000_0000,
+    0x0000_3f33_3f30_3030_3070_f0e0_0000_0000,
+    0x0000_7f63_7f63_6363_6367_e7e6_c000_0000,
+    0x0000_0018_18db_3ce7_3cdb_1818_0000_0000,
+    0x0080_c0e0_f0f8_fef8_f0e0_c080_0000_0000,
+    0x0002_060e_1e3e_fe3e_1e0e_0602_0000_0000,
+    0x0000_183c_7e18_1818_7e3c_1800_0000_0000,
+    0x0000_6666_6666_6666_6600_6666_0000_0000,
+    0x0000_7fdb_dbdb_7b1b_1b1b_1b1b_0000_0000,
+    0x007c_c660_386c_c6c6_6c38_0cc6_7c00_0000,
+    0x0000_0000_0000_0000_fefe_fefe_0000_0000,
+    0x0000_183c_7e18_1818_7e3c_187e_0000_0000,
+    0x0000_183c_7e18_1818_1818_1818_0000_0000,
+    0x0000_1818_1818_1818_187e_3c18_0000_0000,
+    0x0000_0000_0018_0cfe_0c18_0000_0000_0000,
+    0x0000_0000_0030_60fe_6030_0000_0000_0000,
+    0x0000_0000_0000_c0c0_c0fe_0000_0000_0000,
+    0x0000_0000_0028_6cfe_6c28_0000_0000_0000,
+    0x0000_0000_1038_387c_7cfe_fe00_0000_0000,
+    0x0000_0000_fefe_7c7c_3838_1000_0000_0000,
+    // 0x20 起：ASCII 可打印字符
+    0x0000_0000_0000_0000_0000_0000_0000_0000,
+    0x0000_183c_3c3c_1818_1800_1818_0000_0000,
+    0x0066_6666_2400_0000_0000_0000_0000_0000,
+    0x0000_006c_6cfe_6c6c_6cfe_6c6c_0000_0000,
+    0x1818_7cc6_c2c0_7c06_0686_c67c_1818_0000,
+    0x0000_0000_c2c6_0c18_3060_c686_0000_0000,
+    0x0000_386c_6c38_76dc_cccc_cc76_0000_0000,
+    0x0030_3030_6000_0000_0000_0000_0000_0000,
+    0x0000_0c18_3030_3030_3030_180c_0000_0000,
+    0x0000_3018_0c0c_0c0c_0c0c_1830_0000_0000,
+    0x0000_0000_0066_3cff_3c66_0000_0000_0000,
+    0x0000_0000_0018_187e_1818_0000_0000_0000,
+    0x0000_0000_0000_0000_0018_1818_3000_0000,
+    0x0000_0000_0000_00fe_0000_0000_0000_0000,
+    0x0000_0000_0000_0000_0000_1818_0000_0000,
+    0x0000_0000_0206_0c18_3060_c080_0000_0000,
+    0x0000_386c_c6c6_d6d6_c6c6_6c38_0000_0000,
+    0x0000_1838_7818_1818_1818_187e_0000_0000,
+    0x0000_7cc6_060c_1830_60c0_c6fe_0000_0000,
+    0x0000_7cc6_0606_3c06_0606_c67c_0000_0000,
+    0x0000_0c1c_3c6c_ccfe_0c0c_0c1e_0000_0000,
+    0x0000_fec0_c0c0_fc06_0606_c67c_0000_0000,
+    0x0000_3860_c0c0_fcc6_c6c6_c67c_0000_0000,
+    0x0000_fec6_0606_0c18_3030_3030_0000_0000,
+    0x0000_7cc6_c6c6_7cc6_c6c6_c67c_0000_0000,
+    0x0000_7cc6_c6c6_7e06_0606_0c78_0000_0000,
+    0x0000_0000_1818_0000_0018_1800_0000_0000,
+    0x0000_0000_1818_0000_0018_1830_0000_0000,
+    0x0000_0006_0c18_3060_3018_0c06_0000_0000,
+    0x0000_0000_007e_0000_7e00_0000_0000_0000,
+    0x0000_0060_3018_0c06_0c18_3060_0000_0000,
+    0x0000_7cc6_c60c_1818_1800_1818_0000_0000,
+    0x0000_007c_c6c6_dede_dedc_c07c_0000_0000,
+    0x0000_1038_6cc6_c6fe_c6c6_c6c6_0000_0000,
+    0x0000_fc66_6666_7c66_6666_66fc_0000_0000,
+    0x0000_3c66_c2c0_c0c0_c0c2_663c_0000_0000,
+    0x0000_f86c_6666_6666_6666_6cf8_0000_0000,
+    0x0000_fe66_6268_7868_6062_66fe_0000_0000,
+    0x0000_fe66_6268_7868_6060_60f0_0000_0000,
+    0x0000_3c66_c2c0_c0de_c6c6_663a_0000_0000,
+    0x0000_c6c6_c6c6_fec6_c6c6_c6c6_0000_0000,
+    0x0000_3c18_1818_1818_1818_183c_0000_0000,
+    0x0000_1e0c_0c0c_0c0c_cccc_cc78_0000_0000,
+    0x0000_e666_666c_7878_6c66_66e6_0000_0000,
+    0x0000_f060_6060_6060_6062_66fe_0000_0000,
+    0x0000_c6ee_fefe_d6c6_c6c6_c6c6_0000_0000,
+    0x0000_c6e6_f6fe_dece_c6c6_c6c6_0000_0000,
+    0x0000_7cc6_c6c6_c6c6_c6c6_c67c_0000_0000,
+    0x0000_fc66_6666_7c60_6060_60f0_0000_0000,
+    0x0000_7cc6_c6c6_c6c6_c6d6_de7c_0c0e_0000,
+    0x0000_fc66_6666_7c6c_6666_66e6_0000_0000,
+    0x0000_7cc6_c660_380c_06c6_c67c_0000_0000,
+    0x0000_7e7e_5a18_1818_1818_183c_0000_0000,
+    0x0000_c6c6_c6c6_c6c6_c6c6_c67c_0000_0000,
+    0x0000_c6c6_c6c6_c6c6_c66c_3810_0000_0000,
+    0x0000_c6c6_c6c6_d6d6_d6fe_ee6c_0000_0000,
+    0x0000_c6c6_6c7c_3838_7c6c_c6c6_0000_0000,
+    0x0000_6666_6666_3c18_1818_183c_0000_0000,
+    0x0000_fec6_860c_1830_60c2_c6fe_0000_0000,
+    0x0000_3c30_3030_3030_3030_303c_0000_0000,
+    0x0000_0080_c0e0_7038_1c0e_0602_0000_0000,
+    0x0000_3c0c_0c0c_0c0c_0c0c_0c3c_0000_0000,
+    0x1038_6cc6_0000_0000_0000_0000_0000_0000,
+    0x0000_0000_0000_0000_0000_0000_00ff_0000,
+    0x3030_1800_0000_0000_0000_0000_0000_0000,
+    0x0000_0000_0078_0c7c_cccc_cc76_0000_0000,
+    0x0000_e060_6078_6c66_6666_667c_0000_0000,
+    0x0000_0000_007c_c6c0_c0c0_c67c_0000_0000,
+    0x0000_1c0c_0c3c_6ccc_cccc_cc76_0000_0000,
+    0x0000_0000_007c_c6fe_c0c0_c67c_0000_0000,
+    0x0000_1c36_3230_7830_3030_3078_0000_0000,
+    0x0000_0000_0076_cccc_cccc_cc7c_0ccc_7800,
+    0x0000_e060_606c_7666_6666_66e6_0000_0000,
+    0x0000_1818_0038_1818_1818_183c_0000_0000,
+    0x0000_0606_000e_0606_0606_0606_6666_3c00,
+    0x0000_e060_6066_6c78_786c_66e6_0000_0000,
+    0x0000_3818_1818_1818_1818_183c_0000_0000,
+    0x0000_0000_00ec_fed6_d6d6_d6c6_0000_0000,
+    0x0000_0000_00dc_6666_6666_6666_0000_0000,
+    0x0000_0000_007c_c6c6_c6c6_c67c_0000_0000,
+    0x0000_0000_00dc_6666_6666_667c_6060_f000,
+    0x0000_0000_0076_cccc_cccc_cc7c_0c0c_1e00,
+    0x0000_0000_00dc_7666_6060_60f0_0000_0000,
+    0x0000_0000_007c_c660_380c_c67c_0000_0000,
+    0x0000_1030_30fc_3030_3030_361c_0000_0000,
+    0x0000_0000_00cc_cccc_cccc_cc76_0000_0000,
+    0x0000_0000_0066_6666_6666_3c18_0000_0000,
+    0x0000_0000_00c6_c6d6_d6d6_fe6c_0000_0000,
+    0x0000_0000_00c6_6c38_3838_6cc6_0000_0000,
+    0x0000_0000_00c6_c6c6_c6c6_c67e_060c_f800,
+    0x0000_0000_00fe_cc18_3060_c6fe_0000_0000,
+    0x0000_0e18_1818_7018_1818_180e_0000_0000,
+    0x0000_1818_1818_0018_1818_1818_0000_0000,
+    0x0000_7018_1818_0e18_1818_1870_0000_0000,
+    0x0076_dc00_0000_0000_0000_0000_0000_0000,
+    0x0000_0000_1038_6cc6_c6c6_fe00_0000_0000,
+    // 0x80 起：带变音符号的拉丁字母和货币符号
+    0x0000_3c66_c2c0_c0c0_c266_3c0c_067c_0000,
+    0x0000_cc00_00cc_cccc_cccc_cc76_0000_0000,
+    0x000c_1830_007c_c6fe_c0c0_c67c_0000_0000,
+    0x0010_386c_0078_0c7c_cccc_cc76_0000_0000,
+    0x0000_cc00_0078_0c7c_cccc_cc76_0000_0000,
+    0x0060_3018_0078_0c7c_cccc_cc76_0000_0000,
+    0x0038_6c38_0078_0c7c_cccc_cc76_0000_0000,
+    0x0000_0000_3c66_6060_663c_0c06_3c00_0000,
+    0x0010_386c_007c_c6fe_c0c0_c67c_0000_0000,
+    0x0000_c600_007c_c6fe_c0c0_c67c_0000_0000,
+    0x0060_3018_007c_c6fe_c0c0_c67c_0000_0000,
+    0x0000_6600_0038_1818_1818_183c_0000_0000,
+    0x0018_3c66_0038_1818_1818_183c_0000_0000,
+    0x0060_3018_0038_1818_1818_183c_0000_0000,
+    0x00c6_0010_386c_c6c6_fec6_c6c6_0000_0000,
+    0x386c_3800_386c_c6c6_fec6_c6c6_0000_0000,
+    0x1830_6000_fe66_607c_6060_66fe_0000_0000,
+    0x0000_0000_00cc_7636_7ed8_d86e_0000_0000,
+    0x0000_3e6c_cccc_fecc_cccc_ccce_0000_0000,
+    0x0010_386c_007c_c6c6_c6c6_c67c_0000_0000,
+    0x0000_c600_007c_c6c6_c6c6_c67c_0000_0000,
+    0x0060_3018_007c_c6c6_c6c6_c67c_0000_0000,
+    0x0030_78cc_00cc_cccc_cccc_cc76_0000_0000,
+    0x0060_3018_00cc_cccc_cccc_cc76_0000_0000,
+    0x0000_c600_00c6_c6c6_c6c6_c67e_060c_7800,
+    0x00c6_007c_c6c6_c6c6_c6c6_c67c_0000_0000,
+    0x00c6_00c6_c6c6_c6c6_c6c6_c67c_0000_0000,
+    0x0018_187e_c3c0_c0c0_c37e_1818_0000_0000,
+    0x0038_6c64_60f0_6060_6060_e6fc_0000_0000,
+    0x0000_c366_3c18_ff18_ff18_1818_0000_0000,
+    0x00fc_6666_7c62_666f_6666_66f3_0000_0000,
+    0x000e_1b18_1818_7e18_1818_1818_d870_0000,
+    0x0018_3060_0078_0c7c_cccc_cc76_0000_0000,
+    0x000c_1830_0038_1818_1818_183c_0000_0000,
+    0x0018_3060_007c_c6c6_c6c6_c67c_0000_0000,
+    0x0018_3060_00cc_cccc_cccc_cc76_0000_0000,
+    0x0000_76dc_00dc_6666_6666_6666_0000_0000,
+    0x76dc_00c6_e6f6_fede_cec6_c6c6_0000_0000,
+    0x003c_6c6c_3e00_7e00_0000_0000_0000_0000,
+    0x0038_6c6c_3800_7c00_0000_0000_0000_0000,
+    0x0000_3030_0030_3060_c0c6_c67c_0000_0000,
+    0x0000_0000_0000_fec0_c0c0_c000_0000_0000,
+    0x0000_0000_0000_fe06_0606_0600_0000_0000,
+    0x0060_e062_666c_1830_60dc_860c_183e_0000,
+    0x0060_e062_666c_1830_66ce_9a3f_0606_0000,
+    0x0000_1818_0018_1818_3c3c_3c18_0000_0000,
+    0x0000_0000_0036_6cd8_6c36_0000_0000_0000,
+    0x0000_0000_00d8_6c36_6cd8_0000_0000_0000,
+    // 0xb0 起：阴影、制表符和方块
+    0x1144_1144_1144_1144_1144_1144_1144_1144,
+    0x55aa_55aa_55aa_55aa_55aa_55aa_55aa_55aa,
+    0xdd77_dd77_dd77_dd77_dd77_dd77_dd77_dd77,
+    0x1818_1818_1818_1818_1818_1818_1818_1818,
+    0x1818_1818_1818_18f8_1818_1818_1818_1818,
+    0x1818_1818_18f8_18f8_1818_1818_1818_1818,
+    0x3636_3636_3636_36f6_3636_3636_3636_3636,
+    0x0000_0000_0000_00fe_3636_3636_3636_3636,
+    0x0000_0000_00f8_18f8_1818_1818_1818_1818,
+    0x3636_3636_36f6_06f6_3636_3636_3636_3636,
+    0x3636_3636_3636_3636_3636_3636_3636_3636,
+    0x0000_0000_00fe_06f6_3636_3636_3636_3636,
+    0x3636_3636_36f6_06fe_0000_0000_0000_0000,
+    0x3636_3636_3636_36fe_0000_0000_0000_0000,
+    0x1818_1818_18f8_18f8_0000_0000_0000_0000,
+    0x0000_0000_0000_00f8_1818_1818_1818_1818,
+    0x1818_1818_1818_181f_0000_0000_0000_0000,
+    0x1818_1818_1818_18ff_0000_0000_0000_0000,
+    0x0000_0000_0000_00ff_1818_1818_1818_1818,
+    0x1818_1818_1818_181f_1818_1818_1818_1818,
+    0x0000_0000_0000_00ff_0000_0000_0000_0000,
+    0x1818_1818_1818_18ff_1818_1818_1818_1818,
+    0x1818_1818_181f_181f_1818_1818_1818_1818,
+    0x3636_3636_3636_3637_3636_3636_3636_3636,
+    0x3636_3636_3637_303f_0000_0000_0000_0000,
+    0x0000_0000_003f_3037_3636_3636_3636_3636,
+    0x3636_3636_36f7_00ff_0000_0000_0000_0000,
+    0x0000_0000_00ff_00f7_3636_3636_3636_3636,
+    0x3636_3636_3637_3037_3636_3636_3636_3636,
+    0x0000_0000_00ff_00ff_0000_0000_0000_0000,
+    0x3636_3636_36f7_00f7_3636_3636_3636_3636,
+    0x1818_1818_18ff_00ff_0000_0000_0000_0000,
+    0x3636_3636_3636_36ff_0000_0000_0000_0000,
+    0x0000_0000_00ff_00ff_1818_1818_1818_1818,
+    0x0000_0000_0000_00ff_3636_3636_3636_3636,
+    0x3636_3636_3636_363f_0000_0000_0000_0000,
+    0x1818_1818_181f_181f_0000_0000_0000_0000,
+    0x0000_0000_001f_181f_1818_1818_1818_1818,
+    0x0000_0000_0000_003f_3636_3636_3636_3636,
+    0x3636_3636_3636_36ff_3636_3636_3636_3636,
+    0x1818_1818_18ff_18ff_1818_1818_1818_1818,
+    0x1818_1818_1818_18f8_0000_0000_0000_0000,
+    0x0000_0000_0000_001f_1818_1818_1818_1818,
+    0xffff_ffff_ffff_ffff_ffff_ffff_ffff_ffff,
+    0x0000_0000_0000_00ff_ffff_ffff_ffff_ffff,
+    0xf0f0_f0f0_f0f0_f0f0_f0f0_f0f0_f0f0_f0f0,
+    0x0f0f_0f0f_0f0f_0f0f_0f0f_0f0f_0f0f_0f0f,
+    0xffff_ffff_ffff_ff00_0000_0000_0000_0000,
+    // 0xe0 起：希腊字母和数学符号
+    0x0000_0000_0076_dcd8_d8d8_dc76_0000_0000,
+    0x0000_78cc_cccc_d8cc_c6c6_c6cc_0000_0000,
+    0x0000_fec6_c6c0_c0c0_c0c0_c0c0_0000_0000,
+    0x0000_0000_fe6c_6c6c_6c6c_6c6c_0000_0000,
+    0x0000_00fe_c660_3018_3060_c6fe_0000_0000,
+    0x0000_0000_007e_d8d8_d8d8_d870_0000_0000,
+    0x0000_0000_6666_6666_667c_6060_c000_0000,
+    0x0000_0000_76dc_1818_1818_1818_0000_0000,
+    0x0000_007e_183c_6666_663c_187e_0000_0000,
+    0x0000_0038_6cc6_c6fe_c6c6_6c38_0000_0000,
+    0x0000_386c_c6c6_c66c_6c6c_6cee_0000_0000,
+    0x0000_1e30_180c_3e66_6666_663c_0000_0000,
+    0x0000_0000_007e_dbdb_db7e_0000_0000_0000,
+    0x0000_0003_067e_dbdb_f37e_60c0_0000_0000,
+    0x0000_1c30_6060_7c60_6060_301c_0000_0000,
+    0x0000_007c_c6c6_c6c6_c6c6_c6c6_0000_0000,
+    0x0000_0000_fe00_00fe_0000_fe00_0000_0000,
+    0x0000_0000_1818_7e18_1800_00ff_0000_0000,
+    0x0000_0030_180c_060c_1830_007e_0000_0000,
+    0x0000_000c_1830_6030_180c_007e_0000_0000,
+    0x0000_0e1b_1b18_1818_1818_1818_1818_1818,
+    0x1818_1818_1818_1818_d8d8_d870_0000_0000,
+    0x0000_0000_0018_007e_0018_0000_0000_0000,
+    0x0000_0000_0076_dc00_76dc_0000_0000_0000,
+    0x0038_6c6c_3800_0000_0000_0000_0000_0000,
+    0x0000_0000_0000_0018_1800_0000_0000_0000,
+    0x0000_0000_0000_0000_1800_0000_0000_0000,
+    0x000f_0c0c_0c0c_0cec_6c6c_3c1c_0000_0000,
+    0x00d8_6c6c_6c6c_6c00_0000_0000_0000_0000,
+    0x0070_d830_60c8_f800_0000_0000_0000_0000,
+    0x0000_0000_7c7c_7c7c_7c7c_7c00_0000_0000,
+    0x0000_0000_0000_0000_0000_0000_0000_0000,
+];
+
+#[test_case]
+fn test_builtin_font_matches_vga() {
+    let font = Font::builtin();
+    assert_eq!(font.height(), 16);
+    assert!(font.glyph(b' ').iter().all(|&row| row == 0));
+    assert_eq!(
+        font.glyph(b'A'),
+        [
+            0, 0, 0x10, 0x38, 0x6c, 0xc6, 0xc6, 0xfe, 0xc6, 0xc6, 0xc6, 0xc6, 0, 0, 0, 0
+        ]
+    );
+    // 全块字符每一行都是满的
+    assert!(font.glyph(0xdb).iter().all(|&row| row == 0xff));
+}
@@ -0,0 +1,179 @@
+//! 本模块实现了全局描述符表（GDT）和任务状态段（TSS）
+
+use alloc::boxed::Box;
+use alloc::vec;
+
+use lazy_static::lazy_static;
+use x86_64::VirtAddr;
+use x86_64::structures::gdt::{Descriptor, GlobalDescriptorTable, SegmentSelector};
+use x86_64::structures::tss::TaskStateSegment;
+
+/// 双重错误处理函数使用的中断栈表（IST）下标
+pub const DOUBLE_FAULT_IST_INDEX: u16 = 0;
+
+/// 缺页异常处理函数使用的中断栈表（IST）下标
+pub const PAGE_FAULT_IST_INDEX: u16 = 1;
+
+/// 不可屏蔽中断处理函数使用的中断栈表（IST）下标
+pub const NMI_IST_INDEX: u16 = 2;
+
+/// 双重错误处理函数的栈大小
+const DOUBLE_FAULT_STACK_SIZE: usize = 4096 * 5;
+
+/// 缺页异常处理函数的栈大小
+const PAGE_FAULT_STACK_SIZE: usize = 4096 * 5;
+
+/// 不可屏蔽中断处理函数的栈大小
+const NMI_STACK_SIZE: usize = 4096 * 2;
+
+/// 没有自己内核栈的执行流从用户态进入内核时使用的栈大小
+const DEFAULT_KERNEL_STACK_SIZE: usize = 4096 * 5;
+
+/// 任务状态段，切换线程时需要更新其中的 RSP0，因此不能放在 `lazy_static` 中
+static mut TSS: TaskStateSegment = TaskStateSegment::new();
+
+/// 获取默认内核栈的栈顶，启动线程等没有独立内核栈的执行流从用户态进入内核时使用
+pub fn default_kernel_stack() -> VirtAddr {
+    static mut STACK: [u8; DEFAULT_KERNEL_STACK_SIZE] = [0; DEFAULT_KERNEL_STACK_SIZE];
+
+    VirtAddr::from_ptr(&raw const STACK) + DEFAULT_KERNEL_STACK_SIZE as u64
+}
+
+/// 设置从用户态进入内核时使用的栈，包括 TSS 中的 RSP0 和系统调用入口使用的栈
+///
+/// # 参数
+///
+/// - `top`: 内核栈的栈顶
+pub fn set_kernel_stack(top: VirtAddr) {
+    // 只在禁用中断的切换路径上写入当前处理器的 TSS，CPU 只在特权级切换时读取
+    unsafe { (*crate::percpu::current().tss()).privilege_stack_table[0] = top };
+    crate::syscall::set_kernel_stack(top.as_u64());
+}
+
+/// GDT 中各段的选择子
+///
+/// 段的顺序由 `syscall`/`sysret` 决定：内核数据段紧跟内核代码段，用户数据段紧跟在用户代码段之前
+pub struct Selectors {
+    pub kernel_code: SegmentSelector,
+    pub kernel_data: SegmentSelector,
+    pub user_data: SegmentSelector,
+    pub user_code: SegmentSelector,
+    pub tss: SegmentSelector,
+}
+
+/// 创建使用 `tss` 的 GDT
+///
+/// # Safety
+///
+/// `tss` 必须在 GDT 使用期间一直有效
+unsafe fn new_gdt(tss: *const TaskStateSegment) -> (GlobalDescriptorTable, Selectors) {
+    let mut gdt = GlobalDescriptorTable::new();
+    let kernel_code = gdt.append(Descriptor::kernel_code_segment());
+    let kernel_data = gdt.append(Descriptor::kernel_data_segment());
+    let user_data = gdt.append(Descriptor::user_data_segment());
+    let user_code = gdt.append(Descriptor::user_code_segment());
+    let tss = gdt.append(unsafe { Descriptor::tss_segment_unchecked(tss) });
+    (
+        gdt,
+        Selectors {
+            kernel_code,
+            kernel_data,
+            user_data,
+            user_code,
+            tss,
+        },
+    )
+}
+
+lazy_static! {
+    // TSS 是静态变量，之后只修改 RSP0
+    static ref GDT: (GlobalDescriptorTable, Selectors) = unsafe { new_gdt(&raw const TSS) };
+}
+
+/// 获取 GDT 中各段的选择子
+pub fn selectors() -> &'static Selectors {
+    &GDT.1
+}
+
+/// 初始化 TSS，加载 GDT，并重新加载段寄存器和 TSS
+pub fn init() {
+    // 内核栈溢出时原栈已不可用，双重错误必须切换到独立的栈上处理
+    let double_fault_stack = {
+        static mut STACK: [u8; DOUBLE_FAULT_STACK_SIZE] = [0; DOUBLE_FAULT_STACK_SIZE];
+
+        let stack_start = VirtAddr::from_ptr(&raw const STACK);
+        // 栈向下增长，因此写入栈的最高地址
+        stack_start + DOUBLE_FAULT_STACK_SIZE as u64
+    };
+    // 线程栈溢出到保护页时同样无法压入异常栈帧，缺页异常也需要独立的栈
+    let page_fault_stack = {
+        static mut STACK: [u8; PAGE_FAULT_STACK_SIZE] = [0; PAGE_FAULT_STACK_SIZE];
+
+        let stack_start = VirtAddr::from_ptr(&raw const STACK);
+        stack_start + PAGE_FAULT_STACK_SIZE as u64
+    };
+    // NMI 可能打断系统调用入口中尚未切换到内核栈的指令，此时 RSP 仍是用户栈
+    let nmi_stack = {
+        static mut STACK: [u8; NMI_STACK_SIZE] = [0; NMI_STACK_SIZE];
+
+        let stack_start = VirtAddr::from_ptr(&raw const STACK);
+        stack_start + NMI_STACK_SIZE as u64
+    };
+    // 加载 TSS 之前 CPU 不会读取它，此时写入是安全的
+    unsafe {
+        TSS.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = double_fault_stack;
+        TSS.interrupt_stack_table[PAGE_FAULT_IST_INDEX as usize] = page_fault_stack;
+        TSS.interrupt_stack_table[NMI_IST_INDEX as usize] = nmi_stack;
+        TSS.privilege_stack_table[0] = default_kernel_stack();
+    }
+
+    load(&GDT.0, &GDT.1);
+    crate::percpu::init_bsp(&raw mut TSS);
+}
+
+/// 为应用处理器创建并加载自己的 GDT 和 TSS，并初始化它的处理器私有数据
+///
+/// 每个处理器的 TSS 使用各自在堆上分配的 IST 栈，段的顺序与 [`init`] 相同，
+/// 因此 [`selectors`] 返回的选择子对所有处理器都有效
+///
+/// # 参数
+///
+/// - `cpu`: 处理器编号
+pub fn init_ap(cpu: usize) {
+    let tss = Box::leak(Box::new(TaskStateSegment::new()));
+    tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] =
+        allocate_stack(DOUBLE_FAULT_STACK_SIZE);
+    tss.interrupt_stack_table[PAGE_FAULT_IST_INDEX as usize] =
+        allocate_stack(PAGE_FAULT_STACK_SIZE);
+    tss.interrupt_stack_table[NMI_IST_INDEX as usize] = allocate_stack(NMI_STACK_SIZE);
+    tss.privilege_stack_table[0] = allocate_stack(DEFAULT_KERNEL_STACK_SIZE);
+    let (gdt, selectors) = unsafe { new_gdt(tss) };
+    let gdt = Box::leak(Box::new(gdt));
+    load(gdt, &selectors);
+    crate::percpu::init_ap(cpu, tss);
+}
+
+/// 在堆上分配一个永不释放的栈
+///
+/// # 返回
+///
+/// 按 16 字节对齐的栈顶
+fn allocate_stack(size: usize) -> VirtAddr {
+    let stack = Box::leak(vec![0u8; size].into_boxed_slice());
+    (VirtAddr::from_ptr(stack.as_ptr()) + size as u64).align_down(16u64)
+}
+
+/// 加载 GDT，并重新加载段寄存器和 TSS
+fn load(gdt: &'static GlobalDescriptorTable, selectors: &Selectors) {
+    use x86_64::instructions::segmentation::{CS, DS, ES, SS, Segment};
+    use x86_64::instructions::tables::load_tss;
+
+    gdt.load();
+    unsafe {
+        CS::set_reg(selectors.kernel_code);
+        SS::set_reg(selectors.kernel_data);
+        DS::set_reg(selectors.kernel_data);
+        ES::set_reg(selectors.kernel_data);
+        load_tss(selectors.tss);
+    }
+}
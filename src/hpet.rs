@@ -0,0 +1,334 @@
+//! 本模块实现了高精度事件定时器（HPET）
+//!
+//! HPET 由一个频率固定的主计数器和若干个比较器组成，寄存器位于 ACPI HPET 表给出的一页 MMIO 中。
+//! 主计数器的周期以飞秒为单位写在能力寄存器里，不需要校准，因此它既可以用来校准其他计数器，
+//! 也可以在 PIT 精度不够、TSC 又不可靠时作为周期或一次性的定时器。
+//!
+//! 定时器 0 的中断经 IO APIC 投递给引导处理器，到期时调用 [`set_callback`] 登记的函数。
+//! PIT 的中断没有接到 IO APIC 上时，由定时器 0 的周期中断代替它产生 tick
+
+use core::sync::atomic::{AtomicU32, Ordering};
+use core::time::Duration;
+
+use conquer_once::spin::OnceCell;
+use x86_64::VirtAddr;
+
+use crate::ioapic::{self, Polarity, Sharing, Trigger};
+use crate::sync::SpinLock;
+use crate::{acpi, apic, memory, pci, time};
+
+/// 定时器中断的向量号
+pub const VECTOR: u8 = 0xe0;
+
+/// 寄存器区域的大小
+const HPET_SIZE: u64 = 1024;
+
+/// 能力和标识寄存器
+const REG_CAPABILITIES: u64 = 0x000;
+/// 通用配置寄存器
+const REG_CONFIG: u64 = 0x010;
+/// 主计数器
+const REG_COUNTER: u64 = 0x0f0;
+/// 定时器 0 的配置和能力寄存器，之后的定时器每个占 0x20 字节
+const REG_TIMER0_CONFIG: u64 = 0x100;
+/// 定时器 0 的比较器
+const REG_TIMER0_COMPARATOR: u64 = 0x108;
+
+/// 能力寄存器：主计数器为 64 位
+const CAP_COUNTER_64: u64 = 1 << 13;
+/// 通用配置：启动主计数器
+const CONFIG_ENABLE: u64 = 1 << 0;
+/// 定时器配置：开启中断
+const TIMER_INT_ENABLE: u64 = 1 << 2;
+/// 定时器配置：周期模式
+const TIMER_PERIODIC: u64 = 1 << 3;
+/// 定时器能力：支持周期模式
+const TIMER_PERIODIC_CAP: u64 = 1 << 4;
+/// 定时器配置：下一次写入比较器时同时设置周期
+const TIMER_VAL_SET: u64 = 1 << 6;
+/// 定时器配置：中断路由字段的起始位
+const TIMER_INT_ROUTE_SHIFT: u64 = 9;
+
+/// 一秒的飞秒数
+const FEMTOS_PER_SEC: u128 = 1_000_000_000_000_000;
+
+/// 尚未路由中断时 [`ROUTED_GSI`] 的值
+const NOT_ROUTED: u32 = u32::MAX;
+
+/// 已映射的 HPET
+struct Hpet {
+    base: VirtAddr, // 寄存器映射到的虚拟地址
+    period_fs: u64, // 主计数器一个计数的飞秒数
+    wide: bool,     // 主计数器是否为 64 位
+    min_tick: u64,  // 周期模式下的最小周期（计数）
+}
+
+impl Hpet {
+    /// 读取寄存器
+    fn read(&self, reg: u64) -> u64 {
+        unsafe { (self.base + reg).as_ptr::<u64>().read_volatile() }
+    }
+
+    /// 写入寄存器
+    fn write(&self, reg: u64, value: u64) {
+        unsafe { (self.base + reg).as_mut_ptr::<u64>().write_volatile(value) }
+    }
+
+    /// 把时长换算成主计数器的计数，至少为 1
+    fn counts_for(&self, duration: Duration) -> u64 {
+        let counts = duration.as_nanos() * 1_000_000 / u128::from(self.period_fs);
+        counts.clamp(1, u128::from(u64::MAX)) as u64
+    }
+}
+
+/// 初始化之后不再改变
+static HPET: OnceCell<Hpet> = OnceCell::uninit();
+
+/// 定时器 0 的配置需要多次写入寄存器，由这把锁保护
+static TIMER: SpinLock<()> = SpinLock::new(());
+
+/// 定时器 0 的中断已路由到的全局系统中断
+static ROUTED_GSI: AtomicU32 = AtomicU32::new(NOT_ROUTED);
+
+/// 定时器 0 到期时调用的函数
+static CALLBACK: SpinLock<Option<fn()>> = SpinLock::new(None);
+
+/// 映射 ACPI 中描述的 HPET 并启动主计数器，之后用它重新校准 TSC 的频率
+///
+/// # 返回
+///
+/// 没有 HPET 或映射失败时返回 `false`
+pub fn init() -> bool {
+    if HPET.is_initialized() {
+        return true;
+    }
+    let Some(info) = acpi::hpet() else {
+        return false;
+    };
+    let Some(base) = memory::map_mmio(info.address, HPET_SIZE) else {
+        log::warn!("failed to map HPET {}", info.number);
+        return false;
+    };
+    let mut hpet = Hpet {
+        base,
+        period_fs: 0,
+        wide: false,
+        min_tick: u64::from(info.min_tick),
+    };
+    let capabilities = hpet.read(REG_CAPABILITIES);
+    hpet.period_fs = capabilities >> 32;
+    hpet.wide = capabilities & CAP_COUNTER_64 != 0;
+    if hpet.period_fs == 0 {
+        return false;
+    }
+    // 定时器 0 在被使用之前保持关闭
+    let config = hpet.read(REG_TIMER0_CONFIG);
+    hpet.write(
+        REG_TIMER0_CONFIG,
+        config & !(TIMER_INT_ENABLE | TIMER_PERIODIC),
+    );
+    hpet.write(REG_CONFIG, hpet.read(REG_CONFIG) | CONFIG_ENABLE);
+    log::info!(
+        "HPET {} runs at {} kHz with a {}-bit counter",
+        info.number,
+        FEMTOS_PER_SEC / u128::from(hpet.period_fs) / 1000,
+        if hpet.wide { 64 } else { 32 }
+    );
+    HPET.init_once(|| hpet);
+    // TSC 在此之前只能用 PIT 校准
+    time::recalibrate_tsc();
+    true
+}
+
+/// HPET 是否已初始化
+pub fn is_initialized() -> bool {
+    HPET.is_initialized()
+}
+
+/// 主计数器的频率（Hz），尚未初始化时返回 0
+pub fn frequency_hz() -> u64 {
+    HPET.get().map_or(0, |hpet| {
+        (FEMTOS_PER_SEC / u128::from(hpet.period_fs)) as u64
+    })
+}
+
+/// 主计数器的当前值，尚未初始化时返回 `None`
+pub fn counter() -> Option<u64> {
+    Some(HPET.get()?.read(REG_COUNTER))
+}
+
+/// 用主计数器忙等待 `duration`，在计时开始前后分别调用 `start` 和 `stop`
+///
+/// # 参数
+///
+/// - `duration`: 计时的时长
+/// - `start`: 计时开始前调用，例如记录计数器的起始值
+/// - `stop`: 计时结束后立即调用
+///
+/// # 返回
+///
+/// `stop` 的返回值，尚未初始化时返回 `None` 且不调用 `start` 和 `stop`。
+/// 32 位的主计数器只能测量一次回绕以内的时长
+pub fn measure<T>(duration: Duration, start: impl FnOnce(), stop: impl FnOnce() -> T) -> Option<T> {
+    let hpet = HPET.get()?;
+    let counts = hpet.counts_for(duration);
+    // 32 位的主计数器会回绕，按 32 位计算经过的计数
+    let mask = if hpet.wide {
+        u64::MAX
+    } else {
+        u64::from(u32::MAX)
+    };
+    start();
+    let begin = hpet.read(REG_COUNTER);
+    while hpet.read(REG_COUNTER).wrapping_sub(begin) & mask < counts {
+        core::hint::spin_loop();
+    }
+    Some(stop())
+}
+
+/// 登记定时器 0 到期时调用的函数，取代之前登记的函数
+///
+/// 函数在中断上下文中调用，不能睡眠
+///
+/// # 参数
+///
+/// - `callback`: 到期时调用的函数
+pub fn set_callback(callback: fn()) {
+    *CALLBACK.lock() = Some(callback);
+}
+
+/// 让定时器 0 周期性地产生中断
+///
+/// # 参数
+///
+/// - `period`: 中断的间隔，短于 HPET 表给出的最小周期时取最小周期
+///
+/// # 返回
+///
+/// HPET 尚未初始化、定时器 0 不支持周期模式或中断无法路由时返回 `false`
+pub fn start_periodic(period: Duration) -> bool {
+    let Some(hpet) = HPET.get() else {
+        return false;
+    };
+    let _guard = TIMER.lock();
+    let config = hpet.read(REG_TIMER0_CONFIG);
+    if config & TIMER_PERIODIC_CAP == 0 {
+        return false;
+    }
+    let Some(route) = route(config) else {
+        return false;
+    };
+    let counts = hpet.counts_for(period).max(hpet.min_tick);
+    hpet.write(
+        REG_TIMER0_CONFIG,
+        route | TIMER_INT_ENABLE | TIMER_PERIODIC | TIMER_VAL_SET,
+    );
+    // 设置了 VAL_SET 时第一次写入设置比较值，第二次写入设置周期
+    hpet.write(
+        REG_TIMER0_COMPARATOR,
+        hpet.read(REG_COUNTER).wrapping_add(counts),
+    );
+    hpet.write(REG_TIMER0_COMPARATOR, counts);
+    true
+}
+
+/// 让定时器 0 在 `after` 之后产生一次中断，取代之前的设置
+///
+/// # 参数
+///
+/// - `after`: 距离中断的时长
+///
+/// # 返回
+///
+/// HPET 尚未初始化或中断无法路由时返回 `false`
+pub fn set_oneshot(after: Duration) -> bool {
+    let Some(hpet) = HPET.get() else {
+        return false;
+    };
+    let _guard = TIMER.lock();
+    let config = hpet.read(REG_TIMER0_CONFIG);
+    let Some(route) = route(config) else {
+        return false;
+    };
+    let counts = hpet.counts_for(after);
+    hpet.write(REG_TIMER0_CONFIG, route);
+    hpet.write(
+        REG_TIMER0_COMPARATOR,
+        hpet.read(REG_COUNTER).wrapping_add(counts),
+    );
+    hpet.write(REG_TIMER0_CONFIG, route | TIMER_INT_ENABLE);
+    true
+}
+
+/// 停止定时器 0
+pub fn stop() {
+    let Some(hpet) = HPET.get() else {
+        return;
+    };
+    let _guard = TIMER.lock();
+    let config = hpet.read(REG_TIMER0_CONFIG);
+    hpet.write(
+        REG_TIMER0_CONFIG,
+        config & !(TIMER_INT_ENABLE | TIMER_PERIODIC),
+    );
+}
+
+/// 在第一次使用时把定时器 0 的中断路由到引导处理器
+///
+/// # 参数
+///
+/// - `config`: 定时器 0 的配置和能力寄存器，高 32 位为可以使用的全局系统中断
+///
+/// # 返回
+///
+/// 配置中中断路由字段的值，没有可用的全局系统中断时返回 `None`
+fn route(config: u64) -> Option<u64> {
+    let mut gsi = ROUTED_GSI.load(Ordering::Relaxed);
+    if gsi == NOT_ROUTED {
+        if !apic::is_initialized() || !ioapic::is_initialized() {
+            return None;
+        }
+        let capable = (config >> 32) as u32;
+        // 优先使用 ISA 中断之外的引脚；PCI 设备可能还没有路由它的中断线，也要避开
+        gsi = (16..32)
+            .chain(0..16)
+            .filter(|gsi| capable & (1 << gsi) != 0)
+            .filter(|&gsi| !ioapic::is_claimed(gsi) && !pci_line(gsi))
+            .find(|&gsi| {
+                ioapic::claim(gsi, Sharing::Exclusive)
+                    && ioapic::route(gsi, VECTOR, apic::id(), Trigger::Edge, Polarity::ActiveHigh)
+            })?;
+        ROUTED_GSI.store(gsi, Ordering::Relaxed);
+        log::info!("HPET timer 0 routed to GSI {}", gsi);
+    }
+    Some(u64::from(gsi) << TIMER_INT_ROUTE_SHIFT)
+}
+
+/// 是否有 PCI 设备的中断线为 `gsi`
+fn pci_line(gsi: u32) -> bool {
+    pci::devices()
+        .any(|device| device.interrupt_pin != 0 && u32::from(device.interrupt_line) == gsi)
+}
+
+/// 由定时器中断处理函数调用
+pub(crate) fn handle_interrupt() {
+    // 回调中可能重新登记回调
+    let callback = *CALLBACK.lock();
+    if let Some(callback) = callback {
+        callback();
+    }
+}
+
+#[test_case]
+fn test_counts_for_rounds_down_and_clamps() {
+    // 典型的 HPET 为 100 MHz，每个计数 10 纳秒
+    let hpet = Hpet {
+        base: VirtAddr::zero(),
+        period_fs: 10_000_000,
+        wide: true,
+        min_tick: 0,
+    };
+    assert_eq!(hpet.counts_for(Duration::from_micros(1)), 100);
+    assert_eq!(hpet.counts_for(Duration::from_nanos(15)), 1);
+    assert_eq!(hpet.counts_for(Duration::ZERO), 1);
+}
@@ -0,0 +1,291 @@
+//! 本模块实现了 i8042 PS/2 控制器的初始化
+//!
+//! 不假设 BIOS 已经把控制器留在可用的状态：初始化时先关闭两个端口并清空输出缓冲区，
+//! 在关闭中断的情况下完成控制器自检、判断是否有第二个（辅助）端口、逐个测试端口，
+//! 再复位端口上的设备以确认设备存在，最后只为找到设备的端口打开中断。
+//!
+//! 第一个端口保持扫描码转换，键盘驱动按扫描码集 1 解码；辅助端口的中断由鼠标驱动在启用数据报告之后打开
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use crate::arch::port::Port;
+
+/// 数据端口，读出设备发来的字节或写入发给设备的字节
+pub(crate) const DATA_PORT: u16 = 0x60;
+/// 读取时为状态端口，写入时为命令端口
+const CONTROLLER_PORT: u16 = 0x64;
+
+/// 状态：输出缓冲区有数据
+const STATUS_OUTPUT_FULL: u8 = 1 << 0;
+/// 状态：输入缓冲区已满，还不能写入
+const STATUS_INPUT_FULL: u8 = 1 << 1;
+/// 状态：输出缓冲区中的数据来自辅助端口
+const STATUS_AUX_DATA: u8 = 1 << 5;
+
+/// 命令：读取命令字节
+const COMMAND_READ_CONFIG: u8 = 0x20;
+/// 命令：写入命令字节
+const COMMAND_WRITE_CONFIG: u8 = 0x60;
+/// 命令：关闭辅助端口
+const COMMAND_DISABLE_AUX: u8 = 0xa7;
+/// 命令：启用辅助端口
+const COMMAND_ENABLE_AUX: u8 = 0xa8;
+/// 命令：测试辅助端口，通过时返回 0
+const COMMAND_TEST_AUX: u8 = 0xa9;
+/// 命令：控制器自检，通过时返回 [`SELF_TEST_PASSED`]
+const COMMAND_SELF_TEST: u8 = 0xaa;
+/// 命令：测试第一个端口，通过时返回 0
+const COMMAND_TEST_KEYBOARD: u8 = 0xab;
+/// 命令：关闭第一个端口
+const COMMAND_DISABLE_KEYBOARD: u8 = 0xad;
+/// 命令：启用第一个端口
+const COMMAND_ENABLE_KEYBOARD: u8 = 0xae;
+/// 命令：把下一个数据字节发给辅助端口上的设备
+const COMMAND_WRITE_AUX: u8 = 0xd4;
+
+/// 命令字节：第一个端口的中断
+pub(crate) const CONFIG_KEYBOARD_INTERRUPT: u8 = 1 << 0;
+/// 命令字节：辅助端口的中断
+pub(crate) const CONFIG_AUX_INTERRUPT: u8 = 1 << 1;
+/// 命令字节：关闭第一个端口的时钟
+const CONFIG_KEYBOARD_CLOCK_DISABLED: u8 = 1 << 4;
+/// 命令字节：关闭辅助端口的时钟
+const CONFIG_AUX_CLOCK_DISABLED: u8 = 1 << 5;
+/// 命令字节：把第一个端口的扫描码集 2 转换为扫描码集 1
+const CONFIG_TRANSLATION: u8 = 1 << 6;
+
+/// 控制器自检通过时的返回值
+const SELF_TEST_PASSED: u8 = 0x55;
+/// 设备命令：复位并自检
+const DEVICE_RESET: u8 = 0xff;
+/// 设备对命令的应答
+pub(crate) const RESPONSE_ACK: u8 = 0xfa;
+/// 设备自检通过时发送的字节
+pub(crate) const RESPONSE_SELF_TEST_PASSED: u8 = 0xaa;
+
+/// 等待控制器时最多读取状态的次数
+const CONTROLLER_POLLS: usize = 100_000;
+/// 等待设备复位完成时最多读取状态的次数，设备自检可能需要数百毫秒
+const RESET_POLLS: usize = 10_000_000;
+/// 清空输出缓冲区时最多丢弃的字节数
+const FLUSH_LIMIT: usize = 32;
+
+/// 第一个端口上是否有设备
+static KEYBOARD: AtomicBool = AtomicBool::new(false);
+/// 辅助端口上是否有设备
+static AUX: AtomicBool = AtomicBool::new(false);
+
+/// 等待控制器的输入缓冲区清空
+fn wait_input() -> bool {
+    let mut status = Port::<u8>::new(CONTROLLER_PORT);
+    (0..CONTROLLER_POLLS).any(|_| unsafe { status.read() } & STATUS_INPUT_FULL == 0)
+}
+
+/// 读取控制器输出缓冲区中的一个字节
+///
+/// # 参数
+///
+/// - `aux`: 为 `true` 时只接受来自辅助端口的字节，为 `false` 时只接受其他字节，不符合的字节被丢弃
+/// - `polls`: 最多读取状态的次数
+fn read_from(aux: bool, polls: usize) -> Option<u8> {
+    let mut status = Port::<u8>::new(CONTROLLER_PORT);
+    for _ in 0..polls {
+        let flags = unsafe { status.read() };
+        if flags & STATUS_OUTPUT_FULL == 0 {
+            continue;
+        }
+        let byte = unsafe { Port::<u8>::new(DATA_PORT).read() };
+        if (flags & STATUS_AUX_DATA != 0) == aux {
+            return Some(byte);
+        }
+    }
+    None
+}
+
+/// 读取控制器对命令的应答，或第一个端口上的设备发来的字节
+fn read_output() -> Option<u8> {
+    read_from(false, CONTROLLER_POLLS)
+}
+
+/// 丢弃输出缓冲区中残留的字节
+fn flush() {
+    let mut status = Port::<u8>::new(CONTROLLER_PORT);
+    for _ in 0..FLUSH_LIMIT {
+        if unsafe { status.read() } & STATUS_OUTPUT_FULL == 0 {
+            break;
+        }
+        unsafe { Port::<u8>::new(DATA_PORT).read() };
+    }
+}
+
+/// 向控制器写入命令，以及命令的参数
+///
+/// # 返回
+///
+/// 控制器一直不接受写入时返回 `false`
+fn write_command(command: u8, data: Option<u8>) -> bool {
+    if !wait_input() {
+        return false;
+    }
+    unsafe { Port::<u8>::new(CONTROLLER_PORT).write(command) };
+    match data {
+        Some(data) => write_data(data),
+        None => true,
+    }
+}
+
+/// 向第一个端口上的设备写入一个字节
+fn write_data(data: u8) -> bool {
+    if !wait_input() {
+        return false;
+    }
+    unsafe { Port::<u8>::new(DATA_PORT).write(data) };
+    true
+}
+
+/// 读取命令字节
+fn read_config() -> Option<u8> {
+    write_command(COMMAND_READ_CONFIG, None)
+        .then(read_output)
+        .flatten()
+}
+
+/// 修改命令字节
+///
+/// 需要在关闭中断时调用，否则设备发来的字节可能被当成命令字节
+///
+/// # 参数
+///
+/// - `update`: 由旧的命令字节计算新的命令字节
+///
+/// # 返回
+///
+/// 控制器没有响应时返回 `false`
+pub(crate) fn update_config(update: impl FnOnce(u8) -> u8) -> bool {
+    let Some(config) = read_config() else {
+        return false;
+    };
+    write_command(COMMAND_WRITE_CONFIG, Some(update(config)))
+}
+
+/// 向辅助端口上的设备写入一个字节，不等待应答
+///
+/// # 返回
+///
+/// 控制器一直不接受写入时返回 `false`
+pub(crate) fn write_aux(data: u8) -> bool {
+    write_command(COMMAND_WRITE_AUX, Some(data))
+}
+
+/// 向辅助端口上的设备发送命令并等待应答
+///
+/// 需要在关闭辅助端口的中断时调用，否则应答会被中断处理函数读走
+pub(crate) fn send_aux(command: u8) -> bool {
+    write_aux(command) && read_from(true, CONTROLLER_POLLS) == Some(RESPONSE_ACK)
+}
+
+/// 复位端口上的设备，等待自检结果
+///
+/// # 参数
+///
+/// - `aux`: 是否为辅助端口
+fn reset_device(aux: bool) -> bool {
+    let sent = if aux {
+        write_aux(DEVICE_RESET)
+    } else {
+        write_data(DEVICE_RESET)
+    };
+    if !sent || read_from(aux, RESET_POLLS) != Some(RESPONSE_ACK) {
+        return false;
+    }
+    let passed = read_from(aux, RESET_POLLS) == Some(RESPONSE_SELF_TEST_PASSED);
+    // 鼠标在自检结果之后还会发送设备编号，键盘可能不发送，统一丢弃
+    if aux {
+        read_from(aux, CONTROLLER_POLLS);
+    }
+    passed
+}
+
+/// 初始化控制器并检测两个端口上的设备
+///
+/// 需要在关闭中断时调用；之后第一个端口上有设备时打开它的中断
+///
+/// # 返回
+///
+/// 控制器自检失败或没有响应时返回 `false`，此时两个端口都保持关闭
+pub fn init() -> bool {
+    // 先关闭两个端口，避免设备在配置期间发来的字节与控制器的应答混在一起
+    write_command(COMMAND_DISABLE_KEYBOARD, None);
+    write_command(COMMAND_DISABLE_AUX, None);
+    flush();
+
+    let Some(config) = read_config() else {
+        log::warn!("i8042 controller not responding");
+        return false;
+    };
+    let config = config & !(CONFIG_KEYBOARD_INTERRUPT | CONFIG_AUX_INTERRUPT);
+    if !write_command(COMMAND_WRITE_CONFIG, Some(config | CONFIG_TRANSLATION)) {
+        return false;
+    }
+    if !write_command(COMMAND_SELF_TEST, None) || read_output() != Some(SELF_TEST_PASSED) {
+        log::warn!("i8042 controller self-test failed");
+        return false;
+    }
+    // 有些控制器在自检后复位命令字节
+    write_command(COMMAND_WRITE_CONFIG, Some(config | CONFIG_TRANSLATION));
+
+    // 启用辅助端口之后它的时钟位被清除，说明有第二个端口
+    write_command(COMMAND_ENABLE_AUX, None);
+    let dual = read_config().is_some_and(|config| config & CONFIG_AUX_CLOCK_DISABLED == 0);
+    write_command(COMMAND_DISABLE_AUX, None);
+
+    let test = |command| write_command(command, None) && read_output() == Some(0);
+    let keyboard_port = test(COMMAND_TEST_KEYBOARD);
+    let aux_port = dual && test(COMMAND_TEST_AUX);
+
+    if keyboard_port {
+        write_command(COMMAND_ENABLE_KEYBOARD, None);
+    }
+    if aux_port {
+        write_command(COMMAND_ENABLE_AUX, None);
+    }
+    let keyboard = keyboard_port && reset_device(false);
+    let aux = aux_port && reset_device(true);
+    flush();
+
+    update_config(|config| {
+        let mut config = config | CONFIG_TRANSLATION;
+        config &= !(CONFIG_KEYBOARD_CLOCK_DISABLED | CONFIG_AUX_CLOCK_DISABLED);
+        if keyboard {
+            config |= CONFIG_KEYBOARD_INTERRUPT;
+        }
+        if !aux {
+            config |= CONFIG_AUX_CLOCK_DISABLED;
+        }
+        config
+    });
+    KEYBOARD.store(keyboard, Ordering::Relaxed);
+    AUX.store(aux, Ordering::Relaxed);
+    log::info!(
+        "i8042: keyboard {}, auxiliary device {}",
+        if keyboard { "present" } else { "absent" },
+        if aux {
+            "present"
+        } else if dual {
+            "absent"
+        } else {
+            "unsupported"
+        }
+    );
+    true
+}
+
+/// 第一个端口上是否有键盘
+pub fn has_keyboard() -> bool {
+    KEYBOARD.load(Ordering::Relaxed)
+}
+
+/// 辅助端口上是否有设备，通常为鼠标
+pub fn has_aux() -> bool {
+    AUX.load(Ordering::Relaxed)
+}
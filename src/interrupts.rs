@@ -0,0 +1,444 @@
+//! 本模块实现了中断描述符表（IDT）及 CPU 异常处理函数
+//!
+//! 硬件中断最初由 8259 投递，[`route_through_apic`] 把它们改由 IO APIC 投递之后 8259 被屏蔽，
+//! 处理函数通过 [`end_of_interrupt`] 向实际投递中断的控制器发送中断结束信号。
+//!
+//! PCI 设备的中断（MSI 或经 IO APIC 的 INTx）使用 [`register_device`] 动态分配的向量，
+//! 每个向量对应一个登记的处理函数
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use lazy_static::lazy_static;
+use x86_64::PrivilegeLevel;
+use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode};
+
+use crate::sync::SpinLock;
+use crate::{
+    apic, gdt, hpet, i8042, ioapic, keyboard, memory, mouse, percpu, pic, println, process, smp,
+    status, thread, time,
+};
+
+/// 硬件中断在 IDT 中的下标
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum InterruptIndex {
+    Timer = pic::PIC_1_OFFSET,
+    Keyboard,
+    Mouse = pic::PIC_1_OFFSET + mouse::MOUSE_IRQ,
+}
+
+impl InterruptIndex {
+    fn as_u8(self) -> u8 {
+        self as u8
+    }
+
+    /// 对应的 ISA 中断号
+    fn isa_irq(self) -> u8 {
+        self.as_u8() - pic::PIC_1_OFFSET
+    }
+}
+
+// IDT 需要在整个内核运行期间有效，因此使用静态变量
+lazy_static! {
+    static ref IDT: InterruptDescriptorTable = {
+        let mut idt = InterruptDescriptorTable::new();
+        idt.breakpoint.set_handler_fn(breakpoint_handler);
+        idt.invalid_opcode.set_handler_fn(invalid_opcode_handler);
+        idt.general_protection_fault
+            .set_handler_fn(general_protection_fault_handler);
+        unsafe {
+            idt.page_fault
+                .set_handler_fn(page_fault_handler)
+                .set_stack_index(gdt::PAGE_FAULT_IST_INDEX);
+            idt.non_maskable_interrupt
+                .set_handler_fn(nmi_handler)
+                .set_stack_index(gdt::NMI_IST_INDEX);
+            idt.double_fault
+                .set_handler_fn(double_fault_handler)
+                .set_stack_index(gdt::DOUBLE_FAULT_IST_INDEX);
+        }
+        idt[InterruptIndex::Timer.as_u8()].set_handler_fn(timer_interrupt_handler);
+        idt[InterruptIndex::Keyboard.as_u8()].set_handler_fn(keyboard_interrupt_handler);
+        idt[InterruptIndex::Mouse.as_u8()].set_handler_fn(mouse_interrupt_handler);
+        idt[apic::timer::TIMER_VECTOR].set_handler_fn(apic_timer_interrupt_handler);
+        idt[hpet::VECTOR].set_handler_fn(hpet_interrupt_handler);
+        idt[apic::SPURIOUS_VECTOR].set_handler_fn(spurious_interrupt_handler);
+        for (index, handler) in DEVICE_STUBS.into_iter().enumerate() {
+            idt[DEVICE_VECTOR_BASE + index as u8].set_handler_fn(handler);
+        }
+        idt
+    };
+}
+
+/// 分配给设备的第一个中断向量
+const DEVICE_VECTOR_BASE: u8 = 0x50;
+/// 可以分配给设备的向量数
+const DEVICE_VECTORS: usize = 16;
+
+/// 设备向量的处理函数表
+type DeviceHandlers = [Option<fn()>; DEVICE_VECTORS];
+
+/// 设备向量上登记的处理函数，下标为向量号减去 [`DEVICE_VECTOR_BASE`]
+static DEVICE_HANDLERS: SpinLock<DeviceHandlers> = SpinLock::new([None; DEVICE_VECTORS]);
+
+/// 为设备分配一个中断向量并登记处理函数
+///
+/// 向量只由本地 APIC 接收，设备的中断需要以 MSI 或经 IO APIC 投递到这个向量。
+/// 处理函数在中断上下文中调用，不能睡眠；电平触发的中断需要在返回前清除设备的中断状态
+///
+/// # 参数
+///
+/// - `handler`: 中断到达时调用的函数
+///
+/// # 返回
+///
+/// 分配的向量，向量已用完时返回 `None`
+pub fn register_device(handler: fn()) -> Option<u8> {
+    let mut handlers = DEVICE_HANDLERS.lock();
+    let index = handlers.iter().position(Option::is_none)?;
+    handlers[index] = Some(handler);
+    Some(DEVICE_VECTOR_BASE + index as u8)
+}
+
+/// 释放 [`register_device`] 分配的向量，之后到达这个向量的中断被忽略
+///
+/// # 参数
+///
+/// - `vector`: 分配的向量
+pub fn unregister_device(vector: u8) {
+    if let Some(handler) = DEVICE_HANDLERS
+        .lock()
+        .get_mut(usize::from(vector.wrapping_sub(DEVICE_VECTOR_BASE)))
+    {
+        *handler = None;
+    }
+}
+
+/// 调用设备向量上登记的处理函数并发送中断结束信号
+///
+/// # 参数
+///
+/// - `index`: 向量号减去 [`DEVICE_VECTOR_BASE`]
+fn dispatch_device(index: usize) {
+    // 在锁外调用处理函数，它可能需要登记新的向量或获取其他锁
+    let handler = DEVICE_HANDLERS.lock()[index];
+    if let Some(handler) = handler {
+        handler();
+    }
+    apic::eoi();
+}
+
+/// 为每个设备向量生成一个中断处理函数
+macro_rules! device_stubs {
+    ($($index:literal)*) => {
+        [$({
+            extern "x86-interrupt" fn stub(stack_frame: InterruptStackFrame) {
+                let _gs = percpu::enter_from(&stack_frame);
+                dispatch_device($index);
+            }
+            stub as extern "x86-interrupt" fn(InterruptStackFrame)
+        }),*]
+    };
+}
+
+/// 设备向量的中断处理函数
+const DEVICE_STUBS: [extern "x86-interrupt" fn(InterruptStackFrame); DEVICE_VECTORS] =
+    device_stubs!(0 1 2 3 4 5 6 7 8 9 10 11 12 13 14 15);
+
+/// 硬件中断是否已改由 APIC 投递
+static APIC_DELIVERY: AtomicBool = AtomicBool::new(false);
+
+/// tick 是否由 HPET 定时器 0 产生
+static HPET_TICK: AtomicBool = AtomicBool::new(false);
+
+/// 把定时器和键盘中断改由 IO APIC 投递到当前处理器，并屏蔽 8259
+///
+/// 需要在本地 APIC 启用之后调用。
+/// 此后中断结束信号改为发给本地 APIC；PIT 的中断没有接到 IO APIC 上时改用 HPET 产生 tick
+///
+/// # 返回
+///
+/// 本地 APIC 或 IO APIC 不可用时继续使用 8259，返回 `false`
+pub fn route_through_apic() -> bool {
+    use x86_64::instructions::interrupts;
+
+    if !apic::is_initialized() || !ioapic::init() {
+        log::warn!("no IO APIC; keeping the 8259 PIC");
+        return false;
+    }
+    interrupts::without_interrupts(|| {
+        let bsp = apic::id();
+        for index in [
+            InterruptIndex::Timer,
+            InterruptIndex::Keyboard,
+            InterruptIndex::Mouse,
+        ] {
+            if ioapic::route_isa(index.isa_irq(), index.as_u8(), bsp) {
+                continue;
+            }
+            log::warn!("IRQ {} is not wired to an IO APIC", index.isa_irq());
+            if index == InterruptIndex::Timer {
+                start_hpet_tick();
+            }
+        }
+        // 8259 上可能还有已经接收、尚未投递的中断，屏蔽后它们不会再到达
+        pic::disable();
+        APIC_DELIVERY.store(true, Ordering::Release);
+    });
+    log::info!("legacy IRQs now delivered through the IO APIC");
+    true
+}
+
+/// PIT 的中断无法投递时改由 HPET 定时器 0 以相同的间隔产生 tick
+fn start_hpet_tick() {
+    hpet::set_callback(hpet_tick);
+    if hpet::start_periodic(time::tick_duration()) {
+        HPET_TICK.store(true, Ordering::Release);
+        log::info!("HPET timer 0 drives the tick");
+    } else {
+        log::warn!("no HPET periodic timer; the tick is stopped");
+    }
+}
+
+/// HPET 代替 PIT 产生 tick 时的回调，与 PIT 中断处理函数推进相同的时钟
+fn hpet_tick() {
+    time::tick();
+    status::tick();
+}
+
+/// 硬件中断是否由 APIC 投递
+pub fn is_apic_delivery() -> bool {
+    APIC_DELIVERY.load(Ordering::Acquire)
+}
+
+/// 向投递中断的控制器发送中断结束（EOI）信号
+///
+/// # 参数
+///
+/// - `index`: 已处理完毕的硬件中断
+fn end_of_interrupt(index: InterruptIndex) {
+    if is_apic_delivery() {
+        apic::eoi();
+    } else {
+        pic::notify_end_of_interrupt(index.as_u8());
+    }
+}
+
+/// 加载 IDT
+pub fn init_idt() {
+    IDT.load();
+}
+
+/// 为应用处理器加载一份自己的 IDT 副本
+pub fn init_ap_idt() {
+    let idt = alloc::boxed::Box::leak(alloc::boxed::Box::new(IDT.clone()));
+    idt.load();
+}
+
+/// 断点异常（`int3`）处理函数，打印异常栈帧后继续执行
+extern "x86-interrupt" fn breakpoint_handler(stack_frame: InterruptStackFrame) {
+    let _gs = percpu::enter_from(&stack_frame);
+    println!("EXCEPTION: BREAKPOINT\n{:#?}", stack_frame);
+}
+
+/// 异常是否由用户态的代码触发
+fn from_user(stack_frame: &InterruptStackFrame) -> bool {
+    stack_frame.code_segment.rpl() == PrivilegeLevel::Ring3
+}
+
+/// 以 128 加信号编号的退出码结束触发异常的用户进程，切换到其他线程继续运行
+///
+/// # 参数
+///
+/// - `exception`: 异常的名称，用于日志
+/// - `stack_frame`: 异常栈帧
+/// - `signal`: 对应的信号编号
+fn kill_faulting_process(exception: &str, stack_frame: &InterruptStackFrame, signal: u64) -> ! {
+    log::warn!(
+        "{} in user mode at {:?}, killing process {}",
+        exception,
+        stack_frame.instruction_pointer,
+        thread::current_process().map_or(0, |pid| pid.as_u64())
+    );
+    process::exit_current(128 + signal as i64)
+}
+
+/// 无效指令异常（#UD）处理函数，用户态触发时以 `SIGILL` 结束进程
+extern "x86-interrupt" fn invalid_opcode_handler(stack_frame: InterruptStackFrame) {
+    let _gs = percpu::enter_from(&stack_frame);
+    if from_user(&stack_frame) {
+        kill_faulting_process("invalid opcode", &stack_frame, process::signal::SIGILL);
+    }
+    panic!("EXCEPTION: INVALID OPCODE\n{:#?}", stack_frame);
+}
+
+/// 一般保护错误（#GP）处理函数，用户态触发时以 `SIGSEGV` 结束进程
+extern "x86-interrupt" fn general_protection_fault_handler(
+    stack_frame: InterruptStackFrame,
+    error_code: u64,
+) {
+    let _gs = percpu::enter_from(&stack_frame);
+    if from_user(&stack_frame) {
+        kill_faulting_process(
+            "general protection fault",
+            &stack_frame,
+            process::signal::SIGSEGV,
+        );
+    }
+    panic!(
+        "EXCEPTION: GENERAL PROTECTION FAULT (error code {:#x})\n{:#?}",
+        error_code, stack_frame
+    );
+}
+
+/// 不可屏蔽中断（NMI）处理函数，运行在独立的 IST 栈上
+///
+/// 其他处理器通过 NMI 请求刷新 TLB。处理函数不能获取锁，其他来源的 NMI 直接忽略
+extern "x86-interrupt" fn nmi_handler(_stack_frame: InterruptStackFrame) {
+    smp::tlb::handle_nmi();
+}
+
+/// 双重错误处理函数，运行在独立的 IST 栈上，不可返回
+extern "x86-interrupt" fn double_fault_handler(
+    stack_frame: InterruptStackFrame,
+    _error_code: u64,
+) -> ! {
+    panic!("EXCEPTION: DOUBLE FAULT\n{:#?}", stack_frame);
+}
+
+/// 缺页异常处理函数
+///
+/// 写入写时复制页时复制帧后返回；其余由用户态触发的缺页以 `SIGSEGV` 结束进程。
+/// 内核访问线程栈的保护页时报告栈溢出，否则打印访问的地址、错误码和触发异常的指令地址后停机
+extern "x86-interrupt" fn page_fault_handler(
+    stack_frame: InterruptStackFrame,
+    error_code: PageFaultErrorCode,
+) {
+    use x86_64::registers::control::Cr2;
+    use x86_64::structures::paging::Page;
+
+    let _gs = percpu::enter_from(&stack_frame);
+    let write_protected =
+        PageFaultErrorCode::PROTECTION_VIOLATION | PageFaultErrorCode::CAUSED_BY_WRITE;
+    if error_code.contains(write_protected)
+        && let Ok(addr) = Cr2::read()
+        && let Some(Ok(true)) = memory::with_active_mapper(|mapper, frame_allocator| {
+            memory::resolve_copy_on_write(mapper, frame_allocator, Page::containing_address(addr))
+        })
+    {
+        return;
+    }
+
+    if from_user(&stack_frame) {
+        kill_faulting_process("page fault", &stack_frame, process::signal::SIGSEGV);
+    }
+
+    if let Some(thread) = Cr2::read().ok().and_then(thread::guard_page_owner) {
+        panic!(
+            "stack overflow in thread {} at {:?}",
+            thread.as_u64(),
+            stack_frame.instruction_pointer
+        );
+    }
+
+    println!("EXCEPTION: PAGE FAULT");
+    // CR2 寄存器保存了引发缺页的虚拟地址
+    println!("Accessed Address: {:#x}", Cr2::read_raw());
+    println!("Error Code: {:?}", error_code);
+    println!(
+        "  present: {}, write: {}, user: {}",
+        error_code.contains(PageFaultErrorCode::PROTECTION_VIOLATION),
+        error_code.contains(PageFaultErrorCode::CAUSED_BY_WRITE),
+        error_code.contains(PageFaultErrorCode::USER_MODE),
+    );
+    println!("Instruction Pointer: {:?}", stack_frame.instruction_pointer);
+    println!("{:#?}", stack_frame);
+
+    // 内核态的缺页原因尚未处理，无法安全地返回
+    crate::hlt_loop()
+}
+
+/// 定时器中断（IRQ0）处理函数，推进时钟后检查当前线程的时间片
+///
+/// 本地 APIC 定时器在运行时由它负责抢占，这里只推进时钟
+extern "x86-interrupt" fn timer_interrupt_handler(stack_frame: InterruptStackFrame) {
+    let _gs = percpu::enter_from(&stack_frame);
+    time::tick();
+    status::tick();
+    end_of_interrupt(InterruptIndex::Timer);
+    if !apic::timer::drives_preemption() {
+        percpu::current().count_tick();
+        preempt_from(&stack_frame);
+    }
+}
+
+/// 本地 APIC 定时器中断处理函数，检查当前处理器上线程的时间片
+extern "x86-interrupt" fn apic_timer_interrupt_handler(stack_frame: InterruptStackFrame) {
+    let _gs = percpu::enter_from(&stack_frame);
+    percpu::current().count_tick();
+    apic::eoi();
+    preempt_from(&stack_frame);
+}
+
+/// HPET 定时器 0 的中断处理函数，只经由 IO APIC 投递；代替 PIT 产生 tick 时同样检查时间片
+extern "x86-interrupt" fn hpet_interrupt_handler(stack_frame: InterruptStackFrame) {
+    let _gs = percpu::enter_from(&stack_frame);
+    hpet::handle_interrupt();
+    apic::eoi();
+    if HPET_TICK.load(Ordering::Acquire) && !apic::timer::drives_preemption() {
+        percpu::current().count_tick();
+        preempt_from(&stack_frame);
+    }
+}
+
+/// 定时器中断在发送 EOI 之后的公共部分：处理致命信号并检查是否需要切换线程
+///
+/// # 参数
+///
+/// - `stack_frame`: 中断栈帧，用于判断被中断的特权级
+fn preempt_from(stack_frame: &InterruptStackFrame) {
+    // 一直在用户态运行的进程不会经过系统调用的返回路径
+    if from_user(stack_frame) {
+        process::signal::check_fatal();
+    }
+    thread::preempt();
+}
+
+/// 键盘中断（IRQ1）处理函数，读取扫描码交给键盘驱动处理
+extern "x86-interrupt" fn keyboard_interrupt_handler(stack_frame: InterruptStackFrame) {
+    use crate::arch::port::Port;
+
+    let _gs = percpu::enter_from(&stack_frame);
+    let mut port = Port::new(keyboard::KEYBOARD_DATA_PORT);
+    // 必须读取扫描码，否则键盘控制器不会发送下一个中断
+    let scancode: u8 = unsafe { port.read() };
+
+    // 有异步消费者时只将扫描码入队，解码和处理都在任务中完成
+    if !keyboard::add_scancode(scancode) {
+        keyboard::process_scancode(scancode);
+    }
+
+    end_of_interrupt(InterruptIndex::Keyboard);
+}
+
+/// 鼠标中断（IRQ12）处理函数，读取一个字节交给鼠标驱动解码
+extern "x86-interrupt" fn mouse_interrupt_handler(stack_frame: InterruptStackFrame) {
+    use crate::arch::port::Port;
+
+    let _gs = percpu::enter_from(&stack_frame);
+    let byte: u8 = unsafe { Port::new(i8042::DATA_PORT).read() };
+    mouse::add_byte(byte);
+
+    end_of_interrupt(InterruptIndex::Mouse);
+}
+
+/// 本地 APIC 的伪中断处理函数
+///
+/// 伪中断在中断被撤销时产生，不设置服务中的位，因此不能发送中断结束信号
+extern "x86-interrupt" fn spurious_interrupt_handler(_stack_frame: InterruptStackFrame) {}
+
+#[test_case]
+fn test_breakpoint_exception() {
+    // 如果断点异常处理函数能正确返回，这里会继续执行
+    x86_64::instructions::interrupts::int3();
+}
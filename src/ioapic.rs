@@ -0,0 +1,352 @@
+//! 本模块实现了 IO APIC 的中断路由
+//!
+//! 每个 IO APIC 有若干输入引脚，从 MADT 中给出的全局系统中断号（GSI）开始连续编号。
+//! 每个引脚对应重定向表中的一项，决定中断投递到哪个处理器的哪个向量、触发方式以及是否屏蔽。
+//! ISA 中断通过 MADT 中的中断源覆盖找到对应的 GSI，PCI 等其他设备直接使用 GSI。
+//!
+//! 寄存器通过选择寄存器和数据窗口间接访问，一次访问需要两步，因此每个 IO APIC 由一把锁保护。
+//!
+//! 路由之前先用 [`claim`] 占用 GSI：电平触发的 PCI 中断可以共享，其他中断独占，
+//! 避免两个来源把同一个引脚改写成各自的向量
+
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+
+use conquer_once::spin::OnceCell;
+use x86_64::VirtAddr;
+
+use crate::acpi::{self, InterruptOverride, Madt};
+use crate::memory;
+use crate::sync::SpinLock;
+
+/// IO APIC 寄存器区域的大小
+const IOAPIC_SIZE: u64 = 0x20;
+
+/// 选择寄存器的偏移
+const IOREGSEL: u64 = 0x00;
+/// 数据窗口的偏移
+const IOWIN: u64 = 0x10;
+
+/// 版本寄存器，16~23 位为最后一个重定向表项的下标
+const REG_VERSION: u32 = 0x01;
+/// 第一个重定向表项的低 32 位，每项占两个寄存器
+const REG_REDIRECTION: u32 = 0x10;
+
+/// 重定向表项：低电平有效
+const ENTRY_ACTIVE_LOW: u64 = 1 << 13;
+/// 重定向表项：电平触发
+const ENTRY_LEVEL_TRIGGERED: u64 = 1 << 15;
+/// 重定向表项：屏蔽
+const ENTRY_MASKED: u64 = 1 << 16;
+
+/// 中断的触发方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Trigger {
+    Edge,
+    Level,
+}
+
+/// 中断的有效电平
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Polarity {
+    ActiveHigh,
+    ActiveLow,
+}
+
+/// GSI 的占用方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Sharing {
+    Exclusive, // 只有一个中断来源，例如边沿触发的 ISA 中断和 HPET
+    Shared,    // 多个 PCI 设备共享的电平触发中断
+}
+
+/// 一个 IO APIC
+struct IoApic {
+    base: VirtAddr, // 寄存器映射到的虚拟地址
+    gsi_base: u32,  // 第一个输入引脚的全局系统中断号
+    pins: u32,      // 输入引脚数
+}
+
+impl IoApic {
+    /// 读取寄存器
+    fn read(&mut self, reg: u32) -> u32 {
+        unsafe {
+            (self.base + IOREGSEL)
+                .as_mut_ptr::<u32>()
+                .write_volatile(reg);
+            (self.base + IOWIN).as_ptr::<u32>().read_volatile()
+        }
+    }
+
+    /// 写入寄存器
+    fn write(&mut self, reg: u32, value: u32) {
+        unsafe {
+            (self.base + IOREGSEL)
+                .as_mut_ptr::<u32>()
+                .write_volatile(reg);
+            (self.base + IOWIN)
+                .as_mut_ptr::<u32>()
+                .write_volatile(value);
+        }
+    }
+
+    /// 读取引脚的重定向表项
+    fn entry(&mut self, pin: u32) -> u64 {
+        let low = self.read(REG_REDIRECTION + pin * 2);
+        let high = self.read(REG_REDIRECTION + pin * 2 + 1);
+        (u64::from(high) << 32) | u64::from(low)
+    }
+
+    /// 写入引脚的重定向表项
+    ///
+    /// 先写入带屏蔽位的低半部分，避免在高半部分写完之前按新旧混合的设置投递中断
+    fn set_entry(&mut self, pin: u32, entry: u64) {
+        self.write(
+            REG_REDIRECTION + pin * 2,
+            entry as u32 | ENTRY_MASKED as u32,
+        );
+        self.write(REG_REDIRECTION + pin * 2 + 1, (entry >> 32) as u32);
+        self.write(REG_REDIRECTION + pin * 2, entry as u32);
+    }
+}
+
+/// 系统中的 IO APIC 和 MADT 中的中断源覆盖
+struct Routing {
+    io_apics: Vec<SpinLock<IoApic>>,
+    madt: Madt,
+}
+
+/// 初始化之后不再改变
+static ROUTING: OnceCell<Routing> = OnceCell::uninit();
+
+/// 已被占用的 GSI 和占用方式
+static CLAIMS: SpinLock<BTreeMap<u32, Sharing>> = SpinLock::new(BTreeMap::new());
+
+/// 映射 MADT 中列出的所有 IO APIC，并屏蔽它们的所有输入
+///
+/// # 返回
+///
+/// 没有 MADT 或其中没有 IO APIC 时返回 `false`
+pub fn init() -> bool {
+    if ROUTING.is_initialized() {
+        return true;
+    }
+    let Some(madt) = acpi::madt() else {
+        return false;
+    };
+    let mut io_apics = Vec::new();
+    for info in &madt.io_apics {
+        let Some(base) = memory::map_mmio(info.address, IOAPIC_SIZE) else {
+            log::warn!("failed to map IO APIC {}", info.id);
+            continue;
+        };
+        let mut io_apic = IoApic {
+            base,
+            gsi_base: info.gsi_base,
+            pins: 0,
+        };
+        io_apic.pins = ((io_apic.read(REG_VERSION) >> 16) & 0xff) + 1;
+        for pin in 0..io_apic.pins {
+            io_apic.set_entry(pin, ENTRY_MASKED);
+        }
+        log::info!(
+            "IO APIC {} handles GSI {}..{}",
+            info.id,
+            io_apic.gsi_base,
+            io_apic.gsi_base + io_apic.pins
+        );
+        io_apics.push(SpinLock::new(io_apic));
+    }
+    if io_apics.is_empty() {
+        return false;
+    }
+    ROUTING.init_once(|| Routing { io_apics, madt });
+    true
+}
+
+/// IO APIC 是否已初始化
+pub fn is_initialized() -> bool {
+    ROUTING.is_initialized()
+}
+
+/// 对负责 `gsi` 的 IO APIC 上对应的引脚执行 `f`
+///
+/// # 返回
+///
+/// 没有 IO APIC 负责这个 GSI 时返回 `None`
+fn with_pin<R>(gsi: u32, f: impl FnOnce(&mut IoApic, u32) -> R) -> Option<R> {
+    let routing = ROUTING.get()?;
+    let (io_apic, pin) = routing.io_apics.iter().find_map(|io_apic| {
+        let guard = io_apic.lock();
+        let pin = gsi.checked_sub(guard.gsi_base)?;
+        (pin < guard.pins).then_some((io_apic, pin))
+    })?;
+    Some(f(&mut io_apic.lock(), pin))
+}
+
+/// 把全局系统中断投递到处理器的某个向量，路由后该中断处于开启状态
+///
+/// # 参数
+///
+/// - `gsi`: 全局系统中断号
+/// - `vector`: 中断向量号
+/// - `apic_id`: 目标处理器的 APIC ID，物理目标模式下只能使用低 8 位
+/// - `trigger`: 触发方式
+/// - `polarity`: 有效电平
+///
+/// # 返回
+///
+/// 没有 IO APIC 负责这个 GSI 时返回 `false`
+pub fn route(gsi: u32, vector: u8, apic_id: u32, trigger: Trigger, polarity: Polarity) -> bool {
+    let mut entry = u64::from(vector) | (u64::from(apic_id & 0xff) << 56);
+    if trigger == Trigger::Level {
+        entry |= ENTRY_LEVEL_TRIGGERED;
+    }
+    if polarity == Polarity::ActiveLow {
+        entry |= ENTRY_ACTIVE_LOW;
+    }
+    with_pin(gsi, |io_apic, pin| io_apic.set_entry(pin, entry)).is_some()
+}
+
+/// 占用一个 GSI，占用不会释放
+///
+/// # 参数
+///
+/// - `gsi`: 全局系统中断号
+/// - `sharing`: 占用方式
+///
+/// # 返回
+///
+/// GSI 已被独占，或者要独占一个已被占用的 GSI 时返回 `false`
+pub fn claim(gsi: u32, sharing: Sharing) -> bool {
+    let mut claims = CLAIMS.lock();
+    match claims.get(&gsi) {
+        None => {
+            claims.insert(gsi, sharing);
+            true
+        }
+        Some(&claimed) => claimed == Sharing::Shared && sharing == Sharing::Shared,
+    }
+}
+
+/// GSI 是否已被占用
+///
+/// # 参数
+///
+/// - `gsi`: 全局系统中断号
+pub fn is_claimed(gsi: u32) -> bool {
+    CLAIMS.lock().contains_key(&gsi)
+}
+
+/// 按 MADT 中的中断源覆盖把 ISA 中断投递到处理器的某个向量
+///
+/// # 参数
+///
+/// - `irq`: ISA 中断号
+/// - `vector`: 中断向量号
+/// - `apic_id`: 目标处理器的 APIC ID
+///
+/// # 返回
+///
+/// 没有 IO APIC 负责这个中断或它的 GSI 已被占用时返回 `false`
+pub fn route_isa(irq: u8, vector: u8, apic_id: u32) -> bool {
+    let Some(InterruptOverride {
+        gsi,
+        active_low,
+        level_triggered,
+        ..
+    }) = isa_irq(irq)
+    else {
+        return false;
+    };
+    let trigger = if level_triggered {
+        Trigger::Level
+    } else {
+        Trigger::Edge
+    };
+    let polarity = if active_low {
+        Polarity::ActiveLow
+    } else {
+        Polarity::ActiveHigh
+    };
+    claim(gsi, Sharing::Exclusive) && route(gsi, vector, apic_id, trigger, polarity)
+}
+
+/// 把 PCI 设备的 INTx 中断投递到处理器的某个向量，总是电平触发
+///
+/// 固件在配置空间中填写的中断线按 8259 的编号。MADT 中有这条中断线的覆盖时按覆盖的 GSI 和有效电平路由，
+/// 否则按 PCI 规范视为恒等映射的低电平有效中断
+///
+/// # 参数
+///
+/// - `line`: 配置空间中的中断线
+/// - `vector`: 中断向量号
+/// - `apic_id`: 目标处理器的 APIC ID
+///
+/// # 返回
+///
+/// 尚未初始化、没有 IO APIC 负责这个中断或它的 GSI 已被独占时返回 `false`
+pub fn route_pci(line: u8, vector: u8, apic_id: u32) -> bool {
+    let Some(routing) = ROUTING.get() else {
+        return false;
+    };
+    let (gsi, polarity) = match routing.madt.overrides.iter().find(|o| o.source == line) {
+        Some(o) if !o.active_low => (o.gsi, Polarity::ActiveHigh),
+        Some(o) => (o.gsi, Polarity::ActiveLow),
+        None => (u32::from(line), Polarity::ActiveLow),
+    };
+    claim(gsi, Sharing::Shared) && route(gsi, vector, apic_id, Trigger::Level, polarity)
+}
+
+/// ISA 中断对应的全局系统中断和触发方式，尚未初始化时返回 `None`
+///
+/// # 参数
+///
+/// - `irq`: ISA 中断号
+pub fn isa_irq(irq: u8) -> Option<InterruptOverride> {
+    Some(ROUTING.get()?.madt.isa_irq(irq))
+}
+
+/// 屏蔽或开启全局系统中断
+///
+/// # 参数
+///
+/// - `gsi`: 全局系统中断号
+/// - `masked`: 是否屏蔽
+///
+/// # 返回
+///
+/// 没有 IO APIC 负责这个 GSI 时返回 `false`
+pub fn set_masked(gsi: u32, masked: bool) -> bool {
+    with_pin(gsi, |io_apic, pin| {
+        let entry = io_apic.entry(pin);
+        let entry = if masked {
+            entry | ENTRY_MASKED
+        } else {
+            entry & !ENTRY_MASKED
+        };
+        io_apic.set_entry(pin, entry);
+    })
+    .is_some()
+}
+
+/// 全局系统中断是否被屏蔽，没有 IO APIC 负责这个 GSI 时返回 `None`
+///
+/// # 参数
+///
+/// - `gsi`: 全局系统中断号
+pub fn is_masked(gsi: u32) -> Option<bool> {
+    with_pin(gsi, |io_apic, pin| io_apic.entry(pin) & ENTRY_MASKED != 0)
+}
+
+#[test_case]
+fn test_claim_shares_only_pci_interrupts() {
+    // 远高于实际引脚数的 GSI，不会与其他测试占用的冲突
+    assert!(claim(1000, Sharing::Shared));
+    assert!(claim(1000, Sharing::Shared));
+    assert!(!claim(1000, Sharing::Exclusive));
+    assert!(claim(1001, Sharing::Exclusive));
+    assert!(!claim(1001, Sharing::Shared));
+    assert!(is_claimed(1001));
+    assert!(!is_claimed(1002));
+}
@@ -0,0 +1,330 @@
+//! 本模块实现了 PS/2 键盘的扫描码解码
+//!
+//! 扫描码经 `pc-keyboard` 的状态机转换为按键，字符映射由可替换的键盘布局表完成，
+//! 启动时通过 [`set_layout`] 选择。Shift、Caps Lock 和 Num Lock 的状态由状态机跟踪，
+//! 锁定键切换时同步更新键盘指示灯
+//!
+//! 创建 [`ScancodeStream`] 之后，键盘中断只把扫描码放入无锁队列，
+//! 由异步任务 [`handle_keypresses`] 在中断上下文之外完成解码和处理
+
+use core::pin::Pin;
+use core::task::{Context, Poll};
+use core::time::Duration;
+
+use conquer_once::spin::OnceCell;
+use crossbeam_queue::ArrayQueue;
+use futures_util::stream::{Stream, StreamExt};
+use futures_util::task::AtomicWaker;
+use pc_keyboard::layouts::{AnyLayout, Azerty, De105Key, Us104Key};
+use pc_keyboard::{
+    DecodedKey, HandleControl, KeyCode, KeyState, Keyboard, Modifiers, ScancodeSet1,
+};
+use spin::Mutex;
+
+use crate::arch::port::Port;
+use crate::i8042::{self, RESPONSE_ACK};
+use crate::{status, tty, tty_print};
+
+/// PS/2 键盘数据端口
+pub(crate) const KEYBOARD_DATA_PORT: u16 = i8042::DATA_PORT;
+
+/// 设置指示灯的键盘命令，之后需要再发送一个指示灯位图
+const COMMAND_SET_LEDS: u8 = 0xed;
+/// 指示灯位图中的 Num Lock 位
+const LED_NUM_LOCK: u8 = 1 << 1;
+/// 指示灯位图中的 Caps Lock 位
+const LED_CAPS_LOCK: u8 = 1 << 2;
+
+/// Alt+F10 提示音的频率（Hz）
+const DEMO_TONE_HZ: u32 = 440;
+/// Alt+F10 提示音的时长
+const DEMO_TONE: Duration = Duration::from_millis(200);
+
+/// 键盘布局
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Layout {
+    /// 美式 QWERTY
+    Us104,
+    /// 德式 QWERTZ
+    De105,
+    /// 法式 AZERTY
+    Azerty,
+}
+
+impl Layout {
+    /// 布局对应的映射表
+    const fn table(self) -> AnyLayout {
+        match self {
+            Layout::Us104 => AnyLayout::Us104Key(Us104Key),
+            Layout::De105 => AnyLayout::De105Key(De105Key),
+            Layout::Azerty => AnyLayout::Azerty(Azerty),
+        }
+    }
+}
+
+/// 键盘驱动的状态
+struct KeyboardState {
+    decoder: Keyboard<AnyLayout, ScancodeSet1>, // 负责将扫描码集 1 解码为按键事件，并跟踪修饰键
+    layout: Layout,                             // 当前的键盘布局
+    pending_leds: Option<u8>,                   // 等待键盘应答后发送的指示灯位图
+}
+
+impl KeyboardState {
+    const fn new(layout: Layout) -> Self {
+        Self {
+            decoder: Keyboard::new(
+                ScancodeSet1::new(),
+                layout.table(),
+                HandleControl::MapLettersToUnicode,
+            ),
+            layout,
+            pending_leds: None,
+        }
+    }
+}
+
+static KEYBOARD: Mutex<KeyboardState> = Mutex::new(KeyboardState::new(Layout::Us104));
+
+/// 切换键盘布局，修饰键和锁定键的状态会被重置
+///
+/// # 参数
+///
+/// - `layout`: 键盘布局
+pub fn set_layout(layout: Layout) {
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+        *KEYBOARD.lock() = KeyboardState::new(layout);
+    });
+}
+
+/// 当前的键盘布局
+pub fn layout() -> Layout {
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| KEYBOARD.lock().layout)
+}
+
+/// 处理从键盘数据端口读出的一个扫描码，由键盘中断调用
+///
+/// # 参数
+///
+/// - `scancode`: 扫描码
+///
+/// # 返回
+///
+/// 扫描码完成了一次按键时，返回解码后的按键和此时的修饰键状态
+pub(crate) fn handle_scancode(scancode: u8) -> Option<(DecodedKey, Modifiers)> {
+    let mut keyboard = KEYBOARD.lock();
+    if scancode == RESPONSE_ACK
+        && let Some(leds) = keyboard.pending_leds.take()
+    {
+        unsafe { Port::new(KEYBOARD_DATA_PORT).write(leds) };
+        return None;
+    }
+
+    let event = keyboard.decoder.add_byte(scancode).ok()??;
+    let toggles_lock = event.state == KeyState::Down
+        && matches!(event.code, KeyCode::CapsLock | KeyCode::NumpadLock);
+    let key = keyboard.decoder.process_keyevent(event)?;
+    let modifiers = keyboard.decoder.get_modifiers().clone();
+    if toggles_lock {
+        let mut leds = 0;
+        if modifiers.numlock {
+            leds |= LED_NUM_LOCK;
+        }
+        if modifiers.capslock {
+            leds |= LED_CAPS_LOCK;
+        }
+        keyboard.pending_leds = Some(leds);
+        unsafe { Port::new(KEYBOARD_DATA_PORT).write(COMMAND_SET_LEDS) };
+    }
+    Some((key, modifiers))
+}
+
+/// 响应一次按键：切换终端、翻动回滚缓冲区、打印统计、发声或回显字符
+///
+/// # 参数
+///
+/// - `key`: 解码后的按键
+/// - `modifiers`: 按键时的修饰键状态
+fn handle_key(key: DecodedKey, modifiers: &Modifiers) {
+    use x86_64::instructions::interrupts;
+
+    let shift = modifiers.is_shifted();
+    let alt = modifiers.lalt || modifiers.ralt;
+    let has_terminals = crate::console::primary().has_terminals();
+    match key {
+        // Alt+F1~F4 切换虚拟终端
+        DecodedKey::RawKey(code @ (KeyCode::F1 | KeyCode::F2 | KeyCode::F3 | KeyCode::F4))
+            if alt =>
+        {
+            let index = match code {
+                KeyCode::F1 => 0,
+                KeyCode::F2 => 1,
+                KeyCode::F3 => 2,
+                _ => 3,
+            };
+            tty::switch(index);
+            status::refresh();
+        }
+        // Alt+F10 让扬声器发出一声提示音
+        DecodedKey::RawKey(KeyCode::F10) if alt => {
+            crate::speaker::beep(DEMO_TONE_HZ, DEMO_TONE);
+        }
+        // Alt+F11 打印调度器中各线程占用的 CPU 时间
+        DecodedKey::RawKey(KeyCode::F11) if alt => crate::println!("{}", crate::thread::stats()),
+        // Alt+F12 打印执行器中各任务的运行统计
+        DecodedKey::RawKey(KeyCode::F12) if alt => crate::task::executor::request_stats(),
+        // Shift+PageUp/PageDown 翻动当前终端的回滚缓冲区，只有 VGA 文本模式的终端有回滚缓冲区
+        DecodedKey::RawKey(KeyCode::PageUp) if shift && has_terminals => {
+            interrupts::without_interrupts(|| {
+                let mut terminal = tty::active_terminal().lock();
+                let lines = terminal.page_lines();
+                terminal.scroll_up(lines);
+            })
+        }
+        DecodedKey::RawKey(KeyCode::PageDown) if shift && has_terminals => {
+            interrupts::without_interrupts(|| {
+                let mut terminal = tty::active_terminal().lock();
+                let lines = terminal.page_lines();
+                terminal.scroll_down(lines);
+            })
+        }
+        DecodedKey::Unicode(character)
+            if !character.is_control() || matches!(character, '\n' | '\x08') =>
+        {
+            // 回显到当前显示的终端
+            tty_print!(tty::active(), "{}", character)
+        }
+        // Ctrl+C 中断前台进程
+        DecodedKey::Unicode('\x03') => crate::process::signal::interrupt_foreground(),
+        // Ctrl 组合键映射为控制字符，以及功能键等原始按键，暂不回显
+        DecodedKey::Unicode(_) | DecodedKey::RawKey(_) => {}
+    }
+}
+
+/// 解码一个扫描码并响应完成的按键
+///
+/// # 参数
+///
+/// - `scancode`: 扫描码
+pub(crate) fn process_scancode(scancode: u8) {
+    use x86_64::instructions::interrupts;
+
+    let decoded = interrupts::without_interrupts(|| handle_scancode(scancode));
+    if let Some((key, modifiers)) = decoded {
+        handle_key(key, &modifiers);
+    }
+}
+
+/// 扫描码队列的容量
+const SCANCODE_QUEUE_SIZE: usize = 100;
+
+/// 键盘中断与异步任务之间的扫描码队列，创建 [`ScancodeStream`] 时初始化
+static SCANCODE_QUEUE: OnceCell<ArrayQueue<u8>> = OnceCell::uninit();
+/// 等待扫描码的任务
+static WAKER: AtomicWaker = AtomicWaker::new();
+
+/// 将扫描码放入队列并唤醒等待的任务，由键盘中断调用，不会阻塞或分配内存
+///
+/// # 参数
+///
+/// - `scancode`: 扫描码
+///
+/// # 返回
+///
+/// 队列尚未初始化时返回 `false`，此时调用者应直接处理扫描码
+pub(crate) fn add_scancode(scancode: u8) -> bool {
+    let Ok(queue) = SCANCODE_QUEUE.try_get() else {
+        return false;
+    };
+    if queue.push(scancode).is_err() {
+        log::warn!("scancode queue full; dropping keyboard input");
+    } else {
+        WAKER.wake();
+    }
+    true
+}
+
+/// 异步的扫描码流，只能创建一个
+pub struct ScancodeStream {
+    _private: (),
+}
+
+impl ScancodeStream {
+    /// 创建扫描码流并初始化队列，必须在堆初始化之后调用
+    pub fn new() -> Self {
+        SCANCODE_QUEUE
+            .try_init_once(|| ArrayQueue::new(SCANCODE_QUEUE_SIZE))
+            .expect("ScancodeStream::new should only be called once");
+        Self { _private: () }
+    }
+}
+
+impl Default for ScancodeStream {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Stream for ScancodeStream {
+    type Item = u8;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<u8>> {
+        let queue = SCANCODE_QUEUE
+            .try_get()
+            .expect("scancode queue not initialized");
+
+        // 快速路径：队列非空时不必注册唤醒器
+        if let Some(scancode) = queue.pop() {
+            return Poll::Ready(Some(scancode));
+        }
+
+        WAKER.register(cx.waker());
+        // 注册之后再检查一次，避免错过在此期间到达的扫描码
+        match queue.pop() {
+            Some(scancode) => {
+                WAKER.take();
+                Poll::Ready(Some(scancode))
+            }
+            None => Poll::Pending,
+        }
+    }
+}
+
+/// 持续等待并处理按键的异步任务
+pub async fn handle_keypresses() {
+    let mut scancodes = ScancodeStream::new();
+    while let Some(scancode) = scancodes.next().await {
+        process_scancode(scancode);
+    }
+}
+
+#[test_case]
+fn test_layout_changes_mapping() {
+    use x86_64::instructions::interrupts;
+
+    /// 美式布局中 Z 键的按下和松开扫描码
+    const Z_DOWN: u8 = 0x2c;
+    const Z_UP: u8 = 0xac;
+
+    interrupts::without_interrupts(|| {
+        set_layout(Layout::De105);
+        assert_eq!(layout(), Layout::De105);
+        let (key, _) = handle_scancode(Z_DOWN).unwrap();
+        assert_eq!(key, DecodedKey::Unicode('y'));
+        assert!(handle_scancode(Z_UP).is_none());
+
+        set_layout(Layout::Us104);
+        let (key, _) = handle_scancode(Z_DOWN).unwrap();
+        assert_eq!(key, DecodedKey::Unicode('z'));
+        assert!(handle_scancode(Z_UP).is_none());
+    });
+}
+
+#[test_case]
+fn test_add_scancode_without_stream() {
+    // 没有创建扫描码流时，扫描码应交还给调用者直接处理
+    assert!(!add_scancode(0x2c));
+}
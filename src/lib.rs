@@ -0,0 +1,235 @@
+#![feature(custom_test_frameworks)]
+#![feature(abi_x86_interrupt)]
+#![test_runner(crate::test_runner)]
+#![reexport_test_harness_main = "test_main"] // 修改测试入口
+#![no_std] // 禁用标准库
+#![cfg_attr(test, no_main)] // 测试时禁用 main 函数
+
+extern crate alloc;
+
+pub mod acpi;
+pub mod allocator;
+pub mod ansi;
+pub mod apic;
+pub mod arch;
+pub mod block;
+pub mod console;
+pub mod cpu;
+pub mod debugcon;
+pub mod dmesg;
+pub mod elf;
+pub mod framebuffer;
+pub mod gdt;
+pub mod hpet;
+pub mod i8042;
+pub mod interrupts;
+pub mod ioapic;
+pub mod keyboard;
+pub mod logger;
+pub mod memory;
+pub mod mouse;
+pub mod net;
+pub mod panic_screen;
+pub mod pci;
+pub mod percpu;
+pub mod pic;
+pub mod power;
+pub mod process;
+pub mod rand;
+pub mod serial;
+pub mod smbios;
+pub mod smp;
+pub mod speaker;
+pub mod status;
+pub mod sync;
+pub mod syscall;
+pub mod task;
+pub mod thread;
+pub mod time;
+pub mod tty;
+pub mod user;
+pub mod vga_buffer;
+pub mod virtio;
+pub mod workqueue;
+
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+#[cfg(test)]
+use bootloader::{BootInfo, entry_point};
+
+/// 初始化内核
+pub fn init() {
+    logger::init();
+    cpu::init();
+    time::init();
+    gdt::init();
+    syscall::init();
+    interrupts::init_idt();
+    pic::init();
+    i8042::init();
+    x86_64::instructions::interrupts::enable();
+}
+
+/// 使用 `hlt` 指令让 CPU 休眠到下一次中断，而不是空转
+pub fn hlt_loop() -> ! {
+    loop {
+        x86_64::instructions::hlt();
+    }
+}
+
+/// QEMU 退出码
+///
+/// 写入 isa-debug-exit 端口的值 `v` 会使 QEMU 以 `(v << 1) | 1` 退出
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
+pub enum QemuExitCode {
+    Success = 0x10,
+    Failed = 0x11,
+}
+
+/// isa-debug-exit 设备的 I/O 端口
+const ISA_DEBUG_EXIT_PORT: u16 = 0xf4;
+
+/// 通过 isa-debug-exit 设备退出 QEMU
+///
+/// # 参数
+///
+/// - `exit_code`: 退出码
+pub fn exit_qemu(exit_code: QemuExitCode) {
+    use crate::arch::port::Port;
+
+    unsafe {
+        let mut port = Port::new(ISA_DEBUG_EXIT_PORT);
+        port.write(exit_code as u32);
+    }
+}
+
+/// 可测试对象，运行前后自动打印测试名称和结果
+pub trait Testable {
+    fn run(&self);
+}
+
+impl<T> Testable for T
+where
+    T: Fn(),
+{
+    fn run(&self) {
+        serial_print!("{}...\t", core::any::type_name::<T>());
+        self();
+        serial_println!("[ok]");
+    }
+}
+
+/// 本次运行的测试总数
+static TESTS_TOTAL: AtomicUsize = AtomicUsize::new(0);
+/// 已通过的测试数
+static TESTS_PASSED: AtomicUsize = AtomicUsize::new(0);
+
+/// 测试运行器，依次运行所有测试，通过串口输出结果后退出 QEMU
+///
+/// 没有 isa-debug-exit 设备时关闭电源
+///
+/// # 参数
+///
+/// - `tests`: 要运行的测试
+pub fn test_runner(tests: &[&dyn Testable]) {
+    TESTS_TOTAL.store(tests.len(), Ordering::Relaxed);
+    serial_println!("\nrunning {} tests", tests.len());
+    for test in tests {
+        test.run();
+        TESTS_PASSED.fetch_add(1, Ordering::Relaxed);
+    }
+    serial_println!(
+        "\ntest result: ok. {} passed; 0 failed\n",
+        TESTS_PASSED.load(Ordering::Relaxed)
+    );
+    exit_qemu(QemuExitCode::Success);
+    power::shutdown();
+}
+
+/// 测试时的 panic 处理函数，通过串口报告失败并以失败码退出 QEMU
+///
+/// # 参数
+///
+/// - `info`: panic 信息
+pub fn test_panic_handler(info: &PanicInfo) -> ! {
+    serial_println!("[failed]\n");
+    serial_println!("Error: {}\n", info);
+
+    // 不使用测试运行器的测试（`harness = false`）没有统计信息
+    let total = TESTS_TOTAL.load(Ordering::Relaxed);
+    if total > 0 {
+        let passed = TESTS_PASSED.load(Ordering::Relaxed);
+        serial_println!(
+            "test result: FAILED. {} passed; 1 failed; {} not run\n",
+            passed,
+            total - passed - 1
+        );
+    }
+    exit_qemu(QemuExitCode::Failed);
+
+    hlt_loop()
+}
+
+/// 测试用的定长文本缓冲区，不需要堆，写满之后的内容被丢弃
+#[cfg(test)]
+pub(crate) struct TestBuffer<const N: usize> {
+    bytes: [u8; N],
+    len: usize,
+}
+
+#[cfg(test)]
+impl<const N: usize> TestBuffer<N> {
+    pub(crate) const fn new() -> Self {
+        Self {
+            bytes: [0; N],
+            len: 0,
+        }
+    }
+
+    /// 已写入的内容，截断在字符中间时去掉不完整的字符
+    pub(crate) fn as_str(&self) -> &str {
+        let bytes = &self.bytes[..self.len];
+        match core::str::from_utf8(bytes) {
+            Ok(s) => s,
+            Err(error) => core::str::from_utf8(&bytes[..error.valid_up_to()]).unwrap_or(""),
+        }
+    }
+}
+
+#[cfg(test)]
+impl<const N: usize> core::fmt::Write for TestBuffer<N> {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        let n = s.len().min(N - self.len);
+        self.bytes[self.len..self.len + n].copy_from_slice(&s.as_bytes()[..n]);
+        self.len += n;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+entry_point!(test_kernel_main);
+
+/// `cargo test --lib` 的入口，初始化堆之后运行所有单元测试
+#[cfg(test)]
+fn test_kernel_main(boot_info: &'static BootInfo) -> ! {
+    use memory::BootInfoFrameAllocator;
+    use x86_64::VirtAddr;
+
+    init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    memory::install(mapper, frame_allocator);
+    test_main();
+
+    hlt_loop()
+}
+
+#[cfg(test)]
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    test_panic_handler(info)
+}
@@ -0,0 +1,98 @@
+//! 本模块实现了 `log` 门面的内核日志器
+//!
+//! 内核代码使用 `log::info!`、`log::warn!` 等宏记录日志，日志带有 UTC 时间、级别和来源模块，
+//! 经由控制台多路输出打印到所有已启用的输出端，同时保存到 [`dmesg`](crate::dmesg) 缓冲区
+
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+use crate::vga_buffer::Color;
+use crate::{dmesg, println_colored, time};
+
+/// 未设置时的默认最高日志级别
+const DEFAULT_MAX_LEVEL: LevelFilter = LevelFilter::Info;
+
+/// 日志级别在 VGA 上显示的前景色
+///
+/// # 参数
+///
+/// - `level`: 日志级别
+fn level_color(level: Level) -> Color {
+    match level {
+        Level::Error => Color::Red,
+        Level::Warn => Color::Yellow,
+        Level::Info => Color::White,
+        Level::Debug => Color::LightGray,
+        Level::Trace => Color::DarkGray,
+    }
+}
+
+/// 内核日志器
+struct KernelLogger;
+
+impl Log for KernelLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        dmesg::record(record);
+        let now = time::now_utc();
+        println_colored!(
+            level_color(record.level()),
+            Color::Black,
+            "{:02}:{:02}:{:02} [{:<5}] {}: {}",
+            now.hour,
+            now.minute,
+            now.second,
+            record.level(),
+            record.target(),
+            record.args()
+        );
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: KernelLogger = KernelLogger;
+
+/// 注册内核日志器，重复调用时只有第一次生效
+pub fn init() {
+    if log::set_logger(&LOGGER).is_ok() {
+        log::set_max_level(DEFAULT_MAX_LEVEL);
+    }
+}
+
+/// 设置运行时的最高日志级别，低于该级别的日志会被丢弃
+///
+/// # 参数
+///
+/// - `level`: 最高日志级别
+pub fn set_max_level(level: LevelFilter) {
+    log::set_max_level(level);
+}
+
+#[test_case]
+fn test_max_level_filters_records() {
+    let metadata = |level| Metadata::builder().level(level).target("test").build();
+    set_max_level(LevelFilter::Warn);
+    assert!(LOGGER.enabled(&metadata(Level::Error)));
+    assert!(!LOGGER.enabled(&metadata(Level::Info)));
+    set_max_level(DEFAULT_MAX_LEVEL);
+    assert!(LOGGER.enabled(&metadata(Level::Info)));
+    assert!(!LOGGER.enabled(&metadata(Level::Debug)));
+}
+
+#[test_case]
+fn test_log_restores_color() {
+    use x86_64::instructions::interrupts;
+
+    use crate::vga_buffer::WRITER;
+
+    let before = interrupts::without_interrupts(|| WRITER.lock().color_code());
+    log::error!("test_log_restores_color output");
+    let after = interrupts::without_interrupts(|| WRITER.lock().color_code());
+    assert_eq!(before, after);
+}
@@ -1,40 +1,127 @@
 #![feature(custom_test_frameworks)]
-#![test_runner(crate::test_runner)]
+#![test_runner(ricky_os::test_runner)]
 #![reexport_test_harness_main = "test_main"] // 修改测试入口
 #![no_std] // 禁用标准库
 #![no_main] // 禁用 main 函数
 
-mod vga_buffer;
+extern crate alloc;
 
+use alloc::boxed::Box;
+use alloc::vec::Vec;
 use core::panic::PanicInfo;
+use core::time::Duration;
 
-#[unsafe(no_mangle)]
-pub extern "C" fn _start() -> ! {
+use bootloader::{BootInfo, entry_point};
+use ricky_os::console::{self, Sink};
+use ricky_os::keyboard::{self, Layout};
+use ricky_os::memory::{self, BootInfoFrameAllocator};
+use ricky_os::task::executor::Executor;
+use ricky_os::task::{self, Priority, Task};
+use ricky_os::vga_buffer::{self, Color};
+use ricky_os::{
+    acpi, allocator, block, clear, framebuffer, hpet, interrupts, mouse, net, pci, println,
+    println_colored, rand, serial_println, smbios, smp, speaker, status, thread, time, workqueue,
+};
+use x86_64::VirtAddr;
+
+/// 启动时选择的键盘布局
+const KEYBOARD_LAYOUT: Layout = Layout::Us104;
+/// 启动完成提示音的频率（Hz）
+const BOOT_TONE_HZ: u32 = 880;
+/// 启动完成提示音的时长
+const BOOT_TONE: Duration = Duration::from_millis(100);
+
+// 由 bootloader 调用，并检查入口函数的签名
+entry_point!(kernel_main);
+
+fn kernel_main(boot_info: &'static BootInfo) -> ! {
+    clear!();
+    console::primary().show_cursor();
+    // 内核日志同时输出到串口和 debugcon，便于在宿主机上查看
+    console::enable(Sink::Serial);
+    console::enable(Sink::Debugcon);
     println!("Hello World{}", "!");
+    serial_println!("Hello Host{}", "!");
+
+    ricky_os::init();
+    keyboard::set_layout(KEYBOARD_LAYOUT);
+
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    unsafe { vga_buffer::set_text_mode_80x50(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    memory::install(mapper, frame_allocator);
+    // bootloader 0.9 不传递 RSDP 的地址，只能在 BIOS 区域中搜索
+    acpi::init(None);
+    // 年份的世纪由 FADT 中的世纪寄存器给出
+    time::rtc::init_century();
+    smbios::init();
+    hpet::init();
+    pci::init();
+    // 切换到图形模式后 VGA 文本缓冲区不再显示，主控制台随之改为帧缓冲
+    framebuffer::init();
+    console::init();
+    let has_mouse = mouse::init();
+    thread::init();
+    workqueue::init();
+    smp::init();
+    interrupts::route_through_apic();
+    // 磁盘和网卡驱动在中断可以经 APIC 投递之后才能使用 MSI，磁盘驱动等待时挂起线程
+    block::init();
+    net::init();
+    rand::init();
+    log::info!(
+        "heap initialized at {:#x}, {} KiB",
+        allocator::HEAP_START,
+        allocator::HEAP_SIZE / 1024
+    );
+    vga_buffer::init_scrollback();
+    status::init();
+    println_colored!(Color::LightGreen, Color::Black, "Kernel initialized");
+    speaker::beep(BOOT_TONE_HZ, BOOT_TONE);
+
+    let heap_value = Box::new(41);
+    println!("heap_value at {:p}", heap_value);
+    let vec: Vec<usize> = (0..500).collect();
+    println!("vec sum: {}", vec.iter().sum::<usize>());
 
     #[cfg(test)]
     test_main();
 
-    loop {}
+    let mut executor = Executor::new();
+    task::init_spawner(executor.spawner());
+    executor.spawn(Task::with_priority(
+        keyboard::handle_keypresses(),
+        Priority::InterruptFollowup,
+    ));
+    if has_mouse {
+        executor.spawn(Task::with_priority(
+            mouse::handle_events(),
+            Priority::InterruptFollowup,
+        ));
+    }
+    executor.spawn(Task::with_priority(
+        net::handle_frames(),
+        Priority::InterruptFollowup,
+    ));
+    executor.run()
 }
 
+#[cfg(not(test))]
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
-    println!("{}", info);
-    loop {}
+    ricky_os::panic_screen::show(info)
 }
 
 #[cfg(test)]
-pub fn test_runner(tests: &[&dyn Fn()]) {
-    println!("Running {} tests", tests.len());
-    for test in tests {
-        test();
-    }
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    ricky_os::test_panic_handler(info)
 }
 
 #[test_case]
+#[allow(clippy::eq_op)]
 fn trivial_assertion() {
-    print!("trivial assertion... ");
     assert_eq!(1, 1);
-    println!("[ok]");
 }
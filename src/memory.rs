@@ -0,0 +1,782 @@
+//! 本模块实现了页表访问和物理帧分配
+
+pub mod dma;
+
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use bootloader::bootinfo::{MemoryMap, MemoryRegionType};
+use spin::Mutex;
+use x86_64::structures::paging::mapper::{MapToError, UnmapError};
+use x86_64::structures::paging::{
+    FrameAllocator, FrameDeallocator, Mapper, OffsetPageTable, Page, PageSize, PageTable,
+    PageTableFlags, PhysFrame, Size4KiB, Translate,
+};
+use x86_64::{PhysAddr, VirtAddr};
+
+/// 启动时页表所在的物理地址，由 [`install`] 记录
+static KERNEL_LEVEL_4: AtomicU64 = AtomicU64::new(0);
+
+/// 启动完成后由内核统一管理的页表访问器和帧分配器
+static KERNEL_MEMORY: Mutex<Option<(OffsetPageTable<'static>, BootInfoFrameAllocator)>> =
+    Mutex::new(None);
+
+/// 初始化一个基于物理内存偏移映射的页表访问器
+///
+/// # 参数
+///
+/// - `physical_memory_offset`: 完整物理内存被映射到的虚拟地址偏移
+///
+/// # Safety
+///
+/// 调用者必须保证完整的物理内存已被映射到 `physical_memory_offset` 处，
+/// 并且此函数只能被调用一次，以避免产生多个可变引用
+pub unsafe fn init(physical_memory_offset: VirtAddr) -> OffsetPageTable<'static> {
+    unsafe {
+        let level_4_table = active_level_4_table(physical_memory_offset);
+        OffsetPageTable::new(level_4_table, physical_memory_offset)
+    }
+}
+
+/// 将启动时创建的页表访问器和帧分配器交给内核，之后通过 [`with_kernel_memory`] 使用
+///
+/// # 参数
+///
+/// - `mapper`: [`init`] 返回的页表访问器
+/// - `frame_allocator`: 物理帧分配器
+pub fn install(mut mapper: OffsetPageTable<'static>, mut frame_allocator: BootInfoFrameAllocator) {
+    use x86_64::instructions::interrupts;
+    use x86_64::registers::control::Cr3;
+
+    preallocate_kernel_entries(&mut mapper, &mut frame_allocator);
+    KERNEL_LEVEL_4.store(Cr3::read().0.start_address().as_u64(), Ordering::Relaxed);
+    interrupts::without_interrupts(|| *KERNEL_MEMORY.lock() = Some((mapper, frame_allocator)));
+}
+
+/// 为用户空间之外所有空闲的 4 级表项建立空的 3 级页表
+///
+/// 地址空间创建时复制这些表项并与内核共享下级页表，之后内核新建的映射（MMIO、线程栈等）
+/// 在所有地址空间中都可见
+fn preallocate_kernel_entries(
+    mapper: &mut OffsetPageTable<'static>,
+    frame_allocator: &mut BootInfoFrameAllocator,
+) {
+    let phys_offset = mapper.phys_offset();
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+    for (i, entry) in mapper.level_4_table_mut().iter_mut().enumerate() {
+        if USER_LEVEL_4_ENTRIES.contains(&i) || !entry.is_unused() {
+            continue;
+        }
+        let Some(frame) = frame_allocator.allocate_frame() else {
+            return;
+        };
+        table_at(phys_offset, frame).zero();
+        entry.set_frame(frame, flags);
+    }
+}
+
+/// 内核线程使用的 4 级页表，即启动时的页表
+///
+/// 尚未调用 [`install`] 时返回当前活动的页表
+pub fn kernel_page_table() -> PhysFrame {
+    use x86_64::registers::control::Cr3;
+
+    match KERNEL_LEVEL_4.load(Ordering::Relaxed) {
+        0 => Cr3::read().0,
+        addr => PhysFrame::containing_address(PhysAddr::new(addr)),
+    }
+}
+
+/// 使用内核的页表访问器和帧分配器，期间禁用中断
+///
+/// # 参数
+///
+/// - `f`: 使用页表访问器和帧分配器的闭包
+///
+/// # 返回
+///
+/// 闭包的返回值，尚未调用 [`install`] 时返回 `None`
+pub fn with_kernel_memory<R>(
+    f: impl FnOnce(&mut OffsetPageTable<'static>, &mut BootInfoFrameAllocator) -> R,
+) -> Option<R> {
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+        let mut memory = KERNEL_MEMORY.lock();
+        let (mapper, frame_allocator) = memory.as_mut()?;
+        Some(f(mapper, frame_allocator))
+    })
+}
+
+/// 使用当前活动地址空间（CR3 指向的页表）的页表访问器和内核的帧分配器，期间禁用中断
+///
+/// 用户空间的映射应通过它修改，内核空间的映射由所有地址空间共享，使用 [`with_kernel_memory`]
+///
+/// # 参数
+///
+/// - `f`: 使用页表访问器和帧分配器的闭包
+///
+/// # 返回
+///
+/// 闭包的返回值，尚未调用 [`install`] 时返回 `None`
+pub fn with_active_mapper<R>(
+    f: impl FnOnce(&mut OffsetPageTable<'static>, &mut BootInfoFrameAllocator) -> R,
+) -> Option<R> {
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+        let mut memory = KERNEL_MEMORY.lock();
+        let (kernel_mapper, frame_allocator) = memory.as_mut()?;
+        let phys_offset = kernel_mapper.phys_offset();
+        let mut mapper = unsafe { init(phys_offset) };
+        Some(f(&mut mapper, frame_allocator))
+    })
+}
+
+/// 将物理地址转换为完整物理内存映射中的虚拟地址
+///
+/// # 返回
+///
+/// 尚未调用 [`install`] 时返回 `None`
+pub fn phys_to_virt(addr: PhysAddr) -> Option<VirtAddr> {
+    with_kernel_memory(|mapper, _| mapper.phys_offset() + addr.as_u64())
+}
+
+/// 设备内存（MMIO）映射区域的起始虚拟地址
+const MMIO_START: u64 = 0x_3333_3333_0000;
+
+/// 下一段 MMIO 映射的起始虚拟地址
+static NEXT_MMIO: AtomicU64 = AtomicU64::new(MMIO_START);
+
+/// 将设备寄存器所在的物理地址区间映射到内核空间，页不经过缓存
+///
+/// 映射只增不减，同一段寄存器应只映射一次
+///
+/// # 参数
+///
+/// - `phys`: 区间的起始物理地址
+/// - `size`: 区间的字节数
+///
+/// # 返回
+///
+/// 与 `phys` 对应的虚拟地址，帧已耗尽或尚未调用 [`install`] 时返回 `None`
+pub fn map_mmio(phys: PhysAddr, size: u64) -> Option<VirtAddr> {
+    let first = PhysFrame::<Size4KiB>::containing_address(phys);
+    let last = PhysFrame::<Size4KiB>::containing_address(phys + size.max(1) - 1u64);
+    let frames = PhysFrame::range_inclusive(first, last);
+    let pages = frames.count() as u64;
+    let start = NEXT_MMIO.fetch_add(pages * FRAME_SIZE, Ordering::Relaxed);
+    let flags = PageTableFlags::PRESENT
+        | PageTableFlags::WRITABLE
+        | PageTableFlags::WRITE_THROUGH
+        | PageTableFlags::NO_CACHE
+        | PageTableFlags::NO_EXECUTE;
+    with_kernel_memory(|mapper, frame_allocator| {
+        let first_page = Page::containing_address(VirtAddr::new(start));
+        for (page, frame) in Page::range(first_page, first_page + pages).zip(frames) {
+            unsafe { map_page(mapper, page, frame, flags, frame_allocator) }.ok()?;
+        }
+        Some(VirtAddr::new(start) + phys.as_u64() % FRAME_SIZE)
+    })
+    .flatten()
+}
+
+/// 用户空间在 4 级页表中占用的表项范围
+const USER_LEVEL_4_ENTRIES: core::ops::Range<usize> = {
+    use crate::user::{USER_SPACE_END, USER_SPACE_START};
+
+    (USER_SPACE_START >> 39) as usize..(USER_SPACE_END >> 39) as usize
+};
+
+/// 独立的地址空间，拥有自己的 4 级页表
+///
+/// 用户空间之外的 4 级表项在创建时从当前页表复制，下级页表与内核共享。
+/// 这些表项在 [`install`] 时都已建立，内核之后新建的映射在所有地址空间中都可见
+pub struct AddressSpace {
+    level_4_frame: PhysFrame, // 4 级页表所在的物理帧
+}
+
+impl AddressSpace {
+    /// 创建一个只包含内核映射的地址空间
+    ///
+    /// # 返回
+    ///
+    /// 帧已耗尽或尚未调用 [`install`] 时返回 `None`
+    pub fn new() -> Option<Self> {
+        use x86_64::registers::control::Cr3;
+
+        with_kernel_memory(|mapper, frame_allocator| {
+            let frame = frame_allocator.allocate_frame()?;
+            let phys_offset = mapper.phys_offset();
+            let (active_frame, _) = Cr3::read();
+            let table = table_at(phys_offset, frame);
+            let active = table_at(phys_offset, active_frame);
+            table.zero();
+            for (i, entry) in active.iter().enumerate() {
+                if !USER_LEVEL_4_ENTRIES.contains(&i) {
+                    table[i] = entry.clone();
+                }
+            }
+            Some(Self {
+                level_4_frame: frame,
+            })
+        })
+        .flatten()
+    }
+
+    /// 4 级页表所在的物理帧
+    pub fn level_4_frame(&self) -> PhysFrame {
+        self.level_4_frame
+    }
+
+    /// 是否为当前活动的地址空间
+    pub fn is_active(&self) -> bool {
+        use x86_64::registers::control::Cr3;
+
+        Cr3::read().0 == self.level_4_frame
+    }
+
+    /// 释放用户空间中所有映射的帧和页表，以及 4 级页表本身
+    ///
+    /// 调用之前必须切换到其他地址空间
+    pub fn destroy(self) {
+        assert!(!self.is_active(), "cannot destroy the active address space");
+        with_kernel_memory(|mapper, frame_allocator| {
+            let phys_offset = mapper.phys_offset();
+            let level_4 = table_at(phys_offset, self.level_4_frame);
+            for entry in level_4
+                .iter()
+                .take(USER_LEVEL_4_ENTRIES.end)
+                .skip(USER_LEVEL_4_ENTRIES.start)
+            {
+                if let Ok(frame) = entry.frame() {
+                    unsafe { free_table(phys_offset, frame, 3, frame_allocator) };
+                }
+            }
+            unsafe { frame_allocator.deallocate_frame(self.level_4_frame) };
+        });
+    }
+
+    /// 复制地址空间：内核部分共享，用户空间的页表复制一份，数据帧在两个地址空间之间共享
+    ///
+    /// 两边可写的页都改为只读并标记 [`COPY_ON_WRITE`]，第一次写入时再复制
+    ///
+    /// # 返回
+    ///
+    /// 帧已耗尽时释放已复制的部分并返回 `None`
+    pub fn duplicate(&self) -> Option<Self> {
+        let copy = Self::new()?;
+        let copied = with_kernel_memory(|mapper, frame_allocator| {
+            let phys_offset = mapper.phys_offset();
+            let level_4 = table_at(phys_offset, self.level_4_frame);
+            let target = table_at(phys_offset, copy.level_4_frame);
+            for i in USER_LEVEL_4_ENTRIES {
+                let Ok(frame) = level_4[i].frame() else {
+                    continue;
+                };
+                let table = unsafe { copy_table(phys_offset, frame, 3, frame_allocator) }?;
+                target[i].set_frame(table, level_4[i].flags());
+            }
+            Some(())
+        })
+        .flatten();
+        // 原地址空间中的页可能刚被改为只读
+        if self.is_active() {
+            crate::smp::tlb::flush_all();
+        }
+        match copied {
+            Some(()) => Some(copy),
+            None => {
+                copy.destroy();
+                None
+            }
+        }
+    }
+
+    /// 切换到这个地址空间
+    ///
+    /// # Safety
+    ///
+    /// 调用者必须保证地址空间在活动期间不会被释放
+    pub unsafe fn activate(&self) {
+        use x86_64::registers::control::Cr3;
+
+        let (_, flags) = Cr3::read();
+        unsafe { Cr3::write(self.level_4_frame, flags) };
+    }
+}
+
+/// 释放一张页表引用的所有帧和下级页表，以及页表本身
+///
+/// # 参数
+///
+/// - `physical_memory_offset`: 完整物理内存被映射到的虚拟地址偏移
+/// - `frame`: 页表所在的物理帧
+/// - `level`: 页表的级别，1 级页表的表项直接指向数据帧
+/// - `frame_allocator`: 回收帧的分配器
+///
+/// # Safety
+///
+/// 页表及其引用的帧不能再被任何地址空间使用
+unsafe fn free_table(
+    physical_memory_offset: VirtAddr,
+    frame: PhysFrame,
+    level: u8,
+    frame_allocator: &mut BootInfoFrameAllocator,
+) {
+    let table = table_at(physical_memory_offset, frame);
+    for entry in table.iter() {
+        // 用户空间只使用 4 KiB 页，`frame()` 对大页返回错误，这里会被跳过
+        let Ok(child) = entry.frame() else {
+            continue;
+        };
+        if level > 1 {
+            unsafe { free_table(physical_memory_offset, child, level - 1, frame_allocator) };
+        } else {
+            unsafe { frame_allocator.deallocate_frame(child) };
+        }
+    }
+    unsafe { frame_allocator.deallocate_frame(frame) };
+}
+
+/// 复制一张页表和它的下级页表，1 级页表引用的数据帧由原页表和副本共享
+///
+/// 可写的数据页在两边都改为只读并标记 [`COPY_ON_WRITE`]，带有 [`SHARED`] 标志的页除外
+///
+/// # 参数
+///
+/// - `physical_memory_offset`: 完整物理内存被映射到的虚拟地址偏移
+/// - `frame`: 页表所在的物理帧
+/// - `level`: 页表的级别，1 级页表的表项直接指向数据帧
+/// - `frame_allocator`: 分配新页表、记录帧引用数的分配器
+///
+/// # 返回
+///
+/// 新页表所在的物理帧；帧已耗尽时释放已复制的部分并返回 `None`
+///
+/// # Safety
+///
+/// 复制期间页表不能被修改
+unsafe fn copy_table(
+    physical_memory_offset: VirtAddr,
+    frame: PhysFrame,
+    level: u8,
+    frame_allocator: &mut BootInfoFrameAllocator,
+) -> Option<PhysFrame> {
+    let copy = frame_allocator.allocate_frame()?;
+    let table = table_at(physical_memory_offset, frame);
+    let target = table_at(physical_memory_offset, copy);
+    target.zero();
+    for (i, entry) in table.iter_mut().enumerate() {
+        let Ok(child) = entry.frame() else {
+            continue;
+        };
+        let child_copy = if level > 1 {
+            unsafe { copy_table(physical_memory_offset, child, level - 1, frame_allocator) }
+        } else {
+            let flags = entry.flags();
+            // 共享内存的页在两边继续共享同一个可写的帧
+            if flags.contains(PageTableFlags::WRITABLE) && !flags.contains(SHARED) {
+                entry.set_flags((flags - PageTableFlags::WRITABLE) | COPY_ON_WRITE);
+            }
+            frame_allocator.share(child);
+            Some(child)
+        };
+        let Some(child_copy) = child_copy else {
+            unsafe { free_table(physical_memory_offset, copy, level, frame_allocator) };
+            return None;
+        };
+        target[i].set_frame(child_copy, entry.flags());
+    }
+    Some(copy)
+}
+
+/// 写时复制页的标志，使用页表项中留给操作系统的位
+///
+/// 带有这个标志的页映射为只读，第一次写入时由 [`resolve_copy_on_write`] 换成可写的副本
+pub const COPY_ON_WRITE: PageTableFlags = PageTableFlags::BIT_9;
+
+/// 共享内存页的标志，使用页表项中留给操作系统的位
+///
+/// 带有这个标志的页在复制地址空间时保持可写，父子进程的修改互相可见
+pub const SHARED: PageTableFlags = PageTableFlags::BIT_10;
+
+/// 让当前地址空间中的一个写时复制页变为可写
+///
+/// 帧仍被其他地址空间引用时复制一份，否则直接恢复写权限
+///
+/// # 参数
+///
+/// - `mapper`: 当前地址空间的页表访问器
+/// - `frame_allocator`: 帧分配器
+/// - `page`: 要写入的页
+///
+/// # 返回
+///
+/// 页不是写时复制页时返回 `Ok(false)`，帧已耗尽时返回错误
+pub fn resolve_copy_on_write(
+    mapper: &mut OffsetPageTable,
+    frame_allocator: &mut BootInfoFrameAllocator,
+    page: Page,
+) -> Result<bool, MapToError<Size4KiB>> {
+    use x86_64::structures::paging::mapper::{MappedFrame, TranslateResult};
+
+    let TranslateResult::Mapped {
+        frame: MappedFrame::Size4KiB(frame),
+        flags,
+        ..
+    } = mapper.translate(page.start_address())
+    else {
+        return Ok(false);
+    };
+    if !flags.contains(COPY_ON_WRITE) {
+        return Ok(false);
+    }
+    let writable = (flags - COPY_ON_WRITE) | PageTableFlags::WRITABLE;
+    if frame_allocator.references(frame) == 1 {
+        // 其他地址空间已经不再引用这个帧
+        unsafe { mapper.update_flags(page, writable) }
+            .map_err(|_| MapToError::ParentEntryHugePage)?
+            .flush();
+        return Ok(true);
+    }
+    let copy = frame_allocator
+        .allocate_frame()
+        .ok_or(MapToError::FrameAllocationFailed)?;
+    let phys_offset = mapper.phys_offset();
+    let from = (phys_offset + frame.start_address().as_u64()).as_ptr::<u8>();
+    let to = (phys_offset + copy.start_address().as_u64()).as_mut_ptr::<u8>();
+    unsafe { core::ptr::copy_nonoverlapping(from, to, Size4KiB::SIZE as usize) };
+    // 上级页表都已存在，重新映射不会失败
+    let (_, flush) = mapper.unmap(page).expect("copy-on-write page is mapped");
+    flush.ignore();
+    unsafe { mapper.map_to(page, copy, writable, frame_allocator) }
+        .expect("parent tables of a mapped page exist")
+        .ignore();
+    // 运行同一地址空间的其他处理器可能仍缓存着原帧
+    crate::smp::tlb::flush(page);
+    // 释放当前地址空间对原帧的引用
+    unsafe { frame_allocator.deallocate_frame(frame) };
+    Ok(true)
+}
+
+/// 通过物理内存映射访问位于 `frame` 的页表
+///
+/// # 参数
+///
+/// - `physical_memory_offset`: 完整物理内存被映射到的虚拟地址偏移
+/// - `frame`: 页表所在的物理帧
+fn table_at(physical_memory_offset: VirtAddr, frame: PhysFrame) -> &'static mut PageTable {
+    let virt = physical_memory_offset + frame.start_address().as_u64();
+    unsafe { &mut *virt.as_mut_ptr::<PageTable>() }
+}
+
+/// 获取当前活动的 4 级页表的可变引用
+///
+/// # Safety
+///
+/// 同 [`init`]
+unsafe fn active_level_4_table(physical_memory_offset: VirtAddr) -> &'static mut PageTable {
+    use x86_64::registers::control::Cr3;
+
+    let (level_4_table_frame, _) = Cr3::read();
+
+    let phys = level_4_table_frame.start_address();
+    let virt = physical_memory_offset + phys.as_u64();
+    let page_table_ptr: *mut PageTable = virt.as_mut_ptr();
+
+    unsafe { &mut *page_table_ptr }
+}
+
+/// 将虚拟页映射到指定的物理帧，并刷新 TLB
+///
+/// 缺少的中间页表会通过 `frame_allocator` 分配
+///
+/// # 参数
+///
+/// - `mapper`: 页表访问器
+/// - `page`: 要映射的虚拟页
+/// - `frame`: 目标物理帧
+/// - `flags`: 页表项标志
+/// - `frame_allocator`: 用于分配中间页表的帧分配器
+///
+/// # Safety
+///
+/// 调用者必须保证 `frame` 没有被其他映射以违反别名规则的方式使用
+pub unsafe fn map_page(
+    mapper: &mut OffsetPageTable,
+    page: Page,
+    frame: PhysFrame,
+    flags: PageTableFlags,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+) -> Result<(), MapToError<Size4KiB>> {
+    unsafe { mapper.map_to(page, frame, flags, frame_allocator)?.flush() };
+    Ok(())
+}
+
+/// 解除虚拟页的映射，并刷新所有处理器的 TLB
+///
+/// # 参数
+///
+/// - `mapper`: 页表访问器
+/// - `page`: 要解除映射的虚拟页
+///
+/// # 返回
+///
+/// 原先映射到的物理帧，由调用者决定是否回收
+pub fn unmap_page(mapper: &mut OffsetPageTable, page: Page) -> Result<PhysFrame, UnmapError> {
+    let (frame, flush) = mapper.unmap(page)?;
+    flush.ignore();
+    crate::smp::tlb::flush(page);
+    Ok(frame)
+}
+
+/// 将虚拟地址转换为对应的物理地址
+///
+/// # 参数
+///
+/// - `mapper`: 页表访问器
+/// - `addr`: 要转换的虚拟地址
+///
+/// # 返回
+///
+/// 地址未被映射时返回 `None`
+pub fn translate_addr(mapper: &OffsetPageTable, addr: VirtAddr) -> Option<PhysAddr> {
+    mapper.translate_addr(addr)
+}
+
+/// 物理帧大小（4 KiB）
+const FRAME_SIZE: u64 = 4096;
+
+/// 低端内存的上界，实模式下只能访问这之下的地址
+const LOW_MEMORY_END: u64 = 0x10_0000;
+
+/// 从 bootloader 提供的内存映射中分配可用物理帧的分配器
+///
+/// 内存映射中的区域按起始地址升序排列，分配器依次遍历可用区域，
+/// 只需记录当前区域和下一个帧的地址，每次分配都是 O(1) 的。
+/// 释放的帧放入空闲列表，优先被再次分配；空闲列表在堆上，堆初始化之前不能释放帧。
+///
+/// 帧可以被多个地址空间共享（写时复制），被共享的帧记录引用数，
+/// 释放只减少引用数，最后一个引用释放时帧才回到空闲列表。
+///
+/// 创建时预留 1 MiB 以下的第一个可用帧，留给应用处理器的实模式启动代码。
+///
+/// 单个帧从低地址向上分配，供设备 DMA 使用的连续帧从最高的可用区域向下分配，两者在中间相遇
+pub struct BootInfoFrameAllocator {
+    memory_map: &'static MemoryMap,
+    region_index: usize,                // 当前正在分配的区域下标
+    next_addr: u64,                     // 下一个待分配帧的物理地址
+    contiguous_floor: u64,              // 连续帧已分配到的最低物理地址，此后的帧不再逐个分配
+    allocated: usize,                   // 正在使用的帧数
+    free: Vec<PhysFrame>,               // 已释放、可以再次分配的帧
+    shared: BTreeMap<PhysFrame, usize>, // 引用数大于 1 的帧及其引用数
+    low_frame: Option<PhysFrame>,       // 预留的 1 MiB 以下的帧
+}
+
+impl BootInfoFrameAllocator {
+    /// 使用内存映射创建帧分配器
+    ///
+    /// # 参数
+    ///
+    /// - `memory_map`: bootloader 提供的内存映射
+    ///
+    /// # Safety
+    ///
+    /// 调用者必须保证内存映射是有效的，其中标记为可用的帧确实未被使用
+    pub unsafe fn init(memory_map: &'static MemoryMap) -> Self {
+        let mut allocator = Self {
+            memory_map,
+            region_index: 0,
+            next_addr: 0,
+            contiguous_floor: u64::MAX,
+            allocated: 0,
+            free: Vec::new(),
+            shared: BTreeMap::new(),
+            low_frame: None,
+        };
+        // 区域按地址升序排列，第一个可用区域的第一个帧若位于低端内存，便跳过它留作预留帧
+        if let Some(index) = memory_map
+            .iter()
+            .position(|r| r.region_type == MemoryRegionType::Usable)
+        {
+            let region = &memory_map[index];
+            let addr = region.range.start_addr().max(FRAME_SIZE);
+            if addr + FRAME_SIZE <= region.range.end_addr().min(LOW_MEMORY_END) {
+                allocator.region_index = index;
+                allocator.next_addr = addr + FRAME_SIZE;
+                allocator.low_frame = Some(PhysFrame::containing_address(PhysAddr::new(addr)));
+            }
+        }
+        allocator
+    }
+
+    /// 取出预留的 1 MiB 以下的帧，只能取出一次
+    ///
+    /// # 返回
+    ///
+    /// 没有可用的低端内存或已被取出时返回 `None`
+    pub fn take_low_frame(&mut self) -> Option<PhysFrame> {
+        self.low_frame.take()
+    }
+
+    /// 分配物理地址连续的 `count` 个帧
+    ///
+    /// 从最高的、还放得下的可用区域的顶端向下分配，比它更高的区域中剩余的帧不再分配。
+    /// 释放后的帧回到空闲列表，之后只能逐个分配
+    ///
+    /// # 参数
+    ///
+    /// - `count`: 帧数
+    ///
+    /// # 返回
+    ///
+    /// 第一个帧，没有足够大的连续空间时返回 `None`
+    pub fn allocate_contiguous(&mut self, count: usize) -> Option<PhysFrame> {
+        let size = count as u64 * FRAME_SIZE;
+        let start = self
+            .memory_map
+            .iter()
+            .enumerate()
+            .rev()
+            .filter(|(index, region)| {
+                region.region_type == MemoryRegionType::Usable && *index >= self.region_index
+            })
+            .find_map(|(index, region)| {
+                let end = region.range.end_addr().min(self.contiguous_floor);
+                // 当前区域中已经逐个分配出去的帧不能再用
+                let lowest = if index == self.region_index {
+                    self.next_addr.max(region.range.start_addr())
+                } else {
+                    region.range.start_addr()
+                };
+                let start = end.checked_sub(size)?;
+                (start >= lowest).then_some(start)
+            })?;
+        self.contiguous_floor = start;
+        self.allocated += count;
+        Some(PhysFrame::containing_address(PhysAddr::new(start)))
+    }
+
+    /// 分配物理地址连续、整体位于 `limit` 之下的 `count` 个帧，供只能访问 32 位地址的设备使用
+    ///
+    /// 与逐个分配的帧一样从低地址向上分配；跳过的区域中放不下的剩余帧放入空闲列表，不会浪费
+    ///
+    /// # 参数
+    ///
+    /// - `count`: 帧数
+    /// - `limit`: 物理地址的上界（不含）
+    ///
+    /// # 返回
+    ///
+    /// 第一个帧，`limit` 之下没有足够大的连续空间时返回 `None`
+    pub fn allocate_contiguous_below(&mut self, count: usize, limit: u64) -> Option<PhysFrame> {
+        let size = count as u64 * FRAME_SIZE;
+        let (index, start) = self
+            .memory_map
+            .iter()
+            .enumerate()
+            .skip(self.region_index)
+            .filter(|(_, region)| region.region_type == MemoryRegionType::Usable)
+            .take_while(|(_, region)| region.range.start_addr() < limit)
+            .find_map(|(index, region)| {
+                let start = if index == self.region_index {
+                    self.next_addr.max(region.range.start_addr())
+                } else {
+                    region.range.start_addr()
+                };
+                let end = region
+                    .range
+                    .end_addr()
+                    .min(self.contiguous_floor)
+                    .min(limit);
+                (start.checked_add(size)? <= end).then_some((index, start))
+            })?;
+        // 之前的区域中剩余的帧都不够 `count` 个，交给空闲列表逐个分配
+        while self.region_index < index {
+            let region = &self.memory_map[self.region_index];
+            if region.region_type == MemoryRegionType::Usable {
+                let end = region.range.end_addr().min(self.contiguous_floor);
+                let mut addr = self.next_addr.max(region.range.start_addr());
+                while addr < end {
+                    self.free
+                        .push(PhysFrame::containing_address(PhysAddr::new(addr)));
+                    addr += FRAME_SIZE;
+                }
+            }
+            self.region_index += 1;
+        }
+        self.next_addr = start + size;
+        self.allocated += count;
+        Some(PhysFrame::containing_address(PhysAddr::new(start)))
+    }
+
+    /// 内存映射中可用帧的总数
+    pub fn usable_frames(&self) -> usize {
+        self.memory_map
+            .iter()
+            .filter(|r| r.region_type == MemoryRegionType::Usable)
+            .map(|r| ((r.range.end_addr() - r.range.start_addr()) / FRAME_SIZE) as usize)
+            .sum()
+    }
+
+    /// 正在使用的帧数，被共享的帧只计一次
+    pub fn allocated_frames(&self) -> usize {
+        self.allocated
+    }
+
+    /// 增加一个已分配帧的引用数，每次共享都需要对应一次释放
+    ///
+    /// # 参数
+    ///
+    /// - `frame`: 已分配的帧
+    pub fn share(&mut self, frame: PhysFrame) {
+        *self.shared.entry(frame).or_insert(1) += 1;
+    }
+
+    /// 已分配帧的引用数
+    ///
+    /// # 参数
+    ///
+    /// - `frame`: 已分配的帧
+    pub fn references(&self, frame: PhysFrame) -> usize {
+        self.shared.get(&frame).copied().unwrap_or(1)
+    }
+}
+
+unsafe impl FrameAllocator<Size4KiB> for BootInfoFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame> {
+        if let Some(frame) = self.free.pop() {
+            self.allocated += 1;
+            return Some(frame);
+        }
+        while let Some(region) = self.memory_map.get(self.region_index) {
+            if region.region_type == MemoryRegionType::Usable {
+                let addr = self.next_addr.max(region.range.start_addr());
+                if addr < region.range.end_addr().min(self.contiguous_floor) {
+                    self.next_addr = addr + FRAME_SIZE;
+                    self.allocated += 1;
+                    return Some(PhysFrame::containing_address(PhysAddr::new(addr)));
+                }
+            }
+            // 当前区域已耗尽或不可用，移至下一个区域
+            self.region_index += 1;
+        }
+        None
+    }
+}
+
+impl FrameDeallocator<Size4KiB> for BootInfoFrameAllocator {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame) {
+        if let Some(references) = self.shared.get_mut(&frame) {
+            *references -= 1;
+            if *references == 1 {
+                self.shared.remove(&frame);
+            }
+            return;
+        }
+        self.allocated -= 1;
+        self.free.push(frame);
+    }
+}
@@ -0,0 +1,125 @@
+//! 本模块实现了供设备 DMA 使用的物理连续内存
+//!
+//! 设备按物理地址访问内存，描述符环、命令表和数据缓冲区需要物理连续，并且在设备使用期间不能移动。
+//! [`DmaRegion`] 由整页组成并清零，驱动通过完整物理内存映射访问它；x86 的 DMA 与处理器缓存一致，不需要刷新缓存
+
+use core::ptr::NonNull;
+
+use x86_64::structures::paging::{FrameDeallocator, PhysFrame};
+use x86_64::{PhysAddr, VirtAddr};
+
+use super::FRAME_SIZE;
+
+/// 只能访问 32 位地址的设备可以使用的物理地址上界
+pub const DMA32_LIMIT: u64 = 1 << 32;
+
+/// 一段物理连续、按页对齐的 DMA 内存，丢弃时释放
+#[derive(Debug)]
+pub struct DmaRegion {
+    phys: PhysAddr, // 起始物理地址
+    virt: VirtAddr, // 在完整物理内存映射中的虚拟地址
+    frames: usize,  // 占用的帧数
+}
+
+// 区域只由拥有者通过 `&self` 或 `&mut self` 访问，设备看到的是物理地址
+unsafe impl Send for DmaRegion {}
+unsafe impl Sync for DmaRegion {}
+
+impl DmaRegion {
+    /// 分配至少 `size` 字节的 DMA 内存并清零
+    ///
+    /// # 参数
+    ///
+    /// - `size`: 字节数，向上取整到页
+    ///
+    /// # 返回
+    ///
+    /// 没有足够的连续物理内存或尚未调用 [`install`](super::install) 时返回 `None`
+    pub fn new(size: usize) -> Option<Self> {
+        let frames = Self::frames_for(size);
+        let first =
+            super::with_kernel_memory(|_, allocator| allocator.allocate_contiguous(frames))??;
+        Self::from_frames(first, frames)
+    }
+
+    /// 在物理地址 `limit` 之下分配至少 `size` 字节的 DMA 内存并清零，供只能访问 32 位地址的设备使用
+    ///
+    /// # 参数
+    ///
+    /// - `size`: 字节数，向上取整到页
+    /// - `limit`: 区域末尾的物理地址上界（不含）
+    ///
+    /// # 返回
+    ///
+    /// `limit` 之下没有足够的连续物理内存或尚未调用 [`install`](super::install) 时返回 `None`
+    pub fn new_below(size: usize, limit: u64) -> Option<Self> {
+        let frames = Self::frames_for(size);
+        let first = super::with_kernel_memory(|_, allocator| {
+            allocator.allocate_contiguous_below(frames, limit)
+        })??;
+        Self::from_frames(first, frames)
+    }
+
+    /// 容纳 `size` 字节所需的帧数，至少为 1
+    fn frames_for(size: usize) -> usize {
+        (size as u64).div_ceil(FRAME_SIZE).max(1) as usize
+    }
+
+    /// 清零从 `first` 开始的 `frames` 个帧并包装为区域
+    fn from_frames(first: PhysFrame, frames: usize) -> Option<Self> {
+        let phys = first.start_address();
+        let virt = super::phys_to_virt(phys)?;
+        unsafe { core::ptr::write_bytes(virt.as_mut_ptr::<u8>(), 0, frames * FRAME_SIZE as usize) };
+        Some(Self { phys, virt, frames })
+    }
+
+    /// 起始物理地址
+    pub fn phys(&self) -> PhysAddr {
+        self.phys
+    }
+
+    /// 字节数
+    pub fn len(&self) -> usize {
+        self.frames * FRAME_SIZE as usize
+    }
+
+    /// 区域至少有一页，总是返回 `false`
+    pub fn is_empty(&self) -> bool {
+        false
+    }
+
+    /// 区域中 `offset` 处的类型为 `T` 的值的指针
+    ///
+    /// # 参数
+    ///
+    /// - `offset`: 字节偏移，`T` 必须完整地位于区域之内且按 `T` 对齐
+    pub fn ptr<T>(&self, offset: usize) -> NonNull<T> {
+        assert!(
+            offset + size_of::<T>() <= self.len() && offset.is_multiple_of(align_of::<T>()),
+            "bad DMA offset {:#x}",
+            offset
+        );
+        NonNull::new((self.virt + offset as u64).as_mut_ptr()).unwrap()
+    }
+
+    /// 区域的内容
+    pub fn as_slice(&self) -> &[u8] {
+        unsafe { core::slice::from_raw_parts(self.virt.as_ptr(), self.len()) }
+    }
+
+    /// 区域的可变内容
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        unsafe { core::slice::from_raw_parts_mut(self.virt.as_mut_ptr(), self.len()) }
+    }
+}
+
+impl Drop for DmaRegion {
+    fn drop(&mut self) {
+        let first = PhysFrame::containing_address(self.phys);
+        super::with_kernel_memory(|_, allocator| {
+            for frame in PhysFrame::range(first, first + self.frames as u64) {
+                unsafe { allocator.deallocate_frame(frame) };
+            }
+        });
+    }
+}
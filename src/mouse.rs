@@ -0,0 +1,343 @@
+//! 本模块实现了 PS/2 鼠标驱动
+//!
+//! 鼠标接在 i8042 控制器的辅助端口上，启用数据报告之后每次移动或按键变化发送一个 3 字节的数据包：
+//! 第一个字节为按键和符号位，后两个字节为 X、Y 方向的 9 位有符号位移的低 8 位。
+//! 第一个字节的第 3 位恒为 1，丢失字节后据此重新同步。
+//! 鼠标被重新插上或自行复位后发送自检结果 `0xaa 0x00` 并停止报告，此时由工作队列重新启用数据报告。
+//!
+//! 解码在 IRQ12 中完成，创建 [`MouseStream`] 之后解码出的事件放入无锁队列，
+//! 由异步任务 [`handle_events`] 在中断上下文之外更新指针位置
+
+use core::pin::Pin;
+use core::sync::atomic::{AtomicI32, AtomicU8, Ordering};
+use core::task::{Context, Poll};
+
+use conquer_once::spin::OnceCell;
+use crossbeam_queue::ArrayQueue;
+use futures_util::stream::{Stream, StreamExt};
+use futures_util::task::AtomicWaker;
+use spin::Mutex;
+
+use crate::i8042::{self, CONFIG_AUX_INTERRUPT, RESPONSE_ACK, RESPONSE_SELF_TEST_PASSED};
+use crate::pic;
+use crate::workqueue::Work;
+
+/// 鼠标命令：恢复默认设置，数据报告保持关闭
+const MOUSE_SET_DEFAULTS: u8 = 0xf6;
+/// 鼠标命令：启用数据报告（流模式）
+const MOUSE_ENABLE_REPORTING: u8 = 0xf4;
+/// 鼠标中断的 ISA 中断号
+pub const MOUSE_IRQ: u8 = 12;
+
+/// 数据包第一个字节：左键
+const PACKET_LEFT: u8 = 1 << 0;
+/// 数据包第一个字节：右键
+const PACKET_RIGHT: u8 = 1 << 1;
+/// 数据包第一个字节：中键
+const PACKET_MIDDLE: u8 = 1 << 2;
+/// 数据包第一个字节：恒为 1
+const PACKET_ALWAYS_ONE: u8 = 1 << 3;
+/// 数据包第一个字节：X 位移的符号位
+const PACKET_X_SIGN: u8 = 1 << 4;
+/// 数据包第一个字节：Y 位移的符号位
+const PACKET_Y_SIGN: u8 = 1 << 5;
+/// 数据包第一个字节：X 或 Y 位移溢出
+const PACKET_OVERFLOW: u8 = 0b11 << 6;
+
+/// 鼠标按键的状态
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Buttons {
+    pub left: bool,   // 左键是否按下
+    pub right: bool,  // 右键是否按下
+    pub middle: bool, // 中键是否按下
+}
+
+impl Buttons {
+    /// 由数据包第一个字节中的按键位构造
+    fn from_flags(flags: u8) -> Self {
+        Self {
+            left: flags & PACKET_LEFT != 0,
+            right: flags & PACKET_RIGHT != 0,
+            middle: flags & PACKET_MIDDLE != 0,
+        }
+    }
+
+    /// 按数据包第一个字节的格式编码
+    fn flags(self) -> u8 {
+        [
+            (self.left, PACKET_LEFT),
+            (self.right, PACKET_RIGHT),
+            (self.middle, PACKET_MIDDLE),
+        ]
+        .into_iter()
+        .filter(|&(pressed, _)| pressed)
+        .fold(0, |flags, (_, bit)| flags | bit)
+    }
+}
+
+/// 一个鼠标数据包描述的事件
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MouseEvent {
+    pub dx: i16,          // X 方向的位移，向右为正
+    pub dy: i16,          // Y 方向的位移，向上为正
+    pub buttons: Buttons, // 此时按键的状态
+}
+
+/// 解码器根据收到的字节得出的结果
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Decoded {
+    Event(MouseEvent), // 一个完整的数据包
+    Reset,             // 鼠标发送了复位后的自检结果，需要重新启用数据报告
+}
+
+/// 数据包解码器
+struct PacketDecoder {
+    bytes: [u8; 3],     // 已收到的字节
+    len: usize,         // 已收到的字节数
+    awaiting_ack: bool, // 是否在等待重新启用数据报告的应答
+}
+
+impl PacketDecoder {
+    const fn new() -> Self {
+        Self {
+            bytes: [0; 3],
+            len: 0,
+            awaiting_ack: false,
+        }
+    }
+
+    /// 加入一个字节
+    ///
+    /// # 返回
+    ///
+    /// 字节完成了一个数据包或一次复位时返回结果，位移溢出的数据包被丢弃
+    fn add_byte(&mut self, byte: u8) -> Option<Decoded> {
+        if self.awaiting_ack && byte == RESPONSE_ACK {
+            self.awaiting_ack = false;
+            return None;
+        }
+        // 第一个字节的第 3 位不为 1 时说明丢失了字节，丢弃直到重新对齐
+        if self.len == 0 && byte & PACKET_ALWAYS_ONE == 0 {
+            return None;
+        }
+        // 以 0xaa 开头的数据包一定溢出了，因此 0xaa 0x00 只可能是自检结果
+        if self.len == 1 && self.bytes[0] == RESPONSE_SELF_TEST_PASSED && byte == 0 {
+            self.len = 0;
+            self.awaiting_ack = true;
+            return Some(Decoded::Reset);
+        }
+        self.bytes[self.len] = byte;
+        self.len += 1;
+        if self.len < self.bytes.len() {
+            return None;
+        }
+        self.len = 0;
+        let [flags, x, y] = self.bytes;
+        if flags & PACKET_OVERFLOW != 0 {
+            return None;
+        }
+        // 9 位有符号数：符号位在第一个字节中
+        let extend = |low: u8, negative: bool| i16::from(low) - if negative { 0x100 } else { 0 };
+        Some(Decoded::Event(MouseEvent {
+            dx: extend(x, flags & PACKET_X_SIGN != 0),
+            dy: extend(y, flags & PACKET_Y_SIGN != 0),
+            buttons: Buttons::from_flags(flags),
+        }))
+    }
+}
+
+static DECODER: Mutex<PacketDecoder> = Mutex::new(PacketDecoder::new());
+
+/// 启用鼠标的数据报告，并打开辅助端口的中断和 IRQ12
+///
+/// 需要在 [`i8042::init`] 之后调用
+///
+/// # 返回
+///
+/// 辅助端口上没有设备或设备没有应答时返回 `false`
+pub fn init() -> bool {
+    use x86_64::instructions::interrupts;
+
+    if !i8042::has_aux() {
+        return false;
+    }
+    // 辅助端口的中断此时仍然关闭，应答由这里轮询读取
+    let enabled = interrupts::without_interrupts(|| {
+        if !i8042::send_aux(MOUSE_SET_DEFAULTS) || !i8042::send_aux(MOUSE_ENABLE_REPORTING) {
+            return false;
+        }
+        *DECODER.lock() = PacketDecoder::new();
+        i8042::update_config(|config| config | CONFIG_AUX_INTERRUPT)
+    });
+    if !enabled {
+        log::warn!("PS/2 mouse not responding");
+        return false;
+    }
+    pic::unmask(MOUSE_IRQ);
+    log::info!("PS/2 mouse enabled");
+    true
+}
+
+/// 鼠标复位后重新启用数据报告的工作项，写命令需要等待控制器，不在中断中进行
+static REENABLE_REPORTING: Work = Work::new(reenable_reporting);
+
+/// 重新启用数据报告，只写入命令，应答在下一次中断中由解码器丢弃
+fn reenable_reporting() {
+    log::info!("PS/2 mouse reset; re-enabling reporting");
+    i8042::write_aux(MOUSE_ENABLE_REPORTING);
+}
+
+/// 解码从数据端口读出的一个字节，由鼠标中断调用，不会阻塞或分配内存
+///
+/// # 参数
+///
+/// - `byte`: 鼠标发来的字节
+pub(crate) fn add_byte(byte: u8) {
+    let event = match DECODER.lock().add_byte(byte) {
+        Some(Decoded::Event(event)) => event,
+        Some(Decoded::Reset) => {
+            REENABLE_REPORTING.schedule();
+            return;
+        }
+        None => return,
+    };
+    // 没有消费者时丢弃事件，只保持解码器与数据包对齐
+    let Ok(queue) = EVENT_QUEUE.try_get() else {
+        return;
+    };
+    if queue.push(event).is_err() {
+        log::warn!("mouse event queue full; dropping mouse input");
+    } else {
+        WAKER.wake();
+    }
+}
+
+/// 事件队列的容量
+const EVENT_QUEUE_SIZE: usize = 64;
+
+/// 鼠标中断与异步任务之间的事件队列，创建 [`MouseStream`] 时初始化
+static EVENT_QUEUE: OnceCell<ArrayQueue<MouseEvent>> = OnceCell::uninit();
+/// 等待事件的任务
+static WAKER: AtomicWaker = AtomicWaker::new();
+
+/// 异步的鼠标事件流，只能创建一个
+pub struct MouseStream {
+    _private: (),
+}
+
+impl MouseStream {
+    /// 创建事件流并初始化队列，必须在堆初始化之后调用
+    pub fn new() -> Self {
+        EVENT_QUEUE
+            .try_init_once(|| ArrayQueue::new(EVENT_QUEUE_SIZE))
+            .expect("MouseStream::new should only be called once");
+        Self { _private: () }
+    }
+}
+
+impl Default for MouseStream {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Stream for MouseStream {
+    type Item = MouseEvent;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<MouseEvent>> {
+        let queue = EVENT_QUEUE
+            .try_get()
+            .expect("mouse event queue not initialized");
+
+        if let Some(event) = queue.pop() {
+            return Poll::Ready(Some(event));
+        }
+
+        WAKER.register(cx.waker());
+        // 注册之后再检查一次，避免错过在此期间到达的事件
+        match queue.pop() {
+            Some(event) => {
+                WAKER.take();
+                Poll::Ready(Some(event))
+            }
+            None => Poll::Pending,
+        }
+    }
+}
+
+/// 指针的 X 坐标
+static POINTER_X: AtomicI32 = AtomicI32::new(0);
+/// 指针的 Y 坐标，向下为正
+static POINTER_Y: AtomicI32 = AtomicI32::new(0);
+/// 按键的状态，各位与数据包第一个字节相同
+static BUTTONS: AtomicU8 = AtomicU8::new(0);
+
+/// 指针的位置，从启动时的 `(0, 0)` 开始累计，Y 轴向下为正
+pub fn position() -> (i32, i32) {
+    (
+        POINTER_X.load(Ordering::Relaxed),
+        POINTER_Y.load(Ordering::Relaxed),
+    )
+}
+
+/// 最近一次事件中按键的状态
+pub fn buttons() -> Buttons {
+    Buttons::from_flags(BUTTONS.load(Ordering::Relaxed))
+}
+
+/// 持续等待鼠标事件并更新指针位置和按键状态的异步任务
+pub async fn handle_events() {
+    let mut events = MouseStream::new();
+    while let Some(event) = events.next().await {
+        POINTER_X.fetch_add(i32::from(event.dx), Ordering::Relaxed);
+        POINTER_Y.fetch_sub(i32::from(event.dy), Ordering::Relaxed);
+        BUTTONS.store(event.buttons.flags(), Ordering::Relaxed);
+    }
+}
+
+#[test_case]
+fn test_decode_packets() {
+    let mut decoder = PacketDecoder::new();
+    // 左键按下，向右 5、向下 3
+    assert_eq!(decoder.add_byte(0x29), None);
+    assert_eq!(decoder.add_byte(5), None);
+    assert_eq!(
+        decoder.add_byte(0xfd),
+        Some(Decoded::Event(MouseEvent {
+            dx: 5,
+            dy: -3,
+            buttons: Buttons {
+                left: true,
+                ..Buttons::default()
+            },
+        }))
+    );
+    // 第 3 位为 0 的字节不能作为数据包的开头
+    assert_eq!(decoder.add_byte(0x00), None);
+    assert_eq!(decoder.add_byte(0x18), None);
+    assert_eq!(decoder.add_byte(0x80), None);
+    assert_eq!(
+        decoder.add_byte(0x01),
+        Some(Decoded::Event(MouseEvent {
+            dx: -128,
+            dy: 1,
+            buttons: Buttons::default(),
+        }))
+    );
+    // 溢出的数据包被丢弃
+    for byte in [0x48, 0xff, 0xff] {
+        assert_eq!(decoder.add_byte(byte), None);
+    }
+}
+
+#[test_case]
+fn test_decode_hot_reset() {
+    let mut decoder = PacketDecoder::new();
+    assert_eq!(decoder.add_byte(0xaa), None);
+    assert_eq!(decoder.add_byte(0x00), Some(Decoded::Reset));
+    // 重新启用数据报告的应答不是数据包的开头
+    assert_eq!(decoder.add_byte(RESPONSE_ACK), None);
+    assert_eq!(decoder.add_byte(0x08), None);
+    assert_eq!(decoder.add_byte(0x01), None);
+    assert!(matches!(decoder.add_byte(0x00), Some(Decoded::Event(_))));
+}
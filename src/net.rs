@@ -0,0 +1,266 @@
+//! 本模块实现了网络设备的公共接口和设备表
+//!
+//! 网卡驱动实现 [`NetDevice`]，发送以太网帧；收到的帧经 [`deliver`] 放入一个异步通道，
+//! 由网络协议栈通过 [`take_receiver`] 取得通道的接收端后处理。协议栈接管之前由 [`handle_frames`] 统计并丢弃。
+//!
+//! 驱动的中断处理函数只调度工作项，在工作线程中收取帧；设备没有可用的中断时由定时器定期调度
+
+pub mod e1000;
+pub mod rtl8139;
+pub mod virtio;
+
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::fmt;
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use core::time::Duration;
+
+use conquer_once::spin::OnceCell;
+use futures_util::StreamExt;
+use spin::Mutex;
+
+use crate::sync::mpsc::{self, Receiver, Sender, TrySendError};
+use crate::time;
+use crate::workqueue::Work;
+
+/// 以太网帧的最大长度，不含帧校验序列
+pub const MAX_FRAME_SIZE: usize = 1514;
+/// 以太网帧头的长度
+pub const ETHERNET_HEADER_SIZE: usize = 14;
+
+/// 接收通道最多缓存的帧数
+const RX_QUEUE_SIZE: usize = 256;
+/// 没有中断时轮询设备的间隔
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// 以太网 MAC 地址
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct MacAddress(pub [u8; 6]);
+
+impl MacAddress {
+    /// 广播地址
+    pub const BROADCAST: MacAddress = MacAddress([0xff; 6]);
+}
+
+impl fmt::Display for MacAddress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let [a, b, c, d, e, g] = self.0;
+        write!(
+            f,
+            "{:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}",
+            a, b, c, d, e, g
+        )
+    }
+}
+
+/// 网络设备发送时的错误
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NetError {
+    TooLarge,  // 帧超过了 [`MAX_FRAME_SIZE`] 或短于以太网帧头
+    QueueFull, // 发送队列已满，稍后重试
+    LinkDown,  // 链路未连接
+}
+
+impl fmt::Display for NetError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NetError::TooLarge => write!(f, "frame size out of range"),
+            NetError::QueueFull => write!(f, "transmit queue full"),
+            NetError::LinkDown => write!(f, "link down"),
+        }
+    }
+}
+
+/// 网络设备
+pub trait NetDevice: Send + Sync {
+    /// 设备名，例如 `eth0`
+    fn name(&self) -> &str;
+
+    /// 设备的 MAC 地址
+    fn mac_address(&self) -> MacAddress;
+
+    /// 链路是否已连接，不能报告链路状态的设备总是返回 `true`
+    fn link_up(&self) -> bool {
+        true
+    }
+
+    /// 发送一个以太网帧，帧在返回之前已复制到设备的缓冲区
+    ///
+    /// # 参数
+    ///
+    /// - `frame`: 包含帧头、不含帧校验序列的以太网帧
+    fn transmit(&self, frame: &[u8]) -> Result<(), NetError>;
+}
+
+/// 检查待发送的帧的长度
+///
+/// # 参数
+///
+/// - `frame`: 以太网帧
+pub fn check_frame(frame: &[u8]) -> Result<(), NetError> {
+    if (ETHERNET_HEADER_SIZE..=MAX_FRAME_SIZE).contains(&frame.len()) {
+        Ok(())
+    } else {
+        Err(NetError::TooLarge)
+    }
+}
+
+/// 收到的以太网帧
+pub struct Frame {
+    pub device: Arc<dyn NetDevice>, // 收到帧的设备
+    pub data: Vec<u8>,              // 包含帧头、不含帧校验序列的帧
+}
+
+impl Frame {
+    /// 以太网帧头中的上层协议类型
+    pub fn ethertype(&self) -> u16 {
+        ethertype(&self.data)
+    }
+}
+
+/// 以太网帧头中的上层协议类型，帧太短时返回 0
+fn ethertype(data: &[u8]) -> u16 {
+    match data.get(12..14) {
+        Some(&[high, low]) => u16::from_be_bytes([high, low]),
+        _ => 0,
+    }
+}
+
+/// 已找到的网络设备
+static DEVICES: Mutex<Vec<Arc<dyn NetDevice>>> = Mutex::new(Vec::new());
+
+/// 接收通道的发送端，由驱动共用
+static RX_SENDER: OnceCell<Sender<Frame>> = OnceCell::uninit();
+/// 接收通道的接收端，由协议栈取走
+static RX_RECEIVER: Mutex<Option<Receiver<Frame>>> = Mutex::new(None);
+/// 因通道已满或尚未初始化而丢弃的帧数
+static DROPPED: AtomicU64 = AtomicU64::new(0);
+
+/// 登记网络设备
+///
+/// # 参数
+///
+/// - `device`: 网络设备，名称应在设备表中唯一
+pub fn register(device: Arc<dyn NetDevice>) {
+    log::info!(
+        "{}: MAC {}, link {}",
+        device.name(),
+        device.mac_address(),
+        if device.link_up() { "up" } else { "down" }
+    );
+    DEVICES.lock().push(device);
+}
+
+/// 所有已登记的网络设备
+pub fn devices() -> Vec<Arc<dyn NetDevice>> {
+    DEVICES.lock().clone()
+}
+
+/// 按名称查找网络设备
+///
+/// # 参数
+///
+/// - `name`: 设备名
+pub fn find(name: &str) -> Option<Arc<dyn NetDevice>> {
+    DEVICES
+        .lock()
+        .iter()
+        .find(|device| device.name() == name)
+        .cloned()
+}
+
+/// 为新找到的网卡分配设备名，所有驱动的网卡按找到的顺序编号
+pub(crate) fn next_name() -> String {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    format!("eth{}", NEXT.fetch_add(1, Ordering::Relaxed))
+}
+
+/// 把收到的帧交给协议栈，通道已满时丢弃
+///
+/// # 参数
+///
+/// - `device`: 收到帧的设备
+/// - `data`: 包含帧头、不含帧校验序列的帧
+pub fn deliver(device: Arc<dyn NetDevice>, data: Vec<u8>) {
+    let Ok(sender) = RX_SENDER.try_get() else {
+        DROPPED.fetch_add(1, Ordering::Relaxed);
+        return;
+    };
+    match sender.try_send(Frame { device, data }) {
+        Ok(()) => {}
+        Err(TrySendError::Full(_)) | Err(TrySendError::Closed(_)) => {
+            DROPPED.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// 因通道已满而丢弃的帧数
+pub fn dropped_frames() -> u64 {
+    DROPPED.load(Ordering::Relaxed)
+}
+
+/// 取走接收通道的接收端，只有第一次调用返回 `Some`
+pub fn take_receiver() -> Option<Receiver<Frame>> {
+    RX_RECEIVER.lock().take()
+}
+
+/// 每隔 [`POLL_INTERVAL`] 调度一次 `work`，用于没有可用中断的设备
+///
+/// # 参数
+///
+/// - `work`: 收取设备上的帧的工作项
+pub(crate) fn poll_periodically(work: &'static Work) {
+    time::call_after(time::duration_to_ticks(POLL_INTERVAL).max(1), move || {
+        work.schedule();
+        poll_periodically(work);
+    });
+}
+
+/// 协议栈接管之前处理收到的帧的异步任务，只记录帧的来源和协议类型
+pub async fn handle_frames() {
+    let Some(mut frames) = take_receiver() else {
+        return;
+    };
+    while let Some(frame) = frames.next().await {
+        log::trace!(
+            "{}: {} byte frame, ethertype {:#06x}",
+            frame.device.name(),
+            frame.data.len(),
+            frame.ethertype()
+        );
+    }
+}
+
+/// 创建接收通道，探测所有网卡并登记找到的设备
+///
+/// 驱动需要工作队列和 APIC 投递的中断，应在 [`workqueue::init`](crate::workqueue::init)
+/// 和中断改由 APIC 投递之后调用
+pub fn init() {
+    RX_SENDER.init_once(|| {
+        let (sender, receiver) = mpsc::channel(RX_QUEUE_SIZE);
+        *RX_RECEIVER.lock() = Some(receiver);
+        sender
+    });
+    virtio::init();
+    e1000::init();
+    rtl8139::init();
+}
+
+#[test_case]
+fn test_frame_header() {
+    use core::fmt::Write;
+
+    let mac = MacAddress([0x52, 0x54, 0x00, 0x12, 0x34, 0x56]);
+    let mut text = crate::TestBuffer::<32>::new();
+    write!(text, "{}", mac).unwrap();
+    assert_eq!(text.as_str(), "52:54:00:12:34:56");
+    let mut frame = [0u8; 60];
+    frame[12..14].copy_from_slice(&0x0806u16.to_be_bytes());
+    assert_eq!(ethertype(&frame), 0x0806);
+    assert_eq!(ethertype(&frame[..13]), 0);
+    assert_eq!(check_frame(&frame), Ok(()));
+    assert_eq!(check_frame(&[0; 10]), Err(NetError::TooLarge));
+    assert_eq!(check_frame(&[0; 1515]), Err(NetError::TooLarge));
+}
@@ -0,0 +1,510 @@
+//! 本模块实现了 Intel e1000 和 e1000e 网卡的驱动
+//!
+//! 网卡的寄存器位于 BAR0 指向的内存中。接收和发送各用一个描述符环，环和缓冲区都在 DMA 内存中：
+//! 驱动把空闲的接收描述符交给网卡，网卡写入帧后在描述符中置位 DD；
+//! 发送时驱动填好描述符后移动尾指针，网卡发送完成后同样置位 DD。
+//!
+//! MAC 地址从 EEPROM 读取，EEPROM 不可用时使用固件写入接收地址寄存器的地址。
+//! 中断处理函数读取并清除中断原因后调度工作项，在工作线程中收取帧并报告链路状态的变化
+
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, Ordering, fence};
+use core::time::Duration;
+
+use x86_64::{PhysAddr, VirtAddr};
+
+use super::{MacAddress, NetDevice, NetError};
+use crate::memory::{self, dma::DmaRegion};
+use crate::pci::{self, Bar, Device, msi};
+use crate::sync::SpinLock;
+use crate::time::Instant;
+use crate::workqueue::Work;
+
+/// Intel 的 PCI 厂商号
+const VENDOR_INTEL: u16 = 0x8086;
+/// 支持的设备：82540EM（QEMU 的 e1000）、82545EM 和 82574L（QEMU 的 e1000e）
+const DEVICES_SUPPORTED: [(u16, Variant); 3] = [
+    (0x100e, Variant::E1000),
+    (0x100f, Variant::E1000),
+    (0x10d3, Variant::E1000e),
+];
+
+/// 寄存器：设备控制
+const REG_CTRL: u64 = 0x0000;
+/// 寄存器：设备状态
+const REG_STATUS: u64 = 0x0008;
+/// 寄存器：EEPROM 读
+const REG_EERD: u64 = 0x0014;
+/// 寄存器：中断原因，读取时清除
+const REG_ICR: u64 = 0x00c0;
+/// 寄存器：置位中断屏蔽
+const REG_IMS: u64 = 0x00d0;
+/// 寄存器：清除中断屏蔽
+const REG_IMC: u64 = 0x00d8;
+/// 寄存器：接收控制
+const REG_RCTL: u64 = 0x0100;
+/// 寄存器：发送控制
+const REG_TCTL: u64 = 0x0400;
+/// 寄存器：发送包间隔
+const REG_TIPG: u64 = 0x0410;
+/// 寄存器：接收描述符环的地址低 32 位
+const REG_RDBAL: u64 = 0x2800;
+/// 寄存器：接收描述符环的地址高 32 位
+const REG_RDBAH: u64 = 0x2804;
+/// 寄存器：接收描述符环的字节数
+const REG_RDLEN: u64 = 0x2808;
+/// 寄存器：接收描述符环的头
+const REG_RDH: u64 = 0x2810;
+/// 寄存器：接收描述符环的尾
+const REG_RDT: u64 = 0x2818;
+/// 寄存器：发送描述符环的地址低 32 位
+const REG_TDBAL: u64 = 0x3800;
+/// 寄存器：发送描述符环的地址高 32 位
+const REG_TDBAH: u64 = 0x3804;
+/// 寄存器：发送描述符环的字节数
+const REG_TDLEN: u64 = 0x3808;
+/// 寄存器：发送描述符环的头
+const REG_TDH: u64 = 0x3810;
+/// 寄存器：发送描述符环的尾
+const REG_TDT: u64 = 0x3818;
+/// 寄存器：组播表，共 128 项
+const REG_MTA: u64 = 0x5200;
+/// 寄存器：接收地址 0 的低 32 位
+const REG_RAL: u64 = 0x5400;
+/// 寄存器：接收地址 0 的高 16 位和有效位
+const REG_RAH: u64 = 0x5404;
+
+/// 设备控制：建立链路
+const CTRL_SLU: u32 = 1 << 6;
+/// 设备控制：复位
+const CTRL_RST: u32 = 1 << 26;
+/// 设备状态：链路已连接
+const STATUS_LU: u32 = 1 << 1;
+/// EEPROM 读：开始
+const EERD_START: u32 = 1 << 0;
+/// 接收地址：地址有效
+const RAH_AV: u32 = 1 << 31;
+
+/// 中断：发送描述符已写回
+const INT_TXDW: u32 = 1 << 0;
+/// 中断：链路状态改变
+const INT_LSC: u32 = 1 << 2;
+/// 中断：空闲的接收描述符低于阈值
+const INT_RXDMT0: u32 = 1 << 4;
+/// 中断：接收溢出
+const INT_RXO: u32 = 1 << 6;
+/// 中断：接收定时器到期，即收到了帧
+const INT_RXT0: u32 = 1 << 7;
+
+/// 接收控制：启用
+const RCTL_EN: u32 = 1 << 1;
+/// 接收控制：接收广播
+const RCTL_BAM: u32 = 1 << 15;
+/// 接收控制：去掉帧校验序列，缓冲区大小的编码为 0，即 2048 字节
+const RCTL_SECRC: u32 = 1 << 26;
+/// 发送控制：启用
+const TCTL_EN: u32 = 1 << 1;
+/// 发送控制：填充短包
+const TCTL_PSP: u32 = 1 << 3;
+/// 发送控制：冲突阈值
+const TCTL_CT: u32 = 0x0f << 4;
+/// 发送控制：全双工的冲突距离
+const TCTL_COLD: u32 = 0x40 << 12;
+/// IEEE 802.3 推荐的发送包间隔
+const TIPG_DEFAULT: u32 = 0x0060_200a;
+
+/// 描述符状态：网卡已处理完
+const DESC_DD: u8 = 1 << 0;
+/// 描述符状态和发送命令：帧的最后一个描述符
+const DESC_EOP: u8 = 1 << 0;
+/// 发送命令：由网卡插入帧校验序列
+const CMD_IFCS: u8 = 1 << 1;
+/// 发送命令：处理完之后报告状态
+const CMD_RS: u8 = 1 << 3;
+
+/// 描述符的大小
+const DESCRIPTOR_SIZE: usize = 16;
+/// 每个环的描述符数，环的字节数须为 128 的倍数
+const RING_SIZE: usize = 32;
+/// 每个缓冲区的大小，与 `RCTL` 中的缓冲区大小一致
+const BUFFER_SIZE: usize = 2048;
+/// 等待复位和 EEPROM 读完成的时间
+const TIMEOUT: Duration = Duration::from_millis(10);
+
+/// 网卡的型号，决定 EEPROM 读寄存器的格式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Variant {
+    E1000,  // 8254x
+    E1000e, // 8257x
+}
+
+impl Variant {
+    /// EEPROM 读寄存器中地址的偏移和完成位
+    fn eerd_format(self) -> (u32, u32) {
+        match self {
+            Variant::E1000 => (8, 1 << 4),
+            Variant::E1000e => (2, 1 << 1),
+        }
+    }
+}
+
+/// 网卡的寄存器
+#[derive(Clone, Copy)]
+struct Registers(VirtAddr);
+
+impl Registers {
+    fn read(&self, offset: u64) -> u32 {
+        unsafe { (self.0 + offset).as_ptr::<u32>().read_volatile() }
+    }
+
+    fn write(&self, offset: u64, value: u32) {
+        unsafe { (self.0 + offset).as_mut_ptr::<u32>().write_volatile(value) }
+    }
+
+    /// 轮询直到 `done` 对寄存器的值成立
+    ///
+    /// # 返回
+    ///
+    /// 在 [`TIMEOUT`] 之内成立时返回寄存器的值
+    fn wait(&self, offset: u64, done: impl Fn(u32) -> bool) -> Option<u32> {
+        let deadline = Instant::now() + TIMEOUT;
+        loop {
+            let value = self.read(offset);
+            if done(value) {
+                return Some(value);
+            }
+            if Instant::now() >= deadline {
+                return None;
+            }
+            core::hint::spin_loop();
+        }
+    }
+
+    /// 读取 EEPROM 中的一个字
+    fn read_eeprom(&self, variant: Variant, word: u8) -> Option<u16> {
+        let (shift, done) = variant.eerd_format();
+        self.write(REG_EERD, (u32::from(word) << shift) | EERD_START);
+        let value = self.wait(REG_EERD, |value| value & done != 0)?;
+        Some((value >> 16) as u16)
+    }
+}
+
+/// 从 EEPROM 的前 3 个字中取出 MAC 地址
+fn mac_from_words(words: [u16; 3]) -> MacAddress {
+    let [a, b] = words[0].to_le_bytes();
+    let [c, d] = words[1].to_le_bytes();
+    let [e, f] = words[2].to_le_bytes();
+    MacAddress([a, b, c, d, e, f])
+}
+
+/// 从接收地址寄存器的值中取出 MAC 地址，地址无效时返回 `None`
+fn mac_from_receive_address(low: u32, high: u32) -> Option<MacAddress> {
+    if high & RAH_AV == 0 {
+        return None;
+    }
+    let [a, b, c, d] = low.to_le_bytes();
+    let [e, f, _, _] = high.to_le_bytes();
+    Some(MacAddress([a, b, c, d, e, f]))
+}
+
+/// 读取网卡的 MAC 地址
+fn read_mac(regs: Registers, variant: Variant) -> Option<MacAddress> {
+    let eeprom = (|| {
+        Some([
+            regs.read_eeprom(variant, 0)?,
+            regs.read_eeprom(variant, 1)?,
+            regs.read_eeprom(variant, 2)?,
+        ])
+    })();
+    match eeprom {
+        Some(words) => Some(mac_from_words(words)),
+        None => mac_from_receive_address(regs.read(REG_RAL), regs.read(REG_RAH)),
+    }
+}
+
+/// 描述符环和它的缓冲区，第 `i` 个描述符使用第 `i` 个缓冲区
+struct Ring {
+    descriptors: DmaRegion, // 描述符
+    buffers: DmaRegion,     // 缓冲区
+    next: usize,            // 接收时下一个要检查的描述符，发送时下一个要填写的描述符
+}
+
+impl Ring {
+    fn new() -> Option<Self> {
+        Some(Self {
+            descriptors: DmaRegion::new(RING_SIZE * DESCRIPTOR_SIZE)?,
+            buffers: DmaRegion::new(RING_SIZE * BUFFER_SIZE)?,
+            next: 0,
+        })
+    }
+
+    /// 第 `index` 个缓冲区的物理地址
+    fn buffer_phys(&self, index: usize) -> PhysAddr {
+        self.buffers.phys() + (index * BUFFER_SIZE) as u64
+    }
+
+    /// 第 `index` 个缓冲区的内容
+    fn buffer(&mut self, index: usize) -> &mut [u8] {
+        &mut self.buffers.as_mut_slice()[index * BUFFER_SIZE..(index + 1) * BUFFER_SIZE]
+    }
+
+    /// 第 `index` 个描述符的第 `offset` 字节开始的字段
+    fn field<T>(&self, index: usize, offset: usize) -> *mut T {
+        self.descriptors
+            .ptr::<T>(index * DESCRIPTOR_SIZE + offset)
+            .as_ptr()
+    }
+
+    /// 把描述符指向它的缓冲区，并清除其余字段
+    fn reset_descriptor(&self, index: usize, status: u8) {
+        unsafe {
+            self.field::<u64>(index, 0)
+                .write_volatile(self.buffer_phys(index).as_u64());
+            self.field::<u64>(index, 8).write_volatile(0);
+            self.field::<u8>(index, 12).write_volatile(status);
+        }
+    }
+
+    /// 描述符的状态字段，接收和发送描述符的状态都在第 12 字节
+    fn status(&self, index: usize) -> u8 {
+        unsafe { self.field::<u8>(index, 12).read_volatile() }
+    }
+}
+
+/// e1000 网卡
+pub struct E1000 {
+    name: String,       // 设备名
+    mac: MacAddress,    // MAC 地址
+    regs: Registers,    // 寄存器
+    link: AtomicBool,   // 上一次报告的链路状态
+    rx: SpinLock<Ring>, // 接收描述符环
+    tx: SpinLock<Ring>, // 发送描述符环
+}
+
+impl E1000 {
+    /// 收取接收环中的帧后把描述符交还网卡，并报告链路状态的变化
+    fn receive(self: &Arc<Self>) {
+        let link = self.link_up();
+        if self.link.swap(link, Ordering::Relaxed) != link {
+            log::info!("{}: link {}", self.name, if link { "up" } else { "down" });
+        }
+
+        let mut frames = Vec::new();
+        {
+            let mut rx = self.rx.lock();
+            let mut tail = None;
+            while rx.status(rx.next) & DESC_DD != 0 {
+                let index = rx.next;
+                // 读到 DD 之后才能读取网卡写入的其他字段
+                fence(Ordering::Acquire);
+                let (len, status, errors) = unsafe {
+                    (
+                        usize::from(rx.field::<u16>(index, 8).read_volatile()),
+                        rx.status(index),
+                        rx.field::<u8>(index, 13).read_volatile(),
+                    )
+                };
+                // 不会收到跨越多个缓冲区的帧，出错或被截断的帧直接丢弃
+                if status & DESC_EOP != 0 && errors == 0 {
+                    frames.push(rx.buffer(index)[..len.min(BUFFER_SIZE)].to_vec());
+                }
+                rx.reset_descriptor(index, 0);
+                tail = Some(index);
+                rx.next = (index + 1) % RING_SIZE;
+            }
+            if let Some(tail) = tail {
+                fence(Ordering::Release);
+                self.regs.write(REG_RDT, tail as u32);
+            }
+        }
+        for frame in frames {
+            super::deliver(self.clone(), frame);
+        }
+    }
+}
+
+impl NetDevice for E1000 {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn mac_address(&self) -> MacAddress {
+        self.mac
+    }
+
+    fn link_up(&self) -> bool {
+        self.regs.read(REG_STATUS) & STATUS_LU != 0
+    }
+
+    fn transmit(&self, frame: &[u8]) -> Result<(), NetError> {
+        super::check_frame(frame)?;
+        let mut tx = self.tx.lock();
+        let index = tx.next;
+        if tx.status(index) & DESC_DD == 0 {
+            return Err(NetError::QueueFull);
+        }
+        tx.buffer(index)[..frame.len()].copy_from_slice(frame);
+        tx.reset_descriptor(index, 0);
+        unsafe {
+            tx.field::<u16>(index, 8).write_volatile(frame.len() as u16);
+            tx.field::<u8>(index, 11)
+                .write_volatile(DESC_EOP | CMD_IFCS | CMD_RS);
+        }
+        tx.next = (index + 1) % RING_SIZE;
+        // 网卡看到新的尾指针时描述符和缓冲区必须已经写好
+        fence(Ordering::Release);
+        self.regs.write(REG_TDT, tx.next as u32);
+        Ok(())
+    }
+}
+
+/// 所有已初始化的 e1000 网卡
+static DEVICES: SpinLock<Vec<Arc<E1000>>> = SpinLock::new(Vec::new());
+
+/// 收取所有 e1000 网卡上的帧
+static RX_WORK: Work = Work::new(receive_all);
+
+fn receive_all() {
+    let devices = DEVICES.lock().clone();
+    for device in devices {
+        device.receive();
+    }
+}
+
+/// 所有 e1000 网卡共用的中断处理函数
+fn handle_interrupt() {
+    let mut pending = false;
+    for device in DEVICES.lock().iter() {
+        pending |= device.regs.read(REG_ICR) != 0;
+    }
+    if pending {
+        RX_WORK.schedule();
+    }
+}
+
+/// 复位网卡，建立链路并启用接收和发送
+///
+/// # 参数
+///
+/// - `regs`: 网卡的寄存器
+/// - `rx`: 接收描述符环
+/// - `tx`: 发送描述符环
+/// - `mac`: 接收的单播地址
+///
+/// # 返回
+///
+/// 复位超时时返回 `None`
+fn start(regs: Registers, rx: &Ring, tx: &Ring, mac: MacAddress) -> Option<()> {
+    regs.write(REG_IMC, u32::MAX);
+    regs.write(REG_CTRL, regs.read(REG_CTRL) | CTRL_RST);
+    regs.wait(REG_CTRL, |value| value & CTRL_RST == 0)?;
+    regs.write(REG_IMC, u32::MAX);
+    regs.read(REG_ICR);
+    regs.write(REG_CTRL, regs.read(REG_CTRL) | CTRL_SLU);
+
+    let [a, b, c, d, e, f] = mac.0;
+    regs.write(REG_RAL, u32::from_le_bytes([a, b, c, d]));
+    regs.write(REG_RAH, u32::from_le_bytes([e, f, 0, 0]) | RAH_AV);
+    for index in 0..128 {
+        regs.write(REG_MTA + 4 * index, 0);
+    }
+
+    for index in 0..RING_SIZE {
+        rx.reset_descriptor(index, 0);
+        // 发送描述符开始时都是空闲的
+        tx.reset_descriptor(index, DESC_DD);
+    }
+    let ring_bytes = (RING_SIZE * DESCRIPTOR_SIZE) as u32;
+    let rx_base = rx.descriptors.phys().as_u64();
+    regs.write(REG_RDBAL, rx_base as u32);
+    regs.write(REG_RDBAH, (rx_base >> 32) as u32);
+    regs.write(REG_RDLEN, ring_bytes);
+    regs.write(REG_RDH, 0);
+    regs.write(REG_RDT, (RING_SIZE - 1) as u32);
+    let tx_base = tx.descriptors.phys().as_u64();
+    regs.write(REG_TDBAL, tx_base as u32);
+    regs.write(REG_TDBAH, (tx_base >> 32) as u32);
+    regs.write(REG_TDLEN, ring_bytes);
+    regs.write(REG_TDH, 0);
+    regs.write(REG_TDT, 0);
+
+    regs.write(REG_RCTL, RCTL_EN | RCTL_BAM | RCTL_SECRC);
+    regs.write(REG_TIPG, TIPG_DEFAULT);
+    regs.write(REG_TCTL, TCTL_EN | TCTL_PSP | TCTL_CT | TCTL_COLD);
+    Some(())
+}
+
+/// 初始化一个 e1000 网卡
+///
+/// # 参数
+///
+/// - `device`: 网卡的 PCI 设备
+/// - `variant`: 网卡的型号
+///
+/// # 返回
+///
+/// 初始化失败时返回 `None`，以及网卡的中断是否可用
+fn init_device(device: &'static Device, variant: Variant) -> Option<(Arc<E1000>, bool)> {
+    let Some(Bar::Memory { address, size, .. }) = device.bar(0) else {
+        return None;
+    };
+    let regs = Registers(memory::map_mmio(PhysAddr::new(address), size)?);
+    device.enable(pci::COMMAND_MEMORY | pci::COMMAND_BUS_MASTER);
+    let Some(mac) = read_mac(regs, variant) else {
+        log::warn!("e1000 at {}: no MAC address", device.address);
+        return None;
+    };
+    let rx = Ring::new()?;
+    let tx = Ring::new()?;
+    start(regs, &rx, &tx, mac)?;
+
+    let net = Arc::new(E1000 {
+        name: super::next_name(),
+        mac,
+        regs,
+        link: AtomicBool::new(regs.read(REG_STATUS) & STATUS_LU != 0),
+        rx: SpinLock::new(rx),
+        tx: SpinLock::new(tx),
+    });
+    // 先登记网卡，中断处理函数才能清除它的中断原因
+    DEVICES.lock().push(net.clone());
+    let interrupts = msi::route(device, handle_interrupt).is_some();
+    regs.write(
+        REG_IMS,
+        INT_RXT0 | INT_LSC | INT_RXDMT0 | INT_RXO | INT_TXDW,
+    );
+    Some((net, interrupts))
+}
+
+/// 探测所有 e1000 网卡并登记它们
+pub fn init() {
+    let mut polled = false;
+    for (device_id, variant) in DEVICES_SUPPORTED {
+        for device in pci::find(VENDOR_INTEL, device_id) {
+            let Some((net, interrupts)) = init_device(device, variant) else {
+                log::warn!("e1000 at {}: initialization failed", device.address);
+                continue;
+            };
+            super::register(net);
+            if !interrupts && !polled {
+                super::poll_periodically(&RX_WORK);
+                polled = true;
+            }
+        }
+    }
+}
+
+#[test_case]
+fn test_mac_address() {
+    let mac = MacAddress([0x52, 0x54, 0x00, 0x12, 0x34, 0x56]);
+    assert_eq!(mac_from_words([0x5452, 0x1200, 0x5634]), mac);
+    assert_eq!(
+        mac_from_receive_address(0x1200_5452, 0x8000_5634),
+        Some(mac)
+    );
+    assert_eq!(mac_from_receive_address(0x1200_5452, 0x5634), None);
+    assert_eq!(Variant::E1000.eerd_format(), (8, 1 << 4));
+    assert_eq!(Variant::E1000e.eerd_format(), (2, 1 << 1));
+}
@@ -0,0 +1,389 @@
+//! 本模块实现了 Realtek RTL8139 网卡的驱动
+//!
+//! 网卡的寄存器位于 BAR0 指向的 I/O 端口。接收使用一个环形缓冲区，网卡把每个帧连同 4 字节的头依次写入，
+//! 驱动读取后移动 CAPR 告诉网卡哪些空间可以重用；缓冲区末尾多留一个帧的空间，帧不会在缓冲区末尾折回。
+//!
+//! 发送有 4 个槽，轮流使用，每个槽有自己的缓冲区地址和状态寄存器；写入状态寄存器中的长度即开始发送，
+//! 网卡把帧复制进内部 FIFO 之后置位 OWN，槽就可以重用。
+//! 两者都只能使用 4 GiB 以下的物理地址
+
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::time::Duration;
+
+use super::{ETHERNET_HEADER_SIZE, MAX_FRAME_SIZE, MacAddress, NetDevice, NetError};
+use crate::arch::port::{Port, PortValue};
+use crate::memory::dma::{self, DmaRegion};
+use crate::pci::{self, Bar, Device, msi};
+use crate::sync::SpinLock;
+use crate::time::Instant;
+use crate::workqueue::Work;
+
+/// Realtek 的 PCI 厂商号
+const VENDOR_REALTEK: u16 = 0x10ec;
+/// RTL8139 的设备号
+const DEVICE_RTL8139: u16 = 0x8139;
+
+/// 寄存器：MAC 地址，6 字节
+const REG_IDR: u16 = 0x00;
+/// 寄存器：发送状态，每个槽 4 字节
+const REG_TSD: u16 = 0x10;
+/// 寄存器：发送缓冲区地址，每个槽 4 字节
+const REG_TSAD: u16 = 0x20;
+/// 寄存器：接收缓冲区地址
+const REG_RBSTART: u16 = 0x30;
+/// 寄存器：命令
+const REG_CR: u16 = 0x37;
+/// 寄存器：驱动已读取到的接收缓冲区偏移减 16
+const REG_CAPR: u16 = 0x38;
+/// 寄存器：中断屏蔽
+const REG_IMR: u16 = 0x3c;
+/// 寄存器：中断状态，写 1 清除
+const REG_ISR: u16 = 0x3e;
+/// 寄存器：发送配置
+const REG_TCR: u16 = 0x40;
+/// 寄存器：接收配置
+const REG_RCR: u16 = 0x44;
+/// 寄存器：配置 1
+const REG_CONFIG1: u16 = 0x52;
+/// 寄存器：媒体状态
+const REG_MSR: u16 = 0x58;
+
+/// 命令：接收缓冲区为空
+const CR_BUFE: u8 = 1 << 0;
+/// 命令：启用发送
+const CR_TE: u8 = 1 << 2;
+/// 命令：启用接收
+const CR_RE: u8 = 1 << 3;
+/// 命令：复位
+const CR_RST: u8 = 1 << 4;
+
+/// 中断：收到了帧
+const INT_ROK: u16 = 1 << 0;
+/// 中断：接收出错
+const INT_RER: u16 = 1 << 1;
+/// 中断：帧已发送
+const INT_TOK: u16 = 1 << 2;
+/// 中断：发送出错
+const INT_TER: u16 = 1 << 3;
+/// 中断：接收缓冲区溢出
+const INT_RXOVW: u16 = 1 << 4;
+/// 中断：链路状态改变
+const INT_LINK_CHANGE: u16 = 1 << 5;
+
+/// 接收配置：接收发给本机的帧
+const RCR_APM: u32 = 1 << 1;
+/// 接收配置：接收组播帧
+const RCR_AM: u32 = 1 << 2;
+/// 接收配置：接收广播帧
+const RCR_AB: u32 = 1 << 3;
+/// 接收配置：缓冲区末尾的帧不折回，缓冲区大小的编码为 0，即 8 KiB
+const RCR_WRAP: u32 = 1 << 7;
+/// 接收配置：DMA 突发不限长度
+const RCR_MXDMA_UNLIMITED: u32 = 0b111 << 8;
+/// 发送配置：DMA 突发 1024 字节
+const TCR_MXDMA_1024: u32 = 0b110 << 8;
+/// 发送配置：标准的帧间隔
+const TCR_IFG_NORMAL: u32 = 0b11 << 24;
+
+/// 发送状态：网卡已把帧复制进 FIFO，槽可以重用
+const TSD_OWN: u32 = 1 << 13;
+/// 接收帧头：帧完好
+const RX_ROK: u16 = 1 << 0;
+/// 媒体状态：链路未连接
+const MSR_LINKB: u8 = 1 << 2;
+
+/// 接收环形缓冲区的大小
+const RX_RING_SIZE: usize = 8192;
+/// 接收缓冲区的大小：环形部分、16 字节的余量和不折回的帧可能越过末尾的部分
+const RX_BUFFER_SIZE: usize = RX_RING_SIZE + 16 + 1536;
+/// 接收帧头的大小
+const RX_HEADER_SIZE: usize = 4;
+/// 帧校验序列的大小，网卡把它写入接收缓冲区
+const FCS_SIZE: usize = 4;
+/// 发送槽数
+const TX_SLOTS: usize = 4;
+/// 每个发送槽的缓冲区大小
+const TX_BUFFER_SIZE: usize = 2048;
+/// 网卡不会填充短帧，发送前补齐到这个长度
+const MIN_FRAME_SIZE: usize = 60;
+/// 等待复位完成的时间
+const RESET_TIMEOUT: Duration = Duration::from_millis(10);
+
+/// 网卡的 I/O 端口
+#[derive(Clone, Copy)]
+struct Registers(u16);
+
+impl Registers {
+    fn read<T: PortValue>(&self, reg: u16) -> T {
+        unsafe { Port::new(self.0 + reg).read() }
+    }
+
+    fn write<T: PortValue>(&self, reg: u16, value: T) {
+        unsafe { Port::new(self.0 + reg).write(value) }
+    }
+}
+
+/// 解析接收缓冲区中的帧头
+///
+/// # 参数
+///
+/// - `header`: 帧头的 4 字节
+///
+/// # 返回
+///
+/// 帧完好时返回不含帧校验序列的帧长度
+fn parse_rx_header(header: [u8; 4]) -> Option<usize> {
+    let status = u16::from_le_bytes([header[0], header[1]]);
+    let len = usize::from(u16::from_le_bytes([header[2], header[3]]));
+    let valid = status & RX_ROK != 0
+        && (ETHERNET_HEADER_SIZE + FCS_SIZE..=MAX_FRAME_SIZE + FCS_SIZE).contains(&len);
+    valid.then_some(len - FCS_SIZE)
+}
+
+/// 读取完一个帧之后接收缓冲区中的偏移
+///
+/// # 参数
+///
+/// - `offset`: 帧头的偏移
+/// - `len`: 帧头中的长度，含帧校验序列
+fn next_rx_offset(offset: usize, len: usize) -> usize {
+    (offset + RX_HEADER_SIZE + len).next_multiple_of(4) % RX_RING_SIZE
+}
+
+/// 接收缓冲区
+struct Rx {
+    buffer: DmaRegion, // 环形缓冲区
+    offset: usize,     // 下一个帧头的偏移
+}
+
+/// 发送槽
+struct Tx {
+    buffers: DmaRegion, // 每个槽占 [`TX_BUFFER_SIZE`] 字节
+    next: usize,        // 下一个使用的槽
+}
+
+/// RTL8139 网卡
+pub struct Rtl8139 {
+    name: String,     // 设备名
+    mac: MacAddress,  // MAC 地址
+    regs: Registers,  // I/O 端口
+    link: AtomicBool, // 上一次报告的链路状态
+    rx: SpinLock<Rx>, // 接收缓冲区
+    tx: SpinLock<Tx>, // 发送槽
+}
+
+impl Rtl8139 {
+    /// 收取接收缓冲区中的帧后交给协议栈，并报告链路状态的变化
+    fn receive(self: &Arc<Self>) {
+        let link = self.link_up();
+        if self.link.swap(link, Ordering::Relaxed) != link {
+            log::info!("{}: link {}", self.name, if link { "up" } else { "down" });
+        }
+
+        let mut frames = Vec::new();
+        {
+            let mut rx = self.rx.lock();
+            while self.regs.read::<u8>(REG_CR) & CR_BUFE == 0 {
+                let offset = rx.offset;
+                let data = rx.buffer.as_slice();
+                let header = [
+                    data[offset],
+                    data[offset + 1],
+                    data[offset + 2],
+                    data[offset + 3],
+                ];
+                let raw_len = usize::from(u16::from_le_bytes([header[2], header[3]]));
+                match parse_rx_header(header) {
+                    Some(len) => {
+                        let start = offset + RX_HEADER_SIZE;
+                        frames.push(data[start..start + len].to_vec());
+                    }
+                    None if raw_len > MAX_FRAME_SIZE + FCS_SIZE => {
+                        // 帧头已损坏，无法找到下一个帧，丢弃缓冲区中的所有数据
+                        log::warn!("{}: corrupted receive buffer, resetting", self.name);
+                        // 重新开启接收后网卡从缓冲区开头写入，读指针也要回到开头
+                        self.regs.write(REG_CR, CR_TE);
+                        self.regs
+                            .write(REG_RBSTART, rx.buffer.phys().as_u64() as u32);
+                        self.regs.write(REG_CR, CR_RE | CR_TE);
+                        rx.offset = 0;
+                        self.regs.write(REG_CAPR, 0u16.wrapping_sub(16));
+                        break;
+                    }
+                    None => {}
+                }
+                rx.offset = next_rx_offset(offset, raw_len);
+                // CAPR 比实际偏移少 16，这是网卡规定的
+                self.regs
+                    .write(REG_CAPR, (rx.offset as u16).wrapping_sub(16));
+            }
+        }
+        for frame in frames {
+            super::deliver(self.clone(), frame);
+        }
+    }
+}
+
+impl NetDevice for Rtl8139 {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn mac_address(&self) -> MacAddress {
+        self.mac
+    }
+
+    fn link_up(&self) -> bool {
+        self.regs.read::<u8>(REG_MSR) & MSR_LINKB == 0
+    }
+
+    fn transmit(&self, frame: &[u8]) -> Result<(), NetError> {
+        super::check_frame(frame)?;
+        let mut tx = self.tx.lock();
+        let slot = tx.next;
+        let status = REG_TSD + 4 * slot as u16;
+        if self.regs.read::<u32>(status) & TSD_OWN == 0 {
+            return Err(NetError::QueueFull);
+        }
+        let len = frame.len().max(MIN_FRAME_SIZE);
+        let buffer = &mut tx.buffers.as_mut_slice()[slot * TX_BUFFER_SIZE..][..len];
+        buffer[..frame.len()].copy_from_slice(frame);
+        buffer[frame.len()..].fill(0);
+        // 写入长度同时清除 OWN，网卡开始发送；发送阈值为 0，即 8 字节
+        self.regs.write(status, len as u32);
+        tx.next = (slot + 1) % TX_SLOTS;
+        Ok(())
+    }
+}
+
+/// 所有已初始化的 RTL8139 网卡
+static DEVICES: SpinLock<Vec<Arc<Rtl8139>>> = SpinLock::new(Vec::new());
+
+/// 收取所有 RTL8139 网卡上的帧
+static RX_WORK: Work = Work::new(receive_all);
+
+fn receive_all() {
+    let devices = DEVICES.lock().clone();
+    for device in devices {
+        device.receive();
+    }
+}
+
+/// 所有 RTL8139 网卡共用的中断处理函数
+fn handle_interrupt() {
+    let mut pending = false;
+    for device in DEVICES.lock().iter() {
+        let status = device.regs.read::<u16>(REG_ISR);
+        if status != 0 {
+            device.regs.write(REG_ISR, status);
+            pending = true;
+        }
+    }
+    if pending {
+        RX_WORK.schedule();
+    }
+}
+
+/// 初始化一个 RTL8139 网卡
+///
+/// # 参数
+///
+/// - `device`: 网卡的 PCI 设备
+///
+/// # 返回
+///
+/// 初始化失败时返回 `None`，以及网卡的中断是否可用
+fn init_device(device: &'static Device) -> Option<(Arc<Rtl8139>, bool)> {
+    let Some(Bar::Io { port, .. }) = device.bar(0) else {
+        return None;
+    };
+    let regs = Registers(port);
+    device.enable(pci::COMMAND_IO | pci::COMMAND_BUS_MASTER);
+    // 网卡只能访问 32 位的物理地址
+    let Some((rx, tx)) = DmaRegion::new_below(RX_BUFFER_SIZE, dma::DMA32_LIMIT).zip(
+        DmaRegion::new_below(TX_SLOTS * TX_BUFFER_SIZE, dma::DMA32_LIMIT),
+    ) else {
+        log::warn!("rtl8139 at {}: no DMA memory below 4 GiB", device.address);
+        return None;
+    };
+
+    // 唤醒网卡后复位，复位完成时 RST 自动清除
+    regs.write::<u8>(REG_CONFIG1, 0);
+    regs.write(REG_CR, CR_RST);
+    let deadline = Instant::now() + RESET_TIMEOUT;
+    while regs.read::<u8>(REG_CR) & CR_RST != 0 {
+        if Instant::now() >= deadline {
+            return None;
+        }
+        core::hint::spin_loop();
+    }
+    let mac = MacAddress(core::array::from_fn(|index| {
+        regs.read(REG_IDR + index as u16)
+    }));
+
+    regs.write(REG_RBSTART, rx.phys().as_u64() as u32);
+    for slot in 0..TX_SLOTS {
+        let address = tx.phys().as_u64() + (slot * TX_BUFFER_SIZE) as u64;
+        regs.write(REG_TSAD + 4 * slot as u16, address as u32);
+    }
+    regs.write(REG_CR, CR_RE | CR_TE);
+    regs.write(
+        REG_RCR,
+        RCR_APM | RCR_AM | RCR_AB | RCR_WRAP | RCR_MXDMA_UNLIMITED,
+    );
+    regs.write(REG_TCR, TCR_IFG_NORMAL | TCR_MXDMA_1024);
+
+    let net = Arc::new(Rtl8139 {
+        name: super::next_name(),
+        mac,
+        regs,
+        link: AtomicBool::new(regs.read::<u8>(REG_MSR) & MSR_LINKB == 0),
+        rx: SpinLock::new(Rx {
+            buffer: rx,
+            offset: 0,
+        }),
+        tx: SpinLock::new(Tx {
+            buffers: tx,
+            next: 0,
+        }),
+    });
+    // 先登记网卡，中断处理函数才能清除它的中断状态
+    DEVICES.lock().push(net.clone());
+    let interrupts = msi::route(device, handle_interrupt).is_some();
+    regs.write(
+        REG_IMR,
+        INT_ROK | INT_RER | INT_TOK | INT_TER | INT_RXOVW | INT_LINK_CHANGE,
+    );
+    Some((net, interrupts))
+}
+
+/// 探测所有 RTL8139 网卡并登记它们
+pub fn init() {
+    let mut polled = false;
+    for device in pci::find(VENDOR_REALTEK, DEVICE_RTL8139) {
+        let Some((net, interrupts)) = init_device(device) else {
+            log::warn!("rtl8139 at {}: initialization failed", device.address);
+            continue;
+        };
+        super::register(net);
+        if !interrupts && !polled {
+            super::poll_periodically(&RX_WORK);
+            polled = true;
+        }
+    }
+}
+
+#[test_case]
+fn test_rx_header() {
+    // 帧完好，长度 64 字节含帧校验序列
+    assert_eq!(parse_rx_header([0x01, 0x00, 64, 0]), Some(60));
+    assert_eq!(parse_rx_header([0x00, 0x00, 64, 0]), None);
+    assert_eq!(parse_rx_header([0x01, 0x00, 0xff, 0xff]), None);
+    // 下一个帧头按 4 字节对齐，越过环形部分末尾时回到开头
+    assert_eq!(next_rx_offset(0, 64), 68);
+    assert_eq!(next_rx_offset(0, 61), 68);
+    assert_eq!(next_rx_offset(8192 - 68, 64), 0);
+}
@@ -0,0 +1,260 @@
+//! 本模块实现了 virtio 网卡的驱动
+//!
+//! 队列 0 用于接收，队列 1 用于发送。每个缓冲区以 virtio 网络头开始，后面是以太网帧；
+//! 不协商校验和卸载和合并接收缓冲区，发送时网络头全部为 0，使用新式接口时网络头多出 2 字节的缓冲区计数。
+//!
+//! 接收缓冲区在初始化时全部放入接收队列，收取一个帧之后立即放回。
+//! 发送时帧被复制到一个空闲的发送槽，设备处理完的槽在下一次发送时回收
+
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use super::{MacAddress, NetDevice, NetError};
+use crate::memory::dma::DmaRegion;
+use crate::pci::Device;
+use crate::sync::SpinLock;
+use crate::virtio::{self, Buffer, Transport, VirtQueue};
+use crate::workqueue::Work;
+
+/// 特性：设备配置中有 MAC 地址
+const FEATURE_MAC: u64 = 1 << 5;
+/// 特性：设备配置中有链路状态
+const FEATURE_STATUS: u64 = 1 << 16;
+
+/// 设备配置：MAC 地址
+const CONFIG_MAC: u16 = 0;
+/// 设备配置：状态
+const CONFIG_STATUS: u16 = 6;
+/// 状态：链路已连接
+const STATUS_LINK_UP: u16 = 1 << 0;
+
+/// 接收队列的编号
+const RX_QUEUE: u16 = 0;
+/// 发送队列的编号
+const TX_QUEUE: u16 = 1;
+/// 希望的队列大小
+const QUEUE_SIZE: u16 = 64;
+/// 最多的接收缓冲区数
+const RX_BUFFERS: usize = 64;
+/// 最多的发送槽数
+const TX_SLOTS: usize = 16;
+/// 每个接收缓冲区和发送槽的大小，足以放下网络头和最大的帧
+const BUFFER_SIZE: usize = 2048;
+/// 旧式接口的网络头大小
+const HEADER_SIZE_LEGACY: usize = 10;
+/// 新式接口的网络头大小
+const HEADER_SIZE_MODERN: usize = 12;
+
+/// 设备没有 MAC 地址时使用的本地管理地址
+const FALLBACK_MAC: MacAddress = MacAddress([0x02, 0x00, 0x00, 0x00, 0x00, 0x01]);
+
+/// 一个队列和它的缓冲区
+struct Ring {
+    queue: VirtQueue,   // 虚拟队列
+    buffers: DmaRegion, // 缓冲区，每个占 [`BUFFER_SIZE`] 字节
+    owners: Vec<usize>, // 以描述符链头为下标，链使用的缓冲区的编号
+    free: Vec<usize>,   // 空闲的发送槽，接收队列不使用
+}
+
+impl Ring {
+    /// 分配队列的缓冲区
+    ///
+    /// # 参数
+    ///
+    /// - `queue`: 虚拟队列
+    /// - `count`: 缓冲区的个数
+    fn new(queue: VirtQueue, count: usize) -> Option<Self> {
+        Some(Self {
+            owners: alloc::vec![0; usize::from(queue.size())],
+            buffers: DmaRegion::new(count * BUFFER_SIZE)?,
+            queue,
+            free: (0..count).collect(),
+        })
+    }
+
+    /// 把第 `index` 个缓冲区的前 `len` 字节放入队列
+    fn post(&mut self, index: usize, len: usize, writable: bool) {
+        let head = self
+            .queue
+            .add(&[Buffer {
+                addr: self.buffers.phys() + (index * BUFFER_SIZE) as u64,
+                len: len as u32,
+                writable,
+            }])
+            .expect("virtio-net ring has a descriptor per buffer");
+        self.owners[usize::from(head)] = index;
+    }
+
+    /// 第 `index` 个缓冲区的内容
+    fn buffer(&mut self, index: usize) -> &mut [u8] {
+        &mut self.buffers.as_mut_slice()[index * BUFFER_SIZE..(index + 1) * BUFFER_SIZE]
+    }
+}
+
+/// virtio 网卡
+pub struct VirtioNet {
+    name: alloc::string::String, // 设备名
+    mac: MacAddress,             // MAC 地址
+    transport: Transport,        // 传输层
+    header_size: usize,          // 网络头的大小
+    has_status: bool,            // 设备配置中是否有链路状态
+    rx: SpinLock<Ring>,          // 接收队列
+    tx: SpinLock<Ring>,          // 发送队列
+}
+
+impl VirtioNet {
+    /// 收取接收队列中的帧，把缓冲区放回队列后交给协议栈
+    fn receive(self: &Arc<Self>) {
+        let mut frames = Vec::new();
+        {
+            let mut rx = self.rx.lock();
+            while let Some((head, len)) = rx.queue.pop_used() {
+                let index = rx.owners[usize::from(head)];
+                let len = (len as usize).min(BUFFER_SIZE);
+                if len > self.header_size {
+                    let header_size = self.header_size;
+                    frames.push(rx.buffer(index)[header_size..len].to_vec());
+                }
+                rx.post(index, BUFFER_SIZE, true);
+            }
+            if !frames.is_empty() && rx.queue.should_notify() {
+                self.transport.notify(&rx.queue);
+            }
+        }
+        for frame in frames {
+            super::deliver(self.clone(), frame);
+        }
+    }
+}
+
+impl NetDevice for VirtioNet {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn mac_address(&self) -> MacAddress {
+        self.mac
+    }
+
+    fn link_up(&self) -> bool {
+        !self.has_status || self.transport.config_u16(CONFIG_STATUS) & STATUS_LINK_UP != 0
+    }
+
+    fn transmit(&self, frame: &[u8]) -> Result<(), NetError> {
+        super::check_frame(frame)?;
+        let mut tx = self.tx.lock();
+        while let Some((head, _)) = tx.queue.pop_used() {
+            let index = tx.owners[usize::from(head)];
+            tx.free.push(index);
+        }
+        let index = tx.free.pop().ok_or(NetError::QueueFull)?;
+        let header_size = self.header_size;
+        let buffer = tx.buffer(index);
+        buffer[..header_size].fill(0);
+        buffer[header_size..header_size + frame.len()].copy_from_slice(frame);
+        tx.post(index, header_size + frame.len(), false);
+        if tx.queue.should_notify() {
+            self.transport.notify(&tx.queue);
+        }
+        Ok(())
+    }
+}
+
+/// 所有已初始化的 virtio 网卡
+static DEVICES: SpinLock<Vec<Arc<VirtioNet>>> = SpinLock::new(Vec::new());
+
+/// 收取所有 virtio 网卡上的帧
+static RX_WORK: Work = Work::new(receive_all);
+
+fn receive_all() {
+    let devices = DEVICES.lock().clone();
+    for device in devices {
+        device.receive();
+    }
+}
+
+/// 所有 virtio 网卡共用的中断处理函数
+fn handle_interrupt() {
+    for device in DEVICES.lock().iter() {
+        if !device.transport.uses_msix() {
+            device.transport.isr();
+        }
+    }
+    RX_WORK.schedule();
+}
+
+/// 初始化一个 virtio 网卡
+///
+/// # 参数
+///
+/// - `device`: 网卡的 PCI 设备
+///
+/// # 返回
+///
+/// 初始化失败时返回 `None`，以及设备的中断是否可用
+fn init_device(device: &'static Device) -> Option<(Arc<VirtioNet>, bool)> {
+    let transport = Transport::new(device)?;
+    let features = transport.negotiate(FEATURE_MAC | FEATURE_STATUS)?;
+    let vector = transport.route_interrupt(handle_interrupt);
+    let rings = (|| {
+        let rx = transport.setup_queue(RX_QUEUE, QUEUE_SIZE)?;
+        let tx = transport.setup_queue(TX_QUEUE, QUEUE_SIZE)?;
+        let rx_buffers = usize::from(rx.size()).min(RX_BUFFERS);
+        let tx_slots = usize::from(tx.size()).min(TX_SLOTS);
+        Some((
+            Ring::new(rx, rx_buffers)?,
+            rx_buffers,
+            Ring::new(tx, tx_slots)?,
+        ))
+    })();
+    let Some((mut rx, rx_buffers, tx)) = rings else {
+        transport.fail();
+        return None;
+    };
+    for index in 0..rx_buffers {
+        rx.post(index, BUFFER_SIZE, true);
+    }
+    let mac = if features & FEATURE_MAC != 0 {
+        MacAddress(core::array::from_fn(|index| {
+            transport.config_u8(CONFIG_MAC + index as u16)
+        }))
+    } else {
+        FALLBACK_MAC
+    };
+    let header_size = if transport.is_modern() {
+        HEADER_SIZE_MODERN
+    } else {
+        HEADER_SIZE_LEGACY
+    };
+    transport.driver_ok();
+    transport.notify(&rx.queue);
+    Some((
+        Arc::new(VirtioNet {
+            name: super::next_name(),
+            mac,
+            transport,
+            header_size,
+            has_status: features & FEATURE_STATUS != 0,
+            rx: SpinLock::new(rx),
+            tx: SpinLock::new(tx),
+        }),
+        vector.is_some(),
+    ))
+}
+
+/// 探测所有 virtio 网卡并登记它们
+pub fn init() {
+    let mut polled = false;
+    for device in virtio::find(virtio::DEVICE_NET) {
+        let Some((net, interrupts)) = init_device(device) else {
+            log::warn!("virtio-net at {}: initialization failed", device.address);
+            continue;
+        };
+        DEVICES.lock().push(net.clone());
+        super::register(net);
+        if !interrupts && !polled {
+            super::poll_periodically(&RX_WORK);
+            polled = true;
+        }
+    }
+}
@@ -0,0 +1,98 @@
+//! 本模块实现了全屏的 panic 界面
+//!
+//! panic 时接管主控制台的整个屏幕，以红底白字显示 panic 信息、位置、寄存器和栈内容，扬声器发出低音后停机
+
+use core::fmt::Write;
+use core::panic::PanicInfo;
+use core::time::Duration;
+
+use crate::serial_println;
+use crate::{console, speaker};
+
+/// 栈转储的 64 位字数
+const STACK_DUMP_WORDS: usize = 16;
+/// panic 提示音的频率（Hz）
+const PANIC_TONE_HZ: u32 = 220;
+/// panic 提示音的时长
+const PANIC_TONE: Duration = Duration::from_millis(500);
+
+/// 显示 panic 界面并停机
+///
+/// # 参数
+///
+/// - `info`: panic 信息
+pub fn show(info: &PanicInfo) -> ! {
+    x86_64::instructions::interrupts::disable();
+
+    // panic 可能恰好发生在持有控制台的锁期间，接管屏幕时不等待任何锁
+    console::primary_for_panic().panic(&mut |writer| render(writer, info));
+
+    serial_println!("KERNEL PANIC: {}", info);
+    speaker::beep_blocking(PANIC_TONE_HZ, PANIC_TONE);
+
+    crate::hlt_loop()
+}
+
+/// 将 panic 信息、寄存器和栈内容写入 `writer`
+fn render(writer: &mut dyn Write, info: &PanicInfo) -> core::fmt::Result {
+    use x86_64::registers::control::{Cr0, Cr2, Cr3, Cr4};
+    use x86_64::registers::rflags;
+
+    writeln!(writer, " *** KERNEL PANIC ***")?;
+    writeln!(writer)?;
+    writeln!(writer, " {}", info.message())?;
+    if let Some(location) = info.location() {
+        writeln!(
+            writer,
+            " at {}:{}:{}",
+            location.file(),
+            location.line(),
+            location.column()
+        )?;
+    }
+    writeln!(writer)?;
+
+    let rsp: u64;
+    let rbp: u64;
+    unsafe {
+        core::arch::asm!("mov {}, rsp", out(reg) rsp, options(nomem, nostack, preserves_flags));
+        core::arch::asm!("mov {}, rbp", out(reg) rbp, options(nomem, nostack, preserves_flags));
+    }
+    writeln!(writer, " Registers:")?;
+    writeln!(writer, "  RSP={:#018x}  RBP={:#018x}", rsp, rbp)?;
+    writeln!(
+        writer,
+        "  RFLAGS={:#018x}  CR0={:#018x}",
+        rflags::read_raw(),
+        Cr0::read_raw()
+    )?;
+    writeln!(
+        writer,
+        "  CR2={:#018x}  CR3={:#018x}",
+        Cr2::read_raw(),
+        Cr3::read_raw().0.start_address().as_u64()
+    )?;
+    writeln!(writer, "  CR4={:#018x}", Cr4::read_raw())?;
+    writeln!(writer)?;
+
+    writeln!(writer, " Stack:")?;
+    let stack = rsp as *const u64;
+    for line in 0..STACK_DUMP_WORDS / 2 {
+        let offset = line * 2;
+        // 当前栈顶附近的内存一定已被映射
+        let (first, second) = unsafe {
+            (
+                stack.add(offset).read_volatile(),
+                stack.add(offset + 1).read_volatile(),
+            )
+        };
+        writeln!(
+            writer,
+            "  {:#018x}: {:#018x} {:#018x}",
+            rsp + (offset * 8) as u64,
+            first,
+            second
+        )?;
+    }
+    Ok(())
+}
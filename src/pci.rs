@@ -0,0 +1,795 @@
+//! 本模块实现了 PCI 总线的枚举和设备表
+//!
+//! 每个 PCI 功能都有 256 字节的配置空间，通过 0xCF8 端口写入地址、0xCFC 端口读写数据来访问。
+//! 启动时从总线 0 开始扫描，遇到 PCI-PCI 桥时继续扫描它的次级总线，
+//! 把每个功能的厂商、设备、类别、BAR 和中断线记录下来，供之后的驱动程序查找
+//!
+//! 中断线由固件按 8259 的编号填写，经 IO APIC 投递时需要另外确定 GSI，或者改用 [`msi`] 中的消息信号中断。
+//! 固件提供 MCFG 时通过 [`ecam`] 访问配置空间，此时还能访问 256 字节之后的扩展配置空间
+
+pub mod ecam;
+pub mod msi;
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+
+use conquer_once::spin::OnceCell;
+
+use crate::arch::port::Port;
+use crate::sync::SpinLock;
+
+/// 通过端口能访问的配置空间大小
+const LEGACY_CONFIG_SIZE: u16 = 256;
+/// PCIe 功能的配置空间大小
+const CONFIG_SIZE: u16 = 4096;
+
+/// 配置地址端口
+const CONFIG_ADDRESS: u16 = 0xcf8;
+/// 配置数据端口
+const CONFIG_DATA: u16 = 0xcfc;
+/// 配置地址：启用位
+const CONFIG_ENABLE: u32 = 1 << 31;
+
+/// 配置空间：厂商编号
+const REG_VENDOR_ID: u16 = 0x00;
+/// 配置空间：设备编号
+const REG_DEVICE_ID: u16 = 0x02;
+/// 配置空间：命令寄存器
+const REG_COMMAND: u16 = 0x04;
+/// 配置空间：状态寄存器
+const REG_STATUS: u16 = 0x06;
+/// 配置空间：修订号，之后依次为编程接口、子类别和类别
+const REG_REVISION: u16 = 0x08;
+/// 配置空间：头部类型
+const REG_HEADER_TYPE: u16 = 0x0e;
+/// 配置空间：第一个 BAR
+const REG_BAR0: u16 = 0x10;
+/// PCI-PCI 桥的配置空间：次级总线号
+const REG_SECONDARY_BUS: u16 = 0x19;
+/// 配置空间：第一个能力结构的偏移
+const REG_CAPABILITIES: u16 = 0x34;
+/// 配置空间：中断线
+const REG_INTERRUPT_LINE: u16 = 0x3c;
+/// 配置空间：中断引脚
+const REG_INTERRUPT_PIN: u16 = 0x3d;
+
+/// 状态寄存器：有能力结构链表
+const STATUS_CAPABILITIES: u16 = 1 << 4;
+
+/// 没有设备时读到的厂商编号
+const VENDOR_NONE: u16 = 0xffff;
+
+/// 头部类型：多功能设备
+const HEADER_MULTI_FUNCTION: u8 = 1 << 7;
+/// 头部类型：普通设备
+const HEADER_GENERAL: u8 = 0x00;
+/// 头部类型：PCI-PCI 桥
+const HEADER_BRIDGE: u8 = 0x01;
+
+/// 类别：桥设备
+const CLASS_BRIDGE: u8 = 0x06;
+/// 子类别：PCI-PCI 桥
+const SUBCLASS_PCI_BRIDGE: u8 = 0x04;
+
+/// 命令寄存器：响应 I/O 空间访问
+pub const COMMAND_IO: u16 = 1 << 0;
+/// 命令寄存器：响应内存空间访问
+pub const COMMAND_MEMORY: u16 = 1 << 1;
+/// 命令寄存器：允许设备发起总线主控访问（DMA）
+pub const COMMAND_BUS_MASTER: u16 = 1 << 2;
+/// 命令寄存器：禁用传统的 INTx 中断
+pub const COMMAND_INTX_DISABLE: u16 = 1 << 10;
+
+/// BAR：位于 I/O 空间
+const BAR_IO: u32 = 1 << 0;
+/// BAR：64 位内存地址
+const BAR_MEMORY_64: u32 = 0b10 << 1;
+/// BAR：可预取
+const BAR_PREFETCHABLE: u32 = 1 << 3;
+
+/// 配置地址和数据端口需要成对访问
+static CONFIG: SpinLock<()> = SpinLock::new(());
+
+/// PCI 功能的地址
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct PciAddress {
+    pub bus: u8,      // 总线号
+    pub device: u8,   // 设备号，0~31
+    pub function: u8, // 功能号，0~7
+}
+
+impl PciAddress {
+    /// 创建 PCI 地址
+    ///
+    /// # 参数
+    ///
+    /// - `bus`: 总线号
+    /// - `device`: 设备号，0~31
+    /// - `function`: 功能号，0~7
+    pub const fn new(bus: u8, device: u8, function: u8) -> Self {
+        Self {
+            bus,
+            device,
+            function,
+        }
+    }
+
+    /// 写入配置地址端口的值
+    ///
+    /// # 参数
+    ///
+    /// - `offset`: 配置空间中的偏移，低 2 位被忽略
+    fn config_address(self, offset: u16) -> u32 {
+        CONFIG_ENABLE
+            | (u32::from(self.bus) << 16)
+            | (u32::from(self.device & 0x1f) << 11)
+            | (u32::from(self.function & 0x07) << 8)
+            | u32::from(offset & 0xfc)
+    }
+
+    /// 检查配置空间中的偏移，返回对应寄存器的 ECAM 地址
+    ///
+    /// # 参数
+    ///
+    /// - `offset`: 配置空间中的偏移，必须 4 字节对齐
+    ///
+    /// # 返回
+    ///
+    /// 没有 ECAM 覆盖这个功能时返回 `None`，此时偏移必须小于 256
+    fn ecam_register(self, offset: u16) -> Option<*mut u32> {
+        assert!(
+            offset < CONFIG_SIZE && offset.is_multiple_of(4),
+            "bad config offset {:#x}",
+            offset
+        );
+        let register = ecam::register(self, offset);
+        assert!(
+            register.is_some() || offset < LEGACY_CONFIG_SIZE,
+            "extended config offset {:#x} needs ECAM",
+            offset
+        );
+        register
+    }
+
+    /// 读取配置空间中对齐的 32 位值
+    ///
+    /// # 参数
+    ///
+    /// - `offset`: 配置空间中的偏移，必须 4 字节对齐；没有 ECAM 时必须小于 256
+    pub fn read_u32(self, offset: u16) -> u32 {
+        if let Some(register) = self.ecam_register(offset) {
+            return unsafe { register.read_volatile() };
+        }
+        let _guard = CONFIG.lock();
+        unsafe {
+            Port::<u32>::new(CONFIG_ADDRESS).write(self.config_address(offset));
+            Port::<u32>::new(CONFIG_DATA).read()
+        }
+    }
+
+    /// 写入配置空间中对齐的 32 位值
+    ///
+    /// # 参数
+    ///
+    /// - `offset`: 配置空间中的偏移，必须 4 字节对齐；没有 ECAM 时必须小于 256
+    /// - `value`: 写入的值
+    pub fn write_u32(self, offset: u16, value: u32) {
+        if let Some(register) = self.ecam_register(offset) {
+            unsafe { register.write_volatile(value) };
+            return;
+        }
+        let _guard = CONFIG.lock();
+        unsafe {
+            Port::<u32>::new(CONFIG_ADDRESS).write(self.config_address(offset));
+            Port::<u32>::new(CONFIG_DATA).write(value);
+        }
+    }
+
+    /// 读取配置空间中的 16 位值
+    ///
+    /// # 参数
+    ///
+    /// - `offset`: 配置空间中的偏移，必须 2 字节对齐
+    pub fn read_u16(self, offset: u16) -> u16 {
+        (self.read_u32(offset & !3) >> ((offset & 2) * 8)) as u16
+    }
+
+    /// 写入配置空间中的 16 位值，同一个 32 位字中的其他字节保持不变
+    ///
+    /// # 参数
+    ///
+    /// - `offset`: 配置空间中的偏移，必须 2 字节对齐
+    /// - `value`: 写入的值
+    pub fn write_u16(self, offset: u16, value: u16) {
+        let shift = (offset & 2) * 8;
+        let word = self.read_u32(offset & !3) & !(0xffff << shift);
+        self.write_u32(offset & !3, word | (u32::from(value) << shift));
+    }
+
+    /// 读取配置空间中的 8 位值
+    ///
+    /// # 参数
+    ///
+    /// - `offset`: 配置空间中的偏移
+    pub fn read_u8(self, offset: u16) -> u8 {
+        (self.read_u32(offset & !3) >> ((offset & 3) * 8)) as u8
+    }
+}
+
+impl fmt::Display for PciAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:02x}:{:02x}.{}", self.bus, self.device, self.function)
+    }
+}
+
+/// 基地址寄存器（BAR）描述的一段地址
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bar {
+    Memory {
+        address: u64,       // 物理地址
+        size: u64,          // 字节数
+        prefetchable: bool, // 是否可预取
+        wide: bool,         // 是否为 64 位 BAR，占用两个寄存器
+    },
+    Io {
+        port: u16, // 起始端口
+        size: u32, // 端口数
+    },
+}
+
+impl Bar {
+    /// 由 BAR 的原始值和写入全 1 后读回的值解码
+    ///
+    /// # 参数
+    ///
+    /// - `value`: BAR 的原始值，64 位 BAR 为两个寄存器拼接后的值
+    /// - `mask`: 写入全 1 后读回的值，64 位 BAR 同样拼接
+    ///
+    /// # 返回
+    ///
+    /// 未实现的 BAR 返回 `None`
+    fn decode(value: u64, mask: u64) -> Option<Bar> {
+        if value as u32 & BAR_IO != 0 {
+            let mask = (mask as u32 & !0b11) | 0xffff_0000;
+            let size = (!mask).wrapping_add(1);
+            return (size != 0).then_some(Bar::Io {
+                port: (value as u32 & !0b11) as u16,
+                size,
+            });
+        }
+        let wide = value as u32 & BAR_MEMORY_64 != 0;
+        // 32 位 BAR 的高半部分视为全 1，大小只由低位决定
+        let (address, mask) = if wide {
+            (value & !0xf, mask & !0xf)
+        } else {
+            (
+                value & 0xffff_fff0,
+                (mask & 0xffff_fff0) | 0xffff_ffff_0000_0000,
+            )
+        };
+        if mask == 0 || mask == 0xffff_ffff_0000_0000 {
+            return None;
+        }
+        Some(Bar::Memory {
+            address,
+            size: (!mask).wrapping_add(1),
+            prefetchable: value as u32 & BAR_PREFETCHABLE != 0,
+            wide,
+        })
+    }
+}
+
+/// 一个 PCI 功能
+#[derive(Debug, Clone)]
+pub struct Device {
+    pub address: PciAddress,    // 地址
+    pub vendor_id: u16,         // 厂商编号
+    pub device_id: u16,         // 设备编号
+    pub class: u8,              // 类别
+    pub subclass: u8,           // 子类别
+    pub prog_if: u8,            // 编程接口
+    pub revision: u8,           // 修订号
+    pub header_type: u8,        // 头部类型，不含多功能位
+    pub bars: [Option<Bar>; 6], // 基地址寄存器，64 位 BAR 的高半部分为 `None`
+    pub interrupt_line: u8,     // 固件分配的中断线，0xff 表示未连接
+    pub interrupt_pin: u8,      // 中断引脚，1~4 对应 INTA#~INTD#，0 表示不使用
+}
+
+impl Device {
+    /// 读取功能的配置空间头部，没有设备时返回 `None`
+    ///
+    /// # 参数
+    ///
+    /// - `address`: 功能的地址
+    fn probe(address: PciAddress) -> Option<Self> {
+        let vendor_id = address.read_u16(REG_VENDOR_ID);
+        if vendor_id == VENDOR_NONE {
+            return None;
+        }
+        let class = address.read_u32(REG_REVISION);
+        let header_type = address.read_u8(REG_HEADER_TYPE) & !HEADER_MULTI_FUNCTION;
+        let bar_count = match header_type {
+            HEADER_GENERAL => 6,
+            HEADER_BRIDGE => 2,
+            _ => 0,
+        };
+        Some(Self {
+            address,
+            vendor_id,
+            device_id: address.read_u16(REG_DEVICE_ID),
+            class: (class >> 24) as u8,
+            subclass: (class >> 16) as u8,
+            prog_if: (class >> 8) as u8,
+            revision: class as u8,
+            header_type,
+            bars: read_bars(address, bar_count),
+            interrupt_line: address.read_u8(REG_INTERRUPT_LINE),
+            interrupt_pin: address.read_u8(REG_INTERRUPT_PIN),
+        })
+    }
+
+    /// 读取命令寄存器
+    pub fn command(&self) -> u16 {
+        self.address.read_u16(REG_COMMAND)
+    }
+
+    /// 在命令寄存器中设置 `flags`，例如 [`COMMAND_MEMORY`] | [`COMMAND_BUS_MASTER`]
+    ///
+    /// # 参数
+    ///
+    /// - `flags`: 需要设置的位
+    pub fn enable(&self, flags: u16) {
+        self.address.write_u16(REG_COMMAND, self.command() | flags);
+    }
+
+    /// 第 `index` 个 BAR，未实现或为 64 位 BAR 的高半部分时返回 `None`
+    ///
+    /// # 参数
+    ///
+    /// - `index`: BAR 的编号，0~5
+    pub fn bar(&self, index: usize) -> Option<Bar> {
+        self.bars.get(index).copied().flatten()
+    }
+
+    /// 遍历能力结构链表
+    pub fn capabilities(&self) -> Capabilities {
+        let next = if self.address.read_u16(REG_STATUS) & STATUS_CAPABILITIES != 0 {
+            u16::from(self.address.read_u8(REG_CAPABILITIES) & !0b11)
+        } else {
+            0
+        };
+        Capabilities {
+            address: self.address,
+            next,
+            remaining: CAPABILITY_LIMIT,
+        }
+    }
+
+    /// 查找能力结构，返回它在配置空间中的偏移
+    ///
+    /// # 参数
+    ///
+    /// - `id`: 能力的编号，例如 [`msi::CAP_MSI`]
+    pub fn find_capability(&self, id: u8) -> Option<u16> {
+        self.capabilities()
+            .find(|capability| capability.id == id)
+            .map(|capability| capability.offset)
+    }
+
+    /// 遍历扩展配置空间中的扩展能力结构，没有 ECAM 时为空
+    pub fn extended_capabilities(&self) -> ExtendedCapabilities {
+        let has_ecam = ecam::register(self.address, LEGACY_CONFIG_SIZE).is_some();
+        ExtendedCapabilities {
+            address: self.address,
+            next: if has_ecam { LEGACY_CONFIG_SIZE } else { 0 },
+            remaining: EXTENDED_CAPABILITY_LIMIT,
+        }
+    }
+
+    /// 类别的名称
+    pub fn class_name(&self) -> &'static str {
+        class_name(self.class, self.subclass)
+    }
+}
+
+impl fmt::Display for Device {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} [{:02x}{:02x}]: {:04x}:{:04x} (rev {:02x})",
+            self.address,
+            self.class_name(),
+            self.class,
+            self.subclass,
+            self.vendor_id,
+            self.device_id,
+            self.revision
+        )
+    }
+}
+
+/// 能力结构链表最多的项数，避免固件构造的环形链表导致死循环
+const CAPABILITY_LIMIT: usize = 48;
+/// 扩展能力结构链表最多的项数
+const EXTENDED_CAPABILITY_LIMIT: usize = 960;
+
+/// 配置空间中的一个能力结构
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Capability {
+    pub id: u8,      // 能力的编号
+    pub offset: u16, // 在配置空间中的偏移
+}
+
+/// 能力结构链表的迭代器
+pub struct Capabilities {
+    address: PciAddress,
+    next: u16,
+    remaining: usize,
+}
+
+impl Iterator for Capabilities {
+    type Item = Capability;
+
+    fn next(&mut self) -> Option<Capability> {
+        if self.next == 0 || self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+        let offset = self.next;
+        let header = self.address.read_u16(offset);
+        self.next = (header >> 8) & 0xfc;
+        Some(Capability {
+            id: header as u8,
+            offset,
+        })
+    }
+}
+
+/// 扩展配置空间中的一个扩展能力结构
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExtendedCapability {
+    pub id: u16,     // 扩展能力的编号
+    pub version: u8, // 版本
+    pub offset: u16, // 在配置空间中的偏移
+}
+
+/// 扩展能力结构链表的迭代器
+pub struct ExtendedCapabilities {
+    address: PciAddress,
+    next: u16,
+    remaining: usize,
+}
+
+impl Iterator for ExtendedCapabilities {
+    type Item = ExtendedCapability;
+
+    fn next(&mut self) -> Option<ExtendedCapability> {
+        if self.next < LEGACY_CONFIG_SIZE || self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+        let offset = self.next;
+        // 低 16 位为编号，16~19 位为版本，高 12 位为下一项的偏移
+        let header = self.address.read_u32(offset);
+        if header == 0 || header == u32::MAX {
+            return None;
+        }
+        self.next = (header >> 20) as u16 & 0xffc;
+        Some(ExtendedCapability {
+            id: header as u16,
+            version: ((header >> 16) & 0xf) as u8,
+            offset,
+        })
+    }
+}
+
+/// 能力的名称
+///
+/// # 参数
+///
+/// - `id`: 能力的编号
+pub fn capability_name(id: u8) -> &'static str {
+    match id {
+        0x01 => "Power Management",
+        msi::CAP_MSI => "MSI",
+        0x09 => "Vendor Specific",
+        0x10 => "Express",
+        msi::CAP_MSIX => "MSI-X",
+        0x12 => "SATA HBA",
+        _ => "Unknown",
+    }
+}
+
+/// 读取并解码 BAR
+///
+/// 写入全 1 得到地址的大小，期间关闭设备的 I/O 和内存响应，避免它响应一段临时的地址
+///
+/// # 参数
+///
+/// - `address`: 功能的地址
+/// - `count`: 头部中 BAR 的个数
+fn read_bars(address: PciAddress, count: usize) -> [Option<Bar>; 6] {
+    let mut bars = [None; 6];
+    let command = address.read_u16(REG_COMMAND);
+    address.write_u16(REG_COMMAND, command & !(COMMAND_IO | COMMAND_MEMORY));
+    let mut index = 0;
+    while index < count {
+        let reg = REG_BAR0 + index as u16 * 4;
+        let size = |reg: u16| {
+            let value = address.read_u32(reg);
+            address.write_u32(reg, u32::MAX);
+            let mask = address.read_u32(reg);
+            address.write_u32(reg, value);
+            (value, mask)
+        };
+        let (low, low_mask) = size(reg);
+        let wide = low & BAR_IO == 0 && low & BAR_MEMORY_64 != 0 && index + 1 < count;
+        let (value, mask) = if wide {
+            let (high, high_mask) = size(reg + 4);
+            (
+                (u64::from(high) << 32) | u64::from(low),
+                (u64::from(high_mask) << 32) | u64::from(low_mask),
+            )
+        } else {
+            (u64::from(low), u64::from(low_mask))
+        };
+        bars[index] = Bar::decode(value, mask);
+        index += if wide { 2 } else { 1 };
+    }
+    address.write_u16(REG_COMMAND, command);
+    bars
+}
+
+/// 类别和子类别的名称，与 `lspci` 使用的名称相同
+///
+/// # 参数
+///
+/// - `class`: 类别
+/// - `subclass`: 子类别
+pub fn class_name(class: u8, subclass: u8) -> &'static str {
+    match (class, subclass) {
+        (0x01, 0x00) => "SCSI storage controller",
+        (0x01, 0x01) => "IDE interface",
+        (0x01, 0x06) => "SATA controller",
+        (0x01, 0x08) => "Non-Volatile memory controller",
+        (0x01, _) => "Mass storage controller",
+        (0x02, 0x00) => "Ethernet controller",
+        (0x02, _) => "Network controller",
+        (0x03, 0x00) => "VGA compatible controller",
+        (0x03, _) => "Display controller",
+        (0x04, 0x01) => "Multimedia audio controller",
+        (0x04, _) => "Multimedia controller",
+        (0x05, _) => "Memory controller",
+        (0x06, 0x00) => "Host bridge",
+        (0x06, 0x01) => "ISA bridge",
+        (0x06, 0x04) => "PCI bridge",
+        (0x06, _) => "Bridge",
+        (0x07, _) => "Communication controller",
+        (0x08, _) => "System peripheral",
+        (0x09, _) => "Input device controller",
+        (0x0c, 0x03) => "USB controller",
+        (0x0c, 0x05) => "SMBus",
+        (0x0c, _) => "Serial bus controller",
+        (0x0d, _) => "Wireless controller",
+        (0x10, _) => "Encryption controller",
+        (0x11, _) => "Signal processing controller",
+        (0xff, _) => "Unassigned class",
+        _ => "Unclassified device",
+    }
+}
+
+/// 启动时扫描到的所有功能，按地址排序
+static DEVICES: OnceCell<Vec<Device>> = OnceCell::uninit();
+
+/// 扫描所有总线并记录找到的功能
+///
+/// 以 debug 级别记录 [`lspci`] 的输出。需要在堆初始化之后调用，重复调用时不会重新扫描
+pub fn init() {
+    if DEVICES.is_initialized() {
+        return;
+    }
+    ecam::init();
+    let mut devices = Vec::new();
+    // 多功能的主桥的每个功能各自负责一条总线
+    let host = PciAddress::new(0, 0, 0);
+    if host.read_u8(REG_HEADER_TYPE) & HEADER_MULTI_FUNCTION == 0 {
+        scan_bus(0, &mut devices);
+    } else {
+        for function in 0..8 {
+            if PciAddress::new(0, 0, function).read_u16(REG_VENDOR_ID) != VENDOR_NONE {
+                scan_bus(function, &mut devices);
+            }
+        }
+    }
+    devices.sort_by_key(|device| device.address);
+    devices.dedup_by_key(|device| device.address);
+    log::info!("found {} PCI functions", devices.len());
+    DEVICES.init_once(|| devices);
+    if log::log_enabled!(log::Level::Debug) {
+        let mut listing = String::new();
+        let _ = lspci(&mut listing);
+        for line in listing.lines() {
+            log::debug!("{}", line);
+        }
+    }
+}
+
+/// 扫描一条总线上的所有设备
+///
+/// # 参数
+///
+/// - `bus`: 总线号
+/// - `devices`: 找到的功能追加到这里
+fn scan_bus(bus: u8, devices: &mut Vec<Device>) {
+    for device in 0..32 {
+        let Some(first) = Device::probe(PciAddress::new(bus, device, 0)) else {
+            continue;
+        };
+        let functions = if first.address.read_u8(REG_HEADER_TYPE) & HEADER_MULTI_FUNCTION != 0 {
+            8
+        } else {
+            1
+        };
+        scan_function(first, devices);
+        for function in 1..functions {
+            if let Some(found) = Device::probe(PciAddress::new(bus, device, function)) {
+                scan_function(found, devices);
+            }
+        }
+    }
+}
+
+/// 记录一个功能，它是 PCI-PCI 桥时继续扫描次级总线
+///
+/// # 参数
+///
+/// - `device`: 找到的功能
+/// - `devices`: 找到的功能追加到这里
+fn scan_function(device: Device, devices: &mut Vec<Device>) {
+    let secondary = (device.class == CLASS_BRIDGE && device.subclass == SUBCLASS_PCI_BRIDGE)
+        .then(|| device.address.read_u8(REG_SECONDARY_BUS));
+    let bus = device.address.bus;
+    devices.push(device);
+    // 固件未配置的桥次级总线号为 0，跳过以免重复扫描
+    if let Some(secondary) = secondary
+        && secondary > bus
+    {
+        scan_bus(secondary, devices);
+    }
+}
+
+/// 启动时扫描到的所有功能，尚未扫描时为空
+pub fn devices() -> impl Iterator<Item = &'static Device> {
+    DEVICES.get().into_iter().flatten()
+}
+
+/// 按厂商和设备编号查找功能
+///
+/// # 参数
+///
+/// - `vendor_id`: 厂商编号
+/// - `device_id`: 设备编号
+pub fn find(vendor_id: u16, device_id: u16) -> impl Iterator<Item = &'static Device> {
+    devices().filter(move |device| device.vendor_id == vendor_id && device.device_id == device_id)
+}
+
+/// 按类别和子类别查找功能
+///
+/// # 参数
+///
+/// - `class`: 类别
+/// - `subclass`: 子类别
+pub fn find_class(class: u8, subclass: u8) -> impl Iterator<Item = &'static Device> {
+    devices().filter(move |device| device.class == class && device.subclass == subclass)
+}
+
+/// 以 `lspci -v` 的格式输出所有功能
+///
+/// # 参数
+///
+/// - `out`: 输出目标
+pub fn lspci(out: &mut impl fmt::Write) -> fmt::Result {
+    for device in devices() {
+        writeln!(out, "{}", device)?;
+        for capability in device.capabilities() {
+            writeln!(
+                out,
+                "\tCapabilities: [{:02x}] {}",
+                capability.offset,
+                capability_name(capability.id)
+            )?;
+        }
+        for capability in device.extended_capabilities() {
+            writeln!(
+                out,
+                "\tCapabilities: [{:03x} v{}] Extended {:#06x}",
+                capability.offset, capability.version, capability.id
+            )?;
+        }
+        if device.interrupt_pin != 0 {
+            writeln!(
+                out,
+                "\tInterrupt: pin {} routed to IRQ {}",
+                char::from(b'A' + device.interrupt_pin - 1),
+                device.interrupt_line
+            )?;
+        }
+        for (index, bar) in device.bars.iter().enumerate() {
+            match bar {
+                Some(Bar::Memory {
+                    address,
+                    size,
+                    prefetchable,
+                    wide,
+                }) => writeln!(
+                    out,
+                    "\tRegion {}: Memory at {:x} ({}-bit, {}) [size={}K]",
+                    index,
+                    address,
+                    if *wide { 64 } else { 32 },
+                    if *prefetchable {
+                        "prefetchable"
+                    } else {
+                        "non-prefetchable"
+                    },
+                    size.div_ceil(1024)
+                )?,
+                Some(Bar::Io { port, size }) => writeln!(
+                    out,
+                    "\tRegion {}: I/O ports at {:04x} [size={}]",
+                    index, port, size
+                )?,
+                None => {}
+            }
+        }
+    }
+    Ok(())
+}
+
+#[test_case]
+fn test_decode_bars() {
+    // 32 位、4 KiB 的不可预取内存
+    assert_eq!(
+        Bar::decode(0xfebf_0000, 0xffff_f000),
+        Some(Bar::Memory {
+            address: 0xfebf_0000,
+            size: 0x1000,
+            prefetchable: false,
+            wide: false,
+        })
+    );
+    // 64 位、16 KiB 的可预取内存
+    assert_eq!(
+        Bar::decode(0x0000_0080_0000_000c, 0xffff_ffff_ffff_c00c),
+        Some(Bar::Memory {
+            address: 0x80_0000_0000,
+            size: 0x4000,
+            prefetchable: true,
+            wide: true,
+        })
+    );
+    // 32 个 I/O 端口
+    assert_eq!(
+        Bar::decode(0xc041, 0xffff_ffe1),
+        Some(Bar::Io {
+            port: 0xc040,
+            size: 32,
+        })
+    );
+    // 未实现的 BAR
+    assert_eq!(Bar::decode(0, 0), None);
+}
+
+#[test_case]
+fn test_scan_finds_host_bridge() {
+    init();
+    // QEMU 的 i440FX 和 Q35 在 00:00.0 都有主桥
+    let host = devices()
+        .find(|device| device.address == PciAddress::new(0, 0, 0))
+        .expect("no host bridge");
+    assert_eq!((host.class, host.subclass), (0x06, 0x00));
+    let mut output = crate::TestBuffer::<64>::new();
+    lspci(&mut output).unwrap();
+    assert!(output.as_str().starts_with("00:00.0 Host bridge [0600]"));
+}
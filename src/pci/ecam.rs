@@ -0,0 +1,99 @@
+//! 本模块实现了通过 PCIe 增强配置访问机制（ECAM）读写配置空间
+//!
+//! ACPI MCFG 给出一段物理内存，每个功能的 4 KiB 配置空间按总线、设备、功能号依次排列，
+//! 可以像普通内存一样读写，并且能访问 256 字节之后的扩展配置空间。
+//!
+//! 一段区域最多有 256 MiB，全部映射会占用大量页表，因此按总线在第一次访问时映射。
+//! 只支持段组 0，与端口方式能访问的范围相同
+
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+
+use conquer_once::spin::OnceCell;
+use x86_64::VirtAddr;
+
+use super::PciAddress;
+use crate::acpi::{self, EcamRegion};
+use crate::memory;
+use crate::sync::SpinLock;
+
+/// 一条总线的配置空间大小
+const BUS_SIZE: u64 = 1 << 20;
+
+/// 段组 0 中的 ECAM 区域
+static REGIONS: OnceCell<Vec<EcamRegion>> = OnceCell::uninit();
+
+/// 已经映射的总线，值为该总线配置空间的虚拟地址
+static MAPPED: SpinLock<BTreeMap<u8, VirtAddr>> = SpinLock::new(BTreeMap::new());
+
+/// 读取 MCFG 中的 ECAM 区域
+///
+/// # 返回
+///
+/// 没有 MCFG 或其中没有段组 0 时返回 `false`，此后配置空间通过端口访问
+pub fn init() -> bool {
+    if REGIONS.is_initialized() {
+        return true;
+    }
+    let Some(regions) = acpi::mcfg() else {
+        return false;
+    };
+    let regions: Vec<_> = regions
+        .into_iter()
+        .filter(|region| region.segment == 0)
+        .collect();
+    if regions.is_empty() {
+        return false;
+    }
+    for region in &regions {
+        log::info!(
+            "PCIe ECAM for buses {:02x}-{:02x} at {:#x}",
+            region.start_bus,
+            region.end_bus,
+            region.base.as_u64()
+        );
+    }
+    REGIONS.init_once(|| regions);
+    true
+}
+
+/// 是否通过 ECAM 访问配置空间
+pub fn is_available() -> bool {
+    REGIONS.is_initialized()
+}
+
+/// 功能配置空间中 `offset` 处寄存器的地址
+///
+/// # 参数
+///
+/// - `address`: 功能的地址
+/// - `offset`: 配置空间中的偏移，小于 4096
+///
+/// # 返回
+///
+/// 没有 ECAM 区域覆盖这条总线或映射失败时返回 `None`
+pub(super) fn register(address: PciAddress, offset: u16) -> Option<*mut u32> {
+    let bus = map_bus(address.bus)?;
+    let function =
+        (u64::from(address.device & 0x1f) << 15) | (u64::from(address.function & 0x07) << 12);
+    Some((bus + function + u64::from(offset)).as_mut_ptr())
+}
+
+/// 映射一条总线的配置空间，已经映射时直接返回
+///
+/// # 参数
+///
+/// - `bus`: 总线号
+fn map_bus(bus: u8) -> Option<VirtAddr> {
+    let region = REGIONS
+        .get()?
+        .iter()
+        .find(|region| (region.start_bus..=region.end_bus).contains(&bus))?;
+    let mut mapped = MAPPED.lock();
+    if let Some(&virt) = mapped.get(&bus) {
+        return Some(virt);
+    }
+    let virt = memory::map_mmio(region.base + u64::from(bus) * BUS_SIZE, BUS_SIZE)?;
+    mapped.insert(bus, virt);
+    Some(virt)
+}
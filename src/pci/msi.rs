@@ -0,0 +1,322 @@
+//! 本模块实现了 MSI 和 MSI-X 中断的配置
+//!
+//! 支持消息信号中断的设备不再使用共享的 INTx 引脚，而是向本地 APIC 的地址写入一个消息来产生中断，
+//! 消息地址决定目标处理器，消息数据决定向量号。
+//!
+//! MSI 的消息寄存器位于能力结构中，一个设备通常只用一个向量；MSI-X 的每个向量在 BAR 中的表里各占一项，
+//! 可以把不同的队列分给不同的处理器
+
+use core::sync::atomic::{Ordering, fence};
+
+use x86_64::{PhysAddr, VirtAddr};
+
+use super::{Bar, COMMAND_INTX_DISABLE, Device, PciAddress};
+use crate::{apic, interrupts, ioapic, memory};
+
+/// MSI 能力的编号
+pub const CAP_MSI: u8 = 0x05;
+/// MSI-X 能力的编号
+pub const CAP_MSIX: u8 = 0x11;
+
+/// 消息地址：写入这一段地址的消息被本地 APIC 接收，12~19 位为目标 APIC ID
+const MESSAGE_ADDRESS: u32 = 0xfee0_0000;
+
+/// MSI 控制寄存器：启用
+const MSI_ENABLE: u16 = 1 << 0;
+/// MSI 控制寄存器：分配的向量数，以 2 为底的对数
+const MSI_MULTIPLE_ENABLE: u16 = 0b111 << 4;
+/// MSI 控制寄存器：消息地址为 64 位
+const MSI_64_BIT: u16 = 1 << 7;
+/// MSI 控制寄存器：支持按向量屏蔽
+const MSI_PER_VECTOR_MASK: u16 = 1 << 8;
+
+/// MSI-X 控制寄存器：表项数减 1
+const MSIX_TABLE_SIZE: u16 = 0x7ff;
+/// MSI-X 控制寄存器：屏蔽所有向量
+const MSIX_FUNCTION_MASK: u16 = 1 << 14;
+/// MSI-X 控制寄存器：启用
+const MSIX_ENABLE: u16 = 1 << 15;
+/// MSI-X 表项的大小
+const MSIX_ENTRY_SIZE: u64 = 16;
+/// MSI-X 表项的向量控制字：屏蔽
+const MSIX_ENTRY_MASKED: u32 = 1 << 0;
+
+/// 把中断投递到 `apic_id` 的 `vector` 的消息地址和数据，边沿触发，固定投递模式
+///
+/// # 参数
+///
+/// - `apic_id`: 目标处理器的 APIC ID，只能使用低 8 位
+/// - `vector`: 中断向量号
+pub fn message(apic_id: u32, vector: u8) -> (u64, u32) {
+    (
+        u64::from(MESSAGE_ADDRESS | ((apic_id & 0xff) << 12)),
+        u32::from(vector),
+    )
+}
+
+/// 为设备分配中断向量、登记处理函数，并把设备的中断投递到当前处理器
+///
+/// 依次尝试 MSI、MSI-X 的第 0 项和经 IO APIC 的 INTx，需要在中断改由 APIC 投递之后调用
+///
+/// # 参数
+///
+/// - `device`: PCI 设备
+/// - `handler`: 中断到达时调用的函数，使用 INTx 时需要清除设备的中断状态
+///
+/// # 返回
+///
+/// 分配的向量。APIC 不可用、向量已用完或设备没有可用的中断时返回 `None`，此时驱动应改为轮询
+pub fn route(device: &Device, handler: fn()) -> Option<u8> {
+    route_in_order(device, handler, false)
+}
+
+/// 与 [`route`] 相同，但先尝试 MSI-X 的第 0 项再尝试 MSI
+///
+/// 用于按 MSI-X 表项选择中断的设备，例如 NVMe 完成队列的中断向量字段
+///
+/// # 参数
+///
+/// - `device`: PCI 设备
+/// - `handler`: 中断到达时调用的函数，使用 INTx 时需要清除设备的中断状态
+pub fn route_msix_first(device: &Device, handler: fn()) -> Option<u8> {
+    route_in_order(device, handler, true)
+}
+
+/// 按指定的顺序尝试 MSI 和 MSI-X，都没有时退回 INTx
+fn route_in_order(device: &Device, handler: fn(), msix_first: bool) -> Option<u8> {
+    if !interrupts::is_apic_delivery() {
+        return None;
+    }
+    let vector = interrupts::register_device(handler)?;
+    let apic_id = apic::id();
+    let enable_msi = || {
+        Msi::find(device)
+            .map(|msi| msi.enable(apic_id, vector))
+            .is_some()
+    };
+    let enable_msix = || {
+        MsiX::find(device)
+            .map(|msix| {
+                msix.set_vector(0, apic_id, vector);
+                msix.enable();
+            })
+            .is_some()
+    };
+    let (first, second): (&dyn Fn() -> bool, &dyn Fn() -> bool) = if msix_first {
+        (&enable_msix, &enable_msi)
+    } else {
+        (&enable_msi, &enable_msix)
+    };
+    if first() || second() {
+        return Some(vector);
+    }
+    if device.interrupt_pin != 0
+        && device.interrupt_line != 0xff
+        && ioapic::route_pci(device.interrupt_line, vector, apic_id)
+    {
+        return Some(vector);
+    }
+    interrupts::unregister_device(vector);
+    None
+}
+
+/// 设备的 MSI-X 是否已经启用，不映射向量表
+///
+/// 一些设备在启用 MSI-X 之后改变寄存器的布局或需要为每个队列选择表项，驱动用它判断 [`route`] 选择了哪种中断
+///
+/// # 参数
+///
+/// - `device`: PCI 设备
+pub fn is_msix_enabled(device: &Device) -> bool {
+    device
+        .find_capability(CAP_MSIX)
+        .is_some_and(|offset| device.address.read_u16(offset + 2) & MSIX_ENABLE != 0)
+}
+
+/// 设备的 MSI 能力
+#[derive(Debug, Clone, Copy)]
+pub struct Msi {
+    address: PciAddress, // 设备的地址
+    offset: u16,         // 能力结构在配置空间中的偏移
+    control: u16,        // 发现时的控制寄存器
+}
+
+impl Msi {
+    /// 查找设备的 MSI 能力，没有时返回 `None`
+    ///
+    /// # 参数
+    ///
+    /// - `device`: PCI 设备
+    pub fn find(device: &Device) -> Option<Msi> {
+        let offset = device.find_capability(CAP_MSI)?;
+        Some(Msi {
+            address: device.address,
+            offset,
+            control: device.address.read_u16(offset + 2),
+        })
+    }
+
+    /// 设备最多能请求的向量数
+    pub fn max_vectors(&self) -> u8 {
+        1 << ((self.control >> 1) & 0b111)
+    }
+
+    /// 是否支持按向量屏蔽
+    pub fn is_maskable(&self) -> bool {
+        self.control & MSI_PER_VECTOR_MASK != 0
+    }
+
+    /// 用一个向量启用 MSI，同时禁用设备的 INTx 中断
+    ///
+    /// # 参数
+    ///
+    /// - `apic_id`: 目标处理器的 APIC ID
+    /// - `vector`: 中断向量号
+    pub fn enable(&self, apic_id: u32, vector: u8) {
+        let (address, data) = message(apic_id, vector);
+        let pci = self.address;
+        pci.write_u32(self.offset + 4, address as u32);
+        let data_offset = if self.control & MSI_64_BIT != 0 {
+            pci.write_u32(self.offset + 8, (address >> 32) as u32);
+            self.offset + 12
+        } else {
+            self.offset + 8
+        };
+        pci.write_u16(data_offset, data as u16);
+        let control = pci.read_u16(self.offset + 2) & !MSI_MULTIPLE_ENABLE;
+        pci.write_u16(self.offset + 2, control | MSI_ENABLE);
+        pci.write_u16(
+            super::REG_COMMAND,
+            pci.read_u16(super::REG_COMMAND) | COMMAND_INTX_DISABLE,
+        );
+    }
+
+    /// 禁用 MSI
+    pub fn disable(&self) {
+        let control = self.address.read_u16(self.offset + 2);
+        self.address
+            .write_u16(self.offset + 2, control & !MSI_ENABLE);
+    }
+}
+
+/// 设备的 MSI-X 能力和映射好的向量表
+#[derive(Debug, Clone, Copy)]
+pub struct MsiX {
+    address: PciAddress, // 设备的地址
+    offset: u16,         // 能力结构在配置空间中的偏移
+    table_size: u16,     // 表项数
+    table: VirtAddr,     // 向量表映射到的虚拟地址
+}
+
+impl MsiX {
+    /// 查找设备的 MSI-X 能力并映射它的向量表
+    ///
+    /// 映射只增不减，每个设备只应调用一次
+    ///
+    /// # 参数
+    ///
+    /// - `device`: PCI 设备
+    ///
+    /// # 返回
+    ///
+    /// 没有 MSI-X 能力、向量表所在的 BAR 不是内存空间或映射失败时返回 `None`
+    pub fn find(device: &Device) -> Option<MsiX> {
+        let offset = device.find_capability(CAP_MSIX)?;
+        let control = device.address.read_u16(offset + 2);
+        let table_size = (control & MSIX_TABLE_SIZE) + 1;
+        // 低 3 位为 BAR 编号，其余为表在 BAR 中的偏移
+        let location = device.address.read_u32(offset + 4);
+        let Some(Bar::Memory { address, .. }) = device.bar((location & 0b111) as usize) else {
+            return None;
+        };
+        let table = memory::map_mmio(
+            PhysAddr::new(address + u64::from(location & !0b111)),
+            u64::from(table_size) * MSIX_ENTRY_SIZE,
+        )?;
+        Some(MsiX {
+            address: device.address,
+            offset,
+            table_size,
+            table,
+        })
+    }
+
+    /// 向量表的项数
+    pub fn table_size(&self) -> u16 {
+        self.table_size
+    }
+
+    /// 表项的第 `word` 个 32 位字
+    fn entry(&self, entry: u16, word: u64) -> *mut u32 {
+        assert!(
+            entry < self.table_size,
+            "MSI-X entry {} out of range",
+            entry
+        );
+        (self.table + u64::from(entry) * MSIX_ENTRY_SIZE + word * 4).as_mut_ptr()
+    }
+
+    /// 设置表项的目标并取消屏蔽
+    ///
+    /// # 参数
+    ///
+    /// - `entry`: 表项编号
+    /// - `apic_id`: 目标处理器的 APIC ID
+    /// - `vector`: 中断向量号
+    pub fn set_vector(&self, entry: u16, apic_id: u32, vector: u8) {
+        let (address, data) = message(apic_id, vector);
+        self.set_masked(entry, true);
+        unsafe {
+            self.entry(entry, 0).write_volatile(address as u32);
+            self.entry(entry, 1).write_volatile((address >> 32) as u32);
+            self.entry(entry, 2).write_volatile(data);
+        }
+        // 消息写完之后才能取消屏蔽，否则设备可能按新旧混合的消息产生中断
+        fence(Ordering::SeqCst);
+        self.set_masked(entry, false);
+    }
+
+    /// 屏蔽或取消屏蔽表项
+    ///
+    /// # 参数
+    ///
+    /// - `entry`: 表项编号
+    /// - `masked`: 是否屏蔽
+    pub fn set_masked(&self, entry: u16, masked: bool) {
+        let control = self.entry(entry, 3);
+        unsafe {
+            let value = control.read_volatile();
+            control.write_volatile(if masked {
+                value | MSIX_ENTRY_MASKED
+            } else {
+                value & !MSIX_ENTRY_MASKED
+            });
+        }
+    }
+
+    /// 启用 MSI-X，同时禁用设备的 INTx 中断
+    ///
+    /// 表项在复位后都是屏蔽的，需要先用 [`set_vector`](Self::set_vector) 设置用到的表项
+    pub fn enable(&self) {
+        let pci = self.address;
+        let control = pci.read_u16(self.offset + 2) & !MSIX_FUNCTION_MASK;
+        pci.write_u16(self.offset + 2, control | MSIX_ENABLE);
+        pci.write_u16(
+            super::REG_COMMAND,
+            pci.read_u16(super::REG_COMMAND) | COMMAND_INTX_DISABLE,
+        );
+    }
+
+    /// 禁用 MSI-X
+    pub fn disable(&self) {
+        let control = self.address.read_u16(self.offset + 2);
+        self.address
+            .write_u16(self.offset + 2, control & !MSIX_ENABLE);
+    }
+}
+
+#[test_case]
+fn test_message_targets_local_apic() {
+    assert_eq!(message(0, 0x40), (0xfee0_0000, 0x40));
+    assert_eq!(message(3, 0x41), (0xfee0_3000, 0x41));
+}
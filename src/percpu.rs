@@ -0,0 +1,223 @@
+//! 本模块实现了每个处理器私有的数据块
+//!
+//! 每个处理器有一个 [`PerCpu`]，GS 段基址指向它，内核通过 GS 相对寻址访问当前处理器的数据，
+//! 系统调用入口也用它找到当前处理器的内核栈。
+//!
+//! 内核态下 GS 基址总是指向数据块，用户态的 GS 基址保存在 `IA32_KERNEL_GS_BASE` 中：
+//! 从用户态进入内核（系统调用或中断）时执行 `swapgs` 交换两者，返回用户态之前再交换回来。
+//! 中断处理函数通过 [`enter_from`] 返回的 [`KernelGs`] 完成这一对交换
+
+use core::mem::offset_of;
+use core::sync::atomic::{AtomicBool, AtomicPtr, AtomicU32, AtomicU64, AtomicUsize, Ordering};
+
+use x86_64::PrivilegeLevel;
+use x86_64::structures::idt::InterruptStackFrame;
+use x86_64::structures::tss::TaskStateSegment;
+
+use crate::arch::msr::{IA32_GS_BASE, IA32_KERNEL_GS_BASE};
+
+/// 支持的最大处理器数
+pub const MAX_CPUS: usize = 64;
+
+/// 处理器私有的数据块
+///
+/// 字段的偏移会被汇编代码通过 GS 相对寻址使用，因此使用 C 布局
+#[repr(C)]
+pub struct PerCpu {
+    this: AtomicU64,                  // 数据块自身的地址，`gs:[0]` 通过它得到指针
+    kernel_stack: AtomicU64,          // 系统调用使用的内核栈栈顶
+    user_stack: AtomicU64,            // 进入系统调用时暂存的用户栈指针
+    id: AtomicUsize,                  // 处理器编号，引导处理器为 0
+    apic_id: AtomicU32,               // 处理器的本地 APIC ID
+    online: AtomicBool,               // 是否已经能够响应处理器间中断
+    tss: AtomicPtr<TaskStateSegment>, // 处理器的 TSS
+    current_thread: AtomicU64,        // 正在运行的线程的编号
+    ticks: AtomicU64,                 // 处理过的定时器中断数
+    syscalls: AtomicU64,              // 处理过的系统调用数
+}
+
+/// [`PerCpu`] 中系统调用内核栈的偏移
+pub(crate) const KERNEL_STACK_OFFSET: usize = offset_of!(PerCpu, kernel_stack);
+/// [`PerCpu`] 中暂存的用户栈指针的偏移
+pub(crate) const USER_STACK_OFFSET: usize = offset_of!(PerCpu, user_stack);
+
+impl PerCpu {
+    /// 创建一个空的数据块
+    const fn new() -> Self {
+        Self {
+            this: AtomicU64::new(0),
+            kernel_stack: AtomicU64::new(0),
+            user_stack: AtomicU64::new(0),
+            id: AtomicUsize::new(0),
+            apic_id: AtomicU32::new(0),
+            online: AtomicBool::new(false),
+            tss: AtomicPtr::new(core::ptr::null_mut()),
+            current_thread: AtomicU64::new(0),
+            ticks: AtomicU64::new(0),
+            syscalls: AtomicU64::new(0),
+        }
+    }
+
+    /// 处理器编号，引导处理器为 0
+    pub fn id(&self) -> usize {
+        self.id.load(Ordering::Relaxed)
+    }
+
+    /// 处理器的本地 APIC ID，处理器上线之前为 0
+    pub fn apic_id(&self) -> u32 {
+        self.apic_id.load(Ordering::Relaxed)
+    }
+
+    /// 处理器是否已经上线，能够响应处理器间中断
+    pub fn is_online(&self) -> bool {
+        self.online.load(Ordering::Acquire)
+    }
+
+    /// 记录 APIC ID 并标记处理器上线，需要在加载 IDT 之后调用
+    ///
+    /// # 参数
+    ///
+    /// - `apic_id`: 处理器的本地 APIC ID
+    pub(crate) fn set_online(&self, apic_id: u32) {
+        self.apic_id.store(apic_id, Ordering::Relaxed);
+        self.online.store(true, Ordering::Release);
+    }
+
+    /// 处理器的 TSS
+    pub(crate) fn tss(&self) -> *mut TaskStateSegment {
+        self.tss.load(Ordering::Relaxed)
+    }
+
+    /// 设置系统调用使用的内核栈
+    pub(crate) fn set_kernel_stack(&self, top: u64) {
+        self.kernel_stack.store(top, Ordering::Relaxed);
+    }
+
+    /// 正在这个处理器上运行的线程的编号
+    pub fn current_thread(&self) -> u64 {
+        self.current_thread.load(Ordering::Relaxed)
+    }
+
+    /// 记录换上的线程，由调度器在切换时调用
+    pub(crate) fn set_current_thread(&self, id: u64) {
+        self.current_thread.store(id, Ordering::Relaxed);
+    }
+
+    /// 这个处理器处理过的定时器中断数
+    pub fn ticks(&self) -> u64 {
+        self.ticks.load(Ordering::Relaxed)
+    }
+
+    /// 这个处理器处理过的系统调用数
+    pub fn syscalls(&self) -> u64 {
+        self.syscalls.load(Ordering::Relaxed)
+    }
+
+    /// 定时器中断计数加一
+    pub(crate) fn count_tick(&self) {
+        self.ticks.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// 系统调用计数加一
+    pub(crate) fn count_syscall(&self) {
+        self.syscalls.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// 引导处理器的数据块，堆初始化之前就需要使用，因此是静态的
+static BSP: PerCpu = PerCpu::new();
+
+/// 所有已初始化的处理器的数据块，下标为处理器编号
+static CPUS: [AtomicPtr<PerCpu>; MAX_CPUS] =
+    [const { AtomicPtr::new(core::ptr::null_mut()) }; MAX_CPUS];
+
+/// 初始化引导处理器的数据块并设置 GS 基址
+///
+/// # 参数
+///
+/// - `tss`: 引导处理器的 TSS
+pub fn init_bsp(tss: *mut TaskStateSegment) {
+    install(&BSP, 0, tss);
+}
+
+/// 为应用处理器分配数据块并设置 GS 基址，需要在堆初始化之后调用
+///
+/// # 参数
+///
+/// - `id`: 处理器编号，小于 [`MAX_CPUS`]
+/// - `tss`: 处理器的 TSS
+pub fn init_ap(id: usize, tss: *mut TaskStateSegment) {
+    assert!(id < MAX_CPUS, "too many CPUs");
+    let block = alloc::boxed::Box::leak(alloc::boxed::Box::new(PerCpu::new()));
+    install(block, id, tss);
+}
+
+/// 填写数据块并让 GS 基址指向它
+fn install(block: &'static PerCpu, id: usize, tss: *mut TaskStateSegment) {
+    let addr = block as *const PerCpu as u64;
+    block.this.store(addr, Ordering::Relaxed);
+    block.id.store(id, Ordering::Relaxed);
+    block.tss.store(tss, Ordering::Relaxed);
+    CPUS[id].store(block as *const PerCpu as *mut PerCpu, Ordering::Release);
+    unsafe {
+        IA32_GS_BASE.write(addr);
+        // 用户程序从 0 号 GS 基址开始运行
+        IA32_KERNEL_GS_BASE.write(0);
+    }
+}
+
+/// 当前处理器的数据块
+///
+/// 取得的引用在线程被迁移到其他处理器之后指向原来的处理器，需要准确性时应禁用中断
+pub fn current() -> &'static PerCpu {
+    let addr: u64;
+    unsafe {
+        core::arch::asm!("mov {}, gs:[0]", out(reg) addr, options(nostack, readonly, preserves_flags));
+    }
+    debug_assert_ne!(addr, 0, "per-CPU block not initialized");
+    unsafe { &*(addr as *const PerCpu) }
+}
+
+/// 编号为 `id` 的处理器的数据块，处理器尚未初始化时返回 `None`
+pub fn get(id: usize) -> Option<&'static PerCpu> {
+    let ptr = CPUS.get(id)?.load(Ordering::Acquire);
+    unsafe { ptr.as_ref() }
+}
+
+/// 遍历所有已初始化的处理器的数据块
+pub fn all() -> impl Iterator<Item = &'static PerCpu> {
+    (0..MAX_CPUS).filter_map(get)
+}
+
+/// 中断处理函数持有的 GS 基址状态，被中断的是用户态时在释放时换回用户的 GS 基址
+#[must_use]
+pub struct KernelGs(bool);
+
+/// 由中断处理函数在访问数据块之前调用，被中断的是用户态时执行 `swapgs`
+///
+/// # 参数
+///
+/// - `stack_frame`: 中断栈帧，用于判断被中断的特权级
+pub fn enter_from(stack_frame: &InterruptStackFrame) -> KernelGs {
+    let from_user = stack_frame.code_segment.rpl() == PrivilegeLevel::Ring3;
+    if from_user {
+        unsafe { core::arch::asm!("swapgs", options(nostack, preserves_flags)) };
+    }
+    KernelGs(from_user)
+}
+
+impl Drop for KernelGs {
+    fn drop(&mut self) {
+        if self.0 {
+            unsafe { core::arch::asm!("swapgs", options(nostack, preserves_flags)) };
+        }
+    }
+}
+
+#[test_case]
+fn test_current_is_boot_processor() {
+    let cpu = current();
+    assert_eq!(cpu.id(), 0);
+    assert!(core::ptr::eq(cpu, &BSP));
+    assert!(get(0).is_some_and(|block| core::ptr::eq(block, cpu)));
+}
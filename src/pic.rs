@@ -0,0 +1,51 @@
+//! 本模块实现了 8259 可编程中断控制器（PIC）的初始化和重映射
+//!
+//! 硬件中断改由 APIC 投递之后，8259 的所有中断源都被屏蔽
+
+use pic8259::ChainedPics;
+use spin::Mutex;
+
+/// 主 PIC 的中断向量偏移，重映射到 CPU 异常（0~31）之后
+pub const PIC_1_OFFSET: u8 = 32;
+/// 从 PIC 的中断向量偏移
+pub const PIC_2_OFFSET: u8 = PIC_1_OFFSET + 8;
+
+/// 级联的主从 PIC
+pub static PICS: Mutex<ChainedPics> =
+    Mutex::new(unsafe { ChainedPics::new(PIC_1_OFFSET, PIC_2_OFFSET) });
+
+/// 初始化主从 PIC，并将它们的中断向量重映射到 `PIC_1_OFFSET` 之后
+pub fn init() {
+    unsafe { PICS.lock().initialize() };
+}
+
+/// 屏蔽主从 PIC 的所有中断源
+pub fn disable() {
+    unsafe { PICS.lock().disable() };
+}
+
+/// 取消屏蔽一个 ISA 中断，从 PIC 上的中断同时取消屏蔽级联的 IRQ2
+///
+/// # 参数
+///
+/// - `irq`: ISA 中断号
+pub fn unmask(irq: u8) {
+    let mut pics = PICS.lock();
+    let [mut master, mut slave] = unsafe { pics.read_masks() };
+    if irq < 8 {
+        master &= !(1 << irq);
+    } else {
+        master &= !(1 << 2);
+        slave &= !(1 << (irq - 8));
+    }
+    unsafe { pics.write_masks(master, slave) };
+}
+
+/// 向 PIC 发送中断结束（EOI）信号
+///
+/// # 参数
+///
+/// - `vector`: 已处理完毕的中断向量号
+pub fn notify_end_of_interrupt(vector: u8) {
+    unsafe { PICS.lock().notify_end_of_interrupt(vector) };
+}
@@ -0,0 +1,143 @@
+//! 本模块实现了关机和重启
+//!
+//! 关机时先按 FADT 和 DSDT 中的 `\_S5_` 向 PM1 控制寄存器写入睡眠类型和睡眠使能位，让系统进入软关机状态；
+//! 没有 ACPI 或写入没有生效时再尝试 QEMU、Bochs 和 VirtualBox 的关机端口。
+//!
+//! 重启时依次尝试 FADT 中的复位寄存器、键盘控制器的复位命令，最后用空的 IDT 引发三重错误
+
+use x86_64::PhysAddr;
+use x86_64::instructions::interrupts;
+
+use crate::acpi::{self, ADDRESS_SPACE_IO, ADDRESS_SPACE_MEMORY};
+use crate::arch::port::Port;
+use crate::{hlt_loop, memory};
+
+/// PM1 控制寄存器：已处于 ACPI 模式
+const PM1_SCI_ENABLE: u16 = 1 << 0;
+/// PM1 控制寄存器：睡眠类型字段的起始位
+const PM1_SLEEP_TYPE_SHIFT: u16 = 10;
+/// PM1 控制寄存器：睡眠使能，写入后进入睡眠类型指定的状态
+const PM1_SLEEP_ENABLE: u16 = 1 << 13;
+
+/// 等待固件切换到 ACPI 模式时最多读取 PM1 控制寄存器的次数
+const ACPI_ENABLE_POLLS: usize = 1_000_000;
+
+/// 模拟器的关机端口和写入的值：新版 QEMU、Bochs 和旧版 QEMU、VirtualBox
+const EMULATOR_SHUTDOWN_PORTS: [(u16, u16); 3] =
+    [(0x604, 0x2000), (0xb004, 0x2000), (0x4004, 0x3400)];
+
+/// 键盘控制器的状态和命令端口
+const KEYBOARD_CONTROLLER_PORT: u16 = 0x64;
+/// 键盘控制器状态：输入缓冲区已满，还不能写入命令
+const KEYBOARD_INPUT_FULL: u8 = 1 << 1;
+/// 键盘控制器命令：拉低 CPU 复位线
+const KEYBOARD_RESET_COMMAND: u8 = 0xfe;
+/// 等待键盘控制器输入缓冲区清空时最多读取状态的次数
+const KEYBOARD_POLLS: usize = 100_000;
+
+/// 关闭电源，不会返回
+///
+/// 所有方法都失败时关闭中断并停机
+pub fn shutdown() -> ! {
+    log::info!("powering off");
+    interrupts::disable();
+    if let Err(reason) = acpi_shutdown() {
+        log::warn!("ACPI power off failed: {}", reason);
+    }
+    for (port, value) in EMULATOR_SHUTDOWN_PORTS {
+        unsafe { Port::new(port).write(value) };
+    }
+    log::error!("failed to power off; halting");
+    hlt_loop()
+}
+
+/// 重启系统，不会返回
+pub fn reboot() -> ! {
+    log::info!("rebooting");
+    interrupts::disable();
+    acpi_reset();
+    keyboard_controller_reset();
+    triple_fault()
+}
+
+/// 通过 PM1 控制寄存器进入 S5 软关机状态
+///
+/// # 返回
+///
+/// 缺少所需的表或写入之后仍在运行时返回失败原因
+fn acpi_shutdown() -> Result<(), &'static str> {
+    let fadt = acpi::fadt().ok_or("no FADT")?;
+    let (sleep_a, sleep_b) = acpi::s5_sleep_type().ok_or("no \\_S5_ object in the DSDT")?;
+    if fadt.pm1a_control == 0 {
+        return Err("no PM1a control block");
+    }
+    let mut pm1a = Port::<u16>::new(fadt.pm1a_control);
+    // 固件没有切换到 ACPI 模式时，经 SMI 命令端口请求切换
+    if unsafe { pm1a.read() } & PM1_SCI_ENABLE == 0 && fadt.smi_command != 0 {
+        unsafe { Port::new(fadt.smi_command).write(fadt.acpi_enable) };
+        let enabled = (0..ACPI_ENABLE_POLLS).any(|_| unsafe { pm1a.read() } & PM1_SCI_ENABLE != 0);
+        if !enabled {
+            return Err("firmware did not enter ACPI mode");
+        }
+    }
+    let sleep = |sleep_type: u8, port: &mut Port<u16>| unsafe {
+        let value = port.read() & !(0b111 << PM1_SLEEP_TYPE_SHIFT);
+        port.write(value | (u16::from(sleep_type) << PM1_SLEEP_TYPE_SHIFT) | PM1_SLEEP_ENABLE);
+    };
+    sleep(sleep_a, &mut pm1a);
+    if let Some(pm1b) = fadt.pm1b_control {
+        sleep(sleep_b, &mut Port::new(pm1b));
+    }
+    for _ in 0..ACPI_ENABLE_POLLS {
+        core::hint::spin_loop();
+    }
+    Err("still running after entering S5")
+}
+
+/// 写入 FADT 中的复位寄存器，只支持 IO 端口和内存中的寄存器
+fn acpi_reset() {
+    let Some(fadt) = acpi::fadt() else {
+        return;
+    };
+    let Some(register) = fadt.reset_register else {
+        return;
+    };
+    match register.space {
+        ADDRESS_SPACE_IO => unsafe { Port::new(register.address as u16).write(fadt.reset_value) },
+        ADDRESS_SPACE_MEMORY => {
+            if let Some(virt) = memory::map_mmio(PhysAddr::new(register.address), 1) {
+                unsafe { virt.as_mut_ptr::<u8>().write_volatile(fadt.reset_value) };
+            }
+        }
+        _ => {}
+    }
+}
+
+/// 让键盘控制器拉低 CPU 的复位线
+fn keyboard_controller_reset() {
+    let mut controller = Port::<u8>::new(KEYBOARD_CONTROLLER_PORT);
+    for _ in 0..KEYBOARD_POLLS {
+        if unsafe { controller.read() } & KEYBOARD_INPUT_FULL == 0 {
+            break;
+        }
+    }
+    unsafe { controller.write(KEYBOARD_RESET_COMMAND) };
+    for _ in 0..KEYBOARD_POLLS {
+        core::hint::spin_loop();
+    }
+}
+
+/// 加载空的 IDT 后触发异常，处理器无法投递异常和随后的双重错误时复位
+fn triple_fault() -> ! {
+    use x86_64::instructions::tables::{DescriptorTablePointer, lidt};
+
+    let empty = DescriptorTablePointer {
+        limit: 0,
+        base: x86_64::VirtAddr::zero(),
+    };
+    unsafe {
+        lidt(&empty);
+        core::arch::asm!("int3", options(nomem, nostack));
+    }
+    hlt_loop()
+}
@@ -0,0 +1,352 @@
+//! 本模块实现了进程
+//!
+//! 每个进程拥有独立的地址空间（内核部分共享）、属于它的线程和文件描述符表。
+//! 进程的第一个线程在进程的地址空间中加载 ELF 文件，然后进入用户态
+
+pub mod file;
+pub mod futex;
+pub mod pipe;
+pub mod shm;
+pub mod signal;
+
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use spin::Mutex;
+
+use crate::elf::{self, ElfError, ElfFile};
+use crate::memory::{self, AddressSpace};
+use crate::syscall::{self, ECHILD, EINTR, ENOMEM, EPERM, SyscallFrame};
+use crate::thread::{self, ThreadId, WaitQueue};
+use file::FileTable;
+
+/// 进程编号
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Pid(u64);
+
+impl Pid {
+    /// 分配一个新的进程编号，从 1 开始
+    fn new() -> Self {
+        static NEXT_PID: AtomicU64 = AtomicU64::new(1);
+        Self(NEXT_PID.fetch_add(1, Ordering::Relaxed))
+    }
+
+    /// 从数值创建进程编号，用于系统调用的参数
+    pub(crate) fn from_u64(raw: u64) -> Self {
+        Self(raw)
+    }
+
+    /// 编号的数值
+    pub fn as_u64(self) -> u64 {
+        self.0
+    }
+}
+
+/// 进程的状态
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum State {
+    Running,     // 仍有线程在运行
+    Zombie(i64), // 已退出，资源已释放，等待父进程取走退出码
+}
+
+/// 第一个线程进入用户态之前需要的信息
+enum Start {
+    Exec { image: Vec<u8>, argv: Vec<String> }, // 加载 ELF 文件和程序参数，从入口开始运行
+    Fork(SyscallFrame),                         // 从父进程调用 `fork` 的位置返回用户态
+}
+
+/// 进程
+pub struct Process {
+    pid: Pid,
+    parent: Option<Pid>, // 父进程，由内核线程创建或父进程已退出时为 `None`
+    name: String,        // 进程名，取自 argv[0]
+    state: State,        // 进程的状态
+    space: Option<AddressSpace>, // 进程的地址空间，退出后释放
+    threads: Vec<ThreadId>, // 属于进程且尚未退出的线程
+    files: FileTable,    // 文件描述符表
+    mappings: Vec<shm::Mapping>, // 共享内存映射，映射的页随地址空间一起释放
+    signals: signal::Signals, // 待处理的信号和信号的动作
+    start: Option<Start>, // 尚未被第一个线程取走的启动信息
+}
+
+impl Process {
+    /// 进程编号
+    pub fn pid(&self) -> Pid {
+        self.pid
+    }
+
+    /// 进程名
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// 父进程
+    pub fn parent(&self) -> Option<Pid> {
+        self.parent
+    }
+
+    /// 进程的状态
+    pub fn state(&self) -> State {
+        self.state
+    }
+
+    /// 进程的地址空间，进程退出后为 `None`
+    pub fn space(&self) -> Option<&AddressSpace> {
+        self.space.as_ref()
+    }
+
+    /// 属于进程且尚未退出的线程
+    pub fn threads(&self) -> &[ThreadId] {
+        &self.threads
+    }
+
+    /// 文件描述符表
+    pub fn files(&mut self) -> &mut FileTable {
+        &mut self.files
+    }
+
+    /// 退出码，进程仍在运行时为 `None`
+    pub fn exit_code(&self) -> Option<i64> {
+        match self.state {
+            State::Running => None,
+            State::Zombie(code) => Some(code),
+        }
+    }
+}
+
+/// 进程表，僵尸进程被父进程回收之前一直保留在表中
+static PROCESSES: Mutex<BTreeMap<Pid, Process>> = Mutex::new(BTreeMap::new());
+
+/// 有进程退出时唤醒等待子进程的线程
+static EXITED: WaitQueue = WaitQueue::new();
+
+/// 访问进程表中的进程，期间禁用中断
+///
+/// # 参数
+///
+/// - `pid`: 进程编号
+/// - `f`: 访问进程的闭包
+///
+/// # 返回
+///
+/// 闭包的返回值，进程不存在时返回 `None`
+pub fn with_process<R>(pid: Pid, f: impl FnOnce(&mut Process) -> R) -> Option<R> {
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| PROCESSES.lock().get_mut(&pid).map(f))
+}
+
+/// 访问当前线程所属的进程
+///
+/// # 参数
+///
+/// - `f`: 访问进程的闭包
+///
+/// # 返回
+///
+/// 闭包的返回值，当前线程是内核线程时返回 `None`
+pub fn with_current<R>(f: impl FnOnce(&mut Process) -> R) -> Option<R> {
+    with_process(thread::current_process()?, f)
+}
+
+/// 从 ELF 文件创建一个进程
+///
+/// 文件头、段和入口地址在创建时校验，段的加载在进程的第一个线程中进行。
+/// 由内核线程从控制台创建的进程成为前台进程，接收 Ctrl+C 产生的 [`signal::SIGINT`]
+///
+/// # 参数
+///
+/// - `image`: 内存中的 ELF 文件
+/// - `argv`: 程序参数，`argv[0]` 作为进程名
+pub fn spawn(image: &[u8], argv: &[&str]) -> Result<Pid, ElfError> {
+    use x86_64::instructions::interrupts;
+
+    elf::check_segments(&ElfFile::parse(image)?)?;
+    let space = AddressSpace::new().ok_or(ElfError::OutOfMemory)?;
+    let page_table = space.level_4_frame();
+    let pid = Pid::new();
+    let parent = thread::current_process();
+    let process = Process {
+        pid,
+        parent,
+        name: argv
+            .first()
+            .map_or_else(String::new, |name| String::from(*name)),
+        state: State::Running,
+        space: Some(space),
+        threads: Vec::new(),
+        files: FileTable::with_console(),
+        mappings: Vec::new(),
+        signals: signal::Signals::new(),
+        start: Some(Start::Exec {
+            image: image.to_vec(),
+            argv: argv.iter().map(|arg| String::from(*arg)).collect(),
+        }),
+    };
+    // 线程可能立即被调度，必须先把进程放进进程表
+    interrupts::without_interrupts(|| {
+        let mut processes = PROCESSES.lock();
+        processes.insert(pid, process);
+        let thread = thread::spawn_in_process(start_user, pid, page_table);
+        processes.get_mut(&pid).unwrap().threads.push(thread);
+    });
+    if parent.is_none() {
+        signal::set_foreground(Some(pid));
+    }
+    Ok(pid)
+}
+
+/// 复制当前进程，子进程从同一个系统调用返回，返回值为 0
+///
+/// 子进程得到地址空间的完整副本和文件描述符表的副本，打开的文件在父子进程之间共享
+///
+/// # 参数
+///
+/// - `frame`: 父进程进入系统调用时保存的用户态寄存器
+///
+/// # 返回
+///
+/// 子进程的编号；当前线程不属于进程时返回 `EPERM`，帧耗尽时返回 `ENOMEM`
+pub fn fork(frame: &SyscallFrame) -> Result<Pid, i64> {
+    use x86_64::instructions::interrupts;
+
+    let parent = thread::current_process().ok_or(EPERM)?;
+    let mut child_frame = *frame;
+    child_frame.rax = 0;
+    // 复制期间父进程的线程不能修改地址空间，子进程也必须先放进进程表再被调度
+    interrupts::without_interrupts(|| {
+        let mut processes = PROCESSES.lock();
+        let process = processes.get(&parent).expect("current process is missing");
+        let space = process
+            .space
+            .as_ref()
+            .and_then(AddressSpace::duplicate)
+            .ok_or(ENOMEM)?;
+        let page_table = space.level_4_frame();
+        let pid = Pid::new();
+        let child = Process {
+            pid,
+            parent: Some(parent),
+            name: process.name.clone(),
+            state: State::Running,
+            space: Some(space),
+            threads: Vec::new(),
+            files: process.files.clone(),
+            mappings: process.mappings.clone(),
+            signals: process.signals.inherit(),
+            start: Some(Start::Fork(child_frame)),
+        };
+        processes.insert(pid, child);
+        let thread = thread::spawn_in_process(start_user, pid, page_table);
+        processes.get_mut(&pid).unwrap().threads.push(thread);
+        Ok(pid)
+    })
+}
+
+/// 进程第一个线程的入口，此时已运行在进程的地址空间中
+fn start_user() {
+    let start = with_current(|process| process.start.take())
+        .flatten()
+        .expect("process started without start information");
+    match start {
+        Start::Exec { image, argv } => {
+            let argv: Vec<&str> = argv.iter().map(String::as_str).collect();
+            let error = elf::exec(&image, &argv);
+            log::error!("failed to start {}: {}", argv.first().unwrap_or(&""), error);
+            exit_current(-1)
+        }
+        Start::Fork(frame) => unsafe { syscall::return_to_user(&frame) },
+    }
+}
+
+/// 结束当前线程；属于进程时结束整个进程，释放它的资源并转为僵尸进程
+///
+/// # 参数
+///
+/// - `code`: 退出码
+pub fn exit_current(code: i64) -> ! {
+    use x86_64::instructions::interrupts;
+    use x86_64::registers::control::{Cr3, Cr3Flags};
+
+    let id = thread::current_id();
+    let Some(pid) = thread::current_process() else {
+        log::info!("thread {} exited with status {}", id.as_u64(), code);
+        thread::exit()
+    };
+
+    // 从这里开始不能再被抢占：线程被切换回来时会重新加载即将释放的页表。
+    // 线程不会再返回，中断状态由下一个线程恢复
+    interrupts::disable();
+    unsafe { Cr3::write(memory::kernel_page_table(), Cr3Flags::empty()) };
+    let (space, files) = {
+        let mut processes = PROCESSES.lock();
+        // 子进程交给内核回收
+        for child in processes.values_mut().filter(|p| p.parent == Some(pid)) {
+            child.parent = None;
+        }
+        let process = processes.get_mut(&pid).expect("current process is missing");
+        process.threads.retain(|&thread| thread != id);
+        process.state = State::Zombie(code);
+        (process.space.take(), core::mem::take(&mut process.files))
+    };
+    // 在进程表的锁之外关闭文件，关闭时可能唤醒其他线程
+    drop(files);
+    if let Some(space) = space {
+        space.destroy();
+    }
+    signal::release_foreground(pid);
+    log::info!("process {} exited with status {}", pid.as_u64(), code);
+    EXITED.wake_all();
+    thread::exit()
+}
+
+/// 等待当前进程的子进程退出，并回收它
+///
+/// 内核线程创建的进程没有父进程，由内核线程等待
+///
+/// # 参数
+///
+/// - `pid`: 要等待的子进程，为 `None` 时等待任意一个子进程
+///
+/// # 返回
+///
+/// 被回收的子进程和它的退出码；没有符合条件的子进程时返回 `ECHILD`，
+/// 等待时收到信号返回 `EINTR`
+pub fn wait(pid: Option<Pid>) -> Result<(Pid, i64), i64> {
+    use x86_64::instructions::interrupts;
+
+    let parent = thread::current_process();
+    // 检查子进程和挂起之间禁用中断，子进程的退出通知不会丢失
+    interrupts::without_interrupts(|| {
+        loop {
+            {
+                let mut processes = PROCESSES.lock();
+                let mut found = false;
+                let mut zombie = None;
+                for child in processes
+                    .values()
+                    .filter(|p| p.parent == parent && pid.is_none_or(|pid| p.pid == pid))
+                {
+                    found = true;
+                    if let State::Zombie(code) = child.state {
+                        zombie = Some((child.pid, code));
+                        break;
+                    }
+                }
+                if let Some((child, code)) = zombie {
+                    processes.remove(&child);
+                    return Ok((child, code));
+                }
+                if !found {
+                    return Err(ECHILD);
+                }
+            }
+            if signal::pending() {
+                return Err(EINTR);
+            }
+            EXITED.block_current();
+        }
+    })
+}
@@ -0,0 +1,144 @@
+//! 本模块实现了进程的文件描述符表
+
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use crate::print;
+use crate::syscall::{EBADF, EINVAL};
+
+/// 文件描述符的上限，[`FileTable::duplicate`] 的目标不能超过它
+pub const MAX_FILES: usize = 256;
+
+/// 文件描述符可以引用的内核对象
+///
+/// 出错时返回正的错误码，由系统调用层取负后交给用户程序
+pub trait File: Send + Sync {
+    /// 读取数据到 `buf` 中
+    ///
+    /// # 返回
+    ///
+    /// 读取的字节数
+    fn read(&self, _buf: &mut [u8]) -> Result<usize, i64> {
+        Err(EBADF)
+    }
+
+    /// 写入 `buf` 中的数据
+    ///
+    /// # 返回
+    ///
+    /// 写入的字节数
+    fn write(&self, _buf: &[u8]) -> Result<usize, i64> {
+        Err(EBADF)
+    }
+}
+
+/// 控制台，写入的数据输出到所有启用的控制台输出端
+pub struct Console;
+
+impl File for Console {
+    fn write(&self, buf: &[u8]) -> Result<usize, i64> {
+        let s = core::str::from_utf8(buf).map_err(|_| EINVAL)?;
+        print!("{}", s);
+        Ok(buf.len())
+    }
+}
+
+/// 文件描述符表，下标即文件描述符
+#[derive(Clone, Default)]
+pub struct FileTable {
+    files: Vec<Option<Arc<dyn File>>>,
+}
+
+impl FileTable {
+    /// 创建空的文件描述符表
+    pub const fn new() -> Self {
+        Self { files: Vec::new() }
+    }
+
+    /// 创建标准输入、标准输出和标准错误都指向控制台的文件描述符表
+    pub fn with_console() -> Self {
+        let console: Arc<dyn File> = Arc::new(Console);
+        Self {
+            files: alloc::vec![Some(console.clone()), Some(console.clone()), Some(console)],
+        }
+    }
+
+    /// 查找文件描述符引用的对象
+    ///
+    /// # 参数
+    ///
+    /// - `fd`: 文件描述符
+    pub fn get(&self, fd: u64) -> Option<Arc<dyn File>> {
+        self.files.get(usize::try_from(fd).ok()?)?.clone()
+    }
+
+    /// 分配最小的空闲文件描述符
+    ///
+    /// # 参数
+    ///
+    /// - `file`: 文件描述符引用的对象
+    ///
+    /// # 返回
+    ///
+    /// 新的文件描述符
+    pub fn insert(&mut self, file: Arc<dyn File>) -> u64 {
+        match self.files.iter().position(Option::is_none) {
+            Some(fd) => {
+                self.files[fd] = Some(file);
+                fd as u64
+            }
+            None => {
+                self.files.push(Some(file));
+                (self.files.len() - 1) as u64
+            }
+        }
+    }
+
+    /// 让文件描述符 `new` 引用 `old` 引用的对象，`new` 原本打开时先关闭它
+    ///
+    /// # 参数
+    ///
+    /// - `old`: 已打开的文件描述符
+    /// - `new`: 目标文件描述符，小于 [`MAX_FILES`]
+    ///
+    /// # 返回
+    ///
+    /// `old` 未打开或 `new` 超出上限时返回 `false`
+    pub fn duplicate(&mut self, old: u64, new: u64) -> bool {
+        let Some(file) = self.get(old) else {
+            return false;
+        };
+        let Some(new) = usize::try_from(new).ok().filter(|&new| new < MAX_FILES) else {
+            return false;
+        };
+        if self.files.len() <= new {
+            self.files.resize(new + 1, None);
+        }
+        self.files[new] = Some(file);
+        true
+    }
+
+    /// 关闭文件描述符
+    ///
+    /// # 参数
+    ///
+    /// - `fd`: 文件描述符
+    ///
+    /// # 返回
+    ///
+    /// 文件描述符原本是否打开
+    pub fn close(&mut self, fd: u64) -> bool {
+        let Some(slot) = usize::try_from(fd)
+            .ok()
+            .and_then(|fd| self.files.get_mut(fd))
+        else {
+            return false;
+        };
+        slot.take().is_some()
+    }
+
+    /// 关闭所有文件描述符
+    pub fn clear(&mut self) {
+        self.files.clear();
+    }
+}
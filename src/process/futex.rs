@@ -0,0 +1,110 @@
+//! 本模块实现了快速用户态互斥量（futex）的等待和唤醒
+//!
+//! 用户程序在无竞争时只用原子指令操作内存中的一个 32 位字，发生竞争时才进入内核挂起。
+//! 等待队列以字所在的物理地址为键，映射了同一段共享内存的进程即使虚拟地址不同也能互相唤醒
+
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+use x86_64::structures::paging::{Page, Translate};
+use x86_64::{PhysAddr, VirtAddr};
+
+use super::signal;
+use crate::memory;
+use crate::syscall::{EAGAIN, EFAULT, EINTR, EINVAL};
+use crate::thread::WaitQueue;
+use crate::user;
+
+/// 有线程等待的字的物理地址和它们的等待队列
+static FUTEXES: Mutex<BTreeMap<PhysAddr, Arc<WaitQueue>>> = Mutex::new(BTreeMap::new());
+
+/// 查找当前地址空间中 `addr` 处的字对应的物理地址
+///
+/// 写时复制的页先换成独占的副本，否则写入前后同一个字的物理地址会改变
+fn physical_addr(addr: VirtAddr) -> Result<PhysAddr, i64> {
+    if !addr.is_aligned(4u64) {
+        return Err(EINVAL);
+    }
+    if !user::is_accessible(addr.as_u64(), 4, true) {
+        return Err(EFAULT);
+    }
+    memory::with_active_mapper(|mapper, frame_allocator| {
+        memory::resolve_copy_on_write(mapper, frame_allocator, Page::containing_address(addr))
+            .ok()?;
+        mapper.translate_addr(addr)
+    })
+    .flatten()
+    .ok_or(EFAULT)
+}
+
+/// `addr` 处的值仍为 `expected` 时挂起当前线程，直到被 [`wake`] 唤醒
+///
+/// # 参数
+///
+/// - `addr`: 用户空间中按 4 字节对齐的字
+/// - `expected`: 期望的值
+///
+/// # 返回
+///
+/// 值已经改变时返回 `EAGAIN`，地址不可访问时返回 `EFAULT`，被信号打断时返回 `EINTR`
+pub fn wait(addr: VirtAddr, expected: u32) -> Result<(), i64> {
+    interrupts::without_interrupts(|| {
+        let phys = physical_addr(addr)?;
+        if signal::pending() {
+            return Err(EINTR);
+        }
+        // 持有 FUTEXES 期间建立队列并读取值：其他处理器改变值之后调用的 `wake` 一定能找到这个队列，
+        // 在释放锁和挂起之间到达的唤醒由队列记下，不会丢失
+        let queue = {
+            let mut futexes = FUTEXES.lock();
+            let value = unsafe { addr.as_ptr::<u32>().read_volatile() };
+            if value != expected {
+                return Err(EAGAIN);
+            }
+            futexes.entry(phys).or_default().clone()
+        };
+        queue.block_current();
+        if !signal::pending() {
+            return Ok(());
+        }
+        // 被信号唤醒时没有经过 `wake`，由等待者移除空的队列；
+        // 其他等待者可能已经取得队列、还没有挂起，此时队列要保留
+        let mut futexes = FUTEXES.lock();
+        if futexes.get(&phys).is_some_and(|entry| {
+            Arc::ptr_eq(entry, &queue) && Arc::strong_count(&queue) == 2 && queue.is_empty()
+        }) {
+            futexes.remove(&phys);
+        }
+        Err(EINTR)
+    })
+}
+
+/// 唤醒最多 `count` 个在 `addr` 上等待的线程
+///
+/// # 参数
+///
+/// - `addr`: 用户空间中按 4 字节对齐的字
+/// - `count`: 最多唤醒的线程数
+///
+/// # 返回
+///
+/// 被唤醒的线程数
+pub fn wake(addr: VirtAddr, count: u64) -> Result<u64, i64> {
+    interrupts::without_interrupts(|| {
+        let phys = physical_addr(addr)?;
+        let mut futexes = FUTEXES.lock();
+        let Some(queue) = futexes.get(&phys) else {
+            return Ok(0);
+        };
+        let mut woken = 0;
+        while woken < count && queue.wake_one() {
+            woken += 1;
+        }
+        if queue.is_empty() {
+            futexes.remove(&phys);
+        }
+        Ok(woken)
+    })
+}
@@ -0,0 +1,132 @@
+//! 本模块实现了管道
+//!
+//! 管道是内核中的一个有界字节缓冲区，读端和写端分别作为文件放进文件描述符表。
+//! 缓冲区为空时读者挂起，已满时写者挂起；写端关闭后读完剩余数据返回 0（EOF），
+//! 读端关闭后写入返回 `EPIPE`
+
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+use super::file::File;
+use super::signal;
+use crate::syscall::{EINTR, EPIPE};
+use crate::thread::WaitQueue;
+
+/// 管道缓冲区的容量
+pub const PIPE_CAPACITY: usize = 4096;
+
+/// 读端和写端共享的管道状态
+struct Pipe {
+    buffer: Mutex<VecDeque<u8>>, // 已写入、尚未读取的数据
+    reader_closed: AtomicBool,   // 读端是否已关闭
+    writer_closed: AtomicBool,   // 写端是否已关闭
+    readable: WaitQueue,         // 等待数据的读者
+    writable: WaitQueue,         // 等待空间的写者
+}
+
+/// 管道的读端，引用它的文件描述符全部关闭时读端关闭
+pub struct PipeReader(Arc<Pipe>);
+
+/// 管道的写端，引用它的文件描述符全部关闭时写端关闭
+pub struct PipeWriter(Arc<Pipe>);
+
+/// 创建一个管道
+///
+/// # 返回
+///
+/// 管道的读端和写端
+pub fn pipe() -> (PipeReader, PipeWriter) {
+    let pipe = Arc::new(Pipe {
+        buffer: Mutex::new(VecDeque::with_capacity(PIPE_CAPACITY)),
+        reader_closed: AtomicBool::new(false),
+        writer_closed: AtomicBool::new(false),
+        readable: WaitQueue::new(),
+        writable: WaitQueue::new(),
+    });
+    (PipeReader(pipe.clone()), PipeWriter(pipe))
+}
+
+impl File for PipeReader {
+    /// 读取缓冲区中已有的数据，没有数据时挂起，直到有数据写入或写端关闭；
+    /// 挂起时收到信号返回 `EINTR`
+    fn read(&self, buf: &mut [u8]) -> Result<usize, i64> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let pipe = &self.0;
+        // 检查缓冲区和挂起之间禁用中断，写者的唤醒不会丢失
+        interrupts::without_interrupts(|| {
+            loop {
+                {
+                    let mut buffer = pipe.buffer.lock();
+                    if !buffer.is_empty() {
+                        let n = buf.len().min(buffer.len());
+                        for (dst, src) in buf.iter_mut().zip(buffer.drain(..n)) {
+                            *dst = src;
+                        }
+                        pipe.writable.wake_all();
+                        return Ok(n);
+                    }
+                }
+                if pipe.writer_closed.load(Ordering::Acquire) {
+                    return Ok(0);
+                }
+                if signal::pending() {
+                    return Err(EINTR);
+                }
+                pipe.readable.block_current();
+            }
+        })
+    }
+}
+
+impl File for PipeWriter {
+    /// 写入全部数据，缓冲区已满时挂起，直到读者取走数据；
+    /// 挂起时收到信号返回已写入的字节数，还没有写入时返回 `EINTR`。
+    /// 写入部分数据后读端关闭时返回已写入的字节数，下一次写入再返回 `EPIPE`
+    fn write(&self, buf: &[u8]) -> Result<usize, i64> {
+        let pipe = &self.0;
+        let mut written = 0;
+        interrupts::without_interrupts(|| {
+            while written < buf.len() {
+                if pipe.reader_closed.load(Ordering::Acquire) {
+                    return if written > 0 { Ok(written) } else { Err(EPIPE) };
+                }
+                {
+                    let mut buffer = pipe.buffer.lock();
+                    let n = (PIPE_CAPACITY - buffer.len()).min(buf.len() - written);
+                    if n > 0 {
+                        buffer.extend(&buf[written..written + n]);
+                        written += n;
+                        pipe.readable.wake_all();
+                        continue;
+                    }
+                }
+                // 被信号打断时返回已写入的字节数
+                if signal::pending() {
+                    return if written > 0 { Ok(written) } else { Err(EINTR) };
+                }
+                pipe.writable.block_current();
+            }
+            Ok(written)
+        })
+    }
+}
+
+impl Drop for PipeReader {
+    fn drop(&mut self) {
+        self.0.reader_closed.store(true, Ordering::Release);
+        self.0.writable.wake_all();
+    }
+}
+
+impl Drop for PipeWriter {
+    fn drop(&mut self) {
+        self.0.writer_closed.store(true, Ordering::Release);
+        self.0.readable.wake_all();
+    }
+}
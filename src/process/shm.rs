@@ -0,0 +1,201 @@
+//! 本模块实现了命名的共享内存段
+//!
+//! 共享内存段由一组物理帧组成，按名称创建或打开，可以映射到多个进程的地址空间中。
+//! 段本身持有每个帧的一个引用，每次映射再增加一个引用，因此删除名称或进程退出后，
+//! 帧在最后一个引用释放时才被回收。共享页带有 [`SHARED`](memory::SHARED) 标志，
+//! `fork` 时不会改为写时复制
+
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use spin::Mutex;
+use x86_64::VirtAddr;
+use x86_64::instructions::interrupts;
+use x86_64::structures::paging::{
+    FrameAllocator, FrameDeallocator, Mapper, Page, PageTableFlags, PhysFrame, Translate,
+};
+
+use crate::syscall::{EINVAL, ENOENT, ENOMEM, EPERM};
+use crate::user::{USER_SPACE_END, USER_SPACE_START};
+use crate::{memory, smp, thread};
+
+/// 页大小
+const PAGE_SIZE: u64 = 4096;
+
+/// 单个共享内存段的最大页数
+pub const MAX_SEGMENT_PAGES: u64 = 1024;
+
+/// 共享内存段
+struct Segment {
+    name: String,           // 段的名称
+    frames: Vec<PhysFrame>, // 段的物理帧，段持有每个帧的一个引用
+}
+
+impl Drop for Segment {
+    fn drop(&mut self) {
+        memory::with_kernel_memory(|_, frame_allocator| {
+            for &frame in &self.frames {
+                unsafe { frame_allocator.deallocate_frame(frame) };
+            }
+        });
+    }
+}
+
+/// 进程中的一个共享内存映射
+#[derive(Debug, Clone, Copy)]
+pub struct Mapping {
+    start: VirtAddr, // 映射的起始地址
+    pages: u64,      // 映射的页数
+}
+
+/// 按编号索引的共享内存段
+static SEGMENTS: Mutex<BTreeMap<u64, Segment>> = Mutex::new(BTreeMap::new());
+
+/// 打开一个共享内存段，不存在时创建并清零
+///
+/// # 参数
+///
+/// - `name`: 段的名称
+/// - `size`: 段的字节数，向上取整到页；打开已有的段时不能超过它的大小
+///
+/// # 返回
+///
+/// 段的编号
+pub fn open(name: &str, size: u64) -> Result<u64, i64> {
+    let pages = size.div_ceil(PAGE_SIZE);
+    if pages == 0 || pages > MAX_SEGMENT_PAGES {
+        return Err(EINVAL);
+    }
+    interrupts::without_interrupts(|| {
+        let mut segments = SEGMENTS.lock();
+        if let Some((&id, segment)) = segments.iter().find(|(_, s)| s.name == name) {
+            return if pages <= segment.frames.len() as u64 {
+                Ok(id)
+            } else {
+                Err(EINVAL)
+            };
+        }
+        let mut segment = Segment {
+            name: String::from(name),
+            frames: Vec::new(),
+        };
+        memory::with_kernel_memory(|mapper, frame_allocator| {
+            let phys_offset = mapper.phys_offset();
+            for _ in 0..pages {
+                let frame = frame_allocator.allocate_frame()?;
+                let virt = phys_offset + frame.start_address().as_u64();
+                unsafe { core::ptr::write_bytes(virt.as_mut_ptr::<u8>(), 0, PAGE_SIZE as usize) };
+                segment.frames.push(frame);
+            }
+            Some(())
+        })
+        .flatten()
+        // 已分配的帧随段一起释放
+        .ok_or(ENOMEM)?;
+        static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        segments.insert(id, segment);
+        Ok(id)
+    })
+}
+
+/// 删除共享内存段的名称，已有的映射在解除之前仍然有效
+///
+/// # 参数
+///
+/// - `name`: 段的名称
+pub fn unlink(name: &str) -> Result<(), i64> {
+    interrupts::without_interrupts(|| {
+        let mut segments = SEGMENTS.lock();
+        let id = segments
+            .iter()
+            .find(|(_, s)| s.name == name)
+            .map(|(&id, _)| id)
+            .ok_or(ENOENT)?;
+        segments.remove(&id);
+        Ok(())
+    })
+}
+
+/// 将共享内存段映射到当前进程的 `addr` 处
+///
+/// # 参数
+///
+/// - `id`: 段的编号
+/// - `addr`: 映射的起始地址，需要按页对齐，整个区域位于用户空间且尚未映射
+pub fn map(id: u64, addr: VirtAddr) -> Result<(), i64> {
+    let pid = thread::current_process().ok_or(EPERM)?;
+    let flags = PageTableFlags::PRESENT
+        | PageTableFlags::USER_ACCESSIBLE
+        | PageTableFlags::WRITABLE
+        | PageTableFlags::NO_EXECUTE
+        | memory::SHARED;
+    interrupts::without_interrupts(|| {
+        let segments = SEGMENTS.lock();
+        let segment = segments.get(&id).ok_or(EINVAL)?;
+        let pages = segment.frames.len() as u64;
+        let start = addr.as_u64();
+        // 地址由用户程序给出，计算区域末尾时不能溢出
+        let end = pages
+            .checked_mul(PAGE_SIZE)
+            .and_then(|size| start.checked_add(size))
+            .ok_or(EINVAL)?;
+        if !addr.is_aligned(PAGE_SIZE) || start < USER_SPACE_START || end > USER_SPACE_END {
+            return Err(EINVAL);
+        }
+        memory::with_active_mapper(|mapper, frame_allocator| {
+            let first = Page::containing_address(addr);
+            let range = Page::range(first, first + pages);
+            if range
+                .clone()
+                .any(|page| mapper.translate_addr(page.start_address()).is_some())
+            {
+                return Err(EINVAL);
+            }
+            for (page, &frame) in range.zip(&segment.frames) {
+                frame_allocator.share(frame);
+                let Ok(flush) = (unsafe { mapper.map_to(page, frame, flags, frame_allocator) })
+                else {
+                    // 已映射的页留给进程退出时释放
+                    unsafe { frame_allocator.deallocate_frame(frame) };
+                    return Err(ENOMEM);
+                };
+                flush.flush();
+            }
+            Ok(())
+        })
+        .ok_or(ENOMEM)??;
+        super::with_process(pid, |process| {
+            process.mappings.push(Mapping { start: addr, pages })
+        });
+        Ok(())
+    })
+}
+
+/// 解除当前进程中从 `addr` 开始的共享内存映射
+///
+/// # 参数
+///
+/// - `addr`: [`map`] 时使用的起始地址
+pub fn unmap(addr: VirtAddr) -> Result<(), i64> {
+    let mapping = super::with_current(|process| {
+        let index = process.mappings.iter().position(|m| m.start == addr)?;
+        Some(process.mappings.swap_remove(index))
+    })
+    .ok_or(EPERM)?
+    .ok_or(EINVAL)?;
+    memory::with_active_mapper(|mapper, frame_allocator| {
+        let first = Page::containing_address(mapping.start);
+        for page in Page::range(first, first + mapping.pages) {
+            if let Ok((frame, flush)) = mapper.unmap(page) {
+                // 其他处理器不再访问这个帧之后才能回收
+                flush.ignore();
+                smp::tlb::flush(page);
+                unsafe { frame_allocator.deallocate_frame(frame) };
+            }
+        }
+    });
+    Ok(())
+}
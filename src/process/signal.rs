@@ -0,0 +1,281 @@
+//! 本模块实现了向进程异步投递的信号
+//!
+//! 发送的信号先记录在目标进程的待处理集合中，进程下一次从系统调用返回用户态时处理：
+//! 默认动作是结束进程，退出码为 128 加信号编号；注册了处理函数时，内核把被打断的寄存器保存在
+//! 用户栈上，让进程从处理函数开始执行，处理函数返回到注册时给出的恢复函数，
+//! 由它调用 `sigreturn` 恢复原来的寄存器。
+//!
+//! 正在用户态运行、不进行系统调用的进程在时钟中断时检查待处理的信号，但只执行默认动作；
+//! 挂起在系统调用中的进程会被唤醒，系统调用返回 `EINTR`
+
+use core::mem::size_of;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use x86_64::VirtAddr;
+
+use super::{Pid, State};
+use crate::syscall::{EFAULT, EINVAL, ESRCH, SyscallFrame};
+use crate::thread;
+use crate::user::{self, USER_SPACE_END};
+
+/// 中断信号，在键盘上按下 Ctrl+C 时发送给前台进程
+pub const SIGINT: u64 = 2;
+/// 非法指令信号，用户态执行无效的指令（#UD）时结束进程
+pub const SIGILL: u64 = 4;
+/// 强制结束信号，不能被忽略或处理
+pub const SIGKILL: u64 = 9;
+/// 用户自定义信号
+pub const SIGUSR1: u64 = 10;
+/// 段错误信号，用户态访问无效的内存或触发一般保护错误时结束进程
+pub const SIGSEGV: u64 = 11;
+/// 用户自定义信号
+pub const SIGUSR2: u64 = 12;
+/// 请求结束信号
+pub const SIGTERM: u64 = 15;
+
+/// 信号编号的上界，有效的信号为 1..NSIG
+pub const NSIG: u64 = 32;
+
+/// `sigaction` 的处理函数参数：恢复默认动作
+pub const SIG_DFL: u64 = 0;
+/// `sigaction` 的处理函数参数：忽略信号
+pub const SIG_IGN: u64 = 1;
+
+/// 跳过用户栈上的红区，处理函数不能覆盖被打断的函数在栈顶之下保存的数据
+const RED_ZONE: u64 = 128;
+
+/// 恢复用户态寄存器时允许保留的 RFLAGS 位：CF、PF、AF、ZF、SF、DF 和 OF
+const USER_RFLAGS: u64 = 0xcd5;
+/// 返回用户态时总是置位的 RFLAGS 位：IF 和保留位 1
+const REQUIRED_RFLAGS: u64 = 0x202;
+
+/// 收到信号时的动作
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Action {
+    Default,                               // 结束进程
+    Ignore,                                // 丢弃信号
+    Handler { entry: u64, restorer: u64 }, // 调用用户态的处理函数
+}
+
+/// 进程的信号状态
+#[derive(Debug, Clone)]
+pub struct Signals {
+    pending: u32,                     // 待处理的信号，第 n 位对应编号为 n 的信号
+    actions: [Action; NSIG as usize], // 每个信号的动作
+}
+
+impl Signals {
+    /// 创建没有待处理信号、所有信号都执行默认动作的状态
+    pub const fn new() -> Self {
+        Self {
+            pending: 0,
+            actions: [Action::Default; NSIG as usize],
+        }
+    }
+
+    /// `fork` 时复制给子进程的状态：继承动作，不继承待处理的信号
+    pub fn inherit(&self) -> Self {
+        Self {
+            pending: 0,
+            actions: self.actions,
+        }
+    }
+
+    /// 取出编号最小的待处理信号和它的动作，被忽略的信号直接丢弃
+    fn take(&mut self) -> Option<(u64, Action)> {
+        while self.pending != 0 {
+            let signal = u64::from(self.pending.trailing_zeros());
+            self.pending &= !(1 << signal);
+            match self.actions[signal as usize] {
+                Action::Ignore => continue,
+                action => return Some((signal, action)),
+            }
+        }
+        None
+    }
+
+    /// 取出编号最小的待处理且执行默认动作的信号，其他信号留给下一次系统调用处理
+    fn take_fatal(&mut self) -> Option<u64> {
+        let signal = (1..NSIG).find(|&signal| {
+            self.pending & (1 << signal) != 0 && self.actions[signal as usize] == Action::Default
+        })?;
+        self.pending &= !(1 << signal);
+        Some(signal)
+    }
+}
+
+impl Default for Signals {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// 校验信号编号
+fn check(signal: u64) -> Result<(), i64> {
+    if (1..NSIG).contains(&signal) {
+        Ok(())
+    } else {
+        Err(EINVAL)
+    }
+}
+
+/// 向进程发送信号
+///
+/// # 参数
+///
+/// - `pid`: 目标进程
+/// - `signal`: 信号编号
+///
+/// # 返回
+///
+/// 进程不存在或已经退出时返回 `ESRCH`
+pub fn kill(pid: Pid, signal: u64) -> Result<(), i64> {
+    check(signal)?;
+    let threads = super::with_process(pid, |process| {
+        if process.state != State::Running {
+            return Err(ESRCH);
+        }
+        process.signals.pending |= 1 << signal;
+        Ok(process.threads.clone())
+    })
+    .ok_or(ESRCH)??;
+    // 唤醒挂起的线程，阻塞的系统调用看到信号后返回 `EINTR`
+    for thread in threads {
+        thread::interrupt(thread);
+    }
+    Ok(())
+}
+
+/// 当前进程是否有没被忽略的待处理信号，阻塞的系统调用据此提前返回 `EINTR`
+pub(crate) fn pending() -> bool {
+    super::with_current(|process| {
+        let signals = &process.signals;
+        (1..NSIG).any(|signal| {
+            signals.pending & (1 << signal) != 0
+                && signals.actions[signal as usize] != Action::Ignore
+        })
+    })
+    .unwrap_or(false)
+}
+
+/// 设置当前进程收到信号时的动作
+///
+/// # 参数
+///
+/// - `signal`: 信号编号，不能是 [`SIGKILL`]
+/// - `handler`: [`SIG_DFL`]、[`SIG_IGN`] 或处理函数的地址，处理函数的参数为信号编号
+/// - `restorer`: 处理函数返回到的地址，那里的代码需要调用 `sigreturn`
+pub fn set_action(signal: u64, handler: u64, restorer: u64) -> Result<(), i64> {
+    check(signal)?;
+    if signal == SIGKILL {
+        return Err(EINVAL);
+    }
+    let action = match handler {
+        SIG_DFL => Action::Default,
+        SIG_IGN => Action::Ignore,
+        entry if entry < USER_SPACE_END && restorer < USER_SPACE_END => {
+            Action::Handler { entry, restorer }
+        }
+        _ => return Err(EFAULT),
+    };
+    super::with_current(|process| process.signals.actions[signal as usize] = action).ok_or(EINVAL)
+}
+
+/// 在系统调用返回用户态之前处理当前进程待处理的信号
+///
+/// 默认动作直接结束进程；有处理函数时修改 `frame`，让系统调用返回到处理函数
+///
+/// # 参数
+///
+/// - `frame`: 即将恢复的用户态寄存器
+pub(crate) fn deliver(frame: &mut SyscallFrame) {
+    let Some((signal, action)) = super::with_current(|process| process.signals.take()).flatten()
+    else {
+        return;
+    };
+    let Action::Handler { entry, restorer } = action else {
+        super::exit_current(128 + signal as i64)
+    };
+    // 栈上从低到高依次为恢复函数的地址和被打断的寄存器，处理函数入口处的栈满足 16 字节对齐的约定
+    let below = RED_ZONE + size_of::<SyscallFrame>() as u64;
+    let saved = frame.rsp.wrapping_sub(below) & !0xf;
+    let sp = saved.wrapping_sub(8);
+    let bytes = unsafe {
+        core::slice::from_raw_parts(
+            (frame as *const SyscallFrame).cast::<u8>(),
+            size_of::<SyscallFrame>(),
+        )
+    };
+    if !user::is_accessible(sp, 8 + bytes.len() as u64, true)
+        || !user::copy_to(VirtAddr::new(sp), &restorer.to_le_bytes())
+        || !user::copy_to(VirtAddr::new(saved), bytes)
+    {
+        // 无法在用户栈上保存寄存器，只能结束进程
+        super::exit_current(128 + signal as i64)
+    }
+    frame.rip = entry;
+    frame.rsp = sp;
+    frame.rdi = signal;
+}
+
+/// 从信号处理函数返回，恢复 [`deliver`] 保存在用户栈上的寄存器
+///
+/// # 参数
+///
+/// - `frame`: `sigreturn` 系统调用的寄存器，其中的栈指针指向保存的寄存器
+///
+/// # 返回
+///
+/// 被打断的系统调用的返回值；保存的寄存器不可访问时结束进程
+pub(crate) fn sigreturn(frame: &mut SyscallFrame) -> i64 {
+    let len = size_of::<SyscallFrame>() as u64;
+    if !user::is_accessible(frame.rsp, len, false) {
+        super::exit_current(128 + SIGKILL as i64)
+    }
+    let mut saved = unsafe { (frame.rsp as *const SyscallFrame).read_unaligned() };
+    // `sysret` 到非规范地址会在内核态触发异常，返回地址和栈指针必须位于用户空间
+    if saved.rip >= USER_SPACE_END || saved.rsp >= USER_SPACE_END {
+        super::exit_current(128 + SIGKILL as i64)
+    }
+    saved.rflags = (saved.rflags & USER_RFLAGS) | REQUIRED_RFLAGS;
+    *frame = saved;
+    saved.rax as i64
+}
+
+/// 时钟中断打断用户态时调用，有待处理的致命信号时结束当前进程
+pub(crate) fn check_fatal() {
+    if let Some(signal) = super::with_current(|process| process.signals.take_fatal()).flatten() {
+        super::exit_current(128 + signal as i64)
+    }
+}
+
+/// 前台进程的编号，为 0 时没有前台进程
+static FOREGROUND: AtomicU64 = AtomicU64::new(0);
+
+/// 设置前台进程，Ctrl+C 向它发送 [`SIGINT`]
+///
+/// # 参数
+///
+/// - `pid`: 前台进程，为 `None` 时取消
+pub fn set_foreground(pid: Option<Pid>) {
+    FOREGROUND.store(pid.map_or(0, Pid::as_u64), Ordering::Relaxed);
+}
+
+/// 进程 `pid` 退出时调用，它是前台进程时取消前台进程
+///
+/// # 参数
+///
+/// - `pid`: 退出的进程
+pub(crate) fn release_foreground(pid: Pid) {
+    let _ = FOREGROUND.compare_exchange(pid.as_u64(), 0, Ordering::Relaxed, Ordering::Relaxed);
+}
+
+/// 向前台进程发送 [`SIGINT`]，由键盘驱动在按下 Ctrl+C 时调用
+pub(crate) fn interrupt_foreground() {
+    match FOREGROUND.load(Ordering::Relaxed) {
+        0 => {}
+        pid => {
+            let _ = kill(Pid::from_u64(pid), SIGINT);
+        }
+    }
+}
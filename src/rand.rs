@@ -0,0 +1,221 @@
+//! 本模块实现了内核的密码学安全伪随机数生成器
+//!
+//! 生成器的状态是一个 ChaCha20 密钥：输出是用这个密钥加密计数器得到的密钥流，
+//! 每次输出之后再取一块密钥流作为新的密钥，旧的密钥随即被覆盖，已经输出的数据无法由之后的状态推出。
+//!
+//! 熵源通过 [`add_entropy`] 把数据异或进密钥后重新生成密钥，不可信的熵源也不会降低已有状态的随机性。
+//! 启动时用 RDSEED 或 RDRAND 做种子，处理器都不支持时退而使用 TSC 的抖动，之后由 virtio-rng 等设备补充。
+//! 在 [`init`] 之前取随机数时先做种子，不会输出由全零密钥生成的数据
+
+pub mod hardware;
+pub mod virtio;
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use crate::arch::tsc;
+use crate::sync::SpinLock;
+use crate::time;
+
+/// ChaCha20 状态开头的常量 "expand 32-byte k"
+const SIGMA: [u32; 4] = [0x6170_7865, 0x3320_646e, 0x7962_2d32, 0x6b20_6574];
+/// 密钥的字节数
+const KEY_SIZE: usize = 32;
+/// 一块密钥流的字节数
+const BLOCK_SIZE: usize = 64;
+/// 没有硬件随机数时收集的 TSC 抖动样本数
+const JITTER_SAMPLES: usize = 256;
+
+/// ChaCha 的四分之一轮
+fn quarter_round(state: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize) {
+    state[a] = state[a].wrapping_add(state[b]);
+    state[d] = (state[d] ^ state[a]).rotate_left(16);
+    state[c] = state[c].wrapping_add(state[d]);
+    state[b] = (state[b] ^ state[c]).rotate_left(12);
+    state[a] = state[a].wrapping_add(state[b]);
+    state[d] = (state[d] ^ state[a]).rotate_left(8);
+    state[c] = state[c].wrapping_add(state[d]);
+    state[b] = (state[b] ^ state[c]).rotate_left(7);
+}
+
+/// 计算一块 ChaCha20 密钥流（RFC 8439）
+///
+/// # 参数
+///
+/// - `key`: 256 位密钥
+/// - `counter`: 块计数器
+/// - `nonce`: 96 位随机数
+fn chacha20_block(key: &[u8; KEY_SIZE], counter: u32, nonce: [u32; 3]) -> [u8; BLOCK_SIZE] {
+    let mut input = [0u32; 16];
+    input[..4].copy_from_slice(&SIGMA);
+    for (word, bytes) in input[4..12].iter_mut().zip(key.chunks_exact(4)) {
+        *word = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+    }
+    input[12] = counter;
+    input[13..].copy_from_slice(&nonce);
+
+    let mut state = input;
+    for _ in 0..10 {
+        quarter_round(&mut state, 0, 4, 8, 12);
+        quarter_round(&mut state, 1, 5, 9, 13);
+        quarter_round(&mut state, 2, 6, 10, 14);
+        quarter_round(&mut state, 3, 7, 11, 15);
+        quarter_round(&mut state, 0, 5, 10, 15);
+        quarter_round(&mut state, 1, 6, 11, 12);
+        quarter_round(&mut state, 2, 7, 8, 13);
+        quarter_round(&mut state, 3, 4, 9, 14);
+    }
+
+    let mut output = [0u8; BLOCK_SIZE];
+    for (index, bytes) in output.chunks_exact_mut(4).enumerate() {
+        bytes.copy_from_slice(&state[index].wrapping_add(input[index]).to_le_bytes());
+    }
+    output
+}
+
+/// 基于 ChaCha20 的生成器
+struct ChaChaRng {
+    key: [u8; KEY_SIZE], // 当前的密钥
+    counter: u64,        // 下一块密钥流的计数器，高 32 位放在随机数中
+}
+
+impl ChaChaRng {
+    const fn new() -> Self {
+        Self {
+            key: [0; KEY_SIZE],
+            counter: 0,
+        }
+    }
+
+    /// 下一块密钥流
+    fn next_block(&mut self) -> [u8; BLOCK_SIZE] {
+        let counter = self.counter;
+        self.counter = self.counter.wrapping_add(1);
+        chacha20_block(&self.key, counter as u32, [(counter >> 32) as u32, 0, 0])
+    }
+
+    /// 用下一块密钥流替换密钥
+    fn rekey(&mut self) {
+        let block = self.next_block();
+        self.key.copy_from_slice(&block[..KEY_SIZE]);
+        self.counter = 0;
+    }
+
+    fn fill(&mut self, dest: &mut [u8]) {
+        for chunk in dest.chunks_mut(BLOCK_SIZE) {
+            let block = self.next_block();
+            chunk.copy_from_slice(&block[..chunk.len()]);
+        }
+        self.rekey();
+    }
+
+    fn add_entropy(&mut self, data: &[u8]) {
+        for chunk in data.chunks(KEY_SIZE) {
+            for (key, byte) in self.key.iter_mut().zip(chunk) {
+                *key ^= byte;
+            }
+            self.rekey();
+        }
+    }
+}
+
+/// 内核共用的生成器
+static RNG: SpinLock<ChaChaRng> = SpinLock::new(ChaChaRng::new());
+
+/// 生成器是否已经用启动时间和处理器的熵源做过种子
+static SEEDED: AtomicBool = AtomicBool::new(false);
+
+/// 把熵源提供的数据混入生成器
+///
+/// # 参数
+///
+/// - `data`: 熵源的输出，不要求均匀分布
+pub fn add_entropy(data: &[u8]) {
+    RNG.lock().add_entropy(data);
+}
+
+/// 用随机字节填满 `dest`，生成器尚未做种子时先做种子
+pub fn fill(dest: &mut [u8]) {
+    if !SEEDED.load(Ordering::Acquire) {
+        seed();
+    }
+    RNG.lock().fill(dest);
+}
+
+/// 一个随机的 64 位整数
+pub fn u64() -> u64 {
+    let mut bytes = [0u8; 8];
+    fill(&mut bytes);
+    u64::from_le_bytes(bytes)
+}
+
+/// 用启动时间和处理器的熵源做种子
+///
+/// 多个处理器同时调用时各自混入一份种子，不影响生成器的随机性
+fn seed() {
+    let mut seed = [0u8; 16];
+    seed[..8].copy_from_slice(&tsc::read().to_le_bytes());
+    seed[8..].copy_from_slice(&time::ticks().to_le_bytes());
+    add_entropy(&seed);
+    let mut hardware_seed = [0u8; KEY_SIZE];
+    if hardware::fill(&mut hardware_seed) {
+        add_entropy(&hardware_seed);
+        log::info!("rand: seeded from the CPU random number generator");
+    } else {
+        let mut samples = [0u64; JITTER_SAMPLES];
+        hardware::jitter(&mut samples);
+        for sample in samples {
+            add_entropy(&sample.to_le_bytes());
+        }
+        log::warn!("rand: no RDSEED or RDRAND, seeded from TSC jitter");
+    }
+    SEEDED.store(true, Ordering::Release);
+}
+
+/// 为生成器做种子（已经做过时跳过），再探测熵源设备
+///
+/// virtio-rng 需要工作队列和 APIC 投递的中断，应在 [`workqueue::init`](crate::workqueue::init)
+/// 和中断改由 APIC 投递之后调用
+pub fn init() {
+    if !SEEDED.load(Ordering::Acquire) {
+        seed();
+    }
+    virtio::init();
+}
+
+#[test_case]
+fn test_chacha20_block() {
+    // RFC 8439 第 2.3.2 节的测试向量
+    let key = core::array::from_fn(|index| index as u8);
+    let block = chacha20_block(&key, 1, [0x0900_0000, 0x4a00_0000, 0]);
+    assert_eq!(
+        block[..16],
+        [
+            0x10, 0xf1, 0xe7, 0xe4, 0xd1, 0x3b, 0x59, 0x15, 0x50, 0x0f, 0xdd, 0x1f, 0xa3, 0x20,
+            0x71, 0xc4
+        ]
+    );
+    assert_eq!(block[60..], [0xa2, 0x50, 0x3c, 0x4e]);
+}
+
+#[test_case]
+fn test_rng_forward_secrecy() {
+    let mut rng = ChaChaRng::new();
+    let mut first = [0u8; 16];
+    let mut second = [0u8; 16];
+    rng.fill(&mut first);
+    rng.fill(&mut second);
+    assert_ne!(first, second);
+    // 每次输出之后密钥都已更换
+    assert_ne!(rng.key, [0; KEY_SIZE]);
+    let key = rng.key;
+    rng.add_entropy(&[0x5a; 40]);
+    assert_ne!(rng.key, key);
+}
+
+#[test_case]
+fn test_fill_seeds_first() {
+    let mut bytes = [0u8; 32];
+    fill(&mut bytes);
+    assert!(SEEDED.load(Ordering::Relaxed));
+    assert_ne!(bytes, [0; 32]);
+}
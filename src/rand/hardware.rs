@@ -0,0 +1,104 @@
+//! 本模块实现了处理器提供的熵源
+//!
+//! RDSEED 直接读取处理器内部熵源的输出，适合做种子；RDRAND 读取由同一熵源定期重新播种的 DRBG，
+//! 两者在熵源暂时耗尽时都会失败，需要重试。
+//! 处理器不支持这两条指令时，用 TSC 测量一段固定操作的耗时，把耗时的抖动作为熵
+
+use core::arch::x86_64::{_rdrand64_step, _rdseed64_step};
+
+use crate::arch::tsc;
+use crate::cpu::{self, Feature};
+
+/// 每次读取最多重试的次数，Intel 建议 RDRAND 重试 10 次
+const RETRIES: usize = 10;
+/// 测量抖动时每个样本重复的操作次数
+const JITTER_ROUNDS: usize = 64;
+
+/// 用 RDSEED 读取一个 64 位随机数
+///
+/// # 返回
+///
+/// 处理器不支持或重试后仍然失败时返回 `None`
+pub fn rdseed() -> Option<u64> {
+    if !cpu::has(Feature::Rdseed) {
+        return None;
+    }
+    (0..RETRIES).find_map(|_| unsafe { rdseed_step() })
+}
+
+/// 用 RDRAND 读取一个 64 位随机数
+///
+/// # 返回
+///
+/// 处理器不支持或重试后仍然失败时返回 `None`
+pub fn rdrand() -> Option<u64> {
+    if !cpu::has(Feature::Rdrand) {
+        return None;
+    }
+    (0..RETRIES).find_map(|_| unsafe { rdrand_step() })
+}
+
+/// # Safety
+///
+/// 处理器必须支持 RDSEED
+#[target_feature(enable = "rdseed")]
+unsafe fn rdseed_step() -> Option<u64> {
+    let mut value = 0;
+    (_rdseed64_step(&mut value) == 1).then_some(value)
+}
+
+/// # Safety
+///
+/// 处理器必须支持 RDRAND
+#[target_feature(enable = "rdrand")]
+unsafe fn rdrand_step() -> Option<u64> {
+    let mut value = 0;
+    (_rdrand64_step(&mut value) == 1).then_some(value)
+}
+
+/// 用处理器的随机数指令填满 `dest`，优先使用 RDSEED
+///
+/// # 返回
+///
+/// 两者都不可用时返回 `false`，`dest` 的内容没有意义
+pub fn fill(dest: &mut [u8]) -> bool {
+    for chunk in dest.chunks_mut(8) {
+        let Some(value) = rdseed().or_else(rdrand) else {
+            return false;
+        };
+        chunk.copy_from_slice(&value.to_le_bytes()[..chunk.len()]);
+    }
+    true
+}
+
+/// 测量一段固定操作的耗时并收集抖动
+///
+/// 每个样本是一次操作耗费的 TSC 周期数，它随缓存、流水线和中断的状态变化；
+/// 每个样本的熵很少，调用者应收集足够多的样本后整体混入生成器
+///
+/// # 参数
+///
+/// - `samples`: 保存样本的缓冲区
+pub fn jitter(samples: &mut [u64]) {
+    let mut accumulator = 0u64;
+    for sample in samples {
+        let start = tsc::read();
+        for round in 0..JITTER_ROUNDS {
+            // 每一轮依赖上一轮的结果，并经过 `black_box`，编译器无法把它优化掉
+            accumulator = core::hint::black_box(
+                accumulator.rotate_left(7) ^ (round as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15),
+            );
+        }
+        *sample = tsc::read().wrapping_sub(start);
+    }
+}
+
+#[test_case]
+fn test_hardware_sources() {
+    let mut buffer = [0u8; 20];
+    let supported = cpu::has(Feature::Rdseed) || cpu::has(Feature::Rdrand);
+    assert_eq!(fill(&mut buffer), supported);
+    let mut samples = [0u64; 8];
+    jitter(&mut samples);
+    assert!(samples.iter().all(|&sample| sample > 0));
+}
@@ -0,0 +1,183 @@
+//! 本模块实现了 virtio 熵源设备的驱动
+//!
+//! 设备只有一个请求队列：驱动放入一段由设备写入的缓冲区，设备用主机的随机数填满它后放入已用环。
+//! 初始化时同步取一次随机数给生成器做种子，之后每隔 [`RESEED_INTERVAL`] 再请求一次；
+//! 完成由中断调度的工作项处理，设备没有可用的中断时定期轮询
+
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::time::Duration;
+
+use crate::memory::dma::DmaRegion;
+use crate::pci::Device;
+use crate::sync::SpinLock;
+use crate::time::{self, Instant};
+use crate::virtio::{self, Buffer, Transport, VirtQueue};
+use crate::workqueue::Work;
+
+/// 请求队列的编号
+const REQUEST_QUEUE: u16 = 0;
+/// 希望的队列大小，同时只有一个请求
+const QUEUE_SIZE: u16 = 8;
+/// 每次请求的字节数
+const REQUEST_SIZE: usize = 64;
+/// 两次请求之间的间隔
+const RESEED_INTERVAL: Duration = Duration::from_secs(60);
+/// 没有中断时检查请求是否完成的间隔
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+/// 初始化时等待第一次请求完成的时间
+const INIT_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// 熵源设备
+struct Rng {
+    transport: Transport, // 传输层
+    queue: VirtQueue,     // 请求队列
+    buffer: DmaRegion,    // 设备写入随机数的缓冲区
+    pending: bool,        // 是否有请求尚未完成
+}
+
+impl Rng {
+    /// 请求一次随机数，已有请求尚未完成时什么也不做
+    fn request(&mut self) {
+        if self.pending {
+            return;
+        }
+        let buffer = Buffer {
+            addr: self.buffer.phys(),
+            len: REQUEST_SIZE as u32,
+            writable: true,
+        };
+        if self.queue.add(&[buffer]).is_some() {
+            self.pending = true;
+            if self.queue.should_notify() {
+                self.transport.notify(&self.queue);
+            }
+        }
+    }
+
+    /// 取出已完成的请求中的随机数
+    ///
+    /// # 返回
+    ///
+    /// 随机数和它的字节数，请求尚未完成时返回 `None`
+    fn collect(&mut self) -> Option<([u8; REQUEST_SIZE], usize)> {
+        let (_, len) = self.queue.pop_used()?;
+        self.pending = false;
+        let len = (len as usize).min(REQUEST_SIZE);
+        let mut data = [0u8; REQUEST_SIZE];
+        data[..len].copy_from_slice(&self.buffer.as_slice()[..len]);
+        Some((data, len))
+    }
+}
+
+/// 找到的熵源设备，只使用第一个
+static RNG: SpinLock<Option<Rng>> = SpinLock::new(None);
+/// 设备的中断是否可用
+static INTERRUPTS: AtomicBool = AtomicBool::new(false);
+
+/// 收取完成的请求或发出新的请求
+static WORK: Work = Work::new(service);
+
+/// 在 `delay` 之后调度 [`WORK`]
+fn schedule_after(delay: Duration) {
+    time::call_after(time::duration_to_ticks(delay).max(1), || {
+        WORK.schedule();
+    });
+}
+
+fn service() {
+    let collected = {
+        let mut guard = RNG.lock();
+        let Some(rng) = guard.as_mut() else {
+            return;
+        };
+        let collected = rng.collect();
+        if collected.is_none() {
+            rng.request();
+        }
+        collected
+    };
+    match collected {
+        Some((data, len)) => {
+            super::add_entropy(&data[..len]);
+            schedule_after(RESEED_INTERVAL);
+        }
+        None if !INTERRUPTS.load(Ordering::Relaxed) => schedule_after(POLL_INTERVAL),
+        None => {}
+    }
+}
+
+/// 中断处理函数
+fn handle_interrupt() {
+    if let Some(rng) = RNG.lock().as_ref()
+        && !rng.transport.uses_msix()
+    {
+        rng.transport.isr();
+    }
+    WORK.schedule();
+}
+
+/// 初始化熵源设备
+///
+/// # 参数
+///
+/// - `device`: 设备的 PCI 设备
+fn init_device(device: &'static Device) -> Option<Rng> {
+    let transport = Transport::new(device)?;
+    transport.negotiate(0)?;
+    let vector = transport.route_interrupt(handle_interrupt);
+    let Some((queue, buffer)) = transport
+        .setup_queue(REQUEST_QUEUE, QUEUE_SIZE)
+        .zip(DmaRegion::new(REQUEST_SIZE))
+    else {
+        transport.fail();
+        return None;
+    };
+    transport.driver_ok();
+    INTERRUPTS.store(vector.is_some(), Ordering::Relaxed);
+    Some(Rng {
+        transport,
+        queue,
+        buffer,
+        pending: false,
+    })
+}
+
+/// 探测熵源设备，找到时同步取一次随机数混入生成器
+pub fn init() {
+    let Some(device) = virtio::find(virtio::DEVICE_ENTROPY).next() else {
+        return;
+    };
+    let Some(mut rng) = init_device(device) else {
+        log::warn!("virtio-rng at {}: initialization failed", device.address);
+        return;
+    };
+    rng.request();
+    let deadline = Instant::now() + INIT_TIMEOUT;
+    let collected = loop {
+        if let Some(collected) = rng.collect() {
+            break Some(collected);
+        }
+        if Instant::now() >= deadline {
+            break None;
+        }
+        core::hint::spin_loop();
+    };
+    *RNG.lock() = Some(rng);
+    match collected {
+        Some((data, len)) => {
+            super::add_entropy(&data[..len]);
+            log::info!(
+                "virtio-rng at {}: seeded with {} bytes",
+                device.address,
+                len
+            );
+            schedule_after(RESEED_INTERVAL);
+        }
+        None => {
+            // 请求仍未完成，由中断或轮询收取
+            if !INTERRUPTS.load(Ordering::Relaxed) {
+                schedule_after(POLL_INTERVAL);
+            }
+        }
+    }
+}
@@ -0,0 +1,46 @@
+//! 本模块实现了串口（UART 16550）的封装，用于将内核输出发送到宿主机
+
+use core::fmt::{self, Write};
+
+use lazy_static::lazy_static;
+use spin::Mutex;
+use uart_16550::SerialPort;
+
+/// COM1 串口的 I/O 端口基地址
+const COM1_PORT: u16 = 0x3f8;
+
+// 串口同样在第一次使用时初始化
+lazy_static! {
+    pub static ref SERIAL1: Mutex<SerialPort> = {
+        let mut serial_port = unsafe { SerialPort::new(COM1_PORT) };
+        serial_port.init();
+        Mutex::new(serial_port)
+    };
+}
+
+/// 通过串口打印
+#[macro_export]
+macro_rules! serial_print {
+    ($($arg:tt)*) => ($crate::serial::_print(format_args!($($arg)*)));
+}
+
+/// 通过串口打印，并追加换行符
+#[macro_export]
+macro_rules! serial_println {
+    () => ($crate::serial_print!("\n"));
+    ($fmt:expr) => ($crate::serial_print!(concat!($fmt, "\n")));
+    ($fmt:expr, $($arg:tt)*) => ($crate::serial_print!(concat!($fmt, "\n"), $($arg)*));
+}
+
+#[doc(hidden)]
+pub fn _print(args: fmt::Arguments) {
+    use x86_64::instructions::interrupts;
+
+    // 持有锁期间禁用中断，避免中断处理函数打印时发生死锁
+    interrupts::without_interrupts(|| {
+        SERIAL1
+            .lock()
+            .write_fmt(args)
+            .expect("Printing to serial failed");
+    });
+}
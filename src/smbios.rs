@@ -0,0 +1,495 @@
+//! 本模块实现了 SMBIOS 表的查找和解析
+//!
+//! 固件把 SMBIOS 入口点放在 BIOS 只读区域 0xf0000 到 0xfffff 中 16 字节对齐的位置，
+//! 32 位入口点以 `_SM_` 开头，3.0 起的 64 位入口点以 `_SM3_` 开头，两者都存在时使用后者。
+//! 入口点给出结构表的地址和长度。每个结构由格式化区域和紧随其后的字符串集组成：
+//! 格式化区域以类型、长度和句柄开头，其中的字符串字段是字符串集中从 1 开始的编号，字符串集以两个 0 字节结束。
+//!
+//! 目前解析 BIOS 信息（类型 0）、系统信息（类型 1）和内存设备（类型 17）。
+//! bootloader 0.9 不传递 EFI 系统表，只能在 BIOS 区域中搜索
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+
+use conquer_once::spin::OnceCell;
+use x86_64::PhysAddr;
+
+use crate::memory;
+
+/// 搜索入口点的区域
+const SEARCH_AREA: core::ops::Range<u64> = 0xf_0000..0x10_0000;
+/// 32 位入口点的签名
+const ANCHOR_32: &[u8; 4] = b"_SM_";
+/// 64 位入口点的签名
+const ANCHOR_64: &[u8; 5] = b"_SM3_";
+/// 32 位入口点中的中间签名
+const INTERMEDIATE_ANCHOR: &[u8; 5] = b"_DMI_";
+
+/// 结构类型：BIOS 信息
+const TYPE_BIOS: u8 = 0;
+/// 结构类型：系统信息
+const TYPE_SYSTEM: u8 = 1;
+/// 结构类型：内存设备
+const TYPE_MEMORY_DEVICE: u8 = 17;
+/// 结构类型：表结束
+const TYPE_END: u8 = 127;
+/// 结构头的长度
+const HEADER_SIZE: usize = 4;
+
+/// 内存设备的大小字段：大小未知
+const SIZE_UNKNOWN: u16 = 0xffff;
+/// 内存设备的大小字段：大小在扩展大小字段中
+const SIZE_EXTENDED: u16 = 0x7fff;
+/// 内存设备的大小字段：单位为 KiB，否则为 MiB
+const SIZE_IN_KIB: u16 = 1 << 15;
+
+/// 入口点给出的结构表
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct EntryPoint {
+    version: (u8, u8), // SMBIOS 的主版本号和次版本号
+    address: u64,      // 结构表的物理地址
+    length: u32,       // 结构表的字节数，64 位入口点给出的是最大值
+}
+
+/// BIOS 信息
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BiosInfo {
+    pub vendor: String,       // 厂商
+    pub version: String,      // 版本
+    pub release_date: String, // 发布日期，格式为 mm/dd/yyyy
+}
+
+/// 系统信息
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SystemInfo {
+    pub manufacturer: String, // 制造商
+    pub product: String,      // 产品名称
+    pub version: String,      // 版本
+    pub serial: String,       // 序列号
+}
+
+/// 内存设备，即一个内存插槽
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MemoryDevice {
+    pub locator: String,      // 插槽的名称
+    pub bank: String,         // 所在的内存组
+    pub size: Option<u64>,    // 字节数，插槽为空时为 `Some(0)`，未知时为 `None`
+    pub memory_type: u8,      // 内存类型，见 [`memory_type_name`]
+    pub speed: u16,           // 最高速率（MT/s），未知时为 0
+    pub manufacturer: String, // 制造商
+    pub part_number: String,  // 型号
+}
+
+/// 解析得到的 SMBIOS 信息
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Smbios {
+    pub version: (u8, u8),          // SMBIOS 的主版本号和次版本号
+    pub bios: Option<BiosInfo>,     // BIOS 信息
+    pub system: Option<SystemInfo>, // 系统信息
+    pub memory: Vec<MemoryDevice>,  // 所有内存设备
+}
+
+/// 由 [`init`] 记录之后不再改变
+static SMBIOS: OnceCell<Smbios> = OnceCell::uninit();
+
+/// 校验和：所有字节相加的低 8 位为 0
+fn checksum_ok(bytes: &[u8]) -> bool {
+    bytes.iter().fold(0u8, |sum, &b| sum.wrapping_add(b)) == 0
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(
+        bytes.get(offset..offset + 2)?.try_into().ok()?,
+    ))
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(
+        bytes.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> Option<u64> {
+    Some(u64::from_le_bytes(
+        bytes.get(offset..offset + 8)?.try_into().ok()?,
+    ))
+}
+
+/// 读取物理内存中的一段字节
+///
+/// # 返回
+///
+/// 尚未调用 [`memory::install`] 时返回 `None`
+fn phys_bytes(addr: u64, len: u64) -> Option<&'static [u8]> {
+    let virt = memory::phys_to_virt(PhysAddr::new(addr))?;
+    Some(unsafe { core::slice::from_raw_parts(virt.as_ptr(), len as usize) })
+}
+
+/// 解析以入口点签名开头的字节，签名、长度或校验和错误时返回 `None`
+fn parse_entry_point(bytes: &[u8]) -> Option<EntryPoint> {
+    if bytes.starts_with(ANCHOR_64) {
+        let length = usize::from(*bytes.get(6)?);
+        if length < 0x18 || !checksum_ok(bytes.get(..length)?) {
+            return None;
+        }
+        return Some(EntryPoint {
+            version: (bytes[7], bytes[8]),
+            address: read_u64(bytes, 0x10)?,
+            length: read_u32(bytes, 0x0c)?,
+        });
+    }
+    if bytes.starts_with(ANCHOR_32) {
+        // SMBIOS 2.1 规范把入口点的长度误写为 0x1e，按这个值填写的固件实际上仍是 0x1f 字节
+        let length = match (*bytes.get(5)?, bytes.get(6..8)?) {
+            (0x1e, [2, 1]) => 0x1f,
+            (length, _) => usize::from(length),
+        };
+        if length < 0x1f
+            || !checksum_ok(bytes.get(..length)?)
+            || bytes.get(0x10..0x15)? != INTERMEDIATE_ANCHOR
+            || !checksum_ok(&bytes[0x10..length])
+        {
+            return None;
+        }
+        return Some(EntryPoint {
+            version: (bytes[6], bytes[7]),
+            address: u64::from(read_u32(bytes, 0x18)?),
+            length: u32::from(read_u16(bytes, 0x16)?),
+        });
+    }
+    None
+}
+
+/// 在 BIOS 只读区域中搜索入口点，优先使用 64 位入口点
+fn scan_entry_point() -> Option<EntryPoint> {
+    let area = phys_bytes(SEARCH_AREA.start, SEARCH_AREA.end - SEARCH_AREA.start)?;
+    let candidates = || {
+        (0..area.len())
+            .step_by(16)
+            .filter_map(|offset| parse_entry_point(&area[offset..]))
+    };
+    candidates()
+        .find(|entry| entry.version.0 >= 3)
+        .or_else(|| candidates().next())
+}
+
+/// 结构表中的一个结构
+struct Structure<'a> {
+    kind: u8,            // 类型
+    formatted: &'a [u8], // 格式化区域，包括结构头
+    strings: &'a [u8],   // 字符串集，不包括结尾的两个 0 字节
+}
+
+impl Structure<'_> {
+    /// 格式化区域中 `offset` 处的字节，超出结构的长度时返回 0
+    fn byte(&self, offset: usize) -> u8 {
+        self.formatted.get(offset).copied().unwrap_or(0)
+    }
+
+    /// 格式化区域中 `offset` 处的 16 位字，超出结构的长度时返回 0
+    fn word(&self, offset: usize) -> u16 {
+        read_u16(self.formatted, offset).unwrap_or(0)
+    }
+
+    /// 格式化区域中 `offset` 处的字符串编号对应的字符串，编号为 0 或超出范围时返回空字符串
+    fn string(&self, offset: usize) -> String {
+        let index = usize::from(self.byte(offset));
+        if index == 0 {
+            return String::new();
+        }
+        self.strings
+            .split(|&b| b == 0)
+            .nth(index - 1)
+            .map(|bytes| String::from_utf8_lossy(bytes).trim().into())
+            .unwrap_or_default()
+    }
+}
+
+/// 遍历结构表，遇到表结束结构、越界或格式错误时停止
+fn structures(table: &[u8]) -> impl Iterator<Item = Structure<'_>> {
+    let mut rest = table;
+    core::iter::from_fn(move || {
+        let length = usize::from(*rest.get(1)?);
+        if length < HEADER_SIZE || rest.len() < length {
+            return None;
+        }
+        let (formatted, after) = rest.split_at(length);
+        // 字符串集以两个 0 字节结束，没有字符串时也有两个 0 字节
+        let end = after.windows(2).position(|pair| pair == [0, 0])?;
+        let structure = Structure {
+            kind: formatted[0],
+            formatted,
+            strings: &after[..end],
+        };
+        rest = &after[end + 2..];
+        (structure.kind != TYPE_END).then_some(structure)
+    })
+}
+
+/// 内存设备的字节数
+///
+/// # 参数
+///
+/// - `size`: 大小字段
+/// - `extended`: 扩展大小字段，单位为 MiB
+fn memory_size(size: u16, extended: u32) -> Option<u64> {
+    match size {
+        SIZE_UNKNOWN => None,
+        SIZE_EXTENDED => Some(u64::from(extended & 0x7fff_ffff) << 20),
+        size if size & SIZE_IN_KIB != 0 => Some(u64::from(size & !SIZE_IN_KIB) << 10),
+        size => Some(u64::from(size) << 20),
+    }
+}
+
+/// 解析结构表
+///
+/// # 参数
+///
+/// - `version`: 入口点给出的版本号
+/// - `table`: 结构表
+fn parse_table(version: (u8, u8), table: &[u8]) -> Smbios {
+    let mut smbios = Smbios {
+        version,
+        bios: None,
+        system: None,
+        memory: Vec::new(),
+    };
+    for structure in structures(table) {
+        match structure.kind {
+            TYPE_BIOS => {
+                smbios.bios.get_or_insert(BiosInfo {
+                    vendor: structure.string(0x04),
+                    version: structure.string(0x05),
+                    release_date: structure.string(0x08),
+                });
+            }
+            TYPE_SYSTEM => {
+                smbios.system.get_or_insert(SystemInfo {
+                    manufacturer: structure.string(0x04),
+                    product: structure.string(0x05),
+                    version: structure.string(0x06),
+                    serial: structure.string(0x07),
+                });
+            }
+            TYPE_MEMORY_DEVICE => smbios.memory.push(MemoryDevice {
+                locator: structure.string(0x10),
+                bank: structure.string(0x11),
+                size: memory_size(
+                    structure.word(0x0c),
+                    read_u32(structure.formatted, 0x1c).unwrap_or(0),
+                ),
+                memory_type: structure.byte(0x12),
+                speed: structure.word(0x15),
+                manufacturer: structure.string(0x17),
+                part_number: structure.string(0x1a),
+            }),
+            _ => {}
+        }
+    }
+    smbios
+}
+
+/// 内存类型的名称
+///
+/// # 参数
+///
+/// - `memory_type`: 内存设备结构中的内存类型
+pub fn memory_type_name(memory_type: u8) -> &'static str {
+    match memory_type {
+        0x07 => "RAM",
+        0x12 => "DDR",
+        0x13 => "DDR2",
+        0x18 => "DDR3",
+        0x1a => "DDR4",
+        0x1b => "LPDDR",
+        0x1c => "LPDDR2",
+        0x1d => "LPDDR3",
+        0x1e => "LPDDR4",
+        0x22 => "DDR5",
+        0x23 => "LPDDR5",
+        _ => "Unknown",
+    }
+}
+
+/// 找到入口点并解析结构表，以 debug 级别记录 [`dmidecode`] 的输出，需要在 [`memory::install`] 之后调用
+///
+/// # 返回
+///
+/// 没有找到入口点时返回 `false`
+pub fn init() -> bool {
+    if SMBIOS.is_initialized() {
+        return true;
+    }
+    let Some(entry) = scan_entry_point() else {
+        return false;
+    };
+    let Some(table) = phys_bytes(entry.address, u64::from(entry.length)) else {
+        return false;
+    };
+    let smbios = SMBIOS.get_or_init(|| parse_table(entry.version, table));
+    log::info!("SMBIOS {}.{}", smbios.version.0, smbios.version.1);
+    if let Some(bios) = &smbios.bios {
+        log::info!(
+            "BIOS: {} {} ({})",
+            bios.vendor,
+            bios.version,
+            bios.release_date
+        );
+    }
+    if let Some(system) = &smbios.system {
+        log::info!("System: {} {}", system.manufacturer, system.product);
+    }
+    let installed: Vec<_> = smbios
+        .memory
+        .iter()
+        .filter_map(|device| device.size.filter(|&size| size > 0))
+        .collect();
+    log::info!(
+        "Memory: {} of {} slots populated, {} MiB",
+        installed.len(),
+        smbios.memory.len(),
+        installed.iter().sum::<u64>() >> 20
+    );
+    if log::log_enabled!(log::Level::Debug) {
+        let mut listing = String::new();
+        let _ = dmidecode(&mut listing);
+        for line in listing.lines() {
+            log::debug!("{}", line);
+        }
+    }
+    true
+}
+
+/// 解析得到的 SMBIOS 信息，尚未调用 [`init`] 或没有找到时返回 `None`
+pub fn info() -> Option<&'static Smbios> {
+    SMBIOS.get()
+}
+
+/// 以类似 `dmidecode` 的格式输出 BIOS、系统和内存设备信息
+///
+/// # 参数
+///
+/// - `out`: 输出目标
+pub fn dmidecode(out: &mut impl fmt::Write) -> fmt::Result {
+    let Some(smbios) = info() else {
+        return writeln!(out, "No SMBIOS entry point found");
+    };
+    writeln!(
+        out,
+        "SMBIOS {}.{} present.",
+        smbios.version.0, smbios.version.1
+    )?;
+    if let Some(bios) = &smbios.bios {
+        writeln!(out, "BIOS Information")?;
+        writeln!(out, "\tVendor: {}", bios.vendor)?;
+        writeln!(out, "\tVersion: {}", bios.version)?;
+        writeln!(out, "\tRelease Date: {}", bios.release_date)?;
+    }
+    if let Some(system) = &smbios.system {
+        writeln!(out, "System Information")?;
+        writeln!(out, "\tManufacturer: {}", system.manufacturer)?;
+        writeln!(out, "\tProduct Name: {}", system.product)?;
+        writeln!(out, "\tVersion: {}", system.version)?;
+        writeln!(out, "\tSerial Number: {}", system.serial)?;
+    }
+    for device in &smbios.memory {
+        writeln!(out, "Memory Device")?;
+        match device.size {
+            Some(0) => writeln!(out, "\tSize: No Module Installed")?,
+            Some(size) => writeln!(out, "\tSize: {} MB", size >> 20)?,
+            None => writeln!(out, "\tSize: Unknown")?,
+        }
+        writeln!(out, "\tLocator: {}", device.locator)?;
+        writeln!(out, "\tBank Locator: {}", device.bank)?;
+        writeln!(out, "\tType: {}", memory_type_name(device.memory_type))?;
+        if device.speed != 0 {
+            writeln!(out, "\tSpeed: {} MT/s", device.speed)?;
+        }
+        writeln!(out, "\tManufacturer: {}", device.manufacturer)?;
+        writeln!(out, "\tPart Number: {}", device.part_number)?;
+    }
+    Ok(())
+}
+
+#[test_case]
+fn test_parse_entry_point() {
+    let mut entry = [0u8; 0x18];
+    entry[..5].copy_from_slice(ANCHOR_64);
+    entry[6] = 0x18;
+    entry[7] = 3;
+    entry[8] = 2;
+    entry[0x0c..0x10].copy_from_slice(&0x1234u32.to_le_bytes());
+    entry[0x10..0x18].copy_from_slice(&0xbff0_0000u64.to_le_bytes());
+    entry[5] = 0u8.wrapping_sub(entry.iter().fold(0u8, |sum, &b| sum.wrapping_add(b)));
+    assert_eq!(
+        parse_entry_point(&entry),
+        Some(EntryPoint {
+            version: (3, 2),
+            address: 0xbff0_0000,
+            length: 0x1234,
+        })
+    );
+    entry[0x10] ^= 1;
+    assert_eq!(parse_entry_point(&entry), None);
+}
+
+#[test_case]
+fn test_parse_entry_point_accepts_21_length() {
+    let mut entry = [0u8; 0x1f];
+    entry[..4].copy_from_slice(ANCHOR_32);
+    entry[5] = 0x1e;
+    entry[6] = 2;
+    entry[7] = 1;
+    entry[0x10..0x15].copy_from_slice(INTERMEDIATE_ANCHOR);
+    entry[0x16..0x18].copy_from_slice(&0x0200u16.to_le_bytes());
+    entry[0x18..0x1c].copy_from_slice(&0x000f_1000u32.to_le_bytes());
+    let sum = |bytes: &[u8]| bytes.iter().fold(0u8, |sum, &b| sum.wrapping_add(b));
+    entry[0x15] = 0u8.wrapping_sub(sum(&entry[0x10..]));
+    entry[4] = 0u8.wrapping_sub(sum(&entry));
+    assert_eq!(
+        parse_entry_point(&entry),
+        Some(EntryPoint {
+            version: (2, 1),
+            address: 0x000f_1000,
+            length: 0x200,
+        })
+    );
+}
+
+#[test_case]
+fn test_parse_table() {
+    const BIOS_STRINGS: &[u8] = b"SeaBIOS\0rel-1.16\x0004/01/2014\0\0";
+    const MEMORY_STRINGS: &[u8] = b"DIMM 0\0\0";
+    const MEMORY: usize = 0x12 + BIOS_STRINGS.len();
+    const END: usize = MEMORY + 0x22 + MEMORY_STRINGS.len();
+    let mut table = [0u8; END + 6];
+    table[..9].copy_from_slice(&[TYPE_BIOS, 0x12, 0, 0, 1, 2, 0, 0, 3]);
+    table[0x12..MEMORY].copy_from_slice(BIOS_STRINGS);
+    // 内存设备：16 GiB，DDR4，3200 MT/s
+    let memory = &mut table[MEMORY..MEMORY + 0x22];
+    memory[..2].copy_from_slice(&[TYPE_MEMORY_DEVICE, 0x22]);
+    memory[0x0c..0x0e].copy_from_slice(&0x4000u16.to_le_bytes());
+    memory[0x10] = 1;
+    memory[0x12] = 0x1a;
+    memory[0x15..0x17].copy_from_slice(&3200u16.to_le_bytes());
+    table[MEMORY + 0x22..END].copy_from_slice(MEMORY_STRINGS);
+    table[END..].copy_from_slice(&[TYPE_END, 4, 0, 0, 0, 0]);
+
+    let smbios = parse_table((2, 8), &table);
+    let bios = smbios.bios.unwrap();
+    assert_eq!(bios.vendor, "SeaBIOS");
+    assert_eq!(bios.version, "rel-1.16");
+    assert_eq!(bios.release_date, "04/01/2014");
+    assert_eq!(smbios.system, None);
+    assert_eq!(smbios.memory.len(), 1);
+    let device = &smbios.memory[0];
+    assert_eq!(device.locator, "DIMM 0");
+    assert_eq!(device.bank, "");
+    assert_eq!(device.size, Some(16 << 30));
+    assert_eq!(memory_type_name(device.memory_type), "DDR4");
+    assert_eq!(device.speed, 3200);
+    assert_eq!(memory_size(SIZE_EXTENDED, 32768), Some(32 << 30));
+    assert_eq!(memory_size(SIZE_IN_KIB | 512, 0), Some(512 << 10));
+    assert_eq!(memory_size(SIZE_UNKNOWN, 0), None);
+}
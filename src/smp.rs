@@ -0,0 +1,297 @@
+//! 本模块实现了应用处理器（AP）的启动
+//!
+//! 引导处理器（BSP）从 ACPI MADT 中找到其他处理器，把一段实模式启动代码复制到 1 MiB 以下的
+//! 预留帧中，再依次向每个处理器发送 INIT 和 STARTUP IPI。启动代码直接从实模式切换到长模式，
+//! 使用内核页表和为该处理器分配的栈，跳转到 [`ap_main`]。每个应用处理器加载自己的 GDT、TSS
+//! 和 IDT 后开始调度，从其他处理器窃取就绪线程
+//!
+//! 处理器之间通过 [`tlb`] 子模块同步页表的修改
+//!
+//! 应用处理器一个接一个地启动，启动参数放在启动代码末尾，处理器上线后才会被下一个覆盖
+
+use alloc::vec;
+use core::arch::global_asm;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use x86_64::VirtAddr;
+use x86_64::structures::paging::{Mapper, Page, PageTableFlags, PhysFrame, Size4KiB, Translate};
+
+use crate::arch::port::Port;
+use crate::{acpi, apic, gdt, interrupts, memory, percpu, thread};
+
+pub mod tlb;
+
+/// 每个应用处理器的内核栈大小
+const AP_STACK_SIZE: usize = 4096 * 16;
+
+/// INIT IPI 之后等待的时间（微秒）
+const INIT_DELAY_US: u64 = 10_000;
+/// STARTUP IPI 之后等待的时间（微秒）
+const STARTUP_DELAY_US: u64 = 200;
+/// 等待应用处理器上线的最长时间（微秒）
+const ONLINE_TIMEOUT_US: u64 = 100_000;
+
+/// 在线的处理器数，包括引导处理器
+static ONLINE: AtomicUsize = AtomicUsize::new(1);
+
+/// 启动代码末尾的参数，由引导处理器在发送 STARTUP IPI 之前写入
+#[repr(C)]
+struct TrampolineParams {
+    cr3: u64,       // 内核 4 级页表的物理地址，必须位于 4 GiB 以下
+    stack_top: u64, // 应用处理器的栈顶
+    entry: u64,     // 长模式下跳转到的内核函数
+    cpu: u64,       // 处理器编号，作为参数传给入口函数
+}
+
+// 启动代码：以实模式从 4 KiB 对齐的物理地址开始执行，CS 为该地址除以 16。
+// 打开 PAE、加载 CR3、设置 EFER.LME 和 NXE 后同时打开保护模式和分页，直接进入长模式，
+// 远跳转的目标地址和 GDTR 中的基址依赖启动代码的位置，由引导处理器复制后填写
+global_asm!(
+    ".pushsection .text.ap_trampoline, \"ax\"",
+    ".global ap_trampoline_start",
+    ".global ap_trampoline_far_target",
+    ".global ap_trampoline_long_mode",
+    ".global ap_trampoline_gdt",
+    ".global ap_trampoline_gdtr",
+    ".global ap_trampoline_params",
+    ".global ap_trampoline_end",
+    // 实模式下数据段与代码段相同，数据按相对于起始处的偏移访问
+    ".set AP_TRAMPOLINE_GDTR, ap_trampoline_gdtr - ap_trampoline_start",
+    ".set AP_TRAMPOLINE_CR3, ap_trampoline_params - ap_trampoline_start",
+    ".code16",
+    "ap_trampoline_start:",
+    "cli",
+    "cld",
+    "mov ax, cs",
+    "mov ds, ax",
+    "lgdt [AP_TRAMPOLINE_GDTR]",
+    "mov eax, cr4",
+    "or eax, 0x20",
+    "mov cr4, eax",
+    "mov eax, [AP_TRAMPOLINE_CR3]",
+    "mov cr3, eax",
+    "mov ecx, 0xc0000080",
+    "rdmsr",
+    "or eax, 0x900",
+    "wrmsr",
+    "mov eax, cr0",
+    "or eax, 0x80010001",
+    "mov cr0, eax",
+    // jmp far ptr16:32，目标偏移由引导处理器填写
+    ".byte 0x66, 0xea",
+    "ap_trampoline_far_target:",
+    ".long 0",
+    ".word 0x08",
+    ".code64",
+    "ap_trampoline_long_mode:",
+    "xor eax, eax",
+    "mov ds, ax",
+    "mov es, ax",
+    "mov ss, ax",
+    "mov rsp, [rip + ap_trampoline_params + 8]",
+    "mov rdi, [rip + ap_trampoline_params + 24]",
+    // 压入 0 作为返回地址，入口处的栈满足调用约定，回溯也在这里终止
+    "xor ebp, ebp",
+    "push rbp",
+    "jmp qword ptr [rip + ap_trampoline_params + 16]",
+    ".balign 8",
+    "ap_trampoline_gdt:",
+    ".quad 0",
+    ".quad 0x00af9a000000ffff",
+    "ap_trampoline_gdtr:",
+    ".word 15",
+    ".long 0",
+    ".balign 8",
+    "ap_trampoline_params:",
+    ".quad 0, 0, 0, 0",
+    "ap_trampoline_end:",
+    ".popsection",
+);
+
+unsafe extern "C" {
+    static ap_trampoline_start: u8;
+    static ap_trampoline_far_target: u8;
+    static ap_trampoline_long_mode: u8;
+    static ap_trampoline_gdt: u8;
+    static ap_trampoline_gdtr: u8;
+    static ap_trampoline_params: u8;
+    static ap_trampoline_end: u8;
+}
+
+/// 启动代码中的符号相对于起始处的偏移
+fn trampoline_offset(symbol: *const u8) -> u64 {
+    symbol as u64 - (&raw const ap_trampoline_start) as u64
+}
+
+/// 在线的处理器数，包括引导处理器
+pub fn online_cpus() -> usize {
+    ONLINE.load(Ordering::Acquire)
+}
+
+/// 启动 MADT 中列出的所有应用处理器，需要在 [`memory::install`] 之后调用
+pub fn init() {
+    let Some(madt) = acpi::madt() else {
+        log::warn!("no ACPI MADT; running on the boot processor only");
+        return;
+    };
+    if !apic::init(madt.local_apic_address) {
+        log::warn!("failed to map the local APIC");
+        return;
+    }
+    apic::timer::init();
+    let Some(frame) = install_trampoline() else {
+        log::warn!("no low memory for the AP trampoline");
+        return;
+    };
+    let bsp = apic::id();
+    percpu::current().set_online(bsp);
+    let mut next_cpu = 1;
+    for processor in &madt.processors {
+        if !processor.enabled || u32::from(processor.apic_id) == bsp {
+            continue;
+        }
+        if next_cpu as usize >= percpu::MAX_CPUS {
+            log::warn!("more than {} CPUs; ignoring the rest", percpu::MAX_CPUS);
+            break;
+        }
+        if !start(frame, u32::from(processor.apic_id), next_cpu) {
+            // 没有上线的处理器可能稍后才读取参数，不能再为下一个处理器改写它们
+            log::warn!("CPU with APIC ID {} did not start", processor.apic_id);
+            break;
+        }
+        next_cpu += 1;
+    }
+    log::info!(
+        "{} of {} processors online",
+        online_cpus(),
+        madt.processors.iter().filter(|p| p.enabled).count()
+    );
+}
+
+/// 把启动代码复制到预留的低端帧，恒等映射该帧并填写与位置有关的字段
+///
+/// # 返回
+///
+/// 启动代码所在的帧，没有低端内存或映射失败时返回 `None`
+fn install_trampoline() -> Option<PhysFrame> {
+    let frame = memory::with_kernel_memory(|mapper, frame_allocator| {
+        let frame = frame_allocator.take_low_frame()?;
+        // 打开分页后的下一条指令仍在这个物理地址上取指，因此需要恒等映射
+        let page =
+            Page::<Size4KiB>::containing_address(VirtAddr::new(frame.start_address().as_u64()));
+        let phys = frame.start_address();
+        if mapper.translate_addr(page.start_address()) != Some(phys) {
+            if let Ok((_, flush)) = mapper.unmap(page) {
+                flush.flush();
+            }
+            let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+            unsafe { memory::map_page(mapper, page, frame, flags, frame_allocator) }.ok()?;
+        }
+        Some(frame)
+    })
+    .flatten()?;
+    let base = frame.start_address().as_u64();
+    let start = &raw const ap_trampoline_start;
+    let len = trampoline_offset(&raw const ap_trampoline_end) as usize;
+    let dst = memory::phys_to_virt(frame.start_address())?.as_mut_ptr::<u8>();
+    unsafe {
+        core::ptr::copy_nonoverlapping(start, dst, len);
+        let far_target = dst.add(trampoline_offset(&raw const ap_trampoline_far_target) as usize);
+        let long_mode = base + trampoline_offset(&raw const ap_trampoline_long_mode);
+        far_target.cast::<u32>().write_unaligned(long_mode as u32);
+        // GDTR 的 16 位界限之后是 32 位基址
+        let gdtr = dst.add(trampoline_offset(&raw const ap_trampoline_gdtr) as usize);
+        let gdt = base + trampoline_offset(&raw const ap_trampoline_gdt);
+        gdtr.add(2).cast::<u32>().write_unaligned(gdt as u32);
+    }
+    Some(frame)
+}
+
+/// 启动一个应用处理器并等待它上线
+///
+/// # 参数
+///
+/// - `frame`: 启动代码所在的帧
+/// - `apic_id`: 处理器的 APIC ID
+/// - `cpu`: 分配给处理器的编号
+///
+/// # 返回
+///
+/// 处理器在超时之前上线时返回 `true`
+fn start(frame: PhysFrame, apic_id: u32, cpu: u64) -> bool {
+    let cr3 = memory::kernel_page_table().start_address().as_u64();
+    assert!(cr3 < 1 << 32, "kernel page table above 4 GiB");
+    let stack = alloc::boxed::Box::leak(vec![0u8; AP_STACK_SIZE].into_boxed_slice());
+    let stack_top = (VirtAddr::from_ptr(stack.as_ptr()) + AP_STACK_SIZE as u64).align_down(16u64);
+    let params = TrampolineParams {
+        cr3,
+        stack_top: stack_top.as_u64(),
+        entry: ap_main as *const () as u64,
+        cpu,
+    };
+    let Some(dst) = memory::phys_to_virt(
+        frame.start_address() + trampoline_offset(&raw const ap_trampoline_params),
+    ) else {
+        return false;
+    };
+    unsafe { dst.as_mut_ptr::<TrampolineParams>().write_volatile(params) };
+
+    let online = online_cpus();
+    let page = (frame.start_address().as_u64() / 4096) as u8;
+    apic::send_init(apic_id);
+    delay_us(INIT_DELAY_US);
+    // 规范要求发送两次 STARTUP IPI，第一次已经成功时不再发送
+    for _ in 0..2 {
+        apic::send_startup(apic_id, page);
+        delay_us(STARTUP_DELAY_US);
+        if online_cpus() > online {
+            return true;
+        }
+    }
+    for _ in 0..ONLINE_TIMEOUT_US / 10 {
+        if online_cpus() > online {
+            return true;
+        }
+        delay_us(10);
+    }
+    false
+}
+
+/// 忙等待大约 `us` 微秒
+///
+/// 时钟中断的精度不够，这里利用向未使用的 0x80 端口写入大约需要 1 微秒
+fn delay_us(us: u64) {
+    let mut port = Port::<u8>::new(0x80);
+    for _ in 0..us {
+        unsafe { port.write(0) };
+    }
+}
+
+/// 应用处理器进入内核后执行的第一个函数
+///
+/// # 参数
+///
+/// - `cpu`: 处理器编号
+extern "C" fn ap_main(cpu: u64) -> ! {
+    gdt::init_ap(cpu as usize);
+    interrupts::init_ap_idt();
+    apic::init_ap();
+    apic::timer::init_ap();
+    // 加载 IDT 之后才能响应 TLB 击落请求
+    percpu::current().set_online(apic::id());
+    ONLINE.fetch_add(1, Ordering::Release);
+    log::info!("CPU {} online (APIC ID {})", cpu, apic::id());
+    thread::run_ap()
+}
+
+#[test_case]
+fn test_trampoline_fits_in_one_page() {
+    let len = trampoline_offset(&raw const ap_trampoline_end);
+    let params = trampoline_offset(&raw const ap_trampoline_params);
+    assert!(len <= 4096);
+    assert_eq!(params % 8, 0);
+    assert_eq!(
+        len - params,
+        core::mem::size_of::<TrampolineParams>() as u64
+    );
+}
@@ -0,0 +1,177 @@
+//! 本模块实现了处理器间的 TLB 击落（shootdown）
+//!
+//! 修改或解除映射之后，其他处理器的 TLB 中可能仍缓存着旧的表项。发起修改的处理器先刷新自己的 TLB，
+//! 再通过 NMI 通知其他在线的处理器刷新同一范围，并等待它们逐一确认。
+//! 目标处理器可能正禁用中断自旋等待发起者持有的锁，普通的中断不会被处理，因此使用 NMI。
+//!
+//! 用户空间的地址只属于当前地址空间，CR3 不同的处理器在切换页表时已经清空了相关表项，收到请求后直接确认
+
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use x86_64::VirtAddr;
+use x86_64::instructions::tlb;
+use x86_64::registers::control::Cr3;
+use x86_64::structures::paging::{Page, PageSize, Size4KiB};
+
+use crate::sync::SpinLock;
+use crate::user::{USER_SPACE_END, USER_SPACE_START};
+use crate::{apic, percpu};
+
+/// 一次刷新的页数超过这个值时改为刷新整个 TLB
+const FLUSH_ALL_THRESHOLD: u64 = 32;
+
+/// 各处理器是否有尚未确认的刷新请求，下标为 APIC ID
+static PENDING: [AtomicBool; 256] = [const { AtomicBool::new(false) }; 256];
+
+/// 当前请求的起始地址
+static REQUEST_START: AtomicU64 = AtomicU64::new(0);
+/// 当前请求的页数，为 0 时刷新整个 TLB
+static REQUEST_PAGES: AtomicU64 = AtomicU64::new(0);
+/// 当前请求所属地址空间的 4 级页表物理地址，为 0 时所有处理器都需要刷新
+static REQUEST_SPACE: AtomicU64 = AtomicU64::new(0);
+
+/// 发起者持有这把锁直到所有目标确认，同一时刻只有一个请求
+static SHOOTDOWN: SpinLock<()> = SpinLock::new(());
+
+/// 发出过的击落请求数
+static SHOOTDOWNS: AtomicU64 = AtomicU64::new(0);
+
+/// 刷新所有处理器上 `page` 的 TLB 表项
+///
+/// # 参数
+///
+/// - `page`: 映射已被修改或解除的页
+pub fn flush(page: Page) {
+    flush_range(page, 1);
+}
+
+/// 刷新所有处理器上从 `start` 开始的 `pages` 个页的 TLB 表项
+///
+/// # 参数
+///
+/// - `start`: 第一个页
+/// - `pages`: 页数，为 0 时什么都不做
+pub fn flush_range(start: Page, pages: u64) {
+    if pages == 0 {
+        return;
+    }
+    let start = start.start_address();
+    flush_local(start, pages);
+    shootdown(start, pages, space_of(start));
+}
+
+/// 刷新所有运行当前地址空间的处理器的整个 TLB
+pub fn flush_all() {
+    tlb::flush_all();
+    shootdown(VirtAddr::zero(), 0, current_space());
+}
+
+/// 发出过的击落请求数
+pub fn shootdowns() -> u64 {
+    SHOOTDOWNS.load(Ordering::Relaxed)
+}
+
+/// 当前处理器的 4 级页表物理地址
+fn current_space() -> u64 {
+    Cr3::read().0.start_address().as_u64()
+}
+
+/// 地址所属的地址空间，内核地址在所有地址空间中相同，返回 0
+fn space_of(addr: VirtAddr) -> u64 {
+    if (USER_SPACE_START..USER_SPACE_END).contains(&addr.as_u64()) {
+        current_space()
+    } else {
+        0
+    }
+}
+
+/// 刷新当前处理器的 TLB
+///
+/// # 参数
+///
+/// - `start`: 起始地址
+/// - `pages`: 页数，为 0 或超过 [`FLUSH_ALL_THRESHOLD`] 时刷新整个 TLB
+fn flush_local(start: VirtAddr, pages: u64) {
+    if pages == 0 || pages > FLUSH_ALL_THRESHOLD {
+        tlb::flush_all();
+        return;
+    }
+    for i in 0..pages {
+        tlb::flush(start + i * Size4KiB::SIZE);
+    }
+}
+
+/// 通知其他在线的处理器刷新 TLB，并等待它们全部确认
+///
+/// # 参数
+///
+/// - `start`: 起始地址
+/// - `pages`: 页数，为 0 时刷新整个 TLB
+/// - `space`: 请求所属的地址空间，为 0 时与地址空间无关
+fn shootdown(start: VirtAddr, pages: u64, space: u64) {
+    if super::online_cpus() <= 1 {
+        return;
+    }
+    // 持有锁期间中断被禁用，当前处理器不会被迁移
+    let _guard = SHOOTDOWN.lock();
+    REQUEST_START.store(start.as_u64(), Ordering::Relaxed);
+    REQUEST_PAGES.store(pages, Ordering::Relaxed);
+    REQUEST_SPACE.store(space, Ordering::Relaxed);
+    let this = percpu::current().apic_id();
+    let targets = || {
+        percpu::all()
+            .filter(|cpu| cpu.is_online() && cpu.apic_id() != this)
+            .map(|cpu| cpu.apic_id() as usize)
+    };
+    for target in targets() {
+        // Release 保证目标看到标志时也能看到上面写入的请求
+        PENDING[target].store(true, Ordering::Release);
+        apic::send_nmi(target as u32);
+    }
+    for target in targets() {
+        while PENDING[target].load(Ordering::Acquire) {
+            core::hint::spin_loop();
+        }
+    }
+    SHOOTDOWNS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// 由 NMI 处理函数调用，处理发给当前处理器的刷新请求
+///
+/// NMI 可能打断 `swapgs` 前后的指令，GS 基址不一定指向数据块，这里通过本地 APIC 确定自己的身份
+///
+/// # 返回
+///
+/// NMI 是刷新请求时返回 `true`
+pub(crate) fn handle_nmi() -> bool {
+    if !apic::is_initialized() {
+        return false;
+    }
+    let Some(pending) = PENDING.get(apic::id() as usize) else {
+        return false;
+    };
+    if !pending.load(Ordering::Acquire) {
+        return false;
+    }
+    let space = REQUEST_SPACE.load(Ordering::Relaxed);
+    if space == 0 || space == current_space() {
+        let start = VirtAddr::new(REQUEST_START.load(Ordering::Relaxed));
+        flush_local(start, REQUEST_PAGES.load(Ordering::Relaxed));
+    }
+    pending.store(false, Ordering::Release);
+    true
+}
+
+#[test_case]
+fn test_flush_with_one_cpu_sends_nothing() {
+    let page = Page::containing_address(VirtAddr::new(0x_4444_4444_0000));
+    let before = shootdowns();
+    flush_range(page, 4);
+    flush_all();
+    assert_eq!(shootdowns(), before);
+    assert!(
+        PENDING
+            .iter()
+            .all(|pending| !pending.load(Ordering::Relaxed))
+    );
+}
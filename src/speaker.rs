@@ -0,0 +1,92 @@
+//! 本模块实现了 PC 扬声器的驱动
+//!
+//! 扬声器由 PIT 2 号通道的方波驱动，0x61 端口控制通道的门控和扬声器的接通。
+//! [`beep`] 开始发声后立即返回，由定时器在时长到期时停止；新的发声会取代尚未结束的发声，
+//! 旧的定时器到期时不再停止它。panic 时定时器中断已经关闭，使用忙等待的 [`beep_blocking`]。
+//! 2 号通道也用于测量时间，测量期间不能发声。键盘上按 Alt+F10 发出一声提示音
+
+use core::time::Duration;
+
+use crate::sync::SpinLock;
+use crate::time::{self, Instant, pit};
+
+/// 可以发出的最低频率（Hz）
+pub const MIN_FREQUENCY_HZ: u32 = 20;
+/// 可以发出的最高频率（Hz）
+pub const MAX_FREQUENCY_HZ: u32 = 20_000;
+
+/// 当前发声的编号，定时器到期时只停止自己开始的发声
+static CURRENT: SpinLock<u64> = SpinLock::new(0);
+
+/// 以 `hz` 的频率发声 `duration`，立即返回
+///
+/// # 参数
+///
+/// - `hz`: 频率，范围为 [`MIN_FREQUENCY_HZ`] 到 [`MAX_FREQUENCY_HZ`]
+/// - `duration`: 发声的时长，至少一个 tick
+///
+/// # 返回
+///
+/// 分频之后的实际频率（Hz），超出范围或 PIT 2 号通道正被用于测量时返回 `None`
+pub fn beep(hz: u32, duration: Duration) -> Option<u32> {
+    if !(MIN_FREQUENCY_HZ..=MAX_FREQUENCY_HZ).contains(&hz) {
+        return None;
+    }
+    let (actual, id) = {
+        let mut current = CURRENT.lock();
+        let actual = pit::start_tone(hz)?;
+        *current += 1;
+        (actual, *current)
+    };
+    time::call_after(time::duration_to_ticks(duration).max(1), move || {
+        let current = CURRENT.lock();
+        if *current == id {
+            pit::stop_tone();
+        }
+    });
+    Some(actual)
+}
+
+/// 立即停止发声
+pub fn stop() {
+    let mut current = CURRENT.lock();
+    *current += 1;
+    pit::stop_tone();
+}
+
+/// 以 `hz` 的频率发声 `duration`，忙等待到结束才返回
+///
+/// 不经过 [`beep`] 使用的锁，可以在关闭中断或 panic 时调用；用 TSC 计时，TSC 尚未校准时不发声
+///
+/// # 参数
+///
+/// - `hz`: 频率，范围为 [`MIN_FREQUENCY_HZ`] 到 [`MAX_FREQUENCY_HZ`]
+/// - `duration`: 发声的时长
+///
+/// # 返回
+///
+/// 频率超出范围、无法计时或 PIT 2 号通道正被用于测量时返回 `false`，不发声
+pub fn beep_blocking(hz: u32, duration: Duration) -> bool {
+    if !(MIN_FREQUENCY_HZ..=MAX_FREQUENCY_HZ).contains(&hz) || time::tsc_frequency_hz() == 0 {
+        return false;
+    }
+    if pit::start_tone(hz).is_none() {
+        return false;
+    }
+    let deadline = Instant::now() + duration;
+    while Instant::now() < deadline {
+        core::hint::spin_loop();
+    }
+    pit::stop_tone();
+    true
+}
+
+#[test_case]
+fn test_beep_rejects_inaudible_frequencies() {
+    assert_eq!(beep(10, Duration::from_millis(1)), None);
+    assert_eq!(beep(30_000, Duration::from_millis(1)), None);
+    assert!(!beep_blocking(0, Duration::from_millis(1)));
+    // PIT 以 1193182 Hz 计数，440 Hz 的分频系数为 2712
+    assert_eq!(beep(440, Duration::from_millis(1)), Some(439));
+    stop();
+}
@@ -0,0 +1,126 @@
+//! 本模块实现了屏幕顶部的状态栏
+//!
+//! 状态栏固定在第 0 行，不参与滚动，显示运行时间、堆使用情况和自定义消息。
+//! 状态栏画在主控制台上，不支持状态栏的后端（如只用串口）不显示
+
+use core::fmt::{self, Write};
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use spin::Mutex;
+
+use crate::vga_buffer::{Color, MAX_BUFFER_WIDTH};
+use crate::{allocator, console, time, tty};
+
+/// 定长的单行文本缓冲区，超出部分被截断
+struct LineBuffer {
+    bytes: [u8; MAX_BUFFER_WIDTH],
+    len: usize,
+}
+
+impl LineBuffer {
+    const fn new() -> Self {
+        Self {
+            bytes: [b' '; MAX_BUFFER_WIDTH],
+            len: 0,
+        }
+    }
+
+    fn as_str(&self) -> &str {
+        // 只会写入完整的 UTF-8 字符，见 `write_str`
+        core::str::from_utf8(&self.bytes[..self.len]).unwrap_or("")
+    }
+}
+
+impl fmt::Write for LineBuffer {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            let mut encoded = [0; 4];
+            let encoded = c.encode_utf8(&mut encoded).as_bytes();
+            if self.len + encoded.len() > MAX_BUFFER_WIDTH {
+                break;
+            }
+            self.bytes[self.len..self.len + encoded.len()].copy_from_slice(encoded);
+            self.len += encoded.len();
+        }
+        Ok(())
+    }
+}
+
+/// 状态栏上的自定义消息（如当前任务）
+static MESSAGE: Mutex<LineBuffer> = Mutex::new(LineBuffer::new());
+
+/// 状态栏是否已启用
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// 上一次刷新时的运行秒数
+static LAST_REFRESH_SECOND: AtomicU64 = AtomicU64::new(0);
+
+/// 状态栏的前景色
+const FOREGROUND: Color = Color::Black;
+/// 状态栏的背景色
+const BACKGROUND: Color = Color::LightGray;
+
+/// 启用状态栏，在主控制台上保留屏幕第一行。应在 [`console::init`] 之后调用
+pub fn init() {
+    console::primary().reserve_status_line();
+    ENABLED.store(true, Ordering::Relaxed);
+    refresh();
+}
+
+/// 设置状态栏上的自定义消息并刷新
+///
+/// # 参数
+///
+/// - `message`: 要显示的消息
+pub fn set(message: &str) {
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+        let mut buffer = MESSAGE.lock();
+        *buffer = LineBuffer::new();
+        let _ = buffer.write_str(message);
+    });
+    refresh();
+}
+
+/// 在主控制台上重新渲染状态栏
+///
+/// 会被定时器中断周期性调用，相关的锁被占用时直接跳过本次刷新
+pub fn refresh() {
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+        if !ENABLED.load(Ordering::Relaxed) {
+            return;
+        }
+        let Some(message) = MESSAGE.try_lock() else {
+            return;
+        };
+
+        let mut line = LineBuffer::new();
+        let uptime = time::uptime_ms() / 1000;
+        let _ = write!(
+            line,
+            " ricky_os | tty{} | up {}s",
+            tty::active() + 1,
+            uptime
+        );
+        if let Some((used, size)) = allocator::heap_usage() {
+            let _ = write!(line, " | heap {}/{} KiB", used / 1024, size / 1024);
+        }
+        if message.len > 0 {
+            let _ = write!(line, " | {}", message.as_str());
+        }
+        drop(message);
+
+        console::primary().status_line(line.as_str(), FOREGROUND, BACKGROUND);
+    });
+}
+
+/// 由定时器中断调用，每秒刷新一次状态栏
+pub(crate) fn tick() {
+    let second = time::uptime_ms() / 1000;
+    if LAST_REFRESH_SECOND.swap(second, Ordering::Relaxed) != second {
+        refresh();
+    }
+}
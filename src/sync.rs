@@ -0,0 +1,16 @@
+//! 本模块实现了内核的同步原语
+//!
+//! 供异步任务使用的原语在无法继续时登记唤醒器并挂起 future，而不是自旋等待，避免独占协作式执行器；
+//! [`SpinLock`] 和 [`RwLock`] 在持有期间禁用中断，可以在线程和中断处理函数之间共享数据
+
+pub mod mpsc;
+mod mutex;
+mod notify;
+mod rwlock;
+mod spin_lock;
+mod waiters;
+
+pub use mutex::{AsyncMutex, AsyncMutexGuard, Lock};
+pub use notify::{Notified, Notify};
+pub use rwlock::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+pub use spin_lock::{SpinLock, SpinLockGuard};
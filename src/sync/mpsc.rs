@@ -0,0 +1,133 @@
+//! 本模块实现了有界的多生产者单消费者通道
+//!
+//! 发送端基于无锁队列，不会阻塞也不会分配内存，因此可以在中断处理函数中使用；
+//! 接收端是一个异步 [`Stream`]，所有发送端都被丢弃且队列为空时结束
+
+use alloc::sync::Arc;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use core::task::{Context, Poll};
+
+use crossbeam_queue::ArrayQueue;
+use futures_util::stream::{Stream, StreamExt};
+use futures_util::task::AtomicWaker;
+
+/// 发送端和接收端共享的状态
+struct Shared<T> {
+    queue: ArrayQueue<T>,
+    waker: AtomicWaker,         // 等待消息的接收端
+    senders: AtomicUsize,       // 存活的发送端数量
+    receiver_alive: AtomicBool, // 接收端是否存活
+}
+
+/// 创建一个通道
+///
+/// # 参数
+///
+/// - `capacity`: 队列最多容纳的消息数，必须大于 0
+pub fn channel<T>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+    let shared = Arc::new(Shared {
+        queue: ArrayQueue::new(capacity),
+        waker: AtomicWaker::new(),
+        senders: AtomicUsize::new(1),
+        receiver_alive: AtomicBool::new(true),
+    });
+    (
+        Sender {
+            shared: shared.clone(),
+        },
+        Receiver { shared },
+    )
+}
+
+/// 发送失败的原因，消息会被交还给调用者
+#[derive(Debug, PartialEq, Eq)]
+pub enum TrySendError<T> {
+    /// 队列已满
+    Full(T),
+    /// 接收端已被丢弃
+    Closed(T),
+}
+
+/// 通道的发送端，可以克隆
+pub struct Sender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Sender<T> {
+    /// 发送一条消息，立即返回
+    ///
+    /// # 参数
+    ///
+    /// - `value`: 消息
+    pub fn try_send(&self, value: T) -> Result<(), TrySendError<T>> {
+        if !self.shared.receiver_alive.load(Ordering::Acquire) {
+            return Err(TrySendError::Closed(value));
+        }
+        self.shared.queue.push(value).map_err(TrySendError::Full)?;
+        self.shared.waker.wake();
+        Ok(())
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.shared.senders.fetch_add(1, Ordering::Relaxed);
+        Self {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        // 最后一个发送端被丢弃时唤醒接收端，让它看到通道已关闭
+        if self.shared.senders.fetch_sub(1, Ordering::AcqRel) == 1 {
+            self.shared.waker.wake();
+        }
+    }
+}
+
+/// 通道的接收端
+pub struct Receiver<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Receiver<T> {
+    /// 取出一条消息，队列为空时返回 `None`
+    pub fn try_recv(&self) -> Option<T> {
+        self.shared.queue.pop()
+    }
+
+    /// 等待下一条消息，通道关闭且没有剩余消息时返回 `None`
+    pub async fn recv(&mut self) -> Option<T> {
+        self.next().await
+    }
+}
+
+impl<T> Stream for Receiver<T> {
+    type Item = T;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<T>> {
+        if let Some(value) = self.shared.queue.pop() {
+            return Poll::Ready(Some(value));
+        }
+
+        self.shared.waker.register(cx.waker());
+        // 注册之后再检查一次，避免错过在此期间到达的消息
+        if let Some(value) = self.shared.queue.pop() {
+            self.shared.waker.take();
+            return Poll::Ready(Some(value));
+        }
+        if self.shared.senders.load(Ordering::Acquire) == 0 {
+            return Poll::Ready(None);
+        }
+        Poll::Pending
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.shared.receiver_alive.store(false, Ordering::Release);
+    }
+}
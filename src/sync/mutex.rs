@@ -0,0 +1,100 @@
+//! 本模块实现了异步互斥锁
+//!
+//! 锁被占用时，等待的 future 登记唤醒器后挂起，而不是像自旋锁那样占用 CPU，
+//! 因此可以在持有锁期间跨越 `.await`
+
+use core::cell::UnsafeCell;
+use core::future::Future;
+use core::ops::{Deref, DerefMut};
+use core::pin::Pin;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::task::{Context, Poll};
+
+use super::waiters::Waiters;
+
+/// 异步互斥锁
+pub struct AsyncMutex<T> {
+    locked: AtomicBool,
+    waiters: Waiters,
+    value: UnsafeCell<T>,
+}
+
+// 与 `spin::Mutex` 相同，锁保证同一时刻只有一个任务访问内部的值
+unsafe impl<T: Send> Sync for AsyncMutex<T> {}
+unsafe impl<T: Send> Send for AsyncMutex<T> {}
+
+impl<T> AsyncMutex<T> {
+    /// 创建一个异步互斥锁
+    ///
+    /// # 参数
+    ///
+    /// - `value`: 被保护的值
+    pub const fn new(value: T) -> Self {
+        Self {
+            locked: AtomicBool::new(false),
+            waiters: Waiters::new(),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    /// 等待并获取锁
+    pub fn lock(&self) -> Lock<'_, T> {
+        Lock { mutex: self }
+    }
+
+    /// 尝试获取锁，锁被占用时立即返回 `None`
+    pub fn try_lock(&self) -> Option<AsyncMutexGuard<'_, T>> {
+        self.locked
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .ok()
+            .map(|_| AsyncMutexGuard { mutex: self })
+    }
+}
+
+/// 等待获取锁的 future
+pub struct Lock<'a, T> {
+    mutex: &'a AsyncMutex<T>,
+}
+
+impl<'a, T> Future for Lock<'a, T> {
+    type Output = AsyncMutexGuard<'a, T>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        if let Some(guard) = self.mutex.try_lock() {
+            return Poll::Ready(guard);
+        }
+        self.mutex.waiters.register(cx.waker());
+        // 登记之后再尝试一次，避免错过在此期间发生的解锁
+        match self.mutex.try_lock() {
+            Some(guard) => Poll::Ready(guard),
+            None => Poll::Pending,
+        }
+    }
+}
+
+/// 异步互斥锁的守卫，离开作用域时释放锁
+pub struct AsyncMutexGuard<'a, T> {
+    mutex: &'a AsyncMutex<T>,
+}
+
+impl<T> Deref for AsyncMutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.mutex.value.get() }
+    }
+}
+
+impl<T> DerefMut for AsyncMutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.mutex.value.get() }
+    }
+}
+
+impl<T> Drop for AsyncMutexGuard<'_, T> {
+    fn drop(&mut self) {
+        self.mutex.locked.store(false, Ordering::Release);
+        // 等待者可能已经放弃等待，唤醒所有等待者重新竞争，避免唤醒丢失
+        self.mutex.waiters.wake_all();
+    }
+}
@@ -0,0 +1,65 @@
+//! 本模块实现了异步通知
+//!
+//! 一个任务等待 [`Notify::notified`]，另一个任务或中断处理函数调用 [`Notify::notify_one`]
+//! 唤醒它。没有任务在等待时通知会被保留，下一次等待立即完成
+
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::task::{Context, Poll};
+
+use super::waiters::Waiters;
+
+/// 异步通知
+pub struct Notify {
+    permit: AtomicBool, // 尚未被消费的通知
+    waiters: Waiters,
+}
+
+impl Notify {
+    /// 创建一个异步通知
+    pub const fn new() -> Self {
+        Self {
+            permit: AtomicBool::new(false),
+            waiters: Waiters::new(),
+        }
+    }
+
+    /// 等待下一次通知
+    pub fn notified(&self) -> Notified<'_> {
+        Notified { notify: self }
+    }
+
+    /// 发出一次通知，唤醒一个等待者；没有等待者时保留到下一次等待
+    pub fn notify_one(&self) {
+        self.permit.store(true, Ordering::Release);
+        self.waiters.wake_one();
+    }
+}
+
+impl Default for Notify {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// 等待通知的 future
+pub struct Notified<'a> {
+    notify: &'a Notify,
+}
+
+impl Future for Notified<'_> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        if self.notify.permit.swap(false, Ordering::Acquire) {
+            return Poll::Ready(());
+        }
+        self.notify.waiters.register(cx.waker());
+        // 登记之后再检查一次，避免错过在此期间到达的通知
+        if self.notify.permit.swap(false, Ordering::Acquire) {
+            return Poll::Ready(());
+        }
+        Poll::Pending
+    }
+}
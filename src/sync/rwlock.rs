@@ -0,0 +1,179 @@
+//! 本模块实现了可以在中断处理函数中使用的读写锁
+//!
+//! 多个读者可以同时持有锁，写者独占。与 [`SpinLock`](super::SpinLock) 一样，
+//! 持有期间禁用中断，释放时恢复。已有写者在等待时新的读者不再进入，避免写者饿死
+
+use core::cell::UnsafeCell;
+use core::marker::PhantomData;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use x86_64::instructions::interrupts;
+
+/// 状态中表示写者持有锁的位
+const WRITER: usize = 1 << (usize::BITS - 1);
+/// 状态中表示有写者在等待的位
+const WRITER_WAITING: usize = 1 << (usize::BITS - 2);
+/// 状态中的读者计数
+const READERS: usize = WRITER_WAITING - 1;
+
+/// 持有期间禁用中断的读写锁
+pub struct RwLock<T: ?Sized> {
+    state: AtomicUsize, // 写者位、等待写者位和读者数
+    value: UnsafeCell<T>,
+}
+
+unsafe impl<T: ?Sized + Send + Sync> Sync for RwLock<T> {}
+unsafe impl<T: ?Sized + Send> Send for RwLock<T> {}
+
+/// [`RwLock`] 的读守卫
+pub struct RwLockReadGuard<'a, T: ?Sized> {
+    lock: &'a RwLock<T>,
+    interrupts_enabled: bool, // 获取锁之前是否开启了中断
+    _not_send: PhantomData<*const ()>,
+}
+
+/// [`RwLock`] 的写守卫
+pub struct RwLockWriteGuard<'a, T: ?Sized> {
+    lock: &'a RwLock<T>,
+    interrupts_enabled: bool, // 获取锁之前是否开启了中断
+    _not_send: PhantomData<*const ()>,
+}
+
+/// 保存中断状态并禁用中断
+fn save_and_disable() -> bool {
+    let enabled = interrupts::are_enabled();
+    interrupts::disable();
+    enabled
+}
+
+impl<T> RwLock<T> {
+    /// 创建一个读写锁
+    ///
+    /// # 参数
+    ///
+    /// - `value`: 被保护的值
+    pub const fn new(value: T) -> Self {
+        Self {
+            state: AtomicUsize::new(0),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    /// 取出被保护的值
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+}
+
+impl<T: ?Sized> RwLock<T> {
+    /// 以读者身份获取锁，有写者持有或等待时自旋
+    pub fn read(&self) -> RwLockReadGuard<'_, T> {
+        let interrupts_enabled = save_and_disable();
+        loop {
+            let state = self.state.load(Ordering::Relaxed);
+            if state & (WRITER | WRITER_WAITING) == 0
+                && state & READERS < READERS
+                && self
+                    .state
+                    .compare_exchange_weak(state, state + 1, Ordering::Acquire, Ordering::Relaxed)
+                    .is_ok()
+            {
+                break;
+            }
+            core::hint::spin_loop();
+        }
+        RwLockReadGuard {
+            lock: self,
+            interrupts_enabled,
+            _not_send: PhantomData,
+        }
+    }
+
+    /// 以写者身份获取锁，等待所有读者和写者释放
+    pub fn write(&self) -> RwLockWriteGuard<'_, T> {
+        let interrupts_enabled = save_and_disable();
+        loop {
+            let state = self.state.load(Ordering::Relaxed);
+            if state & (WRITER | READERS) == 0 {
+                // 获取成功时一并清除等待位，其他等待的写者会重新设置它
+                if self
+                    .state
+                    .compare_exchange_weak(state, WRITER, Ordering::Acquire, Ordering::Relaxed)
+                    .is_ok()
+                {
+                    break;
+                }
+            } else if state & WRITER_WAITING == 0 {
+                self.state.fetch_or(WRITER_WAITING, Ordering::Relaxed);
+            }
+            core::hint::spin_loop();
+        }
+        RwLockWriteGuard {
+            lock: self,
+            interrupts_enabled,
+            _not_send: PhantomData,
+        }
+    }
+
+    /// 当前持有锁的读者数
+    pub fn reader_count(&self) -> usize {
+        self.state.load(Ordering::Relaxed) & READERS
+    }
+
+    /// 是否有写者持有锁
+    pub fn is_write_locked(&self) -> bool {
+        self.state.load(Ordering::Relaxed) & WRITER != 0
+    }
+
+    /// 通过独占引用访问被保护的值，不需要加锁
+    pub fn get_mut(&mut self) -> &mut T {
+        self.value.get_mut()
+    }
+}
+
+impl<T: Default> Default for RwLock<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+impl<T: ?Sized> Deref for RwLockReadGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.value.get() }
+    }
+}
+
+impl<T: ?Sized> Deref for RwLockWriteGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.value.get() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for RwLockWriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.value.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for RwLockReadGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.state.fetch_sub(1, Ordering::Release);
+        if self.interrupts_enabled {
+            interrupts::enable();
+        }
+    }
+}
+
+impl<T: ?Sized> Drop for RwLockWriteGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.state.fetch_and(!WRITER, Ordering::Release);
+        if self.interrupts_enabled {
+            interrupts::enable();
+        }
+    }
+}
@@ -0,0 +1,153 @@
+//! 本模块实现了可以同时在线程和中断处理函数中使用的自旋锁
+//!
+//! 线程持有普通的自旋锁时被中断，而中断处理函数又去获取同一把锁，就会永远自旋下去。
+//! [`SpinLock`] 在获取锁之前保存中断状态并禁用中断，释放时再恢复，避免了这种死锁。
+//! 嵌套持有多把锁时应按获取的相反顺序释放，否则先释放的外层锁会提前开启中断
+
+use core::cell::UnsafeCell;
+use core::fmt;
+use core::marker::PhantomData;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use x86_64::instructions::interrupts;
+
+/// 持有期间禁用中断的自旋锁
+pub struct SpinLock<T: ?Sized> {
+    locked: AtomicBool,
+    value: UnsafeCell<T>,
+}
+
+// 与 `spin::Mutex` 相同，锁保证同一时刻只有一个执行流访问内部的值
+unsafe impl<T: ?Sized + Send> Sync for SpinLock<T> {}
+unsafe impl<T: ?Sized + Send> Send for SpinLock<T> {}
+
+/// [`SpinLock`] 的守卫，释放时解锁并恢复获取锁之前的中断状态
+///
+/// 中断状态属于当前处理器，守卫不能被发送到其他线程
+pub struct SpinLockGuard<'a, T: ?Sized> {
+    lock: &'a SpinLock<T>,
+    interrupts_enabled: bool, // 获取锁之前是否开启了中断
+    _not_send: PhantomData<*const ()>,
+}
+
+impl<T> SpinLock<T> {
+    /// 创建一个自旋锁
+    ///
+    /// # 参数
+    ///
+    /// - `value`: 被保护的值
+    pub const fn new(value: T) -> Self {
+        Self {
+            locked: AtomicBool::new(false),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    /// 取出被保护的值
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+}
+
+impl<T: ?Sized> SpinLock<T> {
+    /// 禁用中断并自旋等待，直到获取锁
+    pub fn lock(&self) -> SpinLockGuard<'_, T> {
+        let interrupts_enabled = interrupts::are_enabled();
+        interrupts::disable();
+        while self
+            .locked
+            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            // 只读地等待锁被释放，避免反复写入缓存行
+            while self.locked.load(Ordering::Relaxed) {
+                core::hint::spin_loop();
+            }
+        }
+        SpinLockGuard {
+            lock: self,
+            interrupts_enabled,
+            _not_send: PhantomData,
+        }
+    }
+
+    /// 尝试获取锁，锁已被占用时立即返回 `None` 并恢复中断状态
+    pub fn try_lock(&self) -> Option<SpinLockGuard<'_, T>> {
+        let interrupts_enabled = interrupts::are_enabled();
+        interrupts::disable();
+        if self
+            .locked
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok()
+        {
+            Some(SpinLockGuard {
+                lock: self,
+                interrupts_enabled,
+                _not_send: PhantomData,
+            })
+        } else {
+            if interrupts_enabled {
+                interrupts::enable();
+            }
+            None
+        }
+    }
+
+    /// 锁是否正被持有
+    pub fn is_locked(&self) -> bool {
+        self.locked.load(Ordering::Relaxed)
+    }
+
+    /// 通过独占引用访问被保护的值，不需要加锁
+    pub fn get_mut(&mut self) -> &mut T {
+        self.value.get_mut()
+    }
+
+    /// 强制释放锁，不恢复中断状态
+    ///
+    /// # Safety
+    ///
+    /// 只能在持有锁的执行流已经不会再运行时使用，例如在 panic 处理函数中
+    pub unsafe fn force_unlock(&self) {
+        self.locked.store(false, Ordering::Release);
+    }
+}
+
+impl<T: Default> Default for SpinLock<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for SpinLock<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.try_lock() {
+            Some(guard) => f.debug_struct("SpinLock").field("value", &&*guard).finish(),
+            None => f.write_str("SpinLock { <locked> }"),
+        }
+    }
+}
+
+impl<T: ?Sized> Deref for SpinLockGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.value.get() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for SpinLockGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.value.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for SpinLockGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.locked.store(false, Ordering::Release);
+        if self.interrupts_enabled {
+            interrupts::enable();
+        }
+    }
+}
@@ -0,0 +1,53 @@
+//! 本模块实现了等待中的 future 的唤醒器列表
+
+use alloc::collections::VecDeque;
+use core::task::Waker;
+
+use spin::Mutex;
+
+/// 等待队列，按登记的先后顺序保存唤醒器
+pub(super) struct Waiters {
+    wakers: Mutex<VecDeque<Waker>>,
+}
+
+impl Waiters {
+    pub(super) const fn new() -> Self {
+        Self {
+            wakers: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// 登记一个唤醒器，已经登记过的唤醒器不会重复加入
+    ///
+    /// # 参数
+    ///
+    /// - `waker`: 唤醒器
+    pub(super) fn register(&self, waker: &Waker) {
+        use x86_64::instructions::interrupts;
+
+        // 中断处理函数也可能发出通知，持有锁期间禁用中断
+        interrupts::without_interrupts(|| {
+            let mut wakers = self.wakers.lock();
+            if !wakers.iter().any(|w| w.will_wake(waker)) {
+                wakers.push_back(waker.clone());
+            }
+        });
+    }
+
+    /// 唤醒最早登记的一个等待者
+    pub(super) fn wake_one(&self) {
+        use x86_64::instructions::interrupts;
+
+        if let Some(waker) = interrupts::without_interrupts(|| self.wakers.lock().pop_front()) {
+            waker.wake();
+        }
+    }
+
+    /// 唤醒所有等待者
+    pub(super) fn wake_all(&self) {
+        use x86_64::instructions::interrupts;
+
+        let wakers = interrupts::without_interrupts(|| core::mem::take(&mut *self.wakers.lock()));
+        wakers.into_iter().for_each(Waker::wake);
+    }
+}
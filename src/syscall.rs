@@ -0,0 +1,523 @@
+//! 本模块实现了基于 `syscall`/`sysret` 指令的系统调用接口
+//!
+//! 调用约定与 Linux 相同：RAX 为系统调用号，参数依次放在 RDI、RSI、RDX、R10、R8 中，
+//! 返回值放在 RAX 中，出错时返回负的错误码。`syscall` 会用 RCX 和 R11 保存返回地址和 RFLAGS
+
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use core::arch::naked_asm;
+use core::time::Duration;
+
+use x86_64::VirtAddr;
+
+use crate::arch::msr::{EFER_SCE, IA32_EFER, IA32_FMASK, IA32_LSTAR, IA32_STAR};
+use crate::process::{
+    self,
+    file::{Console, File},
+    futex, pipe, shm, signal,
+};
+use crate::{gdt, percpu, thread, user};
+
+/// 向文件描述符写入数据：`write(fd, buf, len) -> 写入的字节数`
+pub const SYS_WRITE: u64 = 0;
+/// 结束当前线程：`exit(code) -> !`
+pub const SYS_EXIT: u64 = 1;
+/// 睡眠指定的毫秒数：`sleep(ms) -> 0`
+pub const SYS_SLEEP: u64 = 2;
+/// 等待子进程退出并回收：`wait(pid, status) -> 子进程编号`，`pid` 为 -1 时等待任意子进程
+pub const SYS_WAIT: u64 = 3;
+/// 复制当前进程：`fork() -> 子进程编号`，子进程中返回 0
+pub const SYS_FORK: u64 = 4;
+/// 从文件描述符读取数据：`read(fd, buf, len) -> 读取的字节数`，读到末尾时返回 0
+pub const SYS_READ: u64 = 5;
+/// 创建管道：`pipe(fds) -> 0`，读端和写端的文件描述符依次以 u32 写入 `fds`
+pub const SYS_PIPE: u64 = 6;
+/// 关闭文件描述符：`close(fd) -> 0`
+pub const SYS_CLOSE: u64 = 7;
+/// 复制文件描述符：`dup2(old, new) -> new`
+pub const SYS_DUP2: u64 = 8;
+/// 打开共享内存段，不存在时创建：`shm_open(name, len, size) -> 段的编号`
+pub const SYS_SHM_OPEN: u64 = 9;
+/// 将共享内存段映射到当前进程：`shm_map(id, addr) -> addr`
+pub const SYS_SHM_MAP: u64 = 10;
+/// 解除共享内存映射：`shm_unmap(addr) -> 0`
+pub const SYS_SHM_UNMAP: u64 = 11;
+/// 删除共享内存段的名称：`shm_unlink(name, len) -> 0`
+pub const SYS_SHM_UNLINK: u64 = 12;
+/// `addr` 处的 32 位值等于 `expected` 时挂起：`futex_wait(addr, expected) -> 0`
+pub const SYS_FUTEX_WAIT: u64 = 13;
+/// 唤醒在 `addr` 上等待的线程：`futex_wake(addr, count) -> 被唤醒的线程数`
+pub const SYS_FUTEX_WAKE: u64 = 14;
+/// 向进程发送信号：`kill(pid, signal) -> 0`
+pub const SYS_KILL: u64 = 15;
+/// 设置收到信号时的动作：`sigaction(signal, handler, restorer) -> 0`
+pub const SYS_SIGACTION: u64 = 16;
+/// 从信号处理函数返回：`sigreturn() -> 被打断的系统调用的返回值`，只能由恢复函数调用
+pub const SYS_SIGRETURN: u64 = 17;
+/// 当前进程的编号：`getpid() -> pid`
+pub const SYS_GETPID: u64 = 18;
+
+/// 操作不允许
+pub const EPERM: i64 = 1;
+/// 对象不存在
+pub const ENOENT: i64 = 2;
+/// 进程不存在
+pub const ESRCH: i64 = 3;
+/// 系统调用被信号打断
+pub const EINTR: i64 = 4;
+/// 参数列表过长
+pub const E2BIG: i64 = 7;
+/// 不是可以执行的文件
+pub const ENOEXEC: i64 = 8;
+/// 错误的文件描述符
+pub const EBADF: i64 = 9;
+/// 没有符合条件的子进程
+pub const ECHILD: i64 = 10;
+/// 条件已改变，需要重试
+pub const EAGAIN: i64 = 11;
+/// 内存不足
+pub const ENOMEM: i64 = 12;
+/// 错误的地址
+pub const EFAULT: i64 = 14;
+/// 参数无效
+pub const EINVAL: i64 = 22;
+/// 管道的读端已关闭
+pub const EPIPE: i64 = 32;
+/// 不存在的系统调用
+pub const ENOSYS: i64 = 38;
+
+/// 标准输出
+const STDOUT: u64 = 1;
+/// 标准错误
+const STDERR: u64 = 2;
+
+/// 单次 `read` 最多读取的字节数，数据先读到内核缓冲区再复制给用户程序
+const READ_CHUNK: usize = 4096;
+
+/// 进入系统调用时屏蔽的 RFLAGS 位：IF、TF 和 DF
+const SYSCALL_RFLAGS_MASK: u64 = 0x200 | 0x100 | 0x400;
+
+/// 系统调用处理函数，`sigreturn` 会改写整个帧
+type Handler = fn(&mut SyscallFrame) -> i64;
+
+/// 系统调用表，下标为系统调用号
+static SYSCALL_TABLE: [Handler; 19] = [
+    sys_write,
+    sys_exit,
+    sys_sleep,
+    sys_wait,
+    sys_fork,
+    sys_read,
+    sys_pipe,
+    sys_close,
+    sys_dup2,
+    sys_shm_open,
+    sys_shm_map,
+    sys_shm_unmap,
+    sys_shm_unlink,
+    sys_futex_wait,
+    sys_futex_wake,
+    sys_kill,
+    sys_sigaction,
+    sys_sigreturn,
+    sys_getpid,
+];
+
+/// 开启 `syscall` 指令并设置入口和段选择子，需要在 GDT 初始化之后调用
+pub fn init() {
+    let selectors = gdt::selectors();
+    // `syscall` 从 STAR[47:32] 加载内核 CS，SS 为其后一项；
+    // `sysret` 的 SS 为 STAR[63:48] 加 8，CS 为加 16
+    let kernel_base = u64::from(selectors.kernel_code.0);
+    let user_base = u64::from(selectors.user_data.0) - 8;
+    unsafe {
+        IA32_STAR.write(user_base << 48 | kernel_base << 32);
+        IA32_LSTAR.write(syscall_entry as *const () as u64);
+        IA32_FMASK.write(SYSCALL_RFLAGS_MASK);
+        IA32_EFER.update(EFER_SCE, 0);
+    }
+    set_kernel_stack(gdt::default_kernel_stack().as_u64());
+}
+
+/// 设置当前处理器上系统调用使用的内核栈，由 [`gdt::set_kernel_stack`] 调用
+///
+/// # 参数
+///
+/// - `top`: 内核栈的栈顶
+pub(crate) fn set_kernel_stack(top: u64) {
+    percpu::current().set_kernel_stack(top);
+}
+
+/// 进入系统调用时保存在内核栈上的用户态寄存器，字段顺序与入口压栈的顺序相反
+///
+/// RCX 和 R11 被 `syscall` 用来保存返回地址和 RFLAGS，原来的值已经丢失
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct SyscallFrame {
+    pub r15: u64,
+    pub r14: u64,
+    pub r13: u64,
+    pub r12: u64,
+    pub r10: u64,
+    pub r9: u64,
+    pub r8: u64,
+    pub rbp: u64,
+    pub rdi: u64,
+    pub rsi: u64,
+    pub rdx: u64,
+    pub rbx: u64,
+    pub rax: u64,    // 系统调用号，返回时为返回值
+    pub rip: u64,    // 用户态的返回地址，来自 RCX
+    pub rflags: u64, // 用户态的 RFLAGS，来自 R11
+    pub rsp: u64,    // 用户栈指针
+}
+
+impl SyscallFrame {
+    /// 系统调用的参数，依次为 RDI、RSI、RDX、R10、R8
+    pub fn args(&self) -> [u64; 5] {
+        [self.rdi, self.rsi, self.rdx, self.r10, self.r8]
+    }
+}
+
+/// `syscall` 指令的入口
+///
+/// 换上内核的 GS 基址，切换到当前处理器的内核栈，把用户态寄存器保存为 [`SyscallFrame`]，
+/// 开启中断后调用 [`dispatch`]，返回时从帧中恢复寄存器
+#[unsafe(naked)]
+unsafe extern "C" fn syscall_entry() {
+    naked_asm!(
+        "swapgs",
+        "mov gs:[{user_stack}], rsp",
+        "mov rsp, gs:[{kernel_stack}]",
+        "push qword ptr gs:[{user_stack}]",
+        "push r11",
+        "push rcx",
+        "push rax",
+        "push rbx",
+        "push rdx",
+        "push rsi",
+        "push rdi",
+        "push rbp",
+        "push r8",
+        "push r9",
+        "push r10",
+        "push r12",
+        "push r13",
+        "push r14",
+        "push r15",
+        // 共压入 16 个寄存器，栈仍按 16 字节对齐
+        "mov rdi, rsp",
+        "sti",
+        "call {dispatch}",
+        "mov rdi, rsp",
+        "jmp {return_to_user}",
+        user_stack = const percpu::USER_STACK_OFFSET,
+        kernel_stack = const percpu::KERNEL_STACK_OFFSET,
+        dispatch = sym dispatch,
+        return_to_user = sym return_to_user,
+    );
+}
+
+/// 从 [`SyscallFrame`] 恢复用户态寄存器并用 `sysret` 返回用户态
+///
+/// `sysret` 到非规范的返回地址会在内核态、已经换上用户栈时触发 #GP，
+/// 因此先检查返回地址位于用户空间，否则交给 [`bad_user_return`] 结束进程
+///
+/// # Safety
+///
+/// `frame` 必须指向来自用户态的有效寄存器帧，帧所在的内存在返回之前不能被改写
+#[unsafe(naked)]
+pub(crate) unsafe extern "C" fn return_to_user(frame: *const SyscallFrame) -> ! {
+    naked_asm!(
+        // 从这里到 `sysret` 之间用的是帧所在的栈，不能被中断
+        "cli",
+        "mov rsp, rdi",
+        "mov rax, {user_space_end}",
+        "cmp [rsp + {rip}], rax",
+        "jae 2f",
+        "pop r15",
+        "pop r14",
+        "pop r13",
+        "pop r12",
+        "pop r10",
+        "pop r9",
+        "pop r8",
+        "pop rbp",
+        "pop rdi",
+        "pop rsi",
+        "pop rdx",
+        "pop rbx",
+        "pop rax",
+        "pop rcx",
+        "pop r11",
+        "pop rsp",
+        "swapgs",
+        "sysretq",
+        // 仍在内核栈上、使用内核的 GS 基址，按调用约定对齐栈后结束进程
+        "2:",
+        "and rsp, -16",
+        "call {bad_user_return}",
+        "ud2",
+        user_space_end = const user::USER_SPACE_END,
+        rip = const core::mem::offset_of!(SyscallFrame, rip),
+        bad_user_return = sym bad_user_return,
+    );
+}
+
+/// 返回地址不在用户空间，无法安全地返回用户态，以 `SIGSEGV` 结束当前进程
+extern "C" fn bad_user_return() -> ! {
+    log::warn!(
+        "process {} has a non-canonical return address",
+        thread::current_process().map_or(0, |pid| pid.as_u64())
+    );
+    process::exit_current(128 + signal::SIGSEGV as i64)
+}
+
+/// 按系统调用号查表并调用处理函数，返回值写回帧中的 RAX
+extern "C" fn dispatch(frame: &mut SyscallFrame) {
+    percpu::current().count_syscall();
+    frame.rax = match SYSCALL_TABLE.get(frame.rax as usize) {
+        Some(handler) => handler(frame),
+        None => -ENOSYS,
+    } as u64;
+    signal::deliver(frame);
+}
+
+/// `write(fd, buf, len)`，写入当前进程的文件描述符
+fn sys_write(frame: &mut SyscallFrame) -> i64 {
+    let [fd, buf, len, ..] = frame.args();
+    if !user::is_accessible(buf, len, false) {
+        return -EFAULT;
+    }
+    // 不属于任何进程的线程只能写控制台
+    let file = match process::with_current(|process| process.files().get(fd)) {
+        Some(file) => file,
+        None if fd == STDOUT || fd == STDERR => Some(Arc::new(Console) as Arc<dyn File>),
+        None => None,
+    };
+    let Some(file) = file else {
+        return -EBADF;
+    };
+    let bytes = unsafe { core::slice::from_raw_parts(buf as *const u8, len as usize) };
+    match file.write(bytes) {
+        Ok(written) => written as i64,
+        Err(errno) => -errno,
+    }
+}
+
+/// `exit(code)`，结束当前进程，内核线程直接进入用户态时只结束当前线程
+fn sys_exit(frame: &mut SyscallFrame) -> i64 {
+    let [code, ..] = frame.args();
+    process::exit_current(code as i64)
+}
+
+/// `sleep(ms)`，挂起当前线程指定的毫秒数
+fn sys_sleep(frame: &mut SyscallFrame) -> i64 {
+    let [ms, ..] = frame.args();
+    thread::sleep(Duration::from_millis(ms));
+    0
+}
+
+/// `wait(pid, status)`，等待子进程退出，`status` 不为空时写入退出码
+fn sys_wait(frame: &mut SyscallFrame) -> i64 {
+    let [pid, status, ..] = frame.args();
+    if status != 0 && !user::is_accessible(status, 8, true) {
+        return -EFAULT;
+    }
+    let pid = (pid as i64 != -1).then(|| process::Pid::from_u64(pid));
+    match process::wait(pid) {
+        Ok((child, code)) => {
+            if status != 0 {
+                user::copy_to(VirtAddr::new(status), &code.to_le_bytes());
+            }
+            child.as_u64() as i64
+        }
+        Err(errno) => -errno,
+    }
+}
+
+/// `fork()`，复制当前进程的地址空间和文件描述符表
+fn sys_fork(frame: &mut SyscallFrame) -> i64 {
+    match process::fork(frame) {
+        Ok(child) => child.as_u64() as i64,
+        Err(errno) => -errno,
+    }
+}
+
+/// `read(fd, buf, len)`，从当前进程的文件描述符读取数据
+fn sys_read(frame: &mut SyscallFrame) -> i64 {
+    let [fd, buf, len, ..] = frame.args();
+    if !user::is_accessible(buf, len, true) {
+        return -EFAULT;
+    }
+    let Some(file) = process::with_current(|process| process.files().get(fd)).flatten() else {
+        return -EBADF;
+    };
+    // 读取可能挂起，用户缓冲区可能是写时复制的页，经由内核缓冲区复制
+    let mut bytes = vec![0; (len as usize).min(READ_CHUNK)];
+    match file.read(&mut bytes) {
+        Ok(read) => {
+            user::copy_to(VirtAddr::new(buf), &bytes[..read]);
+            read as i64
+        }
+        Err(errno) => -errno,
+    }
+}
+
+/// `pipe(fds)`，创建管道并写入读端和写端的文件描述符
+fn sys_pipe(frame: &mut SyscallFrame) -> i64 {
+    let [fds, ..] = frame.args();
+    if !user::is_accessible(fds, 8, true) {
+        return -EFAULT;
+    }
+    let (reader, writer) = pipe::pipe();
+    let Some((read_fd, write_fd)) = process::with_current(|process| {
+        let files = process.files();
+        (
+            files.insert(Arc::new(reader)),
+            files.insert(Arc::new(writer)),
+        )
+    }) else {
+        return -EBADF;
+    };
+    let mut bytes = [0; 8];
+    bytes[..4].copy_from_slice(&(read_fd as u32).to_le_bytes());
+    bytes[4..].copy_from_slice(&(write_fd as u32).to_le_bytes());
+    user::copy_to(VirtAddr::new(fds), &bytes);
+    0
+}
+
+/// `close(fd)`，关闭当前进程的文件描述符
+fn sys_close(frame: &mut SyscallFrame) -> i64 {
+    let [fd, ..] = frame.args();
+    // 在进程表的锁之外释放文件，管道的一端关闭时会唤醒另一端
+    let file = process::with_current(|process| {
+        let file = process.files().get(fd);
+        process.files().close(fd);
+        file
+    })
+    .flatten();
+    match file {
+        Some(_) => 0,
+        None => -EBADF,
+    }
+}
+
+/// `dup2(old, new)`，让文件描述符 `new` 引用 `old` 引用的对象
+fn sys_dup2(frame: &mut SyscallFrame) -> i64 {
+    let [old, new, ..] = frame.args();
+    match process::with_current(|process| process.files().duplicate(old, new)) {
+        Some(true) => new as i64,
+        _ => -EBADF,
+    }
+}
+
+/// 把用户程序传入的名称复制到内核中
+///
+/// 复制之后用户程序的其他线程修改或解除映射这段内存，不会影响内核使用的名称
+///
+/// # 返回
+///
+/// 内存不可访问时返回 `EFAULT`，不是 UTF-8 时返回 `EINVAL`
+fn user_str(ptr: u64, len: u64) -> Result<String, i64> {
+    if !user::is_accessible(ptr, len, false) {
+        return Err(EFAULT);
+    }
+    let bytes = unsafe { core::slice::from_raw_parts(ptr as *const u8, len as usize) };
+    String::from_utf8(bytes.to_vec()).map_err(|_| EINVAL)
+}
+
+/// `shm_open(name, len, size)`，打开或创建共享内存段
+fn sys_shm_open(frame: &mut SyscallFrame) -> i64 {
+    let [name, len, size, ..] = frame.args();
+    match user_str(name, len).and_then(|name| shm::open(&name, size)) {
+        Ok(id) => id as i64,
+        Err(errno) => -errno,
+    }
+}
+
+/// `shm_map(id, addr)`，将共享内存段映射到当前进程的 `addr` 处
+fn sys_shm_map(frame: &mut SyscallFrame) -> i64 {
+    let [id, addr, ..] = frame.args();
+    let Ok(addr) = VirtAddr::try_new(addr) else {
+        return -EINVAL;
+    };
+    match shm::map(id, addr) {
+        Ok(()) => addr.as_u64() as i64,
+        Err(errno) => -errno,
+    }
+}
+
+/// `shm_unmap(addr)`，解除从 `addr` 开始的共享内存映射
+fn sys_shm_unmap(frame: &mut SyscallFrame) -> i64 {
+    let [addr, ..] = frame.args();
+    let Ok(addr) = VirtAddr::try_new(addr) else {
+        return -EINVAL;
+    };
+    match shm::unmap(addr) {
+        Ok(()) => 0,
+        Err(errno) => -errno,
+    }
+}
+
+/// `shm_unlink(name, len)`，删除共享内存段的名称
+fn sys_shm_unlink(frame: &mut SyscallFrame) -> i64 {
+    let [name, len, ..] = frame.args();
+    match user_str(name, len).and_then(|name| shm::unlink(&name)) {
+        Ok(()) => 0,
+        Err(errno) => -errno,
+    }
+}
+
+/// `futex_wait(addr, expected)`，`addr` 处的值仍为 `expected` 时挂起
+fn sys_futex_wait(frame: &mut SyscallFrame) -> i64 {
+    let [addr, expected, ..] = frame.args();
+    let Ok(addr) = VirtAddr::try_new(addr) else {
+        return -EFAULT;
+    };
+    match futex::wait(addr, expected as u32) {
+        Ok(()) => 0,
+        Err(errno) => -errno,
+    }
+}
+
+/// `futex_wake(addr, count)`，唤醒最多 `count` 个在 `addr` 上等待的线程
+fn sys_futex_wake(frame: &mut SyscallFrame) -> i64 {
+    let [addr, count, ..] = frame.args();
+    let Ok(addr) = VirtAddr::try_new(addr) else {
+        return -EFAULT;
+    };
+    match futex::wake(addr, count) {
+        Ok(woken) => woken as i64,
+        Err(errno) => -errno,
+    }
+}
+
+/// `kill(pid, signal)`，向进程发送信号
+fn sys_kill(frame: &mut SyscallFrame) -> i64 {
+    let [pid, sig, ..] = frame.args();
+    match signal::kill(process::Pid::from_u64(pid), sig) {
+        Ok(()) => 0,
+        Err(errno) => -errno,
+    }
+}
+
+/// `sigaction(signal, handler, restorer)`，设置当前进程收到信号时的动作
+fn sys_sigaction(frame: &mut SyscallFrame) -> i64 {
+    let [sig, handler, restorer, ..] = frame.args();
+    match signal::set_action(sig, handler, restorer) {
+        Ok(()) => 0,
+        Err(errno) => -errno,
+    }
+}
+
+/// `sigreturn()`，恢复信号处理函数打断的寄存器
+fn sys_sigreturn(frame: &mut SyscallFrame) -> i64 {
+    signal::sigreturn(frame)
+}
+
+/// `getpid()`，当前进程的编号，内核线程直接进入用户态时返回 0
+fn sys_getpid(_frame: &mut SyscallFrame) -> i64 {
+    thread::current_process().map_or(0, |pid| pid.as_u64() as i64)
+}
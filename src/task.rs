@@ -0,0 +1,165 @@
+//! 本模块实现了协作式的异步任务
+//!
+//! 内核中的长期工作（键盘处理、shell 等）可以写成异步函数，包装为 [`Task`] 后交给执行器运行
+
+pub mod executor;
+pub mod simple_executor;
+
+use alloc::boxed::Box;
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::task::{Context, Poll};
+
+use conquer_once::spin::OnceCell;
+
+use executor::Spawner;
+
+/// 任务编号，在所有任务中唯一
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct TaskId(u64);
+
+impl TaskId {
+    /// 分配一个新的任务编号
+    fn new() -> Self {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+        Self(NEXT_ID.fetch_add(1, Ordering::Relaxed))
+    }
+
+    /// 编号的数值
+    pub fn as_u64(self) -> u64 {
+        self.0
+    }
+}
+
+/// 任务的优先级，执行器总是先运行高优先级的就绪任务
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(u8)]
+pub enum Priority {
+    /// 中断的后续处理，例如键盘回显，需要尽快响应
+    InterruptFollowup = 0,
+    /// 普通任务
+    Normal = 1,
+    /// 后台任务，只在没有其他任务就绪时运行
+    Background = 2,
+}
+
+impl Priority {
+    /// 优先级的数量
+    pub const COUNT: usize = 3;
+
+    /// 优先级的名称
+    pub fn name(self) -> &'static str {
+        match self {
+            Priority::InterruptFollowup => "interrupt-followup",
+            Priority::Normal => "normal",
+            Priority::Background => "background",
+        }
+    }
+}
+
+/// 异步任务，持有一个堆上固定的 future
+///
+/// 任务可能由中断处理函数生成，因此 future 必须是 `Send` 的
+pub struct Task {
+    id: TaskId,
+    priority: Priority,
+    stats: TaskStats,
+    future: Pin<Box<dyn Future<Output = ()> + Send>>,
+}
+
+/// 任务的运行统计
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TaskStats {
+    /// 被轮询的次数
+    pub polls: u64,
+    /// 轮询累计花费的 TSC 周期数
+    pub cycles: u64,
+}
+
+impl Task {
+    /// 创建一个任务
+    ///
+    /// # 参数
+    ///
+    /// - `future`: 任务要执行的 future
+    pub fn new(future: impl Future<Output = ()> + Send + 'static) -> Self {
+        Self::with_priority(future, Priority::Normal)
+    }
+
+    /// 创建一个指定优先级的任务
+    ///
+    /// # 参数
+    ///
+    /// - `future`: 任务要执行的 future
+    /// - `priority`: 优先级
+    pub fn with_priority(
+        future: impl Future<Output = ()> + Send + 'static,
+        priority: Priority,
+    ) -> Self {
+        Self {
+            id: TaskId::new(),
+            priority,
+            stats: TaskStats {
+                polls: 0,
+                cycles: 0,
+            },
+            future: Box::pin(future),
+        }
+    }
+
+    /// 任务编号
+    pub fn id(&self) -> TaskId {
+        self.id
+    }
+
+    /// 任务的优先级
+    pub fn priority(&self) -> Priority {
+        self.priority
+    }
+
+    /// 任务的运行统计
+    pub fn stats(&self) -> TaskStats {
+        self.stats
+    }
+
+    /// 推进任务的执行，并记录轮询次数和耗时
+    ///
+    /// # 参数
+    ///
+    /// - `context`: 轮询上下文
+    fn poll(&mut self, context: &mut Context) -> Poll<()> {
+        use core::arch::x86_64::_rdtsc;
+
+        let start = unsafe { _rdtsc() };
+        let result = self.future.as_mut().poll(context);
+        let end = unsafe { _rdtsc() };
+        self.stats.polls += 1;
+        self.stats.cycles += end.wrapping_sub(start);
+        result
+    }
+}
+
+/// 全局的任务生成器，供驱动程序等没有执行器引用的代码使用
+static SPAWNER: OnceCell<Spawner> = OnceCell::uninit();
+
+/// 注册全局的任务生成器，只有第一次调用生效
+///
+/// # 参数
+///
+/// - `spawner`: 执行器的任务生成器
+pub fn init_spawner(spawner: Spawner) {
+    let _ = SPAWNER.try_init_once(|| spawner);
+}
+
+/// 通过全局的任务生成器添加一个任务
+///
+/// # 参数
+///
+/// - `task`: 要运行的任务
+pub fn spawn(task: Task) {
+    SPAWNER
+        .try_get()
+        .expect("task spawner not initialized")
+        .spawn(task);
+}
@@ -0,0 +1,289 @@
+//! 本模块实现了基于唤醒器的执行器
+//!
+//! 每个任务拥有自己的唤醒器，被唤醒时把任务编号放入其优先级对应的就绪队列，
+//! 执行器只轮询就绪的任务，并且每次都从最高优先级的非空队列中选取，
+//! 没有就绪任务时用 `hlt` 让 CPU 休眠到下一次中断
+//!
+//! 执行器运行之后，其他任务和中断处理函数可以通过 [`Spawner`] 添加新任务
+
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::task::Wake;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::task::{Context, Poll, Waker};
+
+use crossbeam_queue::ArrayQueue;
+
+use super::{Priority, Task, TaskId, TaskStats};
+use crate::println;
+
+/// 每个就绪队列的容量
+const TASK_QUEUE_SIZE: usize = 100;
+
+/// 按优先级划分的就绪队列
+struct ReadyQueues {
+    queues: [ArrayQueue<TaskId>; Priority::COUNT],
+}
+
+impl ReadyQueues {
+    fn new() -> Self {
+        Self {
+            queues: core::array::from_fn(|_| ArrayQueue::new(TASK_QUEUE_SIZE)),
+        }
+    }
+
+    /// 将任务放入对应优先级的队列
+    ///
+    /// # 参数
+    ///
+    /// - `priority`: 任务的优先级
+    /// - `task_id`: 任务编号
+    fn push(&self, priority: Priority, task_id: TaskId) {
+        self.queues[priority as usize]
+            .push(task_id)
+            .expect("task_queue full");
+    }
+
+    /// 取出优先级最高的就绪任务
+    fn pop(&self) -> Option<TaskId> {
+        self.queues.iter().find_map(ArrayQueue::pop)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.queues.iter().all(ArrayQueue::is_empty)
+    }
+
+    /// 所有队列中的任务数
+    fn len(&self) -> usize {
+        self.queues.iter().map(ArrayQueue::len).sum()
+    }
+}
+
+/// 是否有人请求打印运行统计，由执行器在两轮轮询之间处理
+static STATS_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+/// 请求正在运行的执行器打印所有任务的运行统计（见 [`Executor::print_stats`]）
+///
+/// 可以在任务和中断处理函数中调用，统计在当前这一轮轮询结束后打印
+pub fn request_stats() {
+    STATS_REQUESTED.store(true, Ordering::Relaxed);
+}
+
+/// 基于唤醒器的执行器
+pub struct Executor {
+    tasks: BTreeMap<TaskId, Task>,        // 所有未完成的任务
+    task_queue: Arc<ReadyQueues>,         // 就绪任务的编号，唤醒器和执行器共享
+    waker_cache: BTreeMap<TaskId, Waker>, // 每个任务的唤醒器，避免每次轮询都重新创建
+    spawned: Arc<ArrayQueue<Task>>,       // 通过生成器添加、尚未被执行器接收的任务
+}
+
+impl Executor {
+    /// 创建一个空的执行器
+    pub fn new() -> Self {
+        Self {
+            tasks: BTreeMap::new(),
+            task_queue: Arc::new(ReadyQueues::new()),
+            waker_cache: BTreeMap::new(),
+            spawned: Arc::new(ArrayQueue::new(TASK_QUEUE_SIZE)),
+        }
+    }
+
+    /// 获取一个任务生成器
+    pub fn spawner(&self) -> Spawner {
+        Spawner {
+            spawned: self.spawned.clone(),
+        }
+    }
+
+    /// 添加一个任务，新任务总是就绪的
+    ///
+    /// # 参数
+    ///
+    /// - `task`: 要运行的任务
+    pub fn spawn(&mut self, task: Task) {
+        accept(&mut self.tasks, &self.task_queue, task);
+    }
+
+    /// 持续运行任务，没有就绪任务时休眠
+    pub fn run(&mut self) -> ! {
+        loop {
+            self.run_until_idle();
+            if STATS_REQUESTED.swap(false, Ordering::Relaxed) {
+                self.print_stats();
+            }
+            self.sleep_if_idle();
+        }
+    }
+
+    /// 轮询就绪的任务，直到没有任务就绪
+    pub fn run_until_idle(&mut self) {
+        // 解构 self，避免闭包借用整个执行器
+        let Self {
+            tasks,
+            task_queue,
+            waker_cache,
+            spawned,
+        } = self;
+
+        loop {
+            // 接收生成器添加的任务，它们在轮询过程中随时可能到达
+            while let Some(task) = spawned.pop() {
+                accept(tasks, task_queue, task);
+            }
+            let Some(task_id) = task_queue.pop() else {
+                break;
+            };
+            let task = match tasks.get_mut(&task_id) {
+                Some(task) => task,
+                // 任务已经完成
+                None => continue,
+            };
+            let priority = task.priority;
+            let waker = waker_cache
+                .entry(task_id)
+                .or_insert_with(|| TaskWaker::new_waker(task_id, priority, task_queue.clone()));
+            let mut context = Context::from_waker(waker);
+            match task.poll(&mut context) {
+                Poll::Ready(()) => {
+                    tasks.remove(&task_id);
+                    waker_cache.remove(&task_id);
+                }
+                Poll::Pending => {}
+            }
+        }
+    }
+
+    /// 未完成的任务数
+    pub fn task_count(&self) -> usize {
+        self.tasks.len()
+    }
+
+    /// 就绪队列中的任务数，被多次唤醒的任务可能重复计数
+    pub fn ready_queue_depth(&self) -> usize {
+        self.task_queue.len()
+    }
+
+    /// 所有未完成任务的运行统计，按任务编号排序
+    pub fn stats(&self) -> Vec<(TaskId, Priority, TaskStats)> {
+        self.tasks
+            .iter()
+            .map(|(&id, task)| (id, task.priority, task.stats))
+            .collect()
+    }
+
+    /// 打印所有未完成任务的运行统计，用于找出长时间占用执行器的任务
+    pub fn print_stats(&self) {
+        println!(
+            "{} tasks, {} ready",
+            self.task_count(),
+            self.ready_queue_depth()
+        );
+        println!(
+            "{:>6} {:<18} {:>10} {:>14}",
+            "ID", "PRIORITY", "POLLS", "CYCLES"
+        );
+        for (id, priority, stats) in self.stats() {
+            println!(
+                "{:>6} {:<18} {:>10} {:>14}",
+                id.as_u64(),
+                priority.name(),
+                stats.polls,
+                stats.cycles
+            );
+        }
+    }
+
+    /// 没有就绪任务时执行 `hlt`
+    fn sleep_if_idle(&self) {
+        use x86_64::instructions::interrupts;
+
+        // 检查队列和执行 hlt 之间到达的中断会被错过，因此先禁用中断，
+        // 再用 `sti; hlt` 原子地开启中断并休眠
+        interrupts::disable();
+        if self.task_queue.is_empty() && self.spawned.is_empty() {
+            interrupts::enable_and_hlt();
+        } else {
+            interrupts::enable();
+        }
+    }
+}
+
+/// 登记一个新任务并将其放入就绪队列
+///
+/// # 参数
+///
+/// - `tasks`: 所有未完成的任务
+/// - `task_queue`: 就绪队列
+/// - `task`: 新任务
+fn accept(tasks: &mut BTreeMap<TaskId, Task>, task_queue: &ReadyQueues, task: Task) {
+    let task_id = task.id;
+    let priority = task.priority;
+    if tasks.insert(task_id, task).is_some() {
+        panic!("task with same ID already in tasks");
+    }
+    task_queue.push(priority, task_id);
+}
+
+impl Default for Executor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// 任务生成器，可以克隆并在其他任务或中断处理函数中使用
+#[derive(Clone)]
+pub struct Spawner {
+    spawned: Arc<ArrayQueue<Task>>,
+}
+
+impl Spawner {
+    /// 添加一个任务，执行器会在下一轮调度时接收它
+    ///
+    /// # 参数
+    ///
+    /// - `task`: 要运行的任务
+    pub fn spawn(&self, task: Task) {
+        if self.spawned.push(task).is_err() {
+            panic!("spawn queue full");
+        }
+    }
+}
+
+/// 任务的唤醒器，唤醒时将任务编号放入其优先级对应的就绪队列
+struct TaskWaker {
+    task_id: TaskId,
+    priority: Priority,
+    task_queue: Arc<ReadyQueues>,
+}
+
+impl TaskWaker {
+    /// 创建任务的唤醒器
+    ///
+    /// # 参数
+    ///
+    /// - `task_id`: 任务编号
+    /// - `priority`: 任务的优先级
+    /// - `task_queue`: 就绪队列
+    fn new_waker(task_id: TaskId, priority: Priority, task_queue: Arc<ReadyQueues>) -> Waker {
+        Waker::from(Arc::new(Self {
+            task_id,
+            priority,
+            task_queue,
+        }))
+    }
+
+    fn wake_task(&self) {
+        self.task_queue.push(self.priority, self.task_id);
+    }
+}
+
+impl Wake for TaskWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_task();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.wake_task();
+    }
+}
@@ -0,0 +1,64 @@
+//! 本模块实现了一个简单的先进先出执行器
+//!
+//! 执行器依次轮询队列中的任务，未完成的任务重新放回队尾，直到所有任务都完成
+
+use alloc::collections::VecDeque;
+use core::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+
+use super::Task;
+
+/// 先进先出的执行器
+pub struct SimpleExecutor {
+    task_queue: VecDeque<Task>,
+}
+
+impl SimpleExecutor {
+    /// 创建一个空的执行器
+    pub fn new() -> Self {
+        Self {
+            task_queue: VecDeque::new(),
+        }
+    }
+
+    /// 添加一个任务
+    ///
+    /// # 参数
+    ///
+    /// - `task`: 要运行的任务
+    pub fn spawn(&mut self, task: Task) {
+        self.task_queue.push_back(task);
+    }
+
+    /// 运行所有任务，直到它们全部完成
+    pub fn run(&mut self) {
+        while let Some(mut task) = self.task_queue.pop_front() {
+            let waker = dummy_waker();
+            let mut context = Context::from_waker(&waker);
+            match task.poll(&mut context) {
+                Poll::Ready(()) => {}
+                Poll::Pending => self.task_queue.push_back(task),
+            }
+        }
+    }
+}
+
+impl Default for SimpleExecutor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// 创建一个什么也不做的唤醒器，执行器总是会再次轮询所有未完成的任务
+fn dummy_raw_waker() -> RawWaker {
+    fn no_op(_: *const ()) {}
+    fn clone(_: *const ()) -> RawWaker {
+        dummy_raw_waker()
+    }
+
+    static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, no_op, no_op, no_op);
+    RawWaker::new(core::ptr::null(), &VTABLE)
+}
+
+fn dummy_waker() -> Waker {
+    unsafe { Waker::from_raw(dummy_raw_waker()) }
+}
@@ -0,0 +1,682 @@
+//! 本模块实现了内核线程
+//!
+//! 每个线程拥有独立的内核栈，线程可以通过 [`yield_now`] 主动让出 CPU，
+//! 也会在时间片用完时被定时器中断抢占，或者挂起到 [`WaitQueue`] 上等待唤醒。
+//!
+//! 调度使用多级反馈队列：新线程从最高优先级开始，用完整个时间片的线程降低一级，
+//! 优先级越低时间片越长；经常挂起的交互式线程因此保持高优先级，计算密集的线程逐渐下沉。
+//! 同一级内按先进先出的顺序轮流运行，更高优先级的线程就绪时立即抢占当前线程，
+//! 并且每隔 [`BOOST_INTERVAL_TICKS`] 个 tick 把所有线程提升回最高优先级，避免低优先级线程饿死。
+//! 启动流程所在的执行流被视为第一个线程，在第一次切换时登记；
+//! 没有其他线程可以运行时切换到空闲线程。
+//!
+//! 每个处理器有自己的调度器和就绪队列，切换只需要获取本处理器的锁。
+//! 新线程放入创建者所在的处理器，被唤醒的线程回到最近一次运行的处理器，
+//! 空闲的处理器从其他处理器的就绪队列中窃取线程。[`spawn_on`] 和 [`set_affinity`]
+//! 可以为线程指定倾向运行的处理器
+
+mod block_on;
+mod context;
+mod stack;
+mod stats;
+mod wait_queue;
+
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use spin::Mutex;
+use x86_64::VirtAddr;
+use x86_64::structures::paging::PhysFrame;
+
+use crate::arch::tsc;
+use crate::process::Pid;
+use crate::{memory, percpu, time};
+use stack::Stack;
+use stats::Usage;
+
+pub use block_on::{block_on, sleep};
+pub use stats::{Stats, ThreadStats};
+pub use wait_queue::WaitQueue;
+pub(crate) use wait_queue::interrupt;
+
+/// 优先级的级数，0 级最高
+pub const PRIORITY_LEVELS: usize = 3;
+
+/// 各级线程连续运行的最大 tick 数，优先级越低时间片越长
+const TIME_SLICE_TICKS: [u64; PRIORITY_LEVELS] = [1, 2, 4];
+
+/// 把所有线程提升回最高优先级的间隔 tick 数
+pub const BOOST_INTERVAL_TICKS: u64 = 20;
+
+/// 线程编号
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ThreadId(u64);
+
+impl ThreadId {
+    /// 分配一个新的线程编号，0 号保留给启动线程
+    fn new() -> Self {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+        Self(NEXT_ID.fetch_add(1, Ordering::Relaxed))
+    }
+
+    /// 编号的数值
+    pub fn as_u64(self) -> u64 {
+        self.0
+    }
+}
+
+/// 启动线程的编号
+const BOOTSTRAP_ID: ThreadId = ThreadId(0);
+
+/// 内核线程
+struct Thread {
+    id: ThreadId,
+    idle: bool,              // 是否为空闲线程，空闲线程不进入就绪队列
+    level: usize,            // 优先级，0 级最高
+    epoch: u64,              // 最近一次确定优先级时所在处理器的提升轮次
+    cpu: usize,              // 最近一次运行所在的处理器
+    affinity: Option<usize>, // 倾向运行的处理器
+    process: Option<Pid>,    // 所属的进程，内核线程为 `None`
+    page_table: PhysFrame,   // 线程运行时使用的 4 级页表
+    rsp: u64,                // 线程不在运行时保存的栈指针
+    stack: Option<Stack>,    // 线程的内核栈，启动线程使用引导栈
+    usage: Arc<Usage>,       // CPU 时间计数
+}
+
+impl Thread {
+    /// 创建一个从 `entry` 开始运行的线程
+    ///
+    /// # 参数
+    ///
+    /// - `entry`: 线程的入口函数
+    /// - `idle`: 是否为空闲线程
+    /// - `process`: 所属的进程和它的 4 级页表，内核线程为 `None`
+    fn new(entry: fn(), idle: bool, process: Option<(Pid, PhysFrame)>) -> Box<Self> {
+        let id = ThreadId::new();
+        let stack = Stack::allocate(id);
+        let rsp = context::init_stack(stack.top(), entry);
+        let pid = process.map(|(pid, _)| pid);
+        Box::new(Self {
+            id,
+            idle,
+            level: 0,
+            epoch: 0,
+            cpu: percpu::current().id(),
+            affinity: None,
+            process: pid,
+            page_table: process.map_or_else(memory::kernel_page_table, |(_, table)| table),
+            rsp,
+            stack: Some(stack),
+            usage: Usage::register(id, pid, idle),
+        })
+    }
+
+    /// 线程从用户态进入内核时使用的栈顶
+    fn kernel_stack_top(&self) -> VirtAddr {
+        self.stack
+            .as_ref()
+            .map_or_else(crate::gdt::default_kernel_stack, |stack| {
+                VirtAddr::new(stack.top())
+            })
+    }
+
+    /// 为已经在运行的执行流创建线程记录，使用执行流自己的栈
+    ///
+    /// # 参数
+    ///
+    /// - `id`: 线程编号
+    /// - `idle`: 是否为空闲线程
+    fn adopt(id: ThreadId, idle: bool) -> Box<Self> {
+        Box::new(Self {
+            id,
+            idle,
+            level: 0,
+            epoch: 0,
+            cpu: percpu::current().id(),
+            affinity: None,
+            process: None,
+            page_table: memory::kernel_page_table(),
+            rsp: 0,
+            stack: None,
+            usage: Usage::register(id, None, idle),
+        })
+    }
+
+    /// 为启动流程所在的执行流创建线程记录
+    fn bootstrap() -> Box<Self> {
+        Self::adopt(BOOTSTRAP_ID, false)
+    }
+
+    /// 线程变为可运行时应放入的就绪队列所属的处理器
+    ///
+    /// 优先使用亲和性提示，提示的处理器不在线时放回最近一次运行的处理器
+    fn home(&self) -> usize {
+        self.affinity
+            .filter(|&cpu| SCHEDULERS[cpu].lock().online)
+            .unwrap_or(self.cpu)
+    }
+}
+
+/// 切换线程的原因
+#[derive(Clone, Copy)]
+enum Reason<'a> {
+    Yield,                // 当前线程仍可运行，放回同一级就绪队列的末尾
+    Preempt,              // 当前线程用完了时间片，降低一级后放回就绪队列
+    Block(&'a WaitQueue), // 当前线程挂起到等待队列上
+    Exit,                 // 当前线程已结束
+}
+
+/// 换下的线程在切换完成之后的去向
+///
+/// 切换完成之前线程的栈仍在使用，不能被其他处理器取走，因此由换上的线程放置它
+enum Departure {
+    Ready,                   // 放回就绪队列
+    Block(*const WaitQueue), // 挂起到等待队列上
+    Exit,                    // 释放线程记录和栈
+}
+
+// 等待队列本身可以在线程之间共享，指针只在换下的线程所在的处理器上使用
+unsafe impl Send for Departure {}
+
+/// 一个处理器的调度器状态
+///
+/// 线程记录都装在 `Box` 中，切换时保存栈指针的地址在线程移动到其他队列后依然有效
+#[allow(clippy::vec_box)]
+struct Scheduler {
+    online: bool,                                    // 处理器是否已经开始调度
+    current: Option<Box<Thread>>, // 正在运行的线程，启动线程在第一次切换前为 `None`
+    ready: [VecDeque<Box<Thread>>; PRIORITY_LEVELS], // 每一级可以运行的线程
+    idle: Option<Box<Thread>>,    // 不在运行的空闲线程
+    departed: Option<(Box<Thread>, Departure)>, // 刚被换下、尚未放置的线程
+    slice_left: u64,              // 当前线程剩余的时间片
+    boost_left: u64,              // 距离下一次提升优先级的 tick 数
+    epoch: u64,                   // 已经进行的提升轮次
+    started_at: u64,              // 调度器初始化时的 TSC
+    switched_at: u64,             // 上一次切换线程时的 TSC
+    switches: u64,                // 上下文切换的次数
+}
+
+impl Scheduler {
+    /// 创建尚未上线的调度器
+    const fn new() -> Self {
+        Self {
+            online: false,
+            current: None,
+            ready: [const { VecDeque::new() }; PRIORITY_LEVELS],
+            idle: None,
+            departed: None,
+            slice_left: TIME_SLICE_TICKS[0],
+            boost_left: BOOST_INTERVAL_TICKS,
+            epoch: 0,
+            started_at: 0,
+            switched_at: 0,
+            switches: 0,
+        }
+    }
+
+    /// 开始在这个处理器上调度
+    fn start(&mut self) {
+        self.online = true;
+        self.started_at = tsc::read();
+        self.switched_at = self.started_at;
+    }
+
+    /// 把线程放入它所在优先级的就绪队列
+    ///
+    /// 挂起期间错过了提升的线程或从其他处理器迁移来的线程先回到最高优先级
+    fn enqueue(&mut self, mut thread: Box<Thread>) {
+        if thread.epoch != self.epoch {
+            thread.level = 0;
+            thread.epoch = self.epoch;
+        }
+        self.ready[thread.level].push_back(thread);
+    }
+
+    /// 有就绪线程的最高优先级
+    fn highest_ready(&self) -> Option<usize> {
+        self.ready.iter().position(|queue| !queue.is_empty())
+    }
+
+    /// 当前线程的优先级，空闲线程低于所有优先级
+    fn current_level(&self) -> usize {
+        match &self.current {
+            Some(thread) if thread.idle => PRIORITY_LEVELS,
+            Some(thread) => thread.level,
+            None => 0,
+        }
+    }
+
+    /// 把上一次切换以来的周期数记到即将换下的线程上
+    ///
+    /// # 参数
+    ///
+    /// - `thread`: 即将换下的线程
+    fn account(&mut self, thread: &Thread) {
+        let now = tsc::read();
+        let elapsed = now.wrapping_sub(self.switched_at);
+        self.switched_at = now;
+        thread.usage.add(thread.level, elapsed);
+    }
+
+    /// 把就绪队列中的线程和当前线程都提升到最高优先级
+    fn boost(&mut self) {
+        self.epoch += 1;
+        let epoch = self.epoch;
+        for level in 1..PRIORITY_LEVELS {
+            let threads = core::mem::take(&mut self.ready[level]);
+            self.ready[0].extend(threads);
+        }
+        for thread in self.ready[0].iter_mut().chain(self.current.as_mut()) {
+            thread.level = 0;
+            thread.epoch = epoch;
+        }
+    }
+
+    /// 取出一个可以迁移到 `thief` 的就绪线程
+    ///
+    /// 从最高优先级开始，取最晚入队的线程，它的缓存最可能已经冷了；
+    /// 亲和性提示为本处理器的线程留在原地
+    ///
+    /// # 参数
+    ///
+    /// - `victim`: 本处理器的编号
+    fn steal(&mut self, victim: usize) -> Option<Box<Thread>> {
+        for queue in &mut self.ready {
+            if let Some(index) = queue
+                .iter()
+                .rposition(|thread| thread.affinity != Some(victim))
+            {
+                return queue.remove(index);
+            }
+        }
+        None
+    }
+}
+
+/// 各处理器的调度器，下标为处理器编号
+///
+/// 每个处理器大部分时候只访问自己的调度器，只有唤醒其他处理器上的线程和窃取线程时才访问别人的
+static SCHEDULERS: [Mutex<Scheduler>; percpu::MAX_CPUS] =
+    [const { Mutex::new(Scheduler::new()) }; percpu::MAX_CPUS];
+
+/// 当前处理器的调度器，调用时需要禁用中断
+fn local() -> &'static Mutex<Scheduler> {
+    &SCHEDULERS[percpu::current().id()]
+}
+
+/// 创建空闲线程，需要在堆初始化并调用 [`memory::install`](crate::memory::install) 之后调用
+pub fn init() {
+    use x86_64::instructions::interrupts;
+
+    let idle = Thread::new(idle_loop, true, None);
+    interrupts::without_interrupts(|| {
+        let mut scheduler = local().lock();
+        scheduler.idle = Some(idle);
+        scheduler.start();
+    });
+}
+
+/// 在应用处理器上开始调度，当前执行流成为这个处理器的空闲线程
+///
+/// 需要在处理器私有数据初始化之后调用
+pub(crate) fn run_ap() -> ! {
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+        let idle = Thread::adopt(ThreadId::new(), true);
+        percpu::current().set_current_thread(idle.id.as_u64());
+        let mut scheduler = local().lock();
+        scheduler.current = Some(idle);
+        scheduler.start();
+    });
+    interrupts::enable();
+    idle_loop();
+    unreachable!("idle loop returned");
+}
+
+/// 空闲线程，先尝试从其他处理器窃取就绪线程，没有时等待中断
+///
+/// 从未收到过定时器中断的处理器不能指望被中断唤醒，只能自旋等待
+fn idle_loop() {
+    use x86_64::instructions::{hlt, interrupts};
+
+    loop {
+        if interrupts::without_interrupts(steal) {
+            yield_now();
+            continue;
+        }
+        if percpu::current().ticks() == 0 {
+            core::hint::spin_loop();
+        } else {
+            hlt();
+        }
+    }
+}
+
+/// 从其他处理器的就绪队列中取一个线程放入当前处理器的就绪队列，调用时需要禁用中断
+///
+/// 同一时刻只持有一个调度器的锁，两个处理器互相窃取时不会死锁
+///
+/// # 返回
+///
+/// 是否取到了线程
+fn steal() -> bool {
+    let this = percpu::current().id();
+    if local().lock().highest_ready().is_some() {
+        return true;
+    }
+    for (victim, scheduler) in SCHEDULERS.iter().enumerate() {
+        if victim == this {
+            continue;
+        }
+        // 对方正忙时跳过它，稍后再试
+        let Some(thread) = scheduler.try_lock().and_then(|mut s| s.steal(victim)) else {
+            continue;
+        };
+        local().lock().enqueue(thread);
+        return true;
+    }
+    false
+}
+
+/// 把可运行的线程放入它所属处理器的就绪队列
+///
+/// # 参数
+///
+/// - `thread`: 可运行的线程
+fn make_runnable(thread: Box<Thread>) {
+    SCHEDULERS[thread.home()].lock().enqueue(thread);
+}
+
+/// 创建一个内核线程，它会在当前线程让出 CPU 或被抢占后开始运行
+///
+/// # 参数
+///
+/// - `entry`: 线程的入口函数，返回时线程退出
+pub fn spawn(entry: fn()) -> ThreadId {
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+        let thread = Thread::new(entry, false, None);
+        let id = thread.id;
+        make_runnable(thread);
+        id
+    })
+}
+
+/// 创建一个倾向于在 `cpu` 上运行的内核线程
+///
+/// 亲和性只是提示：线程变为可运行时优先放入该处理器的就绪队列，也不会被其他处理器窃取，
+/// 处理器不在线时则与普通线程相同
+///
+/// # 参数
+///
+/// - `entry`: 线程的入口函数，返回时线程退出
+/// - `cpu`: 处理器编号
+pub fn spawn_on(entry: fn(), cpu: usize) -> ThreadId {
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+        let mut thread = Thread::new(entry, false, None);
+        thread.affinity = Some(cpu).filter(|&cpu| cpu < percpu::MAX_CPUS);
+        let id = thread.id;
+        make_runnable(thread);
+        id
+    })
+}
+
+/// 设置当前线程的亲和性提示，在线程下一次变为可运行时生效
+///
+/// # 参数
+///
+/// - `cpu`: 倾向运行的处理器，`None` 表示没有偏好
+pub fn set_affinity(cpu: Option<usize>) {
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+        if let Some(current) = local().lock().current.as_mut() {
+            current.affinity = cpu.filter(|&cpu| cpu < percpu::MAX_CPUS);
+        }
+    })
+}
+
+/// 在进程中创建一个线程，它运行时使用进程的地址空间
+///
+/// # 参数
+///
+/// - `entry`: 线程的入口函数
+/// - `process`: 所属的进程
+/// - `page_table`: 进程的 4 级页表
+pub(crate) fn spawn_in_process(entry: fn(), process: Pid, page_table: PhysFrame) -> ThreadId {
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+        let thread = Thread::new(entry, false, Some((process, page_table)));
+        let id = thread.id;
+        make_runnable(thread);
+        id
+    })
+}
+
+/// 当前线程所属的进程，内核线程返回 `None`
+pub fn current_process() -> Option<Pid> {
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+        local()
+            .lock()
+            .current
+            .as_ref()
+            .and_then(|thread| thread.process)
+    })
+}
+
+/// 当前线程的编号
+pub fn current_id() -> ThreadId {
+    use x86_64::instructions::interrupts;
+
+    // 调度器切换时把换上的线程记在处理器私有数据中，读取时不需要获取调度器的锁
+    interrupts::without_interrupts(|| ThreadId(percpu::current().current_thread()))
+}
+
+/// 当前线程所在的处理器编号
+pub fn current_cpu() -> usize {
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| percpu::current().id())
+}
+
+/// 当前线程的优先级，0 级最高，空闲线程返回 [`PRIORITY_LEVELS`]
+pub fn current_priority() -> usize {
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| local().lock().current_level())
+}
+
+/// 获取调度器的统计信息，包括每个线程和空闲线程占用的 CPU 时间
+pub fn stats() -> Stats {
+    use x86_64::instructions::interrupts;
+
+    let mut threads = stats::snapshot();
+    let mut total_cycles = 0;
+    let mut switches = 0;
+    for scheduler in &SCHEDULERS {
+        interrupts::without_interrupts(|| {
+            let scheduler = scheduler.lock();
+            if !scheduler.online {
+                return;
+            }
+            let now = tsc::read();
+            total_cycles += now.wrapping_sub(scheduler.started_at);
+            switches += scheduler.switches;
+            // 正在运行的线程自上一次切换以来的运行时间尚未记入
+            if let Some(current) = &scheduler.current {
+                let running = now.wrapping_sub(scheduler.switched_at);
+                match threads.iter_mut().find(|t| t.id == current.id) {
+                    Some(usage) => usage.cycles += running,
+                    None => threads.push(ThreadStats {
+                        id: current.id,
+                        process: current.process,
+                        idle: current.idle,
+                        priority: current.level,
+                        cycles: running,
+                    }),
+                }
+            }
+        });
+    }
+    Stats {
+        uptime_ms: time::uptime_ms(),
+        total_cycles,
+        switches,
+        threads,
+    }
+}
+
+/// 让出 CPU，切换到同一级或更高优先级的下一个线程，没有这样的线程时立即返回
+///
+/// 让出不会降低优先级，但也不会重置时间片
+pub fn yield_now() {
+    switch(Reason::Yield);
+}
+
+/// 结束当前线程
+pub fn exit() -> ! {
+    wait_queue::forget(current_id());
+    switch(Reason::Exit);
+    unreachable!("exited thread was scheduled again");
+}
+
+/// 查找保护页包含 `addr` 的线程栈所属的线程，供缺页异常处理函数识别栈溢出
+///
+/// # 参数
+///
+/// - `addr`: 引发缺页的虚拟地址
+pub(crate) fn guard_page_owner(addr: VirtAddr) -> Option<ThreadId> {
+    stack::guard_page_owner(addr)
+}
+
+/// 由定时器中断调用，当前线程的时间片用完或有更高优先级的线程就绪时切换线程，
+/// 并定期提升所有线程的优先级
+///
+/// 必须在发送 EOI 之后调用，否则切换到的线程将收不到后续的定时器中断
+pub(crate) fn preempt() {
+    let reason = {
+        // 中断处理函数中中断已被禁用
+        let mut scheduler = local().lock();
+        scheduler.boost_left -= 1;
+        if scheduler.boost_left == 0 {
+            scheduler.boost();
+            scheduler.boost_left = BOOST_INTERVAL_TICKS;
+        }
+        scheduler.slice_left = scheduler.slice_left.saturating_sub(1);
+        if scheduler.slice_left == 0 {
+            Some(Reason::Preempt)
+        } else if scheduler
+            .highest_ready()
+            .is_some_and(|level| level < scheduler.current_level())
+        {
+            Some(Reason::Yield)
+        } else {
+            None
+        }
+    };
+    if let Some(reason) = reason {
+        switch(reason);
+    }
+}
+
+/// 切换到当前处理器上下一个可以运行的线程
+///
+/// # 参数
+///
+/// - `reason`: 切换的原因，决定当前线程之后的去向
+fn switch(reason: Reason) {
+    use x86_64::instructions::interrupts;
+    use x86_64::registers::control::{Cr3, Cr3Flags};
+
+    // 整个切换过程中禁用中断，切回来之后恢复本线程原来的中断状态
+    interrupts::without_interrupts(|| {
+        let (old_rsp, new_rsp) = {
+            let cpu = percpu::current();
+            let mut scheduler = SCHEDULERS[cpu.id()].lock();
+            if let Reason::Preempt = reason {
+                if let Some(current) = scheduler.current.as_mut().filter(|t| !t.idle) {
+                    current.level = (current.level + 1).min(PRIORITY_LEVELS - 1);
+                }
+                let level = scheduler.current_level().min(PRIORITY_LEVELS - 1);
+                scheduler.slice_left = TIME_SLICE_TICKS[level];
+            }
+            let runnable = matches!(reason, Reason::Yield | Reason::Preempt);
+            let next = match scheduler.highest_ready() {
+                // 仍可运行的线程只让给同一级或更高优先级的线程
+                Some(level) if runnable && level > scheduler.current_level() => return,
+                Some(level) => scheduler.ready[level].pop_front().unwrap(),
+                None if runnable => return,
+                None => scheduler.idle.take().expect("no runnable thread left"),
+            };
+            let mut current = scheduler.current.take().unwrap_or_else(Thread::bootstrap);
+            scheduler.account(&current);
+            scheduler.switches += 1;
+            let old_rsp: *mut u64 = &mut current.rsp;
+            current.cpu = cpu.id();
+            let departure = match reason {
+                _ if current.idle => {
+                    assert!(runnable, "idle thread must not block");
+                    Departure::Ready
+                }
+                Reason::Yield | Reason::Preempt => Departure::Ready,
+                Reason::Block(queue) => Departure::Block(queue),
+                Reason::Exit => Departure::Exit,
+            };
+            scheduler.departed = Some((current, departure));
+            let new_rsp = next.rsp;
+            crate::gdt::set_kernel_stack(next.kernel_stack_top());
+            if Cr3::read().0 != next.page_table {
+                // 内核映射在所有地址空间中相同，切换页表后仍可以继续在当前栈上运行
+                unsafe { Cr3::write(next.page_table, Cr3Flags::empty()) };
+            }
+            scheduler.slice_left = TIME_SLICE_TICKS[next.level];
+            cpu.set_current_thread(next.id.as_u64());
+            scheduler.current = Some(next);
+            (old_rsp, new_rsp)
+        };
+        unsafe { context::switch_context(old_rsp, new_rsp) };
+        finish_switch();
+    });
+}
+
+/// 放置刚被换下的线程，由换上的线程在切换完成后调用，调用时需要禁用中断
+///
+/// 此时换下线程的栈已经不再使用，可以被其他处理器取走或释放
+fn finish_switch() {
+    let Some((thread, departure)) = local().lock().departed.take() else {
+        return;
+    };
+    match departure {
+        _ if thread.idle => local().lock().idle = Some(thread),
+        Departure::Ready => make_runnable(thread),
+        // 挂起的线程在被唤醒之前不会返回，它引用的等待队列仍然有效
+        Departure::Block(queue) => unsafe { &*queue }.park(thread),
+        Departure::Exit => {
+            thread.usage.unregister();
+            drop(thread);
+        }
+    }
+}
+
+/// 新线程的入口，由上下文切换的跳板调用
+///
+/// # 参数
+///
+/// - `entry`: 线程入口函数的地址，由 [`spawn`] 写入初始栈帧
+extern "C" fn thread_start(entry: *const ()) -> ! {
+    let entry: fn() = unsafe { core::mem::transmute(entry) };
+    finish_switch();
+    // 切换时禁用了中断，新线程需要自己开启
+    x86_64::instructions::interrupts::enable();
+    entry();
+    exit()
+}
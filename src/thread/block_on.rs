@@ -0,0 +1,68 @@
+//! 本模块实现了在内核线程上同步等待 future
+//!
+//! future 未就绪时线程挂起到自己的等待队列上，由 future 的唤醒器放回就绪队列，
+//! 这样线程也能复用时间轮等为异步任务准备的设施
+
+use alloc::sync::Arc;
+use alloc::task::Wake;
+use core::future::Future;
+use core::pin::pin;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::task::{Context, Poll, Waker};
+use core::time::Duration;
+
+use super::WaitQueue;
+
+/// 唤醒挂起线程的唤醒器
+struct ThreadWaker {
+    queue: WaitQueue,  // 挂起的线程
+    woken: AtomicBool, // 线程挂起之前是否已被唤醒
+}
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.woken.store(true, Ordering::Release);
+        self.queue.wake_one();
+    }
+}
+
+/// 在当前线程上运行 future 直到完成，未就绪时挂起当前线程
+///
+/// # 参数
+///
+/// - `future`: 要等待的 future
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    use x86_64::instructions::interrupts;
+
+    let mut future = pin!(future);
+    let thread_waker = Arc::new(ThreadWaker {
+        queue: WaitQueue::new(),
+        woken: AtomicBool::new(false),
+    });
+    let waker = Waker::from(thread_waker.clone());
+    let mut cx = Context::from_waker(&waker);
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+            return output;
+        }
+        // 检查标志和挂起之间禁用中断，唤醒不会丢失
+        interrupts::without_interrupts(|| {
+            if !thread_waker.woken.swap(false, Ordering::Acquire) {
+                thread_waker.queue.block_current();
+            }
+        });
+    }
+}
+
+/// 让当前线程睡眠一段时间，精度为一个 tick
+///
+/// # 参数
+///
+/// - `duration`: 睡眠的时长
+pub fn sleep(duration: Duration) {
+    block_on(crate::time::sleep(duration));
+}
@@ -0,0 +1,78 @@
+//! 本模块实现了内核线程的上下文切换
+//!
+//! 切换时只需保存 System V 调用约定中由被调用者保存的寄存器，其余寄存器已由调用者保存
+
+use core::arch::naked_asm;
+
+/// 新线程初始栈上保存的寄存器数，依次为 r15、r14、r13、r12、rbp、rbx
+const SAVED_REGISTERS: usize = 6;
+/// 初始栈帧中 r12 的位置，线程入口函数通过它传给跳板
+const R12_SLOT: usize = 3;
+
+/// 保存当前线程的寄存器和栈指针，切换到另一个线程的栈
+///
+/// # 参数
+///
+/// - `old_rsp`: 保存当前线程栈指针的位置
+/// - `new_rsp`: 要切换到的线程的栈指针
+///
+/// # Safety
+///
+/// `new_rsp` 必须是由 [`switch_context`] 保存或由 [`init_stack`] 构造的栈指针
+#[unsafe(naked)]
+pub(super) unsafe extern "C" fn switch_context(old_rsp: *mut u64, new_rsp: u64) {
+    naked_asm!(
+        "push rbx",
+        "push rbp",
+        "push r12",
+        "push r13",
+        "push r14",
+        "push r15",
+        "mov [rdi], rsp",
+        "mov rsp, rsi",
+        "pop r15",
+        "pop r14",
+        "pop r13",
+        "pop r12",
+        "pop rbp",
+        "pop rbx",
+        "ret",
+    );
+}
+
+/// 新线程第一次被切换到时从这里开始执行，把 r12 中的入口函数传给 `start`
+#[unsafe(naked)]
+unsafe extern "C" fn thread_trampoline() {
+    naked_asm!(
+        "mov rdi, r12",
+        "call {start}",
+        "ud2",
+        start = sym super::thread_start,
+    );
+}
+
+/// 在新线程的栈顶构造初始栈帧，使第一次切换到它时进入 `entry`
+///
+/// # 参数
+///
+/// - `top`: 线程栈的栈顶地址
+/// - `entry`: 线程的入口函数
+///
+/// # 返回
+///
+/// 线程的初始栈指针
+pub(super) fn init_stack(top: u64, entry: fn()) -> u64 {
+    // 栈顶按 16 字节对齐，跳板 `ret` 之后刚好满足调用约定
+    let top = top & !0xf;
+    let frame = (top as *mut u64).wrapping_sub(SAVED_REGISTERS + 1);
+    unsafe {
+        for i in 0..SAVED_REGISTERS {
+            frame.add(i).write(0);
+        }
+        frame.add(R12_SLOT).write(entry as *const () as u64);
+        frame
+            .add(SAVED_REGISTERS)
+            .write(thread_trampoline as *const () as u64);
+    }
+    frame as u64
+}
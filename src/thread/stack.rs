@@ -0,0 +1,113 @@
+//! 本模块实现了带保护页的内核线程栈
+//!
+//! 线程栈位于单独的虚拟地址区域，按固定大小的槽位分配，每个槽位最低的一页不映射，
+//! 作为保护页，栈溢出时会触发缺页异常而不是悄悄覆盖相邻的内存。
+//! 物理帧分配器无法回收帧，因此线程退出后槽位连同映射一起留给下一个线程复用
+
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use spin::Mutex;
+use x86_64::VirtAddr;
+use x86_64::structures::paging::{FrameAllocator, Page, PageTableFlags};
+
+use super::ThreadId;
+use crate::memory;
+
+/// 线程栈区域的起始虚拟地址
+const STACKS_START: u64 = 0x_5555_5555_0000;
+/// 页大小
+const PAGE_SIZE: u64 = 4096;
+/// 每个线程栈映射的页数
+const STACK_PAGES: u64 = 4;
+/// 每个槽位的大小，包括保护页
+const SLOT_SIZE: u64 = (STACK_PAGES + 1) * PAGE_SIZE;
+/// 最多同时存在的线程栈数
+const MAX_STACKS: usize = 256;
+
+/// 下一个从未使用过的槽位
+static NEXT_SLOT: AtomicU64 = AtomicU64::new(0);
+/// 已映射但空闲的槽位
+static FREE_SLOTS: Mutex<Vec<u64>> = Mutex::new(Vec::new());
+/// 每个槽位当前所属的线程编号
+static OWNERS: [AtomicU64; MAX_STACKS] = [const { AtomicU64::new(0) }; MAX_STACKS];
+
+/// 一个线程的内核栈，释放时槽位回到空闲列表
+pub(super) struct Stack {
+    slot: u64,
+}
+
+impl Stack {
+    /// 为线程分配一个栈
+    ///
+    /// # 参数
+    ///
+    /// - `owner`: 使用这个栈的线程
+    pub(super) fn allocate(owner: ThreadId) -> Self {
+        use x86_64::instructions::interrupts;
+
+        let slot = match interrupts::without_interrupts(|| FREE_SLOTS.lock().pop()) {
+            Some(slot) => slot,
+            None => {
+                let slot = NEXT_SLOT.fetch_add(1, Ordering::Relaxed);
+                assert!((slot as usize) < MAX_STACKS, "too many thread stacks");
+                map_slot(slot);
+                slot
+            }
+        };
+        OWNERS[slot as usize].store(owner.as_u64(), Ordering::Relaxed);
+        Self { slot }
+    }
+
+    /// 栈顶地址（最高地址的下一个字节）
+    pub(super) fn top(&self) -> u64 {
+        STACKS_START + (self.slot + 1) * SLOT_SIZE
+    }
+}
+
+impl Drop for Stack {
+    fn drop(&mut self) {
+        use x86_64::instructions::interrupts;
+
+        interrupts::without_interrupts(|| FREE_SLOTS.lock().push(self.slot));
+    }
+}
+
+/// 映射一个槽位中除保护页以外的页
+///
+/// # 参数
+///
+/// - `slot`: 槽位编号
+fn map_slot(slot: u64) {
+    let bottom = STACKS_START + slot * SLOT_SIZE + PAGE_SIZE;
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+    memory::with_kernel_memory(|mapper, frame_allocator| {
+        for i in 0..STACK_PAGES {
+            let page = Page::containing_address(VirtAddr::new(bottom + i * PAGE_SIZE));
+            let frame = frame_allocator
+                .allocate_frame()
+                .expect("out of frames for thread stack");
+            unsafe { memory::map_page(mapper, page, frame, flags, frame_allocator) }
+                .expect("failed to map thread stack");
+        }
+    })
+    .expect("kernel memory is not installed");
+}
+
+/// 查找保护页包含 `addr` 的栈所属的线程
+///
+/// # 参数
+///
+/// - `addr`: 引发缺页的虚拟地址
+///
+/// # 返回
+///
+/// `addr` 不在任何已分配栈的保护页上时返回 `None`
+pub(super) fn guard_page_owner(addr: VirtAddr) -> Option<ThreadId> {
+    let offset = addr.as_u64().checked_sub(STACKS_START)?;
+    let slot = offset / SLOT_SIZE;
+    if slot >= NEXT_SLOT.load(Ordering::Relaxed) || offset % SLOT_SIZE >= PAGE_SIZE {
+        return None;
+    }
+    Some(ThreadId(OWNERS[slot as usize].load(Ordering::Relaxed)))
+}
@@ -0,0 +1,189 @@
+//! 本模块定义了线程的 CPU 时间统计
+//!
+//! 调度器在每次切换线程时读取 TSC，把距离上一次切换的周期数记到换下的线程上，
+//! 空闲线程的周期数即 CPU 的空闲时间。
+//!
+//! 线程可能在处理器之间迁移，计数放在线程记录和统计表共享的 [`Usage`] 中，
+//! 切换时只更新线程自己的计数，不需要获取全局的锁
+
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::fmt;
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+use spin::Mutex;
+
+use super::ThreadId;
+use crate::process::Pid;
+
+/// 尚未退出的线程的计数
+static USAGE: Mutex<BTreeMap<ThreadId, Arc<Usage>>> = Mutex::new(BTreeMap::new());
+
+/// 一个线程的 CPU 时间计数，由线程记录和统计表共享
+pub(super) struct Usage {
+    id: ThreadId,
+    process: Option<Pid>,
+    idle: bool,
+    priority: AtomicUsize, // 最近一次被换下时的优先级
+    cycles: AtomicU64,     // 累计运行的 TSC 周期数
+}
+
+impl Usage {
+    /// 为新线程创建计数并登记到统计表中
+    ///
+    /// # 参数
+    ///
+    /// - `id`: 线程编号
+    /// - `process`: 所属的进程，内核线程为 `None`
+    /// - `idle`: 是否为空闲线程
+    pub(super) fn register(id: ThreadId, process: Option<Pid>, idle: bool) -> Arc<Self> {
+        use x86_64::instructions::interrupts;
+
+        let usage = Arc::new(Self {
+            id,
+            process,
+            idle,
+            priority: AtomicUsize::new(0),
+            cycles: AtomicU64::new(0),
+        });
+        interrupts::without_interrupts(|| USAGE.lock().insert(id, usage.clone()));
+        usage
+    }
+
+    /// 从统计表中移除已退出的线程
+    pub(super) fn unregister(&self) {
+        use x86_64::instructions::interrupts;
+
+        interrupts::without_interrupts(|| USAGE.lock().remove(&self.id));
+    }
+
+    /// 把一段运行时间记到线程上
+    ///
+    /// # 参数
+    ///
+    /// - `priority`: 线程当前的优先级
+    /// - `cycles`: 运行的周期数
+    pub(super) fn add(&self, priority: usize, cycles: u64) {
+        self.priority.store(priority, Ordering::Relaxed);
+        self.cycles.fetch_add(cycles, Ordering::Relaxed);
+    }
+
+    /// 当前计数的快照
+    fn snapshot(&self) -> ThreadStats {
+        ThreadStats {
+            id: self.id,
+            process: self.process,
+            idle: self.idle,
+            priority: self.priority.load(Ordering::Relaxed),
+            cycles: self.cycles.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// 所有尚未退出的线程的统计快照
+pub(super) fn snapshot() -> Vec<ThreadStats> {
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| USAGE.lock().values().map(|u| u.snapshot()).collect())
+}
+
+/// 一个线程的 CPU 时间统计
+#[derive(Debug, Clone)]
+pub struct ThreadStats {
+    pub id: ThreadId,
+    pub process: Option<Pid>, // 所属的进程，内核线程为 `None`
+    pub idle: bool,           // 是否为空闲线程
+    pub priority: usize,      // 最近一次被换下时的优先级
+    pub cycles: u64,          // 累计运行的 TSC 周期数
+}
+
+/// 调度器的统计信息，由 [`stats`](super::stats) 获取
+///
+/// 以 `Display` 格式输出时是一张类似 `top` 的表格，按 CPU 占用从高到低排列
+#[derive(Debug, Clone)]
+pub struct Stats {
+    pub uptime_ms: u64,            // 运行时间
+    pub total_cycles: u64,         // 各处理器的调度器初始化以来的 TSC 周期数之和
+    pub switches: u64,             // 上下文切换的次数
+    pub threads: Vec<ThreadStats>, // 尚未退出的线程，包括空闲线程
+}
+
+impl Stats {
+    /// 空闲线程运行的周期数
+    pub fn idle_cycles(&self) -> u64 {
+        self.threads
+            .iter()
+            .filter(|t| t.idle)
+            .map(|t| t.cycles)
+            .sum()
+    }
+
+    /// CPU 忙碌时间的百分比
+    pub fn busy_percent(&self) -> u64 {
+        100u64.saturating_sub(self.percent(self.idle_cycles()))
+    }
+
+    /// 周期数占总周期数的百分比
+    ///
+    /// # 参数
+    ///
+    /// - `cycles`: 周期数
+    pub fn percent(&self, cycles: u64) -> u64 {
+        if self.total_cycles == 0 {
+            return 0;
+        }
+        (u128::from(cycles) * 100 / u128::from(self.total_cycles)) as u64
+    }
+
+    /// 按运行时间的比例把周期数换算成毫秒
+    ///
+    /// # 参数
+    ///
+    /// - `cycles`: 周期数
+    pub fn cycles_to_ms(&self, cycles: u64) -> u64 {
+        if self.total_cycles == 0 {
+            return 0;
+        }
+        (u128::from(cycles) * u128::from(self.uptime_ms) / u128::from(self.total_cycles)) as u64
+    }
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "uptime {}.{:03}s, {} switches, cpu {}% busy",
+            self.uptime_ms / 1000,
+            self.uptime_ms % 1000,
+            self.switches,
+            self.busy_percent()
+        )?;
+        writeln!(
+            f,
+            "{:>5} {:>5} {:>4} {:>5} {:>9}",
+            "TID", "PID", "PRI", "CPU%", "TIME(ms)"
+        )?;
+        let mut threads: Vec<&ThreadStats> = self.threads.iter().collect();
+        threads.sort_by_key(|t| core::cmp::Reverse(t.cycles));
+        for thread in threads {
+            write!(f, "{:>5} ", thread.id.as_u64())?;
+            match thread.process {
+                Some(pid) => write!(f, "{:>5} ", pid.as_u64())?,
+                None => write!(f, "{:>5} ", "-")?,
+            }
+            if thread.idle {
+                write!(f, "{:>4} ", "idle")?;
+            } else {
+                write!(f, "{:>4} ", thread.priority)?;
+            }
+            writeln!(
+                f,
+                "{:>4}% {:>9}",
+                self.percent(thread.cycles),
+                self.cycles_to_ms(thread.cycles)
+            )?;
+        }
+        Ok(())
+    }
+}
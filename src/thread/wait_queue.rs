@@ -0,0 +1,197 @@
+//! 本模块实现了线程的等待队列
+//!
+//! 线程在条件不满足时挂起到等待队列上，不再被调度，直到中断处理函数或其他线程唤醒它。
+//!
+//! 其他处理器上的唤醒可能发生在线程检查条件之后、真正挂起之前，此时队列为空。
+//! 这样的唤醒会被记下来，随后挂起的线程立即重新变为可运行，因此挂起可能被虚假地唤醒，
+//! 调用者需要在唤醒后重新检查条件。
+//!
+//! 投递信号时可以用 [`interrupt`] 提前唤醒挂起的线程，它同样表现为一次虚假唤醒
+
+use alloc::boxed::Box;
+use alloc::collections::{BTreeMap, BTreeSet, VecDeque};
+
+use spin::Mutex;
+
+use super::{Reason, Thread, ThreadId};
+
+/// 挂起的线程所在的等待队列，加锁顺序总是先 `BLOCKED` 后 `WaitQueue::waiters`
+static BLOCKED: Mutex<Blocked> = Mutex::new(Blocked {
+    queues: BTreeMap::new(),
+    interrupted: BTreeSet::new(),
+});
+
+/// 挂起线程的登记表
+struct Blocked {
+    queues: BTreeMap<ThreadId, usize>, // 线程挂起所在的等待队列的地址
+    interrupted: BTreeSet<ThreadId>,   // 挂起之前被打断的线程，下次挂起时立即唤醒
+}
+
+/// 线程等待队列
+///
+/// 检查条件和挂起之间需要禁用中断，否则唤醒可能发生在挂起之前而丢失：
+///
+/// ```ignore
+/// interrupts::without_interrupts(|| {
+///     while !device_ready() {
+///         QUEUE.block_current();
+///     }
+/// });
+/// ```
+pub struct WaitQueue {
+    waiters: Mutex<Waiters>,
+}
+
+/// 等待队列的内容
+struct Waiters {
+    threads: VecDeque<Box<Thread>>, // 挂起的线程，先挂起的在前
+    missed: bool,                   // 是否有唤醒在队列为空时到达
+}
+
+impl WaitQueue {
+    /// 创建空的等待队列
+    pub const fn new() -> Self {
+        Self {
+            waiters: Mutex::new(Waiters {
+                threads: VecDeque::new(),
+                missed: false,
+            }),
+        }
+    }
+
+    /// 挂起当前线程，直到被 [`wake_one`](Self::wake_one) 或 [`wake_all`](Self::wake_all) 唤醒
+    pub fn block_current(&self) {
+        use x86_64::instructions::interrupts;
+
+        super::switch(Reason::Block(self));
+        // 线程返回之后队列的地址不再有效
+        let id = super::current_id();
+        interrupts::without_interrupts(|| BLOCKED.lock().queues.remove(&id));
+    }
+
+    /// 记录一个挂起的线程，由调度器在切换完成后调用
+    ///
+    /// 挂起之前已有唤醒到达时线程直接变为可运行
+    ///
+    /// # 参数
+    ///
+    /// - `thread`: 被挂起的线程
+    pub(super) fn park(&self, thread: Box<Thread>) {
+        let mut blocked = BLOCKED.lock();
+        if blocked.interrupted.remove(&thread.id) {
+            drop(blocked);
+            super::make_runnable(thread);
+            return;
+        }
+        let mut waiters = self.waiters.lock();
+        if core::mem::take(&mut waiters.missed) {
+            drop(waiters);
+            drop(blocked);
+            super::make_runnable(thread);
+        } else {
+            blocked
+                .queues
+                .insert(thread.id, self as *const Self as usize);
+            waiters.threads.push_back(thread);
+        }
+    }
+
+    /// 是否没有挂起的线程
+    pub fn is_empty(&self) -> bool {
+        use x86_64::instructions::interrupts;
+
+        interrupts::without_interrupts(|| self.waiters.lock().threads.is_empty())
+    }
+
+    /// 唤醒最早挂起的一个线程，没有挂起的线程时记下这次唤醒
+    ///
+    /// # 返回
+    ///
+    /// 是否有线程被唤醒
+    pub fn wake_one(&self) -> bool {
+        use x86_64::instructions::interrupts;
+
+        interrupts::without_interrupts(|| {
+            let thread = {
+                let mut waiters = self.waiters.lock();
+                let thread = waiters.threads.pop_front();
+                waiters.missed = thread.is_none();
+                thread
+            };
+            let Some(thread) = thread else {
+                return false;
+            };
+            super::make_runnable(thread);
+            true
+        })
+    }
+
+    /// 唤醒所有挂起的线程，没有挂起的线程时记下这次唤醒
+    ///
+    /// # 返回
+    ///
+    /// 被唤醒的线程数
+    pub fn wake_all(&self) -> usize {
+        use x86_64::instructions::interrupts;
+
+        interrupts::without_interrupts(|| {
+            let threads = {
+                let mut waiters = self.waiters.lock();
+                let threads = core::mem::take(&mut waiters.threads);
+                waiters.missed = threads.is_empty();
+                threads
+            };
+            let count = threads.len();
+            for thread in threads {
+                super::make_runnable(thread);
+            }
+            count
+        })
+    }
+}
+
+/// 唤醒挂起在任意等待队列上的线程 `id`，它还没有挂起时让它的下一次挂起立即返回
+///
+/// # 参数
+///
+/// - `id`: 要打断的线程
+pub(crate) fn interrupt(id: ThreadId) {
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+        let mut blocked = BLOCKED.lock();
+        let Some(&queue) = blocked.queues.get(&id) else {
+            blocked.interrupted.insert(id);
+            return;
+        };
+        // 登记的线程返回之前要先获取 `BLOCKED`，此时队列仍然有效
+        let queue = unsafe { &*(queue as *const WaitQueue) };
+        let thread = {
+            let mut waiters = queue.waiters.lock();
+            let index = waiters.threads.iter().position(|thread| thread.id == id);
+            index.and_then(|index| waiters.threads.remove(index))
+        };
+        drop(blocked);
+        // 找不到时线程已被唤醒，还没来得及注销
+        if let Some(thread) = thread {
+            super::make_runnable(thread);
+        }
+    });
+}
+
+/// 丢弃线程 `id` 未消耗的打断记录，由退出的线程调用
+///
+/// # 参数
+///
+/// - `id`: 退出的线程
+pub(super) fn forget(id: ThreadId) {
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| BLOCKED.lock().interrupted.remove(&id));
+}
+
+impl Default for WaitQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
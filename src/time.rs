@@ -0,0 +1,123 @@
+//! 本模块实现了基于定时器中断的内核时钟，以及由它驱动的异步睡眠、超时和延迟回调
+//!
+//! tick 的频率由 [`pit`] 在启动时设置。tick 适合粗粒度的睡眠和超时，需要纳秒精度的测量时使用基于 TSC 的 [`Instant`]，
+//! 墙上时钟由启动时读取的 RTC 加上 tick 推进得到，见 [`now_utc`]
+
+mod instant;
+pub mod pit;
+pub mod rtc;
+mod sleep;
+mod timer_wheel;
+
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::time::Duration;
+
+pub use instant::{Instant, tsc_frequency_hz};
+pub use rtc::{DateTime, now_utc, unix_time};
+pub use sleep::{Elapsed, Sleep, Timeout, sleep, timeout};
+pub use timer_wheel::{TimerHandle, call_after, wake_after};
+
+/// PIT 的输入时钟频率（Hz）
+pub(crate) const PIT_BASE_FREQUENCY: u64 = 1_193_182;
+
+/// 自启动以来的定时器中断次数
+static TICKS: AtomicU64 = AtomicU64::new(0);
+
+/// 自启动以来经过的 PIT 输入时钟周期数，tick 的频率可能改变，不能由 tick 数换算
+static CLOCKS: AtomicU64 = AtomicU64::new(0);
+
+/// 设置 tick 的频率、确定 TSC 的频率并读取 RTC，需要在开启中断之前调用
+pub fn init() {
+    pit::init();
+    instant::init();
+    rtc::init();
+}
+
+/// HPET 可用之后重新校准 TSC 的频率，频率由 CPUID 给出时不做任何事
+pub(crate) fn recalibrate_tsc() {
+    instant::recalibrate();
+}
+
+/// 忙等待 `ms` 毫秒，在计时开始前后分别调用 `start` 和 `stop`，用于校准频率未知的计数器
+///
+/// HPET 可用时用它计时，否则使用 PIT 的 2 号通道
+///
+/// # 参数
+///
+/// - `ms`: 计时的毫秒数，不能超过 [`pit::MAX_MS`]
+/// - `start`: 计时开始前调用
+/// - `stop`: 计时结束后立即调用
+///
+/// # 返回
+///
+/// `stop` 的返回值
+pub(crate) fn measure<T>(ms: u64, start: impl FnOnce(), stop: impl FnOnce() -> T) -> T {
+    if crate::hpet::is_initialized() {
+        // HPET 初始化之后不会失效
+        return crate::hpet::measure(Duration::from_millis(ms), start, stop).unwrap();
+    }
+    pit::measure(ms, start, stop)
+}
+
+/// 由定时器中断调用，将时钟前进一个 tick
+pub(crate) fn tick() {
+    CLOCKS.fetch_add(pit::divisor(), Ordering::Relaxed);
+    let now = TICKS.fetch_add(1, Ordering::Relaxed) + 1;
+    timer_wheel::advance(now);
+}
+
+/// 获取自启动以来的 tick 数
+pub fn ticks() -> u64 {
+    TICKS.load(Ordering::Relaxed)
+}
+
+/// 获取自启动以来经过的毫秒数
+pub fn uptime_ms() -> u64 {
+    CLOCKS.load(Ordering::Relaxed) * 1000 / PIT_BASE_FREQUENCY
+}
+
+/// 获取自启动以来经过的时长，精度为一个 tick
+pub fn uptime() -> Duration {
+    let nanos = u128::from(CLOCKS.load(Ordering::Relaxed)) * 1_000_000_000;
+    Duration::from_nanos((nanos / u128::from(PIT_BASE_FREQUENCY)) as u64)
+}
+
+/// 一个 tick 的时长
+pub fn tick_duration() -> Duration {
+    Duration::from_nanos(pit::divisor() * 1_000_000_000 / PIT_BASE_FREQUENCY)
+}
+
+/// 将时长换算为 tick 数，不足一个 tick 的部分向上取整
+///
+/// # 参数
+///
+/// - `duration`: 时长
+pub fn duration_to_ticks(duration: Duration) -> u64 {
+    ticks_for(duration, pit::divisor())
+}
+
+/// 按给定的分频系数将时长换算为 tick 数，不足一个 tick 的部分向上取整
+///
+/// # 参数
+///
+/// - `duration`: 时长
+/// - `divisor`: PIT 的分频系数
+fn ticks_for(duration: Duration, divisor: u64) -> u64 {
+    // 一个 tick 为 divisor / PIT_BASE_FREQUENCY 秒，用 u128 避免溢出
+    let scaled = duration.as_nanos() * u128::from(PIT_BASE_FREQUENCY);
+    scaled.div_ceil(u128::from(divisor) * 1_000_000_000) as u64
+}
+
+#[test_case]
+fn test_duration_to_ticks_rounds_up() {
+    assert_eq!(duration_to_ticks(Duration::ZERO), 0);
+    assert_eq!(duration_to_ticks(Duration::from_nanos(1)), 1);
+    assert_eq!(duration_to_ticks(tick_duration()), 1);
+    // 上电默认的频率约为 18.2 Hz
+    let power_on = u64::from(pit::POWER_ON_DIVISOR);
+    assert_eq!(ticks_for(Duration::from_secs(1), power_on), 19);
+    assert_eq!(ticks_for(Duration::from_secs(10), power_on), 183);
+    // 100 Hz 时分频系数为 11932
+    assert_eq!(ticks_for(Duration::from_secs(1), 11932), 100);
+    assert_eq!(ticks_for(Duration::from_millis(15), 11932), 2);
+}
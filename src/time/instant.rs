@@ -0,0 +1,221 @@
+//! 本模块实现了基于 TSC 的高精度单调时钟
+//!
+//! 启动时确定 TSC 的频率：处理器在 CPUID 0x15 叶中报告了晶振频率时直接换算，
+//! 否则用 PIT 2 号通道计时来测量，HPET 初始化之后再用它测量一次。[`Instant`] 记录自 TSC 复位以来的纳秒数，
+//! 各处理器的 TSC 在上电时同时复位，可以跨处理器比较
+//!
+//! 校准完成之前 [`Instant::now`] 退化为按 tick 计时
+
+use core::arch::x86_64::__cpuid;
+use core::ops::{Add, AddAssign, Sub, SubAssign};
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use core::time::Duration;
+
+use crate::arch::tsc;
+use crate::cpu::{self, Feature};
+
+/// TSC 与晶振频率之比所在的叶
+const LEAF_TSC: u32 = 0x15;
+
+/// 校准时计时的毫秒数
+const CALIBRATION_MS: u64 = 50;
+
+/// TSC 的频率（Hz），为 0 时尚未校准
+static TSC_HZ: AtomicU64 = AtomicU64::new(0);
+
+/// TSC 的频率是否由 CPUID 给出，此时不需要重新校准
+static FROM_CPUID: AtomicBool = AtomicBool::new(false);
+
+/// 确定 TSC 的频率，只进行一次
+pub(crate) fn init() {
+    if TSC_HZ.load(Ordering::Acquire) != 0 {
+        return;
+    }
+    let (hz, source) = match from_cpuid() {
+        Some(hz) => {
+            FROM_CPUID.store(true, Ordering::Relaxed);
+            (hz, "CPUID")
+        }
+        None => (calibrate(), "PIT"),
+    };
+    TSC_HZ.store(hz, Ordering::Release);
+    log::info!("TSC runs at {} kHz ({})", hz / 1000, source);
+    if !cpu::has(Feature::InvariantTsc) {
+        log::warn!("TSC is not invariant, Instant may drift with frequency changes");
+    }
+}
+
+/// 用 HPET 重新测量 TSC 的频率，频率由 CPUID 给出或尚未校准时不做任何事
+///
+/// 启动早期调用，之前记录的 [`Instant`] 按旧的频率换算，与之后的时刻相比可能有少量误差
+pub(crate) fn recalibrate() {
+    if FROM_CPUID.load(Ordering::Relaxed) || TSC_HZ.load(Ordering::Acquire) == 0 {
+        return;
+    }
+    let hz = calibrate();
+    TSC_HZ.store(hz, Ordering::Release);
+    log::info!("TSC runs at {} kHz (HPET)", hz / 1000);
+}
+
+/// TSC 的频率（Hz），尚未校准时返回 0
+pub fn tsc_frequency_hz() -> u64 {
+    TSC_HZ.load(Ordering::Acquire)
+}
+
+/// 从 CPUID 0x15 叶换算 TSC 的频率，处理器没有报告晶振频率时返回 `None`
+fn from_cpuid() -> Option<u64> {
+    if __cpuid(0).eax < LEAF_TSC {
+        return None;
+    }
+    let leaf = __cpuid(LEAF_TSC);
+    // eax 和 ebx 分别为 TSC 与晶振频率之比的分母和分子，ecx 为晶振频率
+    let (denominator, numerator, crystal) = (leaf.eax, leaf.ebx, leaf.ecx);
+    if denominator == 0 || numerator == 0 || crystal == 0 {
+        return None;
+    }
+    Some(u64::from(crystal) * u64::from(numerator) / u64::from(denominator))
+}
+
+/// 计时 [`CALIBRATION_MS`] 毫秒，统计 TSC 在这段时间内的增量，HPET 可用时用它计时，否则使用 PIT
+///
+/// # 返回
+///
+/// TSC 的频率（Hz）
+fn calibrate() -> u64 {
+    let mut start = 0;
+    let end = super::measure(CALIBRATION_MS, || start = tsc::read(), tsc::read);
+    (end.wrapping_sub(start) * 1000 / CALIBRATION_MS).max(1)
+}
+
+/// 单调时钟上的一个时刻，精度为纳秒
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Instant {
+    nanos: u64, // 自 TSC 复位以来的纳秒数
+}
+
+impl Instant {
+    /// 当前时刻
+    pub fn now() -> Self {
+        let hz = TSC_HZ.load(Ordering::Acquire);
+        let nanos = if hz == 0 {
+            super::uptime().as_nanos() as u64
+        } else {
+            (u128::from(tsc::read()) * 1_000_000_000 / u128::from(hz)) as u64
+        };
+        Self { nanos }
+    }
+
+    /// 从 `earlier` 到这一时刻经过的时长，`earlier` 更晚时返回零
+    ///
+    /// # 参数
+    ///
+    /// - `earlier`: 较早的时刻
+    pub fn duration_since(&self, earlier: Instant) -> Duration {
+        self.checked_duration_since(earlier).unwrap_or_default()
+    }
+
+    /// 从 `earlier` 到这一时刻经过的时长，`earlier` 更晚时返回 `None`
+    ///
+    /// # 参数
+    ///
+    /// - `earlier`: 较早的时刻
+    pub fn checked_duration_since(&self, earlier: Instant) -> Option<Duration> {
+        self.nanos
+            .checked_sub(earlier.nanos)
+            .map(Duration::from_nanos)
+    }
+
+    /// 从这一时刻到现在经过的时长
+    pub fn elapsed(&self) -> Duration {
+        Instant::now().duration_since(*self)
+    }
+
+    /// 这一时刻之后 `duration` 的时刻，溢出时返回 `None`
+    ///
+    /// # 参数
+    ///
+    /// - `duration`: 时长
+    pub fn checked_add(&self, duration: Duration) -> Option<Instant> {
+        let nanos = u64::try_from(duration.as_nanos()).ok()?;
+        Some(Self {
+            nanos: self.nanos.checked_add(nanos)?,
+        })
+    }
+
+    /// 这一时刻之前 `duration` 的时刻，早于 TSC 复位时返回 `None`
+    ///
+    /// # 参数
+    ///
+    /// - `duration`: 时长
+    pub fn checked_sub(&self, duration: Duration) -> Option<Instant> {
+        let nanos = u64::try_from(duration.as_nanos()).ok()?;
+        Some(Self {
+            nanos: self.nanos.checked_sub(nanos)?,
+        })
+    }
+
+    /// 自 TSC 复位以来的时长
+    pub fn since_reset(&self) -> Duration {
+        Duration::from_nanos(self.nanos)
+    }
+}
+
+impl Add<Duration> for Instant {
+    type Output = Instant;
+
+    fn add(self, duration: Duration) -> Instant {
+        self.checked_add(duration)
+            .expect("overflow when adding duration to instant")
+    }
+}
+
+impl AddAssign<Duration> for Instant {
+    fn add_assign(&mut self, duration: Duration) {
+        *self = *self + duration;
+    }
+}
+
+impl Sub<Duration> for Instant {
+    type Output = Instant;
+
+    fn sub(self, duration: Duration) -> Instant {
+        self.checked_sub(duration)
+            .expect("overflow when subtracting duration from instant")
+    }
+}
+
+impl SubAssign<Duration> for Instant {
+    fn sub_assign(&mut self, duration: Duration) {
+        *self = *self - duration;
+    }
+}
+
+impl Sub<Instant> for Instant {
+    type Output = Duration;
+
+    fn sub(self, earlier: Instant) -> Duration {
+        self.duration_since(earlier)
+    }
+}
+
+#[test_case]
+fn test_instant_is_monotonic() {
+    let first = Instant::now();
+    let second = Instant::now();
+    assert!(second >= first);
+    assert!(first.elapsed() >= second - first);
+}
+
+#[test_case]
+fn test_instant_arithmetic() {
+    let start = Instant::now();
+    let later = start + Duration::from_micros(1500);
+    assert_eq!(later - start, Duration::from_micros(1500));
+    assert_eq!(start - later, Duration::ZERO);
+    assert_eq!(start.checked_duration_since(later), None);
+    assert_eq!(later - Duration::from_micros(1500), start);
+    assert_eq!(
+        Instant { nanos: 1 }.checked_sub(Duration::from_nanos(2)),
+        None
+    );
+}
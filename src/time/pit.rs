@@ -0,0 +1,206 @@
+//! 本模块实现了可编程间隔定时器（PIT）的配置和用它进行的短时间测量
+//!
+//! 0 号通道产生 IRQ0，也就是内核的 tick。上电时它的分频系数为 65536，约 18.2 Hz，
+//! 启动时改为 [`DEFAULT_FREQUENCY_HZ`]；编译时设置环境变量 `RICKY_OS_TIMER_HZ` 可以选择其他频率。
+//!
+//! 2 号通道的门控和输出可以通过 0x61 端口直接读写，不需要中断，
+//! 适合在启动早期校准 TSC 和本地 APIC 定时器这类频率未知的计数器；
+//! 校准完成之后它的输出接到 PC 扬声器上发声，见 [`speaker`](crate::speaker)
+
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use crate::arch::port::Port;
+use crate::sync::SpinLock;
+
+use super::PIT_BASE_FREQUENCY;
+
+/// 默认的 tick 频率（Hz）
+pub const DEFAULT_FREQUENCY_HZ: u32 = 100;
+/// 可以设置的最低频率，分频系数不能超过 65536
+pub const MIN_FREQUENCY_HZ: u32 = 19;
+/// 可以设置的最高频率，更高的频率只会让中断处理占满 CPU
+pub const MAX_FREQUENCY_HZ: u32 = 10_000;
+
+/// 上电默认的分频系数（写入 0 表示 65536）
+pub(crate) const POWER_ON_DIVISOR: u32 = 65536;
+
+/// PIT 0 号通道的数据端口
+const PIT_CHANNEL_0: u16 = 0x40;
+/// PIT 2 号通道的数据端口
+const PIT_CHANNEL_2: u16 = 0x42;
+/// PIT 的模式/命令端口
+const PIT_COMMAND: u16 = 0x43;
+/// 控制 2 号通道门控和读取其输出的端口
+const PIT_GATE: u16 = 0x61;
+/// 门控端口：开启 2 号通道的门控
+const GATE_ENABLE: u8 = 1 << 0;
+/// 门控端口：把 2 号通道接到扬声器上
+const GATE_SPEAKER: u8 = 1 << 1;
+/// 门控端口：2 号通道的输出
+const GATE_OUTPUT: u8 = 1 << 5;
+
+/// 2 号通道的使用权，测量和发声互斥，发声不会改写测量中的计数或关闭它的门控
+static CHANNEL_2: SpinLock<()> = SpinLock::new(());
+
+/// 0 号通道当前的分频系数
+static DIVISOR: AtomicU64 = AtomicU64::new(POWER_ON_DIVISOR as u64);
+
+/// 按编译时的配置设置 tick 的频率
+pub(crate) fn init() {
+    let hz = option_env!("RICKY_OS_TIMER_HZ")
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(DEFAULT_FREQUENCY_HZ);
+    match set_frequency(hz) {
+        Some(actual) => log::info!("PIT ticks at {} Hz", actual),
+        None => {
+            log::warn!("timer frequency {} Hz out of range", hz);
+            set_frequency(DEFAULT_FREQUENCY_HZ);
+        }
+    }
+}
+
+/// 设置 0 号通道的中断频率，也就是 tick 的频率
+///
+/// 已经按 tick 数登记的睡眠和定时器不会随之调整，应在启动早期设置
+///
+/// # 参数
+///
+/// - `hz`: 目标频率，范围为 [`MIN_FREQUENCY_HZ`] 到 [`MAX_FREQUENCY_HZ`]
+///
+/// # 返回
+///
+/// 分频之后的实际频率（Hz），超出范围时返回 `None`
+pub fn set_frequency(hz: u32) -> Option<u32> {
+    use x86_64::instructions::interrupts;
+
+    if !(MIN_FREQUENCY_HZ..=MAX_FREQUENCY_HZ).contains(&hz) {
+        return None;
+    }
+    let divisor = divisor_for(hz);
+    let mut command = Port::<u8>::new(PIT_COMMAND);
+    let mut channel = Port::<u8>::new(PIT_CHANNEL_0);
+    // 两个字节之间不能被定时器中断打断
+    interrupts::without_interrupts(|| unsafe {
+        // 0 号通道，先低后高字节，模式 2：周期性的频率发生器；写入 0 表示 65536
+        command.write(0b0011_0100);
+        channel.write(divisor as u8);
+        channel.write((divisor >> 8) as u8);
+        DIVISOR.store(divisor, Ordering::Relaxed);
+    });
+    Some(frequency_hz())
+}
+
+/// 最接近 `hz` 的分频系数
+///
+/// # 参数
+///
+/// - `hz`: 目标频率
+fn divisor_for(hz: u32) -> u64 {
+    let hz = u64::from(hz);
+    ((PIT_BASE_FREQUENCY + hz / 2) / hz).clamp(1, u64::from(POWER_ON_DIVISOR))
+}
+
+/// tick 当前的频率（Hz），向下取整
+pub fn frequency_hz() -> u32 {
+    (PIT_BASE_FREQUENCY / divisor()) as u32
+}
+
+/// 0 号通道当前的分频系数
+pub(crate) fn divisor() -> u64 {
+    DIVISOR.load(Ordering::Relaxed)
+}
+
+/// 2 号通道能计时的最长毫秒数，计数值不能超过 16 位
+pub(crate) const MAX_MS: u64 = 0xffff * 1000 / PIT_BASE_FREQUENCY;
+
+/// 用 2 号通道忙等待 `ms` 毫秒，在计时开始前后分别调用 `start` 和 `stop`
+///
+/// 计时期间持有 2 号通道并禁用中断，扬声器在此期间不发声
+///
+/// # 参数
+///
+/// - `ms`: 计时的毫秒数，不能超过 [`MAX_MS`]
+/// - `start`: 计时开始前调用，例如记录计数器的起始值
+/// - `stop`: 计时结束后立即调用
+///
+/// # 返回
+///
+/// `stop` 的返回值
+pub(crate) fn measure<T>(ms: u64, start: impl FnOnce(), stop: impl FnOnce() -> T) -> T {
+    assert!(
+        ms > 0 && ms <= MAX_MS,
+        "PIT channel 2 cannot measure {} ms",
+        ms
+    );
+    let _channel = CHANNEL_2.lock();
+    let mut gate = Port::<u8>::new(PIT_GATE);
+    let mut command = Port::<u8>::new(PIT_COMMAND);
+    let mut channel = Port::<u8>::new(PIT_CHANNEL_2);
+    let count = PIT_BASE_FREQUENCY * ms / 1000;
+    unsafe {
+        // 关闭扬声器，先拉低门控，装入计数后再拉高才开始计数
+        let value = gate.read() & !(GATE_SPEAKER | GATE_ENABLE);
+        gate.write(value);
+        // 2 号通道，先低后高字节，模式 0：计数到 0 时输出变高
+        command.write(0b1011_0000);
+        channel.write(count as u8);
+        channel.write((count >> 8) as u8);
+        start();
+        gate.write(value | GATE_ENABLE);
+        while gate.read() & GATE_OUTPUT == 0 {
+            core::hint::spin_loop();
+        }
+        let result = stop();
+        gate.write(value);
+        result
+    }
+}
+
+/// 让 2 号通道输出方波并接通扬声器
+///
+/// 不等待 2 号通道的锁，可以在 panic 时调用
+///
+/// # 参数
+///
+/// - `hz`: 方波的频率，不为 0
+///
+/// # 返回
+///
+/// 分频之后的实际频率（Hz），2 号通道正被用于测量时返回 `None`
+pub(crate) fn start_tone(hz: u32) -> Option<u32> {
+    let _channel = CHANNEL_2.try_lock()?;
+    let divisor = divisor_for(hz);
+    let mut gate = Port::<u8>::new(PIT_GATE);
+    let mut command = Port::<u8>::new(PIT_COMMAND);
+    let mut channel = Port::<u8>::new(PIT_CHANNEL_2);
+    unsafe {
+        // 2 号通道，先低后高字节，模式 3：方波发生器；写入 0 表示 65536
+        command.write(0b1011_0110);
+        channel.write(divisor as u8);
+        channel.write((divisor >> 8) as u8);
+        let value = gate.read();
+        gate.write(value | GATE_SPEAKER | GATE_ENABLE);
+    }
+    Some((PIT_BASE_FREQUENCY / divisor) as u32)
+}
+
+/// 断开扬声器并停止 2 号通道，2 号通道正被用于测量时什么都不做
+pub(crate) fn stop_tone() {
+    // 测量开始时已经断开了扬声器，不能关闭它的门控
+    let Some(_channel) = CHANNEL_2.try_lock() else {
+        return;
+    };
+    let mut gate = Port::<u8>::new(PIT_GATE);
+    unsafe {
+        let value = gate.read();
+        gate.write(value & !(GATE_SPEAKER | GATE_ENABLE));
+    }
+}
+
+#[test_case]
+fn test_divisor_for_rounds_to_nearest() {
+    assert_eq!(divisor_for(100), 11932);
+    assert_eq!(divisor_for(1000), 1193);
+    assert_eq!(divisor_for(MIN_FREQUENCY_HZ), 62799);
+    assert!(divisor_for(MIN_FREQUENCY_HZ) <= u64::from(POWER_ON_DIVISOR));
+}
@@ -0,0 +1,312 @@
+//! 本模块实现了 CMOS 实时时钟（RTC）的读取和墙上时钟
+//!
+//! RTC 在断电后依靠电池继续走时，寄存器通过 0x70 端口选择、0x71 端口读写。
+//! 它每秒更新一次日期和时间，更新期间读到的值可能不一致，因此等到更新结束并连续两次读到相同的值为止。
+//! 寄存器的格式由状态寄存器 B 决定，可能是 BCD 码，小时也可能是 12 小时制。
+//!
+//! 年寄存器只有后两位，FADT 给出世纪寄存器时由它补全，否则认为是 21 世纪。
+//! 启动时读取一次 RTC 得到 Unix 时间，之后按 tick 数推进，不再访问硬件
+
+use core::fmt;
+use core::sync::atomic::{AtomicU8, AtomicU64, Ordering};
+
+use crate::arch::port::Port;
+use crate::sync::SpinLock;
+
+/// CMOS 的寄存器选择端口，最高位为 1 时会屏蔽 NMI
+const CMOS_ADDRESS: u16 = 0x70;
+/// CMOS 的数据端口
+const CMOS_DATA: u16 = 0x71;
+
+/// 秒寄存器
+const REG_SECONDS: u8 = 0x00;
+/// 分寄存器
+const REG_MINUTES: u8 = 0x02;
+/// 时寄存器
+const REG_HOURS: u8 = 0x04;
+/// 日寄存器
+const REG_DAY: u8 = 0x07;
+/// 月寄存器
+const REG_MONTH: u8 = 0x08;
+/// 年寄存器，只有后两位
+const REG_YEAR: u8 = 0x09;
+/// 状态寄存器 A，最高位表示正在更新
+const REG_STATUS_A: u8 = 0x0a;
+/// 状态寄存器 B，包含数据格式
+const REG_STATUS_B: u8 = 0x0b;
+
+/// 状态寄存器 A：正在更新
+const STATUS_A_UPDATING: u8 = 1 << 7;
+/// 状态寄存器 B：24 小时制
+const STATUS_B_24_HOUR: u8 = 1 << 1;
+/// 状态寄存器 B：二进制格式，否则为 BCD 码
+const STATUS_B_BINARY: u8 = 1 << 2;
+/// 12 小时制下时寄存器表示下午的位
+const HOUR_PM: u8 = 1 << 7;
+
+/// 没有世纪寄存器时年寄存器所在的世纪
+const DEFAULT_CENTURY: u16 = 2000;
+/// 世纪寄存器可能的索引，之前是时钟和状态寄存器，最高位用于屏蔽 NMI
+const CENTURY_REGISTERS: core::ops::Range<u8> = 0x0e..0x80;
+
+/// 1970-01-01 到 0000-03-01 的天数之差，用于把公历日期换算为 Unix 时间
+const DAYS_TO_EPOCH: i64 = 719_468;
+
+/// CMOS 的选择和数据端口需要成对访问
+static CMOS: SpinLock<()> = SpinLock::new(());
+
+/// 世纪寄存器的索引，0 表示没有
+static CENTURY_REGISTER: AtomicU8 = AtomicU8::new(0);
+
+/// 启动时刻的 Unix 时间（秒）
+static BOOT_SECONDS: AtomicU64 = AtomicU64::new(0);
+
+/// UTC 日期和时间
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct DateTime {
+    pub year: u16,  // 年
+    pub month: u8,  // 月，1~12
+    pub day: u8,    // 日，1~31
+    pub hour: u8,   // 时，0~23
+    pub minute: u8, // 分，0~59
+    pub second: u8, // 秒，0~59
+}
+
+impl DateTime {
+    /// 由 Unix 时间得到日期和时间
+    ///
+    /// # 参数
+    ///
+    /// - `seconds`: 自 1970-01-01 00:00:00 UTC 以来的秒数
+    pub fn from_unix(seconds: u64) -> Self {
+        let days = (seconds / 86400) as i64 + DAYS_TO_EPOCH;
+        let time = seconds % 86400;
+        // 以 3 月 1 日为一年的开始，闰日落在年末，每 400 年为一个周期
+        let era = days / 146_097;
+        let day_of_era = days - era * 146_097;
+        let year_of_era =
+            (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let shifted_month = (5 * day_of_year + 2) / 153;
+        let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+        let month = if shifted_month < 10 {
+            shifted_month + 3
+        } else {
+            shifted_month - 9
+        };
+        let year = year_of_era + era * 400 + i64::from(month <= 2);
+        Self {
+            year: year as u16,
+            month: month as u8,
+            day: day as u8,
+            hour: (time / 3600) as u8,
+            minute: (time / 60 % 60) as u8,
+            second: (time % 60) as u8,
+        }
+    }
+
+    /// 自 1970-01-01 00:00:00 UTC 以来的秒数，早于这一时刻时返回 0
+    pub fn unix_timestamp(&self) -> u64 {
+        let month = i64::from(self.month);
+        let year = i64::from(self.year) - i64::from(month <= 2);
+        let era = year / 400;
+        let year_of_era = year - era * 400;
+        let shifted_month = if month > 2 { month - 3 } else { month + 9 };
+        let day_of_year = (153 * shifted_month + 2) / 5 + i64::from(self.day) - 1;
+        let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+        let days = era * 146_097 + day_of_era - DAYS_TO_EPOCH;
+        let seconds = days * 86400
+            + i64::from(self.hour) * 3600
+            + i64::from(self.minute) * 60
+            + i64::from(self.second);
+        seconds.max(0) as u64
+    }
+}
+
+impl fmt::Display for DateTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )
+    }
+}
+
+/// 读取 RTC 并记录启动时刻的 Unix 时间
+pub(crate) fn init() {
+    let now = read();
+    let uptime = super::uptime_ms() / 1000;
+    BOOT_SECONDS.store(
+        now.unix_timestamp().saturating_sub(uptime),
+        Ordering::Relaxed,
+    );
+    log::info!("RTC reports {} UTC", now);
+}
+
+/// 使用 FADT 给出的世纪寄存器，并重新读取 RTC
+///
+/// 应在 [`acpi::init`](crate::acpi::init) 之后调用，没有 FADT 或其中没有世纪寄存器时什么都不做
+pub fn init_century() {
+    let Some(fadt) = crate::acpi::fadt() else {
+        return;
+    };
+    if !CENTURY_REGISTERS.contains(&fadt.century) {
+        return;
+    }
+    CENTURY_REGISTER.store(fadt.century, Ordering::Relaxed);
+    init();
+}
+
+/// 当前的 UTC 日期和时间，RTC 尚未读取时从 1970-01-01 开始计算
+pub fn now_utc() -> DateTime {
+    DateTime::from_unix(unix_time())
+}
+
+/// 当前的 Unix 时间（秒）
+pub fn unix_time() -> u64 {
+    BOOT_SECONDS.load(Ordering::Relaxed) + super::uptime_ms() / 1000
+}
+
+/// 读取 CMOS 寄存器
+///
+/// # 参数
+///
+/// - `reg`: 寄存器编号
+fn read_register(reg: u8) -> u8 {
+    let mut address = Port::<u8>::new(CMOS_ADDRESS);
+    let mut data = Port::<u8>::new(CMOS_DATA);
+    // 最高位保持为 0，TLB 击落依赖 NMI
+    unsafe {
+        address.write(reg & !0x80);
+        data.read()
+    }
+}
+
+/// 等到 RTC 不在更新时读出原始的日期和时间寄存器，以及世纪寄存器（有的话）
+fn read_raw() -> ([u8; 6], Option<u8>) {
+    while read_register(REG_STATUS_A) & STATUS_A_UPDATING != 0 {
+        core::hint::spin_loop();
+    }
+    let raw = [
+        REG_SECONDS,
+        REG_MINUTES,
+        REG_HOURS,
+        REG_DAY,
+        REG_MONTH,
+        REG_YEAR,
+    ]
+    .map(read_register);
+    let century = match CENTURY_REGISTER.load(Ordering::Relaxed) {
+        0 => None,
+        reg => Some(read_register(reg)),
+    };
+    (raw, century)
+}
+
+/// BCD 码转换为二进制
+///
+/// # 参数
+///
+/// - `value`: 两位 BCD 码
+fn from_bcd(value: u8) -> u8 {
+    (value >> 4) * 10 + (value & 0x0f)
+}
+
+/// 读取 RTC 当前的日期和时间
+pub fn read() -> DateTime {
+    let _guard = CMOS.lock();
+    let mut raw = read_raw();
+    // 两次读取之间发生了更新时重新读取
+    loop {
+        let again = read_raw();
+        if again == raw {
+            break;
+        }
+        raw = again;
+    }
+    let (raw, century) = raw;
+    decode(raw, century, read_register(REG_STATUS_B))
+}
+
+/// 按状态寄存器 B 给出的格式解码日期和时间寄存器
+///
+/// # 参数
+///
+/// - `raw`: 依次为秒、分、时、日、月、年寄存器的值
+/// - `century`: 世纪寄存器的值，没有世纪寄存器时为 `None`
+/// - `status_b`: 状态寄存器 B 的值
+fn decode(raw: [u8; 6], century: Option<u8>, status_b: u8) -> DateTime {
+    let [second, minute, hour, day, month, year] = raw;
+    let pm = hour & HOUR_PM != 0;
+    let binary = |value: u8| {
+        if status_b & STATUS_B_BINARY != 0 {
+            value
+        } else {
+            from_bcd(value)
+        }
+    };
+    let mut hour = binary(hour & !HOUR_PM);
+    if status_b & STATUS_B_24_HOUR == 0 {
+        // 12 小时制下 12 点表示 0 点或中午
+        hour %= 12;
+        if pm {
+            hour += 12;
+        }
+    }
+    DateTime {
+        year: century.map_or(DEFAULT_CENTURY, |century| u16::from(binary(century)) * 100)
+            + u16::from(binary(year)),
+        month: binary(month),
+        day: binary(day),
+        hour,
+        minute: binary(minute),
+        second: binary(second),
+    }
+}
+
+#[test_case]
+fn test_unix_timestamp_round_trip() {
+    let epoch = DateTime::from_unix(0);
+    assert_eq!((epoch.year, epoch.month, epoch.day), (1970, 1, 1));
+    // 2024-02-29 12:34:56 UTC
+    let leap = DateTime {
+        year: 2024,
+        month: 2,
+        day: 29,
+        hour: 12,
+        minute: 34,
+        second: 56,
+    };
+    assert_eq!(leap.unix_timestamp(), 1_709_210_096);
+    assert_eq!(DateTime::from_unix(1_709_210_096), leap);
+}
+
+#[test_case]
+fn test_decode_bcd_12_hour() {
+    // 2023-12-31 11:59:58 PM，BCD 码，12 小时制
+    let time = decode([0x58, 0x59, 0x11 | HOUR_PM, 0x31, 0x12, 0x23], None, 0);
+    assert_eq!(
+        (
+            time.year,
+            time.month,
+            time.day,
+            time.hour,
+            time.minute,
+            time.second
+        ),
+        (2023, 12, 31, 23, 59, 58)
+    );
+    let noon = decode([0, 0, 0x12 | HOUR_PM, 1, 1, 0], None, 0);
+    assert_eq!(noon.hour, 12);
+    let binary = decode(
+        [5, 6, 7, 8, 9, 10],
+        None,
+        STATUS_B_BINARY | STATUS_B_24_HOUR,
+    );
+    assert_eq!((binary.year, binary.hour, binary.second), (2010, 7, 5));
+    // 世纪寄存器与其他寄存器使用相同的格式
+    assert_eq!(decode([0, 0, 0, 1, 1, 0x99], Some(0x19), 0).year, 1999);
+    let binary = decode([0, 0, 0, 1, 1, 5], Some(21), STATUS_B_BINARY);
+    assert_eq!(binary.year, 2105);
+}
@@ -0,0 +1,100 @@
+//! 本模块实现了异步的睡眠和超时
+//!
+//! [`Sleep`] 在到期之前把唤醒器登记在时间轮中，由定时器中断唤醒，等待期间不占用 CPU
+
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll};
+use core::time::Duration;
+
+use super::timer_wheel::{self, TimerId};
+use super::{duration_to_ticks, ticks};
+
+/// 在指定时刻完成的 future
+pub struct Sleep {
+    id: TimerId,
+    deadline: u64,
+    expires: Option<u64>, // 在时间轮中登记的到期 tick
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        if ticks() >= self.deadline {
+            return Poll::Ready(());
+        }
+        self.expires = Some(timer_wheel::register(
+            self.id,
+            self.expires,
+            self.deadline,
+            cx.waker(),
+        ));
+        // 登记之后再检查一次，避免错过在此期间到达的 tick
+        if ticks() >= self.deadline {
+            return Poll::Ready(());
+        }
+        Poll::Pending
+    }
+}
+
+impl Drop for Sleep {
+    fn drop(&mut self) {
+        if let Some(expires) = self.expires {
+            timer_wheel::cancel(self.id, expires);
+        }
+    }
+}
+
+/// 睡眠一段时间，精度为一个 tick
+///
+/// # 参数
+///
+/// - `duration`: 睡眠的时长
+pub fn sleep(duration: Duration) -> Sleep {
+    Sleep {
+        id: TimerId::new(),
+        deadline: ticks() + duration_to_ticks(duration),
+        expires: None,
+    }
+}
+
+/// 超时错误
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Elapsed;
+
+/// 为 future 加上时限的 future
+pub struct Timeout<F> {
+    future: F,
+    sleep: Sleep,
+}
+
+impl<F: Future> Future for Timeout<F> {
+    type Output = Result<F::Output, Elapsed>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        // Safety: `future` 字段在固定之后从不移动，`sleep` 是 `Unpin` 的
+        let this = unsafe { self.get_unchecked_mut() };
+        let future = unsafe { Pin::new_unchecked(&mut this.future) };
+        if let Poll::Ready(output) = future.poll(cx) {
+            return Poll::Ready(Ok(output));
+        }
+        match Pin::new(&mut this.sleep).poll(cx) {
+            Poll::Ready(()) => Poll::Ready(Err(Elapsed)),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+/// 等待 future 完成，超过时限时返回 [`Elapsed`]
+///
+/// # 参数
+///
+/// - `duration`: 时限
+/// - `future`: 要等待的 future
+pub fn timeout<F: Future>(duration: Duration, future: F) -> Timeout<F> {
+    Timeout {
+        future,
+        sleep: sleep(duration),
+    }
+}
@@ -0,0 +1,329 @@
+//! 本模块实现了分层的时间轮
+//!
+//! 时间轮分为 [`LEVELS`] 层，每层 [`WHEEL_SLOTS`] 个槽，第 `n` 层的一个槽跨越 `64^n` 个 tick。
+//! 定时器按到期 tick 与当前 tick 最高的不同位落在哪一层放入对应的槽，定时器中断每个 tick 只检查第 0 层的一个槽；
+//! 每当低层转完一圈，高层对应的槽中的定时器被重新放入更低的层，直到在第 0 层到期。
+//! 最高层装不下的远期定时器在每转一圈时重新检查一次。
+//!
+//! 到期时可以唤醒异步任务、调用回调函数或唤醒等待队列上的线程，
+//! 这些动作在释放时间轮的锁之后、在定时器中断中执行，不能睡眠
+
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::task::Waker;
+
+use crate::sync::SpinLock;
+use crate::thread::WaitQueue;
+
+/// 每层的槽数以 2 为底的对数
+const LEVEL_BITS: u32 = 6;
+/// 每层的槽数
+const WHEEL_SLOTS: usize = 1 << LEVEL_BITS;
+/// 层数，默认频率下可以直接容纳约 10 天之内的定时器
+const LEVELS: usize = 4;
+
+/// 定时器编号
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) struct TimerId(u64);
+
+impl TimerId {
+    /// 分配一个新的定时器编号
+    pub(super) fn new() -> Self {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+        Self(NEXT_ID.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+/// 定时器到期时执行的动作
+enum Action {
+    Wake(Waker),                    // 唤醒异步任务
+    Call(Box<dyn FnOnce() + Send>), // 调用回调函数
+    WakeQueue(Arc<WaitQueue>),      // 唤醒等待队列上的所有线程
+}
+
+impl Action {
+    /// 执行动作
+    fn fire(self) {
+        match self {
+            Action::Wake(waker) => waker.wake(),
+            Action::Call(callback) => callback(),
+            Action::WakeQueue(queue) => {
+                queue.wake_all();
+            }
+        }
+    }
+}
+
+/// 时间轮中的定时器
+struct Timer {
+    id: TimerId,
+    expires: u64, // 到期的 tick
+    action: Action,
+}
+
+/// 时间轮
+struct TimerWheel {
+    now: u64, // 已经处理到的 tick
+    levels: [[Vec<Timer>; WHEEL_SLOTS]; LEVELS],
+}
+
+impl TimerWheel {
+    const fn new() -> Self {
+        Self {
+            now: 0,
+            levels: [const { [const { Vec::new() }; WHEEL_SLOTS] }; LEVELS],
+        }
+    }
+
+    /// 在 `expires` 到期的定时器当前所在的层和槽
+    ///
+    /// # 参数
+    ///
+    /// - `expires`: 到期的 tick，不早于 `self.now`
+    fn position(&self, expires: u64) -> (usize, usize) {
+        let diff = expires ^ self.now;
+        let level = match diff {
+            0 => 0,
+            _ => ((u64::BITS - 1 - diff.leading_zeros()) / LEVEL_BITS) as usize,
+        }
+        .min(LEVELS - 1);
+        let slot = (expires >> (LEVEL_BITS * level as u32)) as usize % WHEEL_SLOTS;
+        (level, slot)
+    }
+
+    /// 放入定时器，已经过去的到期时间推迟到下一个 tick
+    ///
+    /// # 返回
+    ///
+    /// 实际的到期 tick，用于之后查找或取消定时器
+    fn insert(&mut self, mut timer: Timer) -> u64 {
+        timer.expires = timer.expires.max(self.now + 1);
+        let expires = timer.expires;
+        self.place(timer);
+        expires
+    }
+
+    /// 按到期时间把定时器放入对应的槽
+    fn place(&mut self, timer: Timer) {
+        let (level, slot) = self.position(timer.expires);
+        self.levels[level][slot].push(timer);
+    }
+
+    /// 查找尚未到期的定时器
+    ///
+    /// # 参数
+    ///
+    /// - `id`: 定时器编号
+    /// - `expires`: 放入时返回的到期 tick
+    fn find(&mut self, id: TimerId, expires: u64) -> Option<&mut Timer> {
+        if expires <= self.now {
+            return None;
+        }
+        let (level, slot) = self.position(expires);
+        self.levels[level][slot]
+            .iter_mut()
+            .find(|timer| timer.id == id)
+    }
+
+    /// 移除尚未到期的定时器
+    ///
+    /// # 参数
+    ///
+    /// - `id`: 定时器编号
+    /// - `expires`: 放入时返回的到期 tick
+    fn remove(&mut self, id: TimerId, expires: u64) -> Option<Timer> {
+        if expires <= self.now {
+            return None;
+        }
+        let (level, slot) = self.position(expires);
+        let slot = &mut self.levels[level][slot];
+        let index = slot.iter().position(|timer| timer.id == id)?;
+        Some(slot.swap_remove(index))
+    }
+
+    /// 前进到 `now`，取出在此期间到期的定时器
+    fn advance(&mut self, now: u64) -> Vec<Timer> {
+        let mut expired = Vec::new();
+        while self.now < now {
+            self.now += 1;
+            let tick = self.now;
+            // 从高层到低层，把转到当前位置的槽中的定时器放入更低的层
+            for level in (1..LEVELS).rev() {
+                let shift = LEVEL_BITS * level as u32;
+                if tick & ((1 << shift) - 1) == 0 {
+                    let slot = (tick >> shift) as usize % WHEEL_SLOTS;
+                    for timer in core::mem::take(&mut self.levels[level][slot]) {
+                        self.place(timer);
+                    }
+                }
+            }
+            let slot = tick as usize % WHEEL_SLOTS;
+            expired.append(&mut self.levels[0][slot]);
+        }
+        expired
+    }
+}
+
+static WHEEL: SpinLock<TimerWheel> = SpinLock::new(TimerWheel::new());
+
+/// 注册唤醒异步任务的定时器，或更新已注册定时器的唤醒器
+///
+/// # 参数
+///
+/// - `id`: 定时器编号
+/// - `expires`: 上一次注册时返回的到期 tick，第一次注册时为 `None`
+/// - `deadline`: 到期的 tick
+/// - `waker`: 到期时调用的唤醒器
+///
+/// # 返回
+///
+/// 实际的到期 tick
+pub(super) fn register(id: TimerId, expires: Option<u64>, deadline: u64, waker: &Waker) -> u64 {
+    let mut wheel = WHEEL.lock();
+    if let Some(expires) = expires
+        && let Some(timer) = wheel.find(id, expires)
+    {
+        if let Action::Wake(old) = &mut timer.action {
+            old.clone_from(waker);
+        }
+        return expires;
+    }
+    wheel.insert(Timer {
+        id,
+        expires: deadline,
+        action: Action::Wake(waker.clone()),
+    })
+}
+
+/// 取消定时器
+///
+/// # 参数
+///
+/// - `id`: 定时器编号
+/// - `expires`: 注册时返回的到期 tick
+///
+/// # 返回
+///
+/// 定时器是否在到期之前被取消
+pub(super) fn cancel(id: TimerId, expires: u64) -> bool {
+    // 在锁外释放定时器，回调函数捕获的值可能在析构时获取其他锁
+    let timer = WHEEL.lock().remove(id, expires);
+    timer.is_some()
+}
+
+/// 已注册的定时器，用于在到期之前取消它
+///
+/// 丢弃句柄不会取消定时器
+#[derive(Debug)]
+pub struct TimerHandle {
+    id: TimerId,
+    expires: u64,
+}
+
+impl TimerHandle {
+    /// 到期的 tick
+    pub fn expires(&self) -> u64 {
+        self.expires
+    }
+
+    /// 取消定时器
+    ///
+    /// # 返回
+    ///
+    /// 定时器是否在到期之前被取消，已经到期时返回 `false`
+    pub fn cancel(self) -> bool {
+        cancel(self.id, self.expires)
+    }
+}
+
+/// 放入 `ticks` 个 tick 之后到期的定时器
+///
+/// # 参数
+///
+/// - `ticks`: 距离到期的 tick 数
+/// - `action`: 到期时执行的动作
+fn schedule(ticks: u64, action: Action) -> TimerHandle {
+    let id = TimerId::new();
+    let expires = WHEEL.lock().insert(Timer {
+        id,
+        expires: super::ticks().saturating_add(ticks),
+        action,
+    });
+    TimerHandle { id, expires }
+}
+
+/// 在 `ticks` 个 tick 之后调用 `callback`
+///
+/// 回调函数在定时器中断中执行，不能睡眠或长时间运行；`ticks` 为 0 时在下一个 tick 调用
+///
+/// # 参数
+///
+/// - `ticks`: 距离到期的 tick 数，可以用 [`duration_to_ticks`](super::duration_to_ticks) 由时长换算
+/// - `callback`: 到期时调用的函数
+pub fn call_after(ticks: u64, callback: impl FnOnce() + Send + 'static) -> TimerHandle {
+    schedule(ticks, Action::Call(Box::new(callback)))
+}
+
+/// 在 `ticks` 个 tick 之后唤醒 `queue` 上的所有线程
+///
+/// # 参数
+///
+/// - `ticks`: 距离到期的 tick 数
+/// - `queue`: 到期时唤醒的等待队列
+pub fn wake_after(ticks: u64, queue: Arc<WaitQueue>) -> TimerHandle {
+    schedule(ticks, Action::WakeQueue(queue))
+}
+
+/// 执行在 `now` 之前到期的定时器，由定时器中断每个 tick 调用一次
+///
+/// # 参数
+///
+/// - `now`: 当前的 tick
+pub(super) fn advance(now: u64) {
+    let expired = WHEEL.lock().advance(now);
+    for timer in expired {
+        timer.action.fire();
+    }
+}
+
+#[test_case]
+fn test_wheel_cascades_far_timers() {
+    let mut wheel = TimerWheel::new();
+    let timer = |expires| Timer {
+        id: TimerId::new(),
+        expires,
+        action: Action::Wake(Waker::noop().clone()),
+    };
+    let near = wheel.insert(timer(3));
+    let far = wheel.insert(timer(5000));
+    let cancelled = timer(4200);
+    let cancelled_id = cancelled.id;
+    let cancelled_at = wheel.insert(cancelled);
+    assert_eq!(wheel.position(far).0, 2);
+
+    let expired = wheel.advance(3);
+    assert_eq!(expired.len(), 1);
+    assert_eq!(expired[0].expires, near);
+    // 经过几次逐层下放之后仍然找得到尚未到期的定时器
+    assert!(wheel.advance(4100).is_empty());
+    assert!(wheel.remove(cancelled_id, cancelled_at).is_some());
+    assert!(wheel.advance(4999).is_empty());
+    let expired = wheel.advance(5000);
+    assert_eq!(expired.len(), 1);
+    assert_eq!(expired[0].expires, far);
+}
+
+#[test_case]
+fn test_wheel_past_deadline_fires_next_tick() {
+    let mut wheel = TimerWheel::new();
+    wheel.advance(10);
+    let expires = wheel.insert(Timer {
+        id: TimerId::new(),
+        expires: 4,
+        action: Action::Wake(Waker::noop().clone()),
+    });
+    assert_eq!(expires, 11);
+    assert_eq!(wheel.advance(11).len(), 1);
+}
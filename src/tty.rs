@@ -0,0 +1,125 @@
+//! 本模块实现了多个虚拟终端，按 Alt+F1~F4 切换
+//!
+//! 每个终端都是一个独立的 [`Writer`]，拥有自己的影子缓冲区、光标和回滚缓冲区，
+//! 只有当前显示的终端会写入 VGA 字符缓冲区。0 号终端即 [`WRITER`]，是 `print!` 的输出目标，
+//! 用于内核日志；其余终端可供交互式 shell 等使用
+//!
+//! 只有主控制台是 VGA 文本模式时才有多个终端。帧缓冲或串口作为主控制台时不能切换终端，
+//! 0 号终端的输出经由主控制台打印
+
+use core::fmt::{self, Write};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::sync::SpinLock;
+use crate::vga_buffer::{WRITER, Writer};
+use lazy_static::lazy_static;
+
+/// 虚拟终端数量
+pub const TTY_COUNT: usize = 4;
+
+lazy_static! {
+    /// 1~3 号终端，启动时不可见
+    static ref EXTRA_TERMINALS: [SpinLock<Writer>; TTY_COUNT - 1] =
+        core::array::from_fn(|_| SpinLock::new(Writer::new(false)));
+}
+
+/// 当前显示的终端编号
+static ACTIVE: AtomicUsize = AtomicUsize::new(0);
+
+/// 获取指定编号的终端
+///
+/// # 参数
+///
+/// - `index`: 终端编号，必须小于 [`TTY_COUNT`]
+pub fn terminal(index: usize) -> &'static SpinLock<Writer> {
+    match index {
+        0 => &WRITER,
+        index => &EXTRA_TERMINALS[index - 1],
+    }
+}
+
+/// 当前显示的终端编号
+pub fn active() -> usize {
+    ACTIVE.load(Ordering::Relaxed)
+}
+
+/// 当前显示的终端
+pub fn active_terminal() -> &'static SpinLock<Writer> {
+    terminal(active())
+}
+
+/// 切换到指定的终端，并将其内容重新绘制到屏幕上
+///
+/// # 参数
+///
+/// - `index`: 终端编号，超出范围或主控制台不支持多个终端时忽略
+pub fn switch(index: usize) {
+    use x86_64::instructions::interrupts;
+
+    if index >= TTY_COUNT || !crate::console::primary().has_terminals() {
+        return;
+    }
+    interrupts::without_interrupts(|| {
+        let previous = ACTIVE.swap(index, Ordering::Relaxed);
+        if previous == index {
+            return;
+        }
+        terminal(previous).lock().set_visible(false);
+        terminal(index).lock().set_visible(true);
+    });
+}
+
+/// 向指定的终端打印
+#[macro_export]
+macro_rules! tty_print {
+    ($tty:expr, $($arg:tt)*) => ($crate::tty::_print($tty, format_args!($($arg)*)));
+}
+
+/// 向指定的终端打印，并追加换行符
+#[macro_export]
+macro_rules! tty_println {
+    ($tty:expr) => ($crate::tty_print!($tty, "\n"));
+    ($tty:expr, $($arg:tt)*) => ($crate::tty_print!($tty, "{}\n", format_args!($($arg)*)));
+}
+
+#[doc(hidden)]
+pub fn _print(index: usize, args: fmt::Arguments) {
+    if index >= TTY_COUNT {
+        return;
+    }
+    let console = crate::console::primary();
+    if !console.has_terminals() {
+        // 主控制台只有一个屏幕，只有当前终端的输出可见
+        if index == active() {
+            crate::console::_print(args);
+        }
+        return;
+    }
+    {
+        let mut writer = terminal(index).lock();
+        writer.write_fmt(args).unwrap();
+        writer.flush();
+    }
+    // 当前显示的终端与 `print!` 一样复制到附加输出端
+    if index == active() {
+        crate::console::mirror(console, args);
+    }
+}
+
+#[test_case]
+fn test_switch_terminal() {
+    use x86_64::instructions::interrupts;
+
+    switch(2);
+    assert_eq!(active(), 2);
+    interrupts::without_interrupts(|| {
+        assert!(terminal(2).lock().is_visible());
+        assert!(!terminal(0).lock().is_visible());
+    });
+    switch(0);
+    assert_eq!(active(), 0);
+    interrupts::without_interrupts(|| {
+        assert!(!terminal(2).lock().is_visible());
+        assert!(terminal(0).lock().is_visible());
+    });
+}
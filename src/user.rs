@@ -0,0 +1,246 @@
+//! 本模块实现了进入用户态（ring 3）执行的基础设施
+//!
+//! 用户程序的代码和栈映射在低半部分顶端单独的区域中，页表项带有 `USER_ACCESSIBLE` 标志；
+//! 内核的页不带该标志，用户态访问时会触发缺页异常。用户态发生中断或异常时，
+//! CPU 切换到 TSS 中 RSP0 指向的内核栈
+
+use core::arch::asm;
+
+use x86_64::VirtAddr;
+use x86_64::structures::paging::mapper::MapToError;
+use x86_64::structures::paging::{
+    FrameAllocator, FrameDeallocator, Mapper, Page, PageTableFlags, Size4KiB,
+};
+
+use crate::{gdt, memory};
+
+/// 用户程序代码的默认加载地址
+pub const USER_CODE_START: u64 = USER_SPACE_START;
+/// 用户栈的栈顶
+pub const USER_STACK_TOP: u64 = 0x_6000_1000_0000;
+/// 用户栈映射的页数
+pub const USER_STACK_PAGES: u64 = 4;
+
+/// 用户地址空间的下界，之下的 4 级表项属于内核，由所有地址空间共享
+pub const USER_SPACE_START: u64 = 0x_6000_0000_0000;
+/// 用户地址空间的上界（低半部分的末尾）
+pub const USER_SPACE_END: u64 = 0x_8000_0000_0000;
+
+/// 页大小
+const PAGE_SIZE: u64 = 4096;
+
+/// 在当前地址空间中为从 `start` 开始的 `pages` 个页分配清零的物理帧，并以用户可访问的权限映射
+///
+/// 已经映射的页不会重新分配，只会加上 `flags` 中的权限，便于映射共享同一页的相邻段；
+/// 需要可写时，写时复制的页先换成独占的副本
+///
+/// # 参数
+///
+/// - `start`: 区域的起始地址，需要按页对齐
+/// - `pages`: 页数
+/// - `flags`: 额外的页表项标志，`PRESENT` 和 `USER_ACCESSIBLE` 会自动加上
+pub fn map_region(
+    start: VirtAddr,
+    pages: u64,
+    flags: PageTableFlags,
+) -> Result<(), MapToError<Size4KiB>> {
+    use x86_64::structures::paging::mapper::{Translate, TranslateResult};
+
+    let flags = flags | PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE;
+    memory::with_active_mapper(|mapper, frame_allocator| {
+        let phys_offset = mapper.phys_offset();
+        for i in 0..pages {
+            let page = Page::containing_address(start + i * PAGE_SIZE);
+            if flags.contains(PageTableFlags::WRITABLE) {
+                memory::resolve_copy_on_write(mapper, frame_allocator, page)?;
+            }
+            if let TranslateResult::Mapped { flags: old, .. } =
+                mapper.translate(page.start_address())
+            {
+                // 只要有一方可执行，页就必须可执行
+                let mut merged = old | flags;
+                if !old.contains(PageTableFlags::NO_EXECUTE)
+                    || !flags.contains(PageTableFlags::NO_EXECUTE)
+                {
+                    merged.remove(PageTableFlags::NO_EXECUTE);
+                }
+                // 已经转换成功的 4 KiB 页只可能因为上级是大页而无法修改
+                let flush = unsafe { mapper.update_flags(page, merged) }
+                    .map_err(|_| MapToError::ParentEntryHugePage)?;
+                flush.flush();
+                continue;
+            }
+            let frame = frame_allocator
+                .allocate_frame()
+                .ok_or(MapToError::FrameAllocationFailed)?;
+            // 帧分配器不会清零，旧的内容不能泄露给用户程序
+            let virt = phys_offset + frame.start_address().as_u64();
+            unsafe { core::ptr::write_bytes(virt.as_mut_ptr::<u8>(), 0, PAGE_SIZE as usize) };
+            unsafe { mapper.map_to(page, frame, flags, frame_allocator)?.flush() };
+        }
+        Ok(())
+    })
+    .ok_or(MapToError::FrameAllocationFailed)?
+}
+
+/// 解除当前地址空间中从 `start` 开始的 `pages` 个页的映射并释放物理帧，未映射的页被跳过
+///
+/// # 参数
+///
+/// - `start`: 区域的起始地址，需要按页对齐
+/// - `pages`: 页数
+pub fn unmap_region(start: VirtAddr, pages: u64) {
+    memory::with_active_mapper(|mapper, frame_allocator| {
+        let first = Page::<Size4KiB>::containing_address(start);
+        for page in Page::range(first, first + pages) {
+            if let Ok(frame) = memory::unmap_page(mapper, page) {
+                unsafe { frame_allocator.deallocate_frame(frame) };
+            }
+        }
+    });
+}
+
+/// 将数据复制到当前地址空间中已映射的用户内存中
+///
+/// 通过物理内存映射写入，因此目标页可以是只读的；写时复制的页先换成独占的副本
+///
+/// # 参数
+///
+/// - `addr`: 目标虚拟地址
+/// - `bytes`: 要复制的数据
+///
+/// # 返回
+///
+/// 目标区域中有未映射的页时返回 `false`
+pub fn copy_to(addr: VirtAddr, bytes: &[u8]) -> bool {
+    use x86_64::structures::paging::Translate;
+
+    memory::with_active_mapper(|mapper, frame_allocator| {
+        let phys_offset = mapper.phys_offset();
+        let mut copied = 0;
+        while copied < bytes.len() {
+            let target = addr + copied as u64;
+            let page = Page::containing_address(target);
+            if memory::resolve_copy_on_write(mapper, frame_allocator, page).is_err() {
+                return false;
+            }
+            let Some(phys) = mapper.translate_addr(target) else {
+                return false;
+            };
+            // 每次最多复制到当前页的末尾，相邻的虚拟页不一定映射到相邻的物理帧
+            let in_page = (PAGE_SIZE - target.as_u64() % PAGE_SIZE) as usize;
+            let n = in_page.min(bytes.len() - copied);
+            let dst = (phys_offset + phys.as_u64()).as_mut_ptr::<u8>();
+            unsafe { core::ptr::copy_nonoverlapping(bytes[copied..].as_ptr(), dst, n) };
+            copied += n;
+        }
+        true
+    })
+    .unwrap_or(false)
+}
+
+/// 检查当前地址空间中一段内存是否都已映射且用户态可以访问，系统调用访问用户指针之前调用
+///
+/// # 参数
+///
+/// - `addr`: 起始地址
+/// - `len`: 长度
+/// - `write`: 是否需要可写
+pub fn is_accessible(addr: u64, len: u64, write: bool) -> bool {
+    use x86_64::structures::paging::mapper::{Translate, TranslateResult};
+
+    // 用户指针必须完全位于用户地址空间中
+    let Some(end) = addr.checked_add(len) else {
+        return false;
+    };
+    if addr < USER_SPACE_START || end > USER_SPACE_END {
+        return false;
+    }
+    let required = PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE;
+    // 写时复制的页在写入时才变为可写
+    let writable = PageTableFlags::WRITABLE | memory::COPY_ON_WRITE;
+    memory::with_active_mapper(|mapper, _| {
+        let mut page = addr & !(PAGE_SIZE - 1);
+        while page < end {
+            match mapper.translate(VirtAddr::new(page)) {
+                TranslateResult::Mapped { flags, .. }
+                    if flags.contains(required) && (!write || flags.intersects(writable)) => {}
+                _ => return false,
+            }
+            page += PAGE_SIZE;
+        }
+        true
+    })
+    .unwrap_or(false)
+}
+
+/// 用户栈最低一页的起始地址
+pub const USER_STACK_BOTTOM: u64 = USER_STACK_TOP - USER_STACK_PAGES * PAGE_SIZE;
+
+/// 映射默认的用户栈
+///
+/// # 返回
+///
+/// 用户栈的栈顶
+pub fn map_stack() -> Result<VirtAddr, MapToError<Size4KiB>> {
+    let top = VirtAddr::new(USER_STACK_TOP);
+    map_region(
+        VirtAddr::new(USER_STACK_BOTTOM),
+        USER_STACK_PAGES,
+        PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE,
+    )?;
+    Ok(top)
+}
+
+/// 切换到用户态，从 `entry` 开始执行
+///
+/// 构造 `iretq` 所需的栈帧，开启中断，并清空通用寄存器，避免泄露内核数据
+///
+/// # 参数
+///
+/// - `entry`: 用户程序的入口地址
+/// - `stack_top`: 用户栈的栈顶
+///
+/// # Safety
+///
+/// `entry` 和 `stack_top` 必须指向已映射且用户可访问的内存
+pub unsafe fn enter(entry: VirtAddr, stack_top: VirtAddr) -> ! {
+    /// 用户态的 RFLAGS：保留位 1 和中断允许位
+    const USER_RFLAGS: u64 = 0x202;
+
+    let selectors = gdt::selectors();
+    unsafe {
+        asm!(
+            "push {ss}",
+            "push {rsp}",
+            "push {rflags}",
+            "push {cs}",
+            "push {rip}",
+            "xor eax, eax",
+            "xor ebx, ebx",
+            "xor ecx, ecx",
+            "xor edx, edx",
+            "xor esi, esi",
+            "xor edi, edi",
+            "xor ebp, ebp",
+            "xor r8d, r8d",
+            "xor r9d, r9d",
+            "xor r10d, r10d",
+            "xor r11d, r11d",
+            "xor r12d, r12d",
+            "xor r13d, r13d",
+            "xor r14d, r14d",
+            "xor r15d, r15d",
+            // 换回用户的 GS 基址之后不能再被中断
+            "cli",
+            "swapgs",
+            "iretq",
+            ss = in(reg) u64::from(selectors.user_data.0),
+            rsp = in(reg) stack_top.as_u64(),
+            rflags = in(reg) USER_RFLAGS,
+            cs = in(reg) u64::from(selectors.user_code.0),
+            rip = in(reg) entry.as_u64(),
+            options(noreturn),
+        );
+    }
+}
@@ -1,11 +1,20 @@
 //! 本模块实现了VGA text mode的封装
 
+pub(crate) mod cp437;
+mod mode;
+mod scrollback;
+
 use core::fmt::{self, Write};
+use core::sync::atomic::{AtomicUsize, Ordering};
 
 use lazy_static::lazy_static;
-use spin::Mutex;
 use volatile::Volatile;
 
+use crate::ansi::{self, CsiSequence};
+use crate::sync::SpinLock;
+#[cfg(test)]
+use crate::{println, println_colored};
+
 /// 颜色
 #[allow(dead_code)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -32,40 +41,171 @@ pub enum Color {
 /// 颜色代码
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(transparent)] // 确保 ColorCode 和 u8 有完全相同的内存布局
-struct ColorCode(u8);
+pub struct ColorCode(u8);
 
 impl ColorCode {
     /// 使用前景色和背景色创建颜色代码字节
-    fn new(foreground: Color, background: Color) -> Self {
+    pub fn new(foreground: Color, background: Color) -> Self {
         Self((background as u8) << 4 | (foreground as u8))
     }
+
+    /// 替换前景色
+    ///
+    /// # 参数
+    ///
+    /// - `foreground`: 前景色的 VGA 颜色编号（0~15）
+    fn with_foreground(self, foreground: u8) -> Self {
+        Self((self.0 & 0xf0) | (foreground & 0x0f))
+    }
+
+    /// 替换背景色
+    ///
+    /// # 参数
+    ///
+    /// - `background`: 背景色的 VGA 颜色编号（0~15）
+    fn with_background(self, background: u8) -> Self {
+        Self((self.0 & 0x0f) | (background & 0x0f) << 4)
+    }
+
+    fn foreground(self) -> u8 {
+        self.0 & 0x0f
+    }
+
+    fn background(self) -> u8 {
+        self.0 >> 4
+    }
 }
 
+/// ANSI 颜色编号（黑、红、绿、黄、蓝、品红、青、白）到 VGA 颜色编号的映射
+pub(crate) const ANSI_TO_VGA_COLOR: [u8; 8] = [0, 4, 2, 6, 1, 5, 3, 7];
+/// VGA 颜色编号中的高亮位
+pub(crate) const BRIGHT_BIT: u8 = 0x08;
+
 /// 屏幕上的字符
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(C)] // 按 C 语言约定的顺序布局它的成员变量，让我们能正确地映射内存片段
-struct ScreenChar {
-    ascii_character: u8,
-    color_code: ColorCode,
+pub struct ScreenChar {
+    pub ascii_character: u8,   // 代码页 437 中的字节
+    pub color_code: ColorCode, // 颜色代码
 }
 
-const BUFFER_HEIGHT: usize = 25;
-const BUFFER_WIDTH: usize = 80;
+/// 支持的最大行数（80x50 文本模式）
+pub const MAX_BUFFER_HEIGHT: usize = 50;
+/// 支持的最大列数
+pub const MAX_BUFFER_WIDTH: usize = 80;
+/// 默认 80x25 文本模式的行数
+const DEFAULT_HEIGHT: usize = 25;
+/// 默认 80x25 文本模式的列数
+const DEFAULT_WIDTH: usize = 80;
 
-///  VGA 字符缓冲区
+///  VGA 字符缓冲区，按当前列数逐行排列
 #[repr(transparent)]
 struct Buffer {
-    chars: [[Volatile<ScreenChar>; BUFFER_WIDTH]; BUFFER_HEIGHT],
+    chars: [Volatile<ScreenChar>; MAX_BUFFER_HEIGHT * MAX_BUFFER_WIDTH],
+}
+
+/// CRT 控制器的地址寄存器端口
+const CRTC_ADDRESS_PORT: u16 = 0x3d4;
+/// CRT 控制器的数据寄存器端口
+const CRTC_DATA_PORT: u16 = 0x3d5;
+
+/// 光标起始扫描线寄存器，第 5 位为光标禁用位
+const CRTC_CURSOR_START: u8 = 0x0a;
+/// 光标结束扫描线寄存器
+const CRTC_CURSOR_END: u8 = 0x0b;
+/// 光标位置高字节寄存器
+const CRTC_CURSOR_LOCATION_HIGH: u8 = 0x0e;
+/// 光标位置低字节寄存器
+const CRTC_CURSOR_LOCATION_LOW: u8 = 0x0f;
+
+/// 光标禁用位
+const CURSOR_DISABLE: u8 = 0x20;
+
+/// 读取 CRT 控制器寄存器
+///
+/// # 参数
+///
+/// - `index`: 寄存器编号
+fn read_crtc(index: u8) -> u8 {
+    use crate::arch::port::Port;
+
+    unsafe {
+        Port::new(CRTC_ADDRESS_PORT).write(index);
+        Port::new(CRTC_DATA_PORT).read()
+    }
 }
 
+/// 写入 CRT 控制器寄存器
+///
+/// # 参数
+///
+/// - `index`: 寄存器编号
+/// - `value`: 要写入的值
+fn write_crtc(index: u8, value: u8) {
+    use crate::arch::port::Port;
+
+    unsafe {
+        Port::new(CRTC_ADDRESS_PORT).write(index);
+        Port::new(CRTC_DATA_PORT).write(value);
+    }
+}
+
+/// 默认颜色的空白字符
+const BLANK: ScreenChar = ScreenChar {
+    ascii_character: b' ',
+    color_code: ColorCode((Color::Black as u8) << 4 | (Color::Yellow as u8)),
+};
+
+/// 退格符
+const BACKSPACE: u8 = 0x08;
+/// 代表非 ASCII 字符的占位字节
+const NON_ASCII: u8 = 0xff;
+/// 制表位间隔
+const TAB_WIDTH: usize = 8;
+
 /// 字符缓冲区的写入器
 pub struct Writer {
-    column_position: usize,      // 光标在最后一行的位置
-    color_code: ColorCode,       // 当前字符的前景和背景色
+    top_row: usize,                // 滚动区域的第一行，之上的行被保留不参与滚动
+    row_position: usize,           // 光标所在的行
+    column_position: usize,        // 光标在当前行的位置
+    color_code: ColorCode,         // 当前字符的前景和背景色
+    default_color_code: ColorCode, // SGR 重置时恢复的颜色
+    ansi_parser: ansi::Parser,     // ANSI 转义序列解析器
+    /// 回滚缓冲区，堆初始化后才可用
+    scrollback: Option<scrollback::Scrollback>,
+    shadow: scrollback::Screen, // 内存中的影子缓冲区，所有写入先落在这里
+    dirty: [bool; MAX_BUFFER_HEIGHT], // 自上次刷新以来被修改过的行
+    height: usize,              // 屏幕的行数
+    width: usize,               // 屏幕的列数
+    visible: bool,              // 是否为当前显示的终端，只有它会写入硬件
     buffer: &'static mut Buffer, //  VGA 字符缓冲区
 }
 
 impl Writer {
+    /// 创建一个写入器
+    ///
+    /// # 参数
+    ///
+    /// - `visible`: 是否立即显示在屏幕上
+    pub(crate) fn new(visible: bool) -> Self {
+        Self {
+            top_row: 0,
+            row_position: DEFAULT_HEIGHT - 1,
+            column_position: 0,
+            color_code: BLANK.color_code,
+            default_color_code: BLANK.color_code,
+            ansi_parser: ansi::Parser::new(),
+            scrollback: None,
+            shadow: [[BLANK; MAX_BUFFER_WIDTH]; MAX_BUFFER_HEIGHT],
+            dirty: [false; MAX_BUFFER_HEIGHT],
+            height: DEFAULT_HEIGHT,
+            width: DEFAULT_WIDTH,
+            visible,
+            // 多个写入器共享 VGA 字符缓冲区，但同一时刻只有可见的那个会写入它
+            buffer: unsafe { &mut *(0xb8000 as *mut Buffer) },
+        }
+    }
+
     /// 将一个字节写入 VGA 字符缓冲区，自动处理换行和移至下一行
     ///
     /// # 参数
@@ -74,50 +214,504 @@ impl Writer {
     pub fn write_byte(&mut self, byte: u8) {
         match byte {
             b'\n' => self.new_line(),
-            byte => {
-                if self.column_position >= BUFFER_WIDTH {
-                    self.new_line();
-                }
-
-                let row = BUFFER_HEIGHT - 1;
-                let col = self.column_position;
+            b'\r' => self.column_position = 0,
+            BACKSPACE => self.backspace(),
+            b'\t' => self.tab(),
+            byte => self.write_glyph(byte),
+        }
+    }
 
-                let color_code = self.color_code;
-                self.buffer.chars[row][col].write(ScreenChar {
-                    ascii_character: byte,
-                    color_code,
-                });
-                self.column_position += 1;
-            }
+    /// 在光标处写入一个字形并前移光标，不解释控制字符
+    ///
+    /// # 参数
+    ///
+    /// - `glyph`: 代码页 437 中的字节
+    fn write_glyph(&mut self, glyph: u8) {
+        if self.column_position >= self.width {
+            self.new_line();
         }
+
+        let row = self.row_position;
+        let col = self.column_position;
+
+        let color_code = self.color_code;
+        self.put_char(
+            row,
+            col,
+            ScreenChar {
+                ascii_character: glyph,
+                color_code,
+            },
+        );
+        self.column_position += 1;
     }
 
-    /// 将字符串写入 VGA 字符缓冲区
+    /// 将字符串写入 VGA 字符缓冲区，并解释其中的 ANSI 转义序列
+    ///
+    /// 非 ASCII 字符会被转换为代码页 437 中对应的字形，无法转换时显示为 `?`
     ///
     /// # 参数
     ///
     /// - `s`: 要写入的字符串
     pub fn write_string(&mut self, s: &str) {
-        for byte in s.bytes() {
+        // 有新的输出时回到当前屏幕
+        self.scroll_to_bottom();
+        for c in s.chars() {
+            // 非 ASCII 字符不会出现在转义序列中，用一个非 ASCII 字节代替它驱动解析器
+            let byte = if c.is_ascii() { c as u8 } else { NON_ASCII };
+            let byte = match self.ansi_parser.advance(byte) {
+                Some(ansi::Action::Print(byte)) => byte,
+                Some(ansi::Action::Csi(seq)) => {
+                    self.apply_csi(&seq);
+                    continue;
+                }
+                None => continue,
+            };
             match byte {
-                // 可以是能打印的 ASCII 码字节，也可以是换行符
-                0x20..=0x7e | b'\n' => self.write_byte(byte),
-                // 不包含在上述范围之内的字节
+                // 可以是能打印的 ASCII 码字节，也可以是换行、回车、退格和制表符
+                0x20..=0x7e | b'\n' | b'\r' | BACKSPACE | b'\t' => self.write_byte(byte),
+                NON_ASCII => self.write_glyph(cp437::from_char(c).unwrap_or(b'?')),
+                // 其余的控制字符
                 _ => self.write_byte(0xfe),
             }
         }
+        self.update_cursor();
     }
 
-    /// 将光标移到下一行
-    fn new_line(&mut self) {
-        for row in 1..BUFFER_HEIGHT {
-            for col in 0..BUFFER_WIDTH {
-                let character = self.buffer.chars[row][col].read();
-                self.buffer.chars[row - 1][col].write(character);
+    /// 执行 CSI 序列，支持 SGR 颜色、光标移动和擦除
+    ///
+    /// # 参数
+    ///
+    /// - `seq`: CSI 序列
+    fn apply_csi(&mut self, seq: &CsiSequence) {
+        let n = usize::from(seq.param_or(0, 1));
+        match seq.final_byte {
+            b'm' => self.apply_sgr(seq),
+            b'A' => self.row_position = self.row_position.saturating_sub(n).max(self.top_row),
+            b'B' => self.row_position = (self.row_position + n).min(self.height - 1),
+            b'C' => self.column_position = (self.column_position + n).min(self.width - 1),
+            b'D' => self.column_position = self.column_position.saturating_sub(n),
+            b'H' | b'f' => {
+                // 行列参数从 1 开始计数，行号相对于滚动区域
+                let row = usize::from(seq.param_or(0, 1)) - 1;
+                let col = usize::from(seq.param_or(1, 1)) - 1;
+                self.row_position = (self.top_row + row).min(self.height - 1);
+                self.column_position = col.min(self.width - 1);
             }
+            b'J' => self.erase_display(seq.param_or(0, 0)),
+            b'K' => self.erase_line(seq.param_or(0, 0)),
+            // 不支持的序列直接忽略
+            _ => {}
+        }
+    }
+
+    /// 执行 SGR（Select Graphic Rendition）序列
+    ///
+    /// # 参数
+    ///
+    /// - `seq`: 终止字节为 `m` 的 CSI 序列
+    fn apply_sgr(&mut self, seq: &CsiSequence) {
+        if seq.params().is_empty() {
+            self.color_code = self.default_color_code;
+            return;
+        }
+        for &param in seq.params() {
+            let code = self.color_code;
+            // 粗体在 VGA 上以高亮前景色表示
+            let bright = code.foreground() & BRIGHT_BIT;
+            self.color_code =
+                match param {
+                    0 => self.default_color_code,
+                    1 => code.with_foreground(code.foreground() | BRIGHT_BIT),
+                    22 => code.with_foreground(code.foreground() & !BRIGHT_BIT),
+                    30..=37 => {
+                        code.with_foreground(ANSI_TO_VGA_COLOR[usize::from(param - 30)] | bright)
+                    }
+                    39 => code.with_foreground(self.default_color_code.foreground()),
+                    40..=47 => code.with_background(ANSI_TO_VGA_COLOR[usize::from(param - 40)]),
+                    49 => code.with_background(self.default_color_code.background()),
+                    90..=97 => code
+                        .with_foreground(ANSI_TO_VGA_COLOR[usize::from(param - 90)] | BRIGHT_BIT),
+                    100..=107 => code
+                        .with_background(ANSI_TO_VGA_COLOR[usize::from(param - 100)] | BRIGHT_BIT),
+                    _ => code,
+                };
+        }
+    }
+
+    /// 擦除屏幕（`ESC [ n J`）
+    ///
+    /// # 参数
+    ///
+    /// - `mode`: 0 擦除光标到屏幕末尾，1 擦除屏幕开头到光标，2 擦除整个屏幕
+    fn erase_display(&mut self, mode: u16) {
+        let row = self.row_position;
+        match mode {
+            0 => {
+                self.erase_line(0);
+                for row in row + 1..self.height {
+                    self.clear_row(row);
+                }
+            }
+            1 => {
+                for row in self.top_row..row {
+                    self.clear_row(row);
+                }
+                self.erase_line(1);
+            }
+            2 => {
+                for row in self.top_row..self.height {
+                    self.clear_row(row);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// 擦除当前行（`ESC [ n K`）
+    ///
+    /// # 参数
+    ///
+    /// - `mode`: 0 擦除光标到行尾，1 擦除行首到光标，2 擦除整行
+    fn erase_line(&mut self, mode: u16) {
+        let col = self.column_position.min(self.width - 1);
+        let cols = match mode {
+            0 => col..self.width,
+            1 => 0..col + 1,
+            2 => 0..self.width,
+            _ => return,
+        };
+        let blank = ScreenChar {
+            ascii_character: b' ',
+            color_code: self.color_code,
+        };
+        for col in cols {
+            self.put_char(self.row_position, col, blank);
+        }
+    }
+
+    /// 在指定位置以指定颜色写入字符串，不移动光标，也不解释控制字符
+    ///
+    /// 超出行尾的部分会被截断
+    ///
+    /// # 参数
+    ///
+    /// - `row`: 行
+    /// - `col`: 起始列
+    /// - `s`: 要写入的字符串
+    /// - `color_code`: 颜色代码
+    pub fn write_at(&mut self, row: usize, col: usize, s: &str, color_code: ColorCode) {
+        if row >= self.height {
+            return;
+        }
+        for (col, byte) in (col..self.width).zip(s.bytes()) {
+            let ascii_character = match byte {
+                0x20..=0x7e => byte,
+                _ => 0xfe,
+            };
+            self.put_char(
+                row,
+                col,
+                ScreenChar {
+                    ascii_character,
+                    color_code,
+                },
+            );
+        }
+    }
+
+    /// 读取影子缓冲区中指定位置的字符，即使写入器不可见也反映其当前内容
+    ///
+    /// # 参数
+    ///
+    /// - `row`: 行
+    /// - `col`: 列
+    ///
+    /// # 返回
+    ///
+    /// 位置超出屏幕时返回 `None`
+    pub fn read_char(&self, row: usize, col: usize) -> Option<ScreenChar> {
+        if row >= self.height || col >= self.width {
+            return None;
+        }
+        Some(self.shadow[row][col])
+    }
+
+    /// 擦除前一个字符并将光标后退一格，光标已在行首时不做任何事
+    fn backspace(&mut self) {
+        if self.column_position == 0 {
+            return;
         }
-        self.clear_row(BUFFER_HEIGHT - 1);
+        self.column_position -= 1;
+        let blank = ScreenChar {
+            ascii_character: b' ',
+            color_code: self.color_code,
+        };
+        self.put_char(self.row_position, self.column_position, blank);
+    }
+
+    /// 用空格填充到下一个制表位，超出行宽时换行
+    fn tab(&mut self) {
+        let next_stop = (self.column_position / TAB_WIDTH + 1) * TAB_WIDTH;
+        if next_stop >= self.width {
+            self.new_line();
+            return;
+        }
+        while self.column_position < next_stop {
+            self.write_byte(b' ');
+        }
+    }
+
+    /// 设置之后写入字符的前景色和背景色
+    ///
+    /// # 参数
+    ///
+    /// - `foreground`: 前景色
+    /// - `background`: 背景色
+    pub fn set_color(&mut self, foreground: Color, background: Color) {
+        self.color_code = ColorCode::new(foreground, background);
+    }
+
+    /// 获取当前的颜色代码
+    pub fn color_code(&self) -> ColorCode {
+        self.color_code
+    }
+
+    /// 设置当前的颜色代码
+    ///
+    /// # 参数
+    ///
+    /// - `color_code`: 颜色代码
+    pub fn set_color_code(&mut self, color_code: ColorCode) {
+        self.color_code = color_code;
+    }
+
+    /// 清空整个滚动区域，并将光标移到左上角
+    pub fn clear_screen(&mut self) {
+        for row in self.top_row..self.height {
+            self.clear_row(row);
+        }
+        self.row_position = self.top_row;
         self.column_position = 0;
+        self.update_cursor();
+    }
+
+    /// 显示硬件光标
+    pub fn show_cursor(&mut self) {
+        if !self.visible {
+            return;
+        }
+        // 下划线光标占据字符的最后两条扫描线，随当前字体高度变化
+        let last_scan_line = read_crtc(mode::CRTC_MAX_SCAN_LINE) & 0x1f;
+        let start = read_crtc(CRTC_CURSOR_START);
+        write_crtc(CRTC_CURSOR_START, (start & 0xc0) | (last_scan_line - 1));
+        let end = read_crtc(CRTC_CURSOR_END);
+        write_crtc(CRTC_CURSOR_END, (end & 0xe0) | last_scan_line);
+        self.update_cursor();
+    }
+
+    /// 隐藏硬件光标
+    pub fn hide_cursor(&mut self) {
+        if !self.visible {
+            return;
+        }
+        write_crtc(CRTC_CURSOR_START, CURSOR_DISABLE);
+    }
+
+    /// 将硬件光标移到当前写入位置
+    fn update_cursor(&self) {
+        if !self.visible {
+            return;
+        }
+        // 行已写满时光标停在行尾，直到下一个字符触发换行
+        let col = self.column_position.min(self.width - 1);
+        let pos = (self.row_position * self.width + col) as u16;
+        write_crtc(CRTC_CURSOR_LOCATION_LOW, (pos & 0xff) as u8);
+        write_crtc(CRTC_CURSOR_LOCATION_HIGH, (pos >> 8) as u8);
+    }
+
+    /// 设置是否为当前显示的终端，变为可见时重新绘制整个屏幕
+    ///
+    /// # 参数
+    ///
+    /// - `visible`: 是否可见
+    pub fn set_visible(&mut self, visible: bool) {
+        self.visible = visible;
+        if !visible {
+            return;
+        }
+        if let Some(scrollback) = &mut self.scrollback {
+            scrollback.set_offset(0);
+        }
+        self.dirty = [true; MAX_BUFFER_HEIGHT];
+        self.flush();
+        self.show_cursor();
+    }
+
+    /// 是否为当前显示的终端
+    pub fn is_visible(&self) -> bool {
+        self.visible
+    }
+
+    /// 保留屏幕第一行作为状态栏，之后的滚动和清屏只作用于其余各行
+    pub fn reserve_status_line(&mut self) {
+        self.top_row = 1;
+        self.row_position = self.row_position.max(self.top_row);
+        self.update_cursor();
+    }
+
+    /// 屏幕的行数
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// 屏幕的列数
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    /// 回滚时每次翻动的行数
+    pub fn page_lines(&self) -> usize {
+        self.height - 1
+    }
+
+    /// 在切换显示模式后调整屏幕尺寸，新出现的行被清空，整个屏幕标记为待刷新
+    ///
+    /// # 参数
+    ///
+    /// - `width`: 新的列数，不超过 [`MAX_BUFFER_WIDTH`]
+    /// - `height`: 新的行数，不超过 [`MAX_BUFFER_HEIGHT`]
+    pub fn resize(&mut self, width: usize, height: usize) {
+        let old_height = self.height;
+        self.width = width.clamp(1, MAX_BUFFER_WIDTH);
+        self.height = height.clamp(self.top_row + 1, MAX_BUFFER_HEIGHT);
+        for row in old_height..self.height {
+            self.clear_row(row);
+        }
+        self.row_position = self.row_position.min(self.height - 1);
+        self.column_position = self.column_position.min(self.width);
+        self.dirty = [true; MAX_BUFFER_HEIGHT];
+        self.flush();
+        self.update_cursor();
+    }
+
+    /// 启用回滚缓冲区，必须在堆初始化之后调用
+    pub fn enable_scrollback(&mut self) {
+        if self.scrollback.is_none() {
+            self.scrollback = Some(scrollback::Scrollback::new());
+        }
+    }
+
+    /// 向上回滚若干行查看历史输出
+    ///
+    /// # 参数
+    ///
+    /// - `lines`: 回滚的行数
+    pub fn scroll_up(&mut self, lines: usize) {
+        let offset = match &self.scrollback {
+            Some(scrollback) => scrollback.offset() + lines,
+            None => return,
+        };
+        self.set_scroll_offset(offset);
+    }
+
+    /// 向下回滚若干行，到达底部时恢复当前屏幕
+    ///
+    /// # 参数
+    ///
+    /// - `lines`: 回滚的行数
+    pub fn scroll_down(&mut self, lines: usize) {
+        let offset = match &self.scrollback {
+            Some(scrollback) => scrollback.offset().saturating_sub(lines),
+            None => return,
+        };
+        self.set_scroll_offset(offset);
+    }
+
+    /// 结束查看历史，恢复当前屏幕
+    pub fn scroll_to_bottom(&mut self) {
+        self.set_scroll_offset(0);
+    }
+
+    /// 设置回滚的行数并重新渲染屏幕
+    ///
+    /// # 参数
+    ///
+    /// - `offset`: 回滚的行数
+    fn set_scroll_offset(&mut self, offset: usize) {
+        let Some(scrollback) = &mut self.scrollback else {
+            return;
+        };
+        let was_scrolled = scrollback.is_scrolled();
+        // 历史为空时实际回滚的行数也为 0，与回到底部相同，不隐藏光标
+        if scrollback.set_offset(offset) == 0 {
+            if was_scrolled {
+                self.dirty = [true; MAX_BUFFER_HEIGHT];
+                self.flush();
+                self.show_cursor();
+            }
+            return;
+        }
+        if !self.visible {
+            return;
+        }
+        // 历史内容直接渲染到硬件缓冲区，影子缓冲区保持当前屏幕不变
+        for row in self.top_row..self.height {
+            let line =
+                scrollback.view_line(row - self.top_row, &self.shadow[self.top_row..self.height]);
+            for (col, &character) in line.iter().enumerate() {
+                self.buffer.chars[row * self.width + col].write(character);
+            }
+        }
+        // 查看历史时隐藏光标
+        self.hide_cursor();
+    }
+
+    /// 将影子缓冲区中被修改过的行复制到 VGA 字符缓冲区
+    pub fn flush(&mut self) {
+        // 不可见或查看历史时屏幕显示的不是当前内容，等切换回来时再刷新
+        if !self.visible || self.scrollback.as_ref().is_some_and(|s| s.is_scrolled()) {
+            return;
+        }
+        for row in 0..self.height {
+            if !self.dirty[row] {
+                continue;
+            }
+            for col in 0..self.width {
+                self.buffer.chars[row * self.width + col].write(self.shadow[row][col]);
+            }
+            self.dirty[row] = false;
+        }
+    }
+
+    /// 在影子缓冲区中写入一个字符，并将所在行标记为待刷新
+    ///
+    /// # 参数
+    ///
+    /// - `row`: 行
+    /// - `col`: 列
+    /// - `character`: 要写入的字符
+    fn put_char(&mut self, row: usize, col: usize, character: ScreenChar) {
+        self.shadow[row][col] = character;
+        self.dirty[row] = true;
+    }
+
+    /// 将光标移到下一行，光标已在最后一行时整屏上滚一行
+    fn new_line(&mut self) {
+        self.column_position = 0;
+        if self.row_position < self.height - 1 {
+            self.row_position += 1;
+            return;
+        }
+
+        if let Some(scrollback) = &mut self.scrollback {
+            scrollback.push(self.shadow[self.top_row]);
+        }
+
+        self.shadow
+            .copy_within(self.top_row + 1..self.height, self.top_row);
+        self.dirty = [true; MAX_BUFFER_HEIGHT];
+        self.clear_row(self.height - 1);
     }
 
     /// 清空一行
@@ -130,8 +724,8 @@ impl Writer {
             ascii_character: b' ',
             color_code: self.color_code,
         };
-        for col in 0..BUFFER_WIDTH {
-            self.buffer.chars[row][col].write(blank);
+        for col in 0..self.width {
+            self.put_char(row, col, blank);
         }
     }
 }
@@ -145,28 +739,409 @@ impl fmt::Write for Writer {
 
 // 使用非常函数初始化静态变量
 // 使用lazy_static包，这个变量的值将在第一次使用时计算，而非在编译时计算
-// 使用使用自旋的互斥锁，使其支持同步的内部可变性
+// 中断处理函数也会打印，因此使用持有期间禁用中断的自旋锁
 lazy_static! {
-    pub static ref WRITER: Mutex<Writer> = Mutex::new(Writer {
-        column_position: 0,
-        color_code: ColorCode::new(Color::Yellow, Color::Black),
-        buffer: unsafe { &mut *(0xb8000 as *mut Buffer) },
+    pub static ref WRITER: SpinLock<Writer> = SpinLock::new(Writer::new(true));
+}
+
+#[doc(hidden)]
+pub fn _clear() {
+    let mut writer = WRITER.lock();
+    writer.clear_screen();
+    writer.flush();
+}
+
+#[doc(hidden)]
+pub fn _print(args: fmt::Arguments) {
+    // 锁在持有期间禁用中断，中断处理函数打印时不会死锁
+    let mut writer = WRITER.lock();
+    writer.write_fmt(args).unwrap();
+    writer.flush();
+}
+
+/// 在指定位置以指定颜色写入字符串并立即刷新，不影响主输出的光标
+///
+/// # 参数
+///
+/// - `row`: 行
+/// - `col`: 起始列
+/// - `s`: 要写入的字符串
+/// - `color_code`: 颜色代码
+pub fn write_at(row: usize, col: usize, s: &str, color_code: ColorCode) {
+    let mut writer = WRITER.lock();
+    writer.write_at(row, col, s, color_code);
+    writer.flush();
+}
+
+/// 读取屏幕上指定位置实际显示的字符，用于在测试中检查输出
+///
+/// # 参数
+///
+/// - `row`: 行
+/// - `col`: 列
+///
+/// # 返回
+///
+/// 位置超出屏幕时返回 `None`
+pub fn read_char(row: usize, col: usize) -> Option<ScreenChar> {
+    let writer = WRITER.lock();
+    if row >= writer.height || col >= writer.width {
+        return None;
+    }
+    Some(writer.buffer.chars[row * writer.width + col].read())
+}
+
+/// 为所有终端启用回滚缓冲区，必须在堆初始化之后调用
+pub fn init_scrollback() {
+    for index in 0..crate::tty::TTY_COUNT {
+        crate::tty::terminal(index).lock().enable_scrollback();
+    }
+}
+
+/// 切换到 8x8 字体的 80x50 文本模式，并调整所有终端的尺寸
+///
+/// # 参数
+///
+/// - `physical_memory_offset`: 完整物理内存映射的起始虚拟地址
+///
+/// # Safety
+///
+/// 调用者必须保证 `physical_memory_offset` 处映射了完整的物理内存
+pub unsafe fn set_text_mode_80x50(physical_memory_offset: x86_64::VirtAddr) {
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+        unsafe { mode::load_8x8_font(physical_memory_offset) };
     });
+    SCREEN_HEIGHT.store(MAX_BUFFER_HEIGHT, Ordering::Relaxed);
+    for index in 0..crate::tty::TTY_COUNT {
+        let mut terminal = crate::tty::terminal(index).lock();
+        terminal.resize(MAX_BUFFER_WIDTH, MAX_BUFFER_HEIGHT);
+        terminal.show_cursor();
+    }
 }
 
-#[macro_export]
-macro_rules! print {
-    ($($arg:tt)*) => ($crate::vga_buffer::_print(format_args!($($arg)*)));
+/// 当前文本模式的行数，切换到 80x50 之后为 [`MAX_BUFFER_HEIGHT`]
+static SCREEN_HEIGHT: AtomicUsize = AtomicUsize::new(DEFAULT_HEIGHT);
+
+/// 当前文本模式的行数，不需要获取任何终端的锁
+pub fn screen_height() -> usize {
+    SCREEN_HEIGHT.load(Ordering::Relaxed)
 }
 
-#[macro_export]
-macro_rules! println {
-    () => ($crate::print!("\n"));
-    ($($arg:tt)*) => ($crate::print!("{}\n", format_args!($($arg)*)));
+/// 文本模式字体中字形的最大高度
+pub const FONT_MAX_HEIGHT: usize = 16;
+
+/// 复制文本模式当前使用的字体，供图形模式下绘制文字，需要在离开文本模式之前调用
+///
+/// # 参数
+///
+/// - `physical_memory_offset`: 完整物理内存映射的起始虚拟地址
+/// - `glyphs`: 保存 256 个字形的缓冲区，每个字形每行一个字节，最高位在左
+///
+/// # 返回
+///
+/// 字形的高度，即字体占用的扫描线数
+///
+/// # Safety
+///
+/// 调用者必须保证 `physical_memory_offset` 处映射了完整的物理内存
+pub unsafe fn read_font(
+    physical_memory_offset: x86_64::VirtAddr,
+    glyphs: &mut [[u8; FONT_MAX_HEIGHT]; 256],
+) -> usize {
+    x86_64::instructions::interrupts::without_interrupts(|| unsafe {
+        mode::read_font(physical_memory_offset, glyphs)
+    })
 }
 
 #[doc(hidden)]
-pub fn _print(args: fmt::Arguments) {
-    use core::fmt::Write;
-    WRITER.lock().write_fmt(args).unwrap();
+pub fn _print_colored(foreground: Color, background: Color, args: fmt::Arguments) {
+    let mut writer = WRITER.lock();
+    let saved = writer.color_code();
+    writer.set_color(foreground, background);
+    writer.write_fmt(args).unwrap();
+    writer.set_color_code(saved);
+    writer.flush();
+}
+
+#[test_case]
+fn test_println_simple() {
+    println!("test_println_simple output");
+}
+
+#[test_case]
+fn test_println_many() {
+    for _ in 0..200 {
+        println!("test_println_many output");
+    }
+}
+
+#[test_case]
+fn test_print_colored_restores_color() {
+    use x86_64::instructions::interrupts;
+
+    let before = interrupts::without_interrupts(|| WRITER.lock().color_code());
+    println_colored!(Color::Red, Color::Black, "test_print_colored output");
+    let after = interrupts::without_interrupts(|| WRITER.lock().color_code());
+    assert_eq!(before, after);
+}
+
+#[test_case]
+fn test_backspace_and_carriage_return() {
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        writeln!(writer).unwrap();
+        write!(writer, "abc\x08\x08").unwrap();
+        let row = writer.row_position;
+        assert_eq!(writer.column_position, 1);
+        assert_eq!(writer.shadow[row][0].ascii_character, b'a');
+        assert_eq!(writer.shadow[row][1].ascii_character, b' ');
+
+        write!(writer, "\rz").unwrap();
+        assert_eq!(writer.column_position, 1);
+        assert_eq!(writer.shadow[row][0].ascii_character, b'z');
+    });
+}
+
+#[test_case]
+fn test_tab_stops() {
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        writeln!(writer).unwrap();
+        write!(writer, "\t").unwrap();
+        assert_eq!(writer.column_position, 8);
+        write!(writer, "abc\t").unwrap();
+        assert_eq!(writer.column_position, 16);
+        write!(writer, "12345678\t").unwrap();
+        assert_eq!(writer.column_position, 32);
+    });
+}
+
+#[test_case]
+fn test_ansi_sgr_colors() {
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        let default = writer.default_color_code;
+        write!(writer, "\x1b[1;31;44m").unwrap();
+        assert_eq!(
+            writer.color_code(),
+            ColorCode::new(Color::LightRed, Color::Blue)
+        );
+        write!(writer, "\x1b[0m").unwrap();
+        assert_eq!(writer.color_code(), default);
+    });
+}
+
+#[test_case]
+fn test_ansi_cursor_movement() {
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        write!(writer, "\x1b[3;5H").unwrap();
+        assert_eq!((writer.row_position, writer.column_position), (2, 4));
+        write!(writer, "\x1b[2A\x1b[3C").unwrap();
+        assert_eq!((writer.row_position, writer.column_position), (0, 7));
+        write!(writer, "\x1b[B\x1b[10D").unwrap();
+        assert_eq!((writer.row_position, writer.column_position), (1, 0));
+        writer.clear_screen();
+    });
+}
+
+#[test_case]
+fn test_flush_copies_dirty_rows() {
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        write!(writer, "\n\x1b[2K").unwrap();
+        writer.flush();
+        let row = writer.row_position;
+        write!(writer, "x").unwrap();
+        assert!(writer.dirty[row]);
+        assert_ne!(
+            writer.buffer.chars[row * writer.width]
+                .read()
+                .ascii_character,
+            b'x'
+        );
+        writer.flush();
+        assert!(!writer.dirty[row]);
+        assert_eq!(
+            writer.buffer.chars[row * writer.width]
+                .read()
+                .ascii_character,
+            b'x'
+        );
+    });
+}
+
+#[test_case]
+fn test_write_at_keeps_cursor() {
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        let cursor = (writer.row_position, writer.column_position);
+        let color = ColorCode::new(Color::White, Color::Red);
+        let width = writer.width();
+        writer.write_at(3, width - 2, "abc", color);
+        assert_eq!((writer.row_position, writer.column_position), cursor);
+        assert_eq!(writer.shadow[3][width - 2].ascii_character, b'a');
+        assert_eq!(writer.shadow[3][width - 1].ascii_character, b'b');
+        assert_eq!(writer.shadow[3][width - 1].color_code, color);
+    });
+}
+
+#[test_case]
+fn test_status_line_is_not_scrolled() {
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        writer.reserve_status_line();
+        let color = writer.color_code();
+        writer.write_at(0, 0, "S", color);
+        for _ in 0..writer.height * 2 {
+            writeln!(writer, "scrolling").unwrap();
+        }
+        writer.clear_screen();
+        assert_eq!(writer.shadow[0][0].ascii_character, b'S');
+        assert_eq!(writer.row_position, 1);
+        writer.top_row = 0;
+    });
+}
+
+#[test_case]
+fn test_hidden_writer_does_not_touch_screen() {
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+        let mut hidden = Writer::new(false);
+        let row = hidden.row_position;
+        let before = hidden.buffer.chars[row * hidden.width].read();
+        write!(
+            hidden,
+            "\x1b[2K{}",
+            char::from(before.ascii_character.wrapping_add(1))
+        )
+        .unwrap();
+        hidden.flush();
+        assert!(hidden.dirty[row]);
+        assert_eq!(hidden.buffer.chars[row * hidden.width].read(), before);
+
+        hidden.set_visible(true);
+        assert_eq!(
+            hidden.buffer.chars[row * hidden.width]
+                .read()
+                .ascii_character,
+            before.ascii_character.wrapping_add(1)
+        );
+        // 恢复原来的屏幕内容
+        WRITER.lock().set_visible(true);
+    });
+}
+
+#[test_case]
+fn test_utf8_transliteration() {
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        writeln!(writer).unwrap();
+        write!(writer, "25°C ◘✓").unwrap();
+        let row = writer.row_position;
+        let glyphs: [u8; 7] = [b'2', b'5', 0xf8, b'C', b' ', 0x08, b'?'];
+        for (col, &glyph) in glyphs.iter().enumerate() {
+            assert_eq!(writer.shadow[row][col].ascii_character, glyph);
+        }
+        assert_eq!(writer.column_position, glyphs.len());
+    });
+}
+
+#[test_case]
+fn test_clear_screen() {
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        writeln!(writer, "some text").unwrap();
+        writer.clear_screen();
+        writer.flush();
+        assert_eq!((writer.row_position, writer.column_position), (0, 0));
+        for row in 0..writer.height {
+            for col in 0..writer.width {
+                let screen_char = writer.buffer.chars[row * writer.width + col].read();
+                assert_eq!(char::from(screen_char.ascii_character), ' ');
+            }
+        }
+    });
+}
+
+#[test_case]
+fn test_resize_uses_new_height() {
+    let mut writer = Writer::new(false);
+    writer.resize(MAX_BUFFER_WIDTH, MAX_BUFFER_HEIGHT);
+    assert_eq!(writer.height(), MAX_BUFFER_HEIGHT);
+    writer.clear_screen();
+    for _ in 0..DEFAULT_HEIGHT {
+        writeln!(writer).unwrap();
+    }
+    assert_eq!(writer.row_position, DEFAULT_HEIGHT);
+    write!(writer, "\x1b[99;99H").unwrap();
+    assert_eq!(
+        (writer.row_position, writer.column_position),
+        (MAX_BUFFER_HEIGHT - 1, MAX_BUFFER_WIDTH - 1)
+    );
+    assert_eq!(writer.page_lines(), MAX_BUFFER_HEIGHT - 1);
+}
+
+#[test_case]
+fn test_println_output() {
+    use x86_64::instructions::interrupts;
+
+    let s = "Some test string that fits on a single line";
+    interrupts::without_interrupts(|| {
+        let row = {
+            let mut writer = WRITER.lock();
+            writeln!(writer, "\n{}", s).unwrap();
+            writer.flush();
+            writer.row_position - 1
+        };
+        for (col, c) in s.bytes().enumerate() {
+            assert_eq!(read_char(row, col).unwrap().ascii_character, c);
+        }
+    });
+}
+
+#[test_case]
+fn test_read_char_after_scrolling() {
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+        let row = {
+            let mut writer = WRITER.lock();
+            // 先移到最后一行，之后的每次换行都会让整屏上滚
+            for _ in 0..writer.height {
+                writeln!(writer).unwrap();
+            }
+            writeln!(writer, "marker").unwrap();
+            for _ in 0..3 {
+                writeln!(writer, "filler").unwrap();
+            }
+            writer.flush();
+            writer.height - 5
+        };
+        for (col, c) in "marker".bytes().enumerate() {
+            assert_eq!(read_char(row, col).unwrap().ascii_character, c);
+        }
+        assert_eq!(read_char(row, MAX_BUFFER_WIDTH), None);
+    });
 }
@@ -0,0 +1,59 @@
+//! 本模块实现了 Unicode 字符到代码页 437（VGA 文本模式字符集）的转换
+
+/// 代码页 437 中 0x01~0x1f 的图形字符
+const LOW_GLYPHS: [char; 31] = [
+    '☺', '☻', '♥', '♦', '♣', '♠', '•', '◘', '○', '◙', '♂', '♀', '♪', '♫', '☼', '►', //
+    '◄', '↕', '‼', '¶', '§', '▬', '↨', '↑', '↓', '→', '←', '∟', '↔', '▲', '▼',
+];
+
+/// 代码页 437 中 0x80~0xff 的字符
+const HIGH_GLYPHS: [char; 128] = [
+    'Ç', 'ü', 'é', 'â', 'ä', 'à', 'å', 'ç', 'ê', 'ë', 'è', 'ï', 'î', 'ì', 'Ä', 'Å', //
+    'É', 'æ', 'Æ', 'ô', 'ö', 'ò', 'û', 'ù', 'ÿ', 'Ö', 'Ü', '¢', '£', '¥', '₧', 'ƒ', //
+    'á', 'í', 'ó', 'ú', 'ñ', 'Ñ', 'ª', 'º', '¿', '⌐', '¬', '½', '¼', '¡', '«', '»', //
+    '░', '▒', '▓', '│', '┤', '╡', '╢', '╖', '╕', '╣', '║', '╗', '╝', '╜', '╛', '┐', //
+    '└', '┴', '┬', '├', '─', '┼', '╞', '╟', '╚', '╔', '╩', '╦', '╠', '═', '╬', '╧', //
+    '╨', '╤', '╥', '╙', '╘', '╒', '╓', '╫', '╪', '┘', '┌', '█', '▄', '▌', '▐', '▀', //
+    'α', 'ß', 'Γ', 'π', 'Σ', 'σ', 'µ', 'τ', 'Φ', 'Θ', 'Ω', 'δ', '∞', 'φ', 'ε', '∩', //
+    '≡', '±', '≥', '≤', '⌠', '⌡', '÷', '≈', '°', '∙', '·', '√', 'ⁿ', '²', '■', '\u{a0}',
+];
+
+/// 外形相同但码点不同的字符
+const ALIASES: [(char, u8); 5] = [
+    ('β', 0xe1), // 希腊字母 beta 与德语 ß 共用一个字形
+    ('μ', 0xe6), // 希腊字母 mu 与微符号
+    ('Ø', 0xed), // 与 φ 共用字形
+    ('∈', 0xee), // 与 ε 共用字形
+    ('⌂', 0x7f),
+];
+
+/// 将非 ASCII 字符转换为代码页 437 中对应的字节
+///
+/// # 参数
+///
+/// - `c`: 要转换的字符
+///
+/// # 返回
+///
+/// 代码页 437 中没有对应字形时返回 `None`
+pub(crate) fn from_char(c: char) -> Option<u8> {
+    if let Some(index) = HIGH_GLYPHS.iter().position(|&glyph| glyph == c) {
+        return Some(0x80 + index as u8);
+    }
+    if let Some(index) = LOW_GLYPHS.iter().position(|&glyph| glyph == c) {
+        return Some(0x01 + index as u8);
+    }
+    ALIASES
+        .iter()
+        .find(|&&(alias, _)| alias == c)
+        .map(|&(_, byte)| byte)
+}
+
+#[test_case]
+fn test_from_char() {
+    assert_eq!(from_char('°'), Some(0xf8));
+    assert_eq!(from_char('é'), Some(0x82));
+    assert_eq!(from_char('┌'), Some(0xda));
+    assert_eq!(from_char('→'), Some(0x1a));
+    assert_eq!(from_char('😀'), None);
+}
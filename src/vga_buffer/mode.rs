@@ -0,0 +1,184 @@
+//! 本模块实现了 VGA 文本模式的寄存器编程
+//!
+//! 80x50 文本模式与 80x25 使用相同的 400 条扫描线，只是字符高度从 16 行降为 8 行。
+//! 这里不依赖 BIOS，而是把显存平面 2 中现有的 8x16 字体逐字形压缩为 8x8 字体，
+//! 再修改 CRT 控制器的最大扫描线寄存器。
+//! 切换到图形模式之前也从平面 2 复制字体，用于在帧缓冲上绘制文字
+
+use x86_64::VirtAddr;
+
+use super::{FONT_MAX_HEIGHT, read_crtc, write_crtc};
+use crate::arch::port::Port;
+
+/// 时序控制器的地址寄存器端口
+const SEQUENCER_ADDRESS_PORT: u16 = 0x3c4;
+/// 时序控制器的数据寄存器端口
+const SEQUENCER_DATA_PORT: u16 = 0x3c5;
+/// 图形控制器的地址寄存器端口
+const GRAPHICS_ADDRESS_PORT: u16 = 0x3ce;
+/// 图形控制器的数据寄存器端口
+const GRAPHICS_DATA_PORT: u16 = 0x3cf;
+
+/// 时序控制器：平面写使能寄存器
+const SEQUENCER_MAP_MASK: u8 = 0x02;
+/// 时序控制器：内存模式寄存器
+const SEQUENCER_MEMORY_MODE: u8 = 0x04;
+/// 图形控制器：读平面选择寄存器
+const GRAPHICS_READ_MAP_SELECT: u8 = 0x04;
+/// 图形控制器：图形模式寄存器
+const GRAPHICS_MODE: u8 = 0x05;
+/// 图形控制器：杂项寄存器
+const GRAPHICS_MISC: u8 = 0x06;
+/// CRT 控制器：最大扫描线寄存器，低 5 位为字符高度减一
+pub(super) const CRTC_MAX_SCAN_LINE: u8 = 0x09;
+
+/// 字体所在的显存平面
+const FONT_PLANE: u8 = 2;
+/// 字体在显存中的物理地址（图形控制器映射到 0xa0000 时）
+const FONT_PHYS_ADDR: u64 = 0xa0000;
+/// 字体中每个字形占用的字节数，与实际字符高度无关
+const GLYPH_STRIDE: usize = 32;
+/// 字形的数量
+const GLYPH_COUNT: usize = 256;
+/// 8x8 字体的字符高度
+const FONT_HEIGHT_8X8: u8 = 8;
+
+/// 读取时序控制器寄存器
+///
+/// # 参数
+///
+/// - `index`: 寄存器编号
+fn read_sequencer(index: u8) -> u8 {
+    unsafe {
+        Port::new(SEQUENCER_ADDRESS_PORT).write(index);
+        Port::new(SEQUENCER_DATA_PORT).read()
+    }
+}
+
+/// 写入时序控制器寄存器
+///
+/// # 参数
+///
+/// - `index`: 寄存器编号
+/// - `value`: 要写入的值
+fn write_sequencer(index: u8, value: u8) {
+    unsafe {
+        Port::new(SEQUENCER_ADDRESS_PORT).write(index);
+        Port::new(SEQUENCER_DATA_PORT).write(value);
+    }
+}
+
+/// 读取图形控制器寄存器
+///
+/// # 参数
+///
+/// - `index`: 寄存器编号
+fn read_graphics(index: u8) -> u8 {
+    unsafe {
+        Port::new(GRAPHICS_ADDRESS_PORT).write(index);
+        Port::new(GRAPHICS_DATA_PORT).read()
+    }
+}
+
+/// 写入图形控制器寄存器
+///
+/// # 参数
+///
+/// - `index`: 寄存器编号
+/// - `value`: 要写入的值
+fn write_graphics(index: u8, value: u8) {
+    unsafe {
+        Port::new(GRAPHICS_ADDRESS_PORT).write(index);
+        Port::new(GRAPHICS_DATA_PORT).write(value);
+    }
+}
+
+/// 把显存映射为只访问平面 2，执行 `f` 之后恢复原来的设置
+///
+/// # Safety
+///
+/// 调用者必须保证在此期间没有其他代码访问 VGA 寄存器或显存
+unsafe fn with_font_plane<T>(f: impl FnOnce() -> T) -> T {
+    let saved_map_mask = read_sequencer(SEQUENCER_MAP_MASK);
+    let saved_memory_mode = read_sequencer(SEQUENCER_MEMORY_MODE);
+    let saved_read_map = read_graphics(GRAPHICS_READ_MAP_SELECT);
+    let saved_mode = read_graphics(GRAPHICS_MODE);
+    let saved_misc = read_graphics(GRAPHICS_MISC);
+
+    // 只读写平面 2，关闭奇偶寻址，并将显存映射到 0xa0000 起的 64 KiB
+    write_sequencer(SEQUENCER_MAP_MASK, 1 << FONT_PLANE);
+    write_sequencer(SEQUENCER_MEMORY_MODE, 0x07);
+    write_graphics(GRAPHICS_READ_MAP_SELECT, FONT_PLANE);
+    write_graphics(GRAPHICS_MODE, 0x00);
+    write_graphics(GRAPHICS_MISC, 0x04);
+
+    let result = f();
+
+    write_sequencer(SEQUENCER_MAP_MASK, saved_map_mask);
+    write_sequencer(SEQUENCER_MEMORY_MODE, saved_memory_mode);
+    write_graphics(GRAPHICS_READ_MAP_SELECT, saved_read_map);
+    write_graphics(GRAPHICS_MODE, saved_mode);
+    write_graphics(GRAPHICS_MISC, saved_misc);
+    result
+}
+
+/// 复制当前字体，字形的高度由 CRT 控制器的最大扫描线寄存器给出
+///
+/// # 返回
+///
+/// 字形的高度
+///
+/// # Safety
+///
+/// 调用者必须保证 `physical_memory_offset` 处映射了完整的物理内存，
+/// 并且在此期间没有其他代码访问 VGA 寄存器或显存
+pub(super) unsafe fn read_font(
+    physical_memory_offset: VirtAddr,
+    glyphs: &mut [[u8; FONT_MAX_HEIGHT]; GLYPH_COUNT],
+) -> usize {
+    let height = (usize::from(read_crtc(CRTC_MAX_SCAN_LINE) & 0x1f) + 1).min(FONT_MAX_HEIGHT);
+    let font = (physical_memory_offset + FONT_PHYS_ADDR).as_ptr::<u8>();
+    unsafe {
+        with_font_plane(|| {
+            for (index, glyph) in glyphs.iter_mut().enumerate() {
+                let base = font.add(index * GLYPH_STRIDE);
+                for (row, byte) in glyph.iter_mut().enumerate().take(height) {
+                    *byte = base.add(row).read_volatile();
+                }
+            }
+        })
+    };
+    height
+}
+
+/// 将当前字体压缩为 8x8 字体，并把字符高度设为 8 条扫描线
+///
+/// 每两条相邻扫描线按位或合并为一条，这样代码页 437 中的所有字形（包括制表符）都得以保留
+///
+/// # Safety
+///
+/// 调用者必须保证 `physical_memory_offset` 处映射了完整的物理内存，
+/// 并且在此期间没有其他代码访问 VGA 寄存器或显存
+pub(super) unsafe fn load_8x8_font(physical_memory_offset: VirtAddr) {
+    let font = (physical_memory_offset + FONT_PHYS_ADDR).as_mut_ptr::<u8>();
+    unsafe {
+        with_font_plane(|| {
+            for glyph in 0..GLYPH_COUNT {
+                let base = font.add(glyph * GLYPH_STRIDE);
+                let mut rows = [0u8; 16];
+                for (i, row) in rows.iter_mut().enumerate() {
+                    *row = base.add(i).read_volatile();
+                }
+                for i in 0..usize::from(FONT_HEIGHT_8X8) {
+                    base.add(i).write_volatile(rows[2 * i] | rows[2 * i + 1]);
+                }
+            }
+        })
+    };
+
+    let max_scan_line = read_crtc(CRTC_MAX_SCAN_LINE);
+    write_crtc(
+        CRTC_MAX_SCAN_LINE,
+        (max_scan_line & 0xe0) | (FONT_HEIGHT_8X8 - 1),
+    );
+}
@@ -0,0 +1,78 @@
+//! 本模块实现了 VGA 文本模式的回滚缓冲区
+//!
+//! 滚出屏幕顶部的行保存在堆上的环形缓冲区中，查看历史时直接渲染到硬件缓冲区，
+//! 当前屏幕内容始终保留在影子缓冲区里，回到底部时重新刷新即可
+
+use alloc::collections::VecDeque;
+
+use super::{MAX_BUFFER_HEIGHT, MAX_BUFFER_WIDTH, ScreenChar};
+
+/// 回滚缓冲区保存的行数，与屏幕行数无关，避免 80x50 模式下占用过多堆内存
+pub(super) const SCROLLBACK_LINES: usize = 100;
+
+/// 一行字符
+pub(super) type Line = [ScreenChar; MAX_BUFFER_WIDTH];
+/// 一屏字符
+pub(super) type Screen = [Line; MAX_BUFFER_HEIGHT];
+
+/// 回滚缓冲区
+pub(super) struct Scrollback {
+    lines: VecDeque<Line>, // 滚出屏幕的历史行，最旧的在前
+    offset: usize,         // 向上回滚的行数，0 表示正在显示当前屏幕
+}
+
+impl Scrollback {
+    /// 创建空的回滚缓冲区
+    pub(super) fn new() -> Self {
+        Self {
+            lines: VecDeque::with_capacity(SCROLLBACK_LINES),
+            offset: 0,
+        }
+    }
+
+    /// 保存一行滚出屏幕的内容，缓冲区已满时丢弃最旧的一行
+    ///
+    /// # 参数
+    ///
+    /// - `line`: 滚出屏幕的行
+    pub(super) fn push(&mut self, line: Line) {
+        if self.lines.len() == SCROLLBACK_LINES {
+            self.lines.pop_front();
+        }
+        self.lines.push_back(line);
+    }
+
+    /// 当前向上回滚的行数
+    pub(super) fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// 是否正在查看历史
+    pub(super) fn is_scrolled(&self) -> bool {
+        self.offset > 0
+    }
+
+    /// 设置回滚的行数，返回实际生效的行数
+    ///
+    /// # 参数
+    ///
+    /// - `offset`: 期望回滚的行数
+    pub(super) fn set_offset(&mut self, offset: usize) -> usize {
+        self.offset = offset.min(self.lines.len());
+        self.offset
+    }
+
+    /// 获取当前视图中第 `row` 行应显示的内容
+    ///
+    /// # 参数
+    ///
+    /// - `row`: 相对于滚动区域顶部的行号
+    /// - `live`: 当前滚动区域的内容
+    pub(super) fn view_line<'a>(&'a self, row: usize, live: &'a [Line]) -> &'a Line {
+        let index = self.lines.len() - self.offset + row;
+        match self.lines.get(index) {
+            Some(line) => line,
+            None => &live[index - self.lines.len()],
+        }
+    }
+}
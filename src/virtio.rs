@@ -0,0 +1,549 @@
+//! 本模块实现了 virtio PCI 设备的传输层
+//!
+//! virtio 设备通过虚拟队列与驱动交换缓冲区，PCI 传输层负责特性协商、建立队列和通知设备。
+//! 新式设备（virtio 1.0）的各组寄存器由厂商能力指向 BAR 中的内存；
+//! 旧式和过渡设备的寄存器位于 BAR0 的 I/O 端口，启用 MSI-X 之后设备配置向后移动 4 个字节。
+//!
+//! 两种设备都支持时优先使用新式接口。驱动按以下顺序初始化设备：
+//! [`Transport::negotiate`]、[`Transport::route_interrupt`]、[`Transport::setup_queue`]、[`Transport::driver_ok`]
+
+pub mod queue;
+
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::time::Duration;
+
+pub use queue::{Buffer, VirtQueue};
+use x86_64::{PhysAddr, VirtAddr};
+
+use crate::arch::port::{Port, PortValue};
+use crate::memory;
+use crate::pci::{self, Bar, Device, msi};
+use crate::time::Instant;
+
+/// virtio 设备的 PCI 厂商编号
+pub const VENDOR_ID: u16 = 0x1af4;
+/// 设备类型：网卡
+pub const DEVICE_NET: u16 = 1;
+/// 设备类型：块设备
+pub const DEVICE_BLOCK: u16 = 2;
+/// 设备类型：随机数发生器
+pub const DEVICE_ENTROPY: u16 = 4;
+
+/// 新式设备的 PCI 设备编号为这个值加上设备类型
+const MODERN_DEVICE_BASE: u16 = 0x1040;
+/// 过渡设备的 PCI 设备编号和对应的设备类型
+const TRANSITIONAL_DEVICES: [(u16, u16); 3] = [
+    (0x1000, DEVICE_NET),
+    (0x1001, DEVICE_BLOCK),
+    (0x1005, DEVICE_ENTROPY),
+];
+
+/// 特性：设备符合 virtio 1.0，新式接口必须协商
+pub const FEATURE_VERSION_1: u64 = 1 << 32;
+
+/// 设备状态：驱动发现了设备
+const STATUS_ACKNOWLEDGE: u8 = 1 << 0;
+/// 设备状态：驱动知道如何驱动设备
+const STATUS_DRIVER: u8 = 1 << 1;
+/// 设备状态：驱动已经准备好
+const STATUS_DRIVER_OK: u8 = 1 << 2;
+/// 设备状态：特性协商完成
+const STATUS_FEATURES_OK: u8 = 1 << 3;
+/// 设备状态：驱动放弃了设备
+const STATUS_FAILED: u8 = 1 << 7;
+
+/// 等待设备完成复位的最长时间
+const RESET_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// MSI-X 表项编号：不使用中断
+const NO_VECTOR: u16 = 0xffff;
+
+/// 厂商能力的编号，virtio 用它描述各组寄存器的位置
+const CAP_VENDOR: u8 = 0x09;
+/// 厂商能力：公共配置
+const CFG_COMMON: u8 = 1;
+/// 厂商能力：通知
+const CFG_NOTIFY: u8 = 2;
+/// 厂商能力：中断状态
+const CFG_ISR: u8 = 3;
+/// 厂商能力：设备配置
+const CFG_DEVICE: u8 = 4;
+
+/// 旧式寄存器：设备支持的特性
+const LEGACY_DEVICE_FEATURES: u16 = 0x00;
+/// 旧式寄存器：驱动接受的特性
+const LEGACY_DRIVER_FEATURES: u16 = 0x04;
+/// 旧式寄存器：所选队列的页帧号
+const LEGACY_QUEUE_ADDRESS: u16 = 0x08;
+/// 旧式寄存器：所选队列的大小
+const LEGACY_QUEUE_SIZE: u16 = 0x0c;
+/// 旧式寄存器：选择队列
+const LEGACY_QUEUE_SELECT: u16 = 0x0e;
+/// 旧式寄存器：通知队列
+const LEGACY_QUEUE_NOTIFY: u16 = 0x10;
+/// 旧式寄存器：设备状态
+const LEGACY_STATUS: u16 = 0x12;
+/// 旧式寄存器：中断状态，读取时清除
+const LEGACY_ISR: u16 = 0x13;
+/// 旧式寄存器：配置变化使用的 MSI-X 表项，仅在启用 MSI-X 时存在
+const LEGACY_CONFIG_VECTOR: u16 = 0x14;
+/// 旧式寄存器：所选队列使用的 MSI-X 表项，仅在启用 MSI-X 时存在
+const LEGACY_QUEUE_VECTOR: u16 = 0x16;
+/// 旧式寄存器：设备配置
+const LEGACY_CONFIG: u16 = 0x14;
+/// 旧式寄存器：启用 MSI-X 时的设备配置
+const LEGACY_CONFIG_MSIX: u16 = 0x18;
+
+/// 公共配置：选择读取的设备特性的 32 位
+const COMMON_DEVICE_FEATURE_SELECT: u64 = 0x00;
+/// 公共配置：设备支持的特性
+const COMMON_DEVICE_FEATURE: u64 = 0x04;
+/// 公共配置：选择写入的驱动特性的 32 位
+const COMMON_DRIVER_FEATURE_SELECT: u64 = 0x08;
+/// 公共配置：驱动接受的特性
+const COMMON_DRIVER_FEATURE: u64 = 0x0c;
+/// 公共配置：配置变化使用的 MSI-X 表项
+const COMMON_MSIX_CONFIG: u64 = 0x10;
+/// 公共配置：设备状态
+const COMMON_STATUS: u64 = 0x14;
+/// 公共配置：设备配置的版本，每次变化加一
+const COMMON_CONFIG_GENERATION: u64 = 0x15;
+/// 公共配置：选择队列
+const COMMON_QUEUE_SELECT: u64 = 0x16;
+/// 公共配置：所选队列的大小
+const COMMON_QUEUE_SIZE: u64 = 0x18;
+/// 公共配置：所选队列使用的 MSI-X 表项
+const COMMON_QUEUE_MSIX_VECTOR: u64 = 0x1a;
+/// 公共配置：启用所选队列
+const COMMON_QUEUE_ENABLE: u64 = 0x1c;
+/// 公共配置：所选队列的通知寄存器编号
+const COMMON_QUEUE_NOTIFY_OFF: u64 = 0x1e;
+/// 公共配置：所选队列描述符表的物理地址
+const COMMON_QUEUE_DESC: u64 = 0x20;
+/// 公共配置：所选队列可用环的物理地址
+const COMMON_QUEUE_DRIVER: u64 = 0x28;
+/// 公共配置：所选队列已用环的物理地址
+const COMMON_QUEUE_DEVICE: u64 = 0x30;
+
+/// PCI 设备的 virtio 设备类型，不是 virtio 设备时返回 `None`
+///
+/// # 参数
+///
+/// - `device`: PCI 设备
+pub fn device_type(device: &Device) -> Option<u16> {
+    kind_of(device.vendor_id, device.device_id)
+}
+
+/// 由 PCI 厂商编号和设备编号得出 virtio 设备类型
+fn kind_of(vendor_id: u16, device_id: u16) -> Option<u16> {
+    if vendor_id != VENDOR_ID {
+        return None;
+    }
+    if let Some(&(_, kind)) = TRANSITIONAL_DEVICES
+        .iter()
+        .find(|&&(id, _)| id == device_id)
+    {
+        return Some(kind);
+    }
+    // 0x1040~0x107f 为新式设备，厂商的其他设备编号不是 virtio 设备
+    (MODERN_DEVICE_BASE..MODERN_DEVICE_BASE + 0x40)
+        .contains(&device_id)
+        .then(|| device_id - MODERN_DEVICE_BASE)
+}
+
+/// 所有类型为 `kind` 的 virtio 设备
+///
+/// # 参数
+///
+/// - `kind`: 设备类型，例如 [`DEVICE_BLOCK`]
+pub fn find(kind: u16) -> impl Iterator<Item = &'static Device> {
+    pci::devices().filter(move |device| device_type(device) == Some(kind))
+}
+
+/// 寄存器的访问方式
+#[derive(Debug, Clone, Copy)]
+enum Access {
+    Legacy(u16), // 旧式接口，BAR0 的起始端口
+    Modern {
+        common: VirtAddr,         // 公共配置
+        notify: VirtAddr,         // 通知区域
+        multiplier: u32,          // 通知寄存器编号与偏移之间的倍数
+        isr: VirtAddr,            // 中断状态
+        config: Option<VirtAddr>, // 设备配置，随机数发生器等设备没有
+    },
+}
+
+/// 读取内存映射的寄存器
+fn mmio_read<T: Copy>(address: VirtAddr) -> T {
+    unsafe { address.as_ptr::<T>().read_volatile() }
+}
+
+/// 写入内存映射的寄存器
+fn mmio_write<T: Copy>(address: VirtAddr, value: T) {
+    unsafe { address.as_mut_ptr::<T>().write_volatile(value) }
+}
+
+/// 读取 I/O 端口
+fn port_read<T: PortValue>(port: u16) -> T {
+    unsafe { Port::new(port).read() }
+}
+
+/// 写入 I/O 端口
+fn port_write<T: PortValue>(port: u16, value: T) {
+    unsafe { Port::new(port).write(value) }
+}
+
+/// 映射厂商能力指向的一组新式寄存器
+///
+/// # 返回
+///
+/// 寄存器的虚拟地址和能力的偏移，设备没有这组寄存器或它不在内存 BAR 中时返回 `None`
+fn map_capability(device: &Device, kind: u8) -> Option<(VirtAddr, u16)> {
+    let capability = device
+        .capabilities()
+        .filter(|capability| capability.id == CAP_VENDOR)
+        .find(|capability| device.address.read_u8(capability.offset + 3) == kind)?;
+    let offset = capability.offset;
+    let bar = device.address.read_u8(offset + 4);
+    let Some(Bar::Memory { address, .. }) = device.bar(usize::from(bar)) else {
+        return None;
+    };
+    let start = u64::from(device.address.read_u32(offset + 8));
+    let length = u64::from(device.address.read_u32(offset + 12)).max(1);
+    let virt = memory::map_mmio(PhysAddr::new(address + start), length)?;
+    Some((virt, offset))
+}
+
+/// 设备在限定时间内没有完成复位
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ResetTimeout;
+
+/// virtio PCI 设备的传输层
+#[derive(Debug)]
+pub struct Transport {
+    device: &'static Device, // PCI 设备
+    access: Access,          // 寄存器的访问方式
+    msix: AtomicBool,        // 是否启用了 MSI-X
+}
+
+impl Transport {
+    /// 找到设备的寄存器，启用内存、I/O 访问和总线主控
+    ///
+    /// # 参数
+    ///
+    /// - `device`: virtio PCI 设备
+    ///
+    /// # 返回
+    ///
+    /// 设备既没有新式寄存器也不是过渡设备时返回 `None`
+    pub fn new(device: &'static Device) -> Option<Self> {
+        let modern = || {
+            let (common, _) = map_capability(device, CFG_COMMON)?;
+            let (notify, notify_cap) = map_capability(device, CFG_NOTIFY)?;
+            let (isr, _) = map_capability(device, CFG_ISR)?;
+            let config = map_capability(device, CFG_DEVICE).map(|(config, _)| config);
+            Some(Access::Modern {
+                common,
+                notify,
+                multiplier: device.address.read_u32(notify_cap + 16),
+                isr,
+                config,
+            })
+        };
+        let legacy = || match device.bar(0) {
+            Some(Bar::Io { port, .. }) if device.device_id < MODERN_DEVICE_BASE => {
+                Some(Access::Legacy(port))
+            }
+            _ => None,
+        };
+        let access = modern().or_else(legacy)?;
+        device.enable(pci::COMMAND_IO | pci::COMMAND_MEMORY | pci::COMMAND_BUS_MASTER);
+        Some(Self {
+            device,
+            access,
+            msix: AtomicBool::new(false),
+        })
+    }
+
+    /// PCI 设备
+    pub fn device(&self) -> &'static Device {
+        self.device
+    }
+
+    /// 是否使用新式接口
+    pub fn is_modern(&self) -> bool {
+        matches!(self.access, Access::Modern { .. })
+    }
+
+    /// 设备状态
+    fn status(&self) -> u8 {
+        match self.access {
+            Access::Legacy(port) => port_read(port + LEGACY_STATUS),
+            Access::Modern { common, .. } => mmio_read(common + COMMON_STATUS),
+        }
+    }
+
+    fn set_status(&self, status: u8) {
+        match self.access {
+            Access::Legacy(port) => port_write(port + LEGACY_STATUS, status),
+            Access::Modern { common, .. } => mmio_write(common + COMMON_STATUS, status),
+        }
+    }
+
+    /// 在设备状态中增加 `bits`
+    fn add_status(&self, bits: u8) {
+        self.set_status(self.status() | bits);
+    }
+
+    /// 复位设备，之后设备回到初始状态，所有队列都被停用
+    ///
+    /// # 返回
+    ///
+    /// 设备在 [`RESET_TIMEOUT`] 内没有完成复位时返回 [`ResetTimeout`]
+    pub fn reset(&self) -> Result<(), ResetTimeout> {
+        self.set_status(0);
+        // 新式设备在复位完成之后才读到 0
+        let deadline = Instant::now() + RESET_TIMEOUT;
+        while self.status() != 0 {
+            if Instant::now() >= deadline {
+                return Err(ResetTimeout);
+            }
+            core::hint::spin_loop();
+        }
+        Ok(())
+    }
+
+    /// 告诉设备驱动放弃了它
+    pub fn fail(&self) {
+        self.add_status(STATUS_FAILED);
+    }
+
+    /// 复位设备并协商特性
+    ///
+    /// # 参数
+    ///
+    /// - `wanted`: 驱动支持的设备特性，新式接口会自动加上 [`FEATURE_VERSION_1`]
+    ///
+    /// # 返回
+    ///
+    /// 协商好的特性。设备没有完成复位时返回 `None`，驱动应跳过它；
+    /// 设备不接受时也返回 `None`，此时设备已标记为失败
+    pub fn negotiate(&self, wanted: u64) -> Option<u64> {
+        if self.reset().is_err() {
+            log::warn!("virtio at {}: reset timed out", self.device.address);
+            return None;
+        }
+        self.add_status(STATUS_ACKNOWLEDGE);
+        self.add_status(STATUS_DRIVER);
+        let accepted = match self.access {
+            Access::Legacy(port) => {
+                // 旧式接口只有低 32 位特性
+                let offered = u64::from(port_read::<u32>(port + LEGACY_DEVICE_FEATURES));
+                let accepted = offered & wanted & u64::from(u32::MAX);
+                port_write(port + LEGACY_DRIVER_FEATURES, accepted as u32);
+                return Some(accepted);
+            }
+            Access::Modern { common, .. } => {
+                let mut offered = 0;
+                for half in 0..2 {
+                    mmio_write(common + COMMON_DEVICE_FEATURE_SELECT, half as u32);
+                    offered |=
+                        u64::from(mmio_read::<u32>(common + COMMON_DEVICE_FEATURE)) << (32 * half);
+                }
+                let accepted = offered & (wanted | FEATURE_VERSION_1);
+                for half in 0..2 {
+                    mmio_write(common + COMMON_DRIVER_FEATURE_SELECT, half as u32);
+                    mmio_write(
+                        common + COMMON_DRIVER_FEATURE,
+                        (accepted >> (32 * half)) as u32,
+                    );
+                }
+                accepted
+            }
+        };
+        if accepted & FEATURE_VERSION_1 == 0 {
+            self.fail();
+            return None;
+        }
+        self.add_status(STATUS_FEATURES_OK);
+        if self.status() & STATUS_FEATURES_OK == 0 {
+            self.fail();
+            return None;
+        }
+        Some(accepted)
+    }
+
+    /// 为设备分配中断向量并登记处理函数，需要在特性协商之后、建立队列之前调用
+    ///
+    /// 使用 MSI-X 时所有队列共用第 0 个表项，配置变化不产生中断
+    ///
+    /// # 参数
+    ///
+    /// - `handler`: 中断到达时调用的函数，使用 INTx 时需要调用 [`isr`](Self::isr) 清除中断
+    ///
+    /// # 返回
+    ///
+    /// 分配的向量，中断不可用时返回 `None`
+    pub fn route_interrupt(&self, handler: fn()) -> Option<u8> {
+        let vector = msi::route(self.device, handler)?;
+        if msi::is_msix_enabled(self.device) {
+            self.msix.store(true, Ordering::Release);
+            match self.access {
+                Access::Legacy(port) => port_write(port + LEGACY_CONFIG_VECTOR, NO_VECTOR),
+                Access::Modern { common, .. } => mmio_write(common + COMMON_MSIX_CONFIG, NO_VECTOR),
+            }
+        }
+        Some(vector)
+    }
+
+    /// 是否使用 MSI-X，此时不需要读取中断状态
+    pub fn uses_msix(&self) -> bool {
+        self.msix.load(Ordering::Acquire)
+    }
+
+    /// 读取并清除中断状态，第 0 位表示队列有新的已用项，第 1 位表示设备配置变化
+    pub fn isr(&self) -> u8 {
+        match self.access {
+            Access::Legacy(port) => port_read(port + LEGACY_ISR),
+            Access::Modern { isr, .. } => mmio_read(isr),
+        }
+    }
+
+    /// 建立并启用一个队列
+    ///
+    /// # 参数
+    ///
+    /// - `index`: 队列编号
+    /// - `max_size`: 驱动希望的最大队列大小，只对新式接口有效，旧式设备的队列大小固定
+    ///
+    /// # 返回
+    ///
+    /// 设备没有这个队列、内存不足或队列的地址超出旧式接口能表示的范围时返回 `None`
+    pub fn setup_queue(&self, index: u16, max_size: u16) -> Option<VirtQueue> {
+        let vector = if self.uses_msix() { 0 } else { NO_VECTOR };
+        match self.access {
+            Access::Legacy(port) => {
+                port_write(port + LEGACY_QUEUE_SELECT, index);
+                let size: u16 = port_read(port + LEGACY_QUEUE_SIZE);
+                if size == 0 {
+                    return None;
+                }
+                let queue = VirtQueue::new(index, size)?;
+                // 旧式接口用 32 位的页帧号给出队列的地址
+                let frame = u32::try_from(queue.descriptors_phys().as_u64() >> 12).ok()?;
+                if self.uses_msix() {
+                    port_write(port + LEGACY_QUEUE_VECTOR, vector);
+                }
+                port_write(port + LEGACY_QUEUE_ADDRESS, frame);
+                Some(queue)
+            }
+            Access::Modern {
+                common, multiplier, ..
+            } => {
+                mmio_write(common + COMMON_QUEUE_SELECT, index);
+                let device_size: u16 = mmio_read(common + COMMON_QUEUE_SIZE);
+                let size = device_size.min(max_size);
+                if size == 0 {
+                    return None;
+                }
+                // 分离式队列的大小必须是 2 的幂
+                let size = 1 << (u16::BITS - 1 - size.leading_zeros());
+                let mut queue = VirtQueue::new(index, size)?;
+                mmio_write(common + COMMON_QUEUE_SIZE, size);
+                for (register, address) in [
+                    (COMMON_QUEUE_DESC, queue.descriptors_phys()),
+                    (COMMON_QUEUE_DRIVER, queue.avail_phys()),
+                    (COMMON_QUEUE_DEVICE, queue.used_phys()),
+                ] {
+                    mmio_write(common + register, address.as_u64() as u32);
+                    mmio_write(common + register + 4, (address.as_u64() >> 32) as u32);
+                }
+                mmio_write(common + COMMON_QUEUE_MSIX_VECTOR, vector);
+                let notify: u16 = mmio_read(common + COMMON_QUEUE_NOTIFY_OFF);
+                queue.set_notify_offset(u64::from(notify) * u64::from(multiplier));
+                mmio_write(common + COMMON_QUEUE_ENABLE, 1u16);
+                Some(queue)
+            }
+        }
+    }
+
+    /// 通知设备队列中有新的可用项
+    ///
+    /// # 参数
+    ///
+    /// - `queue`: 由 [`setup_queue`](Self::setup_queue) 建立的队列
+    pub fn notify(&self, queue: &VirtQueue) {
+        match self.access {
+            Access::Legacy(port) => port_write(port + LEGACY_QUEUE_NOTIFY, queue.index()),
+            Access::Modern { notify, .. } => {
+                mmio_write(notify + queue.notify_offset(), queue.index())
+            }
+        }
+    }
+
+    /// 告诉设备驱动已经准备好，此后设备开始处理队列
+    pub fn driver_ok(&self) {
+        self.add_status(STATUS_DRIVER_OK);
+    }
+
+    /// 读取设备配置中 `offset` 处的值，新式设备没有设备配置时读到 0
+    fn read_config<T: PortValue + Default>(&self, offset: u16) -> T {
+        match self.access {
+            Access::Legacy(port) => {
+                let config = if self.uses_msix() {
+                    LEGACY_CONFIG_MSIX
+                } else {
+                    LEGACY_CONFIG
+                };
+                port_read(port + config + offset)
+            }
+            Access::Modern { config, .. } => config
+                .map(|config| mmio_read(config + u64::from(offset)))
+                .unwrap_or_default(),
+        }
+    }
+
+    /// 读取设备配置中的一个字节
+    pub fn config_u8(&self, offset: u16) -> u8 {
+        self.read_config(offset)
+    }
+
+    /// 读取设备配置中的一个 16 位值
+    pub fn config_u16(&self, offset: u16) -> u16 {
+        self.read_config(offset)
+    }
+
+    /// 读取设备配置中的一个 32 位值
+    pub fn config_u32(&self, offset: u16) -> u32 {
+        self.read_config(offset)
+    }
+
+    /// 读取设备配置中的一个 64 位值，分两次读取，新式设备在读取期间配置变化时重新读取
+    pub fn config_u64(&self, offset: u16) -> u64 {
+        loop {
+            let generation = self.config_generation();
+            let low = u64::from(self.config_u32(offset));
+            let high = u64::from(self.config_u32(offset + 4));
+            if self.config_generation() == generation {
+                return low | (high << 32);
+            }
+        }
+    }
+
+    /// 设备配置的版本，旧式接口没有版本，总是返回 0
+    fn config_generation(&self) -> u8 {
+        match self.access {
+            Access::Legacy(_) => 0,
+            Access::Modern { common, .. } => mmio_read(common + COMMON_CONFIG_GENERATION),
+        }
+    }
+}
+
+#[test_case]
+fn test_device_type() {
+    assert_eq!(kind_of(VENDOR_ID, 0x1001), Some(DEVICE_BLOCK));
+    assert_eq!(kind_of(VENDOR_ID, 0x1042), Some(DEVICE_BLOCK));
+    assert_eq!(kind_of(VENDOR_ID, 0x1044), Some(DEVICE_ENTROPY));
+    assert_eq!(kind_of(VENDOR_ID, 0x1110), None);
+    assert_eq!(kind_of(0x8086, 0x1000), None);
+}
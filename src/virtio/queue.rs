@@ -0,0 +1,260 @@
+//! 本模块实现了 virtio 的分离式虚拟队列
+//!
+//! 一个虚拟队列由三部分组成：描述符表中的每一项描述一段缓冲区，可以用 `next` 串成链；
+//! 驱动把链头放入可用环，设备处理完之后把链头和写入的字节数放入已用环。
+//!
+//! 三部分按旧式设备要求的布局放在同一块 DMA 内存中，已用环从页边界开始，新式设备也可以使用这种布局。
+//! 空闲的描述符同样用 `next` 串成一条链，回收时把整条链接回空闲链的头部
+
+use core::sync::atomic::{Ordering, fence};
+
+use x86_64::PhysAddr;
+
+use crate::memory::dma::DmaRegion;
+
+/// 描述符：链中还有下一项
+const DESC_F_NEXT: u16 = 1 << 0;
+/// 描述符：缓冲区由设备写入
+const DESC_F_WRITE: u16 = 1 << 1;
+/// 已用环：设备不需要通知
+const USED_F_NO_NOTIFY: u16 = 1 << 0;
+
+/// 描述符的大小
+const DESCRIPTOR_SIZE: usize = 16;
+/// 已用环中每一项的大小
+const USED_ELEM_SIZE: usize = 8;
+/// 旧式设备要求的已用环的对齐
+const USED_ALIGN: usize = 4096;
+
+/// 描述符表中的一项
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct Descriptor {
+    addr: u64,  // 缓冲区的物理地址
+    len: u32,   // 缓冲区的字节数
+    flags: u16, // `DESC_F_*`
+    next: u16,  // 链中下一项的编号
+}
+
+/// 交给设备的一段缓冲区
+#[derive(Debug, Clone, Copy)]
+pub struct Buffer {
+    pub addr: PhysAddr, // 物理地址
+    pub len: u32,       // 字节数
+    pub writable: bool, // 是否由设备写入，否则由设备读取
+}
+
+/// 大小为 `size` 的队列中可用环和已用环的偏移，以及占用的总字节数
+///
+/// # 参数
+///
+/// - `size`: 队列大小，即描述符的个数
+fn layout(size: u16) -> (usize, usize, usize) {
+    let size = usize::from(size);
+    let avail = size * DESCRIPTOR_SIZE;
+    // 可用环：flags、idx、ring[size]、used_event
+    let used = (avail + 6 + 2 * size).next_multiple_of(USED_ALIGN);
+    // 已用环：flags、idx、ring[size]、avail_event
+    (avail, used, used + 6 + USED_ELEM_SIZE * size)
+}
+
+/// 分离式虚拟队列
+#[derive(Debug)]
+pub struct VirtQueue {
+    index: u16,        // 队列编号
+    size: u16,         // 描述符的个数
+    memory: DmaRegion, // 描述符表、可用环和已用环
+    avail: usize,      // 可用环的偏移
+    used: usize,       // 已用环的偏移
+    free_head: u16,    // 空闲链的头
+    free_count: u16,   // 空闲描述符的个数
+    next_avail: u16,   // 下一次放入可用环时的 idx
+    last_used: u16,    // 已经处理到的已用环 idx
+    notify: u64,       // 通知寄存器相对于通知区域的偏移，只用于新式设备
+}
+
+impl VirtQueue {
+    /// 分配队列的内存并把所有描述符串成空闲链
+    ///
+    /// # 参数
+    ///
+    /// - `index`: 队列编号
+    /// - `size`: 描述符的个数，不为 0
+    ///
+    /// # 返回
+    ///
+    /// 内存不足时返回 `None`
+    pub fn new(index: u16, size: u16) -> Option<Self> {
+        let (avail, used, total) = layout(size);
+        let queue = Self {
+            index,
+            size,
+            memory: DmaRegion::new(total)?,
+            avail,
+            used,
+            free_head: 0,
+            free_count: size,
+            next_avail: 0,
+            last_used: 0,
+            notify: 0,
+        };
+        for id in 0..size {
+            let mut descriptor = queue.descriptor(id);
+            descriptor.next = id.wrapping_add(1);
+            queue.set_descriptor(id, descriptor);
+        }
+        Some(queue)
+    }
+
+    /// 队列编号
+    pub fn index(&self) -> u16 {
+        self.index
+    }
+
+    /// 描述符的个数
+    pub fn size(&self) -> u16 {
+        self.size
+    }
+
+    /// 空闲描述符的个数
+    pub fn free_count(&self) -> u16 {
+        self.free_count
+    }
+
+    /// 描述符表的物理地址
+    pub fn descriptors_phys(&self) -> PhysAddr {
+        self.memory.phys()
+    }
+
+    /// 可用环的物理地址
+    pub fn avail_phys(&self) -> PhysAddr {
+        self.memory.phys() + self.avail as u64
+    }
+
+    /// 已用环的物理地址
+    pub fn used_phys(&self) -> PhysAddr {
+        self.memory.phys() + self.used as u64
+    }
+
+    /// 通知寄存器相对于通知区域的偏移
+    pub(super) fn notify_offset(&self) -> u64 {
+        self.notify
+    }
+
+    /// 设置通知寄存器的偏移，由传输层在建立队列时调用
+    pub(super) fn set_notify_offset(&mut self, offset: u64) {
+        self.notify = offset;
+    }
+
+    fn descriptor(&self, id: u16) -> Descriptor {
+        let offset = usize::from(id) * DESCRIPTOR_SIZE;
+        unsafe { self.memory.ptr::<Descriptor>(offset).read_volatile() }
+    }
+
+    fn set_descriptor(&self, id: u16, descriptor: Descriptor) {
+        let offset = usize::from(id) * DESCRIPTOR_SIZE;
+        unsafe {
+            self.memory
+                .ptr::<Descriptor>(offset)
+                .write_volatile(descriptor)
+        }
+    }
+
+    fn read_u16(&self, offset: usize) -> u16 {
+        unsafe { self.memory.ptr::<u16>(offset).read_volatile() }
+    }
+
+    fn write_u16(&self, offset: usize, value: u16) {
+        unsafe { self.memory.ptr::<u16>(offset).write_volatile(value) }
+    }
+
+    /// 把一组缓冲区串成一条链放入可用环，之后需要通知设备
+    ///
+    /// # 参数
+    ///
+    /// - `buffers`: 缓冲区，设备读取的应在设备写入的之前
+    ///
+    /// # 返回
+    ///
+    /// 链头的编号，设备用它报告完成。缓冲区为空或空闲描述符不够时返回 `None`
+    pub fn add(&mut self, buffers: &[Buffer]) -> Option<u16> {
+        if buffers.is_empty() || buffers.len() > usize::from(self.free_count) {
+            return None;
+        }
+        let head = self.free_head;
+        for (index, buffer) in buffers.iter().enumerate() {
+            let id = self.free_head;
+            let mut descriptor = self.descriptor(id);
+            self.free_head = descriptor.next;
+            descriptor.addr = buffer.addr.as_u64();
+            descriptor.len = buffer.len;
+            descriptor.flags = if buffer.writable { DESC_F_WRITE } else { 0 };
+            // 空闲链中的下一项就是链中的下一项，`next` 保持不变
+            if index + 1 < buffers.len() {
+                descriptor.flags |= DESC_F_NEXT;
+            }
+            self.set_descriptor(id, descriptor);
+        }
+        self.free_count -= buffers.len() as u16;
+
+        let slot = self.avail + 4 + 2 * usize::from(self.next_avail % self.size);
+        self.write_u16(slot, head);
+        // 设备看到新的 idx 时链和环中的项必须已经写好
+        fence(Ordering::Release);
+        self.next_avail = self.next_avail.wrapping_add(1);
+        self.write_u16(self.avail + 2, self.next_avail);
+        Some(head)
+    }
+
+    /// 设备是否需要在放入新的链之后被通知
+    pub fn should_notify(&self) -> bool {
+        // 先让 idx 的写入对设备可见，再读取设备的标志
+        fence(Ordering::SeqCst);
+        self.read_u16(self.used) & USED_F_NO_NOTIFY == 0
+    }
+
+    /// 取出一条设备已经处理完的链，并把它的描述符放回空闲链
+    ///
+    /// # 返回
+    ///
+    /// 链头的编号和设备写入的字节数，没有处理完的链时返回 `None`
+    pub fn pop_used(&mut self) -> Option<(u16, u32)> {
+        if self.read_u16(self.used + 2) == self.last_used {
+            return None;
+        }
+        // 读到新的 idx 之后才能读取环中的项
+        fence(Ordering::Acquire);
+        let element = self.used + 4 + USED_ELEM_SIZE * usize::from(self.last_used % self.size);
+        let (head, len) = unsafe {
+            (
+                self.memory.ptr::<u32>(element).read_volatile() as u16,
+                self.memory.ptr::<u32>(element + 4).read_volatile(),
+            )
+        };
+        self.last_used = self.last_used.wrapping_add(1);
+
+        let mut tail = head;
+        let mut count = 1;
+        loop {
+            let descriptor = self.descriptor(tail);
+            if descriptor.flags & DESC_F_NEXT == 0 {
+                break;
+            }
+            tail = descriptor.next;
+            count += 1;
+        }
+        let mut descriptor = self.descriptor(tail);
+        descriptor.next = self.free_head;
+        self.set_descriptor(tail, descriptor);
+        self.free_head = head;
+        self.free_count += count;
+        Some((head, len))
+    }
+}
+
+#[test_case]
+fn test_queue_layout() {
+    // 256 项的队列：描述符表 4096 字节，可用环 518 字节，已用环从下一页开始
+    assert_eq!(layout(256), (4096, 8192, 8192 + 6 + 8 * 256));
+    assert_eq!(layout(8), (128, 4096, 4096 + 6 + 64));
+}
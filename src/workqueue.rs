@@ -0,0 +1,139 @@
+//! 本模块实现了内核工作队列
+//!
+//! 中断处理函数只做最必要的工作，把耗时的部分作为工作项放进队列，
+//! 由一组内核工作线程在开启中断的线程上下文中执行。
+//!
+//! 静态的 [`Work`] 入队时不分配内存，可以在中断处理函数中使用，同一个工作项在执行之前只会排队一次；
+//! [`submit`] 提交的闭包需要在堆上分配，只能在线程上下文中调用
+
+use alloc::boxed::Box;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use conquer_once::spin::OnceCell;
+use crossbeam_queue::ArrayQueue;
+
+use crate::thread::{self, WaitQueue};
+
+/// 工作线程的数量
+pub const WORKER_COUNT: usize = 2;
+
+/// 队列的容量
+const QUEUE_SIZE: usize = 256;
+
+/// 静态的工作项
+pub struct Work {
+    func: fn(),          // 要执行的函数
+    pending: AtomicBool, // 是否已在队列中等待执行
+}
+
+impl Work {
+    /// 创建一个执行 `func` 的工作项
+    pub const fn new(func: fn()) -> Self {
+        Self {
+            func,
+            pending: AtomicBool::new(false),
+        }
+    }
+
+    /// 把工作项放进队列，不分配内存，可以在中断处理函数中调用
+    ///
+    /// # 返回
+    ///
+    /// 工作项已在队列中、队列已满或尚未调用 [`init`] 时返回 `false`
+    pub fn schedule(&'static self) -> bool {
+        if self.pending.swap(true, Ordering::AcqRel) {
+            return false;
+        }
+        if push(Item::Static(self)) {
+            true
+        } else {
+            self.pending.store(false, Ordering::Release);
+            false
+        }
+    }
+
+    /// 是否已在队列中等待执行
+    pub fn is_pending(&self) -> bool {
+        self.pending.load(Ordering::Acquire)
+    }
+}
+
+/// 队列中的工作
+enum Item {
+    Static(&'static Work),           // 静态的工作项
+    Boxed(Box<dyn FnOnce() + Send>), // 提交的闭包
+}
+
+/// 待执行的工作，调用 [`init`] 时创建
+static QUEUE: OnceCell<ArrayQueue<Item>> = OnceCell::uninit();
+
+/// 队列已满时丢弃的工作数，由工作线程记录到日志后清零
+static DROPPED: AtomicU64 = AtomicU64::new(0);
+
+/// 等待工作的工作线程
+static IDLE_WORKERS: WaitQueue = WaitQueue::new();
+
+/// 创建队列并启动工作线程，需要在 [`thread::init`] 之后调用
+pub fn init() {
+    QUEUE
+        .try_init_once(|| ArrayQueue::new(QUEUE_SIZE))
+        .expect("workqueue::init should only be called once");
+    for _ in 0..WORKER_COUNT {
+        thread::spawn(worker);
+    }
+}
+
+/// 提交一个闭包，由工作线程执行
+///
+/// 闭包在堆上分配，不能在中断处理函数中调用
+///
+/// # 返回
+///
+/// 队列已满或尚未调用 [`init`] 时返回 `false`
+pub fn submit(f: impl FnOnce() + Send + 'static) -> bool {
+    push(Item::Boxed(Box::new(f)))
+}
+
+/// 把工作放进队列并唤醒一个工作线程
+fn push(item: Item) -> bool {
+    let Ok(queue) = QUEUE.try_get() else {
+        return false;
+    };
+    if queue.push(item).is_err() {
+        // 可能在中断处理函数中，不在这里打印日志
+        DROPPED.fetch_add(1, Ordering::Relaxed);
+        return false;
+    }
+    IDLE_WORKERS.wake_one();
+    true
+}
+
+/// 工作线程，依次执行队列中的工作，没有工作时挂起
+fn worker() {
+    use x86_64::instructions::interrupts;
+
+    let queue = QUEUE.try_get().expect("workqueue not initialized");
+    loop {
+        // 检查队列和挂起之间禁用中断，入队的唤醒不会丢失
+        let item = interrupts::without_interrupts(|| {
+            loop {
+                if let Some(item) = queue.pop() {
+                    return item;
+                }
+                IDLE_WORKERS.block_current();
+            }
+        });
+        let dropped = DROPPED.swap(0, Ordering::Relaxed);
+        if dropped > 0 {
+            log::warn!("workqueue full; dropped {} work items", dropped);
+        }
+        match item {
+            Item::Static(work) => {
+                // 先清除标志，执行期间再次调度的工作项会重新排队
+                work.pending.store(false, Ordering::Release);
+                (work.func)();
+            }
+            Item::Boxed(f) => f(),
+        }
+    }
+}
@@ -0,0 +1,558 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(ricky_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use core::arch::global_asm;
+use core::panic::PanicInfo;
+
+use bootloader::{BootInfo, entry_point};
+use ricky_os::elf::{self, EM_X86_64, ET_EXEC, PF_R, PF_X, PT_LOAD};
+use ricky_os::memory::{self, BootInfoFrameAllocator};
+use ricky_os::syscall::ECHILD;
+use ricky_os::{allocator, process, thread, user};
+use x86_64::VirtAddr;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    ricky_os::init();
+
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    memory::install(mapper, frame_allocator);
+    thread::init();
+
+    test_main();
+
+    ricky_os::hlt_loop()
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    ricky_os::test_panic_handler(info)
+}
+
+// 以 argc 加上 argv[1] 的第一个字节作为状态码退出
+global_asm!(
+    ".section .rodata.user_program, \"a\"",
+    ".global user_program_start",
+    ".global user_program_end",
+    "user_program_start:",
+    "mov rdi, [rsp]",
+    "mov rsi, [rsp + 16]",
+    "movzx eax, byte ptr [rsi]",
+    "add rdi, rax",
+    "mov eax, 1",
+    "syscall",
+    "ud2",
+    "user_program_end:",
+    ".previous",
+);
+
+// fork 之后子进程修改栈上的值并以 r12 + 2 退出，父进程等待子进程，
+// 以子进程的退出码加上自己栈上的值退出；父进程得到的子进程编号不一致时以 255 退出
+global_asm!(
+    ".section .rodata.user_program, \"a\"",
+    ".global fork_program_start",
+    ".global fork_program_end",
+    "fork_program_start:",
+    "mov r12, 5",
+    "sub rsp, 16",
+    "mov qword ptr [rsp + 8], 40",
+    "mov eax, 4",
+    "syscall",
+    "test rax, rax",
+    "jnz 2f",
+    "mov qword ptr [rsp + 8], 1",
+    "lea rdi, [r12 + 2]",
+    "mov eax, 1",
+    "syscall",
+    "ud2",
+    "2:",
+    "mov rbx, rax",
+    "mov rdi, rax",
+    "mov rsi, rsp",
+    "mov eax, 3",
+    "syscall",
+    "cmp rax, rbx",
+    "jne 3f",
+    "mov rdi, [rsp]",
+    "add rdi, [rsp + 8]",
+    "mov eax, 1",
+    "syscall",
+    "ud2",
+    "3:",
+    "mov edi, 255",
+    "mov eax, 1",
+    "syscall",
+    "ud2",
+    "fork_program_end:",
+    ".previous",
+);
+
+// 子进程关闭读端，向管道写入 "pipe" 后以写入的字节数退出；父进程关闭写端，读到 EOF 为止，
+// 以读到的字节数乘 10 加上子进程的退出码退出；出错或数据不一致时以 255 退出
+global_asm!(
+    ".section .rodata.user_program, \"a\"",
+    ".global pipe_program_start",
+    ".global pipe_program_end",
+    "pipe_program_start:",
+    "sub rsp, 64",
+    "mov rdi, rsp",
+    "mov eax, 6",
+    "syscall",
+    "test rax, rax",
+    "jnz 9f",
+    "mov eax, 4",
+    "syscall",
+    "test rax, rax",
+    "jnz 2f",
+    "mov edi, [rsp]",
+    "mov eax, 7",
+    "syscall",
+    "mov edi, [rsp + 4]",
+    "lea rsi, [rip + 5f]",
+    "mov edx, 4",
+    "mov eax, 0",
+    "syscall",
+    "mov rdi, rax",
+    "mov eax, 1",
+    "syscall",
+    "ud2",
+    "2:",
+    "mov rbx, rax",
+    "mov edi, [rsp + 4]",
+    "mov eax, 7",
+    "syscall",
+    "xor r12, r12",
+    "3:",
+    "mov edi, [rsp]",
+    "lea rsi, [rsp + 16]",
+    "mov edx, 32",
+    "mov eax, 5",
+    "syscall",
+    "test rax, rax",
+    "jz 4f",
+    "js 9f",
+    "add r12, rax",
+    "jmp 3b",
+    "4:",
+    "cmp dword ptr [rsp + 16], 0x65706970",
+    "jne 9f",
+    "mov rdi, rbx",
+    "lea rsi, [rsp + 8]",
+    "mov eax, 3",
+    "syscall",
+    "imul rdi, r12, 10",
+    "add rdi, [rsp + 8]",
+    "mov eax, 1",
+    "syscall",
+    "ud2",
+    "9:",
+    "mov edi, 255",
+    "mov eax, 1",
+    "syscall",
+    "ud2",
+    "5:",
+    ".ascii \"pipe\"",
+    "pipe_program_end:",
+    ".previous",
+);
+
+// 打开共享内存段 "buf" 后 fork，子进程映射段并写入 42；父进程等待子进程后把段映射到另一个地址，
+// 读出的值作为退出码，退出前解除映射并删除段；出错时以 255 退出
+global_asm!(
+    ".section .rodata.user_program, \"a\"",
+    ".global shm_program_start",
+    ".global shm_program_end",
+    "shm_program_start:",
+    "mov r13, 0x600200000000",
+    "lea rdi, [rip + 5f]",
+    "mov esi, 3",
+    "mov edx, 4096",
+    "mov eax, 9",
+    "syscall",
+    "test rax, rax",
+    "js 9f",
+    "mov r12, rax",
+    "mov eax, 4",
+    "syscall",
+    "test rax, rax",
+    "jnz 2f",
+    "mov rdi, r12",
+    "mov rsi, r13",
+    "mov eax, 10",
+    "syscall",
+    "test rax, rax",
+    "js 9f",
+    "mov qword ptr [r13], 42",
+    "xor edi, edi",
+    "mov eax, 1",
+    "syscall",
+    "ud2",
+    "2:",
+    "mov rdi, rax",
+    "xor esi, esi",
+    "mov eax, 3",
+    "syscall",
+    "add r13, 0x10000",
+    "mov rdi, r12",
+    "mov rsi, r13",
+    "mov eax, 10",
+    "syscall",
+    "test rax, rax",
+    "js 9f",
+    "mov rbx, [r13]",
+    "mov rdi, r13",
+    "mov eax, 11",
+    "syscall",
+    "test rax, rax",
+    "jnz 9f",
+    "lea rdi, [rip + 5f]",
+    "mov esi, 3",
+    "mov eax, 12",
+    "syscall",
+    "test rax, rax",
+    "jnz 9f",
+    "mov rdi, rbx",
+    "mov eax, 1",
+    "syscall",
+    "ud2",
+    "9:",
+    "mov edi, 255",
+    "mov eax, 1",
+    "syscall",
+    "ud2",
+    "5:",
+    ".ascii \"buf\"",
+    "shm_program_end:",
+    ".previous",
+);
+
+// 父进程映射共享内存段 "futex" 后 fork。子进程先确认值不符时立即返回 EAGAIN，
+// 再在共享的字为 0 时等待，被唤醒后以字的值加 10 退出；父进程睡眠片刻后写入 1 并唤醒子进程，
+// 以子进程的退出码退出；出错时以 255 退出
+global_asm!(
+    ".section .rodata.user_program, \"a\"",
+    ".global futex_program_start",
+    ".global futex_program_end",
+    "futex_program_start:",
+    "mov r13, 0x600200000000",
+    "lea rdi, [rip + 5f]",
+    "mov esi, 5",
+    "mov edx, 4096",
+    "mov eax, 9",
+    "syscall",
+    "test rax, rax",
+    "js 9f",
+    "mov rdi, rax",
+    "mov rsi, r13",
+    "mov eax, 10",
+    "syscall",
+    "test rax, rax",
+    "js 9f",
+    "lea rdi, [rip + 5f]",
+    "mov esi, 5",
+    "mov eax, 12",
+    "syscall",
+    "mov eax, 4",
+    "syscall",
+    "test rax, rax",
+    "jnz 2f",
+    "mov rdi, r13",
+    "mov esi, 5",
+    "mov eax, 13",
+    "syscall",
+    "cmp rax, -11",
+    "jne 9f",
+    "3:",
+    "cmp dword ptr [r13], 0",
+    "jne 4f",
+    "mov rdi, r13",
+    "xor esi, esi",
+    "mov eax, 13",
+    "syscall",
+    "jmp 3b",
+    "4:",
+    "mov edi, [r13]",
+    "add edi, 10",
+    "mov eax, 1",
+    "syscall",
+    "ud2",
+    "2:",
+    "mov r12, rax",
+    "mov edi, 20",
+    "mov eax, 2",
+    "syscall",
+    "mov dword ptr [r13], 1",
+    "mov rdi, r13",
+    "mov esi, 1",
+    "mov eax, 14",
+    "syscall",
+    "test rax, rax",
+    "js 9f",
+    "sub rsp, 16",
+    "mov rdi, r12",
+    "mov rsi, rsp",
+    "mov eax, 3",
+    "syscall",
+    "mov rdi, [rsp]",
+    "mov eax, 1",
+    "syscall",
+    "ud2",
+    "9:",
+    "mov edi, 255",
+    "mov eax, 1",
+    "syscall",
+    "ud2",
+    "5:",
+    ".ascii \"futex\"",
+    "futex_program_end:",
+    ".previous",
+);
+
+// 为 SIGUSR1 注册处理函数后向自己发送 SIGUSR1，处理函数把信号编号写入栈上的变量；
+// 然后 fork 出一直在用户态循环的子进程，用 SIGTERM 结束它。
+// 以子进程的退出码（128 + 15）加上处理函数记录的信号编号退出；出错时以 255 退出
+global_asm!(
+    ".section .rodata.user_program, \"a\"",
+    ".global signal_program_start",
+    ".global signal_program_end",
+    "signal_program_start:",
+    "sub rsp, 16",
+    "mov r15, rsp",
+    "mov qword ptr [r15], 0",
+    "mov edi, 10",
+    "lea rsi, [rip + 6f]",
+    "lea rdx, [rip + 7f]",
+    "mov eax, 16",
+    "syscall",
+    "test rax, rax",
+    "jnz 9f",
+    "mov eax, 18",
+    "syscall",
+    "mov rdi, rax",
+    "mov esi, 10",
+    "mov eax, 15",
+    "syscall",
+    "test rax, rax",
+    "jnz 9f",
+    "mov rbx, [r15]",
+    "mov eax, 4",
+    "syscall",
+    "test rax, rax",
+    "jnz 2f",
+    "1:",
+    "jmp 1b",
+    "2:",
+    "mov r12, rax",
+    "mov rdi, rax",
+    "mov esi, 15",
+    "mov eax, 15",
+    "syscall",
+    "test rax, rax",
+    "jnz 9f",
+    "mov rdi, r12",
+    "lea rsi, [r15 + 8]",
+    "mov eax, 3",
+    "syscall",
+    "mov rdi, [r15 + 8]",
+    "add rdi, rbx",
+    "mov eax, 1",
+    "syscall",
+    "ud2",
+    "6:",
+    "mov [r15], rdi",
+    "ret",
+    "7:",
+    "mov eax, 17",
+    "syscall",
+    "ud2",
+    "9:",
+    "mov edi, 255",
+    "mov eax, 1",
+    "syscall",
+    "ud2",
+    "signal_program_end:",
+    ".previous",
+);
+
+unsafe extern "C" {
+    static user_program_start: u8;
+    static user_program_end: u8;
+    static fork_program_start: u8;
+    static fork_program_end: u8;
+    static pipe_program_start: u8;
+    static pipe_program_end: u8;
+    static shm_program_start: u8;
+    static shm_program_end: u8;
+    static futex_program_start: u8;
+    static futex_program_end: u8;
+    static signal_program_start: u8;
+    static signal_program_end: u8;
+}
+
+/// 取出由链接器符号界定的一段代码
+fn code_between(start: *const u8, end: *const u8) -> &'static [u8] {
+    unsafe { core::slice::from_raw_parts(start, end as usize - start as usize) }
+}
+
+/// 参数测试使用的程序
+fn build_image() -> Vec<u8> {
+    let code = code_between(&raw const user_program_start, &raw const user_program_end);
+    build_image_with(code)
+}
+
+/// 构造只有一个可执行段的 ELF 文件，文件头和程序头也映射在段中
+fn build_image_with(code: &[u8]) -> Vec<u8> {
+    const HEADERS_SIZE: u64 = 64 + 56;
+
+    let base = user::USER_CODE_START;
+    let size = HEADERS_SIZE + code.len() as u64;
+
+    let mut image = Vec::new();
+    image.extend_from_slice(&[0x7f, b'E', b'L', b'F', 2, 1, 1, 0]);
+    image.resize(16, 0);
+    image.extend_from_slice(&ET_EXEC.to_le_bytes());
+    image.extend_from_slice(&EM_X86_64.to_le_bytes());
+    image.extend_from_slice(&1u32.to_le_bytes());
+    image.extend_from_slice(&(base + HEADERS_SIZE).to_le_bytes()); // e_entry
+    image.extend_from_slice(&64u64.to_le_bytes()); // e_phoff
+    image.extend_from_slice(&0u64.to_le_bytes()); // e_shoff
+    image.extend_from_slice(&0u32.to_le_bytes()); // e_flags
+    image.extend_from_slice(&64u16.to_le_bytes()); // e_ehsize
+    image.extend_from_slice(&56u16.to_le_bytes()); // e_phentsize
+    image.extend_from_slice(&1u16.to_le_bytes()); // e_phnum
+    image.extend_from_slice(&[0; 6]); // e_shentsize, e_shnum, e_shstrndx
+
+    image.extend_from_slice(&PT_LOAD.to_le_bytes());
+    image.extend_from_slice(&(PF_R | PF_X).to_le_bytes());
+    image.extend_from_slice(&0u64.to_le_bytes()); // p_offset
+    image.extend_from_slice(&base.to_le_bytes()); // p_vaddr
+    image.extend_from_slice(&base.to_le_bytes()); // p_paddr
+    image.extend_from_slice(&size.to_le_bytes()); // p_filesz
+    image.extend_from_slice(&size.to_le_bytes()); // p_memsz
+    image.extend_from_slice(&4096u64.to_le_bytes()); // p_align
+
+    image.extend_from_slice(code);
+    image
+}
+
+#[test_case]
+fn parses_built_image() {
+    let image = build_image();
+    let elf = elf::ElfFile::parse(&image).expect("failed to parse image");
+    assert_eq!(elf.entry().as_u64(), user::USER_CODE_START + 64 + 56);
+    assert_eq!(
+        elf.program_headers().filter(|h| h.kind == PT_LOAD).count(),
+        1
+    );
+}
+
+#[test_case]
+fn process_runs_with_arguments() {
+    let image = build_image();
+    let pid = process::spawn(&image, &["prog", "A"]).expect("failed to spawn process");
+    // argc 为 2，'A' 为 65
+    assert_eq!(process::wait(Some(pid)), Ok((pid, 67)));
+    // 僵尸进程已被回收
+    assert!(process::with_process(pid, |_| ()).is_none());
+    assert_eq!(process::wait(Some(pid)), Err(ECHILD));
+}
+
+#[test_case]
+fn exited_process_releases_frames() {
+    let image = build_image();
+    let allocated = || memory::with_kernel_memory(|_, frames| frames.allocated_frames()).unwrap();
+
+    // 第一次运行会为线程栈分配新的槽位，之后的进程复用它
+    let pid = process::spawn(&image, &["prog", "A"]).unwrap();
+    process::wait(Some(pid)).unwrap();
+    thread::yield_now();
+    let before = allocated();
+
+    let pid = process::spawn(&image, &["prog", "A"]).unwrap();
+    process::wait(Some(pid)).unwrap();
+    thread::yield_now();
+    assert_eq!(allocated(), before);
+}
+
+#[test_case]
+fn forked_child_runs_with_copied_memory() {
+    let code = code_between(&raw const fork_program_start, &raw const fork_program_end);
+    let image = build_image_with(code);
+    let pid = process::spawn(&image, &["fork"]).expect("failed to spawn process");
+    // 子进程的退出码为 7，父进程栈上的值不受子进程修改的影响，仍为 40
+    assert_eq!(process::wait(Some(pid)), Ok((pid, 47)));
+}
+
+#[test_case]
+fn forked_process_releases_shared_frames() {
+    let code = code_between(&raw const fork_program_start, &raw const fork_program_end);
+    let image = build_image_with(code);
+    let allocated = || memory::with_kernel_memory(|_, frames| frames.allocated_frames()).unwrap();
+
+    let pid = process::spawn(&image, &["fork"]).unwrap();
+    process::wait(Some(pid)).unwrap();
+    thread::yield_now();
+    let before = allocated();
+
+    // 父子进程共享的帧在两边都退出后全部释放
+    let pid = process::spawn(&image, &["fork"]).unwrap();
+    assert_eq!(process::wait(Some(pid)), Ok((pid, 47)));
+    thread::yield_now();
+    assert_eq!(allocated(), before);
+}
+
+#[test_case]
+fn pipe_carries_data_to_eof() {
+    let code = code_between(&raw const pipe_program_start, &raw const pipe_program_end);
+    let image = build_image_with(code);
+    let pid = process::spawn(&image, &["pipe"]).expect("failed to spawn process");
+    // 父进程读到 4 个字节后遇到 EOF，子进程写入 4 个字节
+    assert_eq!(process::wait(Some(pid)), Ok((pid, 44)));
+}
+
+#[test_case]
+fn shared_memory_is_visible_across_processes() {
+    let code = code_between(&raw const shm_program_start, &raw const shm_program_end);
+    let image = build_image_with(code);
+    let allocated = || memory::with_kernel_memory(|_, frames| frames.allocated_frames()).unwrap();
+
+    let pid = process::spawn(&image, &["shm"]).unwrap();
+    assert_eq!(process::wait(Some(pid)), Ok((pid, 42)));
+    thread::yield_now();
+    let before = allocated();
+
+    // 段被删除、映射都已解除或随进程释放后，段的帧全部回收
+    let pid = process::spawn(&image, &["shm"]).unwrap();
+    assert_eq!(process::wait(Some(pid)), Ok((pid, 42)));
+    thread::yield_now();
+    assert_eq!(allocated(), before);
+}
+
+#[test_case]
+fn futex_wakes_waiter_in_another_process() {
+    let code = code_between(&raw const futex_program_start, &raw const futex_program_end);
+    let image = build_image_with(code);
+    let pid = process::spawn(&image, &["futex"]).unwrap();
+    assert_eq!(process::wait(Some(pid)), Ok((pid, 11)));
+}
+
+#[test_case]
+fn signals_run_handlers_and_terminate() {
+    let code = code_between(
+        &raw const signal_program_start,
+        &raw const signal_program_end,
+    );
+    let image = build_image_with(code);
+    let pid = process::spawn(&image, &["signal"]).unwrap();
+    assert_eq!(process::wait(Some(pid)), Ok((pid, 153)));
+}
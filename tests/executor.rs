@@ -0,0 +1,189 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(ricky_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use core::future::Future;
+use core::panic::PanicInfo;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use core::task::{Context, Poll};
+use core::time::Duration;
+
+use bootloader::{BootInfo, entry_point};
+use ricky_os::allocator;
+use ricky_os::memory::{self, BootInfoFrameAllocator};
+use ricky_os::task::executor::Executor;
+use ricky_os::task::simple_executor::SimpleExecutor;
+use ricky_os::task::{Priority, Task};
+use ricky_os::time::{self, Elapsed};
+use x86_64::VirtAddr;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    ricky_os::init();
+
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+
+    test_main();
+
+    ricky_os::hlt_loop()
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    ricky_os::test_panic_handler(info)
+}
+
+/// 第一次轮询时唤醒自己并返回 `Pending`，之后返回 `Ready`
+struct YieldOnce {
+    yielded: bool,
+}
+
+impl Future for YieldOnce {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        if self.yielded {
+            return Poll::Ready(());
+        }
+        self.yielded = true;
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    }
+}
+
+/// 永远不会完成，也不会唤醒自己
+struct Never;
+
+impl Future for Never {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<()> {
+        Poll::Pending
+    }
+}
+
+#[test_case]
+fn simple_executor_runs_all_tasks() {
+    static COMPLETED: AtomicUsize = AtomicUsize::new(0);
+
+    let mut executor = SimpleExecutor::new();
+    for _ in 0..3 {
+        executor.spawn(Task::new(async {
+            YieldOnce { yielded: false }.await;
+            COMPLETED.fetch_add(1, Ordering::Relaxed);
+        }));
+    }
+    executor.run();
+    assert_eq!(COMPLETED.load(Ordering::Relaxed), 3);
+}
+
+#[test_case]
+fn executor_polls_woken_tasks() {
+    static COMPLETED: AtomicUsize = AtomicUsize::new(0);
+
+    let mut executor = Executor::new();
+    for _ in 0..3 {
+        executor.spawn(Task::new(async {
+            YieldOnce { yielded: false }.await;
+            COMPLETED.fetch_add(1, Ordering::Relaxed);
+        }));
+    }
+    executor.spawn(Task::new(Never));
+    // 没有被唤醒的任务不会再被轮询，因此这里会返回
+    executor.run_until_idle();
+    assert_eq!(COMPLETED.load(Ordering::Relaxed), 3);
+    assert_eq!(executor.task_count(), 1);
+}
+
+#[test_case]
+fn spawner_adds_tasks_while_running() {
+    static COMPLETED: AtomicUsize = AtomicUsize::new(0);
+
+    let mut executor = Executor::new();
+    let spawner = executor.spawner();
+    executor.spawn(Task::new(async move {
+        spawner.spawn(Task::new(async {
+            COMPLETED.fetch_add(1, Ordering::Relaxed);
+        }));
+        COMPLETED.fetch_add(1, Ordering::Relaxed);
+    }));
+    executor.run_until_idle();
+    assert_eq!(COMPLETED.load(Ordering::Relaxed), 2);
+    assert_eq!(executor.task_count(), 0);
+}
+
+/// 运行执行器直到所有任务完成，没有就绪任务时等待下一次中断
+fn run_to_completion(executor: &mut Executor) {
+    while executor.task_count() > 0 {
+        executor.run_until_idle();
+        if executor.task_count() > 0 {
+            x86_64::instructions::hlt();
+        }
+    }
+}
+
+#[test_case]
+fn sleep_waits_for_timer_ticks() {
+    let mut executor = Executor::new();
+    let start = time::ticks();
+    executor.spawn(Task::new(async move {
+        time::sleep(Duration::from_millis(100)).await;
+        assert!(time::ticks() >= start + time::duration_to_ticks(Duration::from_millis(100)));
+    }));
+    run_to_completion(&mut executor);
+}
+
+#[test_case]
+fn timeout_gives_up_on_pending_future() {
+    let mut executor = Executor::new();
+    executor.spawn(Task::new(async {
+        let result = time::timeout(Duration::from_millis(100), Never).await;
+        assert_eq!(result, Err(Elapsed));
+        let result = time::timeout(Duration::from_millis(100), async { 7 }).await;
+        assert_eq!(result, Ok(7));
+    }));
+    run_to_completion(&mut executor);
+}
+
+#[test_case]
+fn executor_runs_higher_priority_first() {
+    static ORDER: AtomicUsize = AtomicUsize::new(0);
+
+    /// 记录任务的完成顺序：每个任务把自己的编号追加到 ORDER 的十进制末位
+    async fn record(digit: usize) {
+        let order = ORDER.load(Ordering::Relaxed);
+        ORDER.store(order * 10 + digit, Ordering::Relaxed);
+    }
+
+    let mut executor = Executor::new();
+    executor.spawn(Task::with_priority(record(3), Priority::Background));
+    executor.spawn(Task::new(record(2)));
+    executor.spawn(Task::with_priority(record(1), Priority::InterruptFollowup));
+    executor.run_until_idle();
+    assert_eq!(ORDER.load(Ordering::Relaxed), 123);
+}
+
+#[test_case]
+fn executor_counts_polls() {
+    let mut executor = Executor::new();
+    executor.spawn(Task::new(async {
+        YieldOnce { yielded: false }.await;
+        Never.await;
+    }));
+    executor.run_until_idle();
+    let stats = executor.stats();
+    assert_eq!(stats.len(), 1);
+    // 第一次轮询让出，被唤醒后第二次轮询停在 Never 上
+    assert_eq!(stats[0].2.polls, 2);
+    assert!(stats[0].2.cycles > 0);
+    assert_eq!(executor.ready_queue_depth(), 0);
+}
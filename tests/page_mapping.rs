@@ -0,0 +1,63 @@
+//! 页表映射接口测试：映射、转换、写入、解除映射
+//!
+//! 各步骤依赖同一个页表访问器，因此不使用测试框架（`harness = false`）
+
+#![no_std]
+#![no_main]
+
+use core::panic::PanicInfo;
+
+use bootloader::{BootInfo, entry_point};
+use ricky_os::memory::{self, BootInfoFrameAllocator};
+use ricky_os::{QemuExitCode, exit_qemu, serial_print, serial_println};
+use x86_64::VirtAddr;
+use x86_64::structures::paging::{FrameAllocator, Page, PageTableFlags};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    serial_print!("page_mapping::map_translate_unmap...\t");
+
+    ricky_os::init();
+
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+
+    // 引导阶段映射的 VGA 缓冲区可以被正确转换
+    let vga = phys_mem_offset + 0xb8000u64;
+    assert_eq!(
+        memory::translate_addr(&mapper, vga).map(|addr| addr.as_u64()),
+        Some(0xb8000)
+    );
+
+    let page = Page::containing_address(VirtAddr::new(0x0dea_dbea_f000));
+    let frame = frame_allocator.allocate_frame().expect("no usable frame");
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+    unsafe { memory::map_page(&mut mapper, page, frame, flags, &mut frame_allocator) }
+        .expect("map_page failed");
+
+    assert_eq!(
+        memory::translate_addr(&mapper, page.start_address() + 0x10u64),
+        Some(frame.start_address() + 0x10u64)
+    );
+
+    let ptr: *mut u64 = page.start_address().as_mut_ptr();
+    unsafe {
+        ptr.write_volatile(0x_f021_f077_f065_f04e);
+        assert_eq!(ptr.read_volatile(), 0x_f021_f077_f065_f04e);
+    }
+
+    assert_eq!(memory::unmap_page(&mut mapper, page).ok(), Some(frame));
+    assert_eq!(memory::translate_addr(&mapper, page.start_address()), None);
+
+    serial_println!("[ok]");
+    exit_qemu(QemuExitCode::Success);
+
+    ricky_os::hlt_loop()
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    ricky_os::test_panic_handler(info)
+}
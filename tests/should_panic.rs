@@ -0,0 +1,32 @@
+//! 预期会 panic 的测试，panic 处理函数以成功码退出 QEMU
+//!
+//! 不使用测试框架（`harness = false`），因为只有第一个 panic 能被观察到
+
+#![no_std]
+#![no_main]
+
+use core::panic::PanicInfo;
+
+use ricky_os::{QemuExitCode, exit_qemu, serial_print, serial_println};
+
+#[unsafe(no_mangle)]
+pub extern "C" fn _start() -> ! {
+    should_fail();
+    serial_println!("[test did not panic]");
+    exit_qemu(QemuExitCode::Failed);
+
+    ricky_os::hlt_loop()
+}
+
+fn should_fail() {
+    serial_print!("should_panic::should_fail...\t");
+    assert_eq!(0, 1);
+}
+
+#[panic_handler]
+fn panic(_info: &PanicInfo) -> ! {
+    serial_println!("[ok]");
+    exit_qemu(QemuExitCode::Success);
+
+    ricky_os::hlt_loop()
+}
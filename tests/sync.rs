@@ -0,0 +1,162 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(ricky_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use core::panic::PanicInfo;
+
+use bootloader::{BootInfo, entry_point};
+use ricky_os::allocator;
+use ricky_os::memory::{self, BootInfoFrameAllocator};
+use ricky_os::sync::mpsc::{self, TrySendError};
+use ricky_os::sync::{AsyncMutex, Notify, RwLock, SpinLock};
+use ricky_os::task::Task;
+use ricky_os::task::executor::Executor;
+use x86_64::VirtAddr;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    ricky_os::init();
+
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+
+    test_main();
+
+    ricky_os::hlt_loop()
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    ricky_os::test_panic_handler(info)
+}
+
+#[test_case]
+fn channel_is_bounded() {
+    let (sender, receiver) = mpsc::channel(2);
+    assert_eq!(sender.try_send(1), Ok(()));
+    assert_eq!(sender.try_send(2), Ok(()));
+    assert_eq!(sender.try_send(3), Err(TrySendError::Full(3)));
+    assert_eq!(receiver.try_recv(), Some(1));
+    drop(receiver);
+    assert_eq!(sender.try_send(4), Err(TrySendError::Closed(4)));
+}
+
+#[test_case]
+fn channel_delivers_to_waiting_task() {
+    let (sender, mut receiver) = mpsc::channel::<u32>(4);
+    let mut executor = Executor::new();
+    executor.spawn(Task::new(async move {
+        let mut sum = 0;
+        while let Some(value) = receiver.recv().await {
+            sum += value;
+        }
+        assert_eq!(sum, 6);
+    }));
+    // 接收端在队列为空时挂起
+    executor.run_until_idle();
+    assert_eq!(executor.task_count(), 1);
+
+    let other = sender.clone();
+    sender.try_send(1).unwrap();
+    other.try_send(2).unwrap();
+    executor.run_until_idle();
+    other.try_send(3).unwrap();
+    drop(sender);
+    drop(other);
+    // 所有发送端被丢弃后接收端结束
+    executor.run_until_idle();
+    assert_eq!(executor.task_count(), 0);
+}
+
+#[test_case]
+fn async_mutex_parks_waiting_task() {
+    static MUTEX: AsyncMutex<u32> = AsyncMutex::new(0);
+    static NOTIFY: Notify = Notify::new();
+
+    let mut executor = Executor::new();
+    // 第一个任务持有锁并等待通知
+    executor.spawn(Task::new(async {
+        let mut guard = MUTEX.lock().await;
+        NOTIFY.notified().await;
+        *guard += 1;
+    }));
+    // 第二个任务等待锁
+    executor.spawn(Task::new(async {
+        let mut guard = MUTEX.lock().await;
+        *guard *= 10;
+    }));
+    executor.run_until_idle();
+    assert_eq!(executor.task_count(), 2);
+    assert!(MUTEX.try_lock().is_none());
+
+    NOTIFY.notify_one();
+    executor.run_until_idle();
+    assert_eq!(executor.task_count(), 0);
+    assert_eq!(*MUTEX.try_lock().unwrap(), 10);
+}
+
+#[test_case]
+fn notify_keeps_permit_without_waiter() {
+    let notify = Notify::new();
+    notify.notify_one();
+    let mut executor = Executor::new();
+    executor.spawn(Task::new(async move {
+        notify.notified().await;
+    }));
+    executor.run_until_idle();
+    assert_eq!(executor.task_count(), 0);
+}
+
+#[test_case]
+fn spin_lock_disables_interrupts_while_held() {
+    use x86_64::instructions::interrupts;
+
+    let lock = SpinLock::new(0);
+    assert!(interrupts::are_enabled());
+    {
+        let mut guard = lock.lock();
+        assert!(!interrupts::are_enabled());
+        assert!(lock.try_lock().is_none());
+        *guard += 1;
+    }
+    assert!(interrupts::are_enabled());
+    assert_eq!(*lock.try_lock().unwrap(), 1);
+    assert!(interrupts::are_enabled());
+
+    // 在已禁用中断时获取锁，释放后中断保持禁用
+    interrupts::without_interrupts(|| {
+        drop(lock.lock());
+        assert!(!interrupts::are_enabled());
+    });
+}
+
+#[test_case]
+fn rwlock_shares_readers_and_excludes_writers() {
+    use x86_64::instructions::interrupts;
+
+    let lock = RwLock::new(1);
+    {
+        let first = lock.read();
+        let second = lock.read();
+        assert_eq!(*first + *second, 2);
+        assert_eq!(lock.reader_count(), 2);
+        assert!(!interrupts::are_enabled());
+    }
+    assert!(interrupts::are_enabled());
+    {
+        let mut writer = lock.write();
+        *writer = 5;
+        assert!(lock.is_write_locked());
+        assert_eq!(lock.reader_count(), 0);
+    }
+    assert!(!lock.is_write_locked());
+    assert_eq!(*lock.read(), 5);
+    assert!(interrupts::are_enabled());
+}
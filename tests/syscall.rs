@@ -0,0 +1,97 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(ricky_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::format;
+use core::arch::global_asm;
+use core::panic::PanicInfo;
+
+use bootloader::{BootInfo, entry_point};
+use ricky_os::memory::{self, BootInfoFrameAllocator};
+use ricky_os::{allocator, dmesg, thread, time, user};
+use x86_64::VirtAddr;
+use x86_64::structures::paging::PageTableFlags;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    ricky_os::init();
+
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    memory::install(mapper, frame_allocator);
+    thread::init();
+
+    test_main();
+
+    ricky_os::hlt_loop()
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    ricky_os::test_panic_handler(info)
+}
+
+// 写出一行消息，睡眠 10 毫秒，然后以写入的字节数作为状态码退出
+global_asm!(
+    ".section .rodata.user_program, \"a\"",
+    ".global user_program_start",
+    ".global user_program_end",
+    "user_program_start:",
+    "mov eax, 0",
+    "mov edi, 1",
+    "lea rsi, [rip + user_message]",
+    "lea rdx, [rip + user_message_end]",
+    "sub rdx, rsi",
+    "syscall",
+    "mov rbx, rax",
+    "mov eax, 2",
+    "mov edi, 10",
+    "syscall",
+    "mov eax, 1",
+    "mov rdi, rbx",
+    "syscall",
+    "ud2",
+    "user_message:",
+    ".ascii \"hello from ring 3\\n\"",
+    "user_message_end:",
+    "user_program_end:",
+    ".previous",
+);
+
+unsafe extern "C" {
+    static user_program_start: u8;
+    static user_program_end: u8;
+}
+
+/// 把用户程序复制到用户代码区域，然后进入用户态
+fn run_user_program() {
+    let (start, end) = (&raw const user_program_start, &raw const user_program_end);
+    let program = unsafe { core::slice::from_raw_parts(start, end as usize - start as usize) };
+
+    let entry = VirtAddr::new(user::USER_CODE_START);
+    user::map_region(entry, 1, PageTableFlags::empty()).expect("failed to map user code");
+    assert!(user::copy_to(entry, program));
+    let stack_top = user::map_stack().expect("failed to map user stack");
+    unsafe { user::enter(entry, stack_top) }
+}
+
+#[test_case]
+fn user_program_makes_syscalls() {
+    let id = thread::spawn(run_user_program);
+    let expected = format!("thread {} exited with status 18", id.as_u64());
+
+    let deadline = time::ticks() + 100;
+    let mut exited = false;
+    while !exited {
+        assert!(time::ticks() < deadline, "user program did not exit");
+        thread::yield_now();
+        dmesg::for_each_line(|line| exited |= line.contains(&expected));
+    }
+}
@@ -0,0 +1,89 @@
+//! 线程栈溢出测试：溢出到保护页时缺页异常处理函数报告是哪个线程溢出
+//!
+//! 栈溢出后无法继续执行其他测试，因此不使用测试框架（`harness = false`）
+
+#![no_std]
+#![no_main]
+
+use core::fmt::{self, Write};
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use bootloader::{BootInfo, entry_point};
+use ricky_os::memory::{self, BootInfoFrameAllocator};
+use ricky_os::{QemuExitCode, allocator, exit_qemu, serial_print, serial_println, thread};
+use x86_64::VirtAddr;
+
+entry_point!(main);
+
+/// 溢出线程的编号
+static OVERFLOWING: AtomicU64 = AtomicU64::new(0);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    serial_print!("thread_stack_overflow::guard_page_reports_thread...\t");
+
+    ricky_os::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    memory::install(mapper, frame_allocator);
+    thread::init();
+
+    let id = thread::spawn(stack_overflow);
+    OVERFLOWING.store(id.as_u64(), Ordering::Relaxed);
+    loop {
+        thread::yield_now();
+    }
+}
+
+#[allow(unconditional_recursion)]
+fn stack_overflow() {
+    stack_overflow();
+    // 防止尾递归优化
+    volatile::Volatile::new(0).read();
+}
+
+/// 把 panic 信息写入固定大小的缓冲区，超出部分丢弃
+struct Message {
+    bytes: [u8; 128],
+    len: usize,
+}
+
+impl Write for Message {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let n = s.len().min(self.bytes.len() - self.len);
+        self.bytes[self.len..self.len + n].copy_from_slice(&s.as_bytes()[..n]);
+        self.len += n;
+        Ok(())
+    }
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    let mut message = Message {
+        bytes: [0; 128],
+        len: 0,
+    };
+    let _ = write!(message, "{}", info.message());
+    let mut expected = Message {
+        bytes: [0; 128],
+        len: 0,
+    };
+    let _ = write!(
+        expected,
+        "stack overflow in thread {} ",
+        OVERFLOWING.load(Ordering::Relaxed)
+    );
+
+    if message.bytes[..message.len].starts_with(&expected.bytes[..expected.len]) {
+        serial_println!("[ok]");
+        exit_qemu(QemuExitCode::Success);
+    } else {
+        serial_println!("[failed]\n");
+        serial_println!("Error: {}\n", info);
+        exit_qemu(QemuExitCode::Failed);
+    }
+
+    ricky_os::hlt_loop()
+}
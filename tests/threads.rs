@@ -0,0 +1,230 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(ricky_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use bootloader::{BootInfo, entry_point};
+use ricky_os::allocator;
+use ricky_os::memory::{self, BootInfoFrameAllocator};
+use ricky_os::{thread, workqueue};
+use x86_64::VirtAddr;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    ricky_os::init();
+
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    memory::install(mapper, frame_allocator);
+    thread::init();
+    workqueue::init();
+
+    test_main();
+
+    ricky_os::hlt_loop()
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    ricky_os::test_panic_handler(info)
+}
+
+#[test_case]
+fn threads_take_turns() {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+    fn worker() {
+        for _ in 0..3 {
+            COUNTER.fetch_add(1, Ordering::Relaxed);
+            thread::yield_now();
+        }
+    }
+
+    let first = thread::spawn(worker);
+    let second = thread::spawn(worker);
+    assert_ne!(first, second);
+    while COUNTER.load(Ordering::Relaxed) < 6 {
+        thread::yield_now();
+    }
+    // 两个线程都已退出，只剩下当前线程
+    thread::yield_now();
+    thread::yield_now();
+    assert_eq!(COUNTER.load(Ordering::Relaxed), 6);
+    assert_eq!(thread::current_id().as_u64(), 0);
+}
+
+#[test_case]
+fn timer_preempts_busy_thread() {
+    use core::sync::atomic::AtomicBool;
+
+    static RAN: AtomicBool = AtomicBool::new(false);
+
+    fn worker() {
+        RAN.store(true, Ordering::Relaxed);
+    }
+
+    thread::spawn(worker);
+    // 当前线程从不让出 CPU，只有定时器抢占才能让工作线程运行
+    while !RAN.load(Ordering::Relaxed) {
+        core::hint::spin_loop();
+    }
+}
+
+#[test_case]
+fn wait_queue_blocks_until_woken() {
+    use ricky_os::thread::WaitQueue;
+    use x86_64::instructions::interrupts;
+
+    static QUEUE: WaitQueue = WaitQueue::new();
+    static STAGE: AtomicUsize = AtomicUsize::new(0);
+
+    fn sleeper() {
+        // 记录状态和挂起之间不能被抢占，否则唤醒可能早于挂起
+        interrupts::without_interrupts(|| {
+            STAGE.store(1, Ordering::Relaxed);
+            QUEUE.block_current();
+        });
+        STAGE.store(2, Ordering::Relaxed);
+    }
+
+    thread::spawn(sleeper);
+    while STAGE.load(Ordering::Relaxed) < 1 {
+        thread::yield_now();
+    }
+    // 挂起的线程不会被调度
+    thread::yield_now();
+    assert_eq!(STAGE.load(Ordering::Relaxed), 1);
+
+    assert!(QUEUE.wake_one());
+    while STAGE.load(Ordering::Relaxed) < 2 {
+        thread::yield_now();
+    }
+    assert!(!QUEUE.wake_one());
+    assert_eq!(QUEUE.wake_all(), 0);
+}
+
+#[test_case]
+fn busy_thread_sinks_and_is_boosted() {
+    use core::sync::atomic::AtomicBool;
+    use core::time::Duration;
+
+    static SANK: AtomicBool = AtomicBool::new(false);
+    static BOOSTED: AtomicBool = AtomicBool::new(false);
+
+    fn worker() {
+        // 一直占用 CPU，直到降到最低优先级后又被提升回来
+        while !BOOSTED.load(Ordering::Relaxed) {
+            match thread::current_priority() {
+                level if level == thread::PRIORITY_LEVELS - 1 => {
+                    SANK.store(true, Ordering::Relaxed)
+                }
+                0 if SANK.load(Ordering::Relaxed) => BOOSTED.store(true, Ordering::Relaxed),
+                _ => core::hint::spin_loop(),
+            }
+        }
+    }
+
+    thread::spawn(worker);
+    // 睡眠的线程不会用完时间片，醒来后立即抢占下沉的工作线程
+    for _ in 0..200 {
+        if BOOSTED.load(Ordering::Relaxed) {
+            break;
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+    assert!(SANK.load(Ordering::Relaxed));
+    assert!(BOOSTED.load(Ordering::Relaxed));
+}
+
+#[test_case]
+fn stats_account_cpu_time() {
+    use core::sync::atomic::AtomicBool;
+
+    static DONE: AtomicBool = AtomicBool::new(false);
+
+    fn worker() {
+        while !DONE.load(Ordering::Relaxed) {
+            core::hint::spin_loop();
+        }
+    }
+
+    let id = thread::spawn(worker);
+    // 让工作线程运行一段时间
+    for _ in 0..10 {
+        thread::sleep(core::time::Duration::from_millis(10));
+    }
+    let stats = thread::stats();
+    DONE.store(true, Ordering::Relaxed);
+
+    let find = |id| stats.threads.iter().find(|t| t.id == id);
+    assert!(find(id).is_some_and(|t| t.cycles > 0));
+    assert!(find(thread::current_id()).is_some_and(|t| t.cycles > 0));
+    assert!(stats.threads.iter().any(|t| t.idle));
+    let accounted: u64 = stats.threads.iter().map(|t| t.cycles).sum();
+    assert!(accounted <= stats.total_cycles);
+    assert!(stats.switches > 0);
+}
+
+#[test_case]
+fn affinity_hint_places_thread() {
+    static RAN_ON: AtomicUsize = AtomicUsize::new(usize::MAX);
+    static FALLBACK_RAN: AtomicUsize = AtomicUsize::new(0);
+
+    fn pinned() {
+        RAN_ON.store(thread::current_cpu(), Ordering::Relaxed);
+    }
+
+    fn fallback() {
+        FALLBACK_RAN.store(1, Ordering::Relaxed);
+    }
+
+    thread::spawn_on(pinned, 0);
+    // 提示的处理器不在线时线程仍然会运行
+    thread::spawn_on(fallback, 63);
+    while RAN_ON.load(Ordering::Relaxed) == usize::MAX || FALLBACK_RAN.load(Ordering::Relaxed) == 0
+    {
+        thread::yield_now();
+    }
+    assert_eq!(RAN_ON.load(Ordering::Relaxed), 0);
+}
+
+#[test_case]
+fn workqueue_runs_work_in_worker_threads() {
+    use ricky_os::workqueue::Work;
+    use x86_64::instructions::interrupts;
+
+    static STATIC_RUNS: AtomicUsize = AtomicUsize::new(0);
+    static BOXED_RUNS: AtomicUsize = AtomicUsize::new(0);
+    static WORK: Work = Work::new(|| {
+        STATIC_RUNS.fetch_add(1, Ordering::Relaxed);
+    });
+
+    let test_thread = thread::current_id().as_u64() as usize;
+    // 模拟中断处理函数：禁用中断时入队，重复调度尚未执行的工作项不会再次排队
+    interrupts::without_interrupts(|| {
+        assert!(WORK.schedule());
+        assert!(!WORK.schedule());
+    });
+    assert!(workqueue::submit(move || {
+        let worker = thread::current_id().as_u64() as usize;
+        BOXED_RUNS.store(
+            if worker == test_thread { usize::MAX } else { 1 },
+            Ordering::Relaxed,
+        );
+    }));
+    while STATIC_RUNS.load(Ordering::Relaxed) == 0 || BOXED_RUNS.load(Ordering::Relaxed) == 0 {
+        thread::yield_now();
+    }
+    assert_eq!(STATIC_RUNS.load(Ordering::Relaxed), 1);
+    assert_eq!(BOXED_RUNS.load(Ordering::Relaxed), 1);
+    assert!(!WORK.is_pending());
+}
@@ -0,0 +1,98 @@
+//! 用户态执行测试：用户程序在 ring 3 中自旋，定时器中断从用户态进入内核
+//!
+//! 测试使用自己的 IDT，并且进入用户态后不会返回，因此不使用测试框架（`harness = false`）
+
+#![no_std]
+#![no_main]
+#![feature(abi_x86_interrupt)]
+
+use core::panic::PanicInfo;
+
+use bootloader::{BootInfo, entry_point};
+use lazy_static::lazy_static;
+use ricky_os::memory::{self, BootInfoFrameAllocator};
+use ricky_os::{QemuExitCode, exit_qemu, serial_print, serial_println, user};
+use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode};
+use x86_64::structures::paging::PageTableFlags;
+use x86_64::{PrivilegeLevel, VirtAddr};
+
+/// 第一个硬件中断向量，即定时器中断
+const TIMER_VECTOR: u8 = 32;
+
+lazy_static! {
+    static ref TEST_IDT: InterruptDescriptorTable = {
+        let mut idt = InterruptDescriptorTable::new();
+        idt.general_protection_fault
+            .set_handler_fn(general_protection_fault_handler);
+        idt.page_fault.set_handler_fn(page_fault_handler);
+        idt[TIMER_VECTOR].set_handler_fn(timer_interrupt_handler);
+        idt
+    };
+}
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    serial_print!("user_mode::timer_interrupts_user_code...\t");
+
+    ricky_os::init();
+    x86_64::instructions::interrupts::disable();
+    TEST_IDT.load();
+
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mapper = unsafe { memory::init(phys_mem_offset) };
+    let frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+    memory::install(mapper, frame_allocator);
+
+    // jmp $
+    const SPIN: [u8; 2] = [0xeb, 0xfe];
+    let entry = VirtAddr::new(user::USER_CODE_START);
+    user::map_region(entry, 1, PageTableFlags::empty()).expect("failed to map user code");
+    assert!(user::copy_to(entry, &SPIN));
+    let stack_top = user::map_stack().expect("failed to map user stack");
+
+    unsafe { user::enter(entry, stack_top) }
+}
+
+extern "x86-interrupt" fn timer_interrupt_handler(stack_frame: InterruptStackFrame) {
+    if stack_frame.code_segment.rpl() == PrivilegeLevel::Ring3
+        && stack_frame.instruction_pointer.as_u64() == user::USER_CODE_START
+    {
+        serial_println!("[ok]");
+        exit_qemu(QemuExitCode::Success);
+    } else {
+        serial_println!("[failed]\n");
+        serial_println!("Error: timer interrupted {:#?}\n", stack_frame);
+        exit_qemu(QemuExitCode::Failed);
+    }
+    ricky_os::hlt_loop()
+}
+
+extern "x86-interrupt" fn general_protection_fault_handler(
+    stack_frame: InterruptStackFrame,
+    error_code: u64,
+) {
+    serial_println!("[failed]\n");
+    serial_println!(
+        "Error: general protection fault {:#x}\n{:#?}\n",
+        error_code,
+        stack_frame
+    );
+    exit_qemu(QemuExitCode::Failed);
+    ricky_os::hlt_loop()
+}
+
+extern "x86-interrupt" fn page_fault_handler(
+    stack_frame: InterruptStackFrame,
+    error_code: PageFaultErrorCode,
+) {
+    serial_println!("[failed]\n");
+    serial_println!("Error: page fault {:?}\n{:#?}\n", error_code, stack_frame);
+    exit_qemu(QemuExitCode::Failed);
+    ricky_os::hlt_loop()
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    ricky_os::test_panic_handler(info)
+}